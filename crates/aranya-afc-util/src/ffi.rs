@@ -76,7 +76,7 @@ function create_bidi_channel(
 "#)]
     pub(crate) fn create_bidi_channel<E: Engine>(
         &mut self,
-        _ctx: &CommandContext<'_>,
+        ctx: &CommandContext<'_>,
         eng: &mut E,
         parent_cmd_id: Id,
         our_enc_key_id: EncryptionKeyId,
@@ -104,11 +104,13 @@ function create_bidi_channel(
         let BidiSecrets { author, peer } = BidiSecrets::new(eng, &ch)?;
 
         let key_id = peer.id().into();
-        let wrapped = eng.wrap(author)?;
-        self.store.try_insert(key_id, wrapped).map_err(|err| {
-            error!("unable to insert `BidiAuthorSecret` into KeyStore: {err}");
-            FfiError::KeyStore
-        })?;
+        if !ctx.is_dry_run() {
+            let wrapped = eng.wrap(author)?;
+            self.store.try_insert(key_id, wrapped).map_err(|err| {
+                error!("unable to insert `BidiAuthorSecret` into KeyStore: {err}");
+                FfiError::KeyStore
+            })?;
+        }
 
         Ok(AfcBidiChannel {
             peer_encap: peer.as_bytes().to_vec(),
@@ -129,7 +131,7 @@ function create_uni_channel(
 "#)]
     pub(crate) fn create_uni_channel<E: Engine>(
         &mut self,
-        _ctx: &CommandContext<'_>,
+        ctx: &CommandContext<'_>,
         eng: &mut E,
         parent_cmd_id: Id,
         author_enc_key_id: EncryptionKeyId,
@@ -157,11 +159,13 @@ function create_uni_channel(
         let UniSecrets { author, peer } = UniSecrets::new(eng, &ch)?;
 
         let key_id = peer.id().into();
-        let wrapped = eng.wrap(author)?;
-        self.store.try_insert(key_id, wrapped).map_err(|err| {
-            error!("unable to insert `UniAuthorSecret` into KeyStore: {err}");
-            FfiError::KeyStore
-        })?;
+        if !ctx.is_dry_run() {
+            let wrapped = eng.wrap(author)?;
+            self.store.try_insert(key_id, wrapped).map_err(|err| {
+                error!("unable to insert `UniAuthorSecret` into KeyStore: {err}");
+                FfiError::KeyStore
+            })?;
+        }
 
         Ok(AfcUniChannel {
             peer_encap: peer.as_bytes().to_vec(),
@@ -396,10 +396,7 @@ where
 
     let label = Label::new(42);
     let parent_cmd_id = Id::random(&mut Rng);
-    let ctx = CommandContext::Action(ActionContext {
-        name: "CreateBidiChannel",
-        head_id: parent_cmd_id,
-    });
+    let ctx = CommandContext::Action(ActionContext::new("CreateBidiChannel", parent_cmd_id));
 
     // This is called via FFI.
     let AfcBidiChannel { peer_encap, key_id } = author
@@ -502,10 +499,7 @@ where
 
     let label = Label::new(42);
     let parent_cmd_id = Id::random(&mut Rng);
-    let ctx = CommandContext::Action(ActionContext {
-        name: "CreateSealOnlyChannel",
-        head_id: parent_cmd_id,
-    });
+    let ctx = CommandContext::Action(ActionContext::new("CreateSealOnlyChannel", parent_cmd_id));
 
     // This is called via FFI.
     let AfcUniChannel { peer_encap, key_id } = author
@@ -610,10 +604,7 @@ where
 
     let label = Label::new(42);
     let parent_cmd_id = Id::random(&mut Rng);
-    let ctx = CommandContext::Action(ActionContext {
-        name: "CreateUniOnlyChannel",
-        head_id: parent_cmd_id,
-    });
+    let ctx = CommandContext::Action(ActionContext::new("CreateUniOnlyChannel", parent_cmd_id));
 
     // This is called via FFI.
     let AfcUniChannel { peer_encap, key_id } = author
@@ -72,12 +72,10 @@ where
     E: Engine,
     S: KeyStore,
 {
-    const SEAL_CTX: CommandContext<'static> = CommandContext::Seal(SealContext {
-        name: "dummy",
-        head_id: Id::default(),
-    });
+    const SEAL_CTX: CommandContext<'static> =
+        CommandContext::Seal(SealContext::new("dummy", Id::default()));
 
-    const OPEN_CTX: CommandContext<'static> = CommandContext::Open(OpenContext { name: "dummy" });
+    const OPEN_CTX: CommandContext<'static> = CommandContext::Open(OpenContext::new("dummy"));
 
     /// Test that we can verify valid signatures.
     pub fn test_sign_verify(mut eng: E, mut store: S) {
@@ -237,12 +235,10 @@ where
     /// Test that we reject signatures created with a different
     /// command name.
     pub fn test_verify_reject_different_cmd_name(mut eng: E, mut store: S) {
-        const SEAL_CTX: CommandContext<'static> = CommandContext::Seal(SealContext {
-            name: "foo",
-            head_id: Id::default(),
-        });
+        const SEAL_CTX: CommandContext<'static> =
+            CommandContext::Seal(SealContext::new("foo", Id::default()));
 
-        const OPEN_CTX: CommandContext<'static> = CommandContext::Open(OpenContext { name: "bar" });
+        const OPEN_CTX: CommandContext<'static> = CommandContext::Open(OpenContext::new("bar"));
 
         let (sk, pk) = {
             let sk = SigningKey::<E::CS>::new(&mut eng);
@@ -316,10 +312,7 @@ where
         let command = postcard::to_allocvec(&Command::random(&mut eng))
             .expect("should be able to encode `Command`");
 
-        let seal_ctx = CommandContext::Seal(SealContext {
-            name: "dummy",
-            head_id: Id::random(&mut eng),
-        });
+        let seal_ctx = CommandContext::Seal(SealContext::new("dummy", Id::random(&mut eng)));
         let Signed {
             signature,
             command_id,
@@ -332,7 +325,7 @@ where
             )
             .expect("should be able to create signature");
 
-        let open_ctx = CommandContext::Open(OpenContext { name: "dummy" });
+        let open_ctx = CommandContext::Open(OpenContext::new("dummy"));
         let err = ffi
             .verify(
                 &open_ctx,
@@ -428,23 +421,20 @@ where
             .expect("should be able to encode `Command`");
 
         for ctx in &[
-            CommandContext::Action(ActionContext {
-                name: "dummy",
-                head_id: Id::default(),
-            }),
-            CommandContext::Open(OpenContext { name: "dummy" }),
-            CommandContext::Policy(PolicyContext {
-                name: "dummy",
-                id: Id::default(),
-                author: UserId::default(),
-                version: Id::default(),
-            }),
-            CommandContext::Recall(PolicyContext {
-                name: "dummy",
-                id: Id::default(),
-                author: UserId::default(),
-                version: Id::default(),
-            }),
+            CommandContext::Action(ActionContext::new("dummy", Id::default())),
+            CommandContext::Open(OpenContext::new("dummy")),
+            CommandContext::Policy(PolicyContext::new(
+                "dummy",
+                Id::default(),
+                UserId::default(),
+                Id::default(),
+            )),
+            CommandContext::Recall(PolicyContext::new(
+                "dummy",
+                Id::default(),
+                UserId::default(),
+                Id::default(),
+            )),
         ] {
             let err = ffi
                 .sign(
@@ -494,26 +484,20 @@ where
             .expect("should be able to create signature");
 
         for ctx in &[
-            CommandContext::Action(ActionContext {
-                name: "dummy",
-                head_id: Id::default(),
-            }),
-            CommandContext::Seal(SealContext {
-                name: "dummy",
-                head_id: Id::default(),
-            }),
-            CommandContext::Policy(PolicyContext {
-                name: "dummy",
-                id: Id::default(),
-                author: UserId::default(),
-                version: Id::default(),
-            }),
-            CommandContext::Recall(PolicyContext {
-                name: "dummy",
-                id: Id::default(),
-                author: UserId::default(),
-                version: Id::default(),
-            }),
+            CommandContext::Action(ActionContext::new("dummy", Id::default())),
+            CommandContext::Seal(SealContext::new("dummy", Id::default())),
+            CommandContext::Policy(PolicyContext::new(
+                "dummy",
+                Id::default(),
+                UserId::default(),
+                Id::default(),
+            )),
+            CommandContext::Recall(PolicyContext::new(
+                "dummy",
+                Id::default(),
+                UserId::default(),
+                Id::default(),
+            )),
         ] {
             let err = ffi
                 .verify(
@@ -55,6 +55,7 @@ pub mod id;
 pub mod keystore;
 mod misc;
 mod policy;
+mod storagekey;
 pub mod test_util;
 mod tests;
 
@@ -122,3 +123,4 @@ pub use spideroak_crypto::{
 #[cfg(feature = "hazmat")]
 #[cfg_attr(docsrs, doc(cfg(feature = "hazmat")))]
 pub use spideroak_crypto::{dhkem_impl, hkdf_impl, hmac_impl};
+pub use storagekey::*;
@@ -0,0 +1,203 @@
+#![forbid(unsafe_code)]
+
+use core::{marker::PhantomData, result::Result};
+
+use buggy::Bug;
+
+use crate::{
+    aead::{Aead, BufferTooSmallError, KeyData, OpenError, SealError},
+    ciphersuite::SuiteIds,
+    csprng::{Csprng, Random},
+    engine::unwrapped,
+    error::Error,
+    hmac::Hmac,
+    id::{custom_id, IdError, Identified},
+    import::Import,
+    kdf,
+    subtle::{Choice, ConstantTimeEq},
+    zeroize::{Zeroize, ZeroizeOnDrop},
+    CipherSuite,
+};
+
+/// Key material used to encrypt data at rest, such as the
+/// contents of graph storage.
+///
+/// Like other secret keys, it can be wrapped with an
+/// [`Engine`][crate::Engine] and kept in a
+/// [`KeyStore`][crate::KeyStore].
+pub struct StorageKey<CS> {
+    seed: [u8; 64],
+    _cs: PhantomData<CS>,
+}
+
+impl<CS> ZeroizeOnDrop for StorageKey<CS> {}
+impl<CS> Drop for StorageKey<CS> {
+    fn drop(&mut self) {
+        self.seed.zeroize()
+    }
+}
+
+impl<CS> Clone for StorageKey<CS> {
+    fn clone(&self) -> Self {
+        Self {
+            seed: self.seed,
+            _cs: PhantomData,
+        }
+    }
+}
+
+impl<CS: CipherSuite> StorageKey<CS> {
+    /// Creates a new, random `StorageKey`.
+    pub fn new<R: Csprng>(rng: &mut R) -> StorageKey<CS> {
+        Self::from_seed(Random::random(rng))
+    }
+
+    /// Uniquely identifies the [`StorageKey`].
+    ///
+    /// Two keys with the same ID are the same key.
+    #[inline]
+    pub fn id(&self) -> StorageKeyId {
+        // ID = HMAC(
+        //     key=StorageKey,
+        //     message="StorageKeyId-v1" || suite_id,
+        //     outputBytes=64,
+        // )
+        let mut h = Hmac::<CS::Hash>::new(&self.seed);
+        h.update(b"StorageKeyId-v1");
+        h.update(&SuiteIds::from_suite::<CS>().into_bytes());
+        StorageKeyId(h.tag().into_array().into())
+    }
+
+    /// The size in bytes of the overhead added to plaintexts
+    /// encrypted with [`seal`][Self::seal].
+    pub const OVERHEAD: usize = CS::Aead::NONCE_SIZE + CS::Aead::OVERHEAD;
+
+    /// Returns the size in bytes of the overhead added to
+    /// plaintexts encrypted with [`seal`][Self::seal].
+    ///
+    /// Same as [`OVERHEAD`][Self::OVERHEAD].
+    pub const fn overhead(&self) -> usize {
+        Self::OVERHEAD
+    }
+
+    /// Encrypts and authenticates `plaintext`, binding it to
+    /// `ad`, which must be provided again to
+    /// [`open`][Self::open] it. For example, `ad` might be where
+    /// the ciphertext is stored, so that it cannot be moved.
+    ///
+    /// The resulting ciphertext is written to `dst`, which must
+    /// be at least [`overhead`][Self::overhead] bytes longer
+    /// than `plaintext.len()`.
+    pub fn seal<R: Csprng>(
+        &self,
+        rng: &mut R,
+        dst: &mut [u8],
+        plaintext: &[u8],
+        ad: &[u8],
+    ) -> Result<(), Error> {
+        if dst.len() < self.overhead() {
+            // Not enough room in `dst`.
+            let required = self
+                .overhead()
+                .checked_add(plaintext.len())
+                .ok_or(Error::Bug(Bug::new(
+                    "overhead + plaintext length must not wrap",
+                )))?;
+            return Err(Error::Seal(SealError::BufferTooSmall(BufferTooSmallError(
+                Some(required),
+            ))));
+        }
+        let (nonce, out) = dst.split_at_mut(CS::Aead::NONCE_SIZE);
+        rng.fill_bytes(nonce);
+        let key = self.derive_key()?;
+        Ok(CS::Aead::new(&key).seal(out, nonce, plaintext, ad)?)
+    }
+
+    /// Decrypts and authenticates `ciphertext`, which must have
+    /// been sealed with the same `ad`.
+    ///
+    /// The resulting plaintext is written to `dst`, which must
+    /// be at least as long as the original plaintext (i.e.,
+    /// `ciphertext.len()` - [`overhead`][Self::overhead] bytes
+    /// long).
+    pub fn open(&self, dst: &mut [u8], ciphertext: &[u8], ad: &[u8]) -> Result<(), Error> {
+        if ciphertext.len() < self.overhead() {
+            // Can't find the nonce and/or tag, so it's obviously
+            // invalid.
+            return Err(OpenError::Authentication.into());
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(CS::Aead::NONCE_SIZE);
+        let key = self.derive_key()?;
+        Ok(CS::Aead::new(&key).open(dst, nonce, ciphertext, ad)?)
+    }
+
+    const EXTRACT_CTX: kdf::Context = kdf::Context {
+        domain: "kdf-ext-v1",
+        suite_ids: &SuiteIds::from_suite::<CS>().into_bytes(),
+    };
+
+    const EXPAND_CTX: kdf::Context = kdf::Context {
+        domain: "kdf-exp-v1",
+        suite_ids: &SuiteIds::from_suite::<CS>().into_bytes(),
+    };
+
+    /// Derives a key for [`Self::open`] and [`Self::seal`].
+    fn derive_key(&self) -> Result<<CS::Aead as Aead>::Key, Error> {
+        // StorageKey = KDF(
+        //     key={0,1}^512,
+        //     salt={0}^512,
+        //     info=concat(
+        //         L,
+        //         "kdf-exp-v1",
+        //         suite_id,
+        //         "StorageKey_key",
+        //     ),
+        //     outputBytes=64,
+        // )
+        let prk = Self::EXTRACT_CTX.labeled_extract::<CS::Kdf>(&[], "StorageKey_prk", &self.seed);
+        let key = Self::EXPAND_CTX.labeled_expand::<CS::Kdf, KeyData<CS::Aead>>(
+            &prk,
+            "StorageKey_key",
+            &[],
+        )?;
+        Ok(<<CS::Aead as Aead>::Key as Import<_>>::import(
+            key.as_bytes(),
+        )?)
+    }
+
+    /// Creates itself from the seed.
+    const fn from_seed(seed: [u8; 64]) -> Self {
+        Self {
+            seed,
+            _cs: PhantomData,
+        }
+    }
+}
+
+unwrapped! {
+    name: StorageKey;
+    type: Seed;
+    into: |key: Self| { key.seed };
+    from: |seed: [u8;64] | { Self::from_seed(seed) };
+}
+
+impl<CS: CipherSuite> Identified for StorageKey<CS> {
+    type Id = StorageKeyId;
+
+    #[inline]
+    fn id(&self) -> Result<Self::Id, IdError> {
+        Ok(self.id())
+    }
+}
+
+impl<CS: CipherSuite> ConstantTimeEq for StorageKey<CS> {
+    #[inline]
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.seed.ct_eq(&other.seed)
+    }
+}
+
+custom_id! {
+    /// Uniquely identifies a [`StorageKey`].
+    pub struct StorageKeyId;
+}
@@ -23,6 +23,7 @@ use crate::{
     generic_array::ArrayLength,
     groupkey::{Context, EncryptedGroupKey, GroupKey},
     id::Id,
+    storagekey::StorageKey,
     typenum::{Sum, U64},
     CipherSuite,
 };
@@ -79,6 +80,12 @@ macro_rules! for_each_engine_test {
 
             test_encrypted_group_key_encode,
 
+            // Storage
+
+            test_simple_wrap_storage_key,
+            test_storage_key_seal,
+            test_storage_key_open_wrong_ad,
+
             // APQ
 
             test_simple_sender_signing_key_sign,
@@ -577,6 +584,60 @@ where
     assert_eq!(want.id(), got.id());
 }
 
+/// Simple positive test for wrapping [`StorageKey`]s.
+pub fn test_simple_wrap_storage_key<E: Engine>(eng: &mut E) {
+    let want = StorageKey::new(eng);
+    let bytes = postcard::to_allocvec(
+        &eng.wrap(want.clone())
+            .expect("should be able to wrap `StorageKey`"),
+    )
+    .expect("should be able to encode wrapped `StorageKey`");
+    let wrapped = postcard::from_bytes(&bytes)
+        .expect("should be able to decode encoded wrapped `StorageKey`");
+    let got: StorageKey<E::CS> = eng
+        .unwrap(&wrapped)
+        .expect("should be able to unwrap `StorageKey`");
+    assert_eq!(want.id(), got.id());
+}
+
+/// Simple positive test for encryption using a [`StorageKey`].
+pub fn test_storage_key_seal<E: Engine>(eng: &mut E) {
+    const INPUT: &[u8] = b"hello, world!";
+    const AD: &[u8] = b"test_storage_key_seal";
+
+    let sk = StorageKey::<E::CS>::new(eng);
+    let ciphertext = {
+        let mut dst = vec![0u8; INPUT.len() + sk.overhead()];
+        sk.seal(eng, &mut dst, INPUT, AD).expect("should succeed");
+        dst
+    };
+    let plaintext = {
+        let mut dst = vec![0u8; ciphertext.len() - sk.overhead()];
+        sk.open(&mut dst, &ciphertext, AD).expect("should succeed");
+        dst
+    };
+    assert_eq!(&plaintext, INPUT);
+}
+
+/// Negative test for opening with the wrong associated data
+/// using a [`StorageKey`].
+pub fn test_storage_key_open_wrong_ad<E: Engine>(eng: &mut E) {
+    const INPUT: &[u8] = b"hello, world!";
+
+    let sk = StorageKey::<E::CS>::new(eng);
+    let ciphertext = {
+        let mut dst = vec![0u8; INPUT.len() + sk.overhead()];
+        sk.seal(eng, &mut dst, INPUT, b"right")
+            .expect("should succeed");
+        dst
+    };
+    let mut dst = vec![0u8; ciphertext.len() - sk.overhead()];
+    let err = sk
+        .open(&mut dst, &ciphertext, b"wrong")
+        .expect_err("should fail with wrong associated data");
+    assert_eq!(err, Error::Open(OpenError::Authentication));
+}
+
 /// Simple test for [`SenderSigningKey`].
 /// Creates a signature over an encoded record.
 pub fn test_simple_sender_signing_key_sign<E: Engine>(eng: &mut E)
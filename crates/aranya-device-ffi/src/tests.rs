@@ -15,27 +15,21 @@ fn test_current_user_id() {
     let device = FfiDevice { id: user_id };
 
     let contexts = vec![
-        CommandContext::Action(ActionContext {
-            name: "action",
-            head_id: Id::default(),
-        }),
-        CommandContext::Seal(SealContext {
-            name: "seal",
-            head_id: Id::default(),
-        }),
-        CommandContext::Open(OpenContext { name: "open" }),
-        CommandContext::Policy(PolicyContext {
-            name: "policy",
-            id: Id::default(),
-            author: UserId::default(),
-            version: Id::default(),
-        }),
-        CommandContext::Recall(PolicyContext {
-            name: "recall",
-            id: Id::default(),
-            author: UserId::default(),
-            version: Id::default(),
-        }),
+        CommandContext::Action(ActionContext::new("action", Id::default())),
+        CommandContext::Seal(SealContext::new("seal", Id::default())),
+        CommandContext::Open(OpenContext::new("open")),
+        CommandContext::Policy(PolicyContext::new(
+            "policy",
+            Id::default(),
+            UserId::default(),
+            Id::default(),
+        )),
+        CommandContext::Recall(PolicyContext::new(
+            "recall",
+            Id::default(),
+            UserId::default(),
+            Id::default(),
+        )),
     ];
 
     for context in contexts {
@@ -43,26 +43,24 @@ impl Random for Envelope {
     }
 }
 
-const SEAL_CTX: &CommandContext<'static> = &CommandContext::Seal(SealContext {
-    name: "dummy",
-    head_id: Id::default(),
-});
+const SEAL_CTX: &CommandContext<'static> =
+    &CommandContext::Seal(SealContext::new("dummy", Id::default()));
 
-const OPEN_CTX: &CommandContext<'static> = &CommandContext::Open(OpenContext { name: "dummy" });
+const OPEN_CTX: &CommandContext<'static> = &CommandContext::Open(OpenContext::new("dummy"));
 
-const POLICY_CTX: &CommandContext<'static> = &CommandContext::Policy(PolicyContext {
-    name: "dummy",
-    id: Id::default(),
-    author: UserId::default(),
-    version: Id::default(),
-});
+const POLICY_CTX: &CommandContext<'static> = &CommandContext::Policy(PolicyContext::new(
+    "dummy",
+    Id::default(),
+    UserId::default(),
+    Id::default(),
+));
 
-const RECALL_CTX: &CommandContext<'static> = &CommandContext::Recall(PolicyContext {
-    name: "dummy",
-    id: Id::default(),
-    author: UserId::default(),
-    version: Id::default(),
-});
+const RECALL_CTX: &CommandContext<'static> = &CommandContext::Recall(PolicyContext::new(
+    "dummy",
+    Id::default(),
+    UserId::default(),
+    Id::default(),
+));
 
 #[test]
 fn test_author_id() {
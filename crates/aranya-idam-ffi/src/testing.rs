@@ -79,12 +79,12 @@ where
     E: Engine,
     S: KeyStore,
 {
-    const CTX: CommandContext<'static> = CommandContext::Policy(PolicyContext {
-        name: "dummy",
-        id: Id::default(),
-        author: UserId::default(),
-        version: Id::default(),
-    });
+    const CTX: CommandContext<'static> = CommandContext::Policy(PolicyContext::new(
+        "dummy",
+        Id::default(),
+        UserId::default(),
+        Id::default(),
+    ));
 
     /// Test that we can unwrap `GroupKey`s.
     pub fn test_generate_group_key(mut eng: E, store: S) {
@@ -134,10 +134,7 @@ where
         };
 
         let ffi = Ffi::new(store);
-        let action_ctx = CommandContext::Action(ActionContext {
-            name: "dummy action",
-            head_id: Id::default(),
-        });
+        let action_ctx = CommandContext::Action(ActionContext::new("dummy action", Id::default()));
         let ctx = &Self::CTX;
 
         let StoredGroupKey { wrapped, .. } = ffi
@@ -185,10 +182,7 @@ where
             .generate_group_key(ctx, &mut eng)
             .expect("should be able to create `GroupKey`");
 
-        let action_ctx = CommandContext::Action(ActionContext {
-            name: "dummy action",
-            head_id: Id::default(),
-        });
+        let action_ctx = CommandContext::Action(ActionContext::new("dummy action", Id::default()));
 
         let mut ciphertext = ffi
             .encrypt_message(
@@ -237,10 +231,7 @@ where
             .generate_group_key(ctx, &mut eng)
             .expect("should be able to create `GroupKey`");
 
-        let action_ctx = CommandContext::Action(ActionContext {
-            name: "dummy action",
-            head_id: Id::default(),
-        });
+        let action_ctx = CommandContext::Action(ActionContext::new("dummy action", Id::default()));
 
         let ciphertext = ffi
             .encrypt_message(
@@ -253,12 +244,12 @@ where
             )
             .expect("should be able to encrypt message");
 
-        let ctx = CommandContext::Policy(PolicyContext {
-            name: "different name",
-            id: Id::default(),
-            author: UserId::default(),
-            version: Id::default(),
-        });
+        let ctx = CommandContext::Policy(PolicyContext::new(
+            "different name",
+            Id::default(),
+            UserId::default(),
+            Id::default(),
+        ));
         let err = ffi
             .decrypt_message(&ctx, &mut eng, Id::default(), ciphertext, wrapped, pk)
             .expect_err(
@@ -295,10 +286,8 @@ where
             .generate_group_key(ctx, &mut eng)
             .expect("should be able to create `GroupKey`");
 
-        let action_ctx = CommandContext::Action(ActionContext {
-            name: "dummy action",
-            head_id: Id::random(&mut eng),
-        });
+        let action_ctx =
+            CommandContext::Action(ActionContext::new("dummy action", Id::random(&mut eng)));
 
         let ciphertext = ffi
             .encrypt_message(
@@ -350,10 +339,7 @@ where
                 .expect("should be able to encode `VerifyingKey`")
         };
 
-        let action_ctx = CommandContext::Action(ActionContext {
-            name: "dummy action",
-            head_id: Id::default(),
-        });
+        let action_ctx = CommandContext::Action(ActionContext::new("dummy action", Id::default()));
 
         let ciphertext = ffi
             .encrypt_message(
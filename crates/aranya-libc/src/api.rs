@@ -1,5 +1,5 @@
 use alloc::sync::Arc;
-use core::{ffi::c_int, marker::PhantomData};
+use core::{ffi::c_int, marker::PhantomData, ops::Range, ptr::NonNull, slice};
 
 use cfg_if::cfg_if;
 
@@ -132,3 +132,72 @@ pub fn pwrite(fd: impl AsFd, buf: &[u8], off: i64) -> Result<usize, Errno> {
 pub fn fsync(fd: impl AsFd) -> Result<(), Errno> {
     imp::fsync(fd.as_fd())
 }
+
+/// A read-only mapping of a file, created by [`mmap`].
+///
+/// It's unmapped on drop.
+#[derive(Debug)]
+#[clippy::has_significant_drop]
+pub struct Mmap {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: `Mmap` is an immutable view of memory, so it can be
+// moved to and shared with other threads.
+unsafe impl Send for Mmap {}
+// SAFETY: See above.
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Returns the number of mapped bytes.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Reports whether the mapping is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the mapped bytes in `range`, or `None` if it is
+    /// out of bounds.
+    pub fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        if range.start > range.end || range.end > self.len {
+            return None;
+        }
+        // SAFETY: `ptr` is valid for `len` bytes until the
+        // mapping is dropped, `range` is in bounds, and the
+        // caller of `mmap` promised not to modify bytes while
+        // they are borrowed.
+        unsafe {
+            Some(slice::from_raw_parts(
+                self.ptr.as_ptr().add(range.start),
+                range.len(),
+            ))
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` came from `mmap`, and the
+        // mapping can no longer be borrowed.
+        let _ = unsafe { imp::munmap(self.ptr, self.len) };
+    }
+}
+
+/// See `mmap(2)`.
+///
+/// Maps the first `len` bytes of `fd` for reading. The mapping
+/// is shared, so it observes writes made through `fd`.
+///
+/// # Safety
+///
+/// The file must not be truncated while the mapping exists,
+/// and bytes of the file must not be modified while they are
+/// borrowed through [`Mmap::get`].
+pub unsafe fn mmap(fd: impl AsFd, len: usize) -> Result<Mmap, Errno> {
+    let ptr = imp::mmap(fd.as_fd(), len)?;
+    Ok(Mmap { ptr, len })
+}
@@ -41,6 +41,8 @@ impl Errno {
     pub const EINTR: Errno = Errno(libc::EINTR);
     /// `ENOENT`.
     pub const ENOENT: Errno = Errno(libc::ENOENT);
+    /// `EWOULDBLOCK`.
+    pub const EWOULDBLOCK: Errno = Errno(libc::EWOULDBLOCK);
 
     /// Returns `Errno`.
     fn new() -> Self {
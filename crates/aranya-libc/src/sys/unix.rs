@@ -1,6 +1,9 @@
 #![cfg(all(target_family = "unix", not(target_os = "vxworks")))]
 
-use core::ffi::{c_int, c_uint};
+use core::{
+    ffi::{c_int, c_uint},
+    ptr::{self, NonNull},
+};
 
 pub use libc::{
     mode_t, LOCK_EX, LOCK_NB, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_RDWR, S_IRGRP,
@@ -83,3 +86,41 @@ pub fn fsync(fd: BorrowedFd<'_>) -> Result<(), Errno> {
         Ok(())
     }
 }
+
+/// See `mmap(2)`.
+///
+/// Maps the first `len` bytes of `fd` for reading.
+pub fn mmap(fd: BorrowedFd<'_>, len: usize) -> Result<NonNull<u8>, Errno> {
+    // SAFETY: FFI call, no invariants.
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd.fd,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        Err(errno())
+    } else {
+        NonNull::new(ptr.cast()).ok_or(Errno::from_raw_os_error(libc::EINVAL))
+    }
+}
+
+/// See `munmap(2)`.
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a mapping created by [`mmap`]
+/// which is no longer referenced.
+pub unsafe fn munmap(ptr: NonNull<u8>, len: usize) -> Result<(), Errno> {
+    // SAFETY: See the function's safety docs.
+    let ret = unsafe { libc::munmap(ptr.as_ptr().cast(), len) };
+    if ret < 0 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
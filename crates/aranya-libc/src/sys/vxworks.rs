@@ -5,7 +5,7 @@
 
 #![cfg(target_os = "vxworks")]
 
-use core::{cell::Cell, ffi::c_int, marker::PhantomData};
+use core::{cell::Cell, ffi::c_int, marker::PhantomData, ptr::NonNull};
 
 pub use libc::{
     mode_t, O_CLOEXEC, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, SEEK_SET, S_IRGRP, S_IRUSR, S_IWGRP,
@@ -150,3 +150,20 @@ pub fn fsync(fd: BorrowedFd<'_>) -> Result<(), Errno> {
         Ok(())
     }
 }
+
+/// See `mmap(2)`.
+pub fn mmap(_fd: BorrowedFd<'_>, _len: usize) -> Result<NonNull<u8>, Errno> {
+    // Not supported on VxWorks 6.9.
+    Err(Errno::from_raw_os_error(libc::ENOSYS))
+}
+
+/// See `munmap(2)`.
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a mapping created by [`mmap`]
+/// which is no longer referenced.
+pub unsafe fn munmap(_ptr: NonNull<u8>, _len: usize) -> Result<(), Errno> {
+    // Not supported on VxWorks 6.9.
+    Err(Errno::from_raw_os_error(libc::ENOSYS))
+}
@@ -18,23 +18,17 @@ fn test_head_id() {
     let head_id = Id::default();
 
     {
-        let context = CommandContext::Action(ActionContext {
-            name: "action",
-            head_id,
-        });
+        let context = CommandContext::Action(ActionContext::new("action", head_id));
         assert_eq!(perspective.head_id(&context, &mut eng).unwrap(), head_id);
     }
 
     {
-        let context = CommandContext::Seal(SealContext {
-            name: "seal",
-            head_id,
-        });
+        let context = CommandContext::Seal(SealContext::new("seal", head_id));
         assert_eq!(perspective.head_id(&context, &mut eng).unwrap(), head_id);
     }
 
     {
-        let context = CommandContext::Open(OpenContext { name: "open" });
+        let context = CommandContext::Open(OpenContext::new("open"));
         assert_eq!(
             perspective
                 .head_id(&context, &mut eng)
@@ -47,12 +41,12 @@ fn test_head_id() {
     }
 
     {
-        let context = CommandContext::Policy(PolicyContext {
-            name: "policy",
-            id: Id::default(),
-            author: UserId::default(),
-            version: Id::default(),
-        });
+        let context = CommandContext::Policy(PolicyContext::new(
+            "policy",
+            Id::default(),
+            UserId::default(),
+            Id::default(),
+        ));
         assert_eq!(
             perspective
                 .head_id(&context, &mut eng)
@@ -65,12 +59,12 @@ fn test_head_id() {
     }
 
     {
-        let context = CommandContext::Recall(PolicyContext {
-            name: "recall",
-            id: Id::default(),
-            author: UserId::default(),
-            version: Id::default(),
-        });
+        let context = CommandContext::Recall(PolicyContext::new(
+            "recall",
+            Id::default(),
+            UserId::default(),
+            Id::default(),
+        ));
         assert_eq!(
             perspective
                 .head_id(&context, &mut eng)
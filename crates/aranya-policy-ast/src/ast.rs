@@ -18,7 +18,7 @@ impl fmt::Display for InvalidVersion {
 impl core::error::Error for InvalidVersion {}
 
 /// Policy language version
-#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
 pub enum Version {
     /// Version 1, the initial version of the "new" policy
     /// language.
@@ -48,11 +48,15 @@ impl fmt::Display for Version {
 }
 
 /// An AST node with location information
-#[derive(Debug, Clone, PartialEq)]
+/// Only the node itself is serialized, so that moving it within the source text does not
+/// change its encoding.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
 pub struct AstNode<T> {
     /// The AST element contained within
     pub inner: T,
     /// The locator for where this AST element occurred in the source text
+    #[serde(skip)]
     pub locator: usize,
 }
 
@@ -158,7 +162,7 @@ pub struct FieldDefinition {
 /// An identifier and its type and dynamic effect marker
 ///
 /// A variant used exclusively for Effects
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct EffectFieldDefinition {
     /// the field's name
     pub identifier: String,
@@ -180,7 +184,7 @@ impl From<&EffectFieldDefinition> for FieldDefinition {
 }
 
 /// Value part of a key/value pair for a fact field.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum FactField {
     /// Expression
     Expression(Expression),
@@ -191,7 +195,7 @@ pub enum FactField {
 /// A fact and its key/value field values.
 ///
 /// It is used to create, read, update, and delete facts.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct FactLiteral {
     /// the fact's name
     pub identifier: String,
@@ -204,7 +208,7 @@ pub struct FactLiteral {
 /// A function call with a list of arguments.
 ///
 /// Can only be used in expressions, not on its own.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct FunctionCall {
     /// the function's name
     pub identifier: String,
@@ -213,7 +217,7 @@ pub struct FunctionCall {
 }
 
 /// A named struct literal
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct NamedStruct {
     /// the struct name - should refer to either a Effect or Command
     pub identifier: String,
@@ -221,7 +225,7 @@ pub struct NamedStruct {
     pub fields: Vec<(String, Expression)>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 /// Enumeration definition
 pub struct EnumDefinition {
     /// enum name
@@ -230,7 +234,7 @@ pub struct EnumDefinition {
     pub values: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 /// A reference to an enumeration, e.g. `Color::Red`.
 pub struct EnumReference {
     /// enum name
@@ -240,7 +244,7 @@ pub struct EnumReference {
 }
 
 /// How many facts to expect when counting
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum FactCountType {
     /// Up to
     UpTo,
@@ -264,7 +268,7 @@ impl fmt::Display for FactCountType {
 }
 
 /// Expression atoms with special rules or effects.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum InternalFunction {
     /// A `query` expression
     Query(FactLiteral),
@@ -278,12 +282,14 @@ pub enum InternalFunction {
     Serialize(Box<Expression>),
     /// Deserialize function
     Deserialize(Box<Expression>),
+    /// `policy_fingerprint()`, which returns a hash of the policy being run
+    PolicyFingerprint,
 }
 
 /// A foreign function call with a list of arguments.
 ///
 /// Can only be used in expressions, not on its own.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ForeignFunctionCall {
     /// the function's module name
     pub module: String,
@@ -294,7 +300,7 @@ pub struct ForeignFunctionCall {
 }
 
 /// All of the things which can be in an expression.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Expression {
     /// A 64-bit signed integer
     Int(i64),
@@ -363,7 +369,7 @@ pub struct FunctionDecl {
 }
 
 /// Define a variable with an expression
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct LetStatement {
     /// The variable's name
     pub identifier: String,
@@ -372,14 +378,16 @@ pub struct LetStatement {
 }
 
 /// Check that a boolean expression is true, and fail otherwise
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CheckStatement {
     /// The boolean expression being checked
     pub expression: Expression,
+    /// The reason reported when the check fails
+    pub reason: Option<String>,
 }
 
 /// Match arm pattern
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum MatchPattern {
     /// No values, default case
     Default,
@@ -388,7 +396,7 @@ pub enum MatchPattern {
 }
 
 /// One arm of a match statement
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MatchArm {
     /// The values to check against. Matches any value if the option is None.
     // TODO(chip): Restrict this to only literal values so we can do
@@ -401,7 +409,7 @@ pub struct MatchArm {
 /// Match a value and execute one possibility out of many
 ///
 /// Match arms are tested in order.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MatchStatement {
     /// The value to match against
     pub expression: Expression,
@@ -410,7 +418,7 @@ pub struct MatchStatement {
 }
 
 /// Test a series of conditions and execute the statements for the first true condition.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct IfStatement {
     /// Each `if` and `else if` branch.
     pub branches: Vec<(Expression, Vec<AstNode<Statement>>)>,
@@ -419,7 +427,7 @@ pub struct IfStatement {
 }
 
 /// Iterate over the results of a query, and execute some statements for each one.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MapStatement {
     /// Query
     pub fact: FactLiteral,
@@ -430,14 +438,14 @@ pub struct MapStatement {
 }
 
 /// Create a fact
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CreateStatement {
     /// The fact to create
     pub fact: FactLiteral,
 }
 
 /// Update a fact
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct UpdateStatement {
     /// This fact has to exist as stated
     pub fact: FactLiteral,
@@ -446,7 +454,7 @@ pub struct UpdateStatement {
 }
 
 /// Delete a fact
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct DeleteStatement {
     /// The fact to delete
     pub fact: FactLiteral,
@@ -455,7 +463,7 @@ pub struct DeleteStatement {
 /// Return from a function
 ///
 /// Only valid within functions.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ReturnStatement {
     /// The value to return
     pub expression: Expression,
@@ -463,7 +471,7 @@ pub struct ReturnStatement {
 
 /// Statements in the policy language.
 /// Not all statements are valid in all contexts.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Statement {
     /// A [LetStatement]
     Let(LetStatement),
@@ -523,7 +531,7 @@ pub struct FactDefinition {
 }
 
 /// An action definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ActionDefinition {
     /// The name of the action
     pub identifier: String,
@@ -534,7 +542,7 @@ pub struct ActionDefinition {
 }
 
 /// An effect definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct EffectDefinition {
     /// The name of the effect
     pub identifier: String,
@@ -543,7 +551,7 @@ pub struct EffectDefinition {
 }
 
 /// A struct definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct StructDefinition {
     /// The name of the struct
     pub identifier: String,
@@ -552,7 +560,7 @@ pub struct StructDefinition {
 }
 
 /// A command definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CommandDefinition {
     /// Optional attributes
     pub attributes: Vec<(String, Expression)>,
@@ -571,7 +579,7 @@ pub struct CommandDefinition {
 }
 
 /// A function definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct FunctionDefinition {
     /// The name of the function
     pub identifier: String,
@@ -586,7 +594,7 @@ pub struct FunctionDefinition {
 /// A finish function definition. This is slightly different than a
 /// regular function since it cannot return values and can only
 /// execute finish block statements.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct FinishFunctionDefinition {
     /// The name of the function
     pub identifier: String,
@@ -597,7 +605,7 @@ pub struct FinishFunctionDefinition {
 }
 
 /// A globally scopped let statement
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct GlobalLetStatement {
     /// The variable's name
     pub identifier: String,
@@ -605,6 +613,25 @@ pub struct GlobalLetStatement {
     pub expression: Expression,
 }
 
+/// A minimum version of an FFI module, required by a `use` statement such as
+/// `use crypto >= 2`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FfiVersionRequirement {
+    /// The module name
+    pub module: String,
+    /// The lowest acceptable module version
+    pub min_version: u32,
+}
+
+/// A top-level assertion which is checked during compilation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StaticAssertStatement {
+    /// The boolean expression, which may only refer to constants and globals
+    pub expression: Expression,
+    /// The message reported if the assertion fails
+    pub message: String,
+}
+
 /// A list of (position, size) pairs for text ranges
 pub type TextRanges = Vec<(usize, usize)>;
 
@@ -617,6 +644,8 @@ pub struct Policy {
     pub version: Version,
     /// FFI imports
     pub ffi_imports: Vec<String>,
+    /// Minimum versions of imported FFI modules
+    pub ffi_versions: Vec<AstNode<FfiVersionRequirement>>,
     /// The policy's fact definitions.
     pub facts: Vec<AstNode<FactDefinition>>,
     /// The policy's action definitions.
@@ -635,6 +664,8 @@ pub struct Policy {
     pub finish_functions: Vec<AstNode<FinishFunctionDefinition>>,
     /// The policy's global let statements.
     pub global_lets: Vec<AstNode<GlobalLetStatement>>,
+    /// The policy's static assertions.
+    pub static_asserts: Vec<AstNode<StaticAssertStatement>>,
     /// The source text
     pub text: String,
     /// Text ranges for various nodes (start, end)
@@ -1,4 +1,4 @@
-use std::{fs::File, path::PathBuf, process::ExitCode};
+use std::{fs::File, io::Write, path::PathBuf, process::ExitCode};
 
 use aranya_policy_compiler::{validate::validate, Compiler};
 use aranya_policy_lang::lang::parse_policy_document;
@@ -23,6 +23,12 @@ struct Args {
     /// Do not compile FFI calls
     #[arg(long)]
     stub_ffi: bool,
+    /// Fail on lints which would only warn
+    #[arg(long)]
+    deny_warnings: bool,
+    /// Write the module in the compact encoding, for devices with little storage
+    #[arg(long)]
+    compact: bool,
 }
 
 pub fn main() -> ExitCode {
@@ -46,9 +52,17 @@ pub fn main() -> ExitCode {
             return ExitCode::FAILURE;
         }
     };
-    let compiler = Compiler::new(&ast).stub_ffi(args.stub_ffi);
-    let module = match compiler.compile() {
-        Ok(m) => m,
+    let mut compiler = Compiler::new(&ast).stub_ffi(args.stub_ffi);
+    if args.deny_warnings {
+        compiler = compiler.deny_warnings();
+    }
+    let module = match compiler.compile_with_warnings() {
+        Ok((m, warnings)) => {
+            for w in warnings {
+                eprintln!("warning: {w}");
+            }
+            m
+        }
         Err(e) => {
             println!("{e}");
             return ExitCode::FAILURE;
@@ -66,6 +80,12 @@ pub fn main() -> ExitCode {
 
     let mut out_f = File::create(out_path).expect("could not open output file");
 
-    ciborium::into_writer(&module, &mut out_f).expect("could not write output file");
+    if args.compact {
+        out_f
+            .write_all(&module.to_compact())
+            .expect("could not write output file");
+    } else {
+        ciborium::into_writer(&module, &mut out_f).expect("could not write output file");
+    }
     ExitCode::SUCCESS
 }
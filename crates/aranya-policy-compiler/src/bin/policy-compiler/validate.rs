@@ -2,7 +2,10 @@ use aranya_policy_compiler::{FinishAnalyzer, TraceAnalyzerBuilder, TraceFailure,
 use aranya_policy_module::{LabelType, Module, ModuleData};
 
 pub fn validate(module: &Module) -> bool {
-    let ModuleData::V0(ref m) = module.data;
+    let ModuleData::V1(ref m) = module.data else {
+        println!("cannot validate a {} module", module.version());
+        return false;
+    };
     let mut failed = false;
 
     // Get all global variable names
@@ -1,6 +1,14 @@
+mod call_graph;
+mod collisions;
 mod error;
+mod lint;
+mod pass;
+mod peephole;
+mod stack_depth;
+mod static_assert;
 mod target;
 mod types;
+mod verify;
 
 use std::{
     collections::{btree_map::Entry, BTreeMap, BTreeSet},
@@ -10,8 +18,9 @@ use std::{
 
 use aranya_policy_ast::{self as ast, AstNode, FactCountType, FunctionCall, VType};
 use aranya_policy_module::{
-    ffi::ModuleSchema, CodeMap, ExitReason, Instruction, Label, LabelType, Meta, Module, Struct,
-    Target, Value,
+    ffi::{Func, ModuleSchema},
+    CodeMap, ExitReason, Fingerprint, Instruction, Label, LabelType, Meta, Module, Struct, Target,
+    Value,
 };
 pub use ast::Policy as AstPolicy;
 use ast::{
@@ -21,8 +30,16 @@ use ast::{
 use buggy::{Bug, BugExt};
 pub(crate) use target::CompileTarget;
 
-pub use self::error::{CallColor, CompileError, CompileErrorType};
-use self::types::{IdentifierTypeStack, Typeish};
+use self::{
+    call_graph::CallGraph,
+    lint::LintLevels,
+    types::{IdentifierTypeStack, Typeish},
+};
+pub use self::{
+    error::{CallColor, CompileError, CompileErrorType},
+    lint::{Lint, LintLevel},
+    pass::CompilerPass,
+};
 
 enum FunctionColor {
     /// Function has no side-effects and returns a value
@@ -68,7 +85,9 @@ impl fmt::Display for StatementContext {
 }
 
 /// The "compile state" of the machine.
-struct CompileState<'a> {
+///
+/// Custom [`CompilerPass`]es are given access to the compiled policy through this.
+pub struct CompileState<'a> {
     /// Policy being compiled
     policy: &'a AstPolicy,
     /// The underlying machine
@@ -90,12 +109,28 @@ struct CompileState<'a> {
     identifier_types: IdentifierTypeStack,
     /// FFI module schemas. Used to validate FFI calls.
     ffi_modules: &'a [ModuleSchema<'a>],
+    /// Host-defined instructions, which are called like functions
+    extensions: &'a [Func<'a>],
     /// name/value mappings for enums, e.g. `"Color"->["Red", "Green"]`
     enum_values: BTreeMap<&'a str, Vec<&'a str>>,
     /// Determines if one compiles with debug functionality,
     is_debug: bool,
     /// Auto-defines FFI modules for testing purposes
     stub_ffi: bool,
+    /// Collapses wasteful instruction sequences after compilation
+    optimize: bool,
+    /// The maximum worst-case stack depth allowed for any entry point, if any
+    max_stack_depth: Option<usize>,
+    /// Calls between functions and actions, used to detect recursion
+    call_graph: CallGraph,
+    /// The label of the function or action currently being compiled, if any
+    caller: Option<Label>,
+    /// Whether each lint is allowed, warns, or is denied
+    lints: LintLevels,
+    /// Lint findings which did not stop compilation
+    warnings: Vec<CompileError>,
+    /// The expected type of each command attribute, if attributes are checked
+    attribute_schema: Option<BTreeMap<String, VType>>,
 }
 
 impl<'a> CompileState<'a> {
@@ -134,7 +169,7 @@ impl<'a> CompileState<'a> {
 
     fn append_var(&mut self, identifier: String, vtype: VType) -> Result<(), CompileError> {
         self.append_instruction(Instruction::Meta(Meta::Let(identifier.clone())));
-        self.append_instruction(Instruction::Def(identifier.clone()));
+        self.append_instruction(Instruction::Def(identifier.as_str().into()));
         self.identifier_types
             .add(identifier, Typeish::Type(vtype))?;
         Ok(())
@@ -170,7 +205,7 @@ impl<'a> CompileState<'a> {
     }
 
     /// Insert a struct definition while preventing duplicates of the struct name and fields
-    pub fn define_struct(
+    fn define_struct(
         &mut self,
         identifier: &str,
         fields: &[FieldDefinition],
@@ -273,7 +308,7 @@ impl<'a> CompileState<'a> {
     }
 
     /// Define a named Label.
-    pub fn define_label(&mut self, label: Label, addr: usize) -> Result<(), CompileError> {
+    fn define_label(&mut self, label: Label, addr: usize) -> Result<(), CompileError> {
         match self.m.labels.entry(label.clone()) {
             Entry::Vacant(e) => {
                 e.insert(addr);
@@ -284,7 +319,7 @@ impl<'a> CompileState<'a> {
     }
 
     /// Create an anonymous Label and return its identifier.
-    pub fn anonymous_label(&mut self) -> Label {
+    fn anonymous_label(&mut self) -> Label {
         let name = format!("anonymous{}", self.c);
         self.c = self.c.checked_add(1).expect("self.c + 1 must not wrap");
         Label::new_temp(&name)
@@ -332,12 +367,17 @@ impl<'a> CompileState<'a> {
     }
 
     /// Attempt to resolve any unresolved targets.
-    pub fn resolve_targets(&mut self) -> Result<(), CompileError> {
+    fn resolve_targets(&mut self) -> Result<(), CompileError> {
         for ref mut instr in &mut self.m.progmem {
             match instr {
                 Instruction::Branch(t) | Instruction::Jump(t) | Instruction::Call(t) => {
                     Self::resolve_target(t, &mut self.m.labels)?
                 }
+                Instruction::JumpTable(_, targets) => {
+                    for t in targets {
+                        Self::resolve_target(t, &mut self.m.labels)?
+                    }
+                }
                 _ => (),
             }
         }
@@ -356,10 +396,10 @@ impl<'a> CompileState<'a> {
             // at runtime.
             return Err(self.err(CompileErrorType::BadArgument(s.identifier.clone())));
         }
-        self.append_instruction(Instruction::StructNew(s.identifier.clone()));
+        self.append_instruction(Instruction::StructNew(s.identifier.as_str().into()));
         for field in &s.fields {
             self.compile_expression(&field.1)?;
-            self.append_instruction(Instruction::StructSet(field.0.clone()));
+            self.append_instruction(Instruction::StructSet(field.0.as_str().into()));
         }
         Ok(())
     }
@@ -411,9 +451,8 @@ impl<'a> CompileState<'a> {
                 ))));
             }
 
-            let Some(vtype) = field_vtype(&lit_key.1) else {
-                // If the type cannot be determined, e.g. it's an expression or query, ignore it. The machine will verify the type at runtime.
-                // TODO should we allow expressions/queries for key values?
+            let Typeish::Type(vtype) = self.fact_field_type(&lit_key.1)? else {
+                // If the type cannot be determined, e.g. it's a bind value, ignore it. The machine will verify the type at runtime.
                 continue;
             };
 
@@ -468,8 +507,8 @@ impl<'a> CompileState<'a> {
                 ))));
             }
 
-            let Some(lit_type) = field_vtype(&lit_value.1) else {
-                // Let complex expressions through, the machine will resolve and verify them.
+            let Typeish::Type(lit_type) = self.fact_field_type(&lit_value.1)? else {
+                // Let indeterminate values through, the machine will resolve and verify them.
                 continue;
             };
             if lit_type != schema_value.field_type {
@@ -483,9 +522,73 @@ impl<'a> CompileState<'a> {
         Ok(())
     }
 
+    /// Make sure an emitted struct is a declared effect. If the expression is a struct
+    /// literal, also checks that:
+    /// - every field defined in the effect is present
+    /// - no fields are given which the effect does not define
+    /// - the field values have the correct types
+    fn verify_effect_against_schema(
+        &self,
+        expression: &Expression,
+        name: &str,
+    ) -> Result<(), CompileError> {
+        let Some(effect) = self.policy.effects.iter().find(|e| e.identifier == name) else {
+            return Err(self.err(CompileErrorType::InvalidType(format!(
+                "Emit must be given an effect, but `{name}` is not an effect"
+            ))));
+        };
+
+        let Expression::NamedStruct(s) = expression else {
+            // We only know the type of other expressions, which has already been checked.
+            return Ok(());
+        };
+
+        if let Some(identifier) = find_duplicate(&s.fields, |f| &f.0) {
+            return Err(self.err(CompileErrorType::AlreadyDefined(format!(
+                "{name}.{identifier}"
+            ))));
+        }
+
+        for (field_name, _) in &s.fields {
+            if !effect.fields.iter().any(|f| &f.identifier == field_name) {
+                return Err(self.err(CompileErrorType::NotDefined(format!("{name}.{field_name}"))));
+            }
+        }
+
+        for field_def in &effect.fields {
+            let Some((_, e)) = s.fields.iter().find(|f| f.0 == field_def.identifier) else {
+                return Err(self.err(CompileErrorType::Missing(format!(
+                    "field `{}` of effect `{name}`",
+                    field_def.identifier
+                ))));
+            };
+            let field_type = self
+                .calculate_expression_type(e)
+                .map_err(|e| self.err(e.into()))?;
+            if !field_type.is_maybe(&field_def.field_type) {
+                return Err(self.err(CompileErrorType::InvalidType(format!(
+                    "Effect field `{}` must be {}",
+                    field_def.identifier, field_def.field_type
+                ))));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the type of a fact field. Bind values have no type, so they are indeterminate.
+    fn fact_field_type(&self, f: &FactField) -> Result<Typeish, CompileError> {
+        match f {
+            FactField::Expression(e) => self
+                .calculate_expression_type(e)
+                .map_err(|e| self.err(e.into())),
+            FactField::Bind => Ok(Typeish::Indeterminate),
+        }
+    }
+
     /// Compile instructions to construct a fact literal
     fn compile_fact_literal(&mut self, f: &FactLiteral) -> Result<(), CompileError> {
-        self.append_instruction(Instruction::FactNew(f.identifier.clone()));
+        self.append_instruction(Instruction::FactNew(f.identifier.as_str().into()));
         for field in &f.key_fields {
             if let FactField::Expression(e) = &field.1 {
                 self.compile_expression(e)?;
@@ -493,7 +596,7 @@ impl<'a> CompileState<'a> {
                 // Skip bind values
                 continue;
             }
-            self.append_instruction(Instruction::FactKeySet(field.0.clone()));
+            self.append_instruction(Instruction::FactKeySet(field.0.as_str().into()));
         }
         if let Some(value_fields) = &f.value_fields {
             for field in value_fields {
@@ -503,7 +606,7 @@ impl<'a> CompileState<'a> {
                     // Skip bind values
                     continue;
                 }
-                self.append_instruction(Instruction::FactValueSet(field.0.clone()));
+                self.append_instruction(Instruction::FactValueSet(field.0.as_str().into()));
             }
         }
         Ok(())
@@ -586,6 +689,15 @@ impl<'a> CompileState<'a> {
                     self.compile_expression(e)?;
                     self.append_instruction(Instruction::Deserialize);
                 }
+                ast::InternalFunction::PolicyFingerprint => {
+                    let fingerprint = self
+                        .m
+                        .fingerprint
+                        .assume("policy fingerprint must be computed before compilation")?;
+                    self.append_instruction(Instruction::Const(Value::Bytes(
+                        fingerprint.as_bytes().to_vec().into(),
+                    )));
+                }
             },
             Expression::FunctionCall(f) => {
                 let signature = self
@@ -609,7 +721,14 @@ impl<'a> CompileState<'a> {
                         signature.args.len()
                     ))));
                 }
-                self.compile_function_call(f, false)?;
+                if self.extensions.iter().any(|ext| ext.name == f.identifier) {
+                    for a in &f.arguments {
+                        self.compile_expression(a)?;
+                    }
+                    self.append_instruction(Instruction::Extension(f.identifier.as_str().into()));
+                } else {
+                    self.compile_function_call(f, false)?;
+                }
             }
             Expression::ForeignFunctionCall(f) => {
                 // If the policy hasn't imported this module, don't allow using it
@@ -662,7 +781,7 @@ impl<'a> CompileState<'a> {
             }
             Expression::Identifier(i) => {
                 self.append_instruction(Instruction::Meta(Meta::Get(i.clone())));
-                self.append_instruction(Instruction::Get(i.clone()));
+                self.append_instruction(Instruction::Get(i.as_str().into()));
             }
             Expression::EnumReference(e) => {
                 // get enum by name
@@ -685,7 +804,7 @@ impl<'a> CompileState<'a> {
             }
             Expression::Dot(t, s) => {
                 self.compile_expression(t)?;
-                self.append_instruction(Instruction::StructGet(s.clone()));
+                self.append_instruction(Instruction::StructGet(s.as_str().into()));
             }
             Expression::Add(a, b)
             | Expression::Subtract(a, b)
@@ -819,7 +938,16 @@ impl<'a> CompileState<'a> {
             self.append_instruction(Instruction::Block);
         }
         let context = self.get_statement_context()?;
+        let mut exited = false;
+        let mut reported = false;
         for statement in statements {
+            // Nothing after a statement which always exits can run. Only the first such
+            // statement in a block is reported.
+            if exited && !reported {
+                let err = self.err_loc(CompileErrorType::Unreachable, statement.locator);
+                self.lint(Lint::Unreachable, err)?;
+                reported = true;
+            }
             self.map_range(statement)?;
             // This match statement matches on a pair of the statement and its allowable
             // contexts, so that disallowed contexts will fall through to the default at the
@@ -838,7 +966,7 @@ impl<'a> CompileState<'a> {
                     let et = self.compile_expression(&s.expression)?;
                     self.identifier_types.add(&s.identifier, et)?;
                     self.append_instruction(Instruction::Meta(Meta::Let(s.identifier.clone())));
-                    self.append_instruction(Instruction::Def(s.identifier.clone()));
+                    self.append_instruction(Instruction::Def(s.identifier.as_str().into()));
                 }
                 (
                     ast::Statement::Check(s),
@@ -854,12 +982,19 @@ impl<'a> CompileState<'a> {
                         ))));
                     }
                     // The current instruction is the branch. The next
-                    // instruction is the following panic you arrive at
-                    // if the expression is false. The instruction you
-                    // branch to if the check succeeds is the
-                    // instruction after that - current instruction + 2.
-                    let next = self.wp.checked_add(2).assume("self.wp + 2 must not wrap")?;
+                    // instructions are the reason, if there is one, and
+                    // the following exit you arrive at if the expression
+                    // is false. The instruction you branch to if the
+                    // check succeeds is the instruction after that.
+                    let skip = if s.reason.is_some() { 3 } else { 2 };
+                    let next = self
+                        .wp
+                        .checked_add(skip)
+                        .assume("self.wp + skip must not wrap")?;
                     self.append_instruction(Instruction::Branch(Target::Resolved(next)));
+                    if let Some(reason) = &s.reason {
+                        self.append_instruction(Instruction::Meta(Meta::Check(reason.clone())));
+                    }
                     self.append_instruction(Instruction::Exit(ExitReason::Check));
                 }
                 (
@@ -888,18 +1023,50 @@ impl<'a> CompileState<'a> {
                         ));
                     }
 
+                    // Ensure the default case, if any, is the last case.
+                    if s.arms
+                        .iter()
+                        .rev()
+                        .skip(1)
+                        .any(|a| a.pattern == MatchPattern::Default)
+                    {
+                        return Err(self.err(CompileErrorType::Unknown(String::from(
+                            "Default match case must be last.",
+                        ))));
+                    }
+
                     self.compile_expression(&s.expression)?;
 
                     let end_label = self.anonymous_label();
 
                     // 1. Generate branching instructions, and arm-start labels
-                    let mut arm_labels: Vec<Label> = vec![];
-
-                    for arm in s.arms.iter() {
-                        let arm_label = self.anonymous_label();
-                        arm_labels.push(arm_label.clone());
+                    let arm_labels: Vec<Label> =
+                        s.arms.iter().map(|_| self.anonymous_label()).collect();
+
+                    let table = match_jump_table(&s.arms);
+                    let dispatched = table.is_some();
+                    if let Some((base, table)) = table {
+                        // Dense integer arms dispatch through a single jump table. Values
+                        // outside the table, and holes within it, go to the fallback.
+                        let fallback = self.anonymous_label();
+                        let targets = table
+                            .into_iter()
+                            .map(|arm| {
+                                Target::Unresolved(match arm {
+                                    Some(i) => arm_labels[i].clone(),
+                                    None => fallback.clone(),
+                                })
+                            })
+                            .collect();
+                        self.append_instruction(Instruction::Dup(0));
+                        self.append_instruction(Instruction::JumpTable(base, targets));
+                        self.define_label(fallback, self.wp)?;
+                    }
 
+                    for (arm, arm_label) in s.arms.iter().zip(&arm_labels) {
                         match &arm.pattern {
+                            // Value arms were already dispatched by the jump table.
+                            MatchPattern::Values(_) if dispatched => {}
                             MatchPattern::Values(values) => {
                                 for value in values.iter() {
                                     self.append_instruction(Instruction::Dup(0));
@@ -916,13 +1083,6 @@ impl<'a> CompileState<'a> {
                                 self.append_instruction(Instruction::Jump(Target::Unresolved(
                                     arm_label.clone(),
                                 )));
-
-                                // Ensure this is the last case, and also that it's not the only case.
-                                if arm != s.arms.last().expect("last arm") {
-                                    return Err(self.err(CompileErrorType::Unknown(String::from(
-                                        "Default match case must be last.",
-                                    ))));
-                                }
                             }
                         }
                     }
@@ -977,7 +1137,10 @@ impl<'a> CompileState<'a> {
                     self.define_label(end_label, self.wp)?;
                 }
                 (ast::Statement::Publish(s), StatementContext::Action(_)) => {
-                    self.compile_expression(s)?;
+                    let et = self.compile_expression(s)?;
+                    if let Typeish::Type(VType::Struct(name)) = et {
+                        self.check_publishable(&name)?;
+                    }
                     self.append_instruction(Instruction::Publish);
                 }
                 (ast::Statement::Return(s), StatementContext::PureFunction(fd)) => {
@@ -1028,7 +1191,9 @@ impl<'a> CompileState<'a> {
                     self.define_label(top_label.to_owned(), self.wp)?;
                     // Fetch next result
                     self.append_instruction(Instruction::Block);
-                    self.append_instruction(Instruction::QueryNext(map_stmt.identifier.clone()));
+                    self.append_instruction(Instruction::QueryNext(
+                        map_stmt.identifier.as_str().into(),
+                    ));
                     // If no more results, break
                     self.append_instruction(Instruction::Branch(Target::Unresolved(
                         end_label.clone(),
@@ -1095,7 +1260,7 @@ impl<'a> CompileState<'a> {
                                 self.compile_expression(e)?;
                             }
                         }
-                        self.append_instruction(Instruction::FactValueSet(k.clone()));
+                        self.append_instruction(Instruction::FactValueSet(k.as_str().into()));
                     }
                     self.append_instruction(Instruction::Update);
                 }
@@ -1106,11 +1271,12 @@ impl<'a> CompileState<'a> {
                 }
                 (ast::Statement::Emit(s), StatementContext::Finish) => {
                     let et = self.compile_expression(s)?;
-                    if !matches!(et, Typeish::Type(VType::Struct(_))) {
+                    let Typeish::Type(VType::Struct(name)) = et else {
                         return Err(self.err(CompileErrorType::InvalidType(String::from(
                             "Emit must be given a struct",
                         ))));
-                    }
+                    };
+                    self.verify_effect_against_schema(s, &name)?;
                     self.append_instruction(Instruction::Emit);
                 }
                 (ast::Statement::FunctionCall(f), StatementContext::Finish) => {
@@ -1194,6 +1360,10 @@ impl<'a> CompileState<'a> {
                     }
 
                     let label = Label::new(&fc.identifier, LabelType::Action);
+                    match &self.caller {
+                        Some(caller) => self.call_graph.add_call(caller.clone(), label.clone()),
+                        None => self.call_graph.add_entry_call(label.clone()),
+                    }
                     self.append_instruction(Instruction::Call(Target::Unresolved(label)));
                 }
                 (ast::Statement::DebugAssert(s), _) => {
@@ -1214,6 +1384,7 @@ impl<'a> CompileState<'a> {
                     ))
                 }
             }
+            exited = exited || statement_always_exits(&statement.inner);
         }
         if scope == Scope::Layered {
             self.append_instruction(Instruction::End);
@@ -1235,10 +1406,9 @@ impl<'a> CompileState<'a> {
         function_node: &'a AstNode<ast::FunctionDefinition>,
     ) -> Result<(), CompileError> {
         let function = &function_node.inner;
-        self.define_label(
-            Label::new(&function.identifier, LabelType::Function),
-            self.wp,
-        )?;
+        let label = Label::new(&function.identifier, LabelType::Function);
+        self.define_label(label.clone(), self.wp)?;
+        self.caller = Some(label);
         self.map_range(function_node)?;
         self.define_function_signature(function_node)?;
 
@@ -1263,6 +1433,7 @@ impl<'a> CompileState<'a> {
         self.append_instruction(Instruction::Exit(ExitReason::Panic));
 
         self.identifier_types.exit_function();
+        self.caller = None;
         Ok(())
     }
 
@@ -1272,7 +1443,9 @@ impl<'a> CompileState<'a> {
         function_node: &'a AstNode<ast::FinishFunctionDefinition>,
     ) -> Result<(), CompileError> {
         let function = &function_node.inner;
-        self.define_label(Label::new_temp(&function.identifier), self.wp)?;
+        let label = Label::new_temp(&function.identifier);
+        self.define_label(label.clone(), self.wp)?;
+        self.caller = Some(label);
         self.map_range(function_node)?;
         self.identifier_types.enter_function();
         for arg in function.arguments.iter().rev() {
@@ -1284,6 +1457,7 @@ impl<'a> CompileState<'a> {
         self.append_instruction(Instruction::Return);
 
         self.identifier_types.exit_function();
+        self.caller = None;
         Ok(())
     }
 
@@ -1304,6 +1478,10 @@ impl<'a> CompileState<'a> {
                 LabelType::Function
             },
         );
+        match &self.caller {
+            Some(caller) => self.call_graph.add_call(caller.clone(), label.clone()),
+            None => self.call_graph.add_entry_call(label.clone()),
+        }
         self.append_instruction(Instruction::Call(Target::Unresolved(label)));
         Ok(())
     }
@@ -1315,7 +1493,9 @@ impl<'a> CompileState<'a> {
     ) -> Result<(), CompileError> {
         let action = &action_node.inner;
         self.identifier_types.enter_function();
-        self.define_label(Label::new(&action.identifier, LabelType::Action), self.wp)?;
+        let label = Label::new(&action.identifier, LabelType::Action);
+        self.define_label(label.clone(), self.wp)?;
+        self.caller = Some(label);
         self.map_range(action_node)?;
 
         // check for duplicate args
@@ -1334,6 +1514,7 @@ impl<'a> CompileState<'a> {
         self.compile_statements(&action.statements, Scope::Same)?;
         self.append_instruction(Instruction::Return);
         self.identifier_types.exit_function();
+        self.caller = None;
 
         match self.m.action_defs.entry(action_node.identifier.clone()) {
             Entry::Vacant(e) => {
@@ -1456,11 +1637,10 @@ impl<'a> CompileState<'a> {
         command: &ast::CommandDefinition,
         locator: usize,
     ) -> Result<(), CompileError> {
+        // Commands without a seal block cannot be published, which is checked at each
+        // `publish` statement.
         if command.seal.is_empty() {
-            return Err(self.err_loc(
-                CompileErrorType::Unknown(String::from("Empty/missing seal block in command")),
-                locator,
-            ));
+            return Ok(());
         }
 
         // fake a function def for the seal block
@@ -1506,11 +1686,10 @@ impl<'a> CompileState<'a> {
         command: &ast::CommandDefinition,
         locator: usize,
     ) -> Result<(), CompileError> {
+        // Commands without a open block cannot be published, which is checked at each
+        // `publish` statement.
         if command.open.is_empty() {
-            return Err(self.err_loc(
-                CompileErrorType::Unknown(String::from("Empty/missing open block in command")),
-                locator,
-            ));
+            return Ok(());
         }
 
         // fake a function def for the open block
@@ -1548,6 +1727,27 @@ impl<'a> CompileState<'a> {
         Ok(())
     }
 
+    /// Checks that a published command has the seal and open blocks needed to send and
+    /// receive it.
+    fn check_publishable(&self, name: &str) -> Result<(), CompileError> {
+        let Some(command) = self.policy.commands.iter().find(|c| c.identifier == name) else {
+            return Ok(());
+        };
+        if command.seal.is_empty() {
+            return Err(self.err(CompileErrorType::MissingCommandBlock(
+                name.to_string(),
+                LabelType::CommandSeal,
+            )));
+        }
+        if command.open.is_empty() {
+            return Err(self.err(CompileErrorType::MissingCommandBlock(
+                name.to_string(),
+                LabelType::CommandOpen,
+            )));
+        }
+        Ok(())
+    }
+
     /// Compile a command policy block
     fn compile_command(
         &mut self,
@@ -1572,11 +1772,25 @@ impl<'a> CompileState<'a> {
         for attr in &command.attributes {
             match attr_map.entry(attr.0.clone()) {
                 Entry::Vacant(e) => {
-                    if let Some(value) = expression_value(&attr.1) {
-                        e.insert(value);
-                    } else {
+                    let Some(value) = expression_value(&attr.1) else {
                         return Err(self.err(CompileErrorType::InvalidExpression(attr.1.clone())));
+                    };
+                    if let Some(schema) = &self.attribute_schema {
+                        let Some(expected) = schema.get(&attr.0) else {
+                            return Err(self.err(CompileErrorType::NotDefined(format!(
+                                "attribute `{}`",
+                                attr.0
+                            ))));
+                        };
+                        if value.vtype().as_ref() != Some(expected) {
+                            return Err(self.err(CompileErrorType::InvalidType(format!(
+                                "attribute `{}` must be {expected}, got {}",
+                                attr.0,
+                                value.type_name()
+                            ))));
+                        }
                     }
+                    e.insert(value);
                 }
                 Entry::Occupied(_) => {
                     return Err(self.err(CompileErrorType::AlreadyDefined(attr.0.clone())));
@@ -1644,7 +1858,10 @@ impl<'a> CompileState<'a> {
     }
 
     /// Compile a policy into instructions inside the given Machine.
-    pub fn compile(&mut self) -> Result<(), CompileError> {
+    fn compile(&mut self) -> Result<(), CompileError> {
+        self.check_name_collisions()?;
+        self.check_ffi_versions()?;
+
         // Panic when running a module without setup.
         self.append_instruction(Instruction::Exit(ExitReason::Panic));
 
@@ -1657,6 +1874,9 @@ impl<'a> CompileState<'a> {
             let fields: Vec<FieldDefinition> =
                 effect.inner.fields.iter().map(|f| f.into()).collect();
             self.define_struct(&effect.inner.identifier, &fields)?;
+            self.m
+                .effect_defs
+                .insert(effect.inner.identifier.clone(), fields);
         }
 
         for struct_def in &self.policy.structs {
@@ -1683,6 +1903,8 @@ impl<'a> CompileState<'a> {
             self.compile_enum_definition(enum_def)?;
         }
 
+        self.check_static_asserts()?;
+
         for fact in &self.policy.facts {
             let FactDefinition { key, value, .. } = &fact.inner;
 
@@ -1697,6 +1919,18 @@ impl<'a> CompileState<'a> {
             self.define_struct(&command.identifier, &command.fields)?;
         }
 
+        // Extensions are called like pure functions, so policy functions cannot reuse their
+        // names.
+        for ext in self.extensions {
+            self.function_signatures.insert(
+                ext.name,
+                FunctionSignature {
+                    args: ext.args.iter().map(|a| VType::from(&a.vtype)).collect(),
+                    color: FunctionColor::Pure(VType::from(&ext.return_type)),
+                },
+            );
+        }
+
         // Define the finish function signatures before compiling them, so that they can be
         // used to catch usage errors in regular functions.
         for function_def in &self.policy.finish_functions {
@@ -1728,13 +1962,63 @@ impl<'a> CompileState<'a> {
             self.exit_statement_context();
         }
 
+        if let Some(cycle) = self.call_graph.find_cycle() {
+            return Err(CompileError::new(CompileErrorType::Recursion(
+                cycle.into_iter().map(|l| l.name).collect(),
+            )));
+        }
+
+        self.check_unused_functions()?;
+        self.check_shadowing()?;
+
         self.resolve_targets()?;
 
+        if self.optimize {
+            self.peephole_optimize()?;
+        }
+
+        self.verify_targets()?;
+
+        if let Some(limit) = self.max_stack_depth {
+            self.check_stack_depth(limit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that each FFI module imported with a minimum version is at least that version.
+    /// Stubbed FFI modules are not checked.
+    fn check_ffi_versions(&self) -> Result<(), CompileError> {
+        if self.stub_ffi {
+            return Ok(());
+        }
+        for requirement in &self.policy.ffi_versions {
+            let schema = self
+                .ffi_modules
+                .iter()
+                .find(|m| m.name == requirement.module)
+                .ok_or_else(|| {
+                    self.err_loc(
+                        CompileErrorType::NotDefined(requirement.module.clone()),
+                        requirement.locator,
+                    )
+                })?;
+            if schema.version < requirement.min_version {
+                return Err(self.err_loc(
+                    CompileErrorType::FfiVersionMismatch {
+                        module: requirement.module.clone(),
+                        required: requirement.min_version,
+                        found: schema.version,
+                    },
+                    requirement.locator,
+                ));
+            }
+        }
         Ok(())
     }
 
     /// Finish compilation; return the internal machine
-    pub fn into_module(self) -> Module {
+    fn into_module(self) -> Module {
         self.m.into_module()
     }
 }
@@ -1752,8 +2036,14 @@ enum Scope {
 pub struct Compiler<'a> {
     policy: &'a AstPolicy,
     ffi_modules: &'a [ModuleSchema<'a>],
+    extensions: &'a [Func<'a>],
     is_debug: bool,
     stub_ffi: bool,
+    optimize: bool,
+    max_stack_depth: Option<usize>,
+    passes: Vec<Box<dyn CompilerPass + 'a>>,
+    lints: LintLevels,
+    attribute_schema: Option<BTreeMap<String, VType>>,
 }
 
 impl<'a> Compiler<'a> {
@@ -1762,8 +2052,14 @@ impl<'a> Compiler<'a> {
         Self {
             policy,
             ffi_modules: &[],
+            extensions: &[],
             is_debug: cfg!(debug_assertions),
             stub_ffi: false,
+            optimize: true,
+            max_stack_depth: None,
+            passes: vec![],
+            lints: LintLevels::default(),
+            attribute_schema: None,
         }
     }
 
@@ -1773,6 +2069,14 @@ impl<'a> Compiler<'a> {
         self
     }
 
+    /// Sets the host-defined instructions which policies may call like pure functions. Each
+    /// call compiles to an [`Instruction::Extension`], which the host must register with the
+    /// VM before running the policy.
+    pub fn extensions(mut self, extensions: &'a [Func<'a>]) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
     /// Enables or disables debug mode
     pub fn debug(mut self, is_debug: bool) -> Self {
         self.is_debug = is_debug;
@@ -1784,10 +2088,65 @@ impl<'a> Compiler<'a> {
         self
     }
 
-    /// Consumes the builder to create a [`Module`]
+    /// Enables or disables collapsing wasteful instruction sequences. Enabled by default.
+    pub fn optimize(mut self, flag: bool) -> Self {
+        self.optimize = flag;
+        self
+    }
+
+    /// Rejects policies whose actions or commands may use more than `limit` stack slots
+    pub fn max_stack_depth(mut self, limit: usize) -> Self {
+        self.max_stack_depth = Some(limit);
+        self
+    }
+
+    /// Sets whether findings of `lint` are ignored, returned as warnings, or stop compilation.
+    /// See [`Lint`] for the default levels.
+    pub fn lint(mut self, lint: Lint, level: LintLevel) -> Self {
+        self.lints.set(lint, level);
+        self
+    }
+
+    /// Makes every lint which would warn stop compilation instead
+    pub fn deny_warnings(mut self) -> Self {
+        self.lints.deny_warnings();
+        self
+    }
+
+    /// Checks every command's `attributes` block against `schema`, which gives the expected
+    /// type of each attribute. Attributes not in the schema are rejected, but commands need
+    /// not define every attribute.
+    pub fn attribute_schema(mut self, schema: BTreeMap<String, VType>) -> Self {
+        self.attribute_schema = Some(schema);
+        self
+    }
+
+    /// Adds a custom [`CompilerPass`], which runs after the built-in passes. Passes run in
+    /// the order they were added.
+    pub fn add_pass<P>(mut self, pass: P) -> Self
+    where
+        P: CompilerPass + 'a,
+    {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Consumes the builder to create a [`Module`]. Warnings are discarded.
     pub fn compile(self) -> Result<Module, CompileError> {
+        self.compile_with_warnings().map(|(module, _)| module)
+    }
+
+    /// Consumes the builder to create a [`Module`], along with the findings of any lints
+    /// set to [`LintLevel::Warn`].
+    pub fn compile_with_warnings(self) -> Result<(Module, Vec<CompileError>), CompileError> {
         let codemap = CodeMap::new(&self.policy.text, self.policy.ranges.clone());
-        let machine = CompileTarget::new(codemap);
+        let mut machine = CompileTarget::new(codemap);
+        let fingerprint = Fingerprint::of(self.policy).map_err(|_| {
+            CompileError::new(CompileErrorType::Bug(Bug::new(
+                "policy should be encodable to fingerprint it",
+            )))
+        })?;
+        machine.fingerprint = Some(fingerprint);
         let mut cs = CompileState {
             policy: self.policy,
             m: machine,
@@ -1798,17 +2157,74 @@ impl<'a> Compiler<'a> {
             statement_context: vec![],
             identifier_types: IdentifierTypeStack::new(),
             ffi_modules: self.ffi_modules,
+            extensions: self.extensions,
             enum_values: BTreeMap::new(),
             is_debug: self.is_debug,
             stub_ffi: self.stub_ffi,
+            optimize: self.optimize,
+            max_stack_depth: self.max_stack_depth,
+            call_graph: CallGraph::new(),
+            caller: None,
+            lints: self.lints,
+            warnings: vec![],
+            attribute_schema: self.attribute_schema,
         };
 
         cs.compile()?;
 
-        Ok(cs.into_module())
+        if !self.passes.is_empty() {
+            for mut pass in self.passes {
+                pass.run(&mut cs)?;
+            }
+            // Passes may have rewritten branch targets.
+            cs.verify_targets()?;
+        }
+
+        let warnings = std::mem::take(&mut cs.warnings);
+        Ok((cs.into_module(), warnings))
     }
 }
 
+/// The fewest match values for which a jump table is used instead of a chain of comparisons.
+const JUMP_TABLE_MIN_VALUES: usize = 4;
+
+/// Builds a jump table for a match statement whose arm values are all integer literals, if
+/// the values are dense enough. Returns the lowest value, and the index of the arm selected by
+/// each value from there up to the highest value, or `None` for values no arm selects.
+fn match_jump_table(arms: &[ast::MatchArm]) -> Option<(i64, Vec<Option<usize>>)> {
+    let mut values = vec![];
+    for (i, arm) in arms.iter().enumerate() {
+        if let MatchPattern::Values(vs) = &arm.pattern {
+            for v in vs {
+                let Expression::Int(n) = v else {
+                    return None;
+                };
+                values.push((*n, i));
+            }
+        }
+    }
+    if values.len() < JUMP_TABLE_MIN_VALUES {
+        return None;
+    }
+
+    let min = values.iter().map(|(n, _)| *n).min()?;
+    let max = values.iter().map(|(n, _)| *n).max()?;
+    let len = usize::try_from(max.checked_sub(min)?)
+        .ok()?
+        .checked_add(1)?;
+    // Only use a table when at least half of its entries select an arm.
+    if len > values.len().checked_mul(2)? {
+        return None;
+    }
+
+    let mut table = vec![None; len];
+    for (n, i) in values {
+        let offset = usize::try_from(n.checked_sub(min)?).ok()?;
+        *table.get_mut(offset)? = Some(i);
+    }
+    Some((min, table))
+}
+
 /// Checks whether a vector has duplicate values, and returns the first one, if found.
 ///
 /// Not suitable for large vectors, because complexity is O(n^2).
@@ -1832,30 +2248,28 @@ where
     None
 }
 
-/// Get the `VType` of a fact field. For values that cannot be represented as `VType`, including `Bind`, we return `None`.
-fn field_vtype(f: &FactField) -> Option<VType> {
-    match f {
-        FactField::Expression(e) => {
-            match e {
-                Expression::Int(_) => Some(VType::Int),
-                // Expression::Bytes(_) => Ok(VType::Bytes), // TODO: Bytes expression not implemented
-                Expression::Bool(_) => Some(VType::Bool),
-                Expression::String(_) => Some(VType::String),
-                // We can't resolve var names to values at the moment, so we defer to the machine.
-                Expression::Identifier(_) => None,
-                Expression::NamedStruct(s) => Some(VType::Struct(s.identifier.clone())),
-                Expression::Optional(Some(expr)) => {
-                    let field_expr = FactField::Expression(expr.as_ref().to_owned());
-                    let interior_type = field_vtype(&field_expr)?;
-                    Some(VType::Optional(Box::new(interior_type)))
-                }
-                _ => None,
-            }
+/// Determines whether a statement always leaves the current function or policy block, so
+/// that no statement following it can be executed.
+fn statement_always_exits(s: &ast::Statement) -> bool {
+    match s {
+        ast::Statement::Return(_) | ast::Statement::Finish(_) => true,
+        ast::Statement::Check(c) => c.expression == Expression::Bool(false),
+        // A match without a default arm panics if no arm matches, so it always exits if
+        // every arm does.
+        ast::Statement::Match(m) => m.arms.iter().all(|arm| block_always_exits(&arm.statements)),
+        ast::Statement::If(i) => {
+            i.branches.iter().all(|(_, b)| block_always_exits(b))
+                && i.fallback.as_deref().is_some_and(block_always_exits)
         }
-        FactField::Bind => None,
+        _ => false,
     }
 }
 
+/// Determines whether a block of statements always exits. See [`statement_always_exits`].
+fn block_always_exits(statements: &[AstNode<ast::Statement>]) -> bool {
+    statements.iter().any(|s| statement_always_exits(&s.inner))
+}
+
 /// Get expression value, e.g. Expression::Int => Value::Int
 fn expression_value(e: &Expression) -> Option<Value> {
     match e {
@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use aranya_policy_module::Label;
+
+/// Records which functions and actions call each other, so that recursion and unused
+/// functions can be detected.
+///
+/// Nodes are the labels used to call each item, so pure functions, finish functions, and
+/// actions are kept distinct.
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    edges: BTreeMap<Label, BTreeSet<Label>>,
+    called: BTreeSet<Label>,
+}
+
+impl CallGraph {
+    /// Create an empty `CallGraph`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `caller` calls `callee`
+    pub fn add_call(&mut self, caller: Label, callee: Label) {
+        self.called.insert(callee.clone());
+        self.edges.entry(caller).or_default().insert(callee);
+    }
+
+    /// Record that `callee` is called from outside any function or action, e.g. from a
+    /// command block
+    pub fn add_entry_call(&mut self, callee: Label) {
+        self.called.insert(callee);
+    }
+
+    /// Returns whether anything calls `callee`
+    pub fn is_called(&self, callee: &Label) -> bool {
+        self.called.contains(callee)
+    }
+
+    /// Find a call cycle, if one exists. The cycle is returned as a list of labels which
+    /// begins and ends with the same label, e.g. `[f, g, f]`.
+    pub fn find_cycle(&self) -> Option<Vec<Label>> {
+        let mut done = BTreeSet::new();
+        for start in self.edges.keys() {
+            let mut path = vec![];
+            if let Some(cycle) = self.visit(start, &mut path, &mut done) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    /// Depth-first search for a cycle through `node`. `path` is the chain of calls leading to
+    /// `node`, and `done` holds nodes which are already known not to lead to a cycle.
+    fn visit<'a>(
+        &'a self,
+        node: &'a Label,
+        path: &mut Vec<&'a Label>,
+        done: &mut BTreeSet<&'a Label>,
+    ) -> Option<Vec<Label>> {
+        if let Some(pos) = path.iter().position(|n| *n == node) {
+            let mut cycle: Vec<Label> = path[pos..].iter().map(|&l| l.clone()).collect();
+            cycle.push(node.clone());
+            return Some(cycle);
+        }
+        if done.contains(node) {
+            return None;
+        }
+        path.push(node);
+        for callee in self.edges.get(node).into_iter().flatten() {
+            if let Some(cycle) = self.visit(callee, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(node);
+        None
+    }
+}
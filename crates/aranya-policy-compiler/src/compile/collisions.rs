@@ -0,0 +1,64 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+impl CompileState<'_> {
+    /// Checks that no two top-level definitions of different kinds share a name, reporting
+    /// both definitions if they do. Definitions of the same kind which share a name are
+    /// reported when they are defined.
+    pub(super) fn check_name_collisions(&self) -> Result<(), CompileError> {
+        let policy = self.policy;
+        let mut definitions: Vec<(&str, &'static str, usize)> = Vec::new();
+        for d in &policy.commands {
+            definitions.push((&d.identifier, "command", d.locator));
+        }
+        for d in &policy.actions {
+            definitions.push((&d.identifier, "action", d.locator));
+        }
+        for d in &policy.effects {
+            definitions.push((&d.identifier, "effect", d.locator));
+        }
+        for d in &policy.structs {
+            definitions.push((&d.identifier, "struct", d.locator));
+        }
+        for d in &policy.enums {
+            definitions.push((&d.identifier, "enum", d.locator));
+        }
+        for d in &policy.facts {
+            definitions.push((&d.identifier, "fact", d.locator));
+        }
+        // Report the later definition, in source order.
+        definitions.sort_by_key(|&(_, _, locator)| locator);
+
+        let mut seen = BTreeMap::new();
+        for (name, kind, locator) in definitions {
+            match seen.entry(name) {
+                Entry::Vacant(e) => {
+                    e.insert((kind, locator));
+                }
+                Entry::Occupied(e) => {
+                    let &(first, first_locator) = e.get();
+                    if first == kind {
+                        continue;
+                    }
+                    let first_linecol = self.m.codemap.as_ref().and_then(|codemap| {
+                        codemap
+                            .span_from_locator(first_locator)
+                            .ok()
+                            .map(|span| span.start_linecol())
+                    });
+                    return Err(self.err_loc(
+                        CompileErrorType::NameCollision {
+                            name: name.to_string(),
+                            first,
+                            first_linecol,
+                            second: kind,
+                        },
+                        locator,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
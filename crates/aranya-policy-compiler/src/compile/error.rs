@@ -1,7 +1,7 @@
 use std::fmt;
 
 use aranya_policy_ast as ast;
-use aranya_policy_module::CodeMap;
+use aranya_policy_module::{CodeMap, LabelType};
 use buggy::Bug;
 
 use crate::compile::StatementContext;
@@ -35,6 +35,18 @@ pub enum CompileErrorType {
     NotDefined(String),
     /// A thing by that name has already been defined
     AlreadyDefined(String),
+    /// Two top-level definitions of different kinds share a name. The error points at the
+    /// second definition.
+    NameCollision {
+        /// The shared name
+        name: String,
+        /// The kind of the first definition, e.g. `fact`
+        first: &'static str,
+        /// The line and column of the first definition, if known
+        first_linecol: Option<(usize, usize)>,
+        /// The kind of the second definition
+        second: &'static str,
+    },
     /// A keyword collision occurs with that identifier
     ReservedIdentifier(String),
     /// Expected value was missing
@@ -43,6 +55,34 @@ pub enum CompileErrorType {
     InvalidFactLiteral(String),
     /// A pure function has no return statement
     NoReturn,
+    /// A published command has no seal or open block. Contains the command name and the
+    /// kind of block which is missing.
+    MissingCommandBlock(String, LabelType),
+    /// A statement can never be executed because a previous statement always exits
+    Unreachable,
+    /// A thing is defined but never used
+    Unused(String),
+    /// A `let` rebinds a name which is already defined
+    Shadowed(String),
+    /// Functions or actions call each other recursively. Contains the names in the call
+    /// cycle, beginning and ending with the same name.
+    Recursion(Vec<String>),
+    /// The worst-case stack depth of an entry point exceeds the configured limit, or
+    /// cannot be determined
+    StackDepthExceeded(String),
+    /// An FFI module is older than the version the policy requires
+    FfiVersionMismatch {
+        /// The module name
+        module: String,
+        /// The lowest version the policy accepts
+        required: u32,
+        /// The version of the module provided to the compiler
+        found: u32,
+    },
+    /// A `static_assert` evaluated to false. Contains the assertion's message.
+    StaticAssertFailed(String),
+    /// A custom [`CompilerPass`](crate::CompilerPass) rejected the policy
+    PassFailed(String),
     /// A validation step failed
     Validation,
     /// An implementation bug
@@ -65,10 +105,46 @@ impl fmt::Display for CompileErrorType {
             Self::BadArgument(s) => write!(f, "Bad argument: {}", s),
             Self::NotDefined(s) => write!(f, "Not defined: {}", s),
             Self::AlreadyDefined(s) => write!(f, "Already defined: {}", s),
+            Self::NameCollision {
+                name,
+                first,
+                first_linecol,
+                second,
+            } => {
+                write!(
+                    f,
+                    "Name collision: {second} `{name}` conflicts with {first} `{name}`"
+                )?;
+                if let Some((line, col)) = first_linecol {
+                    write!(f, " (defined at line {line} col {col})")?;
+                }
+                Ok(())
+            }
             Self::ReservedIdentifier(s) => write!(f, "Reserved identifier: {}", s),
             Self::Missing(s) => write!(f, "Missing: {}", s),
             Self::InvalidFactLiteral(s) => write!(f, "Fact literal does not match definition: {s}"),
             Self::NoReturn => write!(f, "Function has no return statement"),
+            Self::MissingCommandBlock(command, block) => {
+                write!(
+                    f,
+                    "Command `{command}` is published but has no {block} block"
+                )
+            }
+            Self::Unreachable => write!(f, "Unreachable code"),
+            Self::Unused(s) => write!(f, "Unused: {}", s),
+            Self::Shadowed(s) => write!(f, "`{}` shadows an earlier definition", s),
+            Self::Recursion(cycle) => write!(f, "Recursive call cycle: {}", cycle.join(" -> ")),
+            Self::StackDepthExceeded(s) => write!(f, "Stack depth exceeded: {}", s),
+            Self::FfiVersionMismatch {
+                module,
+                required,
+                found,
+            } => write!(
+                f,
+                "FFI module `{module}` is version {found}, but version {required} or later is required"
+            ),
+            Self::StaticAssertFailed(s) => write!(f, "Static assertion failed: {}", s),
+            Self::PassFailed(s) => write!(f, "Compiler pass failed: {}", s),
             Self::Validation => write!(f, "Validation failed"),
             Self::Bug(bug) => write!(f, "Bug: {}", bug),
             Self::Unknown(s) => write!(f, "Unknown error: {}", s),
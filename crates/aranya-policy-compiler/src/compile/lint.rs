@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use aranya_policy_ast::{AstNode, Statement};
+use aranya_policy_module::{Label, LabelType};
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+/// An analysis whose findings can be reported as warnings or errors. The level of each lint
+/// is set with [`Compiler::lint`](crate::Compiler::lint).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Lint {
+    /// A statement follows a statement which always exits. Denied by default.
+    Unreachable,
+    /// A function or finish function is never called. Warns by default.
+    UnusedFunction,
+    /// A `let` rebinds a name already defined in the same or an enclosing block, or by an
+    /// argument. Warns by default. Shadowing a global value is always an error.
+    Shadowing,
+}
+
+impl Lint {
+    /// The level of this lint when it has not been set
+    pub fn default_level(self) -> LintLevel {
+        match self {
+            Self::Unreachable => LintLevel::Deny,
+            Self::UnusedFunction | Self::Shadowing => LintLevel::Warn,
+        }
+    }
+}
+
+/// How the findings of a [`Lint`] are reported
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Findings are ignored
+    Allow,
+    /// Findings are returned as warnings, and compilation continues
+    Warn,
+    /// Findings stop compilation with an error
+    Deny,
+}
+
+/// The configured level of each lint.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LintLevels {
+    levels: BTreeMap<Lint, LintLevel>,
+    deny_warnings: bool,
+}
+
+impl LintLevels {
+    /// Sets the level of `lint`
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    /// Treats every lint which would warn as denied
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings = true;
+    }
+
+    /// The effective level of `lint`
+    pub fn get(&self, lint: Lint) -> LintLevel {
+        let level = self
+            .levels
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level());
+        if self.deny_warnings && level == LintLevel::Warn {
+            LintLevel::Deny
+        } else {
+            level
+        }
+    }
+}
+
+impl CompileState<'_> {
+    /// Reports a finding of `lint` at its configured level. Returns `err` if the lint is
+    /// denied.
+    pub(super) fn lint(&mut self, lint: Lint, err: CompileError) -> Result<(), CompileError> {
+        match self.lints.get(lint) {
+            LintLevel::Allow => Ok(()),
+            LintLevel::Warn => {
+                self.warnings.push(err);
+                Ok(())
+            }
+            LintLevel::Deny => Err(err),
+        }
+    }
+
+    /// Reports functions and finish functions which are never called.
+    pub(super) fn check_unused_functions(&mut self) -> Result<(), CompileError> {
+        let policy = self.policy;
+        let functions = policy
+            .functions
+            .iter()
+            .map(|f| (Label::new(&f.identifier, LabelType::Function), f.locator));
+        let finish_functions = policy
+            .finish_functions
+            .iter()
+            .map(|f| (Label::new_temp(&f.identifier), f.locator));
+        for (label, locator) in functions.chain(finish_functions) {
+            if !self.call_graph.is_called(&label) {
+                let err = self.err_loc(
+                    CompileErrorType::Unused(format!("function `{}`", label.name)),
+                    locator,
+                );
+                self.lint(Lint::UnusedFunction, err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CompileState<'_> {
+    /// Reports `let` statements which rebind a name that is already visible.
+    pub(super) fn check_shadowing(&mut self) -> Result<(), CompileError> {
+        let policy = self.policy;
+        let mut found = vec![];
+        for f in &policy.functions {
+            let args = f.arguments.iter().map(|a| a.identifier.as_str()).collect();
+            find_shadowing(&f.statements, &mut vec![args], &mut found);
+        }
+        for f in &policy.finish_functions {
+            let args = f.arguments.iter().map(|a| a.identifier.as_str()).collect();
+            find_shadowing(&f.statements, &mut vec![args], &mut found);
+        }
+        for a in &policy.actions {
+            let args = a.arguments.iter().map(|a| a.identifier.as_str()).collect();
+            find_shadowing(&a.statements, &mut vec![args], &mut found);
+        }
+        for c in &policy.commands {
+            find_shadowing(&c.policy, &mut vec![vec!["this", "envelope"]], &mut found);
+            find_shadowing(&c.recall, &mut vec![vec!["this", "envelope"]], &mut found);
+            find_shadowing(&c.seal, &mut vec![vec!["this"]], &mut found);
+            find_shadowing(&c.open, &mut vec![vec!["envelope"]], &mut found);
+        }
+
+        for (name, locator) in found {
+            let err = self.err_loc(CompileErrorType::Shadowed(name.to_string()), locator);
+            self.lint(Lint::Shadowing, err)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the `let` statements in `statements` which rebind a name defined in `scopes`
+/// or earlier in the same block. Branches are separate blocks, so definitions in one
+/// branch do not conflict with those in another.
+fn find_shadowing<'a>(
+    statements: &'a [AstNode<Statement>],
+    scopes: &mut Vec<Vec<&'a str>>,
+    found: &mut Vec<(&'a str, usize)>,
+) {
+    scopes.push(vec![]);
+    for statement in statements {
+        match &statement.inner {
+            Statement::Let(s) => {
+                if scopes.iter().flatten().any(|name| *name == s.identifier) {
+                    found.push((&s.identifier, statement.locator));
+                }
+                if let Some(scope) = scopes.last_mut() {
+                    scope.push(&s.identifier);
+                }
+            }
+            Statement::If(s) => {
+                for (_, branch) in &s.branches {
+                    find_shadowing(branch, scopes, found);
+                }
+                if let Some(fallback) = &s.fallback {
+                    find_shadowing(fallback, scopes, found);
+                }
+            }
+            Statement::Match(s) => {
+                for arm in &s.arms {
+                    find_shadowing(&arm.statements, scopes, found);
+                }
+            }
+            Statement::Map(s) => {
+                scopes.push(vec![&s.identifier]);
+                find_shadowing(&s.statements, scopes, found);
+                scopes.pop();
+            }
+            Statement::Finish(block) => find_shadowing(block, scopes, found),
+            _ => {}
+        }
+    }
+    scopes.pop();
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use aranya_policy_module::{Instruction, Label};
+
+use crate::{compile::CompileState, AstPolicy, CompileError, CompileErrorType};
+
+/// A custom analysis or transformation which runs over a compiled policy.
+///
+/// Passes are added with [`Compiler::add_pass`](crate::Compiler::add_pass) and run in the
+/// order they were added, once the policy has been compiled, its branch targets have been
+/// resolved, and the built-in checks have succeeded. Returning an error stops compilation.
+///
+/// ```ignore
+/// struct NoEmit;
+///
+/// impl CompilerPass for NoEmit {
+///     fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError> {
+///         match state.instructions().iter().position(|i| *i == Instruction::Emit) {
+///             Some(addr) => Err(state.error_at(
+///                 CompileErrorType::PassFailed(String::from("effects are not allowed")),
+///                 addr,
+///             )),
+///             None => Ok(()),
+///         }
+///     }
+/// }
+///
+/// let module = Compiler::new(&policy).add_pass(NoEmit).compile()?;
+/// ```
+pub trait CompilerPass {
+    /// Runs the pass over the compiled policy
+    fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError>;
+}
+
+impl<P: CompilerPass + ?Sized> CompilerPass for &mut P {
+    fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError> {
+        (**self).run(state)
+    }
+}
+
+impl<'a> CompileState<'a> {
+    /// The policy being compiled
+    pub fn policy(&self) -> &'a AstPolicy {
+        self.policy
+    }
+
+    /// The compiled instructions
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.m.progmem
+    }
+
+    /// The compiled instructions, for passes which rewrite them in place. Instructions cannot
+    /// be added or removed, since that would invalidate labels and branch targets.
+    pub fn instructions_mut(&mut self) -> &mut [Instruction] {
+        &mut self.m.progmem
+    }
+
+    /// The addresses of actions, commands, and functions
+    pub fn labels(&self) -> &BTreeMap<Label, usize> {
+        &self.m.labels
+    }
+
+    /// Creates an error which points at the source code that produced the instruction at
+    /// `addr`, if it is known.
+    pub fn error_at(&self, err_type: CompileErrorType, addr: usize) -> CompileError {
+        match self
+            .m
+            .codemap
+            .as_ref()
+            .and_then(|codemap| codemap.locator_from_instruction(addr).ok())
+        {
+            Some(locator) => self.err_loc(err_type, locator),
+            None => CompileError::new(err_type),
+        }
+    }
+}
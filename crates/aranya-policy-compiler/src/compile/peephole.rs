@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use aranya_policy_module::{Instruction, Target};
+use buggy::BugExt;
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+impl CompileState<'_> {
+    /// Collapses known-wasteful instruction sequences, repeating until no more can be
+    /// collapsed. Branch targets, labels, and the code map are updated to match.
+    ///
+    /// This must be run after branch targets have been resolved.
+    pub(super) fn peephole_optimize(&mut self) -> Result<(), CompileError> {
+        loop {
+            let targets = self.branch_targets();
+            let progmem = &self.m.progmem;
+
+            let mut out = Vec::with_capacity(progmem.len());
+            // The new address of each old address. Instructions which are collapsed map to the
+            // start of their replacement.
+            let mut remap = Vec::with_capacity(progmem.len());
+            let mut pc = 0;
+            while let Some(rest) = progmem.get(pc..).filter(|r| !r.is_empty()) {
+                let end = |n: usize| pc.checked_add(n).assume("pc + n must not wrap");
+                let next = end(1)?;
+                let mut rewrite = peephole(rest, next);
+                if let Some((n, _)) = rewrite {
+                    // Only the first instruction of a sequence may be branched to.
+                    if (next..end(n)?).any(|a| targets.contains(&a)) {
+                        rewrite = None;
+                    }
+                }
+                let (n, replacement) = rewrite.unwrap_or_else(|| (1, rest[..1].to_vec()));
+                remap.extend(std::iter::repeat(out.len()).take(n));
+                out.extend(replacement);
+                pc = end(n)?;
+            }
+            // Allow addresses one past the end, which some code map entries use.
+            remap.push(out.len());
+
+            if out.len() == progmem.len() {
+                return Ok(());
+            }
+
+            let relocate = |addr: usize| {
+                remap
+                    .get(addr)
+                    .copied()
+                    .ok_or_else(|| CompileErrorType::BadTarget(format!("address {addr}")))
+            };
+            for instr in &mut out {
+                for t in targets_mut(instr) {
+                    if let Target::Resolved(addr) = t {
+                        *addr = relocate(*addr)?;
+                    }
+                }
+            }
+            for addr in self.m.labels.values_mut() {
+                *addr = relocate(*addr)?;
+            }
+            let len = out.len();
+            if let Some(codemap) = &mut self.m.codemap {
+                codemap.remap_instructions(|i| remap.get(i).copied().unwrap_or(len));
+            }
+            self.m.progmem = out;
+            self.wp = len;
+        }
+    }
+
+    /// Collects every address which is branched to or labeled.
+    fn branch_targets(&mut self) -> BTreeSet<usize> {
+        let mut targets: BTreeSet<usize> = self.m.labels.values().copied().collect();
+        for instr in &mut self.m.progmem {
+            targets.extend(targets_mut(instr).iter().filter_map(Target::resolved));
+        }
+        targets
+    }
+}
+
+/// The start of the sequence emitted for `a >= b` and `a <= b`, which leaves `a == b` below
+/// `a` and `b`. It is followed by `Gt` or `Lt`, and then `Or`.
+const OR_EQUAL_PREFIX: [Instruction; 5] = [
+    Instruction::Dup(1),
+    Instruction::Dup(1),
+    Instruction::Eq,
+    Instruction::Swap(2),
+    Instruction::Swap(1),
+];
+
+/// Matches a wasteful sequence at the start of `code`, whose instruction after the first is
+/// at address `next`. Returns the length of the sequence and its replacement.
+fn peephole(code: &[Instruction], next: usize) -> Option<(usize, Vec<Instruction>)> {
+    // `a > b || a == b` is `!(a < b)`, and `a < b || a == b` is `!(a > b)`
+    if let Some(rest) = code.strip_prefix(&OR_EQUAL_PREFIX[..]) {
+        let inverse = match rest {
+            [Instruction::Gt, Instruction::Or, ..] => Some(Instruction::Lt),
+            [Instruction::Lt, Instruction::Or, ..] => Some(Instruction::Gt),
+            _ => None,
+        };
+        if let Some(inverse) = inverse {
+            return Some((7, vec![inverse, Instruction::Not]));
+        }
+    }
+
+    match code {
+        // A value which is pushed and immediately popped
+        [push, Instruction::Pop, ..]
+            if matches!(
+                push,
+                Instruction::Const(_) | Instruction::Get(_) | Instruction::Dup(_)
+            ) =>
+        {
+            Some((2, vec![]))
+        }
+        // Swaps which cancel out
+        [Instruction::Swap(a), Instruction::Swap(b), ..] if a == b => Some((2, vec![])),
+        // A jump to the next instruction
+        [Instruction::Jump(Target::Resolved(addr)), ..] if *addr == next => Some((1, vec![])),
+        _ => None,
+    }
+}
+
+/// The branch targets of an instruction.
+fn targets_mut(instr: &mut Instruction) -> &mut [Target] {
+    match instr {
+        Instruction::Jump(t) | Instruction::Branch(t) | Instruction::Call(t) => {
+            std::slice::from_mut(t)
+        }
+        Instruction::JumpTable(_, targets) => targets,
+        _ => &mut [],
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use aranya_policy_module::{Instruction, Label, LabelType, Target};
+use buggy::BugExt;
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+/// The stack usage of a function, relative to the depth of the stack when it was entered.
+#[derive(Clone, Copy, Debug)]
+struct FrameDepth {
+    /// The deepest the stack gets while executing the function
+    max: isize,
+    /// The depth of the stack when the function returns, or `None` if it never returns
+    exit: Option<isize>,
+}
+
+impl CompileState<'_> {
+    /// Computes the worst-case stack depth of every action and command entry point, and
+    /// fails if any of them may exceed `limit`.
+    ///
+    /// This must be run after branch targets have been resolved.
+    pub(super) fn check_stack_depth(&self, limit: usize) -> Result<(), CompileError> {
+        let mut frames = BTreeMap::new();
+        for (label, &addr) in &self.m.labels {
+            let initial = match label.ltype {
+                // Action arguments are pushed onto the stack before the action is called.
+                LabelType::Action => self.m.action_defs.get(&label.name).map_or(0, |a| a.len()),
+                // The envelope or command struct is pushed before calling these.
+                LabelType::CommandPolicy
+                | LabelType::CommandRecall
+                | LabelType::CommandSeal
+                | LabelType::CommandOpen => 1,
+                LabelType::Function | LabelType::Temporary => continue,
+            };
+            let frame = self.frame_depth(addr, limit, &mut frames, &mut vec![])?;
+            let depth = frame.max.max(0).unsigned_abs().saturating_add(initial);
+            if depth > limit {
+                return Err(self.stack_depth_err(label, depth, limit));
+            }
+        }
+        Ok(())
+    }
+
+    fn stack_depth_err(&self, label: &Label, depth: usize, limit: usize) -> CompileError {
+        CompileError::new(CompileErrorType::StackDepthExceeded(format!(
+            "{} `{}` may use {depth} stack slots, but the limit is {limit}",
+            label.ltype, label.name
+        )))
+    }
+
+    /// Finds the label name for an address, for diagnostics.
+    fn label_at(&self, addr: usize) -> Label {
+        self.m
+            .labels
+            .iter()
+            .find(|(_, &a)| a == addr)
+            .map(|(l, _)| l.clone())
+            .unwrap_or_else(|| Label::new(&format!("<{addr}>"), LabelType::Function))
+    }
+
+    /// Traces every path from `entry` to compute its [`FrameDepth`]. Results are memoized in
+    /// `frames`, and `active` holds the entry points currently being traced so that recursion
+    /// can be detected.
+    fn frame_depth(
+        &self,
+        entry: usize,
+        limit: usize,
+        frames: &mut BTreeMap<usize, FrameDepth>,
+        active: &mut Vec<usize>,
+    ) -> Result<FrameDepth, CompileError> {
+        if let Some(frame) = frames.get(&entry) {
+            return Ok(*frame);
+        }
+        if active.contains(&entry) {
+            let label = self.label_at(entry);
+            return Err(CompileError::new(CompileErrorType::StackDepthExceeded(
+                format!(
+                    "{} `{}` is recursive, so its stack depth cannot be bounded",
+                    label.ltype, label.name
+                ),
+            )));
+        }
+        active.push(entry);
+
+        // The greatest depth we have seen at each address. Addresses are only traced again if
+        // they are reached with a deeper stack, so this terminates unless the stack grows
+        // without bound, which is caught by the limit.
+        let mut seen: BTreeMap<usize, isize> = BTreeMap::new();
+        let mut work = vec![(entry, 0isize)];
+        let mut frame = FrameDepth { max: 0, exit: None };
+
+        while let Some((pc, depth)) = work.pop() {
+            if seen.get(&pc).is_some_and(|&d| d >= depth) {
+                continue;
+            }
+            seen.insert(pc, depth);
+
+            if depth.max(0).unsigned_abs() > limit {
+                let label = self.label_at(entry);
+                return Err(self.stack_depth_err(&label, depth.unsigned_abs(), limit));
+            }
+
+            let instr = self
+                .m
+                .progmem
+                .get(pc)
+                .ok_or_else(|| CompileErrorType::BadTarget(format!("address {pc}")))?;
+            let next = pc.checked_add(1).assume("pc + 1 must not wrap")?;
+
+            let after = match instr {
+                Instruction::Return => {
+                    frame.exit = Some(frame.exit.map_or(depth, |e| e.max(depth)));
+                    continue;
+                }
+                Instruction::Exit(_) => continue,
+                Instruction::Jump(t) => {
+                    work.push((resolved(t)?, depth));
+                    continue;
+                }
+                Instruction::Branch(t) => {
+                    let after = depth.saturating_sub(1);
+                    work.push((resolved(t)?, after));
+                    after
+                }
+                Instruction::JumpTable(_, targets) => {
+                    let after = depth.saturating_sub(1);
+                    for t in targets {
+                        work.push((resolved(t)?, after));
+                    }
+                    after
+                }
+                Instruction::Call(t) => {
+                    let callee = self.frame_depth(resolved(t)?, limit, frames, active)?;
+                    frame.max = frame.max.max(depth.saturating_add(callee.max));
+                    match callee.exit {
+                        Some(exit) => depth.saturating_add(exit),
+                        // The callee never returns, so neither does this path.
+                        None => continue,
+                    }
+                }
+                Instruction::ExtCall(module, procedure) => {
+                    let args = self
+                        .ffi_modules
+                        .get(*module)
+                        .and_then(|m| m.functions.get(*procedure))
+                        .map_or(0, |f| f.args.len());
+                    // Arguments are popped and the result is pushed.
+                    depth
+                        .saturating_sub(isize::try_from(args).unwrap_or(isize::MAX))
+                        .saturating_add(1)
+                }
+                Instruction::Extension(name) => {
+                    let args = self
+                        .extensions
+                        .iter()
+                        .find(|ext| ext.name == name.as_str())
+                        .map_or(0, |ext| ext.args.len());
+                    // Arguments are popped and the result is pushed.
+                    depth
+                        .saturating_sub(isize::try_from(args).unwrap_or(isize::MAX))
+                        .saturating_add(1)
+                }
+                i => depth.saturating_add(stack_effect(i)),
+            };
+
+            frame.max = frame.max.max(after);
+            work.push((next, after));
+        }
+
+        active.pop();
+        frames.insert(entry, frame);
+        Ok(frame)
+    }
+}
+
+fn resolved(t: &Target) -> Result<usize, CompileError> {
+    match t {
+        Target::Resolved(addr) => Ok(*addr),
+        Target::Unresolved(l) => Err(CompileErrorType::BadTarget(l.name.clone()).into()),
+    }
+}
+
+/// The net change in stack depth caused by an instruction which does not change control flow.
+fn stack_effect(i: &Instruction) -> isize {
+    match i {
+        Instruction::Const(_)
+        | Instruction::Get(_)
+        | Instruction::Dup(_)
+        | Instruction::FactNew(_)
+        | Instruction::StructNew(_)
+        | Instruction::QueryNext(_) => 1,
+        Instruction::Def(_)
+        | Instruction::Pop
+        | Instruction::Add
+        | Instruction::Sub
+        | Instruction::And
+        | Instruction::Or
+        | Instruction::Gt
+        | Instruction::Lt
+        | Instruction::Eq
+        | Instruction::FactKeySet(_)
+        | Instruction::FactValueSet(_)
+        | Instruction::StructSet(_)
+        | Instruction::Publish
+        | Instruction::Create
+        | Instruction::Delete
+        | Instruction::Emit
+        | Instruction::QueryStart => -1,
+        Instruction::Update => -2,
+        _ => 0,
+    }
+}
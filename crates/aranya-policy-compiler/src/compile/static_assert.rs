@@ -0,0 +1,113 @@
+use aranya_policy_ast::Expression;
+use aranya_policy_module::Value;
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+impl CompileState<'_> {
+    /// Evaluates every `static_assert` in the policy, failing on the first which is false.
+    ///
+    /// This must be run after global values and enums have been defined.
+    pub(super) fn check_static_asserts(&self) -> Result<(), CompileError> {
+        for assertion in &self.policy.static_asserts {
+            let value = self
+                .eval_constant(&assertion.expression)
+                .map_err(|e| self.err_loc(e, assertion.locator))?;
+            match value {
+                Value::Bool(true) => {}
+                Value::Bool(false) => {
+                    return Err(self.err_loc(
+                        CompileErrorType::StaticAssertFailed(assertion.message.clone()),
+                        assertion.locator,
+                    ))
+                }
+                _ => {
+                    return Err(self.err_loc(
+                        CompileErrorType::InvalidType(String::from(
+                            "static_assert must have boolean expression",
+                        )),
+                        assertion.locator,
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates an expression made of literals, global values, and enum references.
+    fn eval_constant(&self, expression: &Expression) -> Result<Value, CompileErrorType> {
+        let int = |e: &Expression| match self.eval_constant(e)? {
+            Value::Int(n) => Ok(n),
+            v => Err(CompileErrorType::InvalidType(format!(
+                "expected int, got {v}"
+            ))),
+        };
+        let bool = |e: &Expression| match self.eval_constant(e)? {
+            Value::Bool(b) => Ok(b),
+            v => Err(CompileErrorType::InvalidType(format!(
+                "expected bool, got {v}"
+            ))),
+        };
+        let overflow = || CompileErrorType::BadArgument(String::from("integer overflow"));
+
+        Ok(match expression {
+            Expression::Int(n) => Value::Int(*n),
+            Expression::Bool(b) => Value::Bool(*b),
+            Expression::String(s) => Value::String(s.clone()),
+            Expression::Optional(None) => Value::None,
+            Expression::Optional(Some(e)) => self.eval_constant(e)?,
+            Expression::Identifier(name) => self
+                .m
+                .globals
+                .get(name)
+                .cloned()
+                .ok_or_else(|| CompileErrorType::NotDefined(name.clone()))?,
+            Expression::EnumReference(e) => {
+                let defined = self
+                    .enum_values
+                    .get(e.identifier.as_str())
+                    .is_some_and(|values| values.contains(&e.value.as_str()));
+                if !defined {
+                    return Err(CompileErrorType::NotDefined(format!(
+                        "{}::{}",
+                        e.identifier, e.value
+                    )));
+                }
+                Value::Enum(e.identifier.clone(), e.value.clone())
+            }
+            Expression::Dot(e, field) => {
+                match self.eval_constant(e)? {
+                    Value::Struct(s) => s.fields.get(field).cloned().ok_or_else(|| {
+                        CompileErrorType::NotDefined(format!("{}.{field}", s.name))
+                    })?,
+                    v => {
+                        return Err(CompileErrorType::InvalidType(format!(
+                            "expected struct, got {v}"
+                        )))
+                    }
+                }
+            }
+            Expression::Add(a, b) => Value::Int(int(a)?.checked_add(int(b)?).ok_or_else(overflow)?),
+            Expression::Subtract(a, b) => {
+                Value::Int(int(a)?.checked_sub(int(b)?).ok_or_else(overflow)?)
+            }
+            Expression::Negative(e) => Value::Int(int(e)?.checked_neg().ok_or_else(overflow)?),
+            Expression::GreaterThan(a, b) => Value::Bool(int(a)? > int(b)?),
+            Expression::LessThan(a, b) => Value::Bool(int(a)? < int(b)?),
+            Expression::GreaterThanOrEqual(a, b) => Value::Bool(int(a)? >= int(b)?),
+            Expression::LessThanOrEqual(a, b) => Value::Bool(int(a)? <= int(b)?),
+            Expression::Equal(a, b) => {
+                Value::Bool(self.eval_constant(a)? == self.eval_constant(b)?)
+            }
+            Expression::NotEqual(a, b) => {
+                Value::Bool(self.eval_constant(a)? != self.eval_constant(b)?)
+            }
+            Expression::And(a, b) => Value::Bool(bool(a)? && bool(b)?),
+            Expression::Or(a, b) => Value::Bool(bool(a)? || bool(b)?),
+            Expression::Not(e) => Value::Bool(!bool(e)?),
+            Expression::Is(e, is_some) => {
+                Value::Bool((self.eval_constant(e)? != Value::None) == *is_some)
+            }
+            _ => return Err(CompileErrorType::InvalidExpression(expression.clone())),
+        })
+    }
+}
@@ -1,7 +1,9 @@
 use std::{collections::BTreeMap, fmt::Display};
 
 use aranya_policy_ast as ast;
-use aranya_policy_module::{CodeMap, Instruction, Label, Module, ModuleData, ModuleV0, Value};
+use aranya_policy_module::{
+    CodeMap, Fingerprint, Instruction, Label, Module, ModuleData, ModuleV1, Value,
+};
 use ast::FactDefinition;
 
 /// This is a stripped down version of the VM `Machine` type, which exists to be a target
@@ -28,6 +30,10 @@ pub struct CompileTarget {
     pub codemap: Option<CodeMap>,
     /// Globally scoped variables
     pub globals: BTreeMap<String, Value>,
+    /// Fingerprint of the policy source
+    pub fingerprint: Option<Fingerprint>,
+    /// Effect schemas, which are also struct schemas
+    pub effect_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
 }
 
 impl CompileTarget {
@@ -43,13 +49,15 @@ impl CompileTarget {
             command_attributes: BTreeMap::new(),
             codemap: Some(codemap),
             globals: BTreeMap::new(),
+            fingerprint: None,
+            effect_defs: BTreeMap::new(),
         }
     }
 
     /// Converts the `CompileTarget` into a `Module`.
     pub fn into_module(self) -> Module {
         Module {
-            data: ModuleData::V0(ModuleV0 {
+            data: ModuleData::V1(ModuleV1 {
                 progmem: self.progmem.into_boxed_slice(),
                 labels: self.labels,
                 action_defs: self.action_defs,
@@ -59,6 +67,8 @@ impl CompileTarget {
                 command_attributes: self.command_attributes,
                 codemap: self.codemap,
                 globals: self.globals,
+                fingerprint: self.fingerprint,
+                effect_defs: self.effect_defs,
             }),
         }
     }
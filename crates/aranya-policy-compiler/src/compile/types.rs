@@ -261,7 +261,10 @@ impl CompileState<'_> {
     }
 
     /// Attempt to determine the type of an expression
-    pub fn calculate_expression_type(&self, expression: &Expression) -> Result<Typeish, TypeError> {
+    pub(super) fn calculate_expression_type(
+        &self,
+        expression: &Expression,
+    ) -> Result<Typeish, TypeError> {
         match expression {
             Expression::Int(_) => Ok(Typeish::Type(VType::Int)),
             Expression::String(_) => Ok(Typeish::Type(VType::String)),
@@ -299,6 +302,7 @@ impl CompileState<'_> {
                     // we're in to determine this concretely
                     Ok(Typeish::Indeterminate)
                 }
+                ast::InternalFunction::PolicyFingerprint => Ok(Typeish::Type(VType::Bytes)),
                 ast::InternalFunction::FactCount(cmp_type, _, _) => match cmp_type {
                     ast::FactCountType::UpTo => Ok(Typeish::Type(VType::Int)),
                     _ => Ok(Typeish::Type(VType::Bool)),
@@ -0,0 +1,67 @@
+use std::collections::BTreeSet;
+
+use aranya_policy_module::{Instruction, Target};
+
+use crate::{compile::CompileState, CompileError, CompileErrorType};
+
+impl CompileState<'_> {
+    /// Checks that every branch target is resolved and within program memory, and that
+    /// jumps and branches stay within the region of code they belong to. A region begins at
+    /// each label and each call target, and continues until the next one. Since program
+    /// memory holds whole instructions, any address in bounds is an instruction boundary.
+    ///
+    /// This must be run after branch targets have been resolved.
+    pub(super) fn verify_targets(&self) -> Result<(), CompileError> {
+        let progmem = &self.m.progmem;
+        let bad_target = |addr: usize, instr: &Instruction, problem: &str| {
+            self.error_at(
+                CompileErrorType::BadTarget(format!("`{instr}` at {addr} {problem}")),
+                addr,
+            )
+        };
+
+        let mut region_starts: BTreeSet<usize> = self.m.labels.values().copied().collect();
+        for (addr, instr) in progmem.iter().enumerate() {
+            for t in targets(instr) {
+                match t {
+                    Target::Unresolved(_) => {
+                        return Err(bad_target(addr, instr, "has an unresolved target"))
+                    }
+                    Target::Resolved(dest) if *dest >= progmem.len() => {
+                        return Err(bad_target(addr, instr, "jumps out of bounds"))
+                    }
+                    Target::Resolved(dest) => {
+                        if matches!(instr, Instruction::Call(_)) {
+                            region_starts.insert(*dest);
+                        }
+                    }
+                }
+            }
+        }
+
+        let region = |addr: usize| region_starts.range(..=addr).next_back();
+        for (addr, instr) in progmem.iter().enumerate() {
+            if matches!(instr, Instruction::Call(_)) {
+                continue;
+            }
+            for dest in targets(instr).iter().filter_map(Target::resolved) {
+                if region(dest) != region(addr) {
+                    return Err(bad_target(addr, instr, "jumps outside its label region"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The branch targets of an instruction.
+fn targets(instr: &Instruction) -> &[Target] {
+    match instr {
+        Instruction::Jump(t) | Instruction::Branch(t) | Instruction::Call(t) => {
+            std::slice::from_ref(t)
+        }
+        Instruction::JumpTable(_, targets) => targets,
+        _ => &[],
+    }
+}
@@ -1,11 +1,19 @@
 #![cfg(test)]
 
+use std::collections::BTreeMap;
+
 use anyhow::anyhow;
-use aranya_policy_ast::{FieldDefinition, VType, Version};
+use aranya_policy_ast::{AstNode, FactDefinition, FieldDefinition, Policy, VType, Version};
 use aranya_policy_lang::lang::parse_policy_str;
-use aranya_policy_module::{ffi::ModuleSchema, Label, LabelType, ModuleData, Value};
+use aranya_policy_module::{
+    ffi::ModuleSchema, CompactError, Fingerprint, Instruction, Label, LabelType, Module,
+    ModuleData, ModuleV0, Target, Value,
+};
 
-use crate::{validate::validate, CallColor, CompileError, CompileErrorType, Compiler};
+use crate::{
+    validate::validate, CallColor, CompileError, CompileErrorType, CompileState, Compiler,
+    CompilerPass, Lint, LintLevel,
+};
 
 #[test]
 fn test_compile() -> anyhow::Result<()> {
@@ -238,7 +246,9 @@ fn test_seal_open_command() -> anyhow::Result<()> {
 
     let policy = parse_policy_str(text, Version::V1)?;
     let module = Compiler::new(&policy).compile()?;
-    let ModuleData::V0(module) = module.data;
+    let ModuleData::V1(module) = module.data else {
+        panic!("should be a V1 module");
+    };
 
     assert!(module
         .labels
@@ -257,8 +267,13 @@ fn test_command_without_seal_block() -> anyhow::Result<()> {
     let text = r#"
         command Foo {
             fields {}
+            open { return None }
             policy {}
         }
+
+        action foo() {
+            publish Foo {}
+        }
     "#;
 
     let policy = parse_policy_str(text, Version::V1)?;
@@ -269,7 +284,7 @@ fn test_command_without_seal_block() -> anyhow::Result<()> {
 
     assert_eq!(
         err,
-        CompileErrorType::Unknown(String::from("Empty/missing seal block in command"))
+        CompileErrorType::MissingCommandBlock(String::from("Foo"), LabelType::CommandSeal)
     );
 
     Ok(())
@@ -283,6 +298,10 @@ fn test_command_without_open_block() -> anyhow::Result<()> {
             seal { return None }
             policy {}
         }
+
+        action foo() {
+            publish Foo {}
+        }
     "#;
 
     let policy = parse_policy_str(text, Version::V1)?;
@@ -293,12 +312,34 @@ fn test_command_without_open_block() -> anyhow::Result<()> {
 
     assert_eq!(
         err,
-        CompileErrorType::Unknown(String::from("Empty/missing open block in command"))
+        CompileErrorType::MissingCommandBlock(String::from("Foo"), LabelType::CommandOpen)
     );
 
     Ok(())
 }
 
+#[test]
+fn test_unpublished_command_without_seal_or_open() -> anyhow::Result<()> {
+    let text = r#"
+        command Foo {
+            fields {}
+            policy {}
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let ModuleData::V1(module) = module.data else {
+        panic!("should be a V1 module");
+    };
+    assert!(module
+        .labels
+        .iter()
+        .all(|l| l.0.ltype != LabelType::CommandSeal && l.0.ltype != LabelType::CommandOpen));
+
+    Ok(())
+}
+
 #[test]
 fn test_command_with_no_return_in_seal_block() -> anyhow::Result<()> {
     let text = r#"
@@ -361,7 +402,7 @@ fn test_command_attributes() {
     let policy = parse_policy_str(text, Version::V1).expect("should parse");
     let m = Compiler::new(&policy).compile().expect("should compile");
     match m.data {
-        ModuleData::V0(m) => {
+        ModuleData::V1(m) => {
             let attrs = m
                 .command_attributes
                 .get("A")
@@ -380,6 +421,7 @@ fn test_command_attributes() {
                 &Value::Enum("Priority".to_string(), "High".to_string())
             );
         }
+        _ => panic!("should be a V1 module"),
     }
 }
 
@@ -426,6 +468,75 @@ fn test_command_attributes_must_be_literals() {
     }
 }
 
+#[test]
+fn test_command_attribute_schema() -> anyhow::Result<()> {
+    let schema = BTreeMap::from([
+        (String::from("priority"), VType::Int),
+        (String::from("ephemeral"), VType::Bool),
+    ]);
+
+    let text = r#"
+        command A {
+            attributes {
+                priority: 5,
+                ephemeral: true
+            }
+            seal { return None }
+            open { return None }
+        }
+        command B {
+            attributes { priority: 2 }
+            seal { return None }
+            open { return None }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy)
+        .attribute_schema(schema.clone())
+        .compile()?;
+    assert_eq!(module.command_attribute::<i64>("A", "priority")?, Some(5));
+    assert_eq!(
+        module.command_attribute::<bool>("A", "ephemeral")?,
+        Some(true)
+    );
+    assert_eq!(module.command_attribute::<bool>("B", "ephemeral")?, None);
+    assert!(module.command_attribute::<String>("B", "priority").is_err());
+
+    let texts = [
+        (
+            r#"
+            command A {
+                attributes { priority: "high" }
+                seal { return None }
+                open { return None }
+            }"#,
+            CompileErrorType::InvalidType(String::from(
+                "attribute `priority` must be int, got String",
+            )),
+        ),
+        (
+            r#"
+            command A {
+                attributes { color: 1 }
+                seal { return None }
+                open { return None }
+            }"#,
+            CompileErrorType::NotDefined(String::from("attribute `color`")),
+        ),
+    ];
+    for (text, expected) in texts {
+        let policy = parse_policy_str(text, Version::V1)?;
+        let err = Compiler::new(&policy)
+            .attribute_schema(schema.clone())
+            .compile()
+            .expect_err("compilation succeeded where it should fail")
+            .err_type;
+        assert_eq!(err, expected);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_autodefine_struct() -> anyhow::Result<()> {
     let text = r#"
@@ -440,7 +551,9 @@ fn test_autodefine_struct() -> anyhow::Result<()> {
 
     let policy = parse_policy_str(text, Version::V1)?;
     let result = Compiler::new(&policy).compile()?;
-    let ModuleData::V0(module) = result.data;
+    let ModuleData::V1(module) = result.data else {
+        panic!("should be a V1 module");
+    };
 
     let want = vec![
         FieldDefinition {
@@ -458,11 +571,164 @@ fn test_autodefine_struct() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_match_jump_table() -> anyhow::Result<()> {
+    let cases = [
+        // Dense integer values use a jump table
+        ("1 => {} 2 | 3 => {} 4 => {}", true),
+        ("1 => {} 3 => {} 4 => {} 6 => {} _ => {}", true),
+        // Too few values
+        ("1 => {} 2 => {} _ => {}", false),
+        // Too sparse
+        ("1 => {} 10 => {} 20 => {} 30 => {}", false),
+        // Not all values are integer literals
+        ("1 => {} 2 => {} 3 => {} y => {}", false),
+    ];
+
+    for (arms, want) in cases {
+        let text = format!(
+            r#"
+            action f(x int, y int) {{
+                match x {{
+                    {arms}
+                }}
+            }}
+            "#
+        );
+        let policy = parse_policy_str(&text, Version::V1)?;
+        let module = Compiler::new(&policy).compile()?;
+        let ModuleData::V1(module) = module.data else {
+            panic!("should be a V1 module");
+        };
+
+        let got = module
+            .progmem
+            .iter()
+            .any(|i| matches!(i, Instruction::JumpTable(..)));
+        assert_eq!(got, want, "{arms}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_peephole_optimization() -> anyhow::Result<()> {
+    let text = r#"
+        function ge(a int, b int) bool {
+            return a >= b
+        }
+
+        function le(a int, b int) bool {
+            return a <= b
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+
+    let lowered = [
+        Instruction::Dup(1),
+        Instruction::Dup(1),
+        Instruction::Eq,
+        Instruction::Swap(2),
+        Instruction::Swap(1),
+    ];
+    let contains = |progmem: &[Instruction], want: &[Instruction]| {
+        progmem.windows(want.len()).any(|w| w == want)
+    };
+
+    let module = Compiler::new(&policy).optimize(false).compile()?;
+    let ModuleData::V1(unoptimized) = module.data else {
+        panic!("should be a V1 module");
+    };
+    assert!(contains(&unoptimized.progmem, &lowered));
+
+    let module = Compiler::new(&policy).compile()?;
+    let ModuleData::V1(optimized) = module.data else {
+        panic!("should be a V1 module");
+    };
+    assert!(!contains(&optimized.progmem, &lowered));
+    assert!(contains(
+        &optimized.progmem,
+        &[Instruction::Lt, Instruction::Not]
+    ));
+    assert!(contains(
+        &optimized.progmem,
+        &[Instruction::Gt, Instruction::Not]
+    ));
+    assert!(optimized.progmem.len() < unoptimized.progmem.len());
+
+    // Labels must still point at the same functions
+    for (label, &addr) in &optimized.labels {
+        assert_eq!(
+            optimized.progmem.get(addr).map(|i| i.to_string()),
+            unoptimized
+                .labels
+                .get(label)
+                .and_then(|&a| unoptimized.progmem.get(a))
+                .map(|i| i.to_string()),
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unary_expressions() -> anyhow::Result<()> {
+    let cases = [
+        (
+            "-x",
+            vec![
+                Instruction::Const(Value::Int(0)),
+                Instruction::Swap(1),
+                Instruction::Sub,
+            ],
+        ),
+        ("!(x > 0)", vec![Instruction::Gt, Instruction::Not]),
+        (
+            "y is None",
+            vec![Instruction::Const(Value::None), Instruction::Eq],
+        ),
+        (
+            "y is Some",
+            vec![
+                Instruction::Const(Value::None),
+                Instruction::Eq,
+                Instruction::Not,
+            ],
+        ),
+    ];
+
+    for (expr, want) in cases {
+        let text = format!(
+            r#"
+            function f(x int, y optional int) bool {{
+                let r = {expr}
+                return true
+            }}
+            "#
+        );
+        let policy = parse_policy_str(&text, Version::V1)?;
+        let module = Compiler::new(&policy).compile()?;
+        let ModuleData::V1(module) = module.data else {
+            panic!("should be a V1 module");
+        };
+
+        assert!(
+            module
+                .progmem
+                .windows(want.len())
+                .any(|w| w == want.as_slice()),
+            "`{expr}` did not compile to {want:?}"
+        );
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_duplicate_struct_fact_names() -> anyhow::Result<()> {
     let texts = &[
         r#"
-            // Should give an "already defined" error.
+            // Should give a name collision error.
             struct Foo {}
             fact Foo[]=>{}
         "#,
@@ -478,7 +744,7 @@ fn test_duplicate_struct_fact_names() -> anyhow::Result<()> {
         assert!(matches!(
             result,
             Err(CompileError {
-                err_type: CompileErrorType::AlreadyDefined(_),
+                err_type: CompileErrorType::NameCollision { .. },
                 ..
             })
         ));
@@ -487,6 +753,39 @@ fn test_duplicate_struct_fact_names() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_name_collisions() -> anyhow::Result<()> {
+    let text = r#"
+        command Foo {
+            fields {}
+            seal { return None }
+            open { return None }
+        }
+
+        effect Bar {}
+        enum Foo { A, B }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let err = Compiler::new(&policy)
+        .compile()
+        .expect_err("compilation succeeded where it should fail");
+    assert_eq!(
+        err.err_type,
+        CompileErrorType::NameCollision {
+            name: String::from("Foo"),
+            first: "command",
+            first_linecol: Some((2, 9)),
+            second: "enum",
+        }
+    );
+    assert!(err.to_string().starts_with(
+        "Name collision: enum `Foo` conflicts with command `Foo` (defined at line 2 col 9) \
+         at line 9 col 9"
+    ));
+
+    Ok(())
+}
+
 #[test]
 fn test_enum_identifiers_are_unique() -> anyhow::Result<()> {
     let text = r#"
@@ -781,10 +1080,70 @@ fn test_fact_expression_value_type() -> anyhow::Result<()> {
     "#;
 
     let policy = parse_policy_str(text, Version::V1)?;
+    let err = Compiler::new(&policy)
+        .compile()
+        .expect_err("compilation should have failed")
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::InvalidType(String::from("field `a` type should be string"))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_fact_identifier_types() -> anyhow::Result<()> {
+    let valid = r#"
+        fact Foo[i int] => {a string}
+        action test(i int, a string) {
+            check exists Foo[i: i] => {a: a}
+        }
+    "#;
+    let policy = parse_policy_str(valid, Version::V1)?;
     Compiler::new(&policy)
         .compile()
         .expect("compilation should have succeeded");
 
+    let cases = [
+        (
+            r#"
+            fact Foo[i int] => {a string}
+            action test(i string) {
+                check exists Foo[i: i]
+            }
+            "#,
+            CompileErrorType::InvalidType(String::from("Fact field `i` must be int")),
+        ),
+        (
+            r#"
+            fact Foo[i int] => {a string}
+            command Set {
+                fields {
+                    a int,
+                }
+                seal { return None }
+                open { return None }
+                policy {
+                    finish {
+                        create Foo[i: 1] => {a: this.a}
+                    }
+                }
+            }
+            "#,
+            CompileErrorType::InvalidType(String::from("field `a` type should be string")),
+        ),
+    ];
+
+    for (text, expected) in cases {
+        let policy = parse_policy_str(text, Version::V1)?;
+        let err = Compiler::new(&policy)
+            .compile()
+            .expect_err("compilation should have failed")
+            .err_type;
+        assert_eq!(err, expected);
+    }
+
     Ok(())
 }
 
@@ -1301,6 +1660,61 @@ fn test_global_let_duplicates() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_shadowing() -> anyhow::Result<()> {
+    let text = r#"
+        action foo(x int) {
+            let y = 1
+            if x > 0 {
+                let y = 2
+                let z = 3
+            } else {
+                let z = 4
+            }
+            match x {
+                0 => {
+                    let x = 5
+                }
+                _ => {}
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let (_, warnings) = Compiler::new(&policy).compile_with_warnings()?;
+    let warnings: Vec<_> = warnings.into_iter().map(|w| w.err_type).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            CompileErrorType::Shadowed(String::from("y")),
+            CompileErrorType::Shadowed(String::from("x")),
+        ]
+    );
+
+    let err = Compiler::new(&policy)
+        .lint(Lint::Shadowing, LintLevel::Deny)
+        .compile()
+        .unwrap_err()
+        .err_type;
+    assert_eq!(err, CompileErrorType::Shadowed(String::from("y")));
+
+    // Shadowing a global value is an error regardless of lint levels
+    let text = r#"
+        let x = 10
+        function f(x int) int {
+            return x
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let err = Compiler::new(&policy)
+        .lint(Lint::Shadowing, LintLevel::Allow)
+        .compile()
+        .unwrap_err()
+        .err_type;
+    assert_eq!(err, CompileErrorType::AlreadyDefined(String::from("x")));
+
+    Ok(())
+}
+
 #[test]
 fn test_field_collision() -> anyhow::Result<()> {
     let text = r#"
@@ -1460,58 +1874,172 @@ fn test_map_identifier_scope() -> anyhow::Result<()> {
 
 const FAKE_SCHEMA: &[ModuleSchema<'static>] = &[ModuleSchema {
     name: "test",
+    version: 1,
     functions: &[],
     structs: &[],
 }];
 
 #[test]
-fn test_type_errors() -> anyhow::Result<()> {
-    struct Case {
-        t: &'static str,
-        e: &'static str,
+fn test_ffi_version_requirements() -> anyhow::Result<()> {
+    let schemas = &[ModuleSchema {
+        name: "test",
+        version: 2,
+        functions: &[],
+        structs: &[],
+    }];
+
+    for text in ["use test", "use test >= 1", "use test >= 2"] {
+        let policy = parse_policy_str(text, Version::V1)?;
+        Compiler::new(&policy).ffi_modules(schemas).compile()?;
     }
-    let cases = [
-        Case {
-            t: r#"
-                function f() bool {
-                    let x = Foo{}
-                }
-            "#,
-            e: "Struct `Foo` not defined",
-        },
-        Case {
-            t: r#"
-                function f() bool {
-                    let x = query Foo[]
-                }
-            "#,
-            e: "Fact `Foo` not defined",
-        },
-        Case {
-            t: r#"
-                function f(x int) bool {
-                    return x + "foo"
-                }
-            "#,
-            e: "types do not match: int and string",
-        },
-        Case {
-            t: r#"
-                function f() int {
-                    return if 0 { 3 } else { 4 }
-                }
-            "#,
-            e: "if condition must be a boolean expression",
-        },
-        Case {
-            t: r#"
-                finish function f() {}
-                function g() bool {
-                    return f()
-                }
-            "#,
-            e: "Finish functions are not allowed outside of finish blocks or finish functions",
-        },
+
+    let policy = parse_policy_str("use test >= 3", Version::V1)?;
+    let err = Compiler::new(&policy)
+        .ffi_modules(schemas)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::FfiVersionMismatch {
+            module: String::from("test"),
+            required: 3,
+            found: 2,
+        }
+    );
+
+    let policy = parse_policy_str("use other >= 1", Version::V1)?;
+    let err = Compiler::new(&policy)
+        .ffi_modules(schemas)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert_eq!(err, CompileErrorType::NotDefined(String::from("other")));
+
+    Ok(())
+}
+
+#[test]
+fn test_extensions() -> anyhow::Result<()> {
+    use aranya_policy_module::ffi::{Arg, Func, Type};
+
+    let extensions = &[Func {
+        name: "hash_eq",
+        args: &[
+            Arg {
+                name: "a",
+                vtype: Type::Bytes,
+            },
+            Arg {
+                name: "b",
+                vtype: Type::Bytes,
+            },
+        ],
+        return_type: Type::Bool,
+    }];
+
+    let policy = parse_policy_str(
+        r#"
+        function same(a bytes, b bytes) bool {
+            return hash_eq(a, b)
+        }
+        "#,
+        Version::V1,
+    )?;
+    let module = Compiler::new(&policy).extensions(extensions).compile()?;
+    let ModuleData::V1(m) = module.data else {
+        panic!("should be a V1 module");
+    };
+    assert!(m
+        .progmem
+        .contains(&Instruction::Extension("hash_eq".into())));
+
+    // Arguments are checked like other function calls
+    let policy = parse_policy_str(
+        r#"
+        function same(a bytes) bool {
+            return hash_eq(a)
+        }
+        "#,
+        Version::V1,
+    )?;
+    let err = Compiler::new(&policy)
+        .extensions(extensions)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert!(matches!(err, CompileErrorType::BadArgument(_)), "{err}");
+
+    // Policy functions cannot reuse an extension's name
+    let policy = parse_policy_str(
+        r#"
+        function hash_eq(a bytes, b bytes) bool {
+            return a == b
+        }
+        "#,
+        Version::V1,
+    )?;
+    let err = Compiler::new(&policy)
+        .extensions(extensions)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::AlreadyDefined(String::from("hash_eq"))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_type_errors() -> anyhow::Result<()> {
+    struct Case {
+        t: &'static str,
+        e: &'static str,
+    }
+    let cases = [
+        Case {
+            t: r#"
+                function f() bool {
+                    let x = Foo{}
+                }
+            "#,
+            e: "Struct `Foo` not defined",
+        },
+        Case {
+            t: r#"
+                function f() bool {
+                    let x = query Foo[]
+                }
+            "#,
+            e: "Fact `Foo` not defined",
+        },
+        Case {
+            t: r#"
+                function f(x int) bool {
+                    return x + "foo"
+                }
+            "#,
+            e: "types do not match: int and string",
+        },
+        Case {
+            t: r#"
+                function f() int {
+                    return if 0 { 3 } else { 4 }
+                }
+            "#,
+            e: "if condition must be a boolean expression",
+        },
+        Case {
+            t: r#"
+                finish function f() {}
+                function g() bool {
+                    return f()
+                }
+            "#,
+            e: "Finish functions are not allowed outside of finish blocks or finish functions",
+        },
         Case {
             t: r#"
                 function g() bool {
@@ -1681,6 +2209,107 @@ fn test_type_errors() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_emit_effect_validation() -> anyhow::Result<()> {
+    let valid = r#"
+        effect Added {
+            a int,
+            b optional string,
+        }
+        command Foo {
+            fields {
+                a int,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    emit Added { a: this.a, b: None }
+                }
+            }
+        }
+    "#;
+    let policy = parse_policy_str(valid, Version::V1)?;
+    Compiler::new(&policy).compile()?;
+
+    let cases = [
+        (
+            r#"
+            struct NotEffect {
+                a int,
+            }
+            command Foo {
+                policy {
+                    finish {
+                        emit NotEffect { a: 1 }
+                    }
+                }
+            }
+            "#,
+            CompileErrorType::InvalidType(String::from(
+                "Emit must be given an effect, but `NotEffect` is not an effect",
+            )),
+        ),
+        (
+            r#"
+            effect Added {
+                a int,
+                b int,
+            }
+            command Foo {
+                policy {
+                    finish {
+                        emit Added { a: 1 }
+                    }
+                }
+            }
+            "#,
+            CompileErrorType::Missing(String::from("field `b` of effect `Added`")),
+        ),
+        (
+            r#"
+            effect Added {
+                a int,
+            }
+            command Foo {
+                policy {
+                    finish {
+                        emit Added { a: 1, c: 2 }
+                    }
+                }
+            }
+            "#,
+            CompileErrorType::NotDefined(String::from("Added.c")),
+        ),
+        (
+            r#"
+            effect Added {
+                a int,
+            }
+            command Foo {
+                policy {
+                    finish {
+                        emit Added { a: "one" }
+                    }
+                }
+            }
+            "#,
+            CompileErrorType::InvalidType(String::from("Effect field `a` must be int")),
+        ),
+    ];
+
+    for (text, expected) in cases {
+        let policy = parse_policy_str(text, Version::V1)?;
+        let err = Compiler::new(&policy)
+            .compile()
+            .expect_err("compilation succeeded where it should fail")
+            .err_type;
+        assert_eq!(err, expected);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_duplicate_definitions() -> anyhow::Result<()> {
     struct Case {
@@ -1914,3 +2543,561 @@ fn test_validate_return() {
         assert!(validate(&m));
     }
 }
+
+#[test]
+fn test_unreachable_code() {
+    let valid = [
+        r#"function a(n int) int {
+            if n > 0 {
+                return 1
+            }
+            return 0
+        }"#,
+        r#"function b(n int) int {
+            match n {
+                0 => {
+                    return 0
+                }
+                _ => {
+                    let x = n
+                }
+            }
+            return 1
+        }"#,
+    ];
+
+    let invalid = [
+        r#"function a() int {
+            return 0
+            let x = 1
+        }"#,
+        r#"function b() int {
+            check false
+            return 0
+        }"#,
+        r#"function c(n int) int {
+            if n > 0 {
+                return 1
+            } else {
+                return 0
+            }
+            return 2
+        }"#,
+        r#"function d(n int) int {
+            match n {
+                0 => {
+                    return 0
+                }
+                1 => {
+                    return 1
+                }
+            }
+            return 2
+        }"#,
+    ];
+
+    for p in valid {
+        let policy = parse_policy_str(p, Version::V1).expect("should parse");
+        Compiler::new(&policy).compile().expect("should compile");
+    }
+
+    for p in invalid {
+        let policy = parse_policy_str(p, Version::V1).expect("should parse");
+        let err = Compiler::new(&policy).compile().unwrap_err().err_type;
+        assert_eq!(err, CompileErrorType::Unreachable);
+    }
+}
+
+#[test]
+fn test_lint_levels() -> anyhow::Result<()> {
+    let text = r#"
+        function used() int {
+            return 1
+        }
+
+        function unused() int {
+            return 2
+        }
+
+        finish function unused_finish() {
+        }
+
+        action foo() {
+            let x = used()
+            check x > 0
+            check false
+            let y = x
+            let z = y
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+
+    // Unreachable code is denied by default
+    let err = Compiler::new(&policy).compile().unwrap_err().err_type;
+    assert_eq!(err, CompileErrorType::Unreachable);
+
+    let (_, warnings) = Compiler::new(&policy)
+        .lint(Lint::Unreachable, LintLevel::Warn)
+        .compile_with_warnings()?;
+    let warnings: Vec<_> = warnings.into_iter().map(|w| w.err_type).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            CompileErrorType::Unreachable,
+            CompileErrorType::Unused(String::from("function `unused`")),
+            CompileErrorType::Unused(String::from("function `unused_finish`")),
+        ]
+    );
+
+    let (_, warnings) = Compiler::new(&policy)
+        .lint(Lint::Unreachable, LintLevel::Allow)
+        .lint(Lint::UnusedFunction, LintLevel::Allow)
+        .compile_with_warnings()?;
+    assert!(warnings.is_empty());
+
+    let err = Compiler::new(&policy)
+        .lint(Lint::Unreachable, LintLevel::Allow)
+        .deny_warnings()
+        .compile()
+        .unwrap_err()
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::Unused(String::from("function `unused`"))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_static_assert() -> anyhow::Result<()> {
+    let valid = [
+        r#"
+            let max = 10
+            static_assert(max > 1, "max is too small")
+            static_assert(-max + 20 == 10, "arithmetic")
+        "#,
+        r#"
+            enum Color { Red, Green }
+            struct Limits { lo int, hi int }
+            let color = Color::Green
+            let limits = Limits { lo: 1, hi: 5 }
+            static_assert(color != Color::Red, "color")
+            static_assert(limits.lo <= limits.hi, "limits")
+            static_assert(!(None is Some) && "a" == "a", "literals")
+        "#,
+    ];
+    for p in valid {
+        let policy = parse_policy_str(p, Version::V1)?;
+        Compiler::new(&policy).compile()?;
+    }
+
+    let invalid = [
+        (
+            r#"
+                let max = 1
+                static_assert(max > 1, "max is too small")
+            "#,
+            CompileErrorType::StaticAssertFailed(String::from("max is too small")),
+        ),
+        (
+            r#"static_assert(1 + 1, "sum")"#,
+            CompileErrorType::InvalidType(String::from(
+                "static_assert must have boolean expression",
+            )),
+        ),
+        (
+            r#"static_assert(x > 1, "undefined")"#,
+            CompileErrorType::NotDefined(String::from("x")),
+        ),
+        (
+            r#"
+                function f() int {
+                    return 1
+                }
+                static_assert(f() == 1, "not constant")
+            "#,
+            CompileErrorType::InvalidExpression(aranya_policy_ast::Expression::FunctionCall(
+                aranya_policy_ast::FunctionCall {
+                    identifier: String::from("f"),
+                    arguments: vec![],
+                },
+            )),
+        ),
+    ];
+    for (p, expected) in invalid {
+        let policy = parse_policy_str(p, Version::V1)?;
+        let err = Compiler::new(&policy).compile().unwrap_err().err_type;
+        assert_eq!(err, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_policy_fingerprint() -> anyhow::Result<()> {
+    let fingerprint = |text: &str| -> anyhow::Result<Option<Fingerprint>> {
+        let policy = parse_policy_str(text, Version::V1)?;
+        Ok(Compiler::new(&policy).compile()?.fingerprint().copied())
+    };
+
+    let original = fingerprint(
+        r#"
+        function f(x int) int {
+            return x + 1
+        }
+        "#,
+    )?;
+    assert!(original.is_some());
+
+    // Formatting and comments do not change the fingerprint
+    let reformatted = fingerprint(
+        r#"
+        // Adds one
+        function f(x int) int { return x + 1 }
+        "#,
+    )?;
+    assert_eq!(original, reformatted);
+
+    // Changes to the logic do
+    let changed = fingerprint(
+        r#"
+        function f(x int) int {
+            return x + 2
+        }
+        "#,
+    )?;
+    assert_ne!(original, changed);
+
+    // `policy_fingerprint()` is the fingerprint of the policy it appears in
+    let policy = parse_policy_str(
+        r#"
+        function f() bytes {
+            return policy_fingerprint()
+        }
+        "#,
+        Version::V1,
+    )?;
+    let module = Compiler::new(&policy).compile()?;
+    let expected = Fingerprint::of(&policy).expect("policy should encode");
+    assert_eq!(module.fingerprint(), Some(&expected));
+    let ModuleData::V1(m) = module.data else {
+        panic!("should be a V1 module");
+    };
+    assert!(m.progmem.contains(&Instruction::Const(Value::Bytes(
+        expected.as_bytes().to_vec().into()
+    ))));
+
+    // The fingerprint is a hash of a fixed encoding, so it does not change between builds
+    let mut policy = Policy::new(Version::V1, "fact F[i int]=>{}");
+    policy.facts.push(AstNode::new(
+        FactDefinition {
+            immutable: false,
+            identifier: "F".into(),
+            key: vec![FieldDefinition {
+                identifier: "i".into(),
+                field_type: VType::Int,
+            }],
+            value: vec![],
+        },
+        0,
+    ));
+    assert_eq!(
+        Fingerprint::of(&policy)
+            .expect("policy should encode")
+            .to_string(),
+        "eee648ced53186eeb0807529fc9c9a639947ab6210d93c9793fb23db31604a75"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_compact_encoding() -> anyhow::Result<()> {
+    let text = r#"
+        enum Color { Red, Green }
+        struct Point { x int, y int }
+        fact Location[id id]=>{point struct Point, color optional enum Color}
+        let origin = Point { x: 0, y: -1 }
+
+        command Move {
+            attributes {
+                priority: 3
+            }
+            fields {
+                id id,
+                point struct Point,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                match this.point.x {
+                    0 => {}
+                    1 => {}
+                    2 => {}
+                    3 => {}
+                    _ => {
+                        check this.point.y >= -1000000
+                    }
+                }
+                finish {
+                    create Location[id: this.id]=>{point: this.point, color: Some(Color::Red)}
+                }
+            }
+        }
+
+        action move(id id) {
+            publish Move { id: id, point: origin }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+
+    let compact = module.to_compact();
+    assert_eq!(Module::from_compact(&compact)?, module);
+
+    let mut cbor = vec![];
+    ciborium::into_writer(&module, &mut cbor)?;
+    assert!(compact.len() < cbor.len());
+
+    // Version 0 modules, which have no fingerprint or effect definitions, are still
+    // supported
+    let ModuleData::V1(m) = module.data.clone() else {
+        panic!("should be a V1 module");
+    };
+    let v0 = Module {
+        data: ModuleData::V0(ModuleV0 {
+            progmem: m.progmem,
+            labels: m.labels,
+            action_defs: m.action_defs,
+            command_defs: m.command_defs,
+            fact_defs: m.fact_defs,
+            struct_defs: m.struct_defs,
+            command_attributes: m.command_attributes,
+            codemap: m.codemap,
+            globals: m.globals,
+        }),
+    };
+    let decoded = Module::from_compact(&v0.to_compact())?;
+    assert_eq!(decoded, v0);
+    assert_eq!(decoded.fingerprint(), None);
+
+    // Corrupt modules are rejected
+    assert_eq!(Module::from_compact(&cbor), Err(CompactError::BadMagic));
+    assert_eq!(
+        Module::from_compact(&compact[..compact.len() - 1]),
+        Err(CompactError::Truncated)
+    );
+    let mut trailing = compact.clone();
+    trailing.push(0);
+    assert_eq!(
+        Module::from_compact(&trailing),
+        Err(CompactError::TrailingData)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_max_stack_depth() -> anyhow::Result<()> {
+    let text = r#"
+        function add(a int, b int) int {
+            return a + b
+        }
+
+        action foo() {
+            let x = 1 + add(2, 3)
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+
+    // `1`, `2`, and `3` are on the stack when `add()` is called, and `add()` pushes `a`
+    // and `b` after defining its arguments.
+    Compiler::new(&policy).max_stack_depth(3).compile()?;
+
+    let err = Compiler::new(&policy)
+        .max_stack_depth(2)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::StackDepthExceeded(String::from(
+            "action `foo` may use 3 stack slots, but the limit is 2"
+        ))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_recursion() -> anyhow::Result<()> {
+    let cases = [
+        (
+            r#"
+            function f(n int) int {
+                return f(n)
+            }
+            "#,
+            vec!["f", "f"],
+        ),
+        (
+            r#"
+            finish function a() {
+                b()
+            }
+            finish function b() {
+                a()
+            }
+            "#,
+            vec!["a", "b", "a"],
+        ),
+        (
+            r#"
+            action a() {
+                action b()
+            }
+            action b() {
+                action c()
+            }
+            action c() {
+                action a()
+            }
+            "#,
+            vec!["a", "b", "c", "a"],
+        ),
+    ];
+
+    for (text, cycle) in cases {
+        let policy = parse_policy_str(text, Version::V1)?;
+        let err = Compiler::new(&policy)
+            .compile()
+            .expect_err("compilation succeeded where it should fail")
+            .err_type;
+        assert_eq!(
+            err,
+            CompileErrorType::Recursion(cycle.into_iter().map(String::from).collect())
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_targets() -> anyhow::Result<()> {
+    /// Points the first jump or branch at `addr`
+    struct Retarget(usize);
+
+    impl CompilerPass for Retarget {
+        fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError> {
+            if let Some(Instruction::Jump(t) | Instruction::Branch(t)) = state
+                .instructions_mut()
+                .iter_mut()
+                .find(|i| matches!(i, Instruction::Jump(_) | Instruction::Branch(_)))
+            {
+                *t = Target::Resolved(self.0);
+            }
+            Ok(())
+        }
+    }
+
+    let text = r#"
+        action a(x int) {
+            if x > 0 {
+                check x < 10
+            }
+        }
+
+        action b() {}
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let ModuleData::V1(m) = module.data else {
+        panic!("should be a V1 module");
+    };
+    let b = m.labels[&Label::new("b", LabelType::Action)];
+
+    for (addr, problem) in [
+        (m.progmem.len(), "jumps out of bounds"),
+        (b, "jumps outside its label region"),
+    ] {
+        let err = Compiler::new(&policy)
+            .add_pass(Retarget(addr))
+            .compile()
+            .expect_err("compilation succeeded where it should fail")
+            .err_type;
+        assert!(
+            matches!(&err, CompileErrorType::BadTarget(s) if s.ends_with(problem)),
+            "{err}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_compiler_pass() -> anyhow::Result<()> {
+    /// Counts the actions in a policy
+    struct CountActions(usize);
+
+    impl CompilerPass for CountActions {
+        fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError> {
+            self.0 = state.policy().actions.len();
+            Ok(())
+        }
+    }
+
+    /// Rejects policies which emit effects
+    struct NoEmit;
+
+    impl CompilerPass for NoEmit {
+        fn run(&mut self, state: &mut CompileState<'_>) -> Result<(), CompileError> {
+            match state
+                .instructions()
+                .iter()
+                .position(|i| *i == Instruction::Emit)
+            {
+                Some(addr) => Err(state.error_at(
+                    CompileErrorType::PassFailed(String::from("effects are not allowed")),
+                    addr,
+                )),
+                None => Ok(()),
+            }
+        }
+    }
+
+    let text = r#"
+        effect Foo {}
+
+        command Bar {
+            fields {}
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    emit Foo {}
+                }
+            }
+        }
+
+        action a() {}
+        action b() {}
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+
+    let mut count = CountActions(0);
+    Compiler::new(&policy).add_pass(&mut count).compile()?;
+    assert_eq!(count.0, 2);
+
+    let err = Compiler::new(&policy)
+        .add_pass(NoEmit)
+        .compile()
+        .expect_err("compilation succeeded where it should fail")
+        .err_type;
+    assert_eq!(
+        err,
+        CompileErrorType::PassFailed(String::from("effects are not allowed"))
+    );
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ mod analyzers;
 mod error;
 
 pub use analyzers::*;
-use aranya_policy_module::{Instruction, Label, ModuleV0, Target};
+use aranya_policy_module::{Instruction, Label, ModuleV1, Target};
 pub use error::TraceError;
 use error::TraceErrorType;
 
@@ -32,12 +32,12 @@ pub struct TraceFailure {
 /// let failures = tracer.trace(Label::new("Init", LabelType::CommandPolicy))?;
 /// ```
 pub struct TraceAnalyzerBuilder<'a> {
-    m: &'a ModuleV0,
+    m: &'a ModuleV1,
     tracers: Vec<Box<dyn Analyzer>>,
 }
 
 impl<'a> TraceAnalyzerBuilder<'a> {
-    pub fn new(m: &'a ModuleV0) -> TraceAnalyzerBuilder<'a> {
+    pub fn new(m: &'a ModuleV1) -> TraceAnalyzerBuilder<'a> {
         TraceAnalyzerBuilder { m, tracers: vec![] }
     }
 }
@@ -71,7 +71,7 @@ impl<'a> TraceAnalyzerBuilder<'a> {
 #[derive(Clone)]
 pub struct TraceAnalyzer<'a> {
     /// The compiled code we're analyzing
-    ct: &'a ModuleV0,
+    ct: &'a ModuleV1,
     /// Stores return addresses from `Call` instructions
     call_stack: Vec<usize>,
     /// The addresses of the instruction path so far in our trace
@@ -179,17 +179,19 @@ impl TraceAnalyzer<'_> {
                 Instruction::Branch(t) => {
                     self.branches.push(pc);
                     // Recurse on the target
-                    let jump_pc = t
-                        .resolved()
-                        .ok_or_else(|| self.trace_err(TraceErrorType::Bug))?;
-                    let mut jump_tracer = self.clone();
-                    let TraceIntermediate {
-                        failures: jump_failures,
-                        successful_branch_paths: mut success_branches,
-                    } = jump_tracer.trace_inner(jump_pc)?;
-                    for (idx, mut jf) in jump_failures.into_iter().enumerate() {
-                        failures[idx].append(&mut jf);
-                        successful_branch_paths.append(&mut success_branches);
+                    self.trace_branch(t, &mut failures, &mut successful_branch_paths)?;
+                }
+                Instruction::JumpTable(_, targets) => {
+                    self.branches.push(pc);
+                    // Recurse on each distinct target. Execution falls through when the value
+                    // is out of range, which is traced below.
+                    let mut seen = vec![];
+                    for t in targets {
+                        if seen.contains(&t) {
+                            continue;
+                        }
+                        seen.push(t);
+                        self.trace_branch(t, &mut failures, &mut successful_branch_paths)?;
                     }
                 }
                 Instruction::Call(t) => {
@@ -234,6 +236,29 @@ impl TraceAnalyzer<'_> {
         Err(self.trace_err(TraceErrorType::Bug))
     }
 
+    /// Trace the path taken when a branch to `target` is taken, and merge its results into
+    /// `failures` and `successful_branch_paths`.
+    fn trace_branch(
+        &self,
+        target: &Target,
+        failures: &mut [Vec<TraceFailure>],
+        successful_branch_paths: &mut Vec<Vec<usize>>,
+    ) -> Result<(), TraceError> {
+        let jump_pc = target
+            .resolved()
+            .ok_or_else(|| self.trace_err(TraceErrorType::Bug))?;
+        let mut jump_tracer = self.clone();
+        let TraceIntermediate {
+            failures: jump_failures,
+            successful_branch_paths: mut success_branches,
+        } = jump_tracer.trace_inner(jump_pc)?;
+        for (idx, mut jf) in jump_failures.into_iter().enumerate() {
+            failures[idx].append(&mut jf);
+            successful_branch_paths.append(&mut success_branches);
+        }
+        Ok(())
+    }
+
     fn analyze_instruction(
         &mut self,
         pc: usize,
@@ -2,7 +2,7 @@ mod finish_analyzer;
 mod function_analyzer;
 mod value_analyzer;
 
-use aranya_policy_module::{Instruction, ModuleV0};
+use aranya_policy_module::{Instruction, ModuleV1};
 pub use finish_analyzer::*;
 pub use function_analyzer::*;
 pub use value_analyzer::*;
@@ -32,7 +32,7 @@ impl Clone for Box<dyn Analyzer> {
 
 pub trait Analyzer: AnalyzerClone {
     /// Optionally initialize the analyzer using some information from the compile target.
-    fn init(&mut self, _ct: &ModuleV0) {}
+    fn init(&mut self, _ct: &ModuleV1) {}
 
     /// Analyzes the current instruction. This may modify its own internal state, modify the
     /// `reqs` reference, and optionally return a string describing a failure at the current
@@ -41,7 +41,7 @@ pub trait Analyzer: AnalyzerClone {
         &mut self,
         pc: usize,
         i: &Instruction,
-        m: &ModuleV0,
+        m: &ModuleV1,
     ) -> Result<AnalyzerStatus, TraceError>;
 
     /// Optionally post-analyze any produced failures using branch information.
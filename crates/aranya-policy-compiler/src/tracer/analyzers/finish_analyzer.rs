@@ -1,4 +1,4 @@
-use aranya_policy_module::{ExitReason, Instruction, Meta, ModuleV0};
+use aranya_policy_module::{ExitReason, Instruction, Meta, ModuleV1};
 
 use super::{Analyzer, AnalyzerStatus};
 use crate::tracer::{TraceError, TraceFailure};
@@ -20,7 +20,7 @@ impl Analyzer for FinishAnalyzer {
         &mut self,
         _pc: usize,
         i: &Instruction,
-        _m: &ModuleV0,
+        _m: &ModuleV1,
     ) -> Result<AnalyzerStatus, TraceError> {
         match i {
             Instruction::Meta(Meta::Finish(s)) => {
@@ -1,4 +1,4 @@
-use aranya_policy_module::{Instruction, ModuleV0};
+use aranya_policy_module::{Instruction, ModuleV1};
 
 use super::{Analyzer, AnalyzerStatus};
 use crate::tracer::TraceError;
@@ -20,7 +20,7 @@ impl Analyzer for FunctionAnalyzer {
         &mut self,
         _pc: usize,
         i: &Instruction,
-        _m: &ModuleV0,
+        _m: &ModuleV1,
     ) -> Result<AnalyzerStatus, TraceError> {
         match i {
             Instruction::Return => self.have_return = true,
@@ -1,6 +1,6 @@
 use std::collections::BTreeSet;
 
-use aranya_policy_module::{Instruction, Meta, ModuleV0};
+use aranya_policy_module::{Instruction, Meta, ModuleV1};
 
 use super::{Analyzer, AnalyzerStatus};
 use crate::tracer::TraceError;
@@ -44,7 +44,7 @@ impl Analyzer for ValueAnalyzer {
         &mut self,
         _pc: usize,
         i: &Instruction,
-        _m: &ModuleV0,
+        _m: &ModuleV1,
     ) -> Result<AnalyzerStatus, TraceError> {
         match i {
             Instruction::Call(_) => {
@@ -3,7 +3,10 @@ use aranya_policy_module::{LabelType, Module, ModuleData};
 use crate::{FinishAnalyzer, FunctionAnalyzer, TraceAnalyzerBuilder, TraceFailure, ValueAnalyzer};
 
 pub fn validate(module: &Module) -> bool {
-    let ModuleData::V0(ref m) = module.data;
+    let ModuleData::V1(ref m) = module.data else {
+        println!("cannot validate a {} module", module.version());
+        return false;
+    };
     let mut failed = false;
 
     // Get all global variable names
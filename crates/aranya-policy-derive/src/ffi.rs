@@ -10,8 +10,8 @@ use syn::{
     parse::{Parse, ParseStream},
     parse_quote,
     spanned::Spanned,
-    Attribute, Error, FnArg, Ident, ImplItem, ImplItemFn, ItemImpl, LitStr, Meta, Pat, PatIdent,
-    PatType, Path, ReturnType, Token,
+    Attribute, Error, FnArg, Ident, ImplItem, ImplItemFn, ItemImpl, LitInt, LitStr, Meta, Pat,
+    PatIdent, PatType, Path, ReturnType, Token,
 };
 
 use crate::attr::{get_lit_str, Attr, Symbol};
@@ -20,7 +20,11 @@ use crate::attr::{get_lit_str, Attr, Symbol};
 // `#[ffi_export(name = "foo")]`?
 
 pub(crate) fn parse(attr: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
-    let FfiAttr { module, structs } = syn::parse2(attr)?;
+    let FfiAttr {
+        module,
+        version,
+        structs,
+    } = syn::parse2(attr)?;
     let mut item: ItemImpl = syn::parse2(item)?;
     // The type that the `#[ffi]` attribute is applied to.
     let self_ty = &item.self_ty;
@@ -268,6 +272,7 @@ pub(crate) fn parse(attr: TokenStream, item: TokenStream) -> syn::Result<TokenSt
 
                 const SCHEMA: #vm::ffi::ModuleSchema<'static> = #vm::ffi::ModuleSchema {
                     name: #module,
+                    version: #version,
                     functions: &[
                         #(#funcs),*
                     ],
@@ -369,21 +374,25 @@ pub(crate) fn parse(attr: TokenStream, item: TokenStream) -> syn::Result<TokenSt
 
 mod kw {
     syn::custom_keyword!(module);
+    syn::custom_keyword!(version);
     syn::custom_keyword!(def);
 }
 
 const MODULE: Symbol = Symbol("name");
+const VERSION: Symbol = Symbol("version");
 const DEF: Symbol = Symbol("def");
 
 /// The `#[ffi]` attribute.
 struct FfiAttr {
     module: String,
+    version: u32,
     structs: Vec<AstNode<StructDefinition>>,
 }
 
 impl Parse for FfiAttr {
     fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
         let mut module = Attr::none(MODULE);
+        let mut version = Attr::none(VERSION);
         let mut def = Attr::none(DEF);
 
         while !input.is_empty() {
@@ -395,6 +404,13 @@ impl Parse for FfiAttr {
                 let name: LitStr = input.parse()?;
                 skip_comma(input)?;
                 module.set(&name, name.value())?;
+            // `version = ...`
+            } else if lookahead.peek(kw::version) {
+                input.parse::<kw::version>()?;
+                let _: Token![=] = input.parse()?;
+                let lit: LitInt = input.parse()?;
+                skip_comma(input)?;
+                version.set(&lit, lit.base10_parse()?)?;
             // `def = "..."`
             } else if lookahead.peek(kw::def) {
                 input.parse::<kw::def>()?;
@@ -415,6 +431,7 @@ impl Parse for FfiAttr {
             .ok_or(Error::new(input.span(), "missing `{MODULE}` argument"))?;
         Ok(Self {
             module,
+            version: version.get().unwrap_or(1),
             structs: def.get().unwrap_or_default(),
         })
     }
@@ -13,15 +13,19 @@ pub fn generate_code(policy: &Policy) -> String {
         .iter()
         .filter(|s| reachable.contains(s.identifier.as_str()))
         .map(|s| {
-            let doc = format!(" {} policy struct.", s.identifier);
+            let doc = item_docs(policy, s.locator, || {
+                format!(" {} policy struct.", s.identifier)
+            });
             let name = mk_ident(&s.identifier);
+            let fields = field_docs(policy, s.locator);
+            let docs = s.fields.iter().map(|f| docs_for(&fields, &f.identifier));
             let names = s.fields.iter().map(|f| mk_ident(&f.identifier));
             let types = s.fields.iter().map(|f| vtype_to_rtype(&f.field_type));
             quote! {
-                #[doc = #doc]
+                #doc
                 #[value]
                 pub struct #name {
-                    #(pub #names: #types),*
+                    #(#docs pub #names: #types),*
                 }
             }
         });
@@ -31,28 +35,36 @@ pub fn generate_code(policy: &Policy) -> String {
         .iter()
         .filter(|e| reachable.contains(e.identifier.as_str()))
         .map(|e| {
-            let doc = format!(" {} policy enum.", e.identifier);
+            let doc = item_docs(policy, e.locator, || {
+                format!(" {} policy enum.", e.identifier)
+            });
             let name = mk_ident(&e.identifier);
+            let values = field_docs(policy, e.locator);
+            let docs = e.values.iter().map(|v| docs_for(&values, v));
             let names = e.values.iter().map(|v| mk_ident(v));
             quote! {
-                #[doc = #doc]
+                #doc
                 #[value]
                 pub enum #name {
-                    #(#names),*
+                    #(#docs #names),*
                 }
             }
         });
 
     let effects = policy.effects.iter().map(|s| {
-        let doc = format!(" {} policy effect.", s.identifier);
+        let doc = item_docs(policy, s.locator, || {
+            format!(" {} policy effect.", s.identifier)
+        });
         let ident = mk_ident(&s.identifier);
+        let fields = field_docs(policy, s.locator);
+        let field_docs = s.fields.iter().map(|f| docs_for(&fields, &f.identifier));
         let field_idents = s.fields.iter().map(|f| mk_ident(&f.identifier));
         let field_types = s.fields.iter().map(|f| vtype_to_rtype(&f.field_type));
         quote! {
-            #[doc = #doc]
+            #doc
             #[effect]
             pub struct #ident {
-                #(pub #field_idents: #field_types),*
+                #(#field_docs pub #field_idents: #field_types),*
             }
         }
     });
@@ -71,6 +83,7 @@ pub fn generate_code(policy: &Policy) -> String {
 
     let actions = {
         let sigs = policy.actions.iter().map(|action| {
+            let doc = item_docs(policy, action.locator, String::new);
             let ident = mk_ident(&action.identifier);
             let argnames = action.arguments.iter().map(|arg| mk_ident(&arg.identifier));
             let argtypes = action
@@ -78,6 +91,7 @@ pub fn generate_code(policy: &Policy) -> String {
                 .iter()
                 .map(|arg| vtype_to_rtype(&arg.field_type));
             quote! {
+                #doc
                 fn #ident(&mut self, #(#argnames: #argtypes),*) -> Result<(), ClientError>;
             }
         });
@@ -90,6 +104,45 @@ pub fn generate_code(policy: &Policy) -> String {
         }
     };
 
+    let action_structs = {
+        let taken = policy
+            .structs
+            .iter()
+            .map(|s| s.identifier.as_str())
+            .chain(policy.enums.iter().map(|e| e.identifier.as_str()))
+            .chain(policy.effects.iter().map(|e| e.identifier.as_str()))
+            .chain(["Effect", "ActorExt", "PolicyClient"])
+            .collect::<HashSet<_>>();
+        policy.actions.iter().map(move |action| {
+            let doc = item_docs(policy, action.locator, || {
+                format!(" {} policy action.", action.identifier)
+            });
+            let args = field_docs(policy, action.locator);
+            let argdocs = action
+                .arguments
+                .iter()
+                .map(|arg| docs_for(&args, &arg.identifier));
+            let action_ident = mk_ident(&action.identifier);
+            let mut name = to_upper_camel_case(&action.identifier);
+            if taken.contains(name.as_str()) {
+                name.push_str("Action");
+            }
+            let ident = mk_ident(&name);
+            let argnames = action.arguments.iter().map(|arg| mk_ident(&arg.identifier));
+            let argtypes = action
+                .arguments
+                .iter()
+                .map(|arg| vtype_to_rtype(&arg.field_type));
+            quote! {
+                #doc
+                #[action(#action_ident)]
+                pub struct #ident {
+                    #(#argdocs pub #argnames: #argtypes),*
+                }
+            }
+        })
+    };
+
     prettyplease::unparse(&syn::parse_quote! {
         //! Code generated by `policy-ifgen`. DO NOT EDIT.
         #![allow(clippy::duplicated_attributes)]
@@ -103,7 +156,7 @@ pub fn generate_code(policy: &Policy) -> String {
         use alloc::{string::String, vec::Vec};
 
         use aranya_policy_ifgen::{
-            macros::{actions, effect, effects, value},
+            macros::{action, actions, effect, effects, value},
             ClientError, Id, Value,
         };
 
@@ -115,6 +168,10 @@ pub fn generate_code(policy: &Policy) -> String {
         #(#effects)*
 
         #actions
+        #(#action_structs)*
+
+        /// Client for the policy, which calls its actions and reports its effects.
+        pub type PolicyClient<EN, SP> = aranya_policy_ifgen::PolicyClient<EN, SP, Effect>;
     })
 }
 
@@ -151,8 +208,10 @@ fn collect_reachable_types(policy: &Policy) -> HashSet<&str> {
     ) {
         match ty {
             VType::Struct(s) => {
+                // Effects are struct types too, but are always generated and their
+                // fields are visited with the other effects.
                 if found.insert(s.as_str()) {
-                    for field in struct_defs[s.as_str()] {
+                    for field in struct_defs.get(s.as_str()).copied().unwrap_or_default() {
                         visit(struct_defs, found, &field.field_type);
                     }
                 }
@@ -188,6 +247,73 @@ fn collect_reachable_types(policy: &Policy) -> HashSet<&str> {
     found
 }
 
+/// Returns the doc comment (`///`) directly before the definition at `locator`, or
+/// `default` if it has none.
+fn item_docs(policy: &Policy, locator: usize, default: impl FnOnce() -> String) -> TokenStream {
+    let before = policy.text.get(..locator).unwrap_or_default();
+    let mut lines = before
+        .lines()
+        .rev()
+        // The definition's own line, up to the definition.
+        .skip(usize::from(!before.ends_with('\n')))
+        .map_while(|line| line.trim().strip_prefix("///"))
+        .collect::<Vec<_>>();
+    lines.reverse();
+    if lines.is_empty() {
+        let default = default();
+        if default.is_empty() {
+            return TokenStream::new();
+        }
+        return quote! { #[doc = #default] };
+    }
+    quote! { #(#[doc = #lines])* }
+}
+
+/// Returns the doc comment lines before each field (or argument, or enum value) of
+/// the definition at `locator`, by name.
+fn field_docs(policy: &Policy, locator: usize) -> HashMap<&str, Vec<&str>> {
+    let text = policy
+        .ranges
+        .iter()
+        .find(|(start, _)| *start == locator)
+        .and_then(|&(start, end)| policy.text.get(start..end))
+        .unwrap_or_default();
+    let mut docs = HashMap::new();
+    let mut pending = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            pending.push(doc);
+        } else if !line.is_empty() && !pending.is_empty() {
+            let name = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .next()
+                .unwrap_or_default();
+            docs.insert(name, std::mem::take(&mut pending));
+        }
+    }
+    docs
+}
+
+/// Returns the doc attributes for the field `name`.
+fn docs_for(docs: &HashMap<&str, Vec<&str>>, name: &str) -> TokenStream {
+    let lines = docs.get(name).map(Vec::as_slice).unwrap_or_default();
+    quote! { #(#[doc = #lines])* }
+}
+
+/// Converts a `snake_case` action name to `UpperCamelCase`, leaving names which are
+/// already camel case as they are.
+fn to_upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
 /// Makes an identifier from a string, using raw identifiers (`r#mod`) when necessary.
 fn mk_ident(string: &str) -> syn::Ident {
     syn::parse_str::<syn::Ident>(string)
@@ -203,4 +329,75 @@ mod test {
         assert_eq!(mk_ident("foo").to_string(), "foo");
         assert_eq!(mk_ident("mod").to_string(), "r#mod");
     }
+
+    #[test]
+    fn test_docs() {
+        let policy = aranya_policy_lang::lang::parse_policy_str(
+            r#"
+            /// A user was added.
+            effect UserAdded {
+                /// The user's name.
+                name string,
+                id id,
+            }
+
+            // Not a doc comment.
+            action add_user(
+                /// The name of the user.
+                name string,
+            ) {
+            }
+            "#,
+            aranya_policy_lang::lang::Version::V1,
+        )
+        .unwrap();
+        let code = generate_code(&policy);
+        assert!(code.contains("/// A user was added.\n#[effect]"));
+        assert!(code.contains("/// The user's name.\n    pub name: String,\n    pub id: Id,"));
+        assert!(code.contains("/// add_user policy action.\n#[action(add_user)]"));
+        assert!(code.contains("/// The name of the user.\n    pub name: String,"));
+        assert!(!code.contains("UserAdded policy effect"));
+        assert!(!code.contains("Not a doc comment"));
+    }
+
+    #[test]
+    fn test_nested_structs() {
+        let policy = aranya_policy_lang::lang::parse_policy_str(
+            r#"
+            struct Inner {
+                x int,
+            }
+
+            struct Outer {
+                inner struct Inner,
+            }
+
+            struct Unused {
+                x int,
+            }
+
+            effect Started {
+                outer struct Outer,
+            }
+
+            effect Finished {
+                started optional struct Started,
+            }
+            "#,
+            aranya_policy_lang::lang::Version::V1,
+        )
+        .unwrap();
+        let code = generate_code(&policy);
+        assert!(code.contains("pub struct Inner {\n    pub x: i64,\n}"));
+        assert!(code.contains("pub struct Outer {\n    pub inner: Inner,\n}"));
+        assert!(code.contains("pub started: Option<Started>,"));
+        assert!(!code.contains("Unused"));
+    }
+
+    #[test]
+    fn test_to_upper_camel_case() {
+        assert_eq!(to_upper_camel_case("create_afc_label"), "CreateAfcLabel");
+        assert_eq!(to_upper_camel_case("StartGame"), "StartGame");
+        assert_eq!(to_upper_camel_case("mod"), "Mod");
+    }
 }
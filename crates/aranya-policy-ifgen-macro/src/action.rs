@@ -0,0 +1,148 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, spanned::Spanned, GenericArgument, Ident, ItemStruct, PathArguments, Type,
+};
+
+use crate::common::get_derive;
+
+pub(super) fn parse(attr: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    // `vm_action!` stringifies the identifier, so drop any `r#`.
+    let action_ident = syn::parse2::<Ident>(attr)?.unraw();
+    let strukt: ItemStruct = syn::parse2(item)?;
+
+    let ident = &strukt.ident;
+    let builder = format_ident!("{}Builder", ident);
+    let vis = &strukt.vis;
+
+    let fields = strukt
+        .fields
+        .iter()
+        .map(|f| {
+            let ident = f
+                .ident
+                .as_ref()
+                .ok_or_else(|| syn::Error::new(f.span(), "tuple structs not allowed"))?;
+            Ok((ident, &f.ty, option_inner(&f.ty)))
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let field_idents = fields.iter().map(|(ident, _, _)| ident).collect::<Vec<_>>();
+    // Optional arguments are stored as they are, and default to `None`.
+    let builder_types = fields.iter().map(|&(_, ty, inner)| match inner {
+        Some(_) => quote! { #ty },
+        None => quote! { ::core::option::Option<#ty> },
+    });
+    let setters = fields.iter().map(|&(ident, ty, inner)| {
+        let doc = format!(" Sets the `{ident}` argument.");
+        let ty = inner.unwrap_or(ty);
+        quote! {
+            #[doc = #doc]
+            pub fn #ident(mut self, #ident: impl ::core::convert::Into<#ty>) -> Self {
+                self.#ident = ::core::option::Option::Some(#ident.into());
+                self
+            }
+        }
+    });
+    let builds = fields.iter().map(|&(ident, _, inner)| {
+        let name = ident.unraw().to_string();
+        match inner {
+            Some(_) => quote! { #ident: self.#ident },
+            None => quote! {
+                #ident: self.#ident.ok_or(
+                    ::aranya_policy_ifgen::ActionError::MissingArgument(#name),
+                )?
+            },
+        }
+    });
+
+    let derive = get_derive();
+    let builder_doc = format!(" Builder for [`{ident}`].");
+
+    Ok(quote! {
+        #derive
+        #strukt
+
+        impl #ident {
+            /// Returns a builder for the action, whose optional arguments default to
+            /// `None`.
+            pub fn builder() -> #builder {
+                #builder::default()
+            }
+
+            /// Calls the action with `actor`.
+            pub fn call<A: ::aranya_policy_ifgen::Actor + ?Sized>(
+                self,
+                actor: &mut A,
+            ) -> ::core::result::Result<(), ::aranya_policy_ifgen::ClientError> {
+                actor.call_action(::aranya_policy_ifgen::vm_action! {
+                    #action_ident( #(self.#field_idents),* )
+                })
+            }
+
+            /// Calls the action with an `actor` which calls actions asynchronously.
+            pub async fn call_async<A: ::aranya_policy_ifgen::AsyncActor + ?Sized>(
+                self,
+                actor: &mut A,
+            ) -> ::core::result::Result<(), ::aranya_policy_ifgen::ClientError> {
+                actor
+                    .call_action(::aranya_policy_ifgen::vm_action! {
+                        #action_ident( #(self.#field_idents),* )
+                    })
+                    .await
+            }
+        }
+
+        #[doc = #builder_doc]
+        #[derive(Clone, Debug, Default)]
+        #vis struct #builder {
+            #(#field_idents: #builder_types),*
+        }
+
+        impl #builder {
+            #(#setters)*
+
+            /// Builds the action, failing if a required argument was not set.
+            pub fn build(
+                self,
+            ) -> ::core::result::Result<#ident, ::aranya_policy_ifgen::ActionError> {
+                ::core::result::Result::Ok(#ident { #(#builds),* })
+            }
+
+            /// Builds the action and calls it with `actor`.
+            pub fn call<A: ::aranya_policy_ifgen::Actor + ?Sized>(
+                self,
+                actor: &mut A,
+            ) -> ::core::result::Result<(), ::aranya_policy_ifgen::ActionError> {
+                ::core::result::Result::Ok(self.build()?.call(actor)?)
+            }
+
+            /// Builds the action and calls it with an `actor` which calls actions
+            /// asynchronously.
+            pub async fn call_async<A: ::aranya_policy_ifgen::AsyncActor + ?Sized>(
+                self,
+                actor: &mut A,
+            ) -> ::core::result::Result<(), ::aranya_policy_ifgen::ActionError> {
+                ::core::result::Result::Ok(self.build()?.call_async(actor).await?)
+            }
+        }
+    })
+}
+
+/// Returns `T` if `ty` is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) if args.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
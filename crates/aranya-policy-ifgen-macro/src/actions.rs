@@ -1,6 +1,8 @@
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::{spanned::Spanned, FnArg, Ident, ItemTrait, Pat, Signature, TraitItem};
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, spanned::Spanned, FnArg, Ident, ItemTrait, Pat, ReturnType, Signature, TraitItem,
+};
 
 pub(super) fn parse(_attr: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
     let act: ItemTrait = syn::parse2(item)?;
@@ -16,24 +18,68 @@ pub(super) fn parse(_attr: TokenStream, item: TokenStream) -> syn::Result<TokenS
             };
 
             let sig = &func.sig;
-            let action_ident = &sig.ident;
+            let attrs = &func.attrs;
+            // `vm_action!` stringifies the identifier, so drop any `r#`.
+            let action_ident = sig.ident.unraw();
             let arg_idents = get_args(sig)?;
 
-            Ok(quote! {
-                #sig {
-                    self.call_action(::aranya_policy_ifgen::vm_action! {
-                        #action_ident( #(#arg_idents),* )
-                    })
-                }
-            })
+            let mut async_sig = sig.clone();
+            let output = match &sig.output {
+                ReturnType::Default => quote! { () },
+                ReturnType::Type(_, ty) => quote! { #ty },
+            };
+            async_sig.output = syn::parse_quote! {
+                -> impl ::core::future::Future<Output = #output> + ::core::marker::Send
+            };
+
+            Ok((
+                quote! {
+                    #sig {
+                        self.call_action(::aranya_policy_ifgen::vm_action! {
+                            #action_ident( #(#arg_idents),* )
+                        })
+                    }
+                },
+                quote! { #(#attrs)* #async_sig; },
+                quote! {
+                    #async_sig {
+                        async move {
+                            self.call_action(::aranya_policy_ifgen::vm_action! {
+                                #action_ident( #(#arg_idents),* )
+                            })
+                            .await
+                        }
+                    }
+                },
+            ))
         })
-        .collect::<syn::Result<TokenStream>>()?;
+        .collect::<syn::Result<Vec<_>>>()?;
+    let (methods, (async_sigs, async_methods)): (Vec<_>, (Vec<_>, Vec<_>)) = methods
+        .into_iter()
+        .map(|(method, sig, async_method)| (method, (sig, async_method)))
+        .unzip();
+
+    let attrs = act.attrs.iter().filter(|attr| !attr.path().is_ident("doc"));
+    let vis = &act.vis;
+    let async_ident = format_ident!("Async{}", ident);
+    let async_doc =
+        format!(" Like [`{ident}`], but for [`AsyncActor`](aranya_policy_ifgen::AsyncActor)s.");
 
     Ok(quote! {
         #act
 
         impl<A: ::aranya_policy_ifgen::Actor> #ident for A {
-            #methods
+            #(#methods)*
+        }
+
+        #[doc = #async_doc]
+        #(#attrs)*
+        #vis trait #async_ident {
+            #(#async_sigs)*
+        }
+
+        impl<A: ::aranya_policy_ifgen::AsyncActor + ::core::marker::Send> #async_ident for A {
+            #(#async_methods)*
         }
     })
 }
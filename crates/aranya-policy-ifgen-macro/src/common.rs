@@ -9,8 +9,19 @@ pub(crate) fn get_serde() -> TokenStream {
     }
 }
 
+/// Points serde's derives at the re-export in `aranya_policy_ifgen`, so that
+/// generated code does not need its own `serde` dependency.
+pub(crate) fn get_serde_crate() -> TokenStream {
+    if cfg!(feature = "serde") {
+        quote! { #[serde(crate = "::aranya_policy_ifgen::serde")] }
+    } else {
+        quote! {}
+    }
+}
+
 pub(crate) fn get_derive() -> TokenStream {
     let serde = get_serde();
+    let serde_crate = get_serde_crate();
     quote! {
         #[derive(
             Clone,
@@ -22,5 +33,6 @@ pub(crate) fn get_derive() -> TokenStream {
             Ord,
             #serde
         )]
+        #serde_crate
     }
 }
@@ -1,14 +1,14 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{spanned::Spanned, ItemStruct};
+use syn::{ext::IdentExt, spanned::Spanned, ItemStruct};
 
-use crate::common::get_derive;
+use crate::{common::get_derive, value::struct_conversions};
 
 pub(super) fn parse(_attr: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
     let strukt: ItemStruct = syn::parse2(item)?;
 
     let ident = &strukt.ident;
-    let name = ident.to_string();
+    let name = ident.unraw().to_string();
 
     let field_idents = strukt
         .fields
@@ -19,13 +19,17 @@ pub(super) fn parse(_attr: TokenStream, item: TokenStream) -> syn::Result<TokenS
                 .ok_or_else(|| syn::Error::new(f.span(), "tuple structs not allowed"))
         })
         .collect::<syn::Result<Vec<_>>>()?;
-    let field_names = field_idents.iter().map(|f| f.to_string());
+    // Raw identifiers (`r#type`) name the policy field without the `r#`.
+    let field_names = field_idents.iter().map(|f| f.unraw().to_string());
 
     let derive = get_derive();
+    // Effects are also policy struct types, so they can be nested in values.
+    let conversions = struct_conversions(&strukt)?;
 
     Ok(quote! {
         #derive
         #strukt
+        #conversions
 
         impl #ident {
             /// Gives the name of the effect.
@@ -7,6 +7,7 @@
 use proc_macro::TokenStream;
 use syn::Error;
 
+mod action;
 mod actions;
 mod common;
 mod effect;
@@ -34,6 +35,13 @@ pub fn effects(attr: TokenStream, item: TokenStream) -> TokenStream {
         .into()
 }
 
+#[proc_macro_attribute]
+pub fn action(attr: TokenStream, item: TokenStream) -> TokenStream {
+    action::parse(attr.into(), item.into())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
 #[proc_macro_attribute]
 pub fn actions(attr: TokenStream, item: TokenStream) -> TokenStream {
     actions::parse(attr.into(), item.into())
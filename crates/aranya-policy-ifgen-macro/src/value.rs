@@ -1,6 +1,6 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{spanned::Spanned, Fields, Item, ItemEnum, ItemStruct};
+use syn::{ext::IdentExt, spanned::Spanned, Fields, Item, ItemEnum, ItemStruct};
 
 use crate::common::get_derive;
 
@@ -13,8 +13,21 @@ pub(super) fn parse(_attr: TokenStream, item: TokenStream) -> syn::Result<TokenS
 }
 
 fn handle_struct(strukt: ItemStruct) -> syn::Result<TokenStream> {
+    let derive = get_derive();
+    let conversions = struct_conversions(&strukt)?;
+
+    Ok(quote! {
+        #derive
+        #strukt
+        #conversions
+    })
+}
+
+/// Generates the conversions between `strukt` and a policy struct `Value`, which let
+/// it be nested in other policy types.
+pub(crate) fn struct_conversions(strukt: &ItemStruct) -> syn::Result<TokenStream> {
     let ident = &strukt.ident;
-    let name = ident.to_string();
+    let name = ident.unraw().to_string();
 
     let field_idents = strukt
         .fields
@@ -25,17 +38,13 @@ fn handle_struct(strukt: ItemStruct) -> syn::Result<TokenStream> {
                 .ok_or_else(|| syn::Error::new(f.span(), "tuple structs not allowed"))
         })
         .collect::<syn::Result<Vec<_>>>()?;
+    // Raw identifiers (`r#type`) name the policy field without the `r#`.
     let field_names = field_idents
         .iter()
-        .map(|f| f.to_string())
+        .map(|f| f.unraw().to_string())
         .collect::<Vec<_>>();
 
-    let derive = get_derive();
-
     Ok(quote! {
-        #derive
-        #strukt
-
         impl ::core::convert::TryFrom<::aranya_policy_ifgen::Value> for #ident {
             type Error = ::aranya_policy_ifgen::ValueConversionError;
             fn try_from(value: ::aranya_policy_ifgen::Value) -> ::core::result::Result<Self, Self::Error> {
@@ -86,7 +95,7 @@ fn handle_struct(strukt: ItemStruct) -> syn::Result<TokenStream> {
 
 fn handle_enum(enumeration: ItemEnum) -> syn::Result<TokenStream> {
     let ident = &enumeration.ident;
-    let enum_ident = ident.to_string();
+    let enum_ident = ident.unraw().to_string();
 
     for variant in &enumeration.variants {
         if !matches!(variant.fields, Fields::Unit) {
@@ -98,7 +107,8 @@ fn handle_enum(enumeration: ItemEnum) -> syn::Result<TokenStream> {
     }
 
     let var_idents: Vec<_> = enumeration.variants.iter().map(|f| &f.ident).collect();
-    let var_vals: Vec<_> = var_idents.iter().map(|id| id.to_string()).collect();
+    // Raw identifiers (`r#type`) name the policy value without the `r#`.
+    let var_vals: Vec<_> = var_idents.iter().map(|id| id.unraw().to_string()).collect();
 
     let derive = get_derive();
 
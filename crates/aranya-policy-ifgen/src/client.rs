@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+use core::{fmt, marker::PhantomData};
+
+use aranya_runtime::{
+    ClientError, ClientState, EffectSubscription, Engine, GraphId, Policy, Sink, StorageProvider,
+    VmAction, VmEffect,
+};
+
+use crate::{Actor, EffectsParseError};
+
+/// A client for a policy whose effects are `E`, which wraps a [`ClientState`] so its
+/// actions are called with the policy's generated `ActorExt` methods, and its effects
+/// are reported as `E`.
+///
+/// Generated code defines a `PolicyClient<EN, SP>` for each policy, whose effects are
+/// the policy's `Effect` enum.
+pub struct PolicyClient<EN: Engine, SP, E> {
+    state: ClientState<EN, SP>,
+    _effects: PhantomData<fn() -> E>,
+}
+
+impl<EN: Engine, SP, E> PolicyClient<EN, SP, E> {
+    /// Creates a `PolicyClient` from a [`ClientState`].
+    pub const fn new(state: ClientState<EN, SP>) -> Self {
+        Self {
+            state,
+            _effects: PhantomData,
+        }
+    }
+
+    /// Provides access to the wrapped [`ClientState`], such as for syncing.
+    pub fn state(&mut self) -> &mut ClientState<EN, SP> {
+        &mut self.state
+    }
+
+    /// Returns the wrapped [`ClientState`].
+    pub fn into_state(self) -> ClientState<EN, SP> {
+        self.state
+    }
+}
+
+impl<EN, SP, E> PolicyClient<EN, SP, E>
+where
+    EN: Engine<Effect = VmEffect>,
+    for<'a> EN::Policy: Policy<Action<'a> = VmAction<'a>>,
+    SP: StorageProvider,
+    E: TryFrom<VmEffect, Error = EffectsParseError>,
+{
+    /// Creates a graph with the policy in `policy_data`. `init` calls the action
+    /// which initializes the graph with the [`GraphInit`] actor it is passed.
+    ///
+    /// Returns the new graph, which holds the effects of the action.
+    pub fn new_graph(
+        &mut self,
+        policy_data: &[u8],
+        init: impl FnOnce(&mut GraphInit<'_, EN, SP>) -> Result<(), ClientError>,
+    ) -> Result<Graph<'_, EN, SP, E>, ClientError> {
+        let mut actor = GraphInit {
+            state: &mut self.state,
+            policy_data,
+            sink: EffectSink::default(),
+            id: None,
+        };
+        init(&mut actor)?;
+        let GraphInit { sink, id, .. } = actor;
+        Ok(Graph {
+            id: id.ok_or(ClientError::InitError)?,
+            client: self,
+            sink,
+        })
+    }
+
+    /// Returns an actor which calls actions on the graph `id`.
+    pub fn graph(&mut self, id: GraphId) -> Graph<'_, EN, SP, E> {
+        Graph {
+            client: self,
+            id,
+            sink: EffectSink::default(),
+        }
+    }
+
+    /// Imports a graph from an archive written by [`PolicyClient::export_graph`].
+    /// See [`ClientState::import_graph`].
+    ///
+    /// Returns the graph, which holds the effects of the imported commands.
+    pub fn import_graph(&mut self, archive: &[u8]) -> Result<Graph<'_, EN, SP, E>, ClientError> {
+        let mut sink = EffectSink::default();
+        let id = self.state.import_graph(archive, &mut sink)?;
+        Ok(Graph {
+            client: self,
+            id,
+            sink,
+        })
+    }
+
+    /// Exports the graph `id` to an archive. See [`ClientState::export_graph`].
+    pub fn export_graph(&mut self, id: GraphId) -> Result<Vec<u8>, ClientError> {
+        self.state.export_graph(id)
+    }
+
+    /// Subscribes to the effects of commands committed by this client. See
+    /// [`ClientState::subscribe_effects`].
+    pub fn subscribe_effects(&mut self) -> Effects<E> {
+        Effects {
+            inner: self.state.subscribe_effects(),
+            _effects: PhantomData,
+        }
+    }
+}
+
+/// An [`Actor`] which calls the action creating a graph, from
+/// [`PolicyClient::new_graph`].
+///
+/// Only one action can be called.
+pub struct GraphInit<'a, EN: Engine, SP> {
+    state: &'a mut ClientState<EN, SP>,
+    policy_data: &'a [u8],
+    sink: EffectSink,
+    id: Option<GraphId>,
+}
+
+impl<EN, SP> Actor for GraphInit<'_, EN, SP>
+where
+    EN: Engine<Effect = VmEffect>,
+    for<'a> EN::Policy: Policy<Action<'a> = VmAction<'a>>,
+    SP: StorageProvider,
+{
+    fn call_action(&mut self, action: VmAction<'_>) -> Result<(), ClientError> {
+        if self.id.is_some() {
+            return Err(ClientError::InitError);
+        }
+        self.id = Some(
+            self.state
+                .new_graph(self.policy_data, action, &mut self.sink)?,
+        );
+        Ok(())
+    }
+}
+
+/// An [`Actor`] which calls actions on a graph, from [`PolicyClient::graph`].
+///
+/// The effects of its actions are kept until they are taken with
+/// [`Graph::take_effects`].
+pub struct Graph<'a, EN: Engine, SP, E> {
+    client: &'a mut PolicyClient<EN, SP, E>,
+    id: GraphId,
+    sink: EffectSink,
+}
+
+impl<EN: Engine, SP, E> Graph<'_, EN, SP, E> {
+    /// Returns the ID of the graph.
+    pub fn id(&self) -> GraphId {
+        self.id
+    }
+}
+
+impl<EN, SP, E> Graph<'_, EN, SP, E>
+where
+    EN: Engine,
+    E: TryFrom<VmEffect, Error = EffectsParseError>,
+{
+    /// Takes the effects of the actions called so far.
+    pub fn take_effects(&mut self) -> Result<Vec<E>, EffectsParseError> {
+        self.sink.effects.drain(..).map(E::try_from).collect()
+    }
+}
+
+impl<EN, SP, E> Actor for Graph<'_, EN, SP, E>
+where
+    EN: Engine<Effect = VmEffect>,
+    for<'a> EN::Policy: Policy<Action<'a> = VmAction<'a>>,
+    SP: StorageProvider,
+{
+    fn call_action(&mut self, action: VmAction<'_>) -> Result<(), ClientError> {
+        self.client
+            .state
+            .action(self.id, &mut self.sink, action)
+            .map(|_| ())
+    }
+}
+
+/// The effects committed by a [`PolicyClient`], from
+/// [`PolicyClient::subscribe_effects`].
+///
+/// Like [`EffectSubscription`], but effects are parsed as `E`.
+pub struct Effects<E> {
+    inner: EffectSubscription<VmEffect>,
+    _effects: PhantomData<fn() -> E>,
+}
+
+impl<E> Effects<E> {
+    /// Returns the number of queued effects.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Reports whether no effects are queued.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl<E> Iterator for Effects<E>
+where
+    E: TryFrom<VmEffect, Error = EffectsParseError>,
+{
+    type Item = Result<E, EffectsParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(E::try_from)
+    }
+}
+
+impl<E> fmt::Debug for Effects<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Effects").field("len", &self.len()).finish()
+    }
+}
+
+/// Collects the committed effects of a graph's actions.
+#[derive(Default)]
+struct EffectSink {
+    effects: Vec<VmEffect>,
+    pending: Vec<VmEffect>,
+}
+
+impl Sink<VmEffect> for EffectSink {
+    fn begin(&mut self) {
+        self.pending.clear();
+    }
+
+    fn consume(&mut self, effect: VmEffect) {
+        self.pending.push(effect);
+    }
+
+    fn rollback(&mut self) {
+        self.pending.clear();
+    }
+
+    fn commit(&mut self) {
+        self.effects.append(&mut self.pending);
+    }
+}
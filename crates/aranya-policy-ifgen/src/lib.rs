@@ -8,20 +8,24 @@
 extern crate alloc;
 
 use alloc::{collections::BTreeMap, string::String, vec::Vec};
-use core::fmt;
+use core::{fmt, future::Future};
+
+mod client;
 
 /// Macros used in code generated by `policy_ifgen_build``.
 pub mod macros {
-    pub use aranya_policy_ifgen_macro::{actions, effect, effects, value};
+    pub use aranya_policy_ifgen_macro::{action, actions, effect, effects, value};
 }
 
 pub use alloc::format;
 
 pub use aranya_policy_vm::{Id, KVPair, Struct, TryFromValue, Value, ValueConversionError};
-pub use aranya_runtime::{vm_action, vm_effect, ClientError, VmAction, VmEffect};
+pub use aranya_runtime::{vm_action, vm_effect, ClientError, GraphId, VmAction, VmEffect};
 #[cfg(feature = "serde")]
 pub use serde;
 
+pub use crate::client::{Effects, Graph, GraphInit, PolicyClient};
+
 /// Struct fields
 pub type Fields = Vec<KVPair>;
 /// Map of struct fields
@@ -33,6 +37,16 @@ pub trait Actor {
     fn call_action(&mut self, action: VmAction<'_>) -> Result<(), ClientError>;
 }
 
+/// An actor which can call policy actions without blocking, such as one which
+/// sends them to a service.
+pub trait AsyncActor {
+    /// Call an "untyped" policy action ([`VmAction`]).
+    fn call_action(
+        &mut self,
+        action: VmAction<'_>,
+    ) -> impl Future<Output = Result<(), ClientError>> + Send;
+}
+
 /// Possible errors from policy effect parsing.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EffectsParseError {
@@ -58,3 +72,36 @@ impl fmt::Display for EffectsParseError {
         }
     }
 }
+
+/// Possible errors from calling a policy action with its builder.
+#[derive(Debug)]
+pub enum ActionError {
+    /// A required argument of the action was not set.
+    MissingArgument(&'static str),
+    /// The actor failed to call the action.
+    Client(ClientError),
+}
+
+impl core::error::Error for ActionError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::MissingArgument(_) => None,
+            Self::Client(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingArgument(name) => write!(f, "action argument `{name}` was not set"),
+            Self::Client(err) => write!(f, "action failed: {err}"),
+        }
+    }
+}
+
+impl From<ClientError> for ActionError {
+    fn from(err: ClientError) -> Self {
+        Self::Client(err)
+    }
+}
@@ -0,0 +1,106 @@
+#![allow(clippy::panic)]
+
+use aranya_policy_compiler::Compiler;
+use aranya_policy_ifgen::{macros::*, ClientError};
+use aranya_policy_lang::lang::parse_policy_document;
+use aranya_policy_vm::ffi::FfiModule;
+use aranya_runtime::{
+    storage::memory::MemStorageProvider,
+    testing::vm::{TestEngine, TEST_POLICY_1},
+    vm_policy::testing::TestFfiEnvelope,
+    ClientState,
+};
+
+#[effects]
+pub enum Effect {
+    StuffHappened(StuffHappened),
+    OutOfRange(OutOfRange),
+}
+
+#[effect]
+pub struct StuffHappened {
+    pub x: i64,
+    pub y: i64,
+}
+
+#[effect]
+pub struct OutOfRange {
+    pub value: i64,
+    pub increment: i64,
+}
+
+#[allow(non_snake_case)]
+#[actions]
+pub trait ActorExt {
+    fn init(&mut self, nonce: i64) -> Result<(), ClientError>;
+    fn create_action(&mut self, v: i64) -> Result<(), ClientError>;
+    fn increment(&mut self) -> Result<(), ClientError>;
+    fn incrementFour(&mut self, n: i64) -> Result<(), ClientError>;
+}
+
+pub type PolicyClient<EN, SP> = aranya_policy_ifgen::PolicyClient<EN, SP, Effect>;
+
+/// Creates a client for [`TEST_POLICY_1`].
+fn new_client() -> PolicyClient<TestEngine, MemStorageProvider> {
+    let ast = parse_policy_document(TEST_POLICY_1).unwrap_or_else(|e| panic!("{e}"));
+    let module = Compiler::new(&ast)
+        .ffi_modules(&[TestFfiEnvelope::SCHEMA])
+        .compile()
+        .unwrap_or_else(|e| panic!("{e}"));
+    PolicyClient::new(ClientState::new(
+        TestEngine::from_module(module),
+        MemStorageProvider::new(),
+    ))
+}
+
+fn stuff(y: i64) -> Effect {
+    Effect::StuffHappened(StuffHappened { x: 1, y })
+}
+
+#[test]
+fn test_policy_client() {
+    let mut client = new_client();
+    let mut effects = client.subscribe_effects();
+
+    let mut graph = client.new_graph(&[0u8], |init| init.init(0)).unwrap();
+    assert!(graph.take_effects().unwrap().is_empty());
+    graph.create_action(3).unwrap();
+    graph.increment().unwrap();
+    assert_eq!(graph.take_effects().unwrap(), [stuff(3), stuff(4)]);
+    let id = graph.id();
+
+    // The effects of a rejected action are not kept.
+    let mut graph = client.graph(id);
+    assert!(graph.incrementFour(3).is_err());
+    graph.increment().unwrap();
+    assert_eq!(graph.take_effects().unwrap(), [stuff(5)]);
+
+    assert_eq!(
+        effects.by_ref().collect::<Result<Vec<_>, _>>().unwrap(),
+        [stuff(3), stuff(4), stuff(5)]
+    );
+    assert!(effects.is_empty());
+
+    let archive = client.export_graph(id).unwrap();
+    let mut other = new_client();
+    let mut graph = other.import_graph(&archive).unwrap();
+    assert_eq!(graph.id(), id);
+    assert_eq!(graph.take_effects().unwrap(), [stuff(3), stuff(4), stuff(5)]);
+}
+
+#[test]
+fn test_policy_client_init() {
+    let mut client = new_client();
+
+    assert!(matches!(
+        client.new_graph(&[0u8], |_| Ok(())),
+        Err(ClientError::InitError)
+    ));
+    assert!(matches!(
+        client.new_graph(&[0u8], |init| {
+            init.init(0)?;
+            init.init(1)
+        }),
+        Err(ClientError::InitError)
+    ));
+}
@@ -7,7 +7,7 @@
 extern crate alloc;
 use alloc::{string::String, vec::Vec};
 use aranya_policy_ifgen::{
-    macros::{actions, effect, effects, value},
+    macros::{action, actions, effect, effects, value},
     ClientError, Id, Value,
 };
 /// Players policy struct.
@@ -57,3 +57,17 @@ pub trait ActorExt {
     fn StartGame(&mut self, players: Players) -> Result<(), ClientError>;
     fn MakeMove(&mut self, gameID: Id, x: i64, y: i64) -> Result<(), ClientError>;
 }
+/// StartGame policy action.
+#[action(StartGame)]
+pub struct StartGame {
+    pub players: Players,
+}
+/// MakeMove policy action.
+#[action(MakeMove)]
+pub struct MakeMove {
+    pub gameID: Id,
+    pub x: i64,
+    pub y: i64,
+}
+/// Client for the policy, which calls its actions and reports its effects.
+pub type PolicyClient<EN, SP> = aranya_policy_ifgen::PolicyClient<EN, SP, Effect>;
@@ -7,7 +7,7 @@
 extern crate alloc;
 use alloc::{string::String, vec::Vec};
 use aranya_policy_ifgen::{
-    macros::{actions, effect, effects, value},
+    macros::{action, actions, effect, effects, value},
     ClientError, Id, Value,
 };
 /// Enum of policy effects that can occur in response to a policy action.
@@ -183,3 +183,102 @@ pub trait ActorExt {
         label: i64,
     ) -> Result<(), ClientError>;
 }
+/// create_ttc_team policy action.
+#[action(create_ttc_team)]
+pub struct CreateTtcTeam {
+    pub ttc_team_name: String,
+    pub user_name: String,
+    pub ident_pk: Vec<u8>,
+    pub sign_pk: Vec<u8>,
+    pub enc_pk: Vec<u8>,
+}
+/// add_owner policy action.
+#[action(add_owner)]
+pub struct AddOwner {
+    pub user_id: Id,
+    pub name: String,
+    pub ident_pk: Vec<u8>,
+    pub sign_pk: Vec<u8>,
+    pub enc_pk: Vec<u8>,
+}
+/// add_admin policy action.
+#[action(add_admin)]
+pub struct AddAdmin {
+    pub user_id: Id,
+    pub name: String,
+    pub ident_pk: Vec<u8>,
+    pub sign_pk: Vec<u8>,
+    pub enc_pk: Vec<u8>,
+}
+/// add_operator policy action.
+#[action(add_operator)]
+pub struct AddOperator {
+    pub user_id: Id,
+    pub name: String,
+    pub ident_pk: Vec<u8>,
+    pub sign_pk: Vec<u8>,
+    pub enc_pk: Vec<u8>,
+}
+/// add_satellite policy action.
+#[action(add_satellite)]
+pub struct AddSatellite {
+    pub user_id: Id,
+    pub name: String,
+    pub ident_pk: Vec<u8>,
+    pub sign_pk: Vec<u8>,
+    pub enc_pk: Vec<u8>,
+}
+/// remove_owner policy action.
+#[action(remove_owner)]
+pub struct RemoveOwner {
+    pub user_id: Id,
+}
+/// remove_admin policy action.
+#[action(remove_admin)]
+pub struct RemoveAdmin {
+    pub user_id: Id,
+}
+/// remove_operator policy action.
+#[action(remove_operator)]
+pub struct RemoveOperator {
+    pub user_id: Id,
+}
+/// remove_satellite policy action.
+#[action(remove_satellite)]
+pub struct RemoveSatellite {
+    pub user_id: Id,
+}
+/// create_afc_label policy action.
+#[action(create_afc_label)]
+pub struct CreateAfcLabel {
+    pub name: String,
+    pub label: i64,
+}
+/// assign_afc_label policy action.
+#[action(assign_afc_label)]
+pub struct AssignAfcLabel {
+    pub user_id: Id,
+    pub label: i64,
+    pub op: String,
+}
+/// revoke_afc_label policy action.
+#[action(revoke_afc_label)]
+pub struct RevokeAfcLabel {
+    pub user_id: Id,
+    pub label: i64,
+}
+/// create_afc_bidi_channel policy action.
+#[action(create_afc_bidi_channel)]
+pub struct CreateAfcBidiChannel {
+    pub peer_id: Id,
+    pub label: i64,
+}
+/// create_afc_uni_channel policy action.
+#[action(create_afc_uni_channel)]
+pub struct CreateAfcUniChannel {
+    pub seal_id: Id,
+    pub open_id: Id,
+    pub label: i64,
+}
+/// Client for the policy, which calls its actions and reports its effects.
+pub type PolicyClient<EN, SP> = aranya_policy_ifgen::PolicyClient<EN, SP, Effect>;
@@ -1,4 +1,13 @@
-use aranya_policy_ifgen::{macros::*, ClientError, KVPair};
+use std::{
+    future::Future,
+    pin::pin,
+    ptr,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use aranya_policy_ifgen::{
+    macros::*, ActionError, Actor, AsyncActor, ClientError, KVPair, Value, VmAction,
+};
 
 #[effects]
 pub enum EffectEnum {
@@ -56,6 +65,21 @@ pub enum TestEnum {
     C,
 }
 
+/// Nests an effect and a struct in a policy struct.
+#[value]
+pub struct Nested {
+    effect: TestEffect,
+    fields: Option<TestStructFields>,
+}
+
+/// Uses raw identifiers for policy names which are Rust keywords.
+#[value]
+#[allow(non_camel_case_types)]
+pub enum Keywords {
+    r#type,
+    r#match,
+}
+
 #[allow(clippy::too_many_arguments)]
 #[actions]
 pub trait TestActions {
@@ -74,6 +98,55 @@ pub trait TestActions {
     ) -> Result<(), ClientError>;
 }
 
+#[action(act)]
+pub struct Act {
+    pub a: i64,
+    pub b: String,
+    pub c: Option<i64>,
+}
+
+/// Uses raw identifiers for a policy action which is a Rust keyword.
+#[action(r#mod)]
+pub struct Mod {
+    pub r#type: i64,
+}
+
+/// Records the actions it is called with.
+#[derive(Default)]
+struct Recorder(Vec<String>);
+
+impl Actor for Recorder {
+    fn call_action(&mut self, action: VmAction<'_>) -> Result<(), ClientError> {
+        self.0.push(action.to_string());
+        Ok(())
+    }
+}
+
+impl AsyncActor for Recorder {
+    async fn call_action(&mut self, action: VmAction<'_>) -> Result<(), ClientError> {
+        Actor::call_action(self, action)
+    }
+}
+
+/// Polls `future` until it is ready.
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    // SAFETY: The waker ignores its data, and its functions do nothing.
+    let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
 #[test]
 fn test_parse_effect() {
     let a = 42;
@@ -117,4 +190,113 @@ fn test_serde() {
     fn impl_serde<'de, T: Serialize + Deserialize<'de>>() {}
 
     impl_serde::<TestEffect>();
+    impl_serde::<EffectEnum>();
+    impl_serde::<TestStructFields>();
+    impl_serde::<TestEnum>();
+}
+
+#[test]
+fn test_action_builder() {
+    let mut actor = Recorder::default();
+
+    Act::builder().a(1).b("x").call(&mut actor).unwrap();
+    Act::builder().c(3).b("y").a(2).call(&mut actor).unwrap();
+    let act = Act {
+        a: 1,
+        b: String::from("x"),
+        c: None,
+    };
+    act.call(&mut actor).unwrap();
+    assert_eq!(actor.0[0], actor.0[2]);
+    assert_ne!(actor.0[0], actor.0[1]);
+
+    assert!(matches!(
+        Act::builder().a(1).call(&mut actor),
+        Err(ActionError::MissingArgument("b"))
+    ));
+    assert_eq!(actor.0.len(), 3);
+}
+
+#[test]
+fn test_async_actions() {
+    let mut actor = Recorder::default();
+
+    Actor::call_action(
+        &mut actor,
+        aranya_policy_ifgen::vm_action!(act(1, String::from("x"), None::<i64>)),
+    )
+    .unwrap();
+    block_on(Act::builder().a(1).b("x").call_async(&mut actor)).unwrap();
+    assert_eq!(actor.0[0], actor.0[1]);
+
+    block_on(AsyncTestActions::act(
+        &mut actor,
+        0,
+        false,
+        String::new(),
+        Vec::new(),
+        TestStructFields::default(),
+        TestEnum::A,
+        None,
+        None,
+        None,
+        None,
+    ))
+    .unwrap();
+    assert_eq!(actor.0.len(), 3);
+}
+
+#[test]
+fn test_raw_enum_values() {
+    let value = Value::from(Keywords::r#type);
+    assert_eq!(value, Value::Enum("Keywords".into(), "type".into()));
+    assert_eq!(Keywords::try_from(value).unwrap(), Keywords::r#type);
+    assert!(Keywords::try_from(Value::Enum("Keywords".into(), "r#match".into())).is_err());
+}
+
+#[test]
+fn test_raw_actions() {
+    let mut actor = Recorder::default();
+
+    Mod::builder().r#type(1).call(&mut actor).unwrap();
+    assert_eq!(actor.0, ["mod(1)"]);
+    assert!(matches!(
+        Mod::builder().call(&mut actor),
+        Err(ActionError::MissingArgument("type"))
+    ));
+}
+
+#[test]
+fn test_nested_values() {
+    let nested = Nested {
+        effect: TestEffect {
+            a: 1,
+            b: String::from("b"),
+        },
+        fields: Some(TestStructFields::default()),
+    };
+    let Value::Struct(value) = Value::from(nested.clone()) else {
+        panic!("expected a struct value");
+    };
+    assert!(matches!(
+        &value.fields["effect"],
+        Value::Struct(s) if s.name == "TestEffect"
+    ));
+    assert!(matches!(
+        &value.fields["fields"],
+        Value::Struct(s) if s.name == "TestStructFields"
+    ));
+    assert_eq!(Nested::try_from(Value::Struct(value)).unwrap(), nested);
+
+    let mismatched = Value::from(Nested {
+        effect: TestEffect::default(),
+        fields: None,
+    });
+    let Value::Struct(mut mismatched) = mismatched else {
+        panic!("expected a struct value");
+    };
+    mismatched
+        .fields
+        .insert(String::from("effect"), Value::from(OtherStruct {}));
+    assert!(Nested::try_from(Value::Struct(mismatched)).is_err());
 }
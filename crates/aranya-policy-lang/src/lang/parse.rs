@@ -516,6 +516,9 @@ pub fn parse_expression(
                     ast::InternalFunction::Deserialize(Box::new(inner)),
                 ))
             }
+            Rule::policy_fingerprint => Ok(Expression::InternalFunction(
+                ast::InternalFunction::PolicyFingerprint,
+            )),
             Rule::identifier => Ok(Expression::Identifier(primary.as_str().to_owned())),
             Rule::expression => parse_expression(primary, pratt),
             _ => Err(ParseError::new(
@@ -755,8 +758,9 @@ fn parse_check_statement(
     let pc = descend(item);
     let token = pc.consume()?;
     let expression = parse_expression(token, pratt)?;
+    let reason = pc.next().map(parse_string_literal).transpose()?;
 
-    Ok(ast::CheckStatement { expression })
+    Ok(ast::CheckStatement { expression, reason })
 }
 
 /// Parse a Rule::match_statement into a MatchStatement.
@@ -1008,14 +1012,30 @@ fn parse_map_statement(
     })
 }
 
+/// Parse a Rule::use_definition into the module name, and its minimum version if one is
+/// given.
 fn parse_use_definition(
     field: Pair<'_, Rule>,
     cc: &mut ChunkContext,
-) -> Result<AstNode<String>, ParseError> {
+) -> Result<(String, Option<AstNode<ast::FfiVersionRequirement>>), ParseError> {
     let locator = cc.add_range(&field)?;
     let pc = descend(field);
     let identifier = pc.consume_string(Rule::identifier)?;
-    Ok(AstNode::new(identifier, locator))
+    let Some(version) = pc.next() else {
+        return Ok((identifier, None));
+    };
+    let min_version = version.as_str().parse::<u32>().map_err(|e| {
+        ParseError::new(
+            ParseErrorKind::InvalidNumber,
+            e.to_string(),
+            Some(version.as_span()),
+        )
+    })?;
+    let requirement = ast::FfiVersionRequirement {
+        module: identifier.clone(),
+        min_version,
+    };
+    Ok((identifier, Some(AstNode::new(requirement, locator))))
 }
 
 /// Parse a Rule::fact_definition into a FactDefinition.
@@ -1340,6 +1360,26 @@ fn parse_global_let_statement(
     ))
 }
 
+/// Parse a `Rule::static_assert` into a [StaticAssertStatement](ast::StaticAssertStatement).
+fn parse_static_assert(
+    item: Pair<'_, Rule>,
+    pratt: &PrattParser<Rule>,
+    cc: &mut ChunkContext,
+) -> Result<AstNode<ast::StaticAssertStatement>, ParseError> {
+    let locator = cc.add_range(&item)?;
+    let pc = descend(item);
+    let expression = pc.consume_expression(pratt)?;
+    let message = parse_string_literal(pc.consume_of_type(Rule::string_literal)?)?;
+
+    Ok(AstNode::new(
+        ast::StaticAssertStatement {
+            expression,
+            message,
+        },
+        locator,
+    ))
+}
+
 /// Parse a policy document string into an [Policy](ast::Policy) object.
 ///
 /// The version parameter asserts that the code conforms to that
@@ -1412,9 +1452,11 @@ pub fn parse_policy_chunk(
 
     for item in chunk {
         match item.as_rule() {
-            Rule::use_definition => policy
-                .ffi_imports
-                .push(parse_use_definition(item, &mut cc)?.to_string()),
+            Rule::use_definition => {
+                let (module, version) = parse_use_definition(item, &mut cc)?;
+                policy.ffi_imports.push(module);
+                policy.ffi_versions.extend(version);
+            }
             Rule::fact_definition => policy.facts.push(parse_fact_definition(item, &mut cc)?),
             Rule::action_definition => policy
                 .actions
@@ -1434,6 +1476,9 @@ pub fn parse_policy_chunk(
             Rule::global_let_statement => policy
                 .global_lets
                 .push(parse_global_let_statement(item, &pratt, &mut cc)?),
+            Rule::static_assert => policy
+                .static_asserts
+                .push(parse_static_assert(item, &pratt, &mut cc)?),
             Rule::EOI => (),
             _ => {
                 return Err(ParseError::new(
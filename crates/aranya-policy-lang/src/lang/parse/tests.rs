@@ -636,6 +636,7 @@ fn parse_policy_test() -> Result<(), ParseError> {
                                     value_fields: Some(vec![]),
                                 }),
                             ),
+                            reason: None,
                         }),
                         673,
                     ),
@@ -657,6 +658,7 @@ fn parse_policy_test() -> Result<(), ParseError> {
                                                     ),)],
                                                 },
                                             ),
+                                            reason: None,
                                         }),
                                         787,
                                     )],
@@ -671,6 +673,7 @@ fn parse_policy_test() -> Result<(), ParseError> {
                                                     arguments: vec![Expression::Optional(None,)],
                                                 },
                                             ),
+                                            reason: None,
                                         }),
                                         887,
                                     )],
@@ -698,6 +701,7 @@ fn parse_policy_test() -> Result<(), ParseError> {
                                             )),
                                             Box::new(Expression::Int(10)),
                                         ),
+                                        reason: None,
                                     }),
                                     1047,
                                 )],
@@ -1448,6 +1452,70 @@ fn parse_keyword_collision() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn parse_static_assert() -> Result<(), ParseError> {
+    let policy_str = r#"
+        let max = 10
+        static_assert(max > 1 && max < 100, "max is out of range\n")
+    "#;
+
+    let policy = parse_policy_str(policy_str, Version::V1)?;
+    let asserts: Vec<_> = policy.static_asserts.into_iter().map(|a| a.inner).collect();
+
+    assert_eq!(
+        asserts,
+        vec![ast::StaticAssertStatement {
+            expression: Expression::And(
+                Box::new(Expression::GreaterThan(
+                    Box::new(Expression::Identifier(String::from("max"))),
+                    Box::new(Expression::Int(1)),
+                )),
+                Box::new(Expression::LessThan(
+                    Box::new(Expression::Identifier(String::from("max"))),
+                    Box::new(Expression::Int(100)),
+                )),
+            ),
+            message: String::from("max is out of range\n"),
+        }]
+    );
+
+    // The message must be a string literal
+    assert!(parse_policy_str(r#"static_assert(true, max)"#, Version::V1).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn parse_check_reason() -> anyhow::Result<()> {
+    let text = r#"
+    action foo(x int) {
+        check x > 0, "x must be \"positive\""
+        check x < 10
+    }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let checks: Vec<_> = policy.actions[0]
+        .statements
+        .iter()
+        .map(|s| match &s.inner {
+            ast::Statement::Check(c) => c.reason.clone(),
+            _ => panic!("not a check"),
+        })
+        .collect();
+    assert_eq!(checks, [Some(String::from("x must be \"positive\"")), None]);
+
+    // The reason must be a string literal
+    let text = r#"
+    action foo(x int) {
+        check x > 0, x
+    }
+    "#;
+    assert!(parse_policy_str(text, Version::V1).is_err());
+
+    Ok(())
+}
+
 #[test]
 fn parse_global_let_statements() -> Result<(), ParseError> {
     let policy_str = r#"
@@ -1573,6 +1641,27 @@ fn test_ffi_use() -> anyhow::Result<()> {
     assert_eq!(policy.ffi_imports.len(), 2);
     assert_eq!(policy.ffi_imports[0], "crypto".to_string());
     assert_eq!(policy.ffi_imports[1], "perspective".to_string());
+    assert!(policy.ffi_versions.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_ffi_use_version() -> anyhow::Result<()> {
+    let text = r#"
+        use crypto >= 2
+        use perspective
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    assert_eq!(policy.ffi_imports, vec!["crypto", "perspective"]);
+    assert_eq!(policy.ffi_versions.len(), 1);
+    assert_eq!(
+        policy.ffi_versions[0].inner,
+        ast::FfiVersionRequirement {
+            module: String::from("crypto"),
+            min_version: 2,
+        }
+    );
     Ok(())
 }
 
@@ -0,0 +1,114 @@
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt, ops::Deref};
+
+use rkyv::{
+    rancor::Fallible,
+    ser::{Allocator, Writer},
+    vec::{ArchivedVec, VecResolver},
+    Place,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An immutable, reference-counted byte buffer, used for [`Value::Bytes`](crate::Value::Bytes).
+///
+/// Cloning a `Bytes` shares the buffer instead of copying it, so byte values can be
+/// duplicated on the stack and passed through `seal` and `open` without allocating. Read
+/// the bytes through [`Deref`], or use [`make_mut()`](Self::make_mut) to modify them.
+///
+/// It is serialized like a `Vec<u8>`.
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Bytes(Arc<[u8]>);
+
+impl Bytes {
+    /// Returns the bytes as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns a mutable reference to the bytes, copying them first if the buffer is shared
+    /// with other clones.
+    pub fn make_mut(&mut self) -> &mut [u8] {
+        if Arc::get_mut(&mut self.0).is_none() {
+            self.0 = Arc::from(&*self.0);
+        }
+        // The buffer is no longer shared, so this always succeeds.
+        Arc::get_mut(&mut self.0).unwrap_or(&mut [])
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(value: &[u8]) -> Self {
+        Self(Arc::from(value))
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(value: Vec<u8>) -> Self {
+        Self(Arc::from(value))
+    }
+}
+
+impl From<Bytes> for Vec<u8> {
+    fn from(value: Bytes) -> Self {
+        value.0.to_vec()
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+// Archived like a `Vec<u8>`, since rkyv cannot share `Arc`s without a sharing serializer.
+impl rkyv::Archive for Bytes {
+    type Archived = ArchivedVec<u8>;
+    type Resolver = VecResolver;
+
+    fn resolve(&self, resolver: Self::Resolver, out: Place<Self::Archived>) {
+        ArchivedVec::resolve_from_len(self.len(), resolver, out);
+    }
+}
+
+impl<S> rkyv::Serialize<S> for Bytes
+where
+    S: Fallible + Allocator + Writer + ?Sized,
+{
+    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+        ArchivedVec::serialize_from_slice(self.as_slice(), serializer)
+    }
+}
+
+impl<D: Fallible + ?Sized> rkyv::Deserialize<Bytes, D> for ArchivedVec<u8> {
+    fn deserialize(&self, _deserializer: &mut D) -> Result<Bytes, D::Error> {
+        Ok(Bytes::from(self.as_slice()))
+    }
+}
@@ -49,7 +49,7 @@ impl<'a> Span<'a> {
 
     /// Return the span as a &str. Assumes the start and end positions
     /// are character-aligned.
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'a str {
         &self.text[self.start..self.end]
     }
 
@@ -96,13 +96,13 @@ impl<'a> Span<'a> {
 )]
 pub struct CodeMap {
     /// The original policy source code
-    text: String,
+    pub(crate) text: String,
     /// All of the text ranges, mapped by locator. The key is the start
     /// of the range and the value is the end of the range.
-    ranges: Vec<(usize, usize)>,
+    pub(crate) ranges: Vec<(usize, usize)>,
     /// A mapping between ranges of instructions and source code
     /// locators. The instuction ranges should be non-overlapping.
-    instruction_mapping: Vec<(usize, usize)>,
+    pub(crate) instruction_mapping: Vec<(usize, usize)>,
 }
 
 impl CodeMap {
@@ -148,6 +148,25 @@ impl CodeMap {
         Ok(())
     }
 
+    /// Update the instruction mappings after instructions have been
+    /// moved. `f` gives the new position of each instruction, and must
+    /// never decrease. If several mappings end up at the same position,
+    /// the last one is kept.
+    pub fn remap_instructions<F>(&mut self, mut f: F)
+    where
+        F: FnMut(usize) -> usize,
+    {
+        let mut mapping: Vec<(usize, usize)> = Vec::with_capacity(self.instruction_mapping.len());
+        for (instruction, locator) in self.instruction_mapping.drain(..) {
+            let instruction = f(instruction);
+            if mapping.last().is_some_and(|(last, _)| *last == instruction) {
+                mapping.pop();
+            }
+            mapping.push((instruction, locator));
+        }
+        self.instruction_mapping = mapping;
+    }
+
     /// Retrieve the [Span] from the given locator
     pub fn span_from_locator(&self, locator: usize) -> Result<Span<'_>, RangeError> {
         match self.ranges.binary_search_by(|(s, _)| s.cmp(&locator)) {
@@ -0,0 +1,847 @@
+//! A size-optimized binary encoding for [`Module`]s.
+//!
+//! Integers are encoded as LEB128 varints (signed integers are zigzag-encoded first), and
+//! every identifier is stored once in a string table and referred to by its index. The
+//! layout is:
+//!
+//! - the magic bytes `APMc`, followed by a module version byte
+//! - the string table: a count, then each string as a length and UTF-8 bytes
+//! - the module data, with each instruction as a one-byte opcode followed by its operands
+
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+
+use aranya_policy_ast::{FactDefinition, FieldDefinition, VType};
+
+use crate::{
+    CodeMap, ExitReason, Fact, FactKey, FactValue, Fingerprint, Float, HashableValue, Id,
+    Identifier, Instruction, Label, LabelType, Meta, Module, ModuleData, ModuleV0, ModuleV1,
+    Struct, Target, Timestamp, Value,
+};
+
+const MAGIC: &[u8; 4] = b"APMc";
+
+/// Errors that can occur while decoding a compact module.
+#[derive(Debug, Eq, PartialEq)]
+pub enum CompactError {
+    /// The data does not begin with the compact module magic bytes
+    BadMagic,
+    /// The module version is not supported
+    UnsupportedVersion(u8),
+    /// The data ended unexpectedly
+    Truncated,
+    /// A tag byte does not identify a known variant of the named type
+    InvalidTag(&'static str, u8),
+    /// An integer does not fit in its type
+    Overflow,
+    /// A string is not valid UTF-8, or its index is not in the string table
+    InvalidString,
+    /// There is data following the module
+    TrailingData,
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a compact module"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported module version {v}"),
+            Self::Truncated => write!(f, "unexpected end of data"),
+            Self::InvalidTag(ty, tag) => write!(f, "invalid {ty} tag {tag}"),
+            Self::Overflow => write!(f, "integer overflow"),
+            Self::InvalidString => write!(f, "invalid string"),
+            Self::TrailingData => write!(f, "trailing data after module"),
+        }
+    }
+}
+
+impl core::error::Error for CompactError {}
+
+impl Module {
+    /// Encodes the module in the compact format, which is smaller than the serde and rkyv
+    /// encodings and suits devices with little storage.
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut e = Encoder::default();
+        let version = match &self.data {
+            ModuleData::V0(m) => {
+                e.module_v0(m);
+                0
+            }
+            ModuleData::V1(m) => {
+                e.module_v1(m);
+                1
+            }
+        };
+
+        let mut out = Vec::with_capacity(e.body.len());
+        out.extend_from_slice(MAGIC);
+        out.push(version);
+        let mut table = Encoder::default();
+        table.usize(e.strings.len());
+        for s in &e.strings {
+            table.bytes(s.as_bytes());
+        }
+        out.extend(table.body);
+        out.extend(e.body);
+        out
+    }
+
+    /// Decodes a module encoded with [`Module::to_compact`].
+    pub fn from_compact(data: &[u8]) -> Result<Module, CompactError> {
+        let data = data.strip_prefix(MAGIC).ok_or(CompactError::BadMagic)?;
+        let mut d = Decoder {
+            data,
+            strings: Vec::new(),
+        };
+        let version = d.byte()?;
+        for _ in 0..d.usize()? {
+            let len = d.usize()?;
+            let s = core::str::from_utf8(d.take(len)?).map_err(|_| CompactError::InvalidString)?;
+            d.strings.push(s);
+        }
+        let data = match version {
+            0 => ModuleData::V0(d.module_v0()?),
+            1 => ModuleData::V1(d.module_v1()?),
+            v => return Err(CompactError::UnsupportedVersion(v)),
+        };
+        if !d.data.is_empty() {
+            return Err(CompactError::TrailingData);
+        }
+        Ok(Module { data })
+    }
+}
+
+/// Encodes the fields which every module version has, in order.
+macro_rules! encode_module {
+    ($e:ident, $m:ident) => {
+        $e.seq($m.progmem.iter(), Encoder::instruction);
+        $e.seq($m.labels.iter(), |e, (label, addr)| {
+            e.label(label);
+            e.usize(*addr);
+        });
+        $e.seq($m.action_defs.iter(), |e, (name, args)| {
+            e.str(name);
+            e.fields(args);
+        });
+        $e.seq($m.command_defs.iter(), |e, (name, fields)| {
+            e.str(name);
+            e.seq(fields.iter(), |e, (field, vtype)| {
+                e.str(field);
+                e.vtype(vtype);
+            });
+        });
+        $e.seq($m.fact_defs.iter(), |e, (name, def)| {
+            e.str(name);
+            e.fact_def(def);
+        });
+        $e.seq($m.struct_defs.iter(), |e, (name, fields)| {
+            e.str(name);
+            e.fields(fields);
+        });
+        $e.seq($m.command_attributes.iter(), |e, (name, attrs)| {
+            e.str(name);
+            e.seq(attrs.iter(), |e, (attr, value)| {
+                e.str(attr);
+                e.value(value);
+            });
+        });
+        match &$m.codemap {
+            Some(codemap) => {
+                $e.bool(true);
+                $e.codemap(codemap);
+            }
+            None => $e.bool(false),
+        }
+        $e.seq($m.globals.iter(), |e, (name, value)| {
+            e.str(name);
+            e.value(value);
+        });
+    };
+}
+
+#[derive(Default)]
+struct Encoder<'a> {
+    body: Vec<u8>,
+    strings: Vec<&'a str>,
+    index: BTreeMap<&'a str, usize>,
+}
+
+impl<'a> Encoder<'a> {
+    fn byte(&mut self, b: u8) {
+        self.body.push(b);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.byte(u8::from(b));
+    }
+
+    fn uint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.byte((n & 0x7f) as u8 | 0x80);
+            n = n.wrapping_shr(7);
+        }
+        self.byte(n as u8);
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.uint(n as u64);
+    }
+
+    fn int(&mut self, n: i64) {
+        let sign = if n < 0 { u64::MAX } else { 0 };
+        self.uint(u64::from_ne_bytes(n.to_ne_bytes()).wrapping_shl(1) ^ sign);
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.usize(b.len());
+        self.body.extend_from_slice(b);
+    }
+
+    /// Writes the index of `s` in the string table, adding it if needed.
+    fn str(&mut self, s: &'a str) {
+        let next = self.strings.len();
+        let i = *self.index.entry(s).or_insert(next);
+        if i == next {
+            self.strings.push(s);
+        }
+        self.usize(i);
+    }
+
+    fn seq<I, F>(&mut self, items: I, mut f: F)
+    where
+        I: IntoIterator,
+        I::IntoIter: ExactSizeIterator,
+        F: FnMut(&mut Self, I::Item),
+    {
+        let items = items.into_iter();
+        self.usize(items.len());
+        for item in items {
+            f(self, item);
+        }
+    }
+
+    fn module_v0(&mut self, m: &'a ModuleV0) {
+        encode_module!(self, m);
+    }
+
+    /// Version 1 modules are encoded as version 0 ones, followed by the effect
+    /// definitions and the fingerprint.
+    fn module_v1(&mut self, m: &'a ModuleV1) {
+        encode_module!(self, m);
+        self.seq(m.effect_defs.iter(), |e, (name, fields)| {
+            e.str(name);
+            e.fields(fields);
+        });
+        match &m.fingerprint {
+            Some(fingerprint) => {
+                self.bool(true);
+                self.body.extend_from_slice(fingerprint.as_bytes());
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn instruction(&mut self, i: &'a Instruction) {
+        self.byte(opcode(i));
+        match i {
+            Instruction::Const(v) => self.value(v),
+            Instruction::Def(s)
+            | Instruction::Get(s)
+            | Instruction::FactNew(s)
+            | Instruction::FactKeySet(s)
+            | Instruction::FactValueSet(s)
+            | Instruction::StructNew(s)
+            | Instruction::StructSet(s)
+            | Instruction::StructGet(s)
+            | Instruction::QueryNext(s)
+            | Instruction::Extension(s) => self.str(s),
+            Instruction::Swap(n) | Instruction::Dup(n) => self.usize(*n),
+            Instruction::Jump(t) | Instruction::Branch(t) | Instruction::Call(t) => self.target(t),
+            Instruction::JumpTable(base, targets) => {
+                self.int(*base);
+                self.seq(targets.iter(), Self::target);
+            }
+            Instruction::ExtCall(module, procedure) => {
+                self.usize(*module);
+                self.usize(*procedure);
+            }
+            Instruction::Exit(reason) => self.byte(match reason {
+                ExitReason::Normal => 0,
+                ExitReason::Check => 1,
+                ExitReason::Panic => 2,
+            }),
+            Instruction::FactCount(limit) => self.int(*limit),
+            Instruction::Meta(meta) => self.meta(meta),
+            _ => {}
+        }
+    }
+
+    fn target(&mut self, t: &'a Target) {
+        match t {
+            Target::Unresolved(label) => {
+                self.byte(0);
+                self.label(label);
+            }
+            Target::Resolved(addr) => {
+                self.byte(1);
+                self.usize(*addr);
+            }
+        }
+    }
+
+    fn label(&mut self, label: &'a Label) {
+        self.str(&label.name);
+        self.byte(match label.ltype {
+            LabelType::Action => 0,
+            LabelType::CommandPolicy => 1,
+            LabelType::CommandRecall => 2,
+            LabelType::CommandSeal => 3,
+            LabelType::CommandOpen => 4,
+            LabelType::Temporary => 5,
+            LabelType::Function => 6,
+        });
+    }
+
+    fn meta(&mut self, meta: &'a Meta) {
+        match meta {
+            Meta::Let(s) => {
+                self.byte(0);
+                self.str(s);
+            }
+            Meta::Get(s) => {
+                self.byte(1);
+                self.str(s);
+            }
+            Meta::Finish(b) => {
+                self.byte(2);
+                self.bool(*b);
+            }
+            Meta::FFI(module, procedure) => {
+                self.byte(3);
+                self.str(module);
+                self.str(procedure);
+            }
+            Meta::Check(reason) => {
+                self.byte(4);
+                self.str(reason);
+            }
+        }
+    }
+
+    fn value(&mut self, v: &'a Value) {
+        match v {
+            Value::Int(n) => {
+                self.byte(0);
+                self.int(*n);
+            }
+            Value::Bool(b) => {
+                self.byte(1);
+                self.bool(*b);
+            }
+            Value::String(s) => {
+                self.byte(2);
+                self.str(s);
+            }
+            Value::Bytes(b) => {
+                self.byte(3);
+                self.bytes(b);
+            }
+            Value::Struct(s) => {
+                self.byte(4);
+                self.str(&s.name);
+                self.seq(s.fields.iter(), |e, (name, value)| {
+                    e.str(name);
+                    e.value(value);
+                });
+            }
+            Value::Fact(f) => {
+                self.byte(5);
+                self.str(&f.name);
+                self.seq(f.keys.iter(), |e, key| {
+                    e.str(&key.identifier);
+                    e.hashable_value(&key.value);
+                });
+                self.seq(f.values.iter(), |e, value| {
+                    e.str(&value.identifier);
+                    e.value(&value.value);
+                });
+            }
+            Value::Id(id) => {
+                self.byte(6);
+                self.body.extend_from_slice(id.as_ref());
+            }
+            Value::Enum(name, variant) => {
+                self.byte(7);
+                self.str(name);
+                self.str(variant);
+            }
+            Value::None => self.byte(8),
+            Value::Float(x) => {
+                self.byte(9);
+                self.body.extend_from_slice(&x.0.to_bits().to_le_bytes());
+            }
+            Value::Timestamp(t) => {
+                self.byte(10);
+                self.int(t.0);
+            }
+            Value::Map(m) => {
+                self.byte(11);
+                self.seq(m.iter(), |e, (key, value)| {
+                    e.str(key);
+                    e.value(value);
+                });
+            }
+        }
+    }
+
+    fn hashable_value(&mut self, v: &'a HashableValue) {
+        match v {
+            HashableValue::Int(n) => {
+                self.byte(0);
+                self.int(*n);
+            }
+            HashableValue::Bool(b) => {
+                self.byte(1);
+                self.bool(*b);
+            }
+            HashableValue::String(s) => {
+                self.byte(2);
+                self.str(s);
+            }
+            HashableValue::Id(id) => {
+                self.byte(3);
+                self.body.extend_from_slice(id.as_ref());
+            }
+        }
+    }
+
+    fn vtype(&mut self, vtype: &'a VType) {
+        match vtype {
+            VType::String => self.byte(0),
+            VType::Bytes => self.byte(1),
+            VType::Int => self.byte(2),
+            VType::Bool => self.byte(3),
+            VType::Id => self.byte(4),
+            VType::Struct(name) => {
+                self.byte(5);
+                self.str(name);
+            }
+            VType::Enum(name) => {
+                self.byte(6);
+                self.str(name);
+            }
+            VType::Optional(inner) => {
+                self.byte(7);
+                self.vtype(inner);
+            }
+        }
+    }
+
+    fn fields(&mut self, fields: &'a [FieldDefinition]) {
+        self.seq(fields.iter(), |e, field| {
+            e.str(&field.identifier);
+            e.vtype(&field.field_type);
+        });
+    }
+
+    fn fact_def(&mut self, def: &'a FactDefinition) {
+        self.bool(def.immutable);
+        self.str(&def.identifier);
+        self.fields(&def.key);
+        self.fields(&def.value);
+    }
+
+    fn codemap(&mut self, codemap: &'a CodeMap) {
+        // The source text is not interned, since it would never be shared.
+        self.bytes(codemap.text.as_bytes());
+        self.seq(codemap.ranges.iter(), |e, (start, end)| {
+            e.usize(*start);
+            e.usize(*end);
+        });
+        self.seq(codemap.instruction_mapping.iter(), |e, (instr, locator)| {
+            e.usize(*instr);
+            e.usize(*locator);
+        });
+    }
+}
+
+/// The one-byte opcode of an instruction in the compact encoding.
+fn opcode(i: &Instruction) -> u8 {
+    match i {
+        Instruction::Const(_) => 0,
+        Instruction::Def(_) => 1,
+        Instruction::Get(_) => 2,
+        Instruction::Swap(_) => 3,
+        Instruction::Dup(_) => 4,
+        Instruction::Pop => 5,
+        Instruction::Block => 6,
+        Instruction::End => 7,
+        Instruction::Jump(_) => 8,
+        Instruction::Branch(_) => 9,
+        Instruction::JumpTable(_, _) => 10,
+        Instruction::Next => 11,
+        Instruction::Last => 12,
+        Instruction::Call(_) => 13,
+        Instruction::ExtCall(_, _) => 14,
+        Instruction::Return => 15,
+        Instruction::Exit(_) => 16,
+        Instruction::Add => 17,
+        Instruction::Sub => 18,
+        Instruction::Not => 19,
+        Instruction::And => 20,
+        Instruction::Or => 21,
+        Instruction::Gt => 22,
+        Instruction::Lt => 23,
+        Instruction::Eq => 24,
+        Instruction::FactNew(_) => 25,
+        Instruction::FactKeySet(_) => 26,
+        Instruction::FactValueSet(_) => 27,
+        Instruction::StructNew(_) => 28,
+        Instruction::StructSet(_) => 29,
+        Instruction::StructGet(_) => 30,
+        Instruction::Publish => 31,
+        Instruction::Create => 32,
+        Instruction::Delete => 33,
+        Instruction::Update => 34,
+        Instruction::Emit => 35,
+        Instruction::Query => 36,
+        Instruction::FactCount(_) => 37,
+        Instruction::QueryStart => 38,
+        Instruction::QueryNext(_) => 39,
+        Instruction::Serialize => 40,
+        Instruction::Deserialize => 41,
+        Instruction::Meta(_) => 42,
+        Instruction::Extension(_) => 43,
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    strings: Vec<&'a str>,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, CompactError> {
+        let (&b, rest) = self.data.split_first().ok_or(CompactError::Truncated)?;
+        self.data = rest;
+        Ok(b)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CompactError> {
+        if n > self.data.len() {
+            return Err(CompactError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn bool(&mut self) -> Result<bool, CompactError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(CompactError::InvalidTag("bool", b)),
+        }
+    }
+
+    fn uint(&mut self) -> Result<u64, CompactError> {
+        let mut n: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let b = self.byte()?;
+            let part = u64::from(b & 0x7f);
+            // The tenth byte may only hold the highest bit.
+            if shift > 63 || (shift == 63 && part > 1) {
+                return Err(CompactError::Overflow);
+            }
+            n |= part.wrapping_shl(shift);
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift = shift.checked_add(7).ok_or(CompactError::Overflow)?;
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, CompactError> {
+        usize::try_from(self.uint()?).map_err(|_| CompactError::Overflow)
+    }
+
+    fn int(&mut self) -> Result<i64, CompactError> {
+        let n = self.uint()?;
+        let sign = if n & 1 == 1 { u64::MAX } else { 0 };
+        Ok(i64::from_ne_bytes((n.wrapping_shr(1) ^ sign).to_ne_bytes()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CompactError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<String, CompactError> {
+        let i = self.usize()?;
+        self.strings
+            .get(i)
+            .map(|s| s.to_string())
+            .ok_or(CompactError::InvalidString)
+    }
+
+    fn ident(&mut self) -> Result<Identifier, CompactError> {
+        self.str().map(Identifier::from)
+    }
+
+    fn id(&mut self) -> Result<Id, CompactError> {
+        let mut id = [0u8; 64];
+        id.copy_from_slice(self.take(64)?);
+        Ok(Id::from(id))
+    }
+
+    fn seq<T, F>(&mut self, mut f: F) -> Result<Vec<T>, CompactError>
+    where
+        F: FnMut(&mut Self) -> Result<T, CompactError>,
+    {
+        // Don't trust the count to preallocate, since it hasn't been checked against the
+        // length of the data.
+        let mut items = Vec::new();
+        for _ in 0..self.usize()? {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn map<K: Ord, V, F>(&mut self, f: F) -> Result<BTreeMap<K, V>, CompactError>
+    where
+        F: FnMut(&mut Self) -> Result<(K, V), CompactError>,
+    {
+        Ok(self.seq(f)?.into_iter().collect())
+    }
+
+    fn module_v0(&mut self) -> Result<ModuleV0, CompactError> {
+        Ok(ModuleV0 {
+            progmem: self.seq(Self::instruction)?.into_boxed_slice(),
+            labels: self.map(|d| Ok((d.label()?, d.usize()?)))?,
+            action_defs: self.map(|d| Ok((d.str()?, d.fields()?)))?,
+            command_defs: self.map(|d| {
+                let name = d.str()?;
+                let fields = d.map(|d| Ok((d.str()?, d.vtype()?)))?;
+                Ok((name, fields))
+            })?,
+            fact_defs: self.map(|d| Ok((d.str()?, d.fact_def()?)))?,
+            struct_defs: self.map(|d| Ok((d.str()?, d.fields()?)))?,
+            command_attributes: self.map(|d| {
+                let name = d.str()?;
+                let attrs = d.map(|d| Ok((d.str()?, d.value()?)))?;
+                Ok((name, attrs))
+            })?,
+            codemap: if self.bool()? {
+                Some(self.codemap()?)
+            } else {
+                None
+            },
+            globals: self.map(|d| Ok((d.str()?, d.value()?)))?,
+        })
+    }
+
+    fn module_v1(&mut self) -> Result<ModuleV1, CompactError> {
+        let m = self.module_v0()?;
+        let effect_defs = self.map(|d| Ok((d.str()?, d.fields()?)))?;
+        let fingerprint = if self.bool()? {
+            let mut fingerprint = [0u8; 32];
+            fingerprint.copy_from_slice(self.take(32)?);
+            Some(Fingerprint::from_bytes(fingerprint))
+        } else {
+            None
+        };
+        Ok(ModuleV1 {
+            effect_defs,
+            fingerprint,
+            ..m.into()
+        })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, CompactError> {
+        Ok(match self.byte()? {
+            0 => Instruction::Const(self.value()?),
+            1 => Instruction::Def(self.ident()?),
+            2 => Instruction::Get(self.ident()?),
+            3 => Instruction::Swap(self.usize()?),
+            4 => Instruction::Dup(self.usize()?),
+            5 => Instruction::Pop,
+            6 => Instruction::Block,
+            7 => Instruction::End,
+            8 => Instruction::Jump(self.target()?),
+            9 => Instruction::Branch(self.target()?),
+            10 => Instruction::JumpTable(self.int()?, self.seq(Self::target)?),
+            11 => Instruction::Next,
+            12 => Instruction::Last,
+            13 => Instruction::Call(self.target()?),
+            14 => Instruction::ExtCall(self.usize()?, self.usize()?),
+            15 => Instruction::Return,
+            16 => Instruction::Exit(match self.byte()? {
+                0 => ExitReason::Normal,
+                1 => ExitReason::Check,
+                2 => ExitReason::Panic,
+                b => return Err(CompactError::InvalidTag("exit reason", b)),
+            }),
+            17 => Instruction::Add,
+            18 => Instruction::Sub,
+            19 => Instruction::Not,
+            20 => Instruction::And,
+            21 => Instruction::Or,
+            22 => Instruction::Gt,
+            23 => Instruction::Lt,
+            24 => Instruction::Eq,
+            25 => Instruction::FactNew(self.ident()?),
+            26 => Instruction::FactKeySet(self.ident()?),
+            27 => Instruction::FactValueSet(self.ident()?),
+            28 => Instruction::StructNew(self.ident()?),
+            29 => Instruction::StructSet(self.ident()?),
+            30 => Instruction::StructGet(self.ident()?),
+            31 => Instruction::Publish,
+            32 => Instruction::Create,
+            33 => Instruction::Delete,
+            34 => Instruction::Update,
+            35 => Instruction::Emit,
+            36 => Instruction::Query,
+            37 => Instruction::FactCount(self.int()?),
+            38 => Instruction::QueryStart,
+            39 => Instruction::QueryNext(self.ident()?),
+            40 => Instruction::Serialize,
+            41 => Instruction::Deserialize,
+            42 => Instruction::Meta(self.meta()?),
+            43 => Instruction::Extension(self.ident()?),
+            b => return Err(CompactError::InvalidTag("instruction", b)),
+        })
+    }
+
+    fn target(&mut self) -> Result<Target, CompactError> {
+        match self.byte()? {
+            0 => Ok(Target::Unresolved(self.label()?)),
+            1 => Ok(Target::Resolved(self.usize()?)),
+            b => Err(CompactError::InvalidTag("target", b)),
+        }
+    }
+
+    fn label(&mut self) -> Result<Label, CompactError> {
+        let name = self.str()?;
+        let ltype = match self.byte()? {
+            0 => LabelType::Action,
+            1 => LabelType::CommandPolicy,
+            2 => LabelType::CommandRecall,
+            3 => LabelType::CommandSeal,
+            4 => LabelType::CommandOpen,
+            5 => LabelType::Temporary,
+            6 => LabelType::Function,
+            b => return Err(CompactError::InvalidTag("label type", b)),
+        };
+        Ok(Label { name, ltype })
+    }
+
+    fn meta(&mut self) -> Result<Meta, CompactError> {
+        match self.byte()? {
+            0 => Ok(Meta::Let(self.str()?)),
+            1 => Ok(Meta::Get(self.str()?)),
+            2 => Ok(Meta::Finish(self.bool()?)),
+            3 => Ok(Meta::FFI(self.str()?, self.str()?)),
+            4 => Ok(Meta::Check(self.str()?)),
+            b => Err(CompactError::InvalidTag("meta", b)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, CompactError> {
+        Ok(match self.byte()? {
+            0 => Value::Int(self.int()?),
+            1 => Value::Bool(self.bool()?),
+            2 => Value::String(self.str()?),
+            3 => Value::Bytes(self.bytes()?.into()),
+            4 => Value::Struct(Struct {
+                name: self.str()?,
+                fields: self.map(|d| Ok((d.str()?, d.value()?)))?,
+            }),
+            5 => Value::Fact(Fact {
+                name: self.str()?,
+                keys: self.seq(|d| {
+                    Ok(FactKey {
+                        identifier: d.str()?,
+                        value: d.hashable_value()?,
+                    })
+                })?,
+                values: self.seq(|d| {
+                    Ok(FactValue {
+                        identifier: d.str()?,
+                        value: d.value()?,
+                    })
+                })?,
+            }),
+            6 => Value::Id(self.id()?),
+            7 => Value::Enum(self.str()?, self.str()?),
+            8 => Value::None,
+            9 => {
+                let mut bits = [0u8; 8];
+                bits.copy_from_slice(self.take(8)?);
+                Value::Float(Float(f64::from_bits(u64::from_le_bytes(bits))))
+            }
+            10 => Value::Timestamp(Timestamp(self.int()?)),
+            11 => Value::Map(self.map(|d| Ok((d.str()?, d.value()?)))?),
+            b => return Err(CompactError::InvalidTag("value", b)),
+        })
+    }
+
+    fn hashable_value(&mut self) -> Result<HashableValue, CompactError> {
+        Ok(match self.byte()? {
+            0 => HashableValue::Int(self.int()?),
+            1 => HashableValue::Bool(self.bool()?),
+            2 => HashableValue::String(self.str()?),
+            3 => HashableValue::Id(self.id()?),
+            b => return Err(CompactError::InvalidTag("hashable value", b)),
+        })
+    }
+
+    fn vtype(&mut self) -> Result<VType, CompactError> {
+        Ok(match self.byte()? {
+            0 => VType::String,
+            1 => VType::Bytes,
+            2 => VType::Int,
+            3 => VType::Bool,
+            4 => VType::Id,
+            5 => VType::Struct(self.str()?),
+            6 => VType::Enum(self.str()?),
+            7 => VType::Optional(Box::new(self.vtype()?)),
+            b => return Err(CompactError::InvalidTag("type", b)),
+        })
+    }
+
+    fn fields(&mut self) -> Result<Vec<FieldDefinition>, CompactError> {
+        self.seq(|d| {
+            Ok(FieldDefinition {
+                identifier: d.str()?,
+                field_type: d.vtype()?,
+            })
+        })
+    }
+
+    fn fact_def(&mut self) -> Result<FactDefinition, CompactError> {
+        Ok(FactDefinition {
+            immutable: self.bool()?,
+            identifier: self.str()?,
+            key: self.fields()?,
+            value: self.fields()?,
+        })
+    }
+
+    fn codemap(&mut self) -> Result<CodeMap, CompactError> {
+        let text = core::str::from_utf8(self.bytes()?)
+            .map_err(|_| CompactError::InvalidString)?
+            .to_string();
+        Ok(CodeMap {
+            text,
+            ranges: self.seq(|d| Ok((d.usize()?, d.usize()?)))?,
+            instruction_mapping: self.seq(|d| Ok((d.usize()?, d.usize()?)))?,
+        })
+    }
+}
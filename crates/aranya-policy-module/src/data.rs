@@ -1,7 +1,10 @@
 extern crate alloc;
 
 use alloc::{borrow::ToOwned, collections::BTreeMap, format, string::String, vec, vec::Vec};
-use core::fmt::{self, Display};
+use core::{
+    cmp::Ordering,
+    fmt::{self, Display},
+};
 
 pub use aranya_crypto::Id;
 use aranya_crypto::{EncryptionKeyId, UserId};
@@ -82,6 +85,7 @@ impl_typed!(&str => String);
 
 impl_typed!(Vec<u8> => Bytes);
 impl_typed!(&[u8] => Bytes);
+impl_typed!(Bytes => Bytes);
 
 impl_typed!(isize => Int);
 impl_typed!(i64 => Int);
@@ -139,7 +143,7 @@ pub enum Value {
     /// String (UTF-8)
     String(String),
     /// Bytes
-    Bytes(Vec<u8>),
+    Bytes(Bytes),
     /// Struct
     Struct(#[rkyv(omit_bounds)] Struct),
     /// Fact
@@ -150,6 +154,12 @@ pub enum Value {
     Enum(String, String),
     /// Empty optional value
     None,
+    /// Floating point number (64-bit)
+    Float(Float),
+    /// A point in time
+    Timestamp(Timestamp),
+    /// Map from strings to values
+    Map(#[rkyv(omit_bounds)] BTreeMap<String, Value>),
 }
 
 /// Trait for converting from a [`Value`], similar to [`TryFrom<Value>`].
@@ -214,6 +224,9 @@ impl Value {
             Value::Id(_) => String::from("Id"),
             Value::Enum(name, _) => format!("Enum {}", name),
             Value::None => String::from("None"),
+            Value::Float(_) => String::from("Float"),
+            Value::Timestamp(_) => String::from("Timestamp"),
+            Value::Map(_) => String::from("Map"),
         }
     }
 
@@ -235,6 +248,47 @@ impl Value {
             (Some(left), right) => left == *right,
         }
     }
+
+    /// Returns an approximation of the heap memory owned by the value, in bytes. Shared
+    /// [`Bytes`] buffers are counted in full, and allocator overhead is ignored.
+    pub fn heap_size(&self) -> usize {
+        fn entries<'a>(entries: impl Iterator<Item = (&'a String, &'a Value)>) -> usize {
+            entries.fold(0, |total: usize, (k, v)| {
+                total
+                    .saturating_add(k.len())
+                    .saturating_add(core::mem::size_of::<Value>())
+                    .saturating_add(v.heap_size())
+            })
+        }
+
+        match self {
+            Value::String(s) => s.len(),
+            Value::Bytes(b) => b.len(),
+            Value::Enum(name, value) => name.len().saturating_add(value.len()),
+            Value::Struct(s) => s.name.len().saturating_add(entries(s.fields.iter())),
+            Value::Map(m) => entries(m.iter()),
+            Value::Fact(f) => {
+                let keys = f.keys.iter().fold(0, |total: usize, k| {
+                    let value = match &k.value {
+                        HashableValue::String(s) => s.len(),
+                        _ => 0,
+                    };
+                    total
+                        .saturating_add(k.identifier.len())
+                        .saturating_add(core::mem::size_of::<FactKey>())
+                        .saturating_add(value)
+                });
+                let values = entries(f.values.iter().map(|v| (&v.identifier, &v.value)));
+                f.name.len().saturating_add(keys).saturating_add(values)
+            }
+            Value::Int(_)
+            | Value::Bool(_)
+            | Value::Id(_)
+            | Value::None
+            | Value::Float(_)
+            | Value::Timestamp(_) => 0,
+        }
+    }
 }
 
 impl<T: Into<Value>> From<Option<T>> for Value {
@@ -269,12 +323,18 @@ impl From<String> for Value {
 
 impl From<&[u8]> for Value {
     fn from(value: &[u8]) -> Self {
-        Value::Bytes(value.to_owned())
+        Value::Bytes(Bytes::from(value))
     }
 }
 
 impl From<Vec<u8>> for Value {
     fn from(value: Vec<u8>) -> Self {
+        Value::Bytes(Bytes::from(value))
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(value: Bytes) -> Self {
         Value::Bytes(value)
     }
 }
@@ -309,6 +369,30 @@ impl From<EncryptionKeyId> for Value {
     }
 }
 
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(Float(value))
+    }
+}
+
+impl From<Float> for Value {
+    fn from(value: Float) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<Timestamp> for Value {
+    fn from(value: Timestamp) -> Self {
+        Value::Timestamp(value)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(value: BTreeMap<String, Value>) -> Self {
+        Value::Map(value)
+    }
+}
+
 impl TryFrom<Value> for i64 {
     type Error = ValueConversionError;
 
@@ -359,7 +443,7 @@ impl TryFrom<Value> for Vec<u8> {
 
     fn try_from(value: Value) -> Result<Self, Self::Error> {
         if let Value::Bytes(v) = value {
-            return Ok(v);
+            return Ok(v.into());
         }
         Err(ValueConversionError::invalid_type(
             "Bytes",
@@ -369,6 +453,36 @@ impl TryFrom<Value> for Vec<u8> {
     }
 }
 
+impl TryFrom<Value> for Bytes {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Bytes(v) = value {
+            return Ok(v);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Bytes",
+            value.type_name(),
+            "Value -> Bytes",
+        ))
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for &'a [u8] {
+    type Error = ValueConversionError;
+
+    fn try_from(value: &'a Value) -> Result<Self, Self::Error> {
+        if let Value::Bytes(v) = value {
+            return Ok(v);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Bytes",
+            value.type_name(),
+            "&Value -> &[u8]",
+        ))
+    }
+}
+
 impl TryFrom<Value> for Struct {
     type Error = ValueConversionError;
 
@@ -447,6 +561,66 @@ impl TryFrom<Value> for EncryptionKeyId {
     }
 }
 
+impl TryFrom<Value> for f64 {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Float(f) = value {
+            return Ok(f.0);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Float",
+            value.type_name(),
+            "Value -> f64",
+        ))
+    }
+}
+
+impl TryFrom<Value> for Float {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Float(f) = value {
+            return Ok(f);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Float",
+            value.type_name(),
+            "Value -> Float",
+        ))
+    }
+}
+
+impl TryFrom<Value> for Timestamp {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Timestamp(t) = value {
+            return Ok(t);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Timestamp",
+            value.type_name(),
+            "Value -> Timestamp",
+        ))
+    }
+}
+
+impl TryFrom<Value> for BTreeMap<String, Value> {
+    type Error = ValueConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if let Value::Map(m) = value {
+            return Ok(m);
+        }
+        Err(ValueConversionError::invalid_type(
+            "Map",
+            value.type_name(),
+            "Value -> BTreeMap",
+        ))
+    }
+}
+
 impl TryAsMut<i64> for Value {
     type Error = ValueConversionError;
     fn try_as_mut(&mut self) -> Result<&mut i64, Self::Error> {
@@ -493,7 +667,7 @@ impl TryAsMut<[u8]> for Value {
     type Error = ValueConversionError;
     fn try_as_mut(&mut self) -> Result<&mut [u8], Self::Error> {
         if let Self::Bytes(v) = self {
-            return Ok(v);
+            return Ok(v.make_mut());
         }
         Err(ValueConversionError::invalid_type(
             "Vec<u8>",
@@ -539,7 +713,7 @@ impl Display for Value {
             Value::String(s) => write!(f, "\"{}\"", s),
             Value::Bytes(v) => {
                 write!(f, "b:")?;
-                for b in v {
+                for b in v.iter() {
                     write!(f, "{:02X}", b)?;
                 }
                 Ok(())
@@ -549,10 +723,86 @@ impl Display for Value {
             Value::Id(id) => id.fmt(f),
             Value::Enum(name, value) => write!(f, "{name}::{value}"),
             Value::None => write!(f, "None"),
+            Value::Float(x) => x.fmt(f),
+            Value::Timestamp(t) => t.fmt(f),
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                let mut i = false;
+                for (k, v) in m {
+                    if i {
+                        write!(f, ", ")?;
+                    }
+                    i = true;
+                    write!(f, "\"{}\": {}", k, v)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
+/// A 64-bit floating point number.
+///
+/// Unlike `f64`, floats are totally ordered by [`f64::total_cmp`], so a NaN is equal to an
+/// identical NaN and `-0.0` is less than `0.0`. This keeps equality in the VM reflexive.
+#[derive(
+    Copy, Clone, Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize,
+)]
+#[serde(transparent)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Always show a decimal point, so floats can be told apart from ints.
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// A point in time, as a signed number of seconds since the Unix epoch.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(pub i64);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.0)
+    }
+}
+
 /// The subset of Values that can be hashed. Only these types of values
 /// can be used in the key portion of a Fact.
 #[derive(
@@ -662,6 +912,34 @@ impl FactKey {
             value,
         }
     }
+
+    /// Encodes the key so that byte-wise comparison of the encodings orders keys with the
+    /// same identifier and value type by value. Query results are returned in the order of
+    /// these encodings.
+    ///
+    /// The encoding is the big-endian identifier length, the identifier, a one-byte type tag
+    /// (0 for int, 1 for bool, 2 for string, and 3 for ID), and then the value. Ints are
+    /// big-endian with the sign bit flipped.
+    pub fn to_sortable_bytes(&self) -> Vec<u8> {
+        let int_bytes;
+        let (tag, value_bytes): (u8, &[u8]) = match &self.value {
+            HashableValue::Int(int) => {
+                int_bytes = (int ^ i64::MIN).to_be_bytes();
+                (0, &int_bytes[..])
+            }
+            HashableValue::Bool(b) => (1, if *b { &[1][..] } else { &[0][..] }),
+            HashableValue::String(s) => (2, s.as_bytes()),
+            HashableValue::Id(id) => (3, id.as_bytes()),
+        };
+        let identifier_len = (self.identifier.len() as u64).to_be_bytes();
+        [
+            identifier_len.as_slice(),
+            self.identifier.as_bytes(),
+            &[tag],
+            value_bytes,
+        ]
+        .concat()
+    }
 }
 
 impl Display for FactKey {
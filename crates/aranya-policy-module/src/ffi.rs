@@ -251,6 +251,8 @@ macro_rules! __type {
 pub struct ModuleSchema<'a> {
     /// module name
     pub name: &'a str,
+    /// module version, which policies can require with `use name >= version`
+    pub version: u32,
     /// list of functions provided by the module
     pub functions: &'a [Func<'a>],
     /// list of structs defined by the module
@@ -0,0 +1,89 @@
+use core::fmt;
+
+use aranya_crypto::{hash::Hash as _, rust::Sha256};
+use aranya_policy_ast as ast;
+use serde::{Deserialize, Serialize};
+
+/// A canonical hash of a parsed policy, which peers can compare to check that they are
+/// running the same policy logic.
+///
+/// Only the parsed definitions are hashed, so policies which differ only in formatting,
+/// comments, or surrounding documentation have the same fingerprint.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Computes the fingerprint of a parsed policy.
+    ///
+    /// The fingerprint is the SHA-256 hash of the policy's definitions encoded with
+    /// postcard, whose encoding is fixed by its wire format specification.
+    pub fn of(policy: &ast::Policy) -> Result<Fingerprint, postcard::Error> {
+        // The source text and text ranges are left out, so policies which differ only in
+        // formatting and comments have the same fingerprint.
+        let ast::Policy {
+            version,
+            ffi_imports,
+            ffi_versions,
+            facts,
+            actions,
+            effects,
+            structs,
+            enums,
+            commands,
+            functions,
+            finish_functions,
+            global_lets,
+            static_asserts,
+            text: _,
+            ranges: _,
+        } = policy;
+        let encoded = postcard::to_allocvec(&(
+            version,
+            ffi_imports,
+            ffi_versions,
+            facts,
+            actions,
+            effects,
+            structs,
+            enums,
+            commands,
+            functions,
+            finish_functions,
+            global_lets,
+            static_asserts,
+        ))?;
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(&Sha256::hash(&encoded)[..]);
+        Ok(Fingerprint(fingerprint))
+    }
+
+    /// Creates a fingerprint from its bytes.
+    pub const fn from_bytes(bytes: [u8; 32]) -> Fingerprint {
+        Fingerprint(bytes)
+    }
+
+    /// Returns the bytes of the fingerprint.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
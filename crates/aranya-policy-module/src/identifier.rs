@@ -0,0 +1,200 @@
+extern crate alloc;
+
+use alloc::{
+    collections::BTreeSet,
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::{borrow::Borrow, cmp::Ordering, fmt, hash, ops::Deref};
+
+use rkyv::{
+    rancor::{Fallible, Source},
+    ser::Writer,
+    string::{ArchivedString, StringResolver},
+    Place,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An identifier for a type, field, assignment, etc.
+///
+/// Identifiers are reference-counted, so that the copies of a name used throughout a
+/// program can share one allocation. An [`Interner`] makes them do so, which also lets
+/// equal identifiers be compared by pointer. They are serialized as strings.
+#[derive(Clone)]
+pub struct Identifier(Arc<str>);
+
+impl Identifier {
+    /// Returns the identifier as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Identifier {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Identifier {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Identifier {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Identifier {}
+
+impl PartialEq<str> for Identifier {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Identifier {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Identifier {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialEq<Identifier> for String {
+    fn eq(&self, other: &Identifier) -> bool {
+        **self == *other.0
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl hash::Hash for Identifier {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl From<&str> for Identifier {
+    fn from(value: &str) -> Self {
+        Self(Arc::from(value))
+    }
+}
+
+impl From<String> for Identifier {
+    fn from(value: String) -> Self {
+        Self(Arc::from(value))
+    }
+}
+
+impl From<Identifier> for String {
+    fn from(value: Identifier) -> Self {
+        value.0.to_string()
+    }
+}
+
+impl fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Identifier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+// Archived like a `String`, since rkyv cannot share `Arc`s without a sharing serializer.
+impl rkyv::Archive for Identifier {
+    type Archived = ArchivedString;
+    type Resolver = StringResolver;
+
+    fn resolve(&self, resolver: Self::Resolver, out: Place<Self::Archived>) {
+        ArchivedString::resolve_from_str(&self.0, resolver, out);
+    }
+}
+
+impl<S> rkyv::Serialize<S> for Identifier
+where
+    S: Fallible + Writer + ?Sized,
+    S::Error: Source,
+{
+    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+        ArchivedString::serialize_from_str(&self.0, serializer)
+    }
+}
+
+impl<D: Fallible + ?Sized> rkyv::Deserialize<Identifier, D> for ArchivedString {
+    fn deserialize(&self, _deserializer: &mut D) -> Result<Identifier, D::Error> {
+        Ok(Identifier::from(self.as_str()))
+    }
+}
+
+/// A string table which makes equal [`Identifier`]s share one allocation.
+#[derive(Clone, Debug, Default)]
+pub struct Interner(BTreeSet<Identifier>);
+
+impl Interner {
+    /// Creates an empty table.
+    pub const fn new() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    /// Replaces `ident` with the copy in the table, adding it if there is none.
+    pub fn intern(&mut self, ident: &mut Identifier) {
+        match self.0.get(ident) {
+            Some(existing) => *ident = existing.clone(),
+            None => {
+                self.0.insert(ident.clone());
+            }
+        }
+    }
+
+    /// The number of distinct identifiers in the table.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Reports whether the table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
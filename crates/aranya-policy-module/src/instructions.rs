@@ -1,6 +1,6 @@
 extern crate alloc;
 
-use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{self, Display};
 
 use serde::{Deserialize, Serialize};
@@ -9,7 +9,7 @@ mod meta;
 
 pub use meta::*;
 
-use crate::{data::Value, Label};
+use crate::{data::Value, Identifier, Label};
 
 /// Reason for ending execution.
 #[must_use]
@@ -89,9 +89,6 @@ impl Display for Target {
     }
 }
 
-/// An identifier for a type, field, assignment, etc.
-pub type Identifier = String;
-
 /// The machine instruction types
 #[derive(
     Debug,
@@ -195,6 +192,84 @@ pub enum Instruction {
     Deserialize,
     /// Metadata for tracing
     Meta(Meta),
+    // Instructions are serialized by their index, so new ones go at the end.
+    /// Pop an integer and jump to the target at its offset from the base value. Execution
+    /// continues with the next instruction if the value is out of range or not an integer.
+    JumpTable(i64, Vec<Target>),
+    /// Execute a host-defined instruction, specified by name. Extensions are registered with
+    /// the RunState.
+    Extension(Identifier),
+}
+
+impl Instruction {
+    /// The identifier this instruction refers to, if any.
+    pub fn identifier_mut(&mut self) -> Option<&mut Identifier> {
+        match self {
+            Instruction::Def(s)
+            | Instruction::Get(s)
+            | Instruction::Extension(s)
+            | Instruction::FactNew(s)
+            | Instruction::FactKeySet(s)
+            | Instruction::FactValueSet(s)
+            | Instruction::StructNew(s)
+            | Instruction::StructSet(s)
+            | Instruction::StructGet(s)
+            | Instruction::QueryNext(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The static cost of executing this instruction, for gas metering and worst-case
+    /// execution estimates. Costs are relative: simple stack and arithmetic operations cost
+    /// 1, building values costs more, and storage, FFI, and serialization cost the most.
+    pub fn cost(&self) -> u64 {
+        match self {
+            Instruction::Meta(_) => 0,
+            Instruction::Const(_)
+            | Instruction::Def(_)
+            | Instruction::Get(_)
+            | Instruction::Swap(_)
+            | Instruction::Dup(_)
+            | Instruction::Pop
+            | Instruction::Block
+            | Instruction::End
+            | Instruction::Jump(_)
+            | Instruction::Branch(_)
+            | Instruction::JumpTable(..)
+            | Instruction::Next
+            | Instruction::Last
+            | Instruction::Call(_)
+            | Instruction::Return
+            | Instruction::Exit(_)
+            | Instruction::Add
+            | Instruction::Sub
+            | Instruction::Not
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::Gt
+            | Instruction::Lt
+            | Instruction::Eq => 1,
+            Instruction::FactNew(_)
+            | Instruction::FactKeySet(_)
+            | Instruction::FactValueSet(_)
+            | Instruction::StructNew(_)
+            | Instruction::StructSet(_)
+            | Instruction::StructGet(_) => 2,
+            Instruction::Publish | Instruction::Emit => 10,
+            Instruction::ExtCall(..)
+            | Instruction::Extension(_)
+            | Instruction::Serialize
+            | Instruction::Deserialize => 20,
+            Instruction::Create
+            | Instruction::Delete
+            | Instruction::Query
+            | Instruction::QueryStart
+            | Instruction::QueryNext(_) => 50,
+            Instruction::Update => 100,
+            // Counting reads up to `limit` facts
+            Instruction::FactCount(limit) => 50u64.saturating_mul(limit.unsigned_abs().max(1)),
+        }
+    }
 }
 
 impl Display for Instruction {
@@ -210,10 +285,21 @@ impl Display for Instruction {
             Instruction::End => write!(f, "end"),
             Instruction::Jump(t) => write!(f, "jump {t}"),
             Instruction::Branch(t) => write!(f, "branch {t}"),
+            Instruction::JumpTable(base, targets) => {
+                write!(f, "jumptable {base} [")?;
+                for (i, t) in targets.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{t}")?;
+                }
+                write!(f, "]")
+            }
             Instruction::Next => write!(f, "next"),
             Instruction::Last => write!(f, "last"),
             Instruction::Call(t) => write!(f, "call {t}"),
             Instruction::ExtCall(module, proc) => write!(f, "extcall {module} {proc}"),
+            Instruction::Extension(name) => write!(f, "ext {name}"),
             Instruction::Return => write!(f, "return"),
             Instruction::Exit(reason) => write!(f, "exit {reason}"),
             Instruction::Add => write!(f, "add"),
@@ -26,6 +26,8 @@ pub enum Meta {
     Finish(bool),
     /// Mark an FFI call (module name, procedure name)
     FFI(String, String),
+    /// The reason for the failed `check` which exits next
+    Check(String),
 }
 
 impl fmt::Display for Meta {
@@ -41,6 +43,7 @@ impl fmt::Display for Meta {
                 }
             }
             Meta::FFI(module, procedure) => write!(f, "FFI call `{module}.{procedure}"),
+            Meta::Check(reason) => write!(f, "check failed: {reason:?}"),
         }
     }
 }
@@ -7,15 +7,23 @@
 #![warn(missing_docs)]
 #![warn(clippy::arithmetic_side_effects)]
 
+mod bytes;
 mod codemap;
+mod compact;
 mod data;
 pub mod ffi;
+mod fingerprint;
+mod identifier;
 mod instructions;
 mod label;
 mod module;
 
+pub use bytes::*;
 pub use codemap::*;
+pub use compact::*;
 pub use data::*;
+pub use fingerprint::*;
+pub use identifier::*;
 pub use instructions::*;
 pub use label::*;
 pub use module::*;
@@ -9,13 +9,15 @@ use aranya_policy_ast as ast;
 use ast::FactDefinition;
 use serde::{Deserialize, Serialize};
 
-use crate::{CodeMap, Instruction, Label, Value};
+use crate::{CodeMap, Fingerprint, Instruction, Label, Value, ValueConversionError};
 
 /// Identifies a [`Module`].
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Version {
     /// Version 0.
     V0,
+    /// Version 1.
+    V1,
 }
 
 impl Version {
@@ -23,6 +25,7 @@ impl Version {
     pub const fn as_str(&self) -> &'static str {
         match self {
             Self::V0 => "V0",
+            Self::V1 => "V1",
         }
     }
 }
@@ -69,8 +72,39 @@ impl Module {
     pub const fn version(&self) -> Version {
         match self.data {
             ModuleData::V0(_) => Version::V0,
+            ModuleData::V1(_) => Version::V1,
         }
     }
+
+    /// Returns the [`Fingerprint`] of the policy the module was compiled from, if known.
+    pub fn fingerprint(&self) -> Option<&Fingerprint> {
+        match &self.data {
+            ModuleData::V0(_) => None,
+            ModuleData::V1(m) => m.fingerprint.as_ref(),
+        }
+    }
+
+    /// Looks up the attribute `name` of `command`, converted to `T`. Returns `Ok(None)` if
+    /// the command does not have the attribute.
+    pub fn command_attribute<T>(
+        &self,
+        command: &str,
+        name: &str,
+    ) -> Result<Option<T>, ValueConversionError>
+    where
+        T: TryFrom<Value, Error = ValueConversionError>,
+    {
+        let command_attributes = match &self.data {
+            ModuleData::V0(m) => &m.command_attributes,
+            ModuleData::V1(m) => &m.command_attributes,
+        };
+        command_attributes
+            .get(command)
+            .and_then(|attrs| attrs.get(name))
+            .cloned()
+            .map(T::try_from)
+            .transpose()
+    }
 }
 
 /// Versioned [`Module`] data.
@@ -89,6 +123,8 @@ impl Module {
 pub enum ModuleData {
     /// Version 0
     V0(ModuleV0),
+    /// Version 1
+    V1(ModuleV1),
 }
 
 /// The Version 0 module format
@@ -124,3 +160,62 @@ pub struct ModuleV0 {
     /// Global static data
     pub globals: BTreeMap<String, Value>,
 }
+
+/// The Version 1 module format, which adds the policy's [`Fingerprint`] and tells
+/// effects apart from other structs
+#[derive(
+    Clone,
+    Debug,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleV1 {
+    /// Program memory
+    pub progmem: Box<[Instruction]>,
+    /// Labels
+    pub labels: BTreeMap<Label, usize>,
+    /// Action definitions
+    pub action_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
+    /// Command definitions
+    pub command_defs: BTreeMap<String, BTreeMap<String, ast::VType>>,
+    /// Fact definition
+    pub fact_defs: BTreeMap<String, FactDefinition>,
+    /// Struct definitions
+    pub struct_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
+    /// Command attributes
+    pub command_attributes: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Code map
+    pub codemap: Option<CodeMap>,
+    /// Global static data
+    pub globals: BTreeMap<String, Value>,
+    /// Effect definitions. Effects are also in `struct_defs`.
+    pub effect_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
+    /// Fingerprint of the policy source
+    pub fingerprint: Option<Fingerprint>,
+}
+
+/// Upgrades a Version 0 module. Its fingerprint is unknown, and its effects are only
+/// among its struct definitions.
+impl From<ModuleV0> for ModuleV1 {
+    fn from(m: ModuleV0) -> Self {
+        Self {
+            progmem: m.progmem,
+            labels: m.labels,
+            action_defs: m.action_defs,
+            command_defs: m.command_defs,
+            fact_defs: m.fact_defs,
+            struct_defs: m.struct_defs,
+            command_attributes: m.command_attributes,
+            codemap: m.codemap,
+            globals: m.globals,
+            effect_defs: BTreeMap::new(),
+            fingerprint: None,
+        }
+    }
+}
@@ -273,21 +273,18 @@ fn main() -> anyhow::Result<()> {
 
             if let Some(action) = &args.action {
                 name = action.clone();
-                ctx = CommandContext::Action(ActionContext {
-                    name: &name,
-                    head_id: Id::default(),
-                });
+                ctx = CommandContext::Action(ActionContext::new(&name, Id::default()));
                 rs = machine.create_run_state(&mut io, &ctx);
                 let call_args = args.args.into_iter().map(convert_arg_value);
                 rs.setup_action(action, call_args)?;
             } else if let Some(command) = args.command {
                 name = command.clone();
-                ctx = CommandContext::Policy(PolicyContext {
-                    name: &name,
-                    id: Id::default(),
-                    author: Id::default().into(),
-                    version: Id::default(),
-                });
+                ctx = CommandContext::Policy(PolicyContext::new(
+                    &name,
+                    Id::default(),
+                    Id::default().into(),
+                    Id::default(),
+                ));
                 rs = machine.create_run_state(&mut io, &ctx);
                 let fields: BTreeMap<String, Value> = args
                     .args
@@ -0,0 +1,127 @@
+//! Compares the `match`-based interpreter loop, [`RunState::run()`], with threaded
+//! dispatch, [`RunState::run_threaded()`].
+
+#![allow(clippy::arithmetic_side_effects)]
+#![allow(clippy::unwrap_used)]
+
+use std::{fmt::Write, iter};
+
+use aranya_crypto::Id;
+use aranya_policy_ast::Version;
+use aranya_policy_compiler::Compiler;
+use aranya_policy_lang::lang::parse_policy_str;
+use aranya_policy_vm::{
+    ActionContext, CommandContext, ExitReason, FactKey, FactKeyList, FactValue, FactValueList,
+    KVPair, Machine, MachineError, MachineErrorType, MachineIO, MachineIOError, MachineStack,
+    RunState, Value,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// I/O which stores nothing.
+struct NoopIO;
+
+impl MachineIO<MachineStack> for NoopIO {
+    type QueryIterator = iter::Empty<Result<(FactKeyList, FactValueList), MachineIOError>>;
+
+    fn fact_insert(
+        &mut self,
+        _name: String,
+        _key: impl IntoIterator<Item = FactKey>,
+        _value: impl IntoIterator<Item = FactValue>,
+    ) -> Result<(), MachineIOError> {
+        Ok(())
+    }
+
+    fn fact_delete(
+        &mut self,
+        _name: String,
+        _key: impl IntoIterator<Item = FactKey>,
+    ) -> Result<(), MachineIOError> {
+        Ok(())
+    }
+
+    fn fact_query(
+        &self,
+        _name: String,
+        _key: impl IntoIterator<Item = FactKey>,
+    ) -> Result<Self::QueryIterator, MachineIOError> {
+        Ok(iter::empty())
+    }
+
+    fn publish(&mut self, _name: String, _fields: impl IntoIterator<Item = KVPair>) {}
+
+    fn effect(
+        &mut self,
+        _name: String,
+        _fields: impl IntoIterator<Item = KVPair>,
+        _command: Id,
+        _recalled: bool,
+    ) {
+    }
+
+    fn call(
+        &mut self,
+        module: usize,
+        _procedure: usize,
+        _stack: &mut MachineStack,
+        _ctx: &CommandContext<'_>,
+    ) -> Result<(), MachineError> {
+        Err(MachineError::new(MachineErrorType::FfiModuleNotDefined(
+            module,
+        )))
+    }
+}
+
+/// Returns a policy whose action makes `calls` function calls, each doing a little
+/// arithmetic and logic.
+fn policy(calls: usize) -> String {
+    let mut text = String::from(
+        r#"
+        function step(a int, b bool) int {
+            if b && a != 0 {
+                return a + 2
+            }
+            return a - 1
+        }
+
+        action run() {
+            let x0 = 0
+        "#,
+    );
+    for i in 1..=calls {
+        let prev = i - 1;
+        writeln!(text, "let x{i} = step(x{prev}, true)").unwrap();
+    }
+    text.push('}');
+    text
+}
+
+/// Runs the action of [`policy()`] with `f`.
+fn run_action<F>(machine: &Machine, ctx: &CommandContext<'_>, f: F) -> ExitReason
+where
+    F: FnOnce(&mut RunState<'_, NoopIO>) -> Result<ExitReason, MachineError>,
+{
+    let mut io = NoopIO;
+    let mut rs = machine.create_run_state(&mut io, ctx);
+    rs.setup_action("run", iter::empty::<Value>()).unwrap();
+    f(&mut rs).unwrap()
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let policy = parse_policy_str(&policy(200), Version::V1).unwrap();
+    let module = Compiler::new(&policy).compile().unwrap();
+    let machine = Machine::from_module(module).unwrap();
+    let ctx = CommandContext::Action(ActionContext::new("run", Id::default()));
+
+    let mut group = c.benchmark_group("dispatch");
+    group.bench_function("match", |b| {
+        b.iter(|| black_box(run_action(&machine, &ctx, |rs| rs.run())))
+    });
+    group.bench_function("threaded", |b| {
+        b.iter(|| black_box(run_action(&machine, &ctx, |rs| rs.run_threaded())))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, string::ToString, vec, vec::Vec};
+
+use aranya_policy_module::{Instruction, Label, Target};
+use buggy::BugExt;
+
+use crate::{
+    error::{MachineError, MachineErrorType},
+    Machine,
+};
+
+impl Machine {
+    /// Estimates the greatest total [cost](Instruction::cost) of the instructions executed
+    /// when running from `label`, for comparison with a gas limit set with
+    /// [`RunState::set_gas_limit()`](crate::RunState::set_gas_limit).
+    ///
+    /// Returns `None` if the cost is unbounded, which happens when the code contains a loop,
+    /// such as a `map` statement over query results.
+    pub fn worst_case_cost(&self, label: &Label) -> Result<Option<u64>, MachineError> {
+        let entry = *self.labels.get(label).ok_or_else(|| {
+            MachineError::new(MachineErrorType::InvalidAddress(label.name.clone()))
+        })?;
+
+        // The worst-case cost from each address until its function returns or execution
+        // exits. `None` marks an address which is still being traced, so reaching it again
+        // means there is a loop.
+        let mut costs: BTreeMap<usize, Option<Option<u64>>> = BTreeMap::new();
+        let mut work = vec![entry];
+        while let Some(&pc) = work.last() {
+            let instr = self.progmem.get(pc).ok_or_else(|| {
+                MachineError::new(MachineErrorType::InvalidAddress(pc.to_string()))
+            })?;
+            let next = pc.checked_add(1).assume("pc + 1 must not wrap")?;
+            let deps = dependencies(instr, next)?;
+
+            match costs.get(&pc) {
+                Some(Some(_)) => {
+                    work.pop();
+                }
+                Some(None) => {
+                    // Every dependency has been traced, unless it loops back here.
+                    let cost_of = |addr: &usize| costs.get(addr).copied().flatten().flatten();
+                    let cost = match instr {
+                        // The callee runs, and then execution continues after the call.
+                        Instruction::Call(_) => deps
+                            .iter()
+                            .try_fold(instr.cost(), |sum, d| sum.checked_add(cost_of(d)?)),
+                        // Otherwise, the most expensive of the possible next instructions.
+                        _ => deps
+                            .iter()
+                            .try_fold(0, |max: u64, d| Some(max.max(cost_of(d)?)))
+                            .and_then(|max| instr.cost().checked_add(max)),
+                    };
+                    costs.insert(pc, Some(cost));
+                    work.pop();
+                }
+                None => {
+                    costs.insert(pc, None);
+                    work.extend(deps.into_iter().filter(|d| !costs.contains_key(d)));
+                }
+            }
+        }
+
+        Ok(costs.get(&entry).copied().flatten().flatten())
+    }
+}
+
+/// The addresses whose costs determine the cost of `instr`. For a `Call`, these are the
+/// callee and the return address; otherwise they are the possible next instructions.
+fn dependencies(instr: &Instruction, next: usize) -> Result<Vec<usize>, MachineError> {
+    let resolved = |t: &Target| match t {
+        Target::Resolved(addr) => Ok(*addr),
+        Target::Unresolved(label) => Err(MachineError::new(MachineErrorType::UnresolvedTarget(
+            label.clone(),
+        ))),
+    };
+    Ok(match instr {
+        Instruction::Return | Instruction::Exit(_) => vec![],
+        Instruction::Jump(t) => vec![resolved(t)?],
+        Instruction::Branch(t) | Instruction::Call(t) => vec![resolved(t)?, next],
+        Instruction::JumpTable(_, targets) => {
+            let mut deps = targets
+                .iter()
+                .map(resolved)
+                .collect::<Result<Vec<_>, _>>()?;
+            deps.push(next);
+            deps
+        }
+        _ => vec![next],
+    })
+}
@@ -0,0 +1,139 @@
+#![cfg_attr(docsrs, doc(cfg(feature = "coverage")))]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+use aranya_policy_module::{Label, LabelType};
+
+use crate::Machine;
+
+/// Records which instructions of a [`Machine`] were executed, across any number of runs.
+///
+/// Attach it to each [`RunState`](crate::RunState) of a test run with
+/// [`RunState::set_coverage()`](crate::RunState::set_coverage), then use
+/// [`lcov()`](Self::lcov) to report which lines of the policy were covered.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Coverage {
+    /// The number of times each instruction was executed, by address
+    hits: Vec<u64>,
+}
+
+impl Coverage {
+    /// Creates an empty record for `machine`.
+    pub fn new(machine: &Machine) -> Self {
+        Self {
+            hits: vec![0; machine.progmem.len()],
+        }
+    }
+
+    /// Counts an execution of the instruction at `addr`.
+    pub(crate) fn record(&mut self, addr: usize) {
+        if let Some(hits) = self.hits.get_mut(addr) {
+            *hits = hits.saturating_add(1);
+        }
+    }
+
+    /// Returns the number of times the instruction at `addr` was executed.
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of instructions which were executed at least once.
+    pub fn executed(&self) -> usize {
+        self.hits.iter().filter(|&&hits| hits > 0).count()
+    }
+
+    /// Returns the number of instructions in the machine.
+    pub fn total(&self) -> usize {
+        self.hits.len()
+    }
+
+    /// Returns the number of times each action, command block, and function of `machine`
+    /// was entered, in label order.
+    pub fn entry_points<'m>(
+        &'m self,
+        machine: &'m Machine,
+    ) -> impl Iterator<Item = (&'m Label, u64)> + 'm {
+        machine
+            .labels
+            .iter()
+            .filter(|(label, _)| label.ltype != LabelType::Temporary)
+            .map(|(label, &addr)| (label, self.hits(addr)))
+    }
+
+    /// Returns an lcov tracefile for `machine`, naming the policy source `source_file`.
+    /// Write it out with its [`Display`](fmt::Display) implementation.
+    ///
+    /// Lines and entry points are mapped back to the policy source with the machine's
+    /// [`CodeMap`](crate::CodeMap), so nothing is reported if it does not have one. A line
+    /// counts as executed as many times as its most executed instruction.
+    pub fn lcov<'m>(&'m self, machine: &'m Machine, source_file: &'m str) -> Lcov<'m> {
+        Lcov {
+            coverage: self,
+            machine,
+            source_file,
+        }
+    }
+}
+
+/// An lcov tracefile, created by [`Coverage::lcov()`].
+#[derive(Clone, Debug)]
+pub struct Lcov<'a> {
+    coverage: &'a Coverage,
+    machine: &'a Machine,
+    source_file: &'a str,
+}
+
+impl Lcov<'_> {
+    /// Returns the source line of the instruction at `addr`.
+    fn line_of(&self, addr: usize) -> Option<usize> {
+        self.machine
+            .codemap
+            .as_ref()
+            .and_then(|codemap| codemap.span_from_instruction(addr).ok())
+            .map(|span| span.start_linecol().0)
+    }
+}
+
+impl fmt::Display for Lcov<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "TN:")?;
+        writeln!(f, "SF:{}", self.source_file)?;
+
+        let mut entries = 0usize;
+        let mut entered = 0usize;
+        for (label, &addr) in self.machine.labels.iter() {
+            if label.ltype == LabelType::Temporary {
+                continue;
+            }
+            let Some(line) = self.line_of(addr) else {
+                continue;
+            };
+            let hits = self.coverage.hits(addr);
+            writeln!(f, "FN:{line},{label}")?;
+            writeln!(f, "FNDA:{hits},{label}")?;
+            entries = entries.saturating_add(1);
+            if hits > 0 {
+                entered = entered.saturating_add(1);
+            }
+        }
+        writeln!(f, "FNF:{entries}")?;
+        writeln!(f, "FNH:{entered}")?;
+
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for (addr, &hits) in self.coverage.hits.iter().enumerate() {
+            if let Some(line) = self.line_of(addr) {
+                let count = lines.entry(line).or_default();
+                *count = (*count).max(hits);
+            }
+        }
+        for (line, hits) in &lines {
+            writeln!(f, "DA:{line},{hits}")?;
+        }
+        writeln!(f, "LF:{}", lines.len())?;
+        writeln!(f, "LH:{}", lines.values().filter(|&&hits| hits > 0).count())?;
+        writeln!(f, "end_of_record")
+    }
+}
@@ -0,0 +1,154 @@
+extern crate alloc;
+
+use alloc::{
+    string::String,
+    vec::{self, Vec},
+};
+
+use aranya_policy_module::{FactKey, FactKeyList, FactValueList};
+
+use crate::{
+    io::{sort_key, MachineIO, MachineIOError},
+    stack::Stack,
+};
+
+/// Pages through the results of a fact query in key order, so that large numbers of facts
+/// can be read without holding them all at once.
+///
+/// Each call to [`next_page()`](Self::next_page) continues after the last fact returned.
+/// The position can be saved with [`continuation()`](Self::continuation) and restored
+/// with [`resume_after()`](Self::resume_after), for example to continue in a later
+/// session. Facts created or deleted between pages are seen if they sort after the
+/// position.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FactCursor {
+    name: String,
+    prefix: FactKeyList,
+    after: Option<FactKeyList>,
+    page_size: usize,
+    done: bool,
+}
+
+impl FactCursor {
+    /// Opens a cursor over the facts named `name` whose keys begin with `prefix`, which
+    /// returns up to `page_size` facts at a time.
+    pub fn new(
+        name: impl Into<String>,
+        prefix: impl IntoIterator<Item = FactKey>,
+        page_size: usize,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            prefix: prefix.into_iter().collect(),
+            after: None,
+            page_size: page_size.max(1),
+            done: false,
+        }
+    }
+
+    /// Continues after the fact with the keys `after`, as returned by
+    /// [`continuation()`](Self::continuation).
+    pub fn resume_after(mut self, after: FactKeyList) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    /// Returns the keys of the last fact returned, which the next page starts after.
+    pub fn continuation(&self) -> Option<&[FactKey]> {
+        self.after.as_deref()
+    }
+
+    /// Reports whether every fact has been returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the next page of facts, which is empty once every fact has been returned.
+    pub fn next_page<M, S>(
+        &mut self,
+        io: &M,
+    ) -> Result<Vec<(FactKeyList, FactValueList)>, MachineIOError>
+    where
+        M: MachineIO<S>,
+        S: Stack,
+    {
+        if self.done {
+            return Ok(Vec::new());
+        }
+        let page = io.fact_query_page(
+            self.name.clone(),
+            self.prefix.iter().cloned(),
+            self.after.as_deref(),
+            self.page_size,
+        )?;
+        if page.len() < self.page_size {
+            self.done = true;
+        }
+        if let Some((keys, _)) = page.last() {
+            self.after = Some(keys.clone());
+        }
+        Ok(page)
+    }
+}
+
+/// The results of a `map` query, read from a [`FactCursor`] a page at a time.
+#[derive(Debug)]
+pub(crate) struct PagedQuery {
+    cursor: FactCursor,
+    page: vec::IntoIter<(FactKeyList, FactValueList)>,
+    /// The number of facts returned so far
+    pub read: usize,
+    /// Whether the facts are read from I/O, rather than given by a
+    /// [`FactHook`](crate::FactHook)
+    pub from_io: bool,
+}
+
+impl PagedQuery {
+    pub fn new(cursor: FactCursor) -> Self {
+        Self {
+            cursor,
+            page: Vec::new().into_iter(),
+            read: 0,
+            from_io: true,
+        }
+    }
+
+    /// Returns `facts` in key order, instead of reading them with `cursor`.
+    pub fn answered(mut cursor: FactCursor, mut facts: Vec<(FactKeyList, FactValueList)>) -> Self {
+        facts.sort_by_cached_key(|(keys, _)| sort_key(keys));
+        cursor.done = true;
+        Self {
+            cursor,
+            page: facts.into_iter(),
+            read: 0,
+            from_io: false,
+        }
+    }
+
+    /// Returns the name of the facts being queried.
+    pub fn name(&self) -> &str {
+        &self.cursor.name
+    }
+
+    /// Returns the next fact, reading another page from `io` if needed.
+    pub fn next<M, S>(
+        &mut self,
+        io: &M,
+    ) -> Result<Option<(FactKeyList, FactValueList)>, MachineIOError>
+    where
+        M: MachineIO<S>,
+        S: Stack,
+    {
+        let fact = match self.page.next() {
+            Some(fact) => Some(fact),
+            None => {
+                self.page = self.cursor.next_page(io)?.into_iter();
+                self.page.next()
+            }
+        };
+        if fact.is_some() {
+            self.read = self.read.saturating_add(1);
+        }
+        Ok(fact)
+    }
+}
@@ -1,33 +1,133 @@
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicBool, AtomicI64, Ordering},
+};
+
 pub use aranya_crypto::Id;
 use aranya_crypto::UserId;
+use aranya_policy_module::Timestamp;
 
 /// Context for actions
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct ActionContext<'a> {
     /// The name of the action
     pub name: &'a str,
     /// The head of the graph
     pub head_id: Id,
+    /// Cancels execution when set, for bounding how long it may take
+    pub cancellation: Option<&'a CancellationToken>,
+    /// The time the action was called, from the host's [`Clock`]
+    pub now: Option<Timestamp>,
+    /// Whether the action is being previewed rather than performed. FFI procedures with
+    /// effects outside the policy, such as storing keys, should not make them.
+    pub dry_run: bool,
+}
+
+impl<'a> ActionContext<'a> {
+    /// Creates the context for calling the action `name` at `head_id`.
+    pub const fn new(name: &'a str, head_id: Id) -> Self {
+        Self {
+            name,
+            head_id,
+            cancellation: None,
+            now: None,
+            dry_run: false,
+        }
+    }
+
+    /// Sets [`ActionContext::cancellation`].
+    #[must_use]
+    pub const fn with_cancellation(mut self, cancellation: Option<&'a CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Sets [`ActionContext::now`].
+    #[must_use]
+    pub const fn with_now(mut self, now: Option<Timestamp>) -> Self {
+        self.now = now;
+        self
+    }
+
+    /// Sets [`ActionContext::dry_run`].
+    #[must_use]
+    pub const fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
 }
 
 /// Context for seal blocks
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct SealContext<'a> {
     /// The name of the command
     pub name: &'a str,
     /// The ID of the command at the head of the perspective
     pub head_id: Id,
+    /// Cancels execution when set, for bounding how long it may take
+    pub cancellation: Option<&'a CancellationToken>,
+    /// The time the command was sealed, from the host's [`Clock`]
+    pub now: Option<Timestamp>,
+}
+
+impl<'a> SealContext<'a> {
+    /// Creates the context for sealing the command `name` at `head_id`.
+    pub const fn new(name: &'a str, head_id: Id) -> Self {
+        Self {
+            name,
+            head_id,
+            cancellation: None,
+            now: None,
+        }
+    }
+
+    /// Sets [`SealContext::cancellation`].
+    #[must_use]
+    pub const fn with_cancellation(mut self, cancellation: Option<&'a CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Sets [`SealContext::now`].
+    #[must_use]
+    pub const fn with_now(mut self, now: Option<Timestamp>) -> Self {
+        self.now = now;
+        self
+    }
 }
 
 /// Context for open blocks
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct OpenContext<'a> {
     /// The name of the command
     pub name: &'a str,
+    /// Cancels execution when set, for bounding how long it may take
+    pub cancellation: Option<&'a CancellationToken>,
+}
+
+impl<'a> OpenContext<'a> {
+    /// Creates the context for opening the command `name`.
+    pub const fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            cancellation: None,
+        }
+    }
+
+    /// Sets [`OpenContext::cancellation`].
+    #[must_use]
+    pub const fn with_cancellation(mut self, cancellation: Option<&'a CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
 }
 
 /// Context for Policy and Recall blocks
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct PolicyContext<'a> {
     /// The name of the command
     pub name: &'a str,
@@ -37,6 +137,38 @@ pub struct PolicyContext<'a> {
     pub author: UserId,
     /// The ID of the version of policy and FFI module set
     pub version: Id,
+    /// Cancels execution when set, for bounding how long it may take
+    pub cancellation: Option<&'a CancellationToken>,
+}
+
+impl<'a> PolicyContext<'a> {
+    /// Creates the context for evaluating the command `name` with ID `id`, authored
+    /// by `author` under policy `version`.
+    pub const fn new(name: &'a str, id: Id, author: UserId, version: Id) -> Self {
+        Self {
+            name,
+            id,
+            author,
+            version,
+            cancellation: None,
+        }
+    }
+
+    /// Sets [`PolicyContext::cancellation`].
+    #[must_use]
+    pub const fn with_cancellation(mut self, cancellation: Option<&'a CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+}
+
+/// Context for pure functions called directly by the host
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionContext<'a> {
+    /// The name of the function
+    pub name: &'a str,
+    /// Cancels execution when set, for bounding how long it may take
+    pub cancellation: Option<&'a CancellationToken>,
 }
 
 /// Properties of policy execution available through FFI.
@@ -52,4 +184,154 @@ pub enum CommandContext<'a> {
     Policy(PolicyContext<'a>),
     /// Recall operation
     Recall(PolicyContext<'a>),
+    /// Pure function called by the host
+    Function(FunctionContext<'a>),
+}
+
+impl CommandContext<'_> {
+    /// Returns the token which cancels this execution, if there is one.
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        match self {
+            Self::Action(ctx) => ctx.cancellation,
+            Self::Seal(ctx) => ctx.cancellation,
+            Self::Open(ctx) => ctx.cancellation,
+            Self::Policy(ctx) | Self::Recall(ctx) => ctx.cancellation,
+            Self::Function(ctx) => ctx.cancellation,
+        }
+    }
+
+    /// Returns the current time, for timestamping new commands.
+    ///
+    /// It is only available to actions and seal blocks, which run once on the client
+    /// which creates a command. Other blocks are evaluated by every peer, perhaps long
+    /// after the command was created, so they must read a timestamp from the command's
+    /// fields to reach the same result.
+    pub fn now(&self) -> Option<Timestamp> {
+        match self {
+            Self::Action(ctx) => ctx.now,
+            Self::Seal(ctx) => ctx.now,
+            _ => None,
+        }
+    }
+
+    /// Reports whether this is part of a dry run, whose results are discarded. See
+    /// [`ActionContext::dry_run`].
+    pub fn is_dry_run(&self) -> bool {
+        matches!(self, Self::Action(ctx) if ctx.dry_run)
+    }
+
+    /// Reports whether this execution has been cancelled. Long-running FFI procedures
+    /// should check this periodically and return early once it is true.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+impl fmt::Display for CommandContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Action(ctx) => write!(f, "action `{}`", ctx.name),
+            Self::Seal(ctx) => write!(f, "seal block of command `{}`", ctx.name),
+            Self::Open(ctx) => write!(f, "open block of command `{}`", ctx.name),
+            Self::Policy(ctx) => write!(f, "policy block of command `{}`", ctx.name),
+            Self::Recall(ctx) => write!(f, "recall block of command `{}`", ctx.name),
+            Self::Function(ctx) => write!(f, "function `{}`", ctx.name),
+        }
+    }
+}
+
+/// A flag for cooperatively cancelling policy execution, passed to FFI procedures in a
+/// [`CommandContext`].
+///
+/// To bound the time spent running a policy, the host cancels the token from another thread
+/// or a timer once a deadline passes. The VM fails with
+/// [`MachineErrorType::Cancelled`](crate::MachineErrorType::Cancelled) before the next
+/// instruction, and FFI procedures which may block or run for a long time should poll
+/// [`CommandContext::is_cancelled()`] so that they return promptly.
+#[derive(Debug, Default)]
+pub struct CancellationToken(AtomicBool);
+
+impl CancellationToken {
+    /// Creates a token which has not been cancelled.
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Cancels every execution using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears the cancellation, so that the token can be reused.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Reports whether [`cancel()`](Self::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tokens are only equal to themselves, so that contexts are equal when they share a token.
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+/// A source of the current time for policy execution, passed to FFI procedures through
+/// [`CommandContext::now()`].
+///
+/// Hosts use [`SystemClock`] in production and [`ManualClock`] in tests, so that
+/// timestamps are consistent across FFI modules and deterministic when testing.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// A [`Clock`] which reads the system time, in seconds since the Unix epoch.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Timestamp(i64::try_from(secs).unwrap_or(i64::MAX))
+    }
+}
+
+/// A [`Clock`] whose time is set by hand, for tests.
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicI64);
+
+impl ManualClock {
+    /// Creates a clock which reads `now`.
+    pub const fn new(now: Timestamp) -> Self {
+        Self(AtomicI64::new(now.0))
+    }
+
+    /// Sets the time.
+    pub fn set(&self, now: Timestamp) {
+        self.0.store(now.0, Ordering::Relaxed);
+    }
+
+    /// Moves the time forward by `secs`.
+    pub fn advance(&self, secs: i64) {
+        self.0.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp(self.0.load(Ordering::Relaxed))
+    }
 }
@@ -0,0 +1,157 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
+
+use aranya_policy_module::{ExitReason, Instruction, Label, Struct, Value};
+
+use crate::{
+    error::{MachineError, MachineErrorType},
+    io::MachineIO,
+    machine::{MachineStack, MachineStatus, RunState},
+};
+
+/// The reason [`Debugger::resume()`] stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// Execution reached a breakpoint at this address. The instruction there has not run.
+    Breakpoint(usize),
+    /// Execution exited.
+    Exited(ExitReason),
+}
+
+/// Runs a [`RunState`] under control of a debugger, with breakpoints, single stepping,
+/// and inspection of the stack, named definitions, and fact query results.
+///
+/// Set up the run state (e.g. with [`RunState::setup_action()`]) before wrapping it.
+pub struct Debugger<'a, M: MachineIO<MachineStack>> {
+    rs: RunState<'a, M>,
+    breakpoints: BTreeSet<usize>,
+    /// The fact most recently fetched by each open query, innermost last
+    cursors: Vec<Option<Struct>>,
+}
+
+impl<'a, M> Debugger<'a, M>
+where
+    M: MachineIO<MachineStack>,
+{
+    /// Creates a debugger for `rs`, with no breakpoints.
+    pub fn new(rs: RunState<'a, M>) -> Self {
+        let cursors = alloc::vec![None; rs.query_depth()];
+        Self {
+            rs,
+            breakpoints: BTreeSet::new(),
+            cursors,
+        }
+    }
+
+    /// Returns the underlying run state.
+    pub fn into_inner(self) -> RunState<'a, M> {
+        self.rs
+    }
+
+    /// The underlying run state.
+    pub fn run_state(&self) -> &RunState<'a, M> {
+        &self.rs
+    }
+
+    /// Stops execution before the instruction at `addr` runs.
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Stops execution at the start of the action, command block, or function at `label`.
+    pub fn add_label_breakpoint(&mut self, label: &Label) -> Result<(), MachineError> {
+        let addr = *self.rs.machine().labels.get(label).ok_or_else(|| {
+            MachineError::new(MachineErrorType::InvalidAddress(label.name.clone()))
+        })?;
+        self.add_breakpoint(addr);
+        Ok(())
+    }
+
+    /// Removes the breakpoint at `addr`. Returns whether there was one.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// The addresses of all breakpoints, in order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<MachineStatus, MachineError> {
+        let pc = self.rs.pc();
+        let next_fact = match self.instruction() {
+            Some(Instruction::QueryNext(ident)) => Some(ident.clone()),
+            _ => None,
+        };
+        let depth = self.rs.query_depth();
+
+        let status = self.rs.step().map_err(|err| self.rs.locate(err, pc))?;
+
+        // A `QueryNext` which leaves its query open has fetched a fact.
+        if let Some(ident) = next_fact {
+            if self.rs.query_depth() == depth {
+                let fact = self.definition(&ident).and_then(|v| match v {
+                    Value::Struct(s) => Some(s),
+                    _ => None,
+                });
+                if let Some(cursor) = self.cursors.last_mut() {
+                    *cursor = fact;
+                }
+            }
+        }
+        self.cursors.resize(self.rs.query_depth(), None);
+
+        Ok(status)
+    }
+
+    /// Executes instructions until execution exits or reaches a breakpoint. At least one
+    /// instruction is executed, so resuming from a breakpoint continues past it.
+    pub fn resume(&mut self) -> Result<DebugEvent, MachineError> {
+        loop {
+            if let MachineStatus::Exited(reason) = self.step()? {
+                return Ok(DebugEvent::Exited(reason));
+            }
+            let pc = self.rs.pc();
+            if self.breakpoints.contains(&pc) {
+                return Ok(DebugEvent::Breakpoint(pc));
+            }
+        }
+    }
+
+    /// The address of the next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.rs.pc()
+    }
+
+    /// The next instruction to run, if the program counter is valid.
+    pub fn instruction(&self) -> Option<&Instruction> {
+        self.rs.machine().progmem.get(self.rs.pc())
+    }
+
+    /// A description of the source code of the next instruction, if available.
+    pub fn source_location(&self) -> Option<String> {
+        self.rs.source_location()
+    }
+
+    /// The values on the stack, from bottom to top.
+    pub fn stack(&self) -> &[Value] {
+        &self.rs.stack.values
+    }
+
+    /// The named values defined in the current function, innermost block first.
+    pub fn definitions(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.rs.scope().locals()
+    }
+
+    /// Looks up a named value, including globals.
+    pub fn definition(&self, name: &str) -> Option<Value> {
+        self.rs.scope().get(name).ok()
+    }
+
+    /// The fact most recently fetched by the innermost open query, if any.
+    pub fn fact_cursor(&self) -> Option<&Struct> {
+        self.cursors.last()?.as_ref()
+    }
+}
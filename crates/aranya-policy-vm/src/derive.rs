@@ -10,6 +10,8 @@
 ///
 /// - `name`: the name of the FFI module (e.g., everything before
 ///   the `::` in `aranya_crypto::encrypt_data`).
+/// - `version`: the version of the FFI module, which policies
+///   can require with `use name >= version`. Defaults to 1.
 ///
 /// Methods and associated functions in the `impl` block with the
 /// `#[ffi_export]` attribute are included in the FFI module's
@@ -0,0 +1,135 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use aranya_policy_module::{Instruction, Label, Target};
+
+use crate::Machine;
+
+/// A listing of a machine's program memory, produced by [`Machine::disassemble()`].
+#[derive(Debug, Eq, PartialEq)]
+pub struct Disassembly<'a> {
+    /// The instructions, in address order
+    pub instructions: Vec<DisassembledInstruction<'a>>,
+}
+
+/// A single instruction in a [`Disassembly`].
+#[derive(Debug, Eq, PartialEq)]
+pub struct DisassembledInstruction<'a> {
+    /// The address of the instruction
+    pub addr: usize,
+    /// The labels which point to this address
+    pub labels: Vec<&'a Label>,
+    /// The instruction
+    pub instruction: &'a Instruction,
+    /// The addresses this instruction may branch to, with the labels pointing to them
+    pub targets: Vec<BranchTarget<'a>>,
+    /// The source code this instruction was compiled from, if the machine has a code map
+    pub source: Option<SourceLocation<'a>>,
+}
+
+/// A resolved branch target of a [`DisassembledInstruction`].
+#[derive(Debug, Eq, PartialEq)]
+pub struct BranchTarget<'a> {
+    /// The address branched to
+    pub addr: usize,
+    /// The labels which point to the address
+    pub labels: Vec<&'a Label>,
+}
+
+/// The position of an instruction's source code.
+#[derive(Debug, Eq, PartialEq)]
+pub struct SourceLocation<'a> {
+    /// The line and column where the source starts, in characters
+    pub start: (usize, usize),
+    /// The source text
+    pub text: &'a str,
+}
+
+impl Machine {
+    /// Produces a listing of the program memory, annotated with label names, branch
+    /// targets, and the source code each instruction was compiled from.
+    pub fn disassemble(&self) -> Disassembly<'_> {
+        let labels_at = |addr: usize| -> Vec<&Label> {
+            self.labels
+                .iter()
+                .filter(|(_, a)| **a == addr)
+                .map(|(l, _)| l)
+                .collect()
+        };
+
+        let instructions = self
+            .progmem
+            .iter()
+            .enumerate()
+            .map(|(addr, instruction)| {
+                let targets = targets(instruction)
+                    .iter()
+                    .filter_map(Target::resolved)
+                    .map(|addr| BranchTarget {
+                        addr,
+                        labels: labels_at(addr),
+                    })
+                    .collect();
+                let source = self
+                    .codemap
+                    .as_ref()
+                    .and_then(|codemap| codemap.span_from_instruction(addr).ok())
+                    .filter(|span| !span.as_str().is_empty())
+                    .map(|span| SourceLocation {
+                        start: span.start_linecol(),
+                        text: span.as_str(),
+                    });
+                DisassembledInstruction {
+                    addr,
+                    labels: labels_at(addr),
+                    instruction,
+                    targets,
+                    source,
+                }
+            })
+            .collect();
+
+        Disassembly { instructions }
+    }
+}
+
+/// The branch targets of an instruction.
+fn targets(instruction: &Instruction) -> &[Target] {
+    match instruction {
+        Instruction::Jump(t) | Instruction::Branch(t) | Instruction::Call(t) => {
+            core::slice::from_ref(t)
+        }
+        Instruction::JumpTable(_, targets) => targets,
+        _ => &[],
+    }
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut last_source = None;
+        for i in &self.instructions {
+            for label in &i.labels {
+                writeln!(f, "{label}:")?;
+            }
+            // Consecutive instructions usually share a source location, so it is only
+            // shown when it changes.
+            if let Some(source) = i.source.as_ref().filter(|s| Some(*s) != last_source) {
+                let (line, col) = source.start;
+                let text = source.text.lines().next().unwrap_or_default().trim();
+                writeln!(f, "        ; {line}:{col}: {text}")?;
+                last_source = Some(source);
+            }
+            write!(f, "  {:4}  {}", i.addr, i.instruction)?;
+            for target in &i.targets {
+                write!(f, " ; -> {}", target.addr)?;
+                for label in &target.labels {
+                    write!(f, " {label}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
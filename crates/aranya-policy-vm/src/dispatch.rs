@@ -0,0 +1,135 @@
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+
+use aranya_policy_ast as ast;
+use aranya_policy_module::{Label, LabelType};
+
+/// An action of a [`Machine`](crate::Machine), found by name with
+/// [`Machine::action_id()`](crate::Machine::action_id).
+///
+/// Calling an action by its ID skips looking up its name, so hosts which call the same
+/// actions repeatedly can look them up once. An ID is only valid for the machine it came from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ActionId(usize);
+
+/// A command of a [`Machine`](crate::Machine), found by name with
+/// [`Machine::command_id()`](crate::Machine::command_id).
+///
+/// Like [`ActionId`], this is only valid for the machine it came from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CommandId(usize);
+
+/// The entry points of an action.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ActionEntry {
+    pub name: String,
+    pub addr: Option<usize>,
+    pub args: Vec<ast::FieldDefinition>,
+}
+
+/// The entry points of a command's blocks, and its fields.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct CommandEntry {
+    pub name: String,
+    pub policy: Option<usize>,
+    pub recall: Option<usize>,
+    pub seal: Option<usize>,
+    pub open: Option<usize>,
+    pub fields: Option<BTreeMap<String, ast::VType>>,
+}
+
+impl CommandEntry {
+    /// Returns the address of the block with the given label type.
+    pub fn addr(&self, label_type: &LabelType) -> Option<usize> {
+        match label_type {
+            LabelType::CommandPolicy => self.policy,
+            LabelType::CommandRecall => self.recall,
+            LabelType::CommandSeal => self.seal,
+            LabelType::CommandOpen => self.open,
+            _ => None,
+        }
+    }
+}
+
+/// Everything needed to start an action or command, resolved once when the machine is
+/// created, so that each call needs at most one name lookup instead of one per schema.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct DispatchTable {
+    /// Sorted by name
+    actions: Vec<ActionEntry>,
+    /// Sorted by name
+    commands: Vec<CommandEntry>,
+}
+
+impl DispatchTable {
+    pub fn new(
+        labels: &BTreeMap<Label, usize>,
+        action_defs: &BTreeMap<String, Vec<ast::FieldDefinition>>,
+        command_defs: &BTreeMap<String, BTreeMap<String, ast::VType>>,
+    ) -> Self {
+        let addr = |name: &str, ltype| labels.get(&Label::new(name, ltype)).copied();
+
+        let actions = action_defs
+            .iter()
+            .map(|(name, args)| ActionEntry {
+                name: name.clone(),
+                addr: addr(name, LabelType::Action),
+                args: args.clone(),
+            })
+            .collect();
+
+        // A command may have blocks without a definition, or the reverse, if the machine
+        // was not compiled from a policy.
+        let command_names: BTreeSet<&str> = command_defs
+            .keys()
+            .map(String::as_str)
+            .chain(labels.keys().filter_map(|label| match label.ltype {
+                LabelType::CommandPolicy
+                | LabelType::CommandRecall
+                | LabelType::CommandSeal
+                | LabelType::CommandOpen => Some(label.name.as_str()),
+                _ => None,
+            }))
+            .collect();
+        let commands = command_names
+            .into_iter()
+            .map(|name| CommandEntry {
+                name: String::from(name),
+                policy: addr(name, LabelType::CommandPolicy),
+                recall: addr(name, LabelType::CommandRecall),
+                seal: addr(name, LabelType::CommandSeal),
+                open: addr(name, LabelType::CommandOpen),
+                fields: command_defs.get(name).cloned(),
+            })
+            .collect();
+
+        Self { actions, commands }
+    }
+
+    pub fn action_id(&self, name: &str) -> Option<ActionId> {
+        self.actions
+            .binary_search_by(|entry| entry.name.as_str().cmp(name))
+            .ok()
+            .map(ActionId)
+    }
+
+    pub fn command_id(&self, name: &str) -> Option<CommandId> {
+        self.commands
+            .binary_search_by(|entry| entry.name.as_str().cmp(name))
+            .ok()
+            .map(CommandId)
+    }
+
+    pub fn action(&self, id: ActionId) -> Option<&ActionEntry> {
+        self.actions.get(id.0)
+    }
+
+    pub fn command(&self, id: CommandId) -> Option<&CommandEntry> {
+        self.commands.get(id.0)
+    }
+}
@@ -1,12 +1,16 @@
 extern crate alloc;
 
-use alloc::{borrow::ToOwned, string::String};
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    vec::Vec,
+};
 use core::{convert::Infallible, fmt};
 
-use aranya_policy_module::{CodeMap, Label, ValueConversionError};
+use aranya_policy_module::{CodeMap, ExitReason, Label, LabelType, ValueConversionError};
 use buggy::Bug;
 
-use crate::io::MachineIOError;
+use crate::{io::MachineIOError, CommandContext};
 
 /// Possible machine errors.
 // TODO(chip): These should be elaborated with additional data, and/or
@@ -19,6 +23,36 @@ pub enum MachineErrorType {
     /// Stack overflow - an operation tried to push a value onto a full
     /// stack.
     StackOverflow,
+    /// Too many definitions - defining a name would exceed
+    /// `Limits::max_definitions`. Parameter is the limit.
+    TooManyDefinitions(usize),
+    /// Struct too large - a serialized command struct would exceed
+    /// `Limits::max_struct_size`.
+    StructTooLarge {
+        /// Size of the serialized struct, in bytes
+        size: usize,
+        /// The limit, in bytes
+        max: usize,
+    },
+    /// Query too large - a query, count, or `map` would traverse more than
+    /// `Limits::max_query_results` facts. Parameter is the limit.
+    QueryTooLarge(usize),
+    /// Fact too large - the serialized values of a created or updated fact would exceed
+    /// `Limits::max_fact_size`.
+    FactTooLarge {
+        /// Size of the serialized values, in bytes
+        size: usize,
+        /// The limit, in bytes
+        max: usize,
+    },
+    /// Out of memory - the values on the stack and defined locals would use more than
+    /// `Limits::max_memory`.
+    OutOfMemory {
+        /// Approximate memory in use, in bytes
+        used: usize,
+        /// The limit, in bytes
+        max: usize,
+    },
     /// Name already defined - an attempt was made to define a name
     /// that was already defined. Parameter is the name.
     AlreadyDefined(String),
@@ -66,10 +100,31 @@ pub enum MachineErrorType {
     CallStack,
     /// IO Error - Some machine I/O operation caused an error
     IO(MachineIOError),
+    /// Out of gas - Execution used up the gas limit set with
+    /// `RunState::set_gas_limit()`.
+    OutOfGas,
+    /// Cancelled - the `CancellationToken` in the command context was cancelled.
+    Cancelled,
+    /// Check failed - a `check` rejected the command or action, rather than the policy or
+    /// VM failing. Parameter is the reason the check gave, if any. Created with
+    /// `RunState::exit_error()` to report where it happened.
+    CheckFailed(Option<String>),
+    /// Execution exited with a panic. Created with `RunState::exit_error()` to report
+    /// where it happened.
+    Exited(ExitReason),
     /// FFI module name not found.
     FfiModuleNotDefined(usize),
     /// FFI module was found, but the procedure index is invalid.
     FfiProcedureNotDefined(String, usize),
+    /// An FFI procedure returned an error.
+    FfiFailed {
+        /// The index of the FFI module
+        module: usize,
+        /// The index of the procedure in the module
+        procedure: usize,
+        /// The error the procedure returned
+        reason: String,
+    },
     /// An implementation bug
     Bug(Bug),
     /// Unknown - every other possible problem
@@ -81,6 +136,21 @@ impl fmt::Display for MachineErrorType {
         match self {
             MachineErrorType::StackUnderflow => write!(f, "stack underflow"),
             MachineErrorType::StackOverflow => write!(f, "stack overflow"),
+            MachineErrorType::TooManyDefinitions(max) => {
+                write!(f, "more than {} names defined", max)
+            }
+            MachineErrorType::StructTooLarge { size, max } => {
+                write!(f, "struct of {} bytes exceeds limit of {} bytes", size, max)
+            }
+            MachineErrorType::QueryTooLarge(max) => {
+                write!(f, "query traverses more than {} facts", max)
+            }
+            MachineErrorType::FactTooLarge { size, max } => {
+                write!(f, "fact of {} bytes exceeds limit of {} bytes", size, max)
+            }
+            MachineErrorType::OutOfMemory { used, max } => {
+                write!(f, "{} bytes of values exceeds limit of {} bytes", used, max)
+            }
             MachineErrorType::AlreadyDefined(s) => write!(f, "name `{}` already defined", s),
             MachineErrorType::NotDefined(s) => write!(f, "name `{}` not defined", s),
             MachineErrorType::InvalidType { want, got, msg } => {
@@ -98,12 +168,30 @@ impl fmt::Display for MachineErrorType {
             MachineErrorType::InvalidInstruction => write!(f, "invalid instruction"),
             MachineErrorType::CallStack => write!(f, "call stack"),
             MachineErrorType::IO(e) => write!(f, "IO: {}", e),
+            MachineErrorType::OutOfGas => write!(f, "out of gas"),
+            MachineErrorType::Cancelled => write!(f, "execution cancelled"),
+            MachineErrorType::CheckFailed(None) => write!(f, "check failed"),
+            MachineErrorType::CheckFailed(Some(reason)) => {
+                write!(f, "check failed: \"{}\"", reason)
+            }
+            MachineErrorType::Exited(ExitReason::Normal) => write!(f, "exited normally"),
+            MachineErrorType::Exited(ExitReason::Check) => write!(f, "check failed"),
+            MachineErrorType::Exited(ExitReason::Panic) => write!(f, "panicked"),
             MachineErrorType::FfiModuleNotDefined(module) => {
                 write!(f, "FFI module not defined: {}", module)
             }
             MachineErrorType::FfiProcedureNotDefined(module, proc) => {
                 write!(f, "FFI proc {} not defined in module {}", proc, module)
             }
+            MachineErrorType::FfiFailed {
+                module,
+                procedure,
+                reason,
+            } => write!(
+                f,
+                "FFI proc {} in module {} failed: {}",
+                procedure, module, reason
+            ),
             MachineErrorType::Bug(bug) => write!(f, "Bug: {}", bug),
             MachineErrorType::Unknown(reason) => write!(f, "unknown error: {}", reason),
         }
@@ -152,6 +240,24 @@ impl From<ValueConversionError> for MachineErrorType {
     }
 }
 
+/// A function call which was in progress when an error occurred.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CallFrame {
+    /// The function which was called
+    pub function: Label,
+    /// Line and column of the call, if known
+    pub call_site: Option<(usize, usize)>,
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.function.ltype {
+            LabelType::Temporary => write!(f, "finish function `{}`", self.function.name),
+            _ => write!(f, "function `{}`", self.function.name),
+        }
+    }
+}
+
 /// The source location and text of an error
 #[derive(Debug, PartialEq)]
 struct MachineErrorSource {
@@ -159,6 +265,10 @@ struct MachineErrorSource {
     linecol: (usize, usize),
     /// The text of the error
     text: String,
+    /// The command or action being executed, e.g. "action `foo`"
+    context: String,
+    /// The functions being called, innermost first
+    backtrace: Vec<CallFrame>,
 }
 
 /// An error returned by [`Machine`][crate::machine::Machine].
@@ -183,15 +293,21 @@ impl MachineError {
         err_type: MachineErrorType,
         pc: usize,
         codemap: Option<&CodeMap>,
+        ctx: &CommandContext<'_>,
     ) -> Self {
         Self {
             err_type,
             source: None,
         }
-        .with_position(pc, codemap)
+        .with_position(pc, codemap, ctx)
     }
 
-    pub(crate) fn with_position(mut self, pc: usize, codemap: Option<&CodeMap>) -> Self {
+    pub(crate) fn with_position(
+        mut self,
+        pc: usize,
+        codemap: Option<&CodeMap>,
+        ctx: &CommandContext<'_>,
+    ) -> Self {
         if self.source.is_none() {
             if let Some(codemap) = codemap {
                 self.source =
@@ -201,21 +317,81 @@ impl MachineError {
                         .map(|span| MachineErrorSource {
                             linecol: span.start_linecol(),
                             text: span.as_str().to_owned(),
+                            context: ctx.to_string(),
+                            backtrace: Vec::new(),
                         });
             }
         }
         self
     }
+
+    /// Records the function calls in progress, if the error has a source location and
+    /// they have not been recorded yet.
+    pub(crate) fn with_backtrace<F>(mut self, backtrace: F) -> Self
+    where
+        F: FnOnce() -> Vec<CallFrame>,
+    {
+        if let Some(source) = &mut self.source {
+            if source.backtrace.is_empty() {
+                source.backtrace = backtrace();
+            }
+        }
+        self
+    }
+
+    /// Reports whether the error is a failed `check`, which rejects the command or action,
+    /// rather than a fault in the policy or the VM.
+    pub fn is_check_failure(&self) -> bool {
+        matches!(
+            self.err_type,
+            MachineErrorType::CheckFailed(_) | MachineErrorType::Exited(ExitReason::Check)
+        )
+    }
+
+    /// Returns the reason given by the failed `check`, if any.
+    pub fn check_reason(&self) -> Option<&str> {
+        match &self.err_type {
+            MachineErrorType::CheckFailed(reason) => reason.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the line and column in the policy where the error occurred, if the machine
+    /// has a code map.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        self.source.as_ref().map(|source| source.linecol)
+    }
+
+    /// Returns the policy source where the error occurred, such as the text of the failed
+    /// `check`, if the machine has a code map.
+    pub fn source_text(&self) -> Option<&str> {
+        self.source.as_ref().map(|source| source.text.as_str())
+    }
+
+    /// Returns the function calls which were in progress when the error occurred,
+    /// innermost first. Like the source location, these are only known if the machine has
+    /// a code map.
+    pub fn backtrace(&self) -> &[CallFrame] {
+        self.source
+            .as_ref()
+            .map_or(&[], |source| source.backtrace.as_slice())
+    }
 }
 
 impl fmt::Display for MachineError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.source {
-            Some(source) => write!(
-                f,
-                "{} at line {} col {}:\n\t{}",
-                self.err_type, source.linecol.0, source.linecol.1, source.text
-            ),
+            Some(source) => {
+                write!(f, "{} in ", self.err_type)?;
+                for frame in &source.backtrace {
+                    write!(f, "{} called from ", frame)?;
+                }
+                write!(
+                    f,
+                    "{} at line {} col {}:\n\t{}",
+                    source.context, source.linecol.0, source.linecol.1, source.text
+                )
+            }
             None => write!(f, "{}", self.err_type),
         }
     }
@@ -1,7 +1,10 @@
 extern crate alloc;
 
-use alloc::string::String;
-use core::fmt;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    fmt,
+    ops::{Bound, RangeBounds},
+};
 
 use aranya_crypto::Id;
 use aranya_policy_module::{FactKey, FactKeyList, FactValue, FactValueList, KVPair};
@@ -35,12 +38,66 @@ impl fmt::Display for MachineIOError {
 
 impl core::error::Error for MachineIOError {}
 
+/// Returns the key by which query results are ordered.
+pub(crate) fn sort_key(keys: &[FactKey]) -> Vec<Vec<u8>> {
+    keys.iter().map(FactKey::to_sortable_bytes).collect()
+}
+
+/// A range of fact keys, for [`MachineIO::fact_query_range`].
+pub type FactKeyRange = (Bound<FactKey>, Bound<FactKey>);
+
 impl From<MachineIOError> for MachineError {
     fn from(value: MachineIOError) -> Self {
         MachineError::new(MachineErrorType::IO(value))
     }
 }
 
+/// An effect emitted by a command's policy, as delivered to an [`EffectSink`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmittedEffect {
+    /// The name of the effect
+    pub name: String,
+    /// The effect's fields
+    pub fields: Vec<KVPair>,
+    /// The ID of the command which emitted it
+    pub command: Id,
+    /// Whether it was emitted by the command's recall block
+    pub recalled: bool,
+}
+
+/// A callback set with [`RunState::set_effect_sink()`](crate::RunState::set_effect_sink),
+/// which receives each effect as soon as it is emitted instead of [`MachineIO::effect`].
+pub type EffectSink<'a> = &'a mut dyn FnMut(EmittedEffect);
+
+/// Intercepts the fact accesses of a running policy. Set it with
+/// [`RunState::set_fact_hook()`](crate::RunState::set_fact_hook).
+///
+/// A hook sees every fact operation without changing the [`MachineIO`] implementation, so
+/// hosts can use it to cache reads, audit access, or mirror writes elsewhere. Every method
+/// does nothing by default.
+pub trait FactHook {
+    /// Called before the facts named `name` whose keys begin with `keys` are queried.
+    ///
+    /// Returning `Some` answers the query with the given facts instead of reading them
+    /// from I/O, for example from a cache. They must be every such fact, in any order.
+    fn query(
+        &mut self,
+        _name: &str,
+        _keys: &[FactKey],
+    ) -> Option<Vec<(FactKeyList, FactValueList)>> {
+        None
+    }
+
+    /// Called with each fact read from I/O by a query.
+    fn read(&mut self, _name: &str, _keys: &[FactKey], _values: &[FactValue]) {}
+
+    /// Called after a fact is inserted through I/O.
+    fn insert(&mut self, _name: &str, _keys: &[FactKey], _values: &[FactValue]) {}
+
+    /// Called after a fact is deleted through I/O.
+    fn delete(&mut self, _name: &str, _keys: &[FactKey]) {}
+}
+
 /// The part of a `Machine` that performs I/O.
 pub trait MachineIO<S>
 where
@@ -65,12 +122,91 @@ where
     ) -> Result<(), MachineIOError>;
 
     /// Query a fact
+    ///
+    /// Results may be returned in any order. The VM only reads them through
+    /// [`fact_query_page`](Self::fact_query_page), which orders them by
+    /// [`FactKey::to_sortable_bytes`], so that every peer sees them in the same order.
     fn fact_query(
         &self,
         name: String,
         key: impl IntoIterator<Item = FactKey>,
     ) -> Result<Self::QueryIterator, MachineIOError>;
 
+    /// Query one page of facts, in key order.
+    ///
+    /// Returns up to `limit` facts which match `key` and whose keys sort after `after`,
+    /// ordered by [`FactKey::to_sortable_bytes`]. Passing the keys of the last fact of a
+    /// page as `after` returns the next page, so a caller only holds one page at a time.
+    /// See [`FactCursor`](crate::FactCursor).
+    ///
+    /// The default implementation reads every result of [`fact_query`](Self::fact_query)
+    /// for each page while keeping only the page, so reading `n` facts costs `n` reads
+    /// per page. Implementations with storage ordered by key should override it to start
+    /// reading at `after` and stop once the page is full.
+    fn fact_query_page(
+        &self,
+        name: String,
+        key: impl IntoIterator<Item = FactKey>,
+        after: Option<&[FactKey]>,
+        limit: usize,
+    ) -> Result<Vec<(FactKeyList, FactValueList)>, MachineIOError> {
+        let after = after.map(sort_key);
+        let mut page = BTreeMap::new();
+        for result in self.fact_query(name, key)? {
+            let (keys, values) = result?;
+            let sort = sort_key(&keys);
+            if after.as_ref().is_some_and(|after| sort <= *after) {
+                continue;
+            }
+            page.insert(sort, (keys, values));
+            if page.len() > limit {
+                page.pop_last();
+            }
+        }
+        Ok(page.into_values().collect())
+    }
+
+    /// Query a range of facts, in key order.
+    ///
+    /// Returns up to `limit` facts whose keys begin with `prefix` and whose next key is
+    /// within `range`, ordered by [`FactKey::to_sortable_bytes`]. Keys are compared by
+    /// that encoding too, so the bounds should have the same identifier and type as the
+    /// key they bound. For example, given `fact Device[user id, n int]=>{}`, a prefix of
+    /// one `user` and a range of `n` from 10 to 20 returns that user's devices 10 to 20.
+    ///
+    /// The default implementation reads every result of [`fact_query`](Self::fact_query).
+    /// Implementations with storage ordered by key may override it to read only the
+    /// range.
+    fn fact_query_range(
+        &self,
+        name: String,
+        prefix: impl IntoIterator<Item = FactKey>,
+        range: FactKeyRange,
+        limit: usize,
+    ) -> Result<Vec<(FactKeyList, FactValueList)>, MachineIOError> {
+        let prefix: FactKeyList = prefix.into_iter().collect();
+        let depth = prefix.len();
+        let range = (
+            range.0.as_ref().map(FactKey::to_sortable_bytes),
+            range.1.as_ref().map(FactKey::to_sortable_bytes),
+        );
+        let mut page = BTreeMap::new();
+        for result in self.fact_query(name, prefix)? {
+            let (keys, values) = result?;
+            let Some(next) = keys.get(depth) else {
+                continue;
+            };
+            if !range.contains(&next.to_sortable_bytes()) {
+                continue;
+            }
+            page.insert(sort_key(&keys), (keys, values));
+            if page.len() > limit {
+                page.pop_last();
+            }
+        }
+        Ok(page.into_values().collect())
+    }
+
     /// Publish a command
     fn publish(&mut self, name: String, fields: impl IntoIterator<Item = KVPair>);
 
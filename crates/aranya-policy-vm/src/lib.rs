@@ -5,20 +5,41 @@
 #![cfg_attr(not(any(test, doctest, feature = "std")), no_std)]
 #![warn(missing_docs)]
 
+mod cost;
+#[cfg(feature = "coverage")]
+mod coverage;
+mod cursor;
 mod data;
+mod debugger;
 mod derive;
+mod disassemble;
+mod dispatch;
 mod error;
 pub mod ffi;
 mod io;
+mod limits;
+mod linker;
 mod machine;
+mod profile;
 mod scope;
+mod snapshot;
 mod stack;
 mod tests;
 
 pub use aranya_policy_ast as ast;
 pub use aranya_policy_module::*;
+#[cfg(feature = "coverage")]
+pub use coverage::*;
+pub use cursor::FactCursor;
 pub use data::*;
+pub use debugger::*;
+pub use disassemble::*;
+pub use dispatch::{ActionId, CommandId};
 pub use error::*;
 pub use io::*;
+pub use limits::*;
+pub use linker::*;
 pub use machine::*;
+pub use profile::*;
+pub use snapshot::*;
 pub use stack::*;
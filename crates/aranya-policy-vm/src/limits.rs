@@ -0,0 +1,43 @@
+/// Bounds on the resources used while running a policy, set with
+/// [`Machine::limits`](crate::Machine::limits). Exceeding one fails execution with an error
+/// instead of aborting or allocating without bound.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The most values the stack may hold. Exceeding it is a
+    /// [`MachineErrorType::StackOverflow`](crate::MachineErrorType::StackOverflow).
+    pub max_stack_depth: usize,
+    /// The most named values which may be defined at once, across every function and block.
+    /// Globals are not counted.
+    pub max_definitions: usize,
+    /// The largest serialized command struct, in bytes, which may be produced by
+    /// `serialize()` or read by `deserialize()`.
+    pub max_struct_size: usize,
+    /// The most facts which a single query, count, or `map` may traverse, whether or not
+    /// they match.
+    pub max_query_results: usize,
+    /// The largest serialized fact values, in bytes, which may be stored by `create` or
+    /// `update`.
+    pub max_fact_size: usize,
+    /// The most memory, in bytes, which values on the stack and defined locals may use
+    /// together. Sizes are approximate; see
+    /// [`Value::heap_size()`](aranya_policy_module::Value::heap_size).
+    pub max_memory: usize,
+}
+
+impl Limits {
+    /// The limits used unless others are set.
+    pub const DEFAULT: Limits = Limits {
+        max_stack_depth: 100,
+        max_definitions: 1000,
+        max_struct_size: 1 << 20,
+        max_query_results: 1 << 16,
+        max_fact_size: 1 << 16,
+        max_memory: 1 << 24,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
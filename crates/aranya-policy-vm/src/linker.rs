@@ -0,0 +1,263 @@
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+
+use aranya_policy_module::{Instruction, Label, Module, Target, UnsupportedVersion};
+use buggy::{Bug, BugExt};
+
+use crate::Machine;
+
+/// Separates a namespace from the name of a linked command, action, fact, or function.
+pub const NAMESPACE_SEPARATOR: &str = "::";
+
+/// Returns the name given to `name` when its module is linked under `namespace`.
+pub fn namespaced(namespace: &str, name: &str) -> String {
+    format!("{namespace}{NAMESPACE_SEPARATOR}{name}")
+}
+
+/// Errors that can occur while linking modules.
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    /// The namespace is not a valid identifier
+    InvalidNamespace(String),
+    /// A module has already been added under this namespace
+    DuplicateNamespace(String),
+    /// Two modules define a struct or effect by this name with different fields
+    StructCollision(String),
+    /// Two modules define a global value by this name with different values
+    GlobalCollision(String),
+    /// A module contains a branch target which has not been resolved
+    UnresolvedTarget(Label),
+    /// A module has an unsupported version
+    UnsupportedVersion(UnsupportedVersion),
+    /// An implementation bug
+    Bug(Bug),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNamespace(s) => write!(f, "invalid namespace `{s}`"),
+            Self::DuplicateNamespace(s) => write!(f, "namespace `{s}` is already in use"),
+            Self::StructCollision(s) => write!(f, "struct `{s}` is defined differently"),
+            Self::GlobalCollision(s) => write!(f, "global `{s}` is defined differently"),
+            Self::UnresolvedTarget(l) => write!(f, "unresolved target {l}"),
+            Self::UnsupportedVersion(e) => write!(f, "{e}"),
+            Self::Bug(bug) => write!(f, "bug: {bug}"),
+        }
+    }
+}
+
+impl core::error::Error for LinkError {}
+
+impl From<UnsupportedVersion> for LinkError {
+    fn from(value: UnsupportedVersion) -> Self {
+        LinkError::UnsupportedVersion(value)
+    }
+}
+
+impl From<Bug> for LinkError {
+    fn from(value: Bug) -> Self {
+        LinkError::Bug(value)
+    }
+}
+
+/// Links several compiled policy modules into a single [`Machine`].
+///
+/// Each module is added under a namespace, and its commands, actions, facts, and functions
+/// are renamed with [`namespaced`], so `Foo` in namespace `teams` becomes `teams::Foo`.
+/// Structs, effects, and global values are shared by all modules, and it is an error for two
+/// modules to define them differently.
+///
+/// FFI calls are linked by index, so every module must be compiled against the same FFI
+/// modules, in the same order. Source code maps and policy fingerprints are not preserved.
+///
+/// ```ignore
+/// let mut linker = Linker::new();
+/// linker.add("teams", teams_module)?;
+/// linker.add("chat", chat_module)?;
+/// let machine = linker.link();
+/// machine.call_action("chat::send", args, &mut io, &ctx)?;
+/// ```
+#[derive(Debug)]
+pub struct Linker {
+    machine: Machine,
+    namespaces: BTreeSet<String>,
+}
+
+impl Linker {
+    /// Creates a `Linker` with no modules.
+    pub fn new() -> Self {
+        Linker {
+            machine: Machine::new([]),
+            namespaces: BTreeSet::new(),
+        }
+    }
+
+    /// Adds a module under `namespace`. If this fails, the linker is left unchanged.
+    pub fn add(&mut self, namespace: &str, module: Module) -> Result<(), LinkError> {
+        if !is_valid_namespace(namespace) {
+            return Err(LinkError::InvalidNamespace(namespace.to_string()));
+        }
+        if self.namespaces.contains(namespace) {
+            return Err(LinkError::DuplicateNamespace(namespace.to_string()));
+        }
+
+        let m = Machine::from_module(module)?;
+
+        // Commands and facts are namespaced, along with the structs defined for them.
+        let renamed: BTreeSet<String> = m
+            .command_defs
+            .keys()
+            .chain(m.fact_defs.keys())
+            .cloned()
+            .collect();
+        let rename = |name: String| {
+            if renamed.contains(&name) {
+                namespaced(namespace, &name)
+            } else {
+                name
+            }
+        };
+
+        for (name, fields) in m.struct_defs.iter() {
+            if renamed.contains(name) {
+                continue;
+            }
+            if self
+                .machine
+                .struct_defs
+                .get(name)
+                .is_some_and(|existing| existing != fields)
+            {
+                return Err(LinkError::StructCollision(name.clone()));
+            }
+        }
+        for (name, value) in m.globals.iter() {
+            if self
+                .machine
+                .globals
+                .get(name)
+                .is_some_and(|existing| existing != value)
+            {
+                return Err(LinkError::GlobalCollision(name.clone()));
+            }
+        }
+
+        let base = self.machine.progmem.len();
+        let progmem = m
+            .progmem
+            .iter()
+            .map(|i| relocate(i.clone(), base, &rename))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut labels = BTreeMap::new();
+        for (label, addr) in m.labels.iter() {
+            let addr = addr.checked_add(base).assume("address must not overflow")?;
+            labels.insert(
+                Label::new(&namespaced(namespace, &label.name), label.ltype.clone()),
+                addr,
+            );
+        }
+
+        // Nothing below can fail, so the module is added completely or not at all.
+        let machine = &mut self.machine;
+        machine.progmem = machine.progmem.iter().cloned().chain(progmem).collect();
+        Arc::make_mut(&mut machine.labels).extend(labels);
+        Arc::make_mut(&mut machine.action_defs).extend(
+            Arc::unwrap_or_clone(m.action_defs)
+                .into_iter()
+                .map(|(name, args)| (namespaced(namespace, &name), args)),
+        );
+        Arc::make_mut(&mut machine.command_defs).extend(
+            Arc::unwrap_or_clone(m.command_defs)
+                .into_iter()
+                .map(|(n, d)| (rename(n), d)),
+        );
+        Arc::make_mut(&mut machine.command_attributes).extend(
+            Arc::unwrap_or_clone(m.command_attributes)
+                .into_iter()
+                .map(|(n, a)| (rename(n), a)),
+        );
+        Arc::make_mut(&mut machine.fact_defs).extend(
+            Arc::unwrap_or_clone(m.fact_defs)
+                .into_iter()
+                .map(|(name, mut def)| {
+                    def.identifier = namespaced(namespace, &def.identifier);
+                    (rename(name), def)
+                }),
+        );
+        let struct_defs = Arc::make_mut(&mut machine.struct_defs);
+        for (name, fields) in Arc::unwrap_or_clone(m.struct_defs) {
+            struct_defs.entry(rename(name)).or_insert(fields);
+        }
+        let effect_defs = Arc::make_mut(&mut machine.effect_defs);
+        for (name, fields) in Arc::unwrap_or_clone(m.effect_defs) {
+            effect_defs.entry(rename(name)).or_insert(fields);
+        }
+        Arc::make_mut(&mut machine.globals).extend(Arc::unwrap_or_clone(m.globals));
+        self.namespaces.insert(namespace.to_string());
+
+        Ok(())
+    }
+
+    /// Consumes the linker to create a [`Machine`] containing every added module.
+    pub fn link(self) -> Machine {
+        let mut machine = self.machine;
+        machine.rebuild_dispatch_table();
+        machine
+    }
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A namespace must be a non-empty identifier.
+fn is_valid_namespace(namespace: &str) -> bool {
+    let mut chars = namespace.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Moves an instruction from a module to `base` in the linked program, renaming the commands
+/// and facts it refers to.
+fn relocate<F>(i: Instruction, base: usize, rename: &F) -> Result<Instruction, LinkError>
+where
+    F: Fn(String) -> String,
+{
+    Ok(match i {
+        Instruction::Jump(t) => Instruction::Jump(relocate_target(t, base)?),
+        Instruction::Branch(t) => Instruction::Branch(relocate_target(t, base)?),
+        Instruction::Call(t) => Instruction::Call(relocate_target(t, base)?),
+        Instruction::JumpTable(first, targets) => Instruction::JumpTable(
+            first,
+            targets
+                .into_iter()
+                .map(|t| relocate_target(t, base))
+                .collect::<Result<_, _>>()?,
+        ),
+        Instruction::StructNew(name) => Instruction::StructNew(rename(name.into()).into()),
+        Instruction::FactNew(name) => Instruction::FactNew(rename(name.into()).into()),
+        i => i,
+    })
+}
+
+fn relocate_target(t: Target, base: usize) -> Result<Target, LinkError> {
+    match t {
+        Target::Resolved(addr) => Ok(Target::Resolved(
+            addr.checked_add(base).assume("address must not overflow")?,
+        )),
+        Target::Unresolved(label) => Err(LinkError::UnresolvedTarget(label)),
+    }
+}
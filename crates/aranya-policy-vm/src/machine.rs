@@ -4,6 +4,7 @@ use alloc::{
     borrow::ToOwned,
     collections::BTreeMap,
     string::{String, ToString},
+    sync::Arc,
     vec,
     vec::Vec,
 };
@@ -11,23 +12,24 @@ use core::fmt::{self, Display};
 
 use aranya_policy_ast as ast;
 use aranya_policy_module::{
-    CodeMap, ExitReason, Fact, FactKey, FactValue, HashableValue, Instruction, KVPair, Label,
-    LabelType, Module, ModuleData, ModuleV0, Struct, Target, TryAsMut, UnsupportedVersion, Value,
-    ValueConversionError,
+    Bytes, CodeMap, ExitReason, Fact, FactKey, FactKeyList, FactValue, FactValueList, Fingerprint,
+    HashableValue, Instruction, Interner, KVPair, Label, LabelType, Meta, Module, ModuleData,
+    ModuleV1, Struct, Target, TryAsMut, UnsupportedVersion, Value, ValueConversionError,
 };
 use buggy::BugExt;
-use heapless::Vec as HVec;
 
+#[cfg(feature = "coverage")]
+use crate::Coverage;
 use crate::{
-    error::{MachineError, MachineErrorType},
-    io::MachineIO,
+    cursor::{FactCursor, PagedQuery},
+    dispatch::{ActionId, CommandId, DispatchTable},
+    error::{CallFrame, MachineError, MachineErrorType},
+    io::{EffectSink, EmittedEffect, FactHook, MachineIO},
     scope::ScopeManager,
     stack::Stack,
-    CommandContext, OpenContext, SealContext,
+    CommandContext, FunctionContext, Limits, OpenContext, Profile, SealContext, Snapshot,
 };
 
-const STACK_SIZE: usize = 100;
-
 /// Compares a fact's keys and values to its schema.
 /// Bind values are omitted from keys/values, so we only compare the given keys/values. This allows us to do partial matches.
 fn validate_fact_schema(fact: &Fact, schema: &ast::FactDefinition) -> bool {
@@ -90,6 +92,32 @@ fn fact_match(query: &Fact, keys: &[FactKey], values: &[FactValue]) -> bool {
     true
 }
 
+/// Reborrows a fact hook for a shorter lifetime.
+fn reborrow<'s>(hook: &'s mut Option<&mut dyn FactHook>) -> Option<&'s mut dyn FactHook> {
+    match hook {
+        Some(hook) => Some(&mut **hook),
+        None => None,
+    }
+}
+
+/// Makes every occurrence of an identifier in `progmem` share one allocation, so that
+/// cloning an instruction only copies a pointer, and names can be compared by pointer.
+///
+/// Identifiers are still strings rather than indices into a table, and facts and structs
+/// built by the program hold their own copies of their names.
+fn intern_identifiers(progmem: &mut [Instruction]) {
+    let mut interner = Interner::new();
+    for ident in progmem.iter_mut().filter_map(Instruction::identifier_mut) {
+        interner.intern(ident);
+    }
+}
+
+#[cfg(feature = "threaded")]
+mod threaded;
+
+/// The most facts a `map` query reads from storage at once.
+const QUERY_PAGE_SIZE: usize = 64;
+
 /// Status of machine execution after stepping through each instruction.
 ///
 /// These are expected states entered after executing instructions, as opposed to MachineErrors,
@@ -117,26 +145,41 @@ impl Display for MachineStatus {
 /// This contains the static data for the VM - instructions, entry points, schemas, globally scoped
 /// static values, and optionally a mapping between instructions and source code locations. For the
 /// VM's runtime data, see [`create_run_state()`](Self::create_run_state) and [`RunState`].
+///
+/// The static data is reference-counted, so cloning a `Machine` is cheap and clones share
+/// it. Use [`Arc::make_mut()`] to modify it, and then
+/// [`rebuild_dispatch_table()`](Self::rebuild_dispatch_table) if the entry points changed.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Machine {
     /// The program memory
-    pub progmem: Vec<Instruction>,
+    pub progmem: Arc<[Instruction]>,
     /// Mapping of Label names to addresses
-    pub labels: BTreeMap<Label, usize>,
+    pub labels: Arc<BTreeMap<Label, usize>>,
     /// Action definitions
-    pub action_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
+    pub action_defs: Arc<BTreeMap<String, Vec<ast::FieldDefinition>>>,
     /// Command definitions
-    pub command_defs: BTreeMap<String, BTreeMap<String, ast::VType>>,
+    pub command_defs: Arc<BTreeMap<String, BTreeMap<String, ast::VType>>>,
     /// Fact schemas
-    pub fact_defs: BTreeMap<String, ast::FactDefinition>,
+    pub fact_defs: Arc<BTreeMap<String, ast::FactDefinition>>,
     /// Struct schemas
-    pub struct_defs: BTreeMap<String, Vec<ast::FieldDefinition>>,
+    pub struct_defs: Arc<BTreeMap<String, Vec<ast::FieldDefinition>>>,
+    /// Effect schemas, which are also in `struct_defs`
+    pub effect_defs: Arc<BTreeMap<String, Vec<ast::FieldDefinition>>>,
     /// Command attributes
-    pub command_attributes: BTreeMap<String, BTreeMap<String, Value>>,
+    pub command_attributes: Arc<BTreeMap<String, BTreeMap<String, Value>>>,
     /// Mapping between program instructions and original code
-    pub codemap: Option<CodeMap>,
+    pub codemap: Option<Arc<CodeMap>>,
     /// Globally scoped variables
-    pub globals: BTreeMap<String, Value>,
+    pub globals: Arc<BTreeMap<String, Value>>,
+    /// Fingerprint of the policy source, if known
+    pub fingerprint: Option<Fingerprint>,
+    /// Resource limits for each `RunState`. These are not part of the `Module`.
+    pub limits: Limits,
+    /// Actions and commands, indexed for calling
+    dispatch: Arc<DispatchTable>,
+    /// The opcode of each instruction, for threaded dispatch
+    #[cfg(feature = "threaded")]
+    opcodes: Arc<[threaded::Opcode]>,
 }
 
 impl Machine {
@@ -145,68 +188,191 @@ impl Machine {
     where
         I: IntoIterator<Item = Instruction>,
     {
+        let mut progmem = Vec::from_iter(instructions);
+        intern_identifiers(&mut progmem);
         Machine {
-            progmem: Vec::from_iter(instructions),
-            labels: BTreeMap::new(),
-            action_defs: BTreeMap::new(),
-            command_defs: BTreeMap::new(),
-            fact_defs: BTreeMap::new(),
-            struct_defs: BTreeMap::new(),
-            command_attributes: BTreeMap::new(),
+            #[cfg(feature = "threaded")]
+            opcodes: threaded::decode(&progmem),
+            progmem: progmem.into(),
+            labels: Arc::default(),
+            action_defs: Arc::default(),
+            command_defs: Arc::default(),
+            fact_defs: Arc::default(),
+            struct_defs: Arc::default(),
+            effect_defs: Arc::default(),
+            command_attributes: Arc::default(),
             codemap: None,
-            globals: BTreeMap::new(),
+            globals: Arc::default(),
+            fingerprint: None,
+            limits: Limits::DEFAULT,
+            dispatch: Arc::default(),
         }
     }
 
     /// Creates an empty `Machine` with a given codemap. Used by the compiler.
     pub fn from_codemap(codemap: CodeMap) -> Self {
         Machine {
-            progmem: vec![],
-            labels: BTreeMap::new(),
-            action_defs: BTreeMap::new(),
-            command_defs: BTreeMap::new(),
-            fact_defs: BTreeMap::new(),
-            struct_defs: BTreeMap::new(),
-            command_attributes: BTreeMap::new(),
-            codemap: Some(codemap),
-            globals: BTreeMap::new(),
+            progmem: Arc::new([]),
+            labels: Arc::default(),
+            action_defs: Arc::default(),
+            command_defs: Arc::default(),
+            fact_defs: Arc::default(),
+            struct_defs: Arc::default(),
+            effect_defs: Arc::default(),
+            command_attributes: Arc::default(),
+            codemap: Some(Arc::new(codemap)),
+            globals: Arc::default(),
+            fingerprint: None,
+            limits: Limits::DEFAULT,
+            dispatch: Arc::default(),
+            #[cfg(feature = "threaded")]
+            opcodes: Arc::new([]),
         }
     }
 
     /// Creates a `Machine` from a `Module`.
     pub fn from_module(m: Module) -> Result<Self, UnsupportedVersion> {
-        match m.data {
-            ModuleData::V0(m) => Ok(Self {
-                progmem: m.progmem.into(),
-                labels: m.labels,
-                action_defs: m.action_defs,
-                command_defs: m.command_defs,
-                fact_defs: m.fact_defs,
-                struct_defs: m.struct_defs,
-                command_attributes: m.command_attributes,
-                codemap: m.codemap,
-                globals: m.globals,
-            }),
-        }
+        let mut m = match m.data {
+            ModuleData::V0(m) => ModuleV1::from(m),
+            ModuleData::V1(m) => m,
+        };
+        Ok(Self {
+            dispatch: Arc::new(DispatchTable::new(
+                &m.labels,
+                &m.action_defs,
+                &m.command_defs,
+            )),
+            #[cfg(feature = "threaded")]
+            opcodes: threaded::decode(&m.progmem),
+            progmem: {
+                intern_identifiers(&mut m.progmem);
+                m.progmem.into()
+            },
+            labels: Arc::new(m.labels),
+            action_defs: Arc::new(m.action_defs),
+            command_defs: Arc::new(m.command_defs),
+            fact_defs: Arc::new(m.fact_defs),
+            struct_defs: Arc::new(m.struct_defs),
+            effect_defs: Arc::new(m.effect_defs),
+            command_attributes: Arc::new(m.command_attributes),
+            codemap: m.codemap.map(Arc::new),
+            globals: Arc::new(m.globals),
+            fingerprint: m.fingerprint,
+            limits: Limits::DEFAULT,
+        })
     }
 
-    /// Converts the `Machine` into a `Module`.
+    /// Converts the `Machine` into a `Module`. Data still shared with clones of the
+    /// `Machine` is copied.
     pub fn into_module(self) -> Module {
         Module {
-            data: ModuleData::V0(ModuleV0 {
-                progmem: self.progmem.into_boxed_slice(),
-                labels: self.labels,
-                action_defs: self.action_defs,
-                command_defs: self.command_defs,
-                fact_defs: self.fact_defs,
-                struct_defs: self.struct_defs,
-                command_attributes: self.command_attributes,
-                codemap: self.codemap,
-                globals: self.globals,
+            data: ModuleData::V1(ModuleV1 {
+                progmem: self.progmem.iter().cloned().collect(),
+                labels: Arc::unwrap_or_clone(self.labels),
+                action_defs: Arc::unwrap_or_clone(self.action_defs),
+                command_defs: Arc::unwrap_or_clone(self.command_defs),
+                fact_defs: Arc::unwrap_or_clone(self.fact_defs),
+                struct_defs: Arc::unwrap_or_clone(self.struct_defs),
+                effect_defs: Arc::unwrap_or_clone(self.effect_defs),
+                command_attributes: Arc::unwrap_or_clone(self.command_attributes),
+                codemap: self.codemap.map(Arc::unwrap_or_clone),
+                globals: Arc::unwrap_or_clone(self.globals),
+                fingerprint: self.fingerprint,
             }),
         }
     }
 
+    /// Rebuilds the index of actions and commands from `labels`, `action_defs`, and
+    /// `command_defs`. This must be called after changing those directly, or the changes
+    /// will not be seen when calling actions and commands. IDs from before the rebuild are
+    /// no longer valid.
+    ///
+    /// With the `threaded` feature, this also decodes `progmem` again for
+    /// [`RunState::run_threaded()`].
+    pub fn rebuild_dispatch_table(&mut self) {
+        self.dispatch = Arc::new(DispatchTable::new(
+            &self.labels,
+            &self.action_defs,
+            &self.command_defs,
+        ));
+        #[cfg(feature = "threaded")]
+        {
+            self.opcodes = threaded::decode(&self.progmem);
+        }
+    }
+
+    /// Finds an action by name, so that it can be called with
+    /// [`RunState::call_action_id()`] without looking it up again.
+    pub fn action_id(&self, name: &str) -> Option<ActionId> {
+        self.dispatch.action_id(name)
+    }
+
+    /// Finds a command by name, so that its blocks can be called with
+    /// [`RunState::call_command_policy_id()`] and
+    /// [`RunState::call_command_recall_id()`] without looking it up again.
+    pub fn command_id(&self, name: &str) -> Option<CommandId> {
+        self.dispatch.command_id(name)
+    }
+
+    /// Returns the name and parameters of each action, in name order.
+    pub fn actions(&self) -> impl Iterator<Item = (&str, &[ast::FieldDefinition])> {
+        self.action_defs
+            .iter()
+            .map(|(name, params)| (name.as_str(), params.as_slice()))
+    }
+
+    /// Returns the parameters of the action `name`.
+    pub fn action(&self, name: &str) -> Option<&[ast::FieldDefinition]> {
+        self.action_defs.get(name).map(Vec::as_slice)
+    }
+
+    /// Returns the name and fields of each command, in name order.
+    pub fn commands(&self) -> impl Iterator<Item = (&str, &BTreeMap<String, ast::VType>)> {
+        self.command_defs
+            .iter()
+            .map(|(name, fields)| (name.as_str(), fields))
+    }
+
+    /// Returns the fields of the command `name`.
+    pub fn command(&self, name: &str) -> Option<&BTreeMap<String, ast::VType>> {
+        self.command_defs.get(name)
+    }
+
+    /// Returns the name and fields of each effect, in name order.
+    pub fn effects(&self) -> impl Iterator<Item = (&str, &[ast::FieldDefinition])> {
+        self.effect_defs
+            .iter()
+            .map(|(name, fields)| (name.as_str(), fields.as_slice()))
+    }
+
+    /// Returns the fields of the effect `name`.
+    pub fn effect(&self, name: &str) -> Option<&[ast::FieldDefinition]> {
+        self.effect_defs.get(name).map(Vec::as_slice)
+    }
+
+    /// Returns the name and fields of each struct which is not an effect, in name order.
+    pub fn structs(&self) -> impl Iterator<Item = (&str, &[ast::FieldDefinition])> {
+        self.struct_defs
+            .iter()
+            .filter(|(name, _)| !self.effect_defs.contains_key(name.as_str()))
+            .map(|(name, fields)| (name.as_str(), fields.as_slice()))
+    }
+
+    /// Returns the fields of the struct or effect `name`.
+    pub fn struct_def(&self, name: &str) -> Option<&[ast::FieldDefinition]> {
+        self.struct_defs.get(name).map(Vec::as_slice)
+    }
+
+    /// Returns the schema of each fact, in name order.
+    pub fn facts(&self) -> impl Iterator<Item = &ast::FactDefinition> {
+        self.fact_defs.values()
+    }
+
+    /// Returns the schema of the fact `name`.
+    pub fn fact(&self, name: &str) -> Option<&ast::FactDefinition> {
+        self.fact_defs.get(name)
+    }
+
     /// Create a RunState associated with this Machine.
     pub fn create_run_state<'a, M>(
         &'a self,
@@ -251,6 +417,27 @@ impl Machine {
         let mut rs = self.create_run_state(io, ctx);
         rs.call_command_policy(name, this_data, envelope)
     }
+
+    /// Call a pure function and return its result. The function runs in a
+    /// [`CommandContext::Function`], so it cannot use FFI procedures which need a command.
+    pub fn call_function<Args, M>(
+        &self,
+        name: &str,
+        args: Args,
+        io: &mut M,
+    ) -> Result<Value, MachineError>
+    where
+        Args: IntoIterator,
+        Args::Item: Into<Value>,
+        M: MachineIO<MachineStack>,
+    {
+        let ctx = CommandContext::Function(FunctionContext {
+            name,
+            cancellation: None,
+        });
+        let mut rs = self.create_run_state(io, &ctx);
+        rs.call_function(name, args)
+    }
 }
 
 impl Display for Machine {
@@ -260,15 +447,15 @@ impl Display for Machine {
             writeln!(f, "  {:4}  {}", addr, instr)?;
         }
         writeln!(f, "Labels:")?;
-        for (k, v) in &self.labels {
+        for (k, v) in self.labels.iter() {
             writeln!(f, "  {}: {:?}", k, v)?;
         }
         writeln!(f, "Fact definitions:")?;
-        for (k, v) in &self.fact_defs {
+        for (k, v) in self.fact_defs.iter() {
             writeln!(f, "  {}: {:?}", k, v)?;
         }
         writeln!(f, "Struct definitions:")?;
-        for (k, v) in &self.struct_defs {
+        for (k, v) in self.struct_defs.iter() {
             writeln!(f, "  {}: {:?}", k, v)?;
         }
         Ok(())
@@ -296,10 +483,52 @@ pub struct RunState<'a, M: MachineIO<MachineStack>> {
     io: &'a mut M,
     /// Execution Context (actually used for more than Commands)
     ctx: &'a CommandContext<'a>,
-    // Cursors for `QueryStart` results
-    query_iter_stack: Vec<M::QueryIterator>,
+    // Cursors for `QueryStart` results, in key order
+    query_iter_stack: Vec<PagedQuery>,
+    /// Remaining gas, if execution is metered
+    gas: Option<u64>,
+    /// Callback invoked as execution proceeds
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer<M>>,
+    /// Host-defined instructions, by name
+    extensions: BTreeMap<String, Extension>,
+    /// Receives emitted effects in place of `io`, if set
+    effect_sink: Option<EffectSink<'a>>,
+    /// Intercepts fact accesses, if set
+    fact_hook: Option<&'a mut dyn FactHook>,
+    /// The reason given by the `check` which failed, if any
+    check_reason: Option<String>,
+    /// Execution counts, if profiling is enabled
+    profile: Option<Profile>,
+    /// Records executed instructions, if set
+    #[cfg(feature = "coverage")]
+    coverage: Option<&'a mut Coverage>,
+}
+
+/// A host-defined instruction, registered with [`RunState::register_extension()`] and run
+/// by [`Instruction::Extension`]. It pops its arguments from the stack and pushes its
+/// result, like a pure function.
+pub type Extension = fn(&mut MachineStack) -> Result<(), MachineError>;
+
+/// Something a [`Tracer`] is notified of.
+#[cfg(feature = "trace")]
+#[derive(Debug)]
+pub enum TraceEvent<'a> {
+    /// An instruction is about to be executed at the current PC.
+    Instruction(&'a Instruction),
+    /// An FFI procedure has returned, leaving its results on the stack.
+    FfiCall {
+        /// The index of the FFI module.
+        module: usize,
+        /// The index of the procedure within the module.
+        procedure: usize,
+    },
 }
 
+/// A callback set with [`RunState::set_tracer()`], for diagnosing policy behavior.
+#[cfg(feature = "trace")]
+pub type Tracer<M> = fn(&TraceEvent<'_>, &RunState<'_, M>);
+
 impl<'a, M> RunState<'a, M>
 where
     M: MachineIO<MachineStack>,
@@ -312,13 +541,24 @@ where
     ) -> RunState<'a, M> {
         RunState {
             machine,
-            scope: ScopeManager::new(&machine.globals),
-            stack: MachineStack(HVec::new()),
+            scope: ScopeManager::new(&machine.globals)
+                .with_max_definitions(machine.limits.max_definitions),
+            stack: MachineStack::with_max_depth(machine.limits.max_stack_depth),
             call_state: vec![],
             pc: 0,
             io,
             ctx,
             query_iter_stack: vec![],
+            gas: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            extensions: BTreeMap::new(),
+            effect_sink: None,
+            fact_hook: None,
+            check_reason: None,
+            profile: None,
+            #[cfg(feature = "coverage")]
+            coverage: None,
         }
     }
 
@@ -329,6 +569,128 @@ where
         self.ctx = ctx;
     }
 
+    /// Limits further execution to `gas` units of instruction [cost](Instruction::cost).
+    /// Execution fails with [`MachineErrorType::OutOfGas`] once it has been used up.
+    pub fn set_gas_limit(&mut self, gas: u64) {
+        self.gas = Some(gas);
+    }
+
+    /// Returns the remaining gas, if a limit was set with
+    /// [`set_gas_limit()`](Self::set_gas_limit).
+    pub fn gas_remaining(&self) -> Option<u64> {
+        self.gas
+    }
+
+    /// Returns the approximate memory, in bytes, used by the values on the stack and the
+    /// defined locals. Execution fails once this exceeds [`Limits::max_memory`].
+    pub fn memory_used(&self) -> usize {
+        self.stack
+            .heap_size()
+            .saturating_add(self.scope.heap_size())
+    }
+
+    /// Records each instruction executed from now on in `coverage`, which should have been
+    /// created for this state's machine.
+    #[cfg(feature = "coverage")]
+    pub fn set_coverage(&mut self, coverage: &'a mut Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    /// Calls `tracer` before each instruction is executed and after each FFI call returns.
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Tracer<M>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Notifies the tracer, if one is set.
+    #[cfg(feature = "trace")]
+    fn trace(&self, event: TraceEvent<'_>) {
+        if let Some(tracer) = self.tracer {
+            tracer(&event, self);
+        }
+    }
+
+    /// Delivers each effect to `sink` as soon as it is emitted, rather than to
+    /// [`MachineIO::effect`], so that the caller can process effects incrementally.
+    pub fn set_effect_sink(&mut self, sink: EffectSink<'a>) {
+        self.effect_sink = Some(sink);
+    }
+
+    /// Passes every fact access from now on through `hook`, which may answer queries in
+    /// place of [`MachineIO`] and is told of each fact read, inserted, and deleted.
+    pub fn set_fact_hook(&mut self, hook: &'a mut dyn FactHook) {
+        self.fact_hook = Some(hook);
+    }
+
+    /// Starts counting the instructions, FFI calls, and fact operations run from each
+    /// action or command label. The counts are returned by [`profile()`](Self::profile).
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
+    }
+
+    /// Returns the execution counts collected since profiling was enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Registers a host-defined instruction, which policies compiled with a matching
+    /// extension schema run with [`Instruction::Extension`]. Replaces any extension already
+    /// registered with the same name.
+    pub fn register_extension(&mut self, name: impl Into<String>, extension: Extension) {
+        self.extensions.insert(name.into(), extension);
+    }
+
+    /// Captures the execution state so that it can be resumed later with
+    /// [`restore()`](Self::restore).
+    ///
+    /// Fails if a query is in progress, since its results come from the I/O state.
+    pub fn snapshot(&self) -> Result<Snapshot, MachineError> {
+        if !self.query_iter_stack.is_empty() {
+            return Err(self.err(MachineErrorType::BadState(
+                "cannot snapshot while a query is in progress",
+            )));
+        }
+        Ok(Snapshot {
+            fingerprint: self.machine.fingerprint,
+            pc: self.pc,
+            stack: self.stack.values.clone(),
+            call_state: self.call_state.clone(),
+            locals: self.scope.frames().to_vec(),
+            gas: self.gas,
+        })
+    }
+
+    /// Replaces the execution state with one captured by [`snapshot()`](Self::snapshot),
+    /// so that [`run()`](Self::run) continues where the snapshot was taken.
+    ///
+    /// The snapshot must be from the same policy as this state's machine.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), MachineError> {
+        if snapshot.fingerprint != self.machine.fingerprint {
+            return Err(MachineError::new(MachineErrorType::BadState(
+                "snapshot is from a different policy",
+            )));
+        }
+        let len = self.machine.progmem.len();
+        if let Some(addr) = core::iter::once(&snapshot.pc)
+            .chain(&snapshot.call_state)
+            .find(|&&addr| addr >= len)
+        {
+            return Err(MachineError::new(MachineErrorType::InvalidAddress(
+                addr.to_string(),
+            )));
+        }
+        if snapshot.stack.len() > self.stack.max_depth {
+            return Err(MachineError::new(MachineErrorType::StackOverflow));
+        }
+        self.scope.set_frames(snapshot.locals)?;
+        self.stack.set_values(snapshot.stack);
+        self.call_state = snapshot.call_state;
+        self.pc = snapshot.pc;
+        self.gas = snapshot.gas;
+        self.query_iter_stack.clear();
+        Ok(())
+    }
+
     /// Returns a string describing the source code at the current PC,
     /// if available.
     pub fn source_location(&self) -> Option<String> {
@@ -354,7 +716,61 @@ where
     /// Internal function to produce a MachineError with location
     /// information.
     fn err(&self, err_type: MachineErrorType) -> MachineError {
-        MachineError::from_position(err_type, self.pc, self.machine.codemap.as_ref())
+        MachineError::from_position(err_type, self.pc, self.machine.codemap.as_deref(), self.ctx)
+            .with_backtrace(|| self.backtrace())
+    }
+
+    /// Adds the source location of the instruction at `pc`, the name of the command or
+    /// action being executed, and the functions being called to `err`, if it does not
+    /// already have a location.
+    pub(crate) fn locate(&self, err: MachineError, pc: usize) -> MachineError {
+        err.with_position(pc, self.machine.codemap.as_deref(), self.ctx)
+            .with_backtrace(|| self.backtrace())
+    }
+
+    /// Returns the function calls in progress, innermost first.
+    fn backtrace(&self) -> Vec<CallFrame> {
+        let codemap = self.machine.codemap.as_deref();
+        self.call_state
+            .iter()
+            .rev()
+            .filter_map(|&site| {
+                let Some(Instruction::Call(Target::Resolved(addr))) =
+                    self.machine.progmem.get(site)
+                else {
+                    return None;
+                };
+                // Functions have `Function` labels, and finish functions `Temporary` ones.
+                let function = [LabelType::Function, LabelType::Temporary]
+                    .into_iter()
+                    .find_map(|ltype| {
+                        self.machine
+                            .labels
+                            .iter()
+                            .find(|(label, a)| label.ltype == ltype && *a == addr)
+                    })?
+                    .0
+                    .clone();
+                let call_site = codemap
+                    .and_then(|codemap| codemap.span_from_instruction(site).ok())
+                    .map(|span| span.start_linecol());
+                Some(CallFrame {
+                    function,
+                    call_site,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns an error describing why execution exited with `reason`, including where in
+    /// the policy it happened. A failed `check` is reported as
+    /// [`MachineErrorType::CheckFailed`] with the reason it gave. This should be called
+    /// before the state is reused.
+    pub fn exit_error(&self, reason: ExitReason) -> MachineError {
+        match reason {
+            ExitReason::Check => self.err(MachineErrorType::CheckFailed(self.check_reason.clone())),
+            reason => self.err(MachineErrorType::Exited(reason)),
+        }
     }
 
     /// Reset the machine state - undefine all named values, empty the
@@ -362,6 +778,7 @@ where
     pub fn reset(&mut self) {
         self.scope.clear();
         self.stack.clear();
+        self.check_reason = None;
         self.pc = 0;
     }
 
@@ -370,6 +787,21 @@ where
         self.pc
     }
 
+    /// The machine being run.
+    pub(crate) fn machine(&self) -> &'a Machine {
+        self.machine
+    }
+
+    /// The named value definitions.
+    pub(crate) fn scope(&self) -> &ScopeManager<'a> {
+        &self.scope
+    }
+
+    /// The number of fact queries with results left to fetch.
+    pub(crate) fn query_depth(&self) -> usize {
+        self.query_iter_stack.len()
+    }
+
     /// Internal wrapper around [Stack::push] that translates
     /// [StackError] into [MachineError] with location information.
     fn ipush<V>(&mut self, value: V) -> Result<(), MachineError>
@@ -406,9 +838,9 @@ where
         // does). We can't do that inside the closure because peek()
         // takes a mutable reference to self.
         let pc = self.pc;
-        self.stack
-            .peek()
-            .map_err(|e| MachineError::from_position(e, pc, self.machine.codemap.as_ref()))
+        self.stack.peek().map_err(|e| {
+            MachineError::from_position(e, pc, self.machine.codemap.as_deref(), self.ctx)
+        })
     }
 
     /// Validate a struct against defined schema.
@@ -453,6 +885,24 @@ where
         // Clone the instruction so we don't take an immutable
         // reference to self while we manipulate the stack later.
         let instruction = self.machine.progmem[self.pc()].clone();
+        if self.ctx.is_cancelled() {
+            return Err(self.err(MachineErrorType::Cancelled));
+        }
+        if let Some(gas) = self.gas {
+            let remaining = gas
+                .checked_sub(instruction.cost())
+                .ok_or_else(|| self.err(MachineErrorType::OutOfGas))?;
+            self.gas = Some(remaining);
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(&instruction);
+        }
+        #[cfg(feature = "coverage")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(self.pc);
+        }
+        #[cfg(feature = "trace")]
+        self.trace(TraceEvent::Instruction(&instruction));
         match instruction {
             Instruction::Const(v) => {
                 self.ipush(v)?;
@@ -462,7 +912,7 @@ where
                 self.scope.set(key, value)?
             }
             Instruction::Get(key) => {
-                let value = self.scope.get(&key)?;
+                let value = self.scope.get(key.as_str())?;
                 self.ipush(value)?;
             }
             Instruction::Swap(d) => {
@@ -477,7 +927,7 @@ where
                 let index2 = index1
                     .checked_sub(d)
                     .ok_or(MachineErrorType::StackUnderflow)?;
-                self.stack.0.swap(index1, index2);
+                self.stack.swap(index1, index2);
             }
             Instruction::Dup(d) => {
                 let index = self
@@ -487,7 +937,7 @@ where
                     .ok_or(MachineErrorType::StackUnderflow)?
                     .checked_sub(1)
                     .ok_or(MachineErrorType::StackUnderflow)?;
-                let v = self.stack.0[index].clone();
+                let v = self.stack.values[index].clone();
                 self.ipush(v)?;
             }
             Instruction::Pop => {
@@ -522,6 +972,27 @@ where
                     }
                 }
             }
+            Instruction::JumpTable(base, targets) => {
+                let value = self.ipop_value()?;
+                let target = match value {
+                    Value::Int(v) => v
+                        .checked_sub(base)
+                        .and_then(|offset| usize::try_from(offset).ok())
+                        .and_then(|offset| targets.into_iter().nth(offset)),
+                    _ => None,
+                };
+                if let Some(t) = target {
+                    match t {
+                        Target::Unresolved(label) => {
+                            return Err(self.err(MachineErrorType::UnresolvedTarget(label)))
+                        }
+                        Target::Resolved(n) => {
+                            self.pc = n;
+                            return Ok(MachineStatus::Executing);
+                        }
+                    }
+                }
+            }
             Instruction::Next => todo!(),
             Instruction::Last => todo!(),
             Instruction::Call(t) => match t {
@@ -549,7 +1020,25 @@ where
                 self.scope.exit_function().map_err(|e| self.err(e))?;
             }
             Instruction::ExtCall(module, proc) => {
-                self.io.call(module, proc, &mut self.stack, self.ctx)?;
+                let result = self.io.call(module, proc, &mut self.stack, self.ctx);
+                // A procedure which was cancelled may have returned early with any result.
+                if self.ctx.is_cancelled() {
+                    return Err(self.err(MachineErrorType::Cancelled));
+                }
+                result?;
+                #[cfg(feature = "trace")]
+                self.trace(TraceEvent::FfiCall {
+                    module,
+                    procedure: proc,
+                });
+            }
+            Instruction::Extension(name) => {
+                let extension = *self.extensions.get(name.as_str()).ok_or_else(|| {
+                    self.err(MachineErrorType::NotDefined(alloc::format!(
+                        "extension `{name}`"
+                    )))
+                })?;
+                extension(&mut self.stack)?;
             }
             Instruction::Exit(reason) => return Ok(MachineStatus::Exited(reason)),
             Instruction::Add | Instruction::Sub => {
@@ -558,10 +1047,10 @@ where
                 let r = match instruction {
                     Instruction::Add => a
                         .checked_add(b)
-                        .ok_or(self.err(MachineErrorType::IntegerOverflow))?,
+                        .ok_or_else(|| self.err(MachineErrorType::IntegerOverflow))?,
                     Instruction::Sub => a
                         .checked_sub(b)
-                        .ok_or(self.err(MachineErrorType::IntegerOverflow))?,
+                        .ok_or_else(|| self.err(MachineErrorType::IntegerOverflow))?,
                     _ => unreachable!(),
                 };
                 self.ipush(r)?;
@@ -586,6 +1075,8 @@ where
                 let v = match instruction {
                     Instruction::Gt => match (&a, &b) {
                         (Value::Int(ia), Value::Int(ib)) => ia > ib,
+                        (Value::Float(fa), Value::Float(fb)) => fa > fb,
+                        (Value::Timestamp(ta), Value::Timestamp(tb)) => ta > tb,
                         _ => {
                             let a_type = a.type_name();
                             let b_type = b.type_name();
@@ -598,6 +1089,8 @@ where
                     },
                     Instruction::Lt => match (&a, &b) {
                         (Value::Int(ia), Value::Int(ib)) => ia < ib,
+                        (Value::Float(fa), Value::Float(fb)) => fa < fb,
+                        (Value::Timestamp(ta), Value::Timestamp(tb)) => ta < tb,
                         _ => {
                             let a_type = a.type_name();
                             let b_type = b.type_name();
@@ -617,22 +1110,25 @@ where
                 self.ipush(v)?;
             }
             Instruction::FactNew(name) => {
-                let fact = Fact::new(name);
+                let fact = Fact::new(name.into());
                 self.ipush(fact)?;
             }
             Instruction::FactKeySet(varname) => {
                 let v: HashableValue = self.ipop()?;
                 let f: &mut Fact = self.ipeek()?;
-                f.set_key(varname, v);
+                f.set_key(varname.into(), v);
             }
             Instruction::FactValueSet(varname) => {
                 let value = self.ipop_value()?;
                 let f: &mut Fact = self.ipeek()?;
-                f.set_value(varname, value);
+                f.set_value(varname.into(), value);
             }
             Instruction::StructNew(name) => {
                 let fields = BTreeMap::new();
-                self.ipush(Struct { name, fields })?;
+                self.ipush(Struct {
+                    name: name.into(),
+                    fields,
+                })?;
             }
             Instruction::StructSet(field_name) => {
                 let value = self.ipop_value()?;
@@ -645,17 +1141,16 @@ where
                     .get(&s.name)
                     .ok_or_else(|| self.err(MachineErrorType::InvalidSchema(s.name.clone())))?;
                 if !struct_def_fields.iter().any(|f| f.identifier == field_name) {
-                    return Err(self.err(MachineErrorType::InvalidStructMember(field_name)));
+                    return Err(self.err(MachineErrorType::InvalidStructMember(field_name.into())));
                 }
-                s.fields.insert(field_name, value);
+                s.fields.insert(field_name.into(), value);
                 self.ipush(s)?;
             }
             Instruction::StructGet(varname) => {
                 let mut s: Struct = self.ipop()?;
-                let v = s
-                    .fields
-                    .remove(&varname)
-                    .ok_or_else(|| self.err(MachineErrorType::InvalidStructMember(varname)))?;
+                let v = s.fields.remove(varname.as_str()).ok_or_else(|| {
+                    self.err(MachineErrorType::InvalidStructMember(varname.into()))
+                })?;
                 self.ipush(v)?;
             }
             Instruction::Publish => {
@@ -668,24 +1163,24 @@ where
             }
             Instruction::Create => {
                 let f: Fact = self.ipop()?;
-                self.io.fact_insert(f.name, f.keys, f.values)?;
+                self.check_fact_size(&f)?;
+                self.fact_insert(f)?;
             }
             Instruction::Delete => {
                 let f: Fact = self.ipop()?;
-                self.io.fact_delete(f.name, f.keys)?;
+                self.fact_delete(f.name, f.keys)?;
             }
             Instruction::Update => {
                 let fact_to: Fact = self.ipop()?;
                 let fact_from: Fact = self.ipop()?;
-                let replaced_fact = {
-                    let mut iter = self.io.fact_query(fact_from.name.clone(), fact_from.keys)?;
-                    iter.next().ok_or_else(|| {
-                        self.err(MachineErrorType::InvalidFact(fact_from.name.clone()))
-                    })??
-                };
-                self.io.fact_delete(fact_from.name, replaced_fact.0)?;
-                self.io
-                    .fact_insert(fact_to.name, fact_to.keys, fact_to.values)?;
+                self.check_fact_size(&fact_to)?;
+                let mut query = self.open_query(fact_from.name.clone(), fact_from.keys);
+                let replaced_fact = self.query_next(&mut query)?;
+                let replaced_fact = replaced_fact.ok_or_else(|| {
+                    self.err(MachineErrorType::InvalidFact(fact_from.name.clone()))
+                })?;
+                self.fact_delete(fact_from.name, replaced_fact.0)?;
+                self.fact_insert(fact_to)?;
             }
             Instruction::Emit => {
                 let s: Struct = self.ipop()?;
@@ -700,7 +1195,15 @@ where
                         )
                     }
                 };
-                self.io.effect(s.name, fields, command, recall);
+                match &mut self.effect_sink {
+                    Some(sink) => sink(EmittedEffect {
+                        name: s.name,
+                        fields: fields.collect(),
+                        command,
+                        recalled: recall,
+                    }),
+                    None => self.io.effect(s.name, fields, command, recall),
+                }
             }
             Instruction::Query => {
                 let qf: Fact = self.ipop()?;
@@ -708,23 +1211,16 @@ where
                 // Before we spend time fetching facts from storage, make sure the given fact literal is valid.
                 self.validate_fact_literal(&qf)?;
 
-                let result = {
-                    let mut iter = self.io.fact_query(qf.name.clone(), qf.keys.clone())?;
-                    // Find the first match, or the first error
-                    iter.find_map(|r| match r {
-                        Ok(f) => {
-                            if fact_match(&qf, &f.0, &f.1) {
-                                Some(Ok(f))
-                            } else {
-                                None
-                            }
-                        }
-                        Err(e) => Some(Err(e)),
-                    })
+                // Find the first match in key order, so that every peer finds the same one.
+                let mut query = self.open_query(qf.name.clone(), qf.keys.clone());
+                let result = loop {
+                    match self.query_next(&mut query)? {
+                        Some(f) if !fact_match(&qf, &f.0, &f.1) => continue,
+                        next => break next,
+                    }
                 };
                 match result {
-                    Some(r) => {
-                        let f = r?;
+                    Some(f) => {
                         let mut fields: Vec<KVPair> = vec![];
                         fields.append(&mut f.0.into_iter().map(|e| e.into()).collect());
                         fields.append(&mut f.1.into_iter().map(|e| e.into()).collect());
@@ -738,24 +1234,18 @@ where
                 let fact: Fact = self.ipop()?;
                 self.validate_fact_literal(&fact)?;
 
+                // Facts are read in key order, so the limit on reads is reached the same way
+                // on every peer.
+                let mut query = self.open_query(fact.name.clone(), fact.keys.clone());
                 let mut count = 0;
-                {
-                    let mut iter = self
-                        .io
-                        .fact_query(fact.name.to_owned(), fact.keys.to_owned())?;
-
-                    while count < limit {
-                        let Some(r) = iter.next() else { break };
-                        match r {
-                            Ok(f) => {
-                                if fact_match(&fact, &f.0, &f.1) {
-                                    count = count
-                                        .checked_add(1)
-                                        .assume("should be able to increment fact counter")?;
-                                }
-                            }
-                            Err(e) => return Err(self.err(MachineErrorType::IO(e))),
-                        }
+                while count < limit {
+                    let Some(f) = self.query_next(&mut query)? else {
+                        break;
+                    };
+                    if fact_match(&fact, &f.0, &f.1) {
+                        count = count
+                            .checked_add(1)
+                            .assume("should be able to increment fact counter")?;
                     }
                 }
 
@@ -764,90 +1254,75 @@ where
             Instruction::QueryStart => {
                 let fact: Fact = self.ipop()?;
                 self.validate_fact_literal(&fact)?;
-                let iter = self.io.fact_query(fact.name, fact.keys)?;
-                self.query_iter_stack.push(iter);
+                let query = self.open_query(fact.name, fact.keys);
+                self.query_iter_stack.push(query);
             }
             Instruction::QueryNext(ident) => {
                 // Fetch next fact from iterator
-                let iter = self.query_iter_stack.last_mut().ok_or_else(|| {
+                let mut query = self.query_iter_stack.pop().ok_or_else(|| {
                     MachineError::from_position(
                         MachineErrorType::BadState("QueryNext: no results"),
                         self.pc,
-                        self.machine.codemap.as_ref(),
+                        self.machine.codemap.as_deref(),
+                        self.ctx,
                     )
                 })?;
+                let next = self.query_next(&mut query)?;
                 // Update `as` variable value and push an end-of-results bool.
-                match iter.next() {
-                    Some(result) => {
-                        let (k, v) = result?;
+                match next {
+                    Some((k, v)) => {
                         let mut fields: Vec<KVPair> = vec![];
                         fields.append(&mut k.into_iter().map(|e| e.into()).collect());
                         fields.append(&mut v.into_iter().map(|e| e.into()).collect());
                         let s = Struct::new(&ident, &fields);
+                        self.query_iter_stack.push(query);
                         self.scope.set(ident, Value::Struct(s))?;
                         self.ipush(Value::Bool(false))?;
                     }
                     None => {
                         // When there are no more results, dispose of the iterator.
-                        self.query_iter_stack.pop();
                         self.ipush(Value::Bool(true))?;
                     }
                 }
             }
             Instruction::Serialize => {
                 let CommandContext::Seal(SealContext { name, .. }) = self.ctx else {
-                    return Err(MachineError::from_position(
-                        MachineErrorType::InvalidInstruction,
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    ));
+                    return Err(self.err(MachineErrorType::InvalidInstruction));
                 };
                 let command_struct: Struct = self.ipop()?;
                 if &command_struct.name != name {
-                    return Err(MachineError::from_position(
-                        MachineErrorType::InvalidInstruction,
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    ));
+                    return Err(self.err(MachineErrorType::InvalidInstruction));
                 }
                 let bytes = postcard::to_allocvec(&command_struct).map_err(|_| {
-                    MachineError::from_position(
-                        MachineErrorType::Unknown(String::from(
-                            "could not serialize command Struct",
-                        )),
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    )
+                    self.err(MachineErrorType::Unknown(String::from(
+                        "could not serialize command Struct",
+                    )))
                 })?;
+                self.check_struct_size(bytes.len())?;
                 self.ipush(bytes)?;
             }
             Instruction::Deserialize => {
                 let CommandContext::Open(OpenContext { name, .. }) = self.ctx else {
-                    return Err(MachineError::from_position(
-                        MachineErrorType::InvalidInstruction,
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    ));
+                    return Err(self.err(MachineErrorType::InvalidInstruction));
                 };
-                let bytes: Vec<u8> = self.ipop()?;
+                let bytes: Bytes = self.ipop()?;
+                self.check_struct_size(bytes.len())?;
                 let s: Struct = postcard::from_bytes(&bytes).map_err(|_| {
-                    MachineError::from_position(
-                        MachineErrorType::Unknown(String::from("could not deserialize Struct")),
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    )
+                    self.err(MachineErrorType::Unknown(String::from(
+                        "could not deserialize Struct",
+                    )))
                 })?;
                 if name != &s.name {
-                    return Err(MachineError::from_position(
-                        MachineErrorType::InvalidInstruction,
-                        self.pc,
-                        self.machine.codemap.as_ref(),
-                    ));
+                    return Err(self.err(MachineErrorType::InvalidInstruction));
                 }
                 self.ipush(s)?;
             }
+            Instruction::Meta(Meta::Check(reason)) => {
+                self.check_reason = Some(reason.clone());
+            }
             Instruction::Meta(_) => (),
         }
+        self.check_memory()?;
         self.pc = self.pc.checked_add(1).assume("self.pc + 1 must not wrap")?;
 
         Ok(MachineStatus::Executing)
@@ -858,10 +1333,7 @@ where
     /// with, or an error.
     pub fn run(&mut self) -> Result<ExitReason, MachineError> {
         loop {
-            match self
-                .step()
-                .map_err(|err| err.with_position(self.pc, self.machine.codemap.as_ref()))?
-            {
+            match self.step().map_err(|err| self.locate(err, self.pc))? {
                 MachineStatus::Executing => continue,
                 MachineStatus::Exited(reason) => return Ok(reason),
             };
@@ -876,6 +1348,9 @@ where
             .get(label)
             .ok_or_else(|| self.err(MachineErrorType::InvalidAddress(label.name.clone())))?;
         self.pc = *addr;
+        if let Some(profile) = &mut self.profile {
+            profile.enter(label);
+        }
         Ok(())
     }
 
@@ -886,14 +1361,34 @@ where
         label_type: LabelType,
         this_data: &Struct,
     ) -> Result<(), MachineError> {
-        self.setup_function(&Label::new(name, label_type))?;
+        let id = self
+            .machine
+            .command_id(name)
+            .ok_or_else(|| self.err(MachineErrorType::InvalidAddress(String::from(name))))?;
+        self.setup_command_id(id, label_type, this_data)
+    }
+
+    /// Set up machine state for a call to a block of the command found with
+    /// [`Machine::command_id()`].
+    pub fn setup_command_id(
+        &mut self,
+        id: CommandId,
+        label_type: LabelType,
+        this_data: &Struct,
+    ) -> Result<(), MachineError> {
+        let machine = self.machine;
+        let entry = machine
+            .dispatch
+            .command(id)
+            .ok_or_else(|| self.err(MachineErrorType::NotDefined(alloc::format!("{id:?}"))))?;
+        let name = entry.name.as_str();
+        self.enter(name, label_type.clone(), entry.addr(&label_type))?;
 
         // Verify 'this' arg matches command's fields
-        let command_def = self
-            .machine
-            .command_defs
-            .get(name)
-            .ok_or(self.err(MachineErrorType::NotDefined(String::from(name))))?;
+        let command_def = entry
+            .fields
+            .as_ref()
+            .ok_or_else(|| self.err(MachineErrorType::NotDefined(String::from(name))))?;
 
         if this_data.fields.len() != command_def.len() {
             return Err(self.err(MachineErrorType::Unknown(alloc::format!(
@@ -951,24 +1446,104 @@ where
         self.run()
     }
 
+    /// Like [`call_command_policy()`](Self::call_command_policy), for a command found with
+    /// [`Machine::command_id()`].
+    pub fn call_command_policy_id(
+        &mut self,
+        id: CommandId,
+        this_data: &Struct,
+        envelope: Struct,
+    ) -> Result<ExitReason, MachineError> {
+        self.setup_command_id(id, LabelType::CommandPolicy, this_data)?;
+        self.ipush(envelope)?;
+        self.run()
+    }
+
+    /// Like [`call_command_recall()`](Self::call_command_recall), for a command found with
+    /// [`Machine::command_id()`].
+    pub fn call_command_recall_id(
+        &mut self,
+        id: CommandId,
+        this_data: &Struct,
+        envelope: Struct,
+    ) -> Result<ExitReason, MachineError> {
+        self.setup_command_id(id, LabelType::CommandRecall, this_data)?;
+        self.ipush(envelope)?;
+        self.run()
+    }
+
     fn setup_function(&mut self, label: &Label) -> Result<(), MachineError> {
         self.set_pc_by_label(label)?;
         self.call_state.clear();
         self.scope.clear();
+        self.check_reason = None;
+
+        Ok(())
+    }
+
+    /// Like [`setup_function()`](Self::setup_function), for an entry point from the
+    /// dispatch table.
+    fn enter(
+        &mut self,
+        name: &str,
+        label_type: LabelType,
+        addr: Option<usize>,
+    ) -> Result<(), MachineError> {
+        self.pc =
+            addr.ok_or_else(|| self.err(MachineErrorType::InvalidAddress(String::from(name))))?;
+        if let Some(profile) = &mut self.profile {
+            profile.enter(&Label::new(name, label_type));
+        }
+        self.call_state.clear();
+        self.scope.clear();
+        self.check_reason = None;
 
         Ok(())
     }
 
+    /// Jumps to a block of the command `name`, without setting up `this`.
+    fn setup_command_block(
+        &mut self,
+        name: &str,
+        label_type: LabelType,
+    ) -> Result<(), MachineError> {
+        let machine = self.machine;
+        let addr = machine
+            .command_id(name)
+            .and_then(|id| machine.dispatch.command(id))
+            .and_then(|entry| entry.addr(&label_type));
+        self.enter(name, label_type, addr)
+    }
+
     /// Set up machine state for an action call.
     pub fn setup_action<Args>(&mut self, name: &str, args: Args) -> Result<(), MachineError>
     where
         Args: IntoIterator,
         Args::Item: Into<Value>,
     {
+        let id =
+            self.machine
+                .action_id(name)
+                .ok_or(MachineError::new(MachineErrorType::NotDefined(
+                    String::from(name),
+                )))?;
+        self.setup_action_id(id, args)
+    }
+
+    /// Set up machine state for a call to the action found with [`Machine::action_id()`].
+    pub fn setup_action_id<Args>(&mut self, id: ActionId, args: Args) -> Result<(), MachineError>
+    where
+        Args: IntoIterator,
+        Args::Item: Into<Value>,
+    {
+        let machine = self.machine;
+        let entry = machine.dispatch.action(id).ok_or_else(|| {
+            MachineError::new(MachineErrorType::NotDefined(alloc::format!("{id:?}")))
+        })?;
+        let name = entry.name.as_str();
+
         // verify number and types of arguments
-        let arg_def = self.machine.action_defs.get(name).ok_or(MachineError::new(
-            MachineErrorType::NotDefined(String::from(name)),
-        ))?;
+        let arg_def = &entry.args;
         let args: Vec<Value> = args.into_iter().map(|a| a.into()).collect();
         if args.len() != arg_def.len() {
             return Err(MachineError::new(MachineErrorType::Unknown(
@@ -991,7 +1566,7 @@ where
             }
         }
 
-        self.setup_function(&Label::new(name, LabelType::Action))?;
+        self.enter(name, LabelType::Action, entry.addr)?;
         for a in args {
             self.ipush(a)?;
         }
@@ -1013,6 +1588,21 @@ where
         self.run()
     }
 
+    /// Like [`call_action()`](Self::call_action), for an action found with
+    /// [`Machine::action_id()`].
+    pub fn call_action_id<Args>(
+        &mut self,
+        id: ActionId,
+        args: Args,
+    ) -> Result<ExitReason, MachineError>
+    where
+        Args: IntoIterator,
+        Args::Item: Into<Value>,
+    {
+        self.setup_action_id(id, args)?;
+        self.run()
+    }
+
     /// Call the seal block on this command to produce an envelope. The
     /// seal block is given an implicit parameter `this` and should
     /// return an opaque envelope struct on the stack.
@@ -1021,7 +1611,7 @@ where
         name: &str,
         this_data: &Struct,
     ) -> Result<ExitReason, MachineError> {
-        self.setup_function(&Label::new(name, LabelType::CommandSeal))?;
+        self.setup_command_block(name, LabelType::CommandSeal)?;
         // Seal/Open pushes the argument and defines it itself, because
         // it calls through a function stub. So we just push `this_data`
         // onto the stack.
@@ -1031,16 +1621,131 @@ where
 
     /// Call the open block on an envelope struct to produce a command struct.
     pub fn call_open(&mut self, name: &str, envelope: Struct) -> Result<ExitReason, MachineError> {
-        self.setup_function(&Label::new(name, LabelType::CommandOpen))?;
+        self.setup_command_block(name, LabelType::CommandOpen)?;
         self.ipush(envelope)?;
         self.run()
     }
 
+    /// Call a pure function loaded into the VM by name and return its result, so that
+    /// the host can reuse calculations defined in the policy. Finish functions cannot be
+    /// called this way.
+    ///
+    /// The module does not record function signatures, so the arguments are not checked
+    /// against them. They are passed in order, and must match the function's parameters.
+    pub fn call_function<Args>(&mut self, name: &str, args: Args) -> Result<Value, MachineError>
+    where
+        Args: IntoIterator,
+        Args::Item: Into<Value>,
+    {
+        self.setup_function(&Label::new(name, LabelType::Function))?;
+        for a in args {
+            self.ipush(a)?;
+        }
+        match self.run()? {
+            ExitReason::Normal => self.ipop_value(),
+            reason => Err(self.exit_error(reason)),
+        }
+    }
+
     /// Destroy the `RunState` and return the value on top of the stack.
     pub fn consume_return(mut self) -> Result<Value, MachineError> {
-        self.stack
-            .pop_value()
-            .map_err(|t| MachineError::from_position(t, self.pc, self.machine.codemap.as_ref()))
+        self.stack.pop_value().map_err(|t| self.err(t))
+    }
+
+    /// Fails if a serialized command struct of `size` bytes exceeds
+    /// [`Limits::max_struct_size`].
+    fn check_struct_size(&self, size: usize) -> Result<(), MachineError> {
+        let max = self.machine.limits.max_struct_size;
+        if size > max {
+            return Err(self.err(MachineErrorType::StructTooLarge { size, max }));
+        }
+        Ok(())
+    }
+
+    /// Fails if the serialized values of `fact` exceed [`Limits::max_fact_size`].
+    fn check_fact_size(&self, fact: &Fact) -> Result<(), MachineError> {
+        let max = self.machine.limits.max_fact_size;
+        let size = postcard::to_allocvec(&fact.values)
+            .map_err(|_| {
+                self.err(MachineErrorType::Unknown(String::from(
+                    "could not serialize fact values",
+                )))
+            })?
+            .len();
+        if size > max {
+            return Err(self.err(MachineErrorType::FactTooLarge { size, max }));
+        }
+        Ok(())
+    }
+
+    /// Inserts `fact` through I/O, then tells the fact hook.
+    fn fact_insert(&mut self, fact: Fact) -> Result<(), MachineError> {
+        match &mut self.fact_hook {
+            Some(hook) => {
+                self.io.fact_insert(
+                    fact.name.clone(),
+                    fact.keys.iter().cloned(),
+                    fact.values.iter().cloned(),
+                )?;
+                hook.insert(&fact.name, &fact.keys, &fact.values);
+            }
+            None => self.io.fact_insert(fact.name, fact.keys, fact.values)?,
+        }
+        Ok(())
+    }
+
+    /// Deletes the fact named `name` with `keys` through I/O, then tells the fact hook.
+    fn fact_delete(&mut self, name: String, keys: FactKeyList) -> Result<(), MachineError> {
+        match &mut self.fact_hook {
+            Some(hook) => {
+                self.io.fact_delete(name.clone(), keys.iter().cloned())?;
+                hook.delete(&name, &keys);
+            }
+            None => self.io.fact_delete(name, keys)?,
+        }
+        Ok(())
+    }
+
+    /// Opens a query of the facts named `name` whose keys begin with `keys`.
+    ///
+    /// Results are read in key order, a page at a time, so that every peer processes them
+    /// in the same order without holding them all at once.
+    fn open_query(&mut self, name: String, keys: FactKeyList) -> PagedQuery {
+        let answer = reborrow(&mut self.fact_hook).and_then(|hook| hook.query(&name, &keys));
+        let cursor = FactCursor::new(name, keys, QUERY_PAGE_SIZE);
+        match answer {
+            Some(facts) => PagedQuery::answered(cursor, facts),
+            None => PagedQuery::new(cursor),
+        }
+    }
+
+    /// Returns the next fact from `query`, and tells the fact hook if it was read from
+    /// I/O. Fails once more than [`Limits::max_query_results`] facts have been read.
+    fn query_next(
+        &mut self,
+        query: &mut PagedQuery,
+    ) -> Result<Option<(FactKeyList, FactValueList)>, MachineError> {
+        let next = query.next::<M, MachineStack>(self.io)?;
+        if let (Some((keys, values)), Some(hook)) = (&next, &mut self.fact_hook) {
+            if query.from_io {
+                hook.read(query.name(), keys, values);
+            }
+        }
+        let max = self.machine.limits.max_query_results;
+        if query.read > max {
+            return Err(self.err(MachineErrorType::QueryTooLarge(max)));
+        }
+        Ok(next)
+    }
+
+    /// Fails if the memory in use exceeds [`Limits::max_memory`].
+    fn check_memory(&self) -> Result<(), MachineError> {
+        let max = self.machine.limits.max_memory;
+        let used = self.memory_used();
+        if used > max {
+            return Err(self.err(MachineErrorType::OutOfMemory { used, max }));
+        }
+        Ok(())
     }
 
     fn validate_fact_literal(&self, fact: &Fact) -> Result<(), MachineError> {
@@ -1050,58 +1755,135 @@ where
             .get(&fact.name)
             .is_some_and(|schema| validate_fact_schema(fact, schema))
         {
-            return Err(MachineError::from_position(
-                MachineErrorType::InvalidSchema(fact.name.clone()),
-                self.pc,
-                self.machine.codemap.as_ref(),
-            ));
+            return Err(self.err(MachineErrorType::InvalidSchema(fact.name.clone())));
         }
         Ok(())
     }
 }
 
 /// An implementation of [`Stack`].
-pub struct MachineStack(pub(crate) HVec<Value, STACK_SIZE>);
+pub struct MachineStack {
+    /// The values, from bottom to top
+    pub(crate) values: Vec<Value>,
+    /// The most values the stack may hold
+    max_depth: usize,
+    /// The approximate heap size of the values, not counting the top value if it may have
+    /// been modified through [`peek_value()`](Stack::peek_value)
+    heap_size: usize,
+    /// Whether the top value is left out of `heap_size`
+    top_unsized: bool,
+}
 
 impl MachineStack {
-    /// Creates an empty stack.
+    /// Creates an empty stack which holds up to [`Limits::DEFAULT`] values.
     pub const fn new() -> Self {
-        Self(HVec::new())
+        Self::with_max_depth(Limits::DEFAULT.max_stack_depth)
+    }
+
+    /// Creates an empty stack which holds up to `max_depth` values.
+    pub const fn with_max_depth(max_depth: usize) -> Self {
+        Self {
+            values: Vec::new(),
+            max_depth,
+            heap_size: 0,
+            top_unsized: false,
+        }
+    }
+
+    /// Returns the most values the stack may hold.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
     }
 
     /// Returns the number of values in the stack.
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.values.len()
     }
 
     /// Reports whether the stack is empty.
     pub fn is_empty(&self) -> bool {
-        self.0.len() == 0
+        self.values.is_empty()
+    }
+
+    /// Returns the approximate heap memory used by the values in the stack, in bytes.
+    /// See [`Value::heap_size()`].
+    pub fn heap_size(&self) -> usize {
+        match self.values.last() {
+            Some(top) if self.top_unsized => self.heap_size.saturating_add(top.heap_size()),
+            _ => self.heap_size,
+        }
+    }
+
+    /// Adds the top value back into `heap_size`, if it was left out.
+    fn size_top(&mut self) {
+        if self.top_unsized {
+            if let Some(top) = self.values.last() {
+                self.heap_size = self.heap_size.saturating_add(top.heap_size());
+            }
+            self.top_unsized = false;
+        }
+    }
+
+    /// Swaps the values at `a` and `b`, counted from the bottom.
+    pub(crate) fn swap(&mut self, a: usize, b: usize) {
+        self.size_top();
+        self.values.swap(a, b);
+    }
+
+    /// Replaces every value in the stack.
+    pub(crate) fn set_values(&mut self, values: Vec<Value>) {
+        self.heap_size = values
+            .iter()
+            .fold(0, |total: usize, v| total.saturating_add(v.heap_size()));
+        self.top_unsized = false;
+        self.values = values;
     }
 
     fn clear(&mut self) {
-        self.0.clear();
+        self.values.clear();
+        self.heap_size = 0;
+        self.top_unsized = false;
     }
 
     /// Turn a Stack into a Vec of Values.
     pub fn into_vec(self) -> Vec<Value> {
-        self.0.into_iter().collect()
+        self.values
     }
 }
 
 impl Stack for MachineStack {
     fn push_value(&mut self, value: Value) -> Result<(), MachineErrorType> {
-        self.0
-            .push(value)
-            .map_err(|_| MachineErrorType::StackOverflow)
+        if self.values.len() >= self.max_depth {
+            return Err(MachineErrorType::StackOverflow);
+        }
+        self.size_top();
+        self.heap_size = self.heap_size.saturating_add(value.heap_size());
+        self.values.push(value);
+        Ok(())
     }
 
     fn pop_value(&mut self) -> Result<Value, MachineErrorType> {
-        self.0.pop().ok_or(MachineErrorType::StackUnderflow)
+        let value = self.values.pop().ok_or(MachineErrorType::StackUnderflow)?;
+        if self.top_unsized {
+            self.top_unsized = false;
+        } else {
+            self.heap_size = self.heap_size.saturating_sub(value.heap_size());
+        }
+        Ok(value)
     }
 
     fn peek_value(&mut self) -> Result<&mut Value, MachineErrorType> {
-        self.0.last_mut().ok_or(MachineErrorType::StackUnderflow)
+        // The caller may change the value, so its size is counted again when it is next
+        // needed.
+        let top = self
+            .values
+            .last_mut()
+            .ok_or(MachineErrorType::StackUnderflow)?;
+        if !self.top_unsized {
+            self.heap_size = self.heap_size.saturating_sub(top.heap_size());
+            self.top_unsized = true;
+        }
+        Ok(top)
     }
 }
 
@@ -1117,7 +1899,7 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "# Name table:")?;
-        for (k, v) in &self.machine.labels {
+        for (k, v) in self.machine.labels.iter() {
             writeln!(f, "  {}: {:?}", k, v)?;
         }
         write!(f, "# Current defs")?;
@@ -1129,13 +1911,13 @@ where
             writeln!(f, "  {}: {}", k, v)?;
         }
         writeln!(f, "# Stack:")?;
-        for v in &self.stack.0 {
+        for v in &self.stack.values {
             write!(f, "{} ", v)?;
         }
         writeln!(f)?;
         writeln!(f, "# Program:")?;
         for (addr, instr) in self.machine.progmem.iter().enumerate() {
-            for (k, v) in &self.machine.labels {
+            for (k, v) in self.machine.labels.iter() {
                 if *v == addr {
                     writeln!(f, "{}:", k)?;
                 }
@@ -0,0 +1,450 @@
+#![cfg_attr(docsrs, doc(cfg(feature = "threaded")))]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+
+use aranya_policy_module::{ExitReason, Instruction, Meta, Struct, Target, Value};
+use buggy::BugExt;
+
+use super::{MachineStack, MachineStatus, RunState};
+use crate::{
+    error::{MachineError, MachineErrorType},
+    io::MachineIO,
+    stack::Stack,
+};
+
+/// The kind of an instruction, which selects its handler in
+/// [`RunState::run_threaded()`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub(crate) enum Opcode {
+    Const,
+    Def,
+    Get,
+    Swap,
+    Dup,
+    Pop,
+    Block,
+    End,
+    Jump,
+    Branch,
+    Call,
+    Return,
+    Add,
+    Sub,
+    Not,
+    And,
+    Or,
+    Eq,
+    StructGet,
+    Meta,
+    /// Executed by [`RunState::step()`]
+    Other,
+}
+
+/// The number of opcodes.
+const OPCODES: usize = Opcode::Other as usize + 1;
+
+impl Opcode {
+    fn of(instruction: &Instruction) -> Self {
+        match instruction {
+            Instruction::Const(_) => Self::Const,
+            Instruction::Def(_) => Self::Def,
+            Instruction::Get(_) => Self::Get,
+            Instruction::Swap(_) => Self::Swap,
+            Instruction::Dup(_) => Self::Dup,
+            Instruction::Pop => Self::Pop,
+            Instruction::Block => Self::Block,
+            Instruction::End => Self::End,
+            Instruction::Jump(_) => Self::Jump,
+            Instruction::Branch(_) => Self::Branch,
+            Instruction::Call(_) => Self::Call,
+            Instruction::Return => Self::Return,
+            Instruction::Add => Self::Add,
+            Instruction::Sub => Self::Sub,
+            Instruction::Not => Self::Not,
+            Instruction::And => Self::And,
+            Instruction::Or => Self::Or,
+            Instruction::Eq => Self::Eq,
+            Instruction::StructGet(_) => Self::StructGet,
+            Instruction::Meta(Meta::Check(_)) => Self::Other,
+            Instruction::Meta(_) => Self::Meta,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Decodes the opcode of each instruction in `progmem`.
+pub(crate) fn decode(progmem: &[Instruction]) -> Arc<[Opcode]> {
+    progmem.iter().map(Opcode::of).collect()
+}
+
+/// Executes one instruction, like [`RunState::step()`].
+type Handler<M> = fn(&mut RunState<'_, M>, &Instruction) -> Result<MachineStatus, MachineError>;
+
+/// Returns the handler of each opcode, in the order of [`Opcode`].
+fn handlers<M>() -> [Handler<M>; OPCODES]
+where
+    M: MachineIO<MachineStack>,
+{
+    [
+        op_const,
+        op_def,
+        op_get,
+        op_swap,
+        op_dup,
+        op_pop,
+        op_block,
+        op_end,
+        op_jump,
+        op_branch,
+        op_call,
+        op_return,
+        op_add,
+        op_sub,
+        op_not,
+        op_and,
+        op_or,
+        op_eq,
+        op_struct_get,
+        op_meta,
+        op_other,
+    ]
+}
+
+impl<M> RunState<'_, M>
+where
+    M: MachineIO<MachineStack>,
+{
+    /// Runs like [`run()`](Self::run), but dispatches each instruction to a handler
+    /// selected by its opcode, which was decoded when the machine was created, instead of
+    /// copying it and matching on it in [`step()`](Self::step).
+    ///
+    /// Only the most common instructions have handlers; the rest are executed by `step()`.
+    /// Runs with gas, profiling, coverage, or tracing enabled use `run()`, as do machines
+    /// whose program changed without calling
+    /// [`Machine::rebuild_dispatch_table()`](crate::Machine::rebuild_dispatch_table).
+    pub fn run_threaded(&mut self) -> Result<ExitReason, MachineError> {
+        let machine = self.machine;
+        if self.instrumented() || machine.opcodes.len() != machine.progmem.len() {
+            return self.run();
+        }
+        let handlers = handlers::<M>();
+        loop {
+            let (Some(instruction), Some(&opcode)) =
+                (machine.progmem.get(self.pc), machine.opcodes.get(self.pc))
+            else {
+                // Fail as `step()` does.
+                return self.run();
+            };
+            let result = if self.ctx.is_cancelled() {
+                self.step()
+            } else {
+                handlers[opcode as usize](self, instruction)
+            };
+            match result.map_err(|err| self.locate(err, self.pc))? {
+                MachineStatus::Executing => continue,
+                MachineStatus::Exited(reason) => return Ok(reason),
+            };
+        }
+    }
+
+    /// Reports whether anything is recorded for each instruction executed.
+    fn instrumented(&self) -> bool {
+        #[allow(unused_mut)]
+        let mut instrumented = self.gas.is_some() || self.profile.is_some();
+        #[cfg(feature = "coverage")]
+        {
+            instrumented |= self.coverage.is_some();
+        }
+        #[cfg(feature = "trace")]
+        {
+            instrumented |= self.tracer.is_some();
+        }
+        instrumented
+    }
+}
+
+/// Continues to the next instruction, as [`RunState::step()`] does.
+fn next<M>(rs: &mut RunState<'_, M>) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    rs.check_memory()?;
+    rs.pc = rs.pc.checked_add(1).assume("self.pc + 1 must not wrap")?;
+    Ok(MachineStatus::Executing)
+}
+
+/// Jumps to `target`.
+fn jump<M>(rs: &mut RunState<'_, M>, target: &Target) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    match target {
+        Target::Unresolved(label) => Err(rs.err(MachineErrorType::UnresolvedTarget(label.clone()))),
+        Target::Resolved(n) => {
+            rs.pc = *n;
+            Ok(MachineStatus::Executing)
+        }
+    }
+}
+
+fn op_other<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    rs.step()
+}
+
+fn op_const<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Const(v) = ins else {
+        return rs.step();
+    };
+    rs.ipush(v.clone())?;
+    next(rs)
+}
+
+fn op_def<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Def(key) = ins else {
+        return rs.step();
+    };
+    let value = rs.ipop_value()?;
+    rs.scope.set(key.clone(), value)?;
+    next(rs)
+}
+
+fn op_get<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Get(key) = ins else {
+        return rs.step();
+    };
+    let value = rs.scope.get(key.as_str())?;
+    rs.ipush(value)?;
+    next(rs)
+}
+
+fn op_swap<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Swap(d) = ins else {
+        return rs.step();
+    };
+    if *d == 0 {
+        return Err(rs.err(MachineErrorType::InvalidInstruction));
+    }
+    let index1 = rs
+        .stack
+        .len()
+        .checked_sub(1)
+        .ok_or(MachineErrorType::StackUnderflow)?;
+    let index2 = index1
+        .checked_sub(*d)
+        .ok_or(MachineErrorType::StackUnderflow)?;
+    rs.stack.swap(index1, index2);
+    next(rs)
+}
+
+fn op_dup<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Dup(d) = ins else {
+        return rs.step();
+    };
+    let index = rs
+        .stack
+        .len()
+        .checked_sub(*d)
+        .ok_or(MachineErrorType::StackUnderflow)?
+        .checked_sub(1)
+        .ok_or(MachineErrorType::StackUnderflow)?;
+    let v = rs.stack.values[index].clone();
+    rs.ipush(v)?;
+    next(rs)
+}
+
+fn op_pop<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let _ = rs.stack.pop_value();
+    next(rs)
+}
+
+fn op_block<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    rs.scope.enter_block().map_err(|e| rs.err(e))?;
+    next(rs)
+}
+
+fn op_end<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    rs.scope.exit_block().map_err(|e| rs.err(e))?;
+    next(rs)
+}
+
+fn op_jump<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Jump(t) = ins else {
+        return rs.step();
+    };
+    jump(rs, t)
+}
+
+fn op_branch<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Branch(t) = ins else {
+        return rs.step();
+    };
+    let conditional = rs.ipop()?;
+    if conditional {
+        return jump(rs, t);
+    }
+    next(rs)
+}
+
+fn op_call<M>(rs: &mut RunState<'_, M>, ins: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::Call(t) = ins else {
+        return rs.step();
+    };
+    match t {
+        Target::Unresolved(label) => Err(rs.err(MachineErrorType::UnresolvedTarget(label.clone()))),
+        Target::Resolved(n) => {
+            rs.scope.enter_function();
+            // Store the current PC. The PC will be incremented after return,
+            // so there's no need to increment here.
+            rs.call_state.push(rs.pc);
+            rs.pc = *n;
+            Ok(MachineStatus::Executing)
+        }
+    }
+}
+
+fn op_return<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    // When the outermost function completes, there is nothing left to do, so exit.
+    if rs.call_state.is_empty() {
+        return Ok(MachineStatus::Exited(ExitReason::Normal));
+    }
+    rs.pc = rs
+        .call_state
+        .pop()
+        .ok_or_else(|| rs.err(MachineErrorType::CallStack))?;
+    rs.scope.exit_function().map_err(|e| rs.err(e))?;
+    next(rs)
+}
+
+fn op_add<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let b: i64 = rs.ipop()?;
+    let a: i64 = rs.ipop()?;
+    let r = a
+        .checked_add(b)
+        .ok_or_else(|| rs.err(MachineErrorType::IntegerOverflow))?;
+    rs.ipush(r)?;
+    next(rs)
+}
+
+fn op_sub<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let b: i64 = rs.ipop()?;
+    let a: i64 = rs.ipop()?;
+    let r = a
+        .checked_sub(b)
+        .ok_or_else(|| rs.err(MachineErrorType::IntegerOverflow))?;
+    rs.ipush(r)?;
+    next(rs)
+}
+
+fn op_not<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let a: &mut bool = rs.ipeek()?;
+    *a = !*a;
+    next(rs)
+}
+
+fn op_and<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let a: bool = rs.ipop()?;
+    let b: bool = rs.ipop()?;
+    rs.ipush(a && b)?;
+    next(rs)
+}
+
+fn op_or<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let a: bool = rs.ipop()?;
+    let b: bool = rs.ipop()?;
+    rs.ipush(a || b)?;
+    next(rs)
+}
+
+fn op_eq<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let b = rs.ipop_value()?;
+    let a = rs.ipop_value()?;
+    rs.ipush(Value::Bool(a == b))?;
+    next(rs)
+}
+
+fn op_struct_get<M>(
+    rs: &mut RunState<'_, M>,
+    ins: &Instruction,
+) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    let Instruction::StructGet(varname) = ins else {
+        return rs.step();
+    };
+    let mut s: Struct = rs.ipop()?;
+    let v = s.fields.remove(varname.as_str()).ok_or_else(|| {
+        rs.err(MachineErrorType::InvalidStructMember(
+            varname.as_str().into(),
+        ))
+    })?;
+    rs.ipush(v)?;
+    next(rs)
+}
+
+fn op_meta<M>(rs: &mut RunState<'_, M>, _: &Instruction) -> Result<MachineStatus, MachineError>
+where
+    M: MachineIO<MachineStack>,
+{
+    next(rs)
+}
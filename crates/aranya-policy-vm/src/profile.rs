@@ -0,0 +1,70 @@
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+
+use aranya_policy_module::{Instruction, Label};
+
+/// Execution counts for one entry point, collected by a [`Profile`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProfileCounters {
+    /// Instructions executed
+    pub instructions: u64,
+    /// FFI procedures called
+    pub ffi_calls: u64,
+    /// Fact queries, including the lookup done by `update`
+    pub fact_reads: u64,
+    /// Facts created or deleted. An `update` counts as both.
+    pub fact_writes: u64,
+}
+
+/// Per-label execution counts, collected when profiling is enabled with
+/// [`RunState::enable_profiling()`](crate::RunState::enable_profiling).
+///
+/// Counts are attributed to the action or command label which execution was started from
+/// with [`RunState::set_pc_by_label()`](crate::RunState::set_pc_by_label), including
+/// those of any functions it calls. Counts accumulate over every run of the same `RunState`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Profile {
+    counters: BTreeMap<Label, ProfileCounters>,
+    current: Option<Label>,
+}
+
+impl Profile {
+    /// Returns the counts for `label`, if execution has been started from it.
+    pub fn get(&self, label: &Label) -> Option<&ProfileCounters> {
+        self.counters.get(label)
+    }
+
+    /// Iterates over the counts for each label, in label order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Label, &ProfileCounters)> {
+        self.counters.iter()
+    }
+
+    /// Attributes further counts to `label`.
+    pub(crate) fn enter(&mut self, label: &Label) {
+        self.counters.entry(label.clone()).or_default();
+        self.current = Some(label.clone());
+    }
+
+    /// Counts an instruction which is about to be executed.
+    pub(crate) fn record(&mut self, instruction: &Instruction) {
+        let Some(counters) = self
+            .current
+            .as_ref()
+            .and_then(|label| self.counters.get_mut(label))
+        else {
+            return;
+        };
+        let (ffi_calls, fact_reads, fact_writes) = match instruction {
+            Instruction::ExtCall(..) => (1, 0, 0),
+            Instruction::Query | Instruction::QueryStart | Instruction::FactCount(_) => (0, 1, 0),
+            Instruction::Create | Instruction::Delete => (0, 0, 1),
+            Instruction::Update => (0, 1, 2),
+            _ => (0, 0, 0),
+        };
+        counters.instructions = counters.instructions.saturating_add(1);
+        counters.ffi_calls = counters.ffi_calls.saturating_add(ffi_calls);
+        counters.fact_reads = counters.fact_reads.saturating_add(fact_reads);
+        counters.fact_writes = counters.fact_writes.saturating_add(fact_writes);
+    }
+}
@@ -11,6 +11,25 @@ use crate::MachineErrorType;
 pub struct ScopeManager<'a> {
     globals: &'a BTreeMap<String, Value>,
     locals: Vec<Vec<BTreeMap<String, Value>>>,
+    max_definitions: usize,
+    /// The number of locals defined
+    definitions: usize,
+    /// The approximate heap size of the locals
+    heap_size: usize,
+}
+
+/// Returns the approximate heap size of a local named `name`.
+fn definition_size(name: &str, value: &Value) -> usize {
+    name.len()
+        .saturating_add(core::mem::size_of::<Value>())
+        .saturating_add(value.heap_size())
+}
+
+/// Returns the approximate heap size of a block's locals.
+fn block_size(block: &BTreeMap<String, Value>) -> usize {
+    block.iter().fold(0, |total: usize, (k, v)| {
+        total.saturating_add(definition_size(k, v))
+    })
 }
 
 impl<'a> ScopeManager<'a> {
@@ -21,9 +40,19 @@ impl<'a> ScopeManager<'a> {
         Self {
             globals,
             locals: vec![vec![BTreeMap::new()]],
+            max_definitions: usize::MAX,
+            definitions: 0,
+            heap_size: 0,
         }
     }
 
+    /// Limits the number of locals which may be defined at once, across all function and
+    /// block scopes.
+    pub fn with_max_definitions(mut self, max: usize) -> Self {
+        self.max_definitions = max;
+        self
+    }
+
     /// Enter a new function scope.
     ///
     /// Previously defined locals will be unvailable until exiting the function.
@@ -33,9 +62,13 @@ impl<'a> ScopeManager<'a> {
 
     /// Exit the current function scope.
     pub fn exit_function(&mut self) -> Result<(), MachineErrorType> {
-        self.locals.pop().ok_or(MachineErrorType::BadState(
+        let function = self.locals.pop().ok_or(MachineErrorType::BadState(
             "exit_function: empty function-scope stack",
         ))?;
+        for block in &function {
+            self.definitions = self.definitions.saturating_sub(block.len());
+            self.heap_size = self.heap_size.saturating_sub(block_size(block));
+        }
         Ok(())
     }
     /// Enter a new block scope.
@@ -54,8 +87,11 @@ impl<'a> ScopeManager<'a> {
         let last = self.locals.last_mut().ok_or(MachineErrorType::BadState(
             "exit_block: empty function-scope stack",
         ))?;
-        last.pop()
+        let block = last
+            .pop()
             .ok_or(MachineErrorType::BadState("exit_block: no block"))?;
+        self.definitions = self.definitions.saturating_sub(block.len());
+        self.heap_size = self.heap_size.saturating_sub(block_size(&block));
         Ok(())
     }
 
@@ -97,9 +133,19 @@ impl<'a> ScopeManager<'a> {
             }
         }
 
-        let block = locals
+        if self.definitions >= self.max_definitions {
+            return Err(MachineErrorType::TooManyDefinitions(self.max_definitions));
+        }
+
+        let block = self
+            .locals
             .last_mut()
+            .and_then(|locals| locals.last_mut())
             .ok_or(MachineErrorType::BadState("set: no locals"))?;
+        self.definitions = self.definitions.saturating_add(1);
+        self.heap_size = self
+            .heap_size
+            .saturating_add(definition_size(ident.as_ref(), &value));
         block.insert(ident.into(), value);
 
         Ok(())
@@ -109,6 +155,36 @@ impl<'a> ScopeManager<'a> {
     pub fn clear(&mut self) {
         self.locals.clear();
         self.locals.push(vec![BTreeMap::new()]);
+        self.definitions = 0;
+        self.heap_size = 0;
+    }
+
+    /// Returns the approximate heap memory used by the locals, in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    /// Returns every local assignment, by function and then block.
+    pub(crate) fn frames(&self) -> &[Vec<BTreeMap<String, Value>>] {
+        &self.locals
+    }
+
+    /// Replaces every local assignment, as returned by [`frames()`](Self::frames).
+    pub(crate) fn set_frames(
+        &mut self,
+        locals: Vec<Vec<BTreeMap<String, Value>>>,
+    ) -> Result<(), MachineErrorType> {
+        if locals.iter().any(Vec::is_empty) {
+            return Err(MachineErrorType::BadState(
+                "set_frames: function without a block",
+            ));
+        }
+        self.definitions = locals.iter().flatten().map(BTreeMap::len).sum();
+        self.heap_size = locals.iter().flatten().fold(0, |total: usize, block| {
+            total.saturating_add(block_size(block))
+        });
+        self.locals = locals;
+        Ok(())
     }
 
     /// Returns an iterator of currently reachable local assignments.
@@ -184,4 +260,29 @@ mod test {
         assert!(scope.get("a3").is_err());
         assert!(scope.get("b4").is_err());
     }
+
+    #[test]
+    fn test_max_definitions() {
+        let globals = BTreeMap::new();
+        let mut scope = ScopeManager::new(&globals).with_max_definitions(2);
+
+        scope.set("a", Value::Int(1)).unwrap();
+        scope.enter_function();
+        scope.set("b", Value::Int(2)).unwrap();
+        assert_eq!(
+            scope.set("c", Value::Int(3)),
+            Err(MachineErrorType::TooManyDefinitions(2))
+        );
+
+        // Leaving a scope frees its definitions.
+        scope.exit_function().unwrap();
+        scope.enter_block().unwrap();
+        scope.set("c", Value::Int(3)).unwrap();
+        scope.exit_block().unwrap();
+        scope.set("d", Value::Int(4)).unwrap();
+        assert!(scope.set("e", Value::Int(5)).is_err());
+
+        scope.clear();
+        scope.set("e", Value::Int(5)).unwrap();
+    }
 }
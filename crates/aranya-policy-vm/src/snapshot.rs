@@ -0,0 +1,36 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use aranya_policy_module::{Fingerprint, Value};
+use serde::{Deserialize, Serialize};
+
+/// The execution state of a [`RunState`](crate::RunState), taken with
+/// [`RunState::snapshot()`](crate::RunState::snapshot). It can be serialized to suspend
+/// execution, and later resumed with [`RunState::restore()`](crate::RunState::restore),
+/// possibly in another process.
+///
+/// The I/O state and command context are not part of the snapshot, so they must be
+/// recreated by the host before restoring.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The fingerprint of the policy being run, if known
+    pub(crate) fingerprint: Option<Fingerprint>,
+    /// The program counter
+    pub(crate) pc: usize,
+    /// The stack, from bottom to top
+    pub(crate) stack: Vec<Value>,
+    /// Return addresses
+    pub(crate) call_state: Vec<usize>,
+    /// Named values, by function and then block
+    pub(crate) locals: Vec<Vec<BTreeMap<String, Value>>>,
+    /// Remaining gas, if execution is metered
+    pub(crate) gas: Option<u64>,
+}
+
+impl Snapshot {
+    /// The program counter at which execution will resume.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+}
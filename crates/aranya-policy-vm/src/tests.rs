@@ -6,7 +6,7 @@ extern crate alloc;
 mod ffi;
 mod io;
 
-use alloc::collections::BTreeMap;
+use alloc::{collections::BTreeMap, sync::Arc};
 
 use aranya_crypto::Id;
 use io::TestIO;
@@ -17,23 +17,20 @@ use crate::{
     machine::{Machine, MachineStatus, RunState},
     stack::Stack,
     ActionContext, CodeMap, CommandContext, ExitReason, Fact, Instruction, Label, LabelType,
-    MachineError, PolicyContext, Struct, Target, Value,
+    Limits, MachineError, PolicyContext, Struct, Target, Timestamp, Value,
 };
 
 fn dummy_ctx_action(name: &str) -> CommandContext<'_> {
-    CommandContext::Action(ActionContext {
-        name,
-        head_id: Id::default(),
-    })
+    CommandContext::Action(ActionContext::new(name, Id::default()))
 }
 
 fn dummy_ctx_policy(name: &str) -> CommandContext<'_> {
-    CommandContext::Policy(PolicyContext {
+    CommandContext::Policy(PolicyContext::new(
         name,
-        id: Id::default(),
-        author: Id::default().into(),
-        version: Id::default(),
-    })
+        Id::default(),
+        Id::default().into(),
+        Id::default(),
+    ))
 }
 
 #[test]
@@ -97,9 +94,9 @@ fn test_swap_middle() {
     rs.stack.push(5).unwrap();
     rs.stack.push(8).unwrap();
     assert!(rs.step().unwrap() == MachineStatus::Executing);
-    assert!(rs.stack.0[0] == Value::Int(3));
-    assert!(rs.stack.0[1] == Value::Int(8));
-    assert!(rs.stack.0[2] == Value::Int(5));
+    assert!(rs.stack.values[0] == Value::Int(3));
+    assert!(rs.stack.values[1] == Value::Int(8));
+    assert!(rs.stack.values[2] == Value::Int(5));
 }
 
 #[test]
@@ -128,9 +125,32 @@ fn test_dup() {
     rs.stack.push(5).unwrap();
     assert!(rs.step().unwrap() == MachineStatus::Executing);
     assert!(rs.stack.len() == 3);
-    assert!(rs.stack.0[0] == Value::Int(3));
-    assert!(rs.stack.0[1] == Value::Int(5));
-    assert!(rs.stack.0[2] == Value::Int(3));
+    assert!(rs.stack.values[0] == Value::Int(3));
+    assert!(rs.stack.values[1] == Value::Int(5));
+    assert!(rs.stack.values[2] == Value::Int(3));
+}
+
+#[test]
+fn test_dup_bytes() {
+    let mut io = TestIO::new();
+    let ctx = dummy_ctx_policy("test");
+    let machine = Machine::new([Instruction::Dup(0)]);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+
+    // Duplicated bytes share a buffer rather than being copied.
+    rs.stack.push(vec![1u8, 2, 3]).unwrap();
+    assert!(rs.step().unwrap() == MachineStatus::Executing);
+    let [Value::Bytes(a), Value::Bytes(b)] = &rs.stack.values[..] else {
+        panic!("expected two byte values, got {:?}", rs.stack.values);
+    };
+    assert_eq!(a.as_slice(), [1, 2, 3]);
+    assert!(core::ptr::eq(a.as_slice(), b.as_slice()));
+
+    // Modifying one copy leaves the other unchanged.
+    let mut c = b.clone();
+    c.make_mut()[0] = 9;
+    assert_eq!(a.as_slice(), [1, 2, 3]);
+    assert_eq!(c.as_slice(), [9, 2, 3]);
 }
 
 #[test]
@@ -155,7 +175,7 @@ fn test_add() {
         rs.stack.push(t.1).unwrap();
         assert!(rs.step().unwrap() == MachineStatus::Executing);
         assert!(rs.stack.len() == 1);
-        assert_eq!(rs.stack.0[0], Value::Int(t.2));
+        assert_eq!(rs.stack.values[0], Value::Int(t.2));
     }
 }
 
@@ -202,7 +222,7 @@ fn test_sub() {
         rs.stack.push(t.1).unwrap();
         assert!(rs.step().unwrap() == MachineStatus::Executing);
         assert!(rs.stack.len() == 1);
-        assert_eq!(rs.stack.0[0], Value::Int(t.2));
+        assert_eq!(rs.stack.values[0], Value::Int(t.2));
     }
 }
 
@@ -235,6 +255,47 @@ fn test_sub_overflow() {
     }
 }
 
+#[test]
+fn test_compare_float_timestamp() {
+    // expect (t.0 > t.1, t.0 < t.1, t.0 == t.1)
+    let tups: [(Value, Value, [bool; 3]); 5] = [
+        (Value::from(1.5), Value::from(-2.0), [true, false, false]),
+        (Value::from(-0.0), Value::from(0.0), [false, true, false]),
+        (
+            Value::from(f64::NAN),
+            Value::from(f64::NAN),
+            [false, false, true],
+        ),
+        (
+            Value::Timestamp(Timestamp(10)),
+            Value::Timestamp(Timestamp(20)),
+            [false, true, false],
+        ),
+        (
+            Value::Timestamp(Timestamp(10)),
+            Value::Timestamp(Timestamp(10)),
+            [false, false, true],
+        ),
+    ];
+
+    for t in tups.iter() {
+        for (instruction, want) in [Instruction::Gt, Instruction::Lt, Instruction::Eq]
+            .into_iter()
+            .zip(t.2)
+        {
+            let mut io = TestIO::new();
+            let ctx = dummy_ctx_policy("test");
+            let machine = Machine::new([instruction]);
+            let mut rs = machine.create_run_state(&mut io, &ctx);
+
+            rs.stack.push(t.0.clone()).unwrap();
+            rs.stack.push(t.1.clone()).unwrap();
+            assert!(rs.step().unwrap() == MachineStatus::Executing);
+            assert_eq!(rs.stack.values, [Value::Bool(want)]);
+        }
+    }
+}
+
 struct TestStack {
     stack: Vec<Value>,
 }
@@ -493,15 +554,22 @@ fn test_errors() {
 
     // StackUnderflow: Pop an empty stack
     error_test_harness(
-        &[Instruction::Def(x.clone())],
+        &[Instruction::Def(x.as_str().into())],
         MachineErrorType::StackUnderflow,
     );
 
-    // StackOverflow untested as the stack has no maximum size
+    // StackOverflow: Push more values than the stack limit
+    error_test_harness(
+        &vec![
+            Instruction::Const(Value::None);
+            Limits::DEFAULT.max_stack_depth.checked_add(1).unwrap()
+        ],
+        MachineErrorType::StackOverflow,
+    );
 
     // NotDefined: Get a name that isn't defined
     error_test_harness(
-        &[Instruction::Get(x.clone())],
+        &[Instruction::Get(x.as_str().into())],
         MachineErrorType::NotDefined(x.clone()),
     );
 
@@ -510,8 +578,8 @@ fn test_errors() {
         &[
             Instruction::Const(Value::Int(3)),
             Instruction::Dup(0),
-            Instruction::Def(x.clone()),
-            Instruction::Def(x.clone()),
+            Instruction::Def(x.as_str().into()),
+            Instruction::Def(x.as_str().into()),
         ],
         MachineErrorType::AlreadyDefined(x.clone()),
     );
@@ -557,7 +625,7 @@ fn test_errors() {
         &[
             Instruction::Const(Value::Int(3)),
             Instruction::Const(Value::Int(3)),
-            Instruction::StructSet(x.clone()),
+            Instruction::StructSet(x.as_str().into()),
         ],
         MachineErrorType::invalid_type("Struct", "Int", "Value -> Struct"),
     );
@@ -567,7 +635,7 @@ fn test_errors() {
         &[
             Instruction::Const(Value::Int(3)),
             Instruction::Const(Value::Int(3)),
-            Instruction::FactKeySet(x.clone()),
+            Instruction::FactKeySet(x.as_str().into()),
         ],
         MachineErrorType::invalid_type("Fact", "Int", "Value -> Fact"),
     );
@@ -577,7 +645,7 @@ fn test_errors() {
         &[
             Instruction::Const(Value::Int(3)),
             Instruction::Const(Value::Int(3)),
-            Instruction::FactValueSet(x.clone()),
+            Instruction::FactValueSet(x.as_str().into()),
         ],
         MachineErrorType::invalid_type("Fact", "Int", "Value -> Fact"),
     );
@@ -595,7 +663,7 @@ fn test_errors() {
     error_test_harness(
         &[
             Instruction::Const(Value::Struct(Struct::new("foo", &[]))),
-            Instruction::StructGet(x.clone()),
+            Instruction::StructGet(x.as_str().into()),
         ],
         MachineErrorType::InvalidStructMember(x.clone()),
     );
@@ -668,7 +736,7 @@ fn test_errors() {
     general_test_harness(
         &[],
         |m| {
-            m.labels.insert(Label::new(&x, LabelType::Action), 0);
+            Arc::make_mut(&mut m.labels).insert(Label::new(&x, LabelType::Action), 0);
             Ok(())
         },
         |rs| {
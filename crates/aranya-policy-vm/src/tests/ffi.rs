@@ -12,6 +12,7 @@ impl FfiModule for PrintFfi {
 
     const SCHEMA: ModuleSchema<'static> = ModuleSchema {
         name: "print",
+        version: 1,
         functions: &[ffi::Func {
             name: "print",
             args: &[ffi::Arg {
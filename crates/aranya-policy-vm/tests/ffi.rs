@@ -45,12 +45,12 @@ impl<M: FfiModule> TestState<M, DefaultEngine<Rng>> {
     }
 
     fn call(&mut self, name: &str) -> Result<(), TestStateError<M::Error>> {
-        let ctx = CommandContext::Policy(PolicyContext {
-            name: "SomeCommand",
-            id: Id::default(),
-            author: Id::default().into(),
-            version: Id::default(),
-        });
+        let ctx = CommandContext::Policy(PolicyContext::new(
+            "SomeCommand",
+            Id::default(),
+            Id::default().into(),
+            Id::default(),
+        ));
         let idx = self.procs.get(name).ok_or(TestStateError::UnknownFunc)?;
         self.module
             .call(*idx, &mut self.stack, &ctx, &mut self.engine)
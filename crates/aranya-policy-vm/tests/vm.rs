@@ -3,44 +3,41 @@
 
 mod bits;
 
-use std::{collections::BTreeMap, iter};
+use std::{collections::BTreeMap, iter, sync::Arc};
 
 use aranya_crypto::Id;
 use aranya_policy_ast::{self as ast, Version};
 use aranya_policy_compiler::{CompileErrorType, Compiler};
 use aranya_policy_lang::lang::parse_policy_str;
 use aranya_policy_vm::{
-    ActionContext, CommandContext, ExitReason, FactValue, KVPair, Machine, MachineError,
-    MachineErrorType, Module, OpenContext, PolicyContext, SealContext, Struct, Value,
+    ActionContext, CancellationToken, Clock, CommandContext, Coverage, DebugEvent, Debugger,
+    EmittedEffect, ExitReason, FactCursor, FactHook, FactKey, FactKeyList, FactKeyRange, FactValue,
+    FactValueList, HashableValue, Instruction, KVPair, Label, LabelType, Limits, LinkError, Linker,
+    Machine, MachineError, MachineErrorType, MachineIO, MachineIOError, MachineStack, ManualClock,
+    Module, OpenContext, PolicyContext, SealContext, Stack, Struct, Timestamp, Value,
 };
 use bits::{policies::*, testio::*};
 use ciborium as cbor;
 
 fn dummy_ctx_action(name: &str) -> CommandContext<'_> {
-    CommandContext::Action(ActionContext {
-        name,
-        head_id: Id::default(),
-    })
+    CommandContext::Action(ActionContext::new(name, Id::default()))
 }
 
 fn dummy_ctx_seal(name: &str) -> CommandContext<'_> {
-    CommandContext::Seal(SealContext {
-        name,
-        head_id: Id::default(),
-    })
+    CommandContext::Seal(SealContext::new(name, Id::default()))
 }
 
 fn dummy_ctx_open(name: &str) -> CommandContext<'_> {
-    CommandContext::Open(OpenContext { name })
+    CommandContext::Open(OpenContext::new(name))
 }
 
 fn dummy_ctx_policy(name: &str) -> CommandContext<'_> {
-    CommandContext::Policy(PolicyContext {
+    CommandContext::Policy(PolicyContext::new(
         name,
-        id: Id::default(),
-        author: Id::default().into(),
-        version: Id::default(),
-    })
+        Id::default(),
+        Id::default().into(),
+        Id::default(),
+    ))
 }
 
 fn dummy_envelope() -> Struct {
@@ -82,7 +79,10 @@ fn test_bytes() -> anyhow::Result<()> {
 
         rs.call_action(
             name,
-            [Value::Id(Id::default()), Value::Bytes(vec![0, 255, 42])],
+            [
+                Value::Id(Id::default()),
+                Value::Bytes(vec![0, 255, 42].into()),
+            ],
         )?
         .success();
     }
@@ -93,7 +93,7 @@ fn test_bytes() -> anyhow::Result<()> {
             "Foo".to_string(),
             vec![
                 KVPair::new("id_field", Value::Id(Id::default())),
-                KVPair::new("x", Value::Bytes(vec![0, 255, 42]))
+                KVPair::new("x", Value::Bytes(vec![0, 255, 42].into()))
             ]
         )
     );
@@ -350,6 +350,50 @@ fn test_command_policy() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_dispatch_ids() -> anyhow::Result<()> {
+    let policy = parse_policy_str(TEST_POLICY_1.trim(), Version::V1)?;
+    let module = Compiler::new(&policy)
+        .ffi_modules(TestIO::FFI_SCHEMAS)
+        .compile()?;
+    let machine = Machine::from_module(module)?;
+
+    assert_eq!(machine.action_id("missing"), None);
+    assert_eq!(machine.command_id("missing"), None);
+
+    let foo = machine.action_id("foo").expect("action should exist");
+    {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_action("foo");
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.call_action_id(foo, [Value::from(3)])?.success();
+        assert_eq!(io.publish_stack.len(), 1);
+    }
+
+    let cmd = machine.command_id("Foo").expect("command should exist");
+    {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_policy("Foo");
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        let self_data = Struct {
+            name: String::from("Foo"),
+            fields: [
+                (String::from("a"), Value::Int(3)),
+                (String::from("b"), Value::Int(4)),
+            ]
+            .into(),
+        };
+        rs.call_command_policy_id(cmd, &self_data, dummy_envelope())?
+            .success();
+        assert_eq!(
+            io.effect_stack,
+            [("Bar".to_string(), vec![KVPair::new("x", Value::Int(7))])]
+        );
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_command_invalid_this() {
     let policy = parse_policy_str(TEST_POLICY_1.trim(), Version::V1).expect("should parse");
@@ -982,6 +1026,148 @@ fn test_if_true() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_error_locations() -> anyhow::Result<()> {
+    let text = r#"
+        action foo(x int) {
+            check x > 0
+        }
+
+        action bar(x int) {
+            let y = x + 1
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+
+    // A failed check reports where it failed
+    let ctx = dummy_ctx_action("foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let result = rs.call_action("foo", [0])?;
+    assert_eq!(result, ExitReason::Check);
+    let err = rs.exit_error(result);
+    assert_eq!(err.err_type, MachineErrorType::CheckFailed(None));
+    assert!(err.is_check_failure());
+    let msg = err.to_string();
+    assert!(
+        msg.starts_with("check failed in action `foo` at line 3 "),
+        "{msg}"
+    );
+    assert!(msg.contains("check x > 0"), "{msg}");
+
+    // So do runtime errors
+    let ctx = dummy_ctx_action("bar");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let err = rs
+        .call_action("bar", [i64::MAX])
+        .expect_err("should overflow");
+    assert_eq!(err.err_type, MachineErrorType::IntegerOverflow);
+    let msg = err.to_string();
+    assert!(
+        msg.starts_with("integer wrap in action `bar` at line 7 "),
+        "{msg}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_check_reason() -> anyhow::Result<()> {
+    let text = r#"
+        action foo(x int) {
+            check x > 0, "x must be positive"
+            check x < 10
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+    let ctx = dummy_ctx_action("foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+
+    let result = rs.call_action("foo", [0])?;
+    assert_eq!(result, ExitReason::Check);
+    let err = rs.exit_error(result);
+    assert!(err.is_check_failure());
+    assert_eq!(err.check_reason(), Some("x must be positive"));
+    assert_eq!(err.location().map(|(line, _)| line), Some(3));
+    assert!(err
+        .source_text()
+        .is_some_and(|text| text.starts_with("check x > 0")));
+    assert!(err
+        .to_string()
+        .starts_with("check failed: \"x must be positive\" in action `foo`"));
+
+    // The reason is not carried over to a later check without one.
+    let result = rs.call_action("foo", [10])?;
+    assert_eq!(result, ExitReason::Check);
+    let err = rs.exit_error(result);
+    assert_eq!(err.err_type, MachineErrorType::CheckFailed(None));
+    assert_eq!(err.location().map(|(line, _)| line), Some(4));
+
+    // Runtime errors are not check failures.
+    let err = MachineError::new(MachineErrorType::IntegerOverflow);
+    assert!(!err.is_check_failure());
+
+    Ok(())
+}
+
+#[test]
+fn test_error_backtrace() -> anyhow::Result<()> {
+    let text = r#"
+        function positive(x int) bool {
+            check x > 0
+            return true
+        }
+
+        function validate(x int) bool {
+            return positive(x)
+        }
+
+        action foo(x int) {
+            let ok = validate(x)
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+
+    let ctx = dummy_ctx_action("foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let result = rs.call_action("foo", [0])?;
+    assert_eq!(result, ExitReason::Check);
+    let err = rs.exit_error(result);
+
+    let frames: Vec<_> = err
+        .backtrace()
+        .iter()
+        .map(|frame| {
+            (
+                frame.function.name.as_str(),
+                frame.call_site.map(|(line, _)| line),
+            )
+        })
+        .collect();
+    assert_eq!(frames, [("positive", Some(8)), ("validate", Some(12))]);
+    let msg = err.to_string();
+    assert!(
+        msg.starts_with(
+            "check failed in function `positive` called from function `validate` \
+             called from action `foo` at line 3 "
+        ),
+        "{msg}"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_if_false() -> anyhow::Result<()> {
     let text = r#"
@@ -1193,6 +1379,58 @@ fn test_match_default() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_match_jump_table() -> anyhow::Result<()> {
+    let policy_str = r#"
+        command Result {
+            fields {
+                x int
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action foo(x int) {
+            match x {
+                1 => {
+                    publish Result { x: 10 }
+                }
+                2 | 4 => {
+                    publish Result { x: 20 }
+                }
+                5 => {
+                    publish Result { x: 50 }
+                }
+                _ => {
+                    publish Result { x: 0 }
+                }
+            }
+        }
+    "#;
+    let name = "foo";
+    let policy = parse_policy_str(policy_str, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    // 3 is a hole in the table, and 0 and 6 are outside of it.
+    for (input, want) in [(1, 10), (2, 20), (3, 0), (4, 20), (5, 50), (0, 0), (6, 0)] {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.call_action(name, [Value::Int(input)])?.success();
+        assert_eq!(io.publish_stack.len(), 1);
+        assert_eq!(
+            io.publish_stack[0],
+            (
+                "Result".to_string(),
+                vec![KVPair::new("x", Value::Int(want))]
+            )
+        );
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_match_return() -> anyhow::Result<()> {
     let text = r#"
@@ -1300,6 +1538,37 @@ fn test_negative_numeric_expression() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_or_equal_comparisons() -> anyhow::Result<()> {
+    let text = r#"
+        action foo(a int, b int, ge bool, le bool) {
+            check (a >= b) == ge
+            check (a <= b) == le
+        }
+    "#;
+    let name = "foo";
+    let policy = parse_policy_str(text, Version::V1)?;
+
+    for optimize in [true, false] {
+        let module = Compiler::new(&policy).optimize(optimize).compile()?;
+        let machine = Machine::from_module(module)?;
+
+        for (a, b, ge, le) in [
+            (1i64, 2i64, false, true),
+            (2, 2, true, true),
+            (3, 2, true, false),
+        ] {
+            let mut io = TestIO::new();
+            let ctx = dummy_ctx_action(name);
+            let mut rs = machine.create_run_state(&mut io, &ctx);
+            let args: [Value; 4] = [a.into(), b.into(), ge.into(), le.into()];
+            rs.call_action(name, args)?.success();
+        }
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_negative_logical_expression() -> anyhow::Result<()> {
     let text = r#"
@@ -1392,6 +1661,50 @@ fn test_pure_function() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_call_function() -> anyhow::Result<()> {
+    let text = r#"
+        function boost(x int, bonus int) int {
+            return x + bonus
+        }
+
+        function rank(role string, level int) int {
+            if role == "admin" {
+                return boost(level, 10)
+            }
+            return level
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+
+    let got = machine.call_function(
+        "rank",
+        [Value::String("admin".into()), Value::Int(3)],
+        &mut io,
+    )?;
+    assert_eq!(got, Value::Int(13));
+    let got = machine.call_function(
+        "rank",
+        [Value::String("member".into()), Value::Int(3)],
+        &mut io,
+    )?;
+    assert_eq!(got, Value::Int(3));
+
+    let err = machine
+        .call_function("missing", Vec::<Value>::new(), &mut io)
+        .unwrap_err();
+    assert_eq!(
+        err.err_type,
+        MachineErrorType::InvalidAddress("missing".to_string())
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_finish_function() -> anyhow::Result<()> {
     let text = r#"
@@ -1509,7 +1822,7 @@ fn test_serialize_deserialize() -> anyhow::Result<()> {
         // through an FFI module.
         let envelope = Struct::new(
             "Envelope",
-            [KVPair::new("payload", Value::Bytes(this_bytes))],
+            [KVPair::new("payload", Value::Bytes(this_bytes.into()))],
         );
         rs.call_open(name, envelope)?.success();
         let result = rs.consume_return()?;
@@ -1801,7 +2114,7 @@ fn test_global_let_statements() -> anyhow::Result<()> {
     let machine = Machine::from_module(module)?;
 
     // Check if the global variables are defined correctly in the machine
-    assert_eq!(machine.globals, {
+    assert_eq!(*machine.globals, {
         BTreeMap::from([
             (
                 String::from("d"),
@@ -2181,3 +2494,1653 @@ fn test_optional_type_validation() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_link_modules() -> anyhow::Result<()> {
+    let text_a = r#"
+        fact Count[]=>{n int}
+
+        command Set {
+            fields {
+                n int
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    create Count[]=>{n: this.n}
+                }
+            }
+        }
+
+        action set(n int) {
+            publish Set { n: n }
+        }
+    "#;
+    let text_b = r#"
+        command Set {
+            fields {
+                name string
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {}
+            }
+        }
+
+        action set(name string) {
+            publish Set { name: name }
+        }
+    "#;
+
+    let mut linker = Linker::new();
+    for (namespace, text) in [("a", text_a), ("b", text_b)] {
+        let policy = parse_policy_str(text, Version::V1)?;
+        linker.add(namespace, Compiler::new(&policy).compile()?)?;
+    }
+    let mut machine = linker.link();
+    let mut io = TestIO::new();
+
+    {
+        let ctx = dummy_ctx_action("a::set");
+        machine.call_action("a::set", [3], &mut io, &ctx)?.success();
+        let ctx = dummy_ctx_action("b::set");
+        machine
+            .call_action("b::set", [Value::String("x".into())], &mut io, &ctx)?
+            .success();
+    }
+    assert_eq!(
+        io.publish_stack,
+        vec![
+            ("a::Set".to_string(), vec![KVPair::new_int("n", 3)]),
+            (
+                "b::Set".to_string(),
+                vec![KVPair::new("name", Value::String("x".into()))]
+            ),
+        ]
+    );
+
+    {
+        let name = "a::Set";
+        let ctx = dummy_ctx_policy(name);
+        let this = Struct::new(name, [KVPair::new_int("n", 3)]);
+        machine
+            .call_command_policy(name, &this, dummy_envelope(), &mut io, &ctx)?
+            .success();
+    }
+    let fk = ("a::Count".to_owned(), vec![]);
+    assert_eq!(io.facts[&fk], vec![FactValue::new("n", Value::Int(3))]);
+
+    Ok(())
+}
+
+#[test]
+fn test_link_errors() -> anyhow::Result<()> {
+    let module = |text: &str| -> anyhow::Result<Module> {
+        let policy = parse_policy_str(text, Version::V1)?;
+        Ok(Compiler::new(&policy).compile()?)
+    };
+
+    let mut linker = Linker::new();
+    linker.add("a", module("struct S { x int }")?)?;
+
+    assert_eq!(
+        linker.add("a", module("struct T { x int }")?),
+        Err(LinkError::DuplicateNamespace("a".to_string()))
+    );
+    assert_eq!(
+        linker.add("b::c", module("struct T { x int }")?),
+        Err(LinkError::InvalidNamespace("b::c".to_string()))
+    );
+    assert_eq!(
+        linker.add("b", module("struct S { x string }")?),
+        Err(LinkError::StructCollision("S".to_string()))
+    );
+    assert_eq!(linker.add("b", module("let g = 1")?), Ok(()));
+    assert_eq!(
+        linker.add("c", module("let g = 2")?),
+        Err(LinkError::GlobalCollision("g".to_string()))
+    );
+    // Identical shared definitions are allowed
+    linker.add("d", module("struct S { x int } let g = 1")?)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_gas_metering() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{}
+
+        function inc(n int) int {
+            return n + 1
+        }
+
+        action foo(x int) {
+            let y = inc(x)
+            check y > x
+        }
+
+        action bar() {
+            map F[i:?] as f {
+                let i = f.i
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let name = "foo";
+    let cost = machine
+        .worst_case_cost(&Label::new(name, LabelType::Action))?
+        .expect("`foo` should have a bounded cost");
+
+    // `foo` has no skipped branches, so it uses exactly its worst-case cost
+    {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_gas_limit(cost);
+        rs.call_action(name, [Value::Int(1)])?.success();
+        assert_eq!(rs.gas_remaining(), Some(0));
+    }
+    {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_gas_limit(cost.checked_sub(1).unwrap());
+        let err = rs
+            .call_action(name, [Value::Int(1)])
+            .expect_err("should run out of gas");
+        assert_eq!(err.err_type, MachineErrorType::OutOfGas);
+    }
+
+    // Loops have no bound
+    assert_eq!(
+        machine.worst_case_cost(&Label::new("bar", LabelType::Action))?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_policy_fingerprint() -> anyhow::Result<()> {
+    let text = r#"
+        command Foo {
+            fields {
+                fingerprint bytes,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action foo() {
+            publish Foo {
+                fingerprint: policy_fingerprint(),
+            }
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let fingerprint = *module
+        .fingerprint()
+        .expect("module should have a fingerprint");
+    let machine = Machine::from_module(module)?;
+    assert_eq!(machine.fingerprint, Some(fingerprint));
+
+    let mut io = TestIO::new();
+    {
+        let name = "foo";
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.call_action(name, iter::empty::<Value>())?.success();
+    }
+
+    assert_eq!(
+        io.publish_stack[0],
+        (
+            "Foo".to_string(),
+            vec![KVPair::new(
+                "fingerprint",
+                Value::Bytes(fingerprint.as_bytes().to_vec().into())
+            )]
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_disassemble() -> anyhow::Result<()> {
+    let text = r#"
+        function double(x int) int {
+            return x + x
+        }
+
+        action foo(x int) {
+            check double(x) > 0
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let disassembly = machine.disassemble();
+    assert_eq!(disassembly.instructions.len(), machine.progmem.len());
+
+    let double = Label::new("double", LabelType::Function);
+    let foo = Label::new("foo", LabelType::Action);
+    let entry = &disassembly.instructions[machine.labels[&foo]];
+    assert_eq!(entry.labels, vec![&foo]);
+    assert!(entry
+        .source
+        .as_ref()
+        .is_some_and(|s| s.text.starts_with("action foo")));
+
+    // The call to `double` names its target
+    let call = disassembly
+        .instructions
+        .iter()
+        .find(|i| matches!(i.instruction, Instruction::Call(_)))
+        .expect("should call `double`");
+    assert_eq!(call.targets.len(), 1);
+    assert_eq!(call.targets[0].addr, machine.labels[&double]);
+    assert_eq!(call.targets[0].labels, vec![&double]);
+
+    let listing = disassembly.to_string();
+    assert!(listing.contains("fn:double:"));
+    assert!(listing.contains("action:foo:"));
+
+    Ok(())
+}
+
+#[test]
+fn test_debugger() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{}
+
+        function inc(n int) int {
+            return n + 1
+        }
+
+        action foo(x int) {
+            let y = inc(x)
+            map F[i:?] as f {
+                check f.i < y
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let name = "foo";
+    let mut io = TestIO::new();
+    io.fact_insert(
+        String::from("F"),
+        [FactKey::new("i", HashableValue::Int(1))],
+        iter::empty::<FactValue>(),
+    )?;
+    let ctx = dummy_ctx_action(name);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.setup_action(name, [Value::Int(5)])?;
+
+    let mut debugger = Debugger::new(rs);
+    let inc = Label::new("inc", LabelType::Function);
+    debugger.add_label_breakpoint(&inc)?;
+    let query_next = machine
+        .progmem
+        .iter()
+        .position(|i| matches!(i, Instruction::QueryNext(_)))
+        .expect("should have a `QueryNext`");
+    debugger.add_breakpoint(query_next.checked_add(1).unwrap());
+
+    // Stop when `inc` is called, with its argument on the stack
+    assert_eq!(
+        debugger.resume()?,
+        DebugEvent::Breakpoint(machine.labels[&inc])
+    );
+    assert_eq!(debugger.stack().last(), Some(&Value::Int(5)));
+    assert!(debugger.fact_cursor().is_none());
+
+    // Stop after the first fact is fetched
+    assert_eq!(
+        debugger.resume()?,
+        DebugEvent::Breakpoint(query_next.checked_add(1).unwrap())
+    );
+    assert_eq!(debugger.definition("y"), Some(Value::Int(6)));
+    assert!(debugger.definitions().any(|(k, _)| k == "f"));
+    let fact = debugger.fact_cursor().expect("should have fetched a fact");
+    assert_eq!(fact.fields.get("i"), Some(&Value::Int(1)));
+
+    // Single stepping runs one instruction
+    let pc = debugger.pc();
+    debugger.step()?;
+    assert_eq!(debugger.pc(), pc.checked_add(1).unwrap());
+
+    // The query ends, and so does the action
+    debugger.remove_breakpoint(query_next.checked_add(1).unwrap());
+    assert_eq!(debugger.resume()?, DebugEvent::Exited(ExitReason::Normal));
+    assert!(debugger.fact_cursor().is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace() -> anyhow::Result<()> {
+    use std::cell::RefCell;
+
+    use aranya_policy_vm::{RunState, TraceEvent};
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn tracer(event: &TraceEvent<'_>, rs: &RunState<'_, TestIO>) {
+        let line = match event {
+            TraceEvent::Instruction(instr) => format!("{}: {instr}", rs.pc()),
+            TraceEvent::FfiCall { module, procedure } => {
+                format!("ffi {module} {procedure} ({} on stack)", rs.stack.len())
+            }
+        };
+        EVENTS.with_borrow_mut(|events| events.push(line));
+    }
+
+    let text = r#"
+        use print
+
+        action foo() {
+            let s = print::print("hi")
+            check s == "HI"
+        }
+    "#;
+
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy)
+        .ffi_modules(TestIO::FFI_SCHEMAS)
+        .compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let name = "foo";
+    let mut io = TestIO::new();
+    let ctx = dummy_ctx_action(name);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.set_tracer(tracer);
+    rs.call_action(name, iter::empty::<Value>())?.success();
+
+    let events = EVENTS.take();
+    let instructions = events.iter().filter(|e| !e.starts_with("ffi")).count();
+    assert!(instructions > 0);
+    let call = events
+        .iter()
+        .position(|e| e.ends_with("extcall 0 0"))
+        .expect("should trace the `ExtCall`");
+    // The FFI call is traced once it returns, before the next instruction
+    assert!(events[call.checked_add(1).unwrap()].starts_with("ffi 0 0 "));
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot() -> anyhow::Result<()> {
+    let text = r#"
+        command Foo {
+            fields {
+                x int,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        function double(n int) int {
+            return n + n
+        }
+
+        action foo(x int) {
+            let y = double(x)
+            publish Foo{x: y}
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let double = machine.labels[&Label::new("double", LabelType::Function)];
+
+    let name = "foo";
+    let ctx = dummy_ctx_action(name);
+
+    // Suspend execution once `double` is called
+    let data = {
+        let mut io = TestIO::new();
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.setup_action(name, [Value::Int(3)])?;
+        while rs.pc() != double {
+            rs.step()?;
+        }
+        let snapshot = rs.snapshot()?;
+        assert_eq!(snapshot.pc(), double);
+
+        let mut buf = Vec::new();
+        cbor::into_writer(&snapshot, &mut buf)?;
+        buf
+    };
+
+    // Resume it with a new run state
+    let mut io = TestIO::new();
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.restore(cbor::from_reader(&data[..])?)?;
+    rs.run()?.success();
+    drop(rs);
+    assert_eq!(
+        io.publish_stack,
+        vec![(String::from("Foo"), vec![KVPair::new("x", Value::Int(6))])]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_extension_instructions() -> anyhow::Result<()> {
+    use aranya_policy_vm::{
+        ffi::{Arg, Func, Type},
+        MachineStack, Stack,
+    };
+
+    const EXTENSIONS: &[Func<'static>] = &[Func {
+        name: "between",
+        args: &[
+            Arg {
+                name: "x",
+                vtype: Type::Int,
+            },
+            Arg {
+                name: "lo",
+                vtype: Type::Int,
+            },
+            Arg {
+                name: "hi",
+                vtype: Type::Int,
+            },
+        ],
+        return_type: Type::Bool,
+    }];
+
+    fn between(stack: &mut MachineStack) -> Result<(), MachineError> {
+        let hi: i64 = stack.pop()?;
+        let lo: i64 = stack.pop()?;
+        let x: i64 = stack.pop()?;
+        stack.push((lo..=hi).contains(&x))?;
+        Ok(())
+    }
+
+    let text = r#"
+        action foo(x int) {
+            check between(x, 1, 10)
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).extensions(EXTENSIONS).compile()?;
+    let machine = Machine::from_module(module)?;
+    assert!(machine
+        .progmem
+        .contains(&Instruction::Extension("between".into())));
+
+    let name = "foo";
+    let ctx = dummy_ctx_action(name);
+    let mut io = TestIO::new();
+    for (x, want) in [(5, ExitReason::Normal), (11, ExitReason::Check)] {
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.register_extension("between", between);
+        assert_eq!(rs.call_action(name, [x])?, want);
+    }
+
+    // Extensions must be registered before they are run
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let err = rs
+        .call_action(name, [5])
+        .expect_err("should not be registered");
+    assert_eq!(
+        err.err_type,
+        MachineErrorType::NotDefined(String::from("extension `between`"))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_limits() -> anyhow::Result<()> {
+    let text = r#"
+        struct Envelope {
+            payload bytes
+        }
+
+        command Foo {
+            fields {
+                s string,
+            }
+            seal {
+                return Envelope {
+                    payload: serialize(this)
+                }
+            }
+            open {
+                return deserialize(envelope.payload)
+            }
+        }
+
+        action foo() {
+            let a = 1
+            let b = 2
+            let c = 3
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let mut machine = Machine::from_module(module)?;
+    machine.limits = Limits {
+        max_stack_depth: 10,
+        max_definitions: 2,
+        max_struct_size: 16,
+        ..Limits::DEFAULT
+    };
+    let mut io = TestIO::new();
+
+    let ctx = dummy_ctx_action("foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    assert_eq!(rs.stack.max_depth(), 10);
+    let err = rs
+        .call_action("foo", iter::empty::<Value>())
+        .expect_err("should define too many names");
+    assert_eq!(err.err_type, MachineErrorType::TooManyDefinitions(2));
+
+    let this = Struct::new("Foo", [KVPair::new("s", Value::String("x".repeat(32)))]);
+    let ctx = dummy_ctx_seal("Foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let err = rs
+        .call_seal("Foo", &this)
+        .expect_err("should serialize too large a struct");
+    assert!(
+        matches!(
+            err.err_type,
+            MachineErrorType::StructTooLarge { max: 16, .. }
+        ),
+        "{err}"
+    );
+
+    let envelope = Struct::new(
+        "Envelope",
+        [KVPair::new("payload", Value::Bytes(vec![0; 17].into()))],
+    );
+    let ctx = dummy_ctx_open("Foo");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let err = rs
+        .call_open("Foo", envelope)
+        .expect_err("should refuse to deserialize too large a struct");
+    assert_eq!(
+        err.err_type,
+        MachineErrorType::StructTooLarge { size: 17, max: 16 }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_query_and_fact_limits() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{s string}
+
+        command Add {
+            fields {
+                i int,
+                s string,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    create F[i: this.i]=>{s: this.s}
+                }
+            }
+        }
+
+        action all() {
+            map F[i:?] as f {
+                let x = f.i
+            }
+        }
+
+        action some() {
+            let n = count_up_to 5 F[i:?]
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let mut machine = Machine::from_module(module)?;
+    machine.limits = Limits {
+        max_query_results: 2,
+        max_fact_size: 16,
+        ..Limits::DEFAULT
+    };
+    let mut io = TestIO::new();
+
+    let add = |io: &mut TestIO, i: i64, s: &str| {
+        let this = Struct::new(
+            "Add",
+            [
+                KVPair::new("i", Value::Int(i)),
+                KVPair::new("s", Value::String(s.to_string())),
+            ],
+        );
+        let ctx = dummy_ctx_policy("Add");
+        let mut rs = machine.create_run_state(io, &ctx);
+        rs.call_command_policy("Add", &this, dummy_envelope())
+    };
+    add(&mut io, 1, "a")?.success();
+    add(&mut io, 2, "b")?.success();
+    let err = add(&mut io, 3, &"x".repeat(32)).expect_err("fact should be too large");
+    assert!(
+        matches!(err.err_type, MachineErrorType::FactTooLarge { max: 16, .. }),
+        "{err}"
+    );
+
+    // Traversing as many facts as the limit allows succeeds.
+    for name in ["all", "some"] {
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.call_action(name, iter::empty::<Value>())?.success();
+    }
+
+    add(&mut io, 3, "c")?.success();
+    for name in ["all", "some"] {
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        let err = rs
+            .call_action(name, iter::empty::<Value>())
+            .expect_err("query should traverse too many facts");
+        assert_eq!(err.err_type, MachineErrorType::QueryTooLarge(2));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_limit() -> anyhow::Result<()> {
+    let text = r#"
+        action copy(s string) {
+            let a = s
+            let b = a
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let mut machine = Machine::from_module(module)?;
+    machine.limits = Limits {
+        max_memory: 1024,
+        ..Limits::DEFAULT
+    };
+    let mut io = TestIO::new();
+    let ctx = dummy_ctx_action("copy");
+
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.call_action("copy", [Value::String("x".repeat(64))])?
+        .success();
+
+    // Each copy of the argument counts against the limit.
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    let err = rs
+        .call_action("copy", [Value::String("x".repeat(400))])
+        .expect_err("copies should use too much memory");
+    assert!(
+        matches!(
+            err.err_type,
+            MachineErrorType::OutOfMemory { max: 1024, .. }
+        ),
+        "{err}"
+    );
+    assert!(rs.memory_used() > 1024);
+
+    Ok(())
+}
+
+#[test]
+fn test_fact_cursor() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{s string}
+
+        command Seen {
+            fields {
+                i int
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action all() {
+            map F[i:?] as f {
+                publish Seen { i: f.i }
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+
+    let key = |i: i64| vec![FactKey::new("i", HashableValue::Int(i))];
+    for i in (0..70).rev() {
+        io.facts.insert(
+            (String::from("F"), key(i)),
+            vec![FactValue::new("s", Value::String(i.to_string()))],
+        );
+    }
+
+    let mut cursor = FactCursor::new("F", [], 32);
+    let mut seen = vec![];
+    while !cursor.is_done() {
+        let page = cursor.next_page::<_, MachineStack>(&io)?;
+        assert!(page.len() <= 32);
+        seen.extend(page.into_iter().map(|(keys, _)| keys));
+    }
+    assert_eq!(seen, (0..70).map(key).collect::<Vec<_>>());
+    assert_eq!(cursor.continuation(), Some(&key(69)[..]));
+    assert!(cursor.next_page::<_, MachineStack>(&io)?.is_empty());
+
+    // A cursor can continue from a saved position.
+    let mut cursor = FactCursor::new("F", [], 32).resume_after(key(67));
+    let page = cursor.next_page::<_, MachineStack>(&io)?;
+    assert_eq!(
+        page.into_iter().map(|(keys, _)| keys).collect::<Vec<_>>(),
+        [key(68), key(69)]
+    );
+    assert!(cursor.is_done());
+
+    // `map` reads facts a page at a time, still in key order.
+    let ctx = dummy_ctx_action("all");
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.call_action("all", iter::empty::<Value>())?.success();
+    let published: Vec<_> = io
+        .publish_stack
+        .iter()
+        .map(|(_, fields)| fields[0].value().clone())
+        .collect();
+    assert_eq!(published, (0..70).map(Value::Int).collect::<Vec<_>>());
+
+    Ok(())
+}
+
+#[test]
+fn test_fact_query_range() -> anyhow::Result<()> {
+    use std::ops::Bound;
+
+    let mut io = TestIO::new();
+    let key = |user: i64, n: i64| {
+        vec![
+            FactKey::new("user", HashableValue::Int(user)),
+            FactKey::new("n", HashableValue::Int(n)),
+        ]
+    };
+    for user in 0..3 {
+        for n in -5..25 {
+            io.facts
+                .insert((String::from("Device"), key(user, n)), vec![]);
+        }
+    }
+    let user = |user: i64| FactKey::new("user", HashableValue::Int(user));
+    let n = |n: i64| FactKey::new("n", HashableValue::Int(n));
+    let query = |prefix: &[FactKey], range: FactKeyRange, limit| -> anyhow::Result<_> {
+        let facts = MachineIO::<MachineStack>::fact_query_range(
+            &io,
+            String::from("Device"),
+            prefix.iter().cloned(),
+            range,
+            limit,
+        )?;
+        Ok(facts.into_iter().map(|(keys, _)| keys).collect::<Vec<_>>())
+    };
+
+    // Only keys within the range of the key after the prefix.
+    assert_eq!(
+        query(
+            &[user(1)],
+            (Bound::Included(n(-2)), Bound::Excluded(n(3))),
+            100
+        )?,
+        (-2..3).map(|n| key(1, n)).collect::<Vec<_>>()
+    );
+    // Up to `limit` keys, in order.
+    assert_eq!(
+        query(&[user(2)], (Bound::Excluded(n(10)), Bound::Unbounded), 4)?,
+        (11..15).map(|n| key(2, n)).collect::<Vec<_>>()
+    );
+    // The range applies to the first key when there is no prefix.
+    assert_eq!(
+        query(&[], (Bound::Included(user(2)), Bound::Unbounded), 1)?,
+        [key(2, -5)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_fact_hook() -> anyhow::Result<()> {
+    #[derive(Default)]
+    struct Recorder {
+        log: Vec<String>,
+        cached: Option<Vec<(FactKeyList, FactValueList)>>,
+    }
+
+    impl FactHook for Recorder {
+        fn query(
+            &mut self,
+            name: &str,
+            _keys: &[FactKey],
+        ) -> Option<Vec<(FactKeyList, FactValueList)>> {
+            self.log.push(format!("query {name}"));
+            self.cached.clone()
+        }
+
+        fn read(&mut self, name: &str, _keys: &[FactKey], _values: &[FactValue]) {
+            self.log.push(format!("read {name}"));
+        }
+
+        fn insert(&mut self, name: &str, _keys: &[FactKey], _values: &[FactValue]) {
+            self.log.push(format!("insert {name}"));
+        }
+
+        fn delete(&mut self, name: &str, _keys: &[FactKey]) {
+            self.log.push(format!("delete {name}"));
+        }
+    }
+
+    let text = r#"
+        fact F[i int]=>{s string}
+
+        command Add {
+            fields {
+                i int,
+                s string,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    create F[i: this.i]=>{s: this.s}
+                }
+            }
+        }
+
+        command Seen {
+            fields {
+                s string
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action find(i int) {
+            let f = unwrap query F[i: i]
+            publish Seen { s: f.s }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut io = TestIO::new();
+    let mut hook = Recorder::default();
+
+    {
+        let this = Struct::new(
+            "Add",
+            [
+                KVPair::new("i", Value::Int(1)),
+                KVPair::new("s", Value::String(String::from("stored"))),
+            ],
+        );
+        let ctx = dummy_ctx_policy("Add");
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_fact_hook(&mut hook);
+        rs.call_command_policy("Add", &this, dummy_envelope())?
+            .success();
+    }
+    assert_eq!(hook.log, ["insert F"]);
+
+    let ctx = dummy_ctx_action("find");
+    {
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_fact_hook(&mut hook);
+        rs.call_action("find", [Value::Int(1)])?.success();
+    }
+    assert_eq!(hook.log, ["insert F", "query F", "read F"]);
+
+    // A hook can answer queries in place of I/O.
+    hook.log.clear();
+    hook.cached = Some(vec![(
+        vec![FactKey::new("i", HashableValue::Int(1))],
+        vec![FactValue::new("s", Value::String(String::from("cached")))],
+    )]);
+    {
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_fact_hook(&mut hook);
+        rs.call_action("find", [Value::Int(1)])?.success();
+    }
+    assert_eq!(hook.log, ["query F"]);
+
+    let published: Vec<_> = io
+        .publish_stack
+        .iter()
+        .map(|(_, fields)| fields[0].value().clone())
+        .collect();
+    assert_eq!(
+        published,
+        [
+            Value::String(String::from("stored")),
+            Value::String(String::from("cached"))
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_run_threaded() -> anyhow::Result<()> {
+    let text = r#"
+        struct Pair {
+            a int,
+            b bool,
+        }
+
+        command Result {
+            fields {
+                x int,
+                big bool,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        function step(p struct Pair) int {
+            if p.b && p.a != 0 {
+                return p.a + 2
+            }
+            return p.a - 1
+        }
+
+        action run(x int) {
+            let y = step(Pair { a: x, b: true })
+            let z = step(Pair { a: y, b: !(y == 3) })
+            publish Result { x: z, big: z > 10 }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let ctx = dummy_ctx_action("run");
+
+    for x in [0, 1, 12, i64::MAX] {
+        let mut results = vec![];
+        for threaded in [false, true] {
+            let mut io = TestIO::new();
+            let mut rs = machine.create_run_state(&mut io, &ctx);
+            rs.setup_action("run", [Value::Int(x)])?;
+            let result = if threaded {
+                rs.run_threaded()
+            } else {
+                rs.run()
+            };
+            let stack = rs.stack.len();
+            results.push((result, stack, io.publish_stack));
+        }
+        assert_eq!(results[0], results[1], "x = {x}");
+    }
+
+    Ok(())
+}
+
+/// A call into a policy, for [`test_run_threaded_differential`].
+enum Call {
+    Action(&'static str, Vec<Value>),
+    Policy(&'static str, Struct),
+}
+
+/// Makes each call in turn with `run()` on one [`TestIO`], and with `run_threaded()` on
+/// another, and checks that every call and the resulting IO state are the same.
+fn assert_threaded_matches(policy: &str, calls: &[Call]) -> anyhow::Result<()> {
+    let policy = parse_policy_str(policy, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let mut ios = [TestIO::new(), TestIO::new()];
+    for (i, call) in calls.iter().enumerate() {
+        let mut results = vec![];
+        for (io, threaded) in ios.iter_mut().zip([false, true]) {
+            let ctx = match call {
+                Call::Action(name, _) => dummy_ctx_action(name),
+                Call::Policy(name, _) => dummy_ctx_policy(name),
+            };
+            let mut rs = machine.create_run_state(io, &ctx);
+            match call {
+                Call::Action(name, args) => rs.setup_action(name, args.iter().cloned())?,
+                Call::Policy(name, this) => {
+                    rs.setup_command(name, LabelType::CommandPolicy, this)?;
+                    rs.stack.push(dummy_envelope())?;
+                }
+            }
+            let result = if threaded {
+                rs.run_threaded()
+            } else {
+                rs.run()
+            };
+            results.push((result, rs.stack.len()));
+        }
+        assert_eq!(results[0], results[1], "call {i}");
+    }
+    let [io, threaded_io] = ios;
+    assert_eq!(io.facts, threaded_io.facts);
+    assert_eq!(io.publish_stack, threaded_io.publish_stack);
+    assert_eq!(io.effect_stack, threaded_io.effect_stack);
+
+    Ok(())
+}
+
+#[test]
+fn test_run_threaded_differential() -> anyhow::Result<()> {
+    let text = r#"
+        struct Pair {
+            a int,
+            b bool,
+        }
+
+        command Result {
+            fields {
+                x int,
+                big bool,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        function clamp(x int, lo int, hi int) int {
+            if x < lo {
+                return lo
+            }
+            if x > hi {
+                return hi
+            }
+            return x
+        }
+
+        action checked(x int) {
+            let y = x + 1
+            check y != 8
+            let p = Pair { a: clamp(x, -5, 5), b: x > 0 || x == -1 }
+            match p.a {
+                0 => {
+                    publish Result { x: 0, big: false }
+                }
+                1 => {
+                    publish Result { x: 1, big: !p.b && true }
+                }
+                _ => {
+                    publish Result { x: p.a - x, big: !p.b }
+                }
+            }
+        }
+    "#;
+    let calls = [0, 1, -1, 7, 100, i64::MIN, i64::MAX]
+        .map(|x| Call::Action("checked", vec![Value::Int(x)]));
+    assert_threaded_matches(text, &calls)?;
+
+    let calls = [4, 5, 6].map(|x| Call::Action("foo", vec![Value::Int(x)]));
+    assert_threaded_matches(POLICY_MATCH, &calls)?;
+
+    let calls = [Value::None, Value::Int(3)].map(|x| Call::Action("check_none", vec![x]));
+    assert_threaded_matches(POLICY_IS, &calls)?;
+
+    let calls = [
+        Call::Action("bar", vec![]),
+        Call::Policy(
+            "Foo",
+            Struct::new("Foo", [KVPair::new_int("a", 3), KVPair::new_int("b", 4)]),
+        ),
+    ];
+    assert_threaded_matches(TEST_POLICY_1, &calls)?;
+
+    // Facts carry over between calls, so each runner must leave them the same.
+    let calls = [
+        Call::Policy("Increment", Struct::new("Increment", &[])),
+        Call::Policy("Set", Struct::new("Set", [KVPair::new_int("a", 3)])),
+        Call::Policy("Increment", Struct::new("Increment", &[])),
+        Call::Policy("Clear", Struct::new("Clear", &[])),
+        Call::Policy("Increment", Struct::new("Increment", &[])),
+    ];
+    assert_threaded_matches(TEST_POLICY_2, &calls)?;
+
+    Ok(())
+}
+
+/// Returns query results in the reverse of [`TestIO`]'s order.
+struct ReversedIO(TestIO);
+
+impl MachineIO<MachineStack> for ReversedIO {
+    type QueryIterator = std::vec::IntoIter<Result<(FactKeyList, FactValueList), MachineIOError>>;
+
+    fn fact_insert(
+        &mut self,
+        name: String,
+        key: impl IntoIterator<Item = FactKey>,
+        value: impl IntoIterator<Item = FactValue>,
+    ) -> Result<(), MachineIOError> {
+        MachineIO::<MachineStack>::fact_insert(&mut self.0, name, key, value)
+    }
+
+    fn fact_delete(
+        &mut self,
+        name: String,
+        key: impl IntoIterator<Item = FactKey>,
+    ) -> Result<(), MachineIOError> {
+        MachineIO::<MachineStack>::fact_delete(&mut self.0, name, key)
+    }
+
+    fn fact_query(
+        &self,
+        name: String,
+        key: impl IntoIterator<Item = FactKey>,
+    ) -> Result<Self::QueryIterator, MachineIOError> {
+        let mut results: Vec<_> =
+            MachineIO::<MachineStack>::fact_query(&self.0, name, key)?.collect();
+        results.reverse();
+        Ok(results.into_iter())
+    }
+
+    fn publish(&mut self, name: String, fields: impl IntoIterator<Item = KVPair>) {
+        MachineIO::<MachineStack>::publish(&mut self.0, name, fields)
+    }
+
+    fn effect(
+        &mut self,
+        name: String,
+        fields: impl IntoIterator<Item = KVPair>,
+        command: Id,
+        recalled: bool,
+    ) {
+        MachineIO::<MachineStack>::effect(&mut self.0, name, fields, command, recalled)
+    }
+
+    fn call(
+        &mut self,
+        module: usize,
+        procedure: usize,
+        stack: &mut MachineStack,
+        ctx: &CommandContext<'_>,
+    ) -> Result<(), MachineError> {
+        self.0.call(module, procedure, stack, ctx)
+    }
+}
+
+#[test]
+fn test_query_order() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{}
+
+        command Foo {
+            fields {
+                i int,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action foo() {
+            map F[i:?] as f {
+                publish Foo{i: f.i}
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let mut io = ReversedIO(TestIO::new());
+    for i in [2, -1, 10] {
+        MachineIO::<MachineStack>::fact_insert(
+            &mut io,
+            String::from("F"),
+            [FactKey::new("i", HashableValue::Int(i))],
+            iter::empty::<FactValue>(),
+        )?;
+    }
+
+    let name = "foo";
+    let ctx = dummy_ctx_action(name);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.call_action(name, iter::empty::<Value>())?.success();
+
+    let published: Vec<_> =
+        io.0.publish_stack
+            .iter()
+            .map(|(_, fields)| fields[0].value().clone())
+            .collect();
+    assert_eq!(published, [Value::Int(-1), Value::Int(2), Value::Int(10)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_query_first_in_key_order() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{}
+
+        command Foo {
+            fields {
+                i int,
+            }
+            seal { return None }
+            open { return None }
+        }
+
+        action foo() {
+            let f = unwrap query F[i:?]
+            publish Foo{i: f.i}
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let mut io = ReversedIO(TestIO::new());
+    for i in [2, -1, 10] {
+        MachineIO::<MachineStack>::fact_insert(
+            &mut io,
+            String::from("F"),
+            [FactKey::new("i", HashableValue::Int(i))],
+            iter::empty::<FactValue>(),
+        )?;
+    }
+
+    let name = "foo";
+    let ctx = dummy_ctx_action(name);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.call_action(name, iter::empty::<Value>())?.success();
+
+    // The provider returns 10 first, but -1 sorts first.
+    assert_eq!(io.0.publish_stack[0].1[0].value(), &Value::Int(-1));
+
+    Ok(())
+}
+
+#[test]
+fn test_query_limit_in_key_order() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{v int}
+
+        action foo() {
+            let f = unwrap query F[i:?]=>{v: 0}
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let mut machine = Machine::from_module(module)?;
+    machine.limits = Limits {
+        max_query_results: 2,
+        ..Limits::DEFAULT
+    };
+
+    fn run<M: MachineIO<MachineStack>>(machine: &Machine, io: &mut M) -> anyhow::Result<()> {
+        for (i, v) in [(1, 1), (2, 1), (3, 0)] {
+            io.fact_insert(
+                String::from("F"),
+                [FactKey::new("i", HashableValue::Int(i))],
+                [FactValue::new("v", Value::Int(v))],
+            )?;
+        }
+        let name = "foo";
+        let ctx = dummy_ctx_action(name);
+        let mut rs = machine.create_run_state(io, &ctx);
+        let err = rs
+            .call_action(name, iter::empty::<Value>())
+            .expect_err("query should read too many facts");
+        assert_eq!(err.err_type, MachineErrorType::QueryTooLarge(2));
+        Ok(())
+    }
+
+    // The match is read last in key order, so the limit is hit no matter
+    // which order the provider returns facts in.
+    run(&machine, &mut TestIO::new())?;
+    run(&machine, &mut ReversedIO(TestIO::new()))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_effect_sink() -> anyhow::Result<()> {
+    let text = r#"
+        effect Counted {
+            n int,
+        }
+
+        command Count {
+            fields {
+                n int,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    emit Counted { n: this.n }
+                    emit Counted { n: this.n + 1 }
+                }
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let mut io = TestIO::new();
+    let mut effects = vec![];
+    let mut sink = |effect: EmittedEffect| effects.push(effect);
+    {
+        let name = "Count";
+        let ctx = dummy_ctx_policy(name);
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_effect_sink(&mut sink);
+        let this_data = Struct::new(name, [KVPair::new("n", Value::Int(1))]);
+        rs.call_command_policy(name, &this_data, dummy_envelope())?
+            .success();
+    }
+
+    // The sink receives the effects instead of the I/O interface.
+    assert!(io.effect_stack.is_empty());
+    assert_eq!(
+        effects,
+        [1, 2].map(|n| EmittedEffect {
+            name: String::from("Counted"),
+            fields: vec![KVPair::new("n", Value::Int(n))],
+            command: Id::default(),
+            recalled: false,
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_profile() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{}
+
+        command Add {
+            fields {
+                i int,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                check !exists F[i: this.i]
+                finish {
+                    create F[i: this.i]=>{}
+                }
+            }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let mut io = TestIO::new();
+    let name = "Add";
+    let ctx = dummy_ctx_policy(name);
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    assert_eq!(rs.profile(), None);
+    rs.enable_profiling();
+    for i in [1, 2] {
+        let this_data = Struct::new(name, [KVPair::new("i", Value::Int(i))]);
+        rs.call_command_policy(name, &this_data, dummy_envelope())?
+            .success();
+    }
+
+    let profile = rs.profile().expect("profiling is enabled");
+    let label = Label::new(name, LabelType::CommandPolicy);
+    assert_eq!(profile.iter().count(), 1);
+    let counters = profile.get(&label).expect("command was run");
+    assert!(counters.instructions > 0);
+    assert_eq!(counters.ffi_calls, 0);
+    assert_eq!(counters.fact_reads, 2);
+    assert_eq!(counters.fact_writes, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_cancellation() -> anyhow::Result<()> {
+    let text = r#"
+        action foo() {
+            let x = 1
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let name = "foo";
+    let token = CancellationToken::new();
+    let ctx = CommandContext::Action(
+        ActionContext::new(name, Id::default()).with_cancellation(Some(&token)),
+    );
+    let mut io = TestIO::new();
+    {
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.call_action(name, iter::empty::<Value>())?.success();
+    }
+
+    token.cancel();
+    assert!(ctx.is_cancelled());
+    {
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        let err = rs
+            .call_action(name, iter::empty::<Value>())
+            .expect_err("execution should be cancelled");
+        assert_eq!(err.err_type, MachineErrorType::Cancelled);
+    }
+
+    token.reset();
+    let mut rs = machine.create_run_state(&mut io, &ctx);
+    rs.call_action(name, iter::empty::<Value>())?.success();
+
+    Ok(())
+}
+
+#[test]
+fn test_interned_identifiers() -> anyhow::Result<()> {
+    let text = r#"
+        action foo(x int) {
+            let y = x + x
+            let z = y + x
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    // Every reference to `x` shares one allocation.
+    let xs: Vec<_> = machine
+        .progmem
+        .iter()
+        .filter_map(|i| match i {
+            Instruction::Get(ident) if ident == "x" => Some(ident.as_ptr()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(xs.len(), 3);
+    assert!(xs.iter().all(|p| *p == xs[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_machine_clone_shares_program() -> anyhow::Result<()> {
+    let policy = parse_policy_str(TEST_POLICY_1, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let clone = machine.clone();
+    assert!(Arc::ptr_eq(&machine.progmem, &clone.progmem));
+    assert!(Arc::ptr_eq(&machine.labels, &clone.labels));
+    assert!(Arc::ptr_eq(&machine.struct_defs, &clone.struct_defs));
+
+    // Converting back to a module copies the shared data.
+    assert_eq!(Machine::from_module(clone.into_module())?, machine);
+
+    Ok(())
+}
+
+#[test]
+fn test_machine_interface() -> anyhow::Result<()> {
+    let text = r#"
+        fact F[i int]=>{s string}
+
+        struct Pair {
+            a int,
+            b int,
+        }
+
+        effect Added {
+            i int,
+        }
+
+        command Add {
+            fields {
+                i int,
+            }
+            seal { return None }
+            open { return None }
+            policy {
+                finish {
+                    emit Added { i: this.i }
+                }
+            }
+        }
+
+        action add(i int) {
+            publish Add { i: i }
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+
+    let field = |name: &str, field_type| ast::FieldDefinition {
+        identifier: name.to_string(),
+        field_type,
+    };
+
+    let actions: Vec<_> = machine.actions().collect();
+    assert_eq!(actions, [("add", &[field("i", ast::VType::Int)][..])]);
+    assert_eq!(
+        machine.action("add"),
+        Some(&[field("i", ast::VType::Int)][..])
+    );
+    assert_eq!(machine.action("Add"), None);
+
+    let commands: Vec<_> = machine.commands().map(|(name, _)| name).collect();
+    assert_eq!(commands, ["Add"]);
+    assert_eq!(
+        machine.command("Add").and_then(|fields| fields.get("i")),
+        Some(&ast::VType::Int)
+    );
+
+    let effects: Vec<_> = machine.effects().collect();
+    assert_eq!(effects, [("Added", &[field("i", ast::VType::Int)][..])]);
+
+    let structs: Vec<_> = machine.structs().map(|(name, _)| name).collect();
+    assert!(structs.contains(&"Pair"));
+    assert!(!structs.contains(&"Added"));
+    assert_eq!(
+        machine.struct_def("Pair"),
+        Some(&[field("a", ast::VType::Int), field("b", ast::VType::Int)][..])
+    );
+    assert!(machine.struct_def("Added").is_some());
+
+    let facts: Vec<_> = machine.facts().map(|f| f.identifier.as_str()).collect();
+    assert_eq!(facts, ["F"]);
+    assert_eq!(
+        machine.fact("F").map(|f| &f.value),
+        Some(&vec![field("s", ast::VType::String)])
+    );
+
+    // The interface survives a round trip through a module.
+    let machine = Machine::from_module(machine.clone().into_module())?;
+    assert_eq!(machine.effects().count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_coverage() -> anyhow::Result<()> {
+    let text = r#"
+        function unused(x int) int {
+            return x
+        }
+
+        action foo(x int) {
+            let y = x + 1
+        }
+    "#;
+    let policy = parse_policy_str(text, Version::V1)?;
+    let module = Compiler::new(&policy).compile()?;
+    let machine = Machine::from_module(module)?;
+    let mut coverage = Coverage::new(&machine);
+
+    for x in [1, 2] {
+        let mut io = TestIO::new();
+        let ctx = dummy_ctx_action("foo");
+        let mut rs = machine.create_run_state(&mut io, &ctx);
+        rs.set_coverage(&mut coverage);
+        rs.call_action("foo", [x])?.success();
+    }
+
+    assert!(coverage.executed() > 0);
+    assert!(coverage.executed() < coverage.total());
+    let entries: Vec<_> = coverage
+        .entry_points(&machine)
+        .map(|(label, hits)| (label.to_string(), hits))
+        .collect();
+    assert_eq!(
+        entries,
+        [("action:foo".to_string(), 2), ("fn:unused".to_string(), 0)]
+    );
+
+    let report = coverage.lcov(&machine, "policy.md").to_string();
+    assert!(report.starts_with("TN:\nSF:policy.md\n"));
+    assert!(report.contains("FNDA:2,action:foo\n"));
+    assert!(report.contains("FNDA:0,fn:unused\n"));
+    assert!(report.contains("FNF:2\nFNH:1\n"));
+    assert!(report
+        .lines()
+        .any(|line| line.starts_with("DA:") && line.ends_with(",0")));
+    assert!(report.ends_with("end_of_record\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_clock() {
+    let clock = ManualClock::new(Timestamp(100));
+    clock.advance(5);
+    assert_eq!(clock.now(), Timestamp(105));
+
+    let ctx = CommandContext::Action(
+        ActionContext::new("foo", Id::default()).with_now(Some(clock.now())),
+    );
+    assert_eq!(ctx.now(), Some(Timestamp(105)));
+
+    // Blocks evaluated by every peer can't read the time.
+    clock.set(Timestamp(200));
+    assert_eq!(clock.now(), Timestamp(200));
+    assert_eq!(dummy_ctx_policy("foo").now(), None);
+}
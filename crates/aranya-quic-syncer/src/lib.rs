@@ -13,9 +13,11 @@ use std::{
 use aranya_crypto::{Csprng, Rng};
 use aranya_runtime::{
     engine::{Engine, Sink},
+    respond_multi,
     storage::{GraphId, StorageProvider},
-    ClientError, ClientState, PeerCache, Storage as _, StorageError, SubscribeResult, SyncError,
-    SyncRequestMessage, SyncRequester, SyncResponder, SyncType, MAX_SYNC_MESSAGE_SIZE,
+    ClientError, ClientState, MultiSyncRequester, PeerCache, Storage as _, StorageError,
+    SubscribeResult, SyncError, SyncRequestMessage, SyncRequester, SyncResponder, SyncType,
+    MAX_MULTI_SYNC_MESSAGE_SIZE, MAX_SYNC_MESSAGE_SIZE,
 };
 use buggy::{bug, Bug, BugExt};
 use heapless::{FnvIndexMap, Vec};
@@ -135,7 +137,8 @@ where
     S: Sink<<EN as Engine>::Effect>,
 {
     if let Ok(Some(req)) = stream.receive().await {
-        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        // The response to a `MultiPoll` holds a response for each graph.
+        let mut buffer = vec![0u8; MAX_MULTI_SYNC_MESSAGE_SIZE];
         let len = syncer.lock().await.dispatch(&req, &mut buffer).await?;
         buffer.truncate(len);
 
@@ -155,6 +158,7 @@ where
 {
     quic_client: Client,
     remote_heads: BTreeMap<SocketAddr, PeerCache>,
+    remote_graph_heads: BTreeMap<SocketAddr, BTreeMap<GraphId, PeerCache>>,
     sender: mpsc::UnboundedSender<GraphId>,
     subscriptions: FnvIndexMap<SocketAddr, Subscription, MAXIMUM_SUBSCRIPTIONS>,
     client_state: Arc<TMutex<ClientState<EN, SP>>>,
@@ -183,6 +187,7 @@ where
         Ok(Syncer {
             quic_client: client,
             remote_heads: BTreeMap::new(),
+            remote_graph_heads: BTreeMap::new(),
             sender,
             subscriptions: FnvIndexMap::new(),
             client_state,
@@ -227,7 +232,8 @@ where
         }
         // An empty response means we're up to date and there's nothing to sync.
         if !received_data.is_empty() {
-            if let Some(cmds) = syncer.receive(&received_data)? {
+            let data = syncer.decompress(&received_data)?;
+            if let Some(cmds) = syncer.receive(&data)? {
                 received = cmds.len();
                 let mut trx = client.transaction(storage_id);
                 client.add_commands(&mut trx, sink, &cmds, heads)?;
@@ -239,6 +245,55 @@ where
         Ok(received)
     }
 
+    /// Syncs every graph in `syncer` from the peer at
+    /// [`MultiSyncRequester::server_addr`] in a single exchange.
+    /// Returns the number of commands received.
+    ///
+    /// The sync will update your storage, not the peer's.
+    pub async fn sync_multi(
+        &mut self,
+        client: &mut ClientState<EN, SP>,
+        mut syncer: MultiSyncRequester<'_, SocketAddr>,
+        sink: &mut S,
+    ) -> Result<usize, QuicSyncError> {
+        let mut buffer = vec![0u8; MAX_MULTI_SYNC_MESSAGE_SIZE];
+        let heads = self
+            .remote_graph_heads
+            .entry(syncer.server_addr())
+            .or_default();
+        let len = syncer.poll(&mut buffer, client.provider(), heads)?;
+
+        let mut conn = self
+            .quic_client
+            .connect(Connect::new(syncer.server_addr()).with_server_name("localhost"))
+            .await?;
+        conn.keep_alive(true)?;
+        let mut stream = conn.open_bidirectional_stream().await?;
+
+        buffer.truncate(len);
+        buffer.shrink_to_fit();
+        stream.send(buffer.into()).await?;
+        let mut received_data = std::vec::Vec::new();
+        while let Some(chunk) = stream.receive().await? {
+            received_data.extend_from_slice(&chunk);
+        }
+        conn.close(0u32.into());
+
+        let graphs = syncer.receive(&received_data)?;
+        let received = graphs.iter().map(|(_, cmds)| cmds.len()).sum();
+        client.add_graph_commands(
+            sink,
+            graphs.iter().map(|(id, cmds)| (*id, cmds.as_slice())),
+            heads,
+        )?;
+        for (storage_id, cmds) in &graphs {
+            if !cmds.is_empty() {
+                self.push(*storage_id)?;
+            }
+        }
+        Ok(received)
+    }
+
     /// Subscribe the specified graph to a peer at the given address.
     ///
     /// This will tell the peer to send new commands to us.
@@ -356,6 +411,13 @@ where
                     }
                 }
             }
+            SyncType::MultiPoll { requests, address } => {
+                let caches = self.remote_graph_heads.entry(address).or_default();
+                let mut client = self.client_state.lock().await;
+                respond_multi(requests, target, client.provider(), caches, || {
+                    SyncResponder::new(self.server_addr)
+                })?
+            }
             SyncType::Unsubscribe { address } => {
                 self.subscriptions.remove(&address);
                 0
@@ -409,6 +471,9 @@ where
                 storage_id,
                 max_bytes: 0,
                 commands,
+                filter: None,
+                compression: Vec::new(),
+                selection: None,
             })?;
             assert!(response_syncer.ready());
             let mut target = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
@@ -7,7 +7,7 @@ use aranya_runtime::{
     engine::{Engine, Sink},
     protocol::{TestActions, TestEffect, TestEngine, TestSink},
     storage::{memory::MemStorageProvider, StorageProvider},
-    ClientState, GraphId, SyncRequester,
+    ClientState, GraphId, MultiSyncRequester, SyncRequester,
 };
 use buggy::BugExt;
 use s2n_quic::{provider::congestion_controller::Bbr, Server};
@@ -78,6 +78,73 @@ async fn test_sync() -> Result<()> {
     Ok(())
 }
 
+#[test_log::test(tokio::test)]
+async fn test_sync_multi() -> Result<()> {
+    let client1 = make_client();
+    let sink1 = Arc::new(TMutex::new(TestSink::new()));
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let key = cert.serialize_private_key_pem();
+    let cert = cert.serialize_pem()?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let server_addr1 = get_server(cert.clone(), key.clone())?;
+    let syncer1 = Arc::new(TMutex::new(Syncer::new(
+        &*cert.clone(),
+        client1.clone(),
+        sink1.clone(),
+        tx,
+        server_addr1.local_addr()?,
+    )?));
+
+    let client2 = make_client();
+    let sink2 = Arc::new(TMutex::new(TestSink::new()));
+
+    let mut storage_ids = Vec::new();
+    for i in 0..3 {
+        let storage_id = client1.lock().await.new_graph(
+            &i.to_be_bytes(),
+            TestActions::Init(i),
+            sink1.lock().await.deref_mut(),
+        )?;
+        sink1.lock().await.add_expectation(TestEffect::Got(i));
+        client1.lock().await.action(
+            storage_id,
+            sink1.lock().await.deref_mut(),
+            TestActions::SetValue(i, i),
+        )?;
+        sink2.lock().await.add_expectation(TestEffect::Got(i));
+        storage_ids.push(storage_id);
+    }
+    assert_eq!(sink1.lock().await.count(), 0);
+
+    let addr1 = spawn_syncer(syncer1.clone(), rx, server_addr1)?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (tx, _) = mpsc::unbounded_channel();
+    let server_addr2 = get_server(cert.clone(), key)?;
+    let mut syncer2 = Syncer::new(
+        &*cert,
+        client2.clone(),
+        sink2.clone(),
+        tx,
+        server_addr2.local_addr()?,
+    )?;
+    let mut requester = MultiSyncRequester::new(addr1);
+    for &storage_id in &storage_ids {
+        requester.push(SyncRequester::new(storage_id, &mut Rng, addr1))?;
+    }
+    let received = syncer2
+        .sync_multi(
+            client2.lock().await.deref_mut(),
+            requester,
+            sink2.lock().await.deref_mut(),
+        )
+        .await?;
+    assert_eq!(received, 6);
+    assert_eq!(sink2.lock().await.count(), 0);
+
+    Ok(())
+}
+
 #[test_log::test(tokio::test)]
 async fn test_sync_subscribe() -> Result<()> {
     let client1 = make_client();
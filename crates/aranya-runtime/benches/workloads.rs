@@ -0,0 +1,75 @@
+//! Benchmarks the runtime with the workloads in [`aranya_runtime::testing::bench`].
+
+#![allow(clippy::arithmetic_side_effects)]
+#![allow(clippy::unwrap_used)]
+
+use aranya_policy_compiler::Compiler;
+use aranya_policy_lang::lang::parse_policy_document;
+use aranya_policy_module::Module;
+use aranya_policy_vm::ffi::FfiModule;
+use aranya_runtime::{
+    testing::{
+        bench::{self, BENCH_POLICY},
+        vm::TestEngine,
+    },
+    vm_policy::testing::TestFfiEnvelope,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+fn compile() -> Module {
+    let ast = parse_policy_document(BENCH_POLICY).unwrap();
+    Compiler::new(&ast)
+        .ffi_modules(&[TestFfiEnvelope::SCHEMA])
+        .compile()
+        .unwrap()
+}
+
+fn action_storm(c: &mut Criterion) {
+    let module = compile();
+    let mut group = c.benchmark_group("action_storm");
+    for count in [10, 100] {
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter_batched(
+                || bench::new_client(TestEngine::from_module(module.clone())).unwrap(),
+                |(mut cs, storage_id)| bench::action_storm(&mut cs, storage_id, count).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn graph_validation(c: &mut Criterion) {
+    let module = compile();
+    let mut group = c.benchmark_group("graph_validation");
+    for count in [100, 1000] {
+        let (mut from, storage_id) =
+            bench::new_client(TestEngine::from_module(module.clone())).unwrap();
+        bench::action_storm(&mut from, storage_id, count).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter_batched(
+                || bench::new_peer(TestEngine::from_module(module.clone())),
+                |mut to| bench::sync_graph(storage_id, &mut from, &mut to),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn fact_scan(c: &mut Criterion) {
+    let module = compile();
+    let mut group = c.benchmark_group("fact_scan");
+    for count in [100, 1000] {
+        let (mut cs, storage_id) =
+            bench::new_client(TestEngine::from_module(module.clone())).unwrap();
+        bench::add_items(&mut cs, storage_id, count).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| bench::fact_scan(&mut cs, storage_id).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, action_storm, graph_validation, fact_scan);
+criterion_main!(benches);
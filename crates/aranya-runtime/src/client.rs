@@ -1,18 +1,60 @@
-use alloc::{collections::BinaryHeap, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BinaryHeap},
+    sync::Arc,
+    vec::Vec,
+};
 use core::fmt;
 
-use buggy::{Bug, BugExt};
+use aranya_crypto::{CipherSuite, SigningKey, VerifyingKey};
+use buggy::{bug, Bug, BugExt};
 use tracing::trace;
 
 use crate::{
-    Command, CommandId, Engine, EngineError, GraphId, Location, PeerCache, Perspective, Policy,
-    Prior, Priority, Segment, Sink, Storage, StorageError, StorageProvider,
+    metrics::{Metrics, MetricsHandle},
+    Command, CommandId, Engine, EngineError, Fact, GcStats, GraphId, Keys, Location, PeerCache,
+    Perspective, Policy, Prior, Query, Rejection, Segment, Sink, Storage, StorageError,
+    StorageProvider, SyncError,
 };
 
+mod actions;
+mod archive;
+mod dry_run;
+mod effects;
+mod expiry;
+mod introspect;
+mod order;
+mod recall;
+mod receipt;
+mod replay;
+mod revoke;
 mod session;
+mod snapshot;
 mod transaction;
+mod verify;
+mod watch;
 
-pub use self::{session::Session, transaction::Transaction};
+pub use self::{
+    actions::ActionTransaction,
+    dry_run::{DryRun, FactDelta},
+    effects::EffectSubscription,
+    introspect::{Ancestors, CommandInfo},
+    order::{BraidKey, BraidOrder, DefaultBraidOrder},
+    recall::{Recall, RECALL_LOG_MAX},
+    receipt::Receipt,
+    replay::{ReplayKind, ReplayLog, ReplayStep, Replayer},
+    session::{Session, SessionLimit, SessionLimits},
+    transaction::Transaction,
+    verify::{Corruption, CorruptionKind},
+    watch::{FactChange, FactSubscription},
+};
+use self::{
+    dry_run::{DryRunPerspective, EffectLog},
+    effects::Subscribers,
+    recall::RecallLog,
+    replay::Recorder,
+    watch::FactWatchers,
+};
 
 /// An error returned by the runtime client.
 #[derive(Debug)]
@@ -21,8 +63,41 @@ pub enum ClientError {
     EngineError(EngineError),
     StorageError(StorageError),
     InitError,
-    NotAuthorized,
+    /// A `check` in the policy rejected the action or command.
+    NotAuthorized(Rejection),
+    /// A foreign function called by the policy failed, such as a cryptographic
+    /// operation.
+    Ffi(String),
     SessionDeserialize(postcard::Error),
+    /// A [`Session`] exceeded one of its [`SessionLimits`].
+    SessionLimit(SessionLimit),
+    /// The head of the graph changed before an [`ActionTransaction`] was committed.
+    HeadChanged,
+    /// A graph archive's digest does not match its contents.
+    ArchiveCorrupt,
+    /// A graph archive was written in a format version this client does not support.
+    UnsupportedArchive(u32),
+    ArchiveEncoding(postcard::Error),
+    /// A snapshot's checkpoint is concurrent with a later command, so the facts at the
+    /// checkpoint are not final.
+    SnapshotCheckpoint(CommandId),
+    /// A snapshot could not be signed, or its signature is not valid for the key it
+    /// was checked with.
+    SnapshotSignature(aranya_crypto::Error),
+    /// A snapshot was written in a format version this client does not support.
+    UnsupportedSnapshot(u32),
+    SnapshotEncoding(postcard::Error),
+    /// A [`ReplayLog`] was written in a format version this client does not support.
+    UnsupportedReplayLog(u32),
+    ReplayEncoding(postcard::Error),
+    /// The command can't be revoked, because it is the init command of its graph or a
+    /// merge.
+    CannotRevoke(CommandId),
+    /// An action revoked a command without [`ClientState::revoke`], or didn't revoke
+    /// the command passed to it with its first command.
+    UnexpectedRevocation,
+    /// Syncing with a peer failed.
+    Sync(SyncError),
     Bug(Bug),
 }
 
@@ -33,8 +108,34 @@ impl fmt::Display for ClientError {
             Self::EngineError(e) => write!(f, "engine error: {e}"),
             Self::StorageError(e) => write!(f, "storage error: {e}"),
             Self::InitError => write!(f, "init error"),
-            Self::NotAuthorized => write!(f, "not authorized"),
+            Self::NotAuthorized(rejection) => write!(f, "not authorized: {rejection}"),
+            Self::Ffi(reason) => write!(f, "FFI error: {reason}"),
             Self::SessionDeserialize(e) => write!(f, "session deserialize error: {e}"),
+            Self::SessionLimit(limit) => write!(f, "session limit exceeded: {limit}"),
+            Self::HeadChanged => write!(f, "graph head changed"),
+            Self::ArchiveCorrupt => write!(f, "graph archive is corrupt"),
+            Self::UnsupportedArchive(version) => {
+                write!(f, "unsupported graph archive version {version}")
+            }
+            Self::ArchiveEncoding(e) => write!(f, "graph archive encoding error: {e}"),
+            Self::SnapshotCheckpoint(id) => {
+                write!(
+                    f,
+                    "snapshot checkpoint {id} is concurrent with later commands"
+                )
+            }
+            Self::SnapshotSignature(e) => write!(f, "graph snapshot signature error: {e}"),
+            Self::UnsupportedSnapshot(version) => {
+                write!(f, "unsupported graph snapshot version {version}")
+            }
+            Self::SnapshotEncoding(e) => write!(f, "graph snapshot encoding error: {e}"),
+            Self::UnsupportedReplayLog(version) => {
+                write!(f, "unsupported replay log version {version}")
+            }
+            Self::ReplayEncoding(e) => write!(f, "replay log encoding error: {e}"),
+            Self::CannotRevoke(id) => write!(f, "cannot revoke command {id}"),
+            Self::UnexpectedRevocation => write!(f, "unexpected revocation"),
+            Self::Sync(e) => write!(f, "sync error: {e}"),
             Self::Bug(bug) => write!(f, "{bug}"),
         }
     }
@@ -45,6 +146,8 @@ impl core::error::Error for ClientError {
         match self {
             Self::EngineError(e) => Some(e),
             Self::StorageError(e) => Some(e),
+            Self::SnapshotSignature(e) => Some(e),
+            Self::Sync(e) => Some(e),
             Self::Bug(e) => Some(e),
             _ => None,
         }
@@ -54,7 +157,9 @@ impl core::error::Error for ClientError {
 impl From<EngineError> for ClientError {
     fn from(error: EngineError) -> Self {
         match error {
-            EngineError::Check => Self::NotAuthorized,
+            EngineError::Check(rejection) => Self::NotAuthorized(rejection),
+            EngineError::Ffi(reason) => Self::Ffi(reason),
+            EngineError::Bug(bug) => Self::Bug(bug),
             _ => Self::EngineError(error),
         }
     }
@@ -66,6 +171,12 @@ impl From<StorageError> for ClientError {
     }
 }
 
+impl From<SyncError> for ClientError {
+    fn from(error: SyncError) -> Self {
+        ClientError::Sync(error)
+    }
+}
+
 impl From<Bug> for ClientError {
     fn from(error: Bug) -> Self {
         ClientError::Bug(error)
@@ -77,15 +188,51 @@ impl From<Bug> for ClientError {
 /// - `E` should be an implementation of [`Engine`].
 /// - `SP` should be an implementation of [`StorageProvider`].
 #[derive(Debug)]
-pub struct ClientState<E, SP> {
+pub struct ClientState<E: Engine, SP> {
     engine: E,
     provider: SP,
+    subscribers: Subscribers<E::Effect>,
+    facts: FactWatchers,
+    recalls: RecallLog<E::Effect>,
+    replay: Recorder,
+    metrics: MetricsHandle,
+    /// Whether received commands are applied in order of priority.
+    prioritize_received: bool,
 }
 
-impl<E, SP> ClientState<E, SP> {
+impl<E: Engine, SP> ClientState<E, SP> {
     /// Creates a `ClientState`.
     pub const fn new(engine: E, provider: SP) -> ClientState<E, SP> {
-        ClientState { engine, provider }
+        ClientState {
+            engine,
+            provider,
+            subscribers: Subscribers::new(),
+            facts: FactWatchers::new(),
+            recalls: RecallLog::new(),
+            replay: Recorder::new(),
+            metrics: MetricsHandle::new(),
+            prioritize_received: false,
+        }
+    }
+
+    /// Reports metrics, such as the number of commands validated and checks failed,
+    /// into `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics.set(metrics);
+        self
+    }
+
+    /// Applies the commands passed to [`ClientState::add_commands`] in order of their
+    /// [`Priority`](crate::Priority), while keeping each after its parents, rather
+    /// than in the order they were received.
+    ///
+    /// Policies can give a higher priority to commands which others depend on, such as
+    /// those managing keys, so a large sync applies them first. Commands which arrive
+    /// before their parents are also applied once their parents are, rather than
+    /// failing.
+    pub fn with_prioritized_receive(mut self) -> Self {
+        self.prioritize_received = true;
+        self
     }
 
     /// Provide access to the [`StorageProvider`].
@@ -103,6 +250,7 @@ where
     /// provided which must be compatible with the engine E. The `payload` is the initial
     /// init message that will bootstrap the graph facts. Effects produced when processing
     /// the payload are emitted to the sink.
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip_all))]
     pub fn new_graph(
         &mut self,
         policy_data: &[u8],
@@ -113,30 +261,69 @@ where
         let policy = self.engine.get_policy(policy_id)?;
 
         let mut perspective = self.provider.new_perspective(policy_id);
+        let sink = &mut self.subscribers.tee(sink);
         sink.begin();
         policy
             .call_action(action, &mut perspective, sink)
             .inspect_err(|_| sink.rollback())?;
         sink.commit();
 
-        let (graph_id, _) = self.provider.new_storage(perspective)?;
+        let (graph_id, storage) = self.provider.new_storage(perspective)?;
+        if self.replay.is_recording() {
+            let segment = storage.get_segment(storage.get_head()?)?;
+            self.replay
+                .record(Some(ReplayStep::action(graph_id, &segment)?));
+        }
 
         Ok(graph_id)
     }
 
     /// Commit the [`Transaction`] to storage, after merging all temporary heads.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %trx.storage_id()))
+    )]
     pub fn commit(
         &mut self,
         trx: &mut Transaction<SP, E>,
         sink: &mut impl Sink<E::Effect>,
     ) -> Result<(), ClientError> {
+        let storage_id = trx.storage_id();
+        let watched = self.facts.is_watching(storage_id);
+        let old = if watched {
+            match self.provider.get_storage(storage_id) {
+                Ok(storage) => Some(storage.get_head()?),
+                // The transaction creates the graph.
+                Err(StorageError::NoSuchStorage) => None,
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            None
+        };
+
+        let sink = &mut self.subscribers.tee(sink);
+        #[cfg(feature = "std")]
+        let start = std::time::Instant::now();
         trx.commit(&mut self.provider, &mut self.engine, sink)?;
+        #[cfg(feature = "std")]
+        self.metrics.get().merge_duration(start.elapsed());
+        trx.take_recalls(&mut self.recalls);
+        self.replay.record(Some(ReplayStep::commit(storage_id)));
+
+        if watched {
+            let storage = self.provider.get_storage(storage_id)?;
+            self.facts.publish(storage_id, storage, old);
+        }
         Ok(())
     }
 
     /// Add commands to the transaction, writing the results to
     /// `sink`.
     /// Returns the number of commands that were added.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %trx.storage_id(), count = commands.len()))
+    )]
     pub fn add_commands(
         &mut self,
         trx: &mut Transaction<SP, E>,
@@ -144,23 +331,115 @@ where
         commands: &[impl Command],
         request_heads: &mut PeerCache,
     ) -> Result<usize, ClientError> {
-        let count = trx.add_commands(
-            commands,
-            &mut self.provider,
-            &mut self.engine,
-            sink,
-            request_heads,
-        )?;
+        if self.prioritize_received {
+            let ordered = order::prioritize(commands)?;
+            return self.receive(trx, sink, &ordered, request_heads);
+        }
+        self.receive(trx, sink, commands, request_heads)
+    }
+
+    fn receive(
+        &mut self,
+        trx: &mut Transaction<SP, E>,
+        sink: &mut impl Sink<E::Effect>,
+        commands: &[impl Command],
+        request_heads: &mut PeerCache,
+    ) -> Result<usize, ClientError> {
+        let count = trx
+            .add_commands(
+                commands,
+                &mut self.provider,
+                &mut self.engine,
+                &mut self.subscribers.tee(sink),
+                request_heads,
+            )
+            .inspect_err(|e| report_error(self.metrics.get(), e))?;
+        trx.take_recalls(&mut self.recalls);
+        if self.replay.is_recording() {
+            self.replay
+                .record(Some(ReplayStep::receive(trx.storage_id(), commands)?));
+        }
+        self.metrics.get().commands_validated(count);
         Ok(count)
     }
 
-    /// Performs an `action`, writing the results to `sink`.
+    /// Like [`ClientState::add_commands`], but validates commands on independent
+    /// branches of the graph on separate threads before they are merged. This cuts
+    /// the time to add long divergent branches on multi-core devices.
+    ///
+    /// If a command in any of the branches is rejected, none of the branches are added.
+    /// Commands are applied in the order given, even with
+    /// [`ClientState::with_prioritized_receive`].
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %trx.storage_id(), count = commands.len()))
+    )]
+    pub fn add_commands_parallel<C: Command + Sync>(
+        &mut self,
+        trx: &mut Transaction<SP, E>,
+        sink: &mut impl Sink<E::Effect>,
+        commands: &[C],
+        request_heads: &mut PeerCache,
+    ) -> Result<usize, ClientError>
+    where
+        SP::Storage: Sync,
+        SP::Perspective: Send,
+        E: Sync,
+        E::Effect: Send,
+    {
+        let count = trx
+            .add_commands_parallel(
+                commands,
+                &mut self.provider,
+                &mut self.engine,
+                &mut self.subscribers.tee(sink),
+                request_heads,
+            )
+            .inspect_err(|e| report_error(self.metrics.get(), e))?;
+        trx.take_recalls(&mut self.recalls);
+        if self.replay.is_recording() {
+            self.replay
+                .record(Some(ReplayStep::receive(trx.storage_id(), commands)?));
+        }
+        self.metrics.get().commands_validated(count);
+        Ok(count)
+    }
+
+    /// Adds the commands received for several graphs, such as from a
+    /// [`MultiSyncRequester`](crate::MultiSyncRequester), committing
+    /// each graph in its own transaction. Returns the number of
+    /// commands that were added.
+    pub fn add_graph_commands<'a, C: Command + 'a>(
+        &mut self,
+        sink: &mut impl Sink<E::Effect>,
+        graphs: impl IntoIterator<Item = (GraphId, &'a [C])>,
+        request_heads: &mut BTreeMap<GraphId, PeerCache>,
+    ) -> Result<usize, ClientError> {
+        let mut count: usize = 0;
+        for (storage_id, commands) in graphs {
+            let mut trx = self.transaction(storage_id);
+            let heads = request_heads.entry(storage_id).or_default();
+            let added = self.add_commands(&mut trx, sink, commands, heads)?;
+            self.commit(&mut trx, sink)?;
+            count = count.checked_add(added).assume("must not overflow")?;
+        }
+        Ok(count)
+    }
+
+    /// Performs an `action`, writing the results to `sink`. Returns a [`Receipt`] for
+    /// the commands it committed.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %storage_id))
+    )]
     pub fn action(
         &mut self,
         storage_id: GraphId,
         sink: &mut impl Sink<E::Effect>,
         action: <E::Policy as Policy>::Action<'_>,
-    ) -> Result<(), ClientError> {
+    ) -> Result<Receipt, ClientError> {
         let storage = self.provider.get_storage(storage_id)?;
 
         let head = storage.get_head()?;
@@ -175,34 +454,457 @@ where
         // No need to checkpoint the perspective since it is only for this action.
         // Must checkpoint once we add action transactions.
 
+        let sink = &mut self.subscribers.tee(sink);
         sink.begin();
         match policy.call_action(action, &mut perspective, sink) {
             Ok(_) => {
                 let segment = storage.write(perspective)?;
+                if let Err(e) = expiry::check_segment(policy, &segment) {
+                    sink.rollback();
+                    let e = e.into();
+                    report_error(self.metrics.get(), &e);
+                    return Err(e);
+                }
+                if !revoke::revocations(&*storage, policy, &segment)?.is_empty() {
+                    sink.rollback();
+                    return Err(ClientError::UnexpectedRevocation);
+                }
+                let receipt = Receipt::new(storage_id, &segment)?;
+                let step = self
+                    .replay
+                    .is_recording()
+                    .then(|| ReplayStep::action(storage_id, &segment))
+                    .transpose()?;
                 storage.commit(segment)?;
+                self.replay.record(step);
                 sink.commit();
-                Ok(())
+                if self.facts.is_watching(storage_id) {
+                    self.facts.publish(storage_id, storage, Some(head));
+                }
+                Ok(receipt)
             }
             Err(e) => {
                 sink.rollback();
-                Err(e.into())
+                let e = e.into();
+                report_error(self.metrics.get(), &e);
+                Err(e)
             }
         }
     }
+
+    /// Revokes the command `revoked` with `action`, writing the results to `sink`.
+    /// Returns a [`Receipt`] for the commands it committed.
+    ///
+    /// The facts written since `revoked` are recomputed as if it had never been
+    /// accepted, and the effects of the commands recalled as a result are written to
+    /// `sink`, before `action` is performed. The first command `action` publishes must
+    /// be a revocation of `revoked`, as reported by [`Policy::revokes`], which the
+    /// policy checks as usual.
+    pub fn revoke(
+        &mut self,
+        storage_id: GraphId,
+        revoked: CommandId,
+        sink: &mut impl Sink<E::Effect>,
+        action: <E::Policy as Policy>::Action<'_>,
+    ) -> Result<Receipt, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+
+        let head = storage.get_head()?;
+
+        let mut perspective = storage
+            .get_linear_perspective(head)?
+            .assume("can always get perspective at head")?;
+
+        let policy_id = perspective.policy();
+        let policy = self.engine.get_policy(policy_id)?;
+
+        let revocation = revoke::revoke(&*storage, policy, head, revoked)?;
+
+        let sink = &mut self.subscribers.tee(sink);
+        sink.begin();
+        revocation.apply(&mut perspective, sink);
+        if let Err(e) = policy.call_action(action, &mut perspective, sink) {
+            sink.rollback();
+            let e = e.into();
+            report_error(self.metrics.get(), &e);
+            return Err(e);
+        }
+
+        let segment = storage.write(perspective)?;
+        if let Err(e) = expiry::check_segment(policy, &segment) {
+            sink.rollback();
+            let e = e.into();
+            report_error(self.metrics.get(), &e);
+            return Err(e);
+        }
+        if revoke::revocations(&*storage, policy, &segment)? != [(0, revoked)] {
+            sink.rollback();
+            return Err(ClientError::UnexpectedRevocation);
+        }
+        let receipt = Receipt::new(storage_id, &segment)?;
+        let step = self
+            .replay
+            .is_recording()
+            .then(|| ReplayStep::action(storage_id, &segment))
+            .transpose()?;
+        storage.commit(segment)?;
+        self.replay.record(step);
+        sink.commit();
+        if self.facts.is_watching(storage_id) {
+            self.facts.publish(storage_id, storage, Some(head));
+        }
+        Ok(receipt)
+    }
+
+    /// Previews an `action` at the head of the graph, returning the effects it would emit
+    /// and the facts it would change. Nothing is written to storage, and FFIs are told not
+    /// to make changes outside the graph (see [`Policy::call_action_dry_run()`]).
+    pub fn dry_run(
+        &mut self,
+        storage_id: GraphId,
+        action: <E::Policy as Policy>::Action<'_>,
+    ) -> Result<DryRun<E::Effect>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+
+        let head = storage.get_head()?;
+
+        let perspective = storage
+            .get_linear_perspective(head)?
+            .assume("can always get perspective at head")?;
+
+        let policy_id = perspective.policy();
+        let policy = self.engine.get_policy(policy_id)?;
+
+        // The perspective is dropped instead of written, so nothing is committed.
+        let mut perspective = DryRunPerspective::new(perspective);
+        let mut effects = EffectLog::new();
+        policy.call_action_dry_run(action, &mut perspective, &mut effects)?;
+
+        Ok(DryRun {
+            effects: effects.into_effects(),
+            facts: perspective.into_facts(),
+        })
+    }
+
+    /// Reclaims the storage of segments of the graph which are not reachable from its
+    /// head, such as abandoned branches of a failed sync. See
+    /// [`Storage::collect_garbage`].
+    ///
+    /// Fails with [`StorageError::Unsupported`] if the graph's storage cannot reclaim
+    /// segments.
+    ///
+    /// Must not be called while a [`Transaction`] for the graph is open.
+    pub fn collect_garbage(&mut self, storage_id: GraphId) -> Result<GcStats, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        Ok(storage.collect_garbage()?)
+    }
+
+    /// Returns whether the commands in `receipt` are durable. See
+    /// [`Storage::durable_sequence`].
+    pub fn is_durable(&mut self, receipt: &Receipt) -> Result<bool, ClientError> {
+        let storage = self.provider.get_storage(receipt.storage_id)?;
+        Ok(storage.durable_sequence()? >= receipt.sequence)
+    }
+
+    /// Waits until the commands in `receipt` are durable, flushing storage if needed.
+    pub fn wait_durable(&mut self, receipt: &Receipt) -> Result<(), ClientError> {
+        let storage = self.provider.get_storage(receipt.storage_id)?;
+        if storage.durable_sequence()? >= receipt.sequence {
+            return Ok(());
+        }
+        storage.flush()?;
+        if storage.durable_sequence()? < receipt.sequence {
+            bug!("storage must be durable after flushing");
+        }
+        Ok(())
+    }
+
+    /// Exports the commands of a graph to a self-contained archive, which can be
+    /// imported with [`ClientState::import_graph`] by a client using any
+    /// [`StorageProvider`]. This can back up a graph, move it to another storage
+    /// provider, or carry it to a device which cannot sync.
+    ///
+    /// The archive ends with a digest of its contents, so it can't be imported if it
+    /// is corrupted.
+    pub fn export_graph(&mut self, storage_id: GraphId) -> Result<Vec<u8>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        archive::export(storage_id, storage)
+    }
+
+    /// Imports a graph from an archive written by [`ClientState::export_graph`],
+    /// returning its ID. Effects of the commands are written to `sink`.
+    ///
+    /// The commands are checked against the graph's policy, as if they were received
+    /// by syncing. If the graph already exists, only the commands it is missing are
+    /// added.
+    pub fn import_graph(
+        &mut self,
+        archive: &[u8],
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<GraphId, ClientError> {
+        let (storage_id, commands) = archive::import(archive)?;
+        let mut trx = self.transaction(storage_id);
+        self.add_commands(&mut trx, sink, &commands, &mut PeerCache::new())?;
+        self.commit(&mut trx, sink)?;
+        Ok(storage_id)
+    }
+
+    /// Exports a snapshot of a graph at `checkpoint`, signed with `key`, from which a
+    /// new client can bootstrap with [`ClientState::import_snapshot`].
+    ///
+    /// The snapshot holds the facts at the checkpoint, as well as every command in
+    /// the graph, so a client which imports it only evaluates the commands since the
+    /// checkpoint. Every command since the checkpoint must be its descendant.
+    pub fn export_snapshot<CS: CipherSuite>(
+        &mut self,
+        storage_id: GraphId,
+        checkpoint: CommandId,
+        key: &SigningKey<CS>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        snapshot::export(storage_id, storage, &self.engine, checkpoint, key)
+    }
+
+    /// Bootstraps a graph from a snapshot written by [`ClientState::export_snapshot`]
+    /// and signed by the holder of `key`, returning its ID. Effects of the commands
+    /// since the snapshot's checkpoint are written to `sink`.
+    ///
+    /// The commands up to the checkpoint are stored without being checked against
+    /// the graph's policy, and the signer of the snapshot is trusted for the facts at
+    /// the checkpoint. This is much faster than [`ClientState::import_graph`] for
+    /// graphs with a long history. The graph must not already exist.
+    pub fn import_snapshot<CS: CipherSuite>(
+        &mut self,
+        snapshot: &[u8],
+        key: &VerifyingKey<CS>,
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<GraphId, ClientError> {
+        let (storage_id, recent) =
+            snapshot::import(snapshot, key, &mut self.provider, &mut self.engine)?;
+        let mut trx = self.transaction(storage_id);
+        self.add_commands(&mut trx, sink, &recent, &mut PeerCache::new())?;
+        self.commit(&mut trx, sink)?;
+        Ok(storage_id)
+    }
+
+    /// Subscribes to the effects of commands committed by this client, both from
+    /// local actions and from commands received when syncing.
+    ///
+    /// Effects are sent to the subscription as well as to the sink passed to each
+    /// method, once the sink commits them. Effects which are rolled back are not sent.
+    pub fn subscribe_effects(&mut self) -> EffectSubscription<E::Effect>
+    where
+        E::Effect: Clone,
+    {
+        self.subscribers.subscribe()
+    }
+
+    /// Subscribes to changes to the facts named `name` in the graph `storage_id` whose
+    /// keys start with `prefix`, as this client commits commands, both from local
+    /// actions and from commands received when syncing. An empty `prefix` watches
+    /// every fact named `name`.
+    ///
+    /// This lets applications keep state derived from facts up to date without
+    /// polling them with a [`Session`].
+    pub fn watch_facts(
+        &mut self,
+        storage_id: GraphId,
+        name: &str,
+        prefix: Keys,
+    ) -> FactSubscription {
+        self.facts.watch(storage_id, name, prefix)
+    }
+
+    /// Starts logging the commands recalled on this client when branches of a graph are
+    /// merged, so operators can audit why they were rejected. See
+    /// [`ClientState::recalls`].
+    pub fn log_recalls(&mut self)
+    where
+        E::Effect: Clone,
+    {
+        self.recalls.enable();
+    }
+
+    /// Returns the commands recalled on this client since [`ClientState::log_recalls`]
+    /// was called, oldest first. At most [`RECALL_LOG_MAX`] are kept.
+    pub fn recalls(&self) -> impl Iterator<Item = &Recall<E::Effect>> {
+        self.recalls.iter()
+    }
+
+    /// Removes and returns the logged recalls, oldest first.
+    pub fn take_recalls(&mut self) -> Vec<Recall<E::Effect>> {
+        self.recalls.take()
+    }
+
+    /// Starts recording the commands this client commits and receives to a
+    /// [`ReplayLog`], which can rebuild its graphs on another client with a
+    /// [`Replayer`]. Sessions are not recorded.
+    pub fn record_replay(&mut self) {
+        self.replay.enable();
+    }
+
+    /// Returns the changes recorded since [`ClientState::record_replay`] was called,
+    /// or `None` if the client is not recording.
+    pub fn replay_log(&self) -> Option<&ReplayLog> {
+        self.replay.log()
+    }
+
+    /// Removes and returns the recorded changes. Recording continues to a new log.
+    pub fn take_replay_log(&mut self) -> Option<ReplayLog> {
+        self.replay.take()
+    }
+
+    /// Applies every step of `log` to this client, writing the effects of its
+    /// commands to `sink`. Use a [`Replayer`] to apply them one at a time.
+    pub fn replay(
+        &mut self,
+        log: &ReplayLog,
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<(), ClientError> {
+        Replayer::new(log).finish(self, sink)
+    }
 }
 
 impl<E, SP> ClientState<E, SP>
 where
+    E: Engine,
     SP: StorageProvider,
 {
     /// Create a new [`Transaction`], used to receive [`Command`]s when syncing.
     pub fn transaction(&mut self, storage_id: GraphId) -> Transaction<SP, E> {
-        Transaction::new(storage_id)
+        let mut trx = Transaction::new(storage_id);
+        trx.log_recalls(&self.recalls);
+        trx
+    }
+
+    /// Starts an [`ActionTransaction`], used to commit several actions as a unit.
+    pub fn action_transaction(
+        &mut self,
+        storage_id: GraphId,
+    ) -> Result<ActionTransaction<SP, E>, ClientError> {
+        ActionTransaction::new(&mut self.provider, storage_id)
     }
 
     /// Create an ephemeral [`Session`] associated with this client.
     pub fn session(&mut self, storage_id: GraphId) -> Result<Session<SP, E>, ClientError> {
-        Session::new(&mut self.provider, storage_id)
+        Session::new(&mut self.provider, storage_id, SessionLimits::default())
+    }
+
+    /// Like [`ClientState::session`], but the session fails once it exceeds `limits`.
+    pub fn session_with_limits(
+        &mut self,
+        storage_id: GraphId,
+        limits: SessionLimits,
+    ) -> Result<Session<SP, E>, ClientError> {
+        Session::new(&mut self.provider, storage_id, limits)
+    }
+
+    /// Resumes a [`Session`] saved by [`Session::save`].
+    ///
+    /// The session fails once it exceeds `limits`. Its lifetime, for
+    /// [`SessionLimits::max_lifetime`], starts again from when it is resumed.
+    pub fn resume_session(
+        &mut self,
+        saved: &[u8],
+        limits: SessionLimits,
+    ) -> Result<Session<SP, E>, ClientError> {
+        Session::resume(&mut self.provider, saved, limits)
+    }
+
+    /// Returns the facts named `name` whose keys start with `prefix`, as committed at
+    /// the head of the graph `storage_id`, in sorted key order. An empty `prefix`
+    /// returns every fact named `name`.
+    ///
+    /// Unlike a [`Session`], this reads the committed facts directly, without running
+    /// any policy code.
+    pub fn query_facts(
+        &mut self,
+        storage_id: GraphId,
+        name: &str,
+        prefix: &[Box<[u8]>],
+    ) -> Result<Vec<Fact>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        let facts = storage.get_fact_perspective(storage.get_head()?)?;
+        Ok(facts
+            .query_prefix(name, prefix)?
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+/// Read-only introspection of the command graph, for admin and debugging tools.
+///
+/// These see the commands reachable from the head of each graph, which includes
+/// every committed command.
+impl<E, SP> ClientState<E, SP>
+where
+    E: Engine,
+    SP: StorageProvider,
+{
+    /// Returns the head of a graph. Once committed, a graph has a single head which
+    /// merges all of its branches.
+    pub fn head(&mut self, storage_id: GraphId) -> Result<CommandInfo, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        let head = storage.get_head()?;
+        Ok(CommandInfo::read(storage, head)?)
+    }
+
+    /// Returns the metadata of the command with ID `id`, or `None` if it is not in the
+    /// graph.
+    pub fn command_info(
+        &mut self,
+        storage_id: GraphId,
+        id: CommandId,
+    ) -> Result<Option<CommandInfo>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        match introspect::find(storage, id)? {
+            Some(location) => Ok(Some(CommandInfo::read(storage, location)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns an iterator over the ancestors of the command with ID `id`, which are
+    /// yielded in descending order of max cut.
+    pub fn ancestors(
+        &mut self,
+        storage_id: GraphId,
+        id: CommandId,
+    ) -> Result<Ancestors<'_, SP::Storage>, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        let location = introspect::find(storage, id)?.ok_or(StorageError::NoSuchId(id))?;
+        Ok(Ancestors::new(storage, location)?)
+    }
+
+    /// Returns whether the command with ID `ancestor` is an ancestor of the command
+    /// with ID `descendant`. A command is not its own ancestor.
+    pub fn is_ancestor(
+        &mut self,
+        storage_id: GraphId,
+        ancestor: CommandId,
+        descendant: CommandId,
+    ) -> Result<bool, ClientError> {
+        let storage = self.provider.get_storage(storage_id)?;
+        let ancestor =
+            introspect::find(storage, ancestor)?.ok_or(StorageError::NoSuchId(ancestor))?;
+        let descendant =
+            introspect::find(storage, descendant)?.ok_or(StorageError::NoSuchId(descendant))?;
+        Ok(introspect::is_ancestor(storage, ancestor, descendant)?)
+    }
+
+    /// Verifies every command stored for a graph, returning the commands which are
+    /// corrupted. The graph is intact if none are returned.
+    ///
+    /// Each command's parents and max cut are checked against where it is stored, and
+    /// its envelope, such as its ID and signature, is checked by the policy with
+    /// [`Policy::verify_envelope`]. The commands are not evaluated again.
+    pub fn verify_graph(&mut self, storage_id: GraphId) -> Result<Vec<Corruption>, ClientError> {
+        verify::verify(&mut self.provider, &self.engine, storage_id)
+    }
+}
+
+/// Reports a failed check to `metrics`, if `error` is one.
+fn report_error(metrics: &dyn Metrics, error: &ClientError) {
+    if let ClientError::NotAuthorized(_) = error {
+        metrics.check_failed();
     }
 }
 
@@ -285,9 +987,19 @@ pub fn braid<S: Storage>(
     storage: &mut S,
     left: Location,
     right: Location,
+) -> Result<Vec<Location>, ClientError> {
+    braid_with(storage, left, right, &DefaultBraidOrder)
+}
+
+/// Like [`braid`], but orders concurrent commands with `order`.
+pub fn braid_with<S: Storage>(
+    storage: &mut S,
+    left: Location,
+    right: Location,
+    order: &dyn BraidOrder,
 ) -> Result<Vec<Location>, ClientError> {
     struct Strand<S> {
-        key: (Priority, CommandId),
+        key: BraidKey,
         next: Location,
         segment: S,
     }
@@ -297,6 +1009,7 @@ pub fn braid<S: Storage>(
             storage: &mut impl Storage<Segment = S>,
             location: Location,
             cached_segment: Option<S>,
+            order: &dyn BraidOrder,
         ) -> Result<Self, ClientError> {
             let segment = cached_segment.map_or_else(|| storage.get_segment(location), Ok)?;
 
@@ -304,7 +1017,7 @@ pub fn braid<S: Storage>(
                 let cmd = segment
                     .get_command(location)
                     .ok_or(StorageError::CommandOutOfBounds(location))?;
-                (cmd.priority(), cmd.id())
+                order.key(&cmd)
             };
 
             Ok(Strand {
@@ -338,7 +1051,7 @@ pub fn braid<S: Storage>(
     trace!(%left, %right, "braiding");
 
     for head in [left, right] {
-        strands.push(Strand::new(storage, head, None)?);
+        strands.push(Strand::new(storage, head, None, order)?);
     }
 
     // Get latest command
@@ -375,6 +1088,7 @@ pub fn braid<S: Storage>(
                 // Taking is OK here because `maybe_cached_segment` is `Some` when
                 // the current strand has a single parent that is in the same segment
                 Option::take(&mut maybe_cached_segment),
+                order,
             )?);
         }
         if strands.len() == 1 {
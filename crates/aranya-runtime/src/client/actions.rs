@@ -0,0 +1,134 @@
+//! Atomic transactions of several actions.
+//!
+//! See [`ClientState::action_transaction`] and [`ActionTransaction`].
+
+use alloc::vec::Vec;
+
+use buggy::BugExt;
+
+use super::{
+    dry_run::EffectLog, expiry, receipt::Receipt, replay::ReplayStep, report_error, revoke,
+};
+use crate::{
+    ClientError, ClientState, Engine, GraphId, Location, Perspective, Policy, Revertable, Sink,
+    Storage, StorageProvider,
+};
+
+/// Actions staged against the head of a graph, to be committed together.
+///
+/// Each action sees the changes made by the actions staged before it. Nothing is
+/// written to storage, and no effects are emitted, until
+/// [`ActionTransaction::commit`]. Dropping the transaction discards every action.
+pub struct ActionTransaction<SP: StorageProvider, E: Engine> {
+    storage_id: GraphId,
+    /// The head of the graph when the transaction was started.
+    head: Location,
+    perspective: <SP::Storage as Storage>::Perspective,
+    /// The effects of the staged actions, in order.
+    effects: Vec<E::Effect>,
+}
+
+impl<SP: StorageProvider, E: Engine> ActionTransaction<SP, E> {
+    pub(super) fn new(provider: &mut SP, storage_id: GraphId) -> Result<Self, ClientError> {
+        let storage = provider.get_storage(storage_id)?;
+        let head = storage.get_head()?;
+        let perspective = storage
+            .get_linear_perspective(head)?
+            .assume("can always get perspective at head")?;
+
+        Ok(Self {
+            storage_id,
+            head,
+            perspective,
+            effects: Vec::new(),
+        })
+    }
+
+    /// Returns the ID of the graph the actions are staged against.
+    pub fn storage_id(&self) -> GraphId {
+        self.storage_id
+    }
+
+    /// Returns the effects of the staged actions, in order.
+    pub fn effects(&self) -> &[E::Effect] {
+        &self.effects
+    }
+
+    /// Stages an `action`.
+    ///
+    /// If the action fails, none of its changes are staged and the transaction can
+    /// still be committed with the actions staged before it, or dropped.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %self.storage_id))
+    )]
+    pub fn action(
+        &mut self,
+        client: &ClientState<E, SP>,
+        action: <E::Policy as Policy>::Action<'_>,
+    ) -> Result<(), ClientError> {
+        let policy = client.engine.get_policy(self.perspective.policy())?;
+
+        let checkpoint = self.perspective.checkpoint();
+        let mut effects = EffectLog::new();
+        if let Err(e) = policy.call_action(action, &mut self.perspective, &mut effects) {
+            self.perspective.revert(checkpoint)?;
+            let e = e.into();
+            report_error(client.metrics.get(), &e);
+            return Err(e);
+        }
+        self.effects.extend(effects.into_effects());
+
+        Ok(())
+    }
+
+    /// Commits every staged action to storage, then writes their effects to `sink`.
+    /// Returns a [`Receipt`] for the committed commands.
+    ///
+    /// Fails without committing anything if the head of the graph has changed since
+    /// the transaction was started, such as by a sync, or if an action revoked a
+    /// command, which must be done with [`ClientState::revoke`]. The actions should then be
+    /// staged again in a new transaction.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(storage_id = %self.storage_id))
+    )]
+    pub fn commit(
+        self,
+        client: &mut ClientState<E, SP>,
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<Receipt, ClientError> {
+        let storage = client.provider.get_storage(self.storage_id)?;
+        if storage.get_head()? != self.head {
+            return Err(ClientError::HeadChanged);
+        }
+        let policy = client.engine.get_policy(self.perspective.policy())?;
+        let segment = storage.write(self.perspective)?;
+        expiry::check_segment(policy, &segment)?;
+        if !revoke::revocations(&*storage, policy, &segment)?.is_empty() {
+            return Err(ClientError::UnexpectedRevocation);
+        }
+        let receipt = Receipt::new(self.storage_id, &segment)?;
+        let step = client
+            .replay
+            .is_recording()
+            .then(|| ReplayStep::action(self.storage_id, &segment))
+            .transpose()?;
+        storage.commit(segment)?;
+        client.replay.record(step);
+        if client.facts.is_watching(self.storage_id) {
+            client
+                .facts
+                .publish(self.storage_id, storage, Some(self.head));
+        }
+
+        let sink = &mut client.subscribers.tee(sink);
+        sink.begin();
+        for effect in self.effects {
+            sink.consume(effect);
+        }
+        sink.commit();
+
+        Ok(receipt)
+    }
+}
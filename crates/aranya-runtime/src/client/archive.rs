@@ -0,0 +1,131 @@
+//! Portable archives of a graph's commands.
+//!
+//! See [`ClientState::export_graph`](crate::ClientState::export_graph) and
+//! [`ClientState::import_graph`](crate::ClientState::import_graph).
+//!
+//! An archive is a postcard-encoded list of commands followed by its SHA-512 digest,
+//! which detects corruption in transit or at rest. It does not authenticate the
+//! archive; commands are checked against the graph's policy when imported, just as
+//! when they are received by syncing.
+
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
+
+use aranya_crypto::{hash::Hash, rust::Sha512};
+use buggy::Bug;
+use serde::{Deserialize, Serialize};
+
+use crate::{Address, ClientError, Command, CommandId, GraphId, Prior, Priority, Segment, Storage};
+
+/// The version of the archive format written by this crate.
+const ARCHIVE_VERSION: u32 = 1;
+
+/// The length of the digest which ends an archive.
+const DIGEST_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveBody {
+    version: u32,
+    graph: GraphId,
+    /// The graph's commands, with every command after its parents.
+    commands: Vec<ArchivedCommand>,
+}
+
+/// A command read from an archive.
+#[derive(Serialize, Deserialize)]
+pub(super) struct ArchivedCommand {
+    priority: Priority,
+    id: CommandId,
+    parent: Prior<Address>,
+    policy: Option<Box<[u8]>>,
+    data: Box<[u8]>,
+    max_cut: usize,
+}
+
+impl ArchivedCommand {
+    pub(super) fn from_cmd(command: &impl Command) -> Result<Self, Bug> {
+        Ok(Self {
+            priority: command.priority(),
+            id: command.id(),
+            parent: command.parent(),
+            policy: command.policy().map(Box::from),
+            data: command.bytes().into(),
+            max_cut: command.max_cut()?,
+        })
+    }
+}
+
+impl Command for ArchivedCommand {
+    fn priority(&self) -> Priority {
+        self.priority.clone()
+    }
+
+    fn id(&self) -> CommandId {
+        self.id
+    }
+
+    fn parent(&self) -> Prior<Address> {
+        self.parent
+    }
+
+    fn policy(&self) -> Option<&[u8]> {
+        self.policy.as_deref()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn max_cut(&self) -> Result<usize, Bug> {
+        Ok(self.max_cut)
+    }
+}
+
+/// Writes an archive of the commands of `graph` reachable from its head.
+pub(super) fn export(graph: GraphId, storage: &impl Storage) -> Result<Vec<u8>, ClientError> {
+    let mut seen = BTreeSet::new();
+    let mut queue = Vec::new();
+    queue.push(storage.get_head()?);
+    let mut commands = Vec::new();
+    while let Some(location) = queue.pop() {
+        if !seen.insert(location.segment) {
+            continue;
+        }
+        let segment = storage.get_segment(location)?;
+        for command in segment.get_from(segment.first_location()) {
+            commands.push(ArchivedCommand::from_cmd(&command)?);
+        }
+        queue.extend(segment.prior());
+    }
+    // A command's max cut is greater than its parents', so this puts every
+    // command after its parents.
+    commands.sort_by_key(|command| command.max_cut);
+
+    let body = ArchiveBody {
+        version: ARCHIVE_VERSION,
+        graph,
+        commands,
+    };
+    let mut archive = postcard::to_allocvec(&body).map_err(ClientError::ArchiveEncoding)?;
+    let digest: [u8; DIGEST_LEN] = Sha512::hash(&archive).into_array().into();
+    archive.extend_from_slice(&digest);
+    Ok(archive)
+}
+
+/// Reads the graph ID and commands from an archive, checking its digest.
+pub(super) fn import(archive: &[u8]) -> Result<(GraphId, Vec<ArchivedCommand>), ClientError> {
+    let split = archive
+        .len()
+        .checked_sub(DIGEST_LEN)
+        .ok_or(ClientError::ArchiveCorrupt)?;
+    let (body, digest) = archive.split_at(split);
+    let expected: [u8; DIGEST_LEN] = Sha512::hash(body).into_array().into();
+    if digest != expected {
+        return Err(ClientError::ArchiveCorrupt);
+    }
+
+    let body: ArchiveBody = postcard::from_bytes(body).map_err(ClientError::ArchiveEncoding)?;
+    if body.version != ARCHIVE_VERSION {
+        return Err(ClientError::UnsupportedArchive(body.version));
+    }
+    Ok((body.graph, body.commands))
+}
@@ -0,0 +1,188 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+use buggy::Bug;
+
+use crate::{
+    Address, Command, CommandId, Fact, FactPerspective, Keys, Perspective, PolicyId, Prior, Query,
+    QueryMut, Sink, StorageError,
+};
+
+/// The results of an action previewed with
+/// [`ClientState::dry_run()`](crate::ClientState::dry_run), none of which were committed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DryRun<E> {
+    /// The effects the action would emit, in order.
+    pub effects: Vec<E>,
+    /// The changes the action would make to facts, in order.
+    pub facts: Vec<FactDelta>,
+}
+
+/// A change to a fact.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FactDelta {
+    /// The fact `name` with `keys` is created or replaced with `value`.
+    Insert {
+        /// The name of the fact.
+        name: String,
+        /// The keys of the fact.
+        keys: Keys,
+        /// The new value of the fact.
+        value: Box<[u8]>,
+    },
+    /// The fact `name` with `keys` is deleted.
+    Delete {
+        /// The name of the fact.
+        name: String,
+        /// The keys of the fact.
+        keys: Keys,
+    },
+}
+
+/// Wraps a perspective which will be discarded, recording the changes made to its facts.
+pub(super) struct DryRunPerspective<P> {
+    inner: P,
+    facts: Vec<FactDelta>,
+}
+
+impl<P> DryRunPerspective<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            facts: Vec::new(),
+        }
+    }
+
+    /// Discards the perspective, returning the changes made to its facts.
+    pub fn into_facts(self) -> Vec<FactDelta> {
+        self.facts
+    }
+}
+
+impl<P: Query> Query for DryRunPerspective<P> {
+    fn query(&self, name: &str, keys: &[Box<[u8]>]) -> Result<Option<Box<[u8]>>, StorageError> {
+        self.inner.query(name, keys)
+    }
+
+    type QueryIterator = P::QueryIterator;
+
+    fn query_prefix(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+    ) -> Result<Self::QueryIterator, StorageError> {
+        self.inner.query_prefix(name, prefix)
+    }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        self.inner.query_page(name, prefix, after, limit)
+    }
+}
+
+impl<P: QueryMut> QueryMut for DryRunPerspective<P> {
+    fn insert(&mut self, name: String, keys: Keys, value: Box<[u8]>) {
+        self.facts.push(FactDelta::Insert {
+            name: name.clone(),
+            keys: keys.clone(),
+            value: value.clone(),
+        });
+        self.inner.insert(name, keys, value);
+    }
+
+    fn delete(&mut self, name: String, keys: Keys) {
+        self.facts.push(FactDelta::Delete {
+            name: name.clone(),
+            keys: keys.clone(),
+        });
+        self.inner.delete(name, keys);
+    }
+}
+
+impl<P: FactPerspective> FactPerspective for DryRunPerspective<P> {}
+
+impl<P: Perspective> Perspective for DryRunPerspective<P> {
+    fn policy(&self) -> PolicyId {
+        self.inner.policy()
+    }
+
+    fn add_command(&mut self, command: &impl Command) -> Result<usize, StorageError> {
+        self.inner.add_command(command)
+    }
+
+    fn includes(&self, id: CommandId) -> bool {
+        self.inner.includes(id)
+    }
+
+    fn head_address(&self) -> Result<Prior<Address>, Bug> {
+        self.inner.head_address()
+    }
+}
+
+/// Collects the effects of a dry run.
+///
+/// A rollback only discards the effects consumed since the last `begin`.
+pub(super) struct EffectLog<E> {
+    effects: Vec<E>,
+    start: usize,
+}
+
+impl<E> EffectLog<E> {
+    pub fn new() -> Self {
+        Self {
+            effects: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Returns the effects which were not rolled back.
+    pub fn into_effects(self) -> Vec<E> {
+        self.effects
+    }
+}
+
+impl<E> Sink<E> for EffectLog<E> {
+    fn begin(&mut self) {
+        self.start = self.effects.len();
+    }
+
+    fn consume(&mut self, effect: E) {
+        self.effects.push(effect);
+    }
+
+    fn rollback(&mut self) {
+        self.effects.truncate(self.start);
+    }
+
+    fn commit(&mut self) {
+        self.start = self.effects.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effect_log_rollback() {
+        let mut log = EffectLog::new();
+        log.begin();
+        log.consume(1);
+        log.commit();
+
+        log.begin();
+        log.consume(2);
+        log.consume(3);
+        log.rollback();
+
+        log.begin();
+        log.consume(4);
+        log.commit();
+
+        assert_eq!(log.into_effects(), [1, 4]);
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::fmt;
+
+use spin::Mutex;
+
+use crate::Sink;
+
+type Queue<E> = Mutex<VecDeque<E>>;
+
+/// The effects committed by a [`ClientState`](crate::ClientState), from
+/// [`ClientState::subscribe_effects`](crate::ClientState::subscribe_effects).
+///
+/// Effects are queued until they are taken with [`Iterator::next`], which returns `None`
+/// once the queue is empty. More effects may be returned after later commits. Dropping
+/// the subscription unsubscribes it.
+pub struct EffectSubscription<E> {
+    queue: Arc<Queue<E>>,
+}
+
+impl<E> EffectSubscription<E> {
+    /// Returns the number of queued effects.
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    /// Reports whether no effects are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+}
+
+impl<E> Iterator for EffectSubscription<E> {
+    type Item = E;
+
+    fn next(&mut self) -> Option<E> {
+        self.queue.lock().pop_front()
+    }
+}
+
+impl<E> fmt::Debug for EffectSubscription<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EffectSubscription")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// The subscriptions to a client's effects.
+pub(super) struct Subscribers<E> {
+    queues: Vec<Weak<Queue<E>>>,
+    /// Copies effects for each subscriber. It is set by the first subscription, which
+    /// requires effects to be [`Clone`], so that other methods don't.
+    clone: Option<fn(&E) -> E>,
+}
+
+impl<E> Subscribers<E> {
+    pub const fn new() -> Self {
+        Self {
+            queues: Vec::new(),
+            clone: None,
+        }
+    }
+
+    pub fn subscribe(&mut self) -> EffectSubscription<E>
+    where
+        E: Clone,
+    {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        self.queues.push(Arc::downgrade(&queue));
+        self.clone = Some(E::clone);
+        EffectSubscription { queue }
+    }
+
+    /// Wraps `sink` so committed effects are also sent to each subscriber.
+    pub fn tee<'a, S>(&'a mut self, sink: &'a mut S) -> TeeSink<'a, S, E> {
+        TeeSink {
+            inner: sink,
+            subscribers: self,
+            pending: Vec::new(),
+        }
+    }
+
+    fn publish(&mut self, effects: &mut Vec<E>) {
+        let Some(clone) = self.clone else {
+            return;
+        };
+        self.queues.retain(|queue| {
+            let Some(queue) = queue.upgrade() else {
+                return false;
+            };
+            queue.lock().extend(effects.iter().map(clone));
+            true
+        });
+        effects.clear();
+    }
+}
+
+impl<E> fmt::Debug for Subscribers<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscribers")
+            .field("len", &self.queues.len())
+            .finish()
+    }
+}
+
+/// A [`Sink`] which also sends effects to a client's subscribers once they are committed.
+pub(super) struct TeeSink<'a, S, E> {
+    inner: &'a mut S,
+    subscribers: &'a mut Subscribers<E>,
+    pending: Vec<E>,
+}
+
+impl<S: Sink<E>, E> Sink<E> for TeeSink<'_, S, E> {
+    fn begin(&mut self) {
+        self.pending.clear();
+        self.inner.begin();
+    }
+
+    fn consume(&mut self, effect: E) {
+        if let Some(clone) = self.subscribers.clone {
+            if !self.subscribers.queues.is_empty() {
+                self.pending.push(clone(&effect));
+            }
+        }
+        self.inner.consume(effect);
+    }
+
+    fn rollback(&mut self) {
+        self.pending.clear();
+        self.inner.rollback();
+    }
+
+    fn commit(&mut self) {
+        self.inner.commit();
+        self.subscribers.publish(&mut self.pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::Rng;
+
+    use crate::{
+        protocol::{TestActions, TestEffect, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink, PeerCache, SyncRequester, SyncResponder, SyncType,
+        MAX_SYNC_MESSAGE_SIZE,
+    };
+
+    #[test]
+    fn test_subscribe_effects() {
+        let mut from = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let mut to = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let mut local = from.subscribe_effects();
+        let mut synced = to.subscribe_effects();
+
+        let storage_id = from
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for value in [10, 20, 30] {
+            from.action(storage_id, &mut NullSink, TestActions::SetValue(0, value))
+                .unwrap();
+        }
+        assert_eq!(local.len(), 3);
+        assert!(local.by_ref().eq([10, 20, 30].map(TestEffect::Got)));
+        assert!(local.is_empty());
+
+        let mut requester = SyncRequester::new(storage_id, &mut Rng, ());
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester
+            .poll(&mut buffer, to.provider(), &mut PeerCache::new())
+            .unwrap();
+        let SyncType::Poll { request, .. } =
+            postcard::from_bytes::<SyncType<()>>(&buffer[..len]).unwrap()
+        else {
+            panic!("expected a poll")
+        };
+        let mut responder = SyncResponder::new(());
+        responder.receive(request).unwrap();
+        let len = responder
+            .poll(&mut buffer, from.provider(), &mut PeerCache::new())
+            .unwrap();
+        let cmds = requester.receive(&buffer[..len]).unwrap().unwrap();
+        let mut trx = to.transaction(storage_id);
+        to.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())
+            .unwrap();
+        to.commit(&mut trx, &mut NullSink).unwrap();
+        assert!(synced.eq([10, 20, 30].map(TestEffect::Got)));
+
+        // Dropped subscriptions are no longer sent effects.
+        drop(local);
+        from.action(storage_id, &mut NullSink, TestActions::SetValue(0, 40))
+            .unwrap();
+        assert!(from.subscribers.queues.is_empty());
+    }
+}
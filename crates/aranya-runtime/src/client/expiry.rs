@@ -0,0 +1,138 @@
+//! Expiry of commands. See [`Policy::expiry`].
+
+use alloc::string::String;
+
+use buggy::BugExt;
+
+use super::introspect;
+use crate::{
+    ClientError, Command, EngineError, Location, Policy, Rejection, Segment, Storage, StorageError,
+};
+
+/// The rejection of an expired command.
+pub(super) fn rejection() -> Rejection {
+    Rejection {
+        reason: Some(String::from("command expired")),
+        location: None,
+    }
+}
+
+/// Rejects `command` if its own max cut is past its expiry.
+pub(super) fn check(policy: &impl Policy, command: &impl Command) -> Result<(), EngineError> {
+    if let Some(expiry) = policy.expiry(command)? {
+        if command.max_cut()? > expiry {
+            return Err(EngineError::Check(rejection()));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects each command in `segment`, which was written by an action, if it is
+/// expired.
+pub(super) fn check_segment(
+    policy: &impl Policy,
+    segment: &impl Segment,
+) -> Result<(), EngineError> {
+    for command in segment.get_from(segment.first_location()) {
+        check(policy, &command)?;
+    }
+    Ok(())
+}
+
+/// The heads of two branches being merged.
+pub(super) struct Heads {
+    left: (Location, usize),
+    right: (Location, usize),
+}
+
+impl Heads {
+    pub fn new(
+        storage: &impl Storage,
+        left: Location,
+        right: Location,
+    ) -> Result<Self, StorageError> {
+        let max_cut = |location: Location| -> Result<usize, StorageError> {
+            let segment = storage.get_segment(location)?;
+            let command = segment
+                .get_command(location)
+                .assume("location must exist")?;
+            Ok(command.max_cut()?)
+        };
+        Ok(Self {
+            left: (left, max_cut(left)?),
+            right: (right, max_cut(right)?),
+        })
+    }
+
+    /// Reports whether `command`, at `location` in the braid of the branches, has
+    /// expired: whether the head of a branch which does not include it is past its
+    /// expiry.
+    pub fn expired(
+        &self,
+        storage: &impl Storage,
+        policy: &impl Policy,
+        command: &impl Command,
+        location: Location,
+    ) -> Result<bool, ClientError> {
+        let Some(expiry) = policy.expiry(command)? else {
+            return Ok(false);
+        };
+        for (head, max_cut) in [self.left, self.right] {
+            if max_cut <= expiry || location == head {
+                continue;
+            }
+            if !introspect::is_ancestor(storage, location, head)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink,
+    };
+
+    #[test]
+    fn test_expired_on_merge() {
+        let mut alice = ClientState::new(TestEngine::with_ttl(2), MemStorageProvider::new());
+        let mut bob = ClientState::new(TestEngine::with_ttl(2), MemStorageProvider::new());
+        bob.log_recalls();
+
+        let storage_id = alice
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+
+        // Bob's command is concurrent with more of the graph than its TTL allows.
+        let expired = bob
+            .action(storage_id, &mut NullSink, TestActions::SetValue(0, 0))
+            .unwrap()
+            .commands[0];
+        for i in 1..=4 {
+            alice
+                .action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+
+        let recalls = bob.take_recalls();
+        assert_eq!(recalls.len(), 1);
+        assert_eq!(recalls[0].command, expired);
+        assert_eq!(recalls[0].rejection, super::rejection());
+
+        for (key, present) in [(0u64, false), (1, true), (4, true)] {
+            let keys = [Box::from(key.to_be_bytes())];
+            let facts = bob.query_facts(storage_id, "payload", &keys).unwrap();
+            assert_eq!(!facts.is_empty(), present);
+        }
+    }
+}
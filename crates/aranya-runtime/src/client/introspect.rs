@@ -0,0 +1,169 @@
+//! Read-only views of the command graph, for admin and debugging tools.
+
+use alloc::{
+    collections::{BTreeSet, BinaryHeap},
+    vec::Vec,
+};
+
+use buggy::BugExt;
+
+use crate::{
+    Address, Command, CommandId, Location, PolicyId, Prior, Priority, Segment, Storage,
+    StorageError,
+};
+
+/// Metadata about a command in a graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// The command's ID.
+    pub id: CommandId,
+    /// The command's priority.
+    pub priority: Priority,
+    /// The command's parents.
+    pub parent: Prior<Address>,
+    /// The length of the longest path from the command to the root of the graph.
+    pub max_cut: usize,
+    /// The policy the command was evaluated with.
+    pub policy: PolicyId,
+    /// Where the command is stored.
+    pub location: Location,
+    /// The length of the serialized command in bytes.
+    pub len: usize,
+}
+
+impl CommandInfo {
+    /// Returns the address of the command.
+    pub fn address(&self) -> Address {
+        Address {
+            id: self.id,
+            max_cut: self.max_cut,
+        }
+    }
+
+    pub(super) fn read(storage: &impl Storage, location: Location) -> Result<Self, StorageError> {
+        let segment = storage.get_segment(location)?;
+        let command = segment
+            .get_command(location)
+            .ok_or(StorageError::CommandOutOfBounds(location))?;
+        Ok(Self {
+            id: command.id(),
+            priority: command.priority(),
+            parent: command.parent(),
+            max_cut: command.max_cut()?,
+            policy: segment.policy(),
+            location,
+            len: command.bytes().len(),
+        })
+    }
+}
+
+/// Returns the location of the command with ID `id`, if it is reachable from the head
+/// of the graph.
+///
+/// Unlike [`Storage::get_location`], this does not need the command's max cut, so it
+/// may search the whole graph.
+pub(super) fn find(
+    storage: &impl Storage,
+    id: CommandId,
+) -> Result<Option<Location>, StorageError> {
+    find_from(storage, storage.get_head()?, id)
+}
+
+/// Returns the location of the command with ID `id`, if it is `start` or one of its
+/// ancestors.
+pub(super) fn find_from(
+    storage: &impl Storage,
+    start: Location,
+    id: CommandId,
+) -> Result<Option<Location>, StorageError> {
+    let mut seen = BTreeSet::new();
+    let mut queue = Vec::new();
+    queue.push(start);
+    while let Some(location) = queue.pop() {
+        if !seen.insert(location.segment) {
+            continue;
+        }
+        let segment = storage.get_segment(location)?;
+        let first = segment.first_location();
+        if let Some(i) = segment.get_from(first).iter().position(|c| c.id() == id) {
+            return Ok(Some(Location::new(first.segment, i)));
+        }
+        queue.extend(segment.prior());
+    }
+    Ok(None)
+}
+
+/// Returns whether the command at `ancestor` is a strict ancestor of the command at
+/// `descendant`.
+pub(super) fn is_ancestor(
+    storage: &impl Storage,
+    ancestor: Location,
+    descendant: Location,
+) -> Result<bool, StorageError> {
+    if ancestor.same_segment(descendant) {
+        return Ok(ancestor.command < descendant.command);
+    }
+    let segment = storage.get_segment(descendant)?;
+    storage.is_ancestor(ancestor, &segment)
+}
+
+/// An iterator over the ancestors of a command, from
+/// [`ClientState::ancestors`](crate::ClientState::ancestors).
+///
+/// Ancestors are yielded once each, in descending order of max cut.
+pub struct Ancestors<'a, S> {
+    storage: &'a S,
+    /// The ancestors found but not yet yielded, by max cut.
+    queue: BinaryHeap<(usize, Location)>,
+    seen: BTreeSet<Location>,
+}
+
+impl<'a, S: Storage> Ancestors<'a, S> {
+    pub(super) fn new(storage: &'a S, location: Location) -> Result<Self, StorageError> {
+        let mut ancestors = Self {
+            storage,
+            queue: BinaryHeap::new(),
+            seen: BTreeSet::new(),
+        };
+        ancestors.push_parents(location)?;
+        Ok(ancestors)
+    }
+
+    fn push_parents(&mut self, location: Location) -> Result<(), StorageError> {
+        let parents = match location.previous() {
+            Some(previous) => Prior::Single(previous),
+            None => self.storage.get_segment(location)?.prior(),
+        };
+        for parent in parents {
+            if !self.seen.insert(parent) {
+                continue;
+            }
+            let segment = self.storage.get_segment(parent)?;
+            let max_cut = segment
+                .get_command(parent)
+                .assume("location must exist")?
+                .max_cut()?;
+            self.queue.push((max_cut, parent));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, location: Location) -> Result<CommandInfo, StorageError> {
+        let info = CommandInfo::read(self.storage, location)?;
+        self.push_parents(location)?;
+        Ok(info)
+    }
+}
+
+impl<S: Storage> Iterator for Ancestors<'_, S> {
+    type Item = Result<CommandInfo, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, location) = self.queue.pop()?;
+        let result = self.step(location);
+        if result.is_err() {
+            self.queue.clear();
+        }
+        Some(result)
+    }
+}
@@ -0,0 +1,176 @@
+use alloc::{
+    collections::{BTreeMap, BinaryHeap},
+    vec,
+    vec::Vec,
+};
+use core::cmp::Reverse;
+
+use buggy::{Bug, BugExt};
+
+use crate::{Command, CommandId, Priority};
+
+/// Where a command falls among the commands it is concurrent with in a braid.
+///
+/// Keys are ordered by `priority`, then `bias`, then `id`. Since command IDs are unique,
+/// so are keys.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BraidKey {
+    /// The command's priority.
+    pub priority: Priority,
+    /// Orders commands with the same priority.
+    pub bias: u64,
+    /// The command's ID, which breaks any remaining ties.
+    pub id: CommandId,
+}
+
+/// Decides the order of concurrent commands when branches of the graph are braided.
+///
+/// Every client must braid the same branches in the same order, or they will disagree
+/// about the state of the graph, so the order is chosen by the [`Policy`](crate::Policy)
+/// with [`Policy::braid_order`](crate::Policy::braid_order). The key must only depend on
+/// the command.
+pub trait BraidOrder {
+    /// Returns the key `command` is ordered by.
+    fn key(&self, command: &dyn Command) -> BraidKey;
+}
+
+/// The default [`BraidOrder`], which orders commands by their priority and then their ID.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DefaultBraidOrder;
+
+impl BraidOrder for DefaultBraidOrder {
+    fn key(&self, command: &dyn Command) -> BraidKey {
+        BraidKey {
+            priority: command.priority(),
+            bias: 0,
+            id: command.id(),
+        }
+    }
+}
+
+/// Orders received `commands` so that each follows its parents among them. Of the
+/// commands whose parents have been ordered, the one with the highest [`Priority`]
+/// goes first, and commands with the same priority keep their order. Commands whose
+/// parents can't be ordered, such as ones missing from `commands`, go last.
+///
+/// See [`ClientState::with_prioritized_receive`](crate::ClientState::with_prioritized_receive).
+pub(super) fn prioritize<C: Command>(commands: &[C]) -> Result<Vec<&C>, Bug> {
+    let index: BTreeMap<CommandId, usize> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, command)| (command.id(), i))
+        .collect();
+
+    // The number of each command's parents which have not been ordered.
+    let mut waiting = vec![0usize; commands.len()];
+    let mut children = vec![Vec::new(); commands.len()];
+    for (i, command) in commands.iter().enumerate() {
+        for parent in command.parent() {
+            if let Some(&p) = index.get(&parent.id) {
+                waiting[i] = waiting[i].checked_add(1).assume("must not overflow")?;
+                children[p].push(i);
+            }
+        }
+    }
+
+    let mut ready: BinaryHeap<(Priority, Reverse<usize>)> = waiting
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count == 0)
+        .map(|(i, _)| (commands[i].priority(), Reverse(i)))
+        .collect();
+    let mut ordered = Vec::with_capacity(commands.len());
+    let mut done = vec![false; commands.len()];
+    while let Some((_, Reverse(i))) = ready.pop() {
+        ordered.push(&commands[i]);
+        done[i] = true;
+        for &child in &children[i] {
+            waiting[child] = waiting[child]
+                .checked_sub(1)
+                .assume("child must be waiting")?;
+            if waiting[child] == 0 {
+                ready.push((commands[child].priority(), Reverse(child)));
+            }
+        }
+    }
+    ordered.extend(
+        commands
+            .iter()
+            .zip(done)
+            .filter(|&(_, done)| !done)
+            .map(|(command, _)| command),
+    );
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, Prior};
+
+    struct Cmd {
+        id: CommandId,
+        parent: Prior<Address>,
+        priority: Priority,
+    }
+
+    impl Command for Cmd {
+        fn priority(&self) -> Priority {
+            self.priority.clone()
+        }
+
+        fn id(&self) -> CommandId {
+            self.id
+        }
+
+        fn parent(&self) -> Prior<Address> {
+            self.parent
+        }
+
+        fn policy(&self) -> Option<&[u8]> {
+            None
+        }
+
+        fn bytes(&self) -> &[u8] {
+            &[]
+        }
+
+        fn max_cut(&self) -> Result<usize, Bug> {
+            Ok(0)
+        }
+    }
+
+    fn cmd(name: &str, parent: Option<&str>, priority: u32) -> Cmd {
+        let address = |name: &str| Address {
+            id: CommandId::hash_for_testing_only(name.as_bytes()),
+            max_cut: 0,
+        };
+        Cmd {
+            id: CommandId::hash_for_testing_only(name.as_bytes()),
+            parent: parent.map_or(Prior::None, |parent| Prior::Single(address(parent))),
+            priority: Priority::Basic(priority),
+        }
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let commands = [
+            cmd("a", Some("x"), 1),
+            cmd("b", Some("a"), 1),
+            // Received before its parent, but applied as soon as its parent is.
+            cmd("key", Some("c"), 5),
+            cmd("c", Some("y"), 2),
+            cmd("d", Some("z"), 3),
+        ];
+        let ordered: Vec<_> = prioritize(&commands)
+            .unwrap()
+            .into_iter()
+            .map(|command| command.id())
+            .collect();
+        let expected: Vec<_> = ["d", "c", "key", "a", "b"]
+            .into_iter()
+            .map(|name| CommandId::hash_for_testing_only(name.as_bytes()))
+            .collect();
+        assert_eq!(ordered, expected);
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::fmt;
+
+use crate::{CommandId, Rejection, Sink};
+
+/// The most recalls a [`ClientState`](crate::ClientState) keeps. Older recalls are
+/// dropped.
+pub const RECALL_LOG_MAX: usize = 256;
+
+/// A command which entered recall on this client, because it failed a check when its
+/// branch was merged with another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recall<E> {
+    /// The recalled command.
+    pub command: CommandId,
+    /// Why the command was rejected, including the location of the failed check.
+    pub rejection: Rejection,
+    /// The effects produced by the command's recall block.
+    pub effects: Vec<E>,
+}
+
+/// A log of recalled commands.
+pub(super) struct RecallLog<E> {
+    recalls: VecDeque<Recall<E>>,
+    /// Copies recall effects for the log. Recalls are only logged once it is set, which
+    /// requires effects to be [`Clone`].
+    clone: Option<fn(&E) -> E>,
+}
+
+impl<E> RecallLog<E> {
+    pub const fn new() -> Self {
+        Self {
+            recalls: VecDeque::new(),
+            clone: None,
+        }
+    }
+
+    /// Starts logging recalls.
+    pub fn enable(&mut self)
+    where
+        E: Clone,
+    {
+        self.clone = Some(E::clone);
+    }
+
+    /// Returns a new, empty log which logs recalls if this one does.
+    pub fn child(&self) -> Self {
+        Self {
+            recalls: VecDeque::new(),
+            clone: self.clone,
+        }
+    }
+
+    /// Moves the recalls from `other` to the end of this log.
+    pub fn append(&mut self, other: &mut Self) {
+        for recall in other.recalls.drain(..) {
+            self.push(recall);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Recall<E>> {
+        self.recalls.iter()
+    }
+
+    pub fn take(&mut self) -> Vec<Recall<E>> {
+        self.recalls.drain(..).collect()
+    }
+
+    /// Wraps `sink` to record the effects of `command`, which may be recalled.
+    pub fn record<'a, S: Sink<E>>(
+        &'a mut self,
+        command: CommandId,
+        sink: &'a mut S,
+    ) -> RecallSink<'a, S, E> {
+        RecallSink {
+            inner: sink,
+            log: self,
+            command,
+            effects: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, recall: Recall<E>) {
+        if self.recalls.len() >= RECALL_LOG_MAX {
+            self.recalls.pop_front();
+        }
+        self.recalls.push_back(recall);
+    }
+}
+
+impl<E> fmt::Debug for RecallLog<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecallLog")
+            .field("len", &self.recalls.len())
+            .finish()
+    }
+}
+
+/// A [`Sink`] which records the effects of a command, so they can be logged if it is
+/// recalled.
+pub(super) struct RecallSink<'a, S, E> {
+    inner: &'a mut S,
+    log: &'a mut RecallLog<E>,
+    command: CommandId,
+    effects: Vec<E>,
+}
+
+impl<S, E> RecallSink<'_, S, E> {
+    /// Logs the command as recalled because of `rejection`.
+    pub fn recalled(self, rejection: Rejection) {
+        if self.log.clone.is_some() {
+            self.log.push(Recall {
+                command: self.command,
+                rejection,
+                effects: self.effects,
+            });
+        }
+    }
+}
+
+impl<S: Sink<E>, E> Sink<E> for RecallSink<'_, S, E> {
+    fn begin(&mut self) {
+        self.inner.begin();
+    }
+
+    fn consume(&mut self, effect: E) {
+        if let Some(clone) = self.log.clone {
+            self.effects.push(clone(&effect));
+        }
+        self.inner.consume(effect);
+    }
+
+    fn rollback(&mut self) {
+        self.inner.rollback();
+    }
+
+    fn commit(&mut self) {
+        self.inner.commit();
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::vec::Vec;
+
+use crate::{Command, CommandId, GraphId, Segment, StorageError};
+
+/// A record of the commands committed by an action, from [`ClientState::action`] or
+/// [`ActionTransaction::commit`].
+///
+/// Pass it to [`ClientState::is_durable`] or [`ClientState::wait_durable`] to learn
+/// when the commands are safely persisted, such as before acknowledging them to an
+/// external system.
+///
+/// [`ClientState::action`]: crate::ClientState::action
+/// [`ActionTransaction::commit`]: crate::ActionTransaction::commit
+/// [`ClientState::is_durable`]: crate::ClientState::is_durable
+/// [`ClientState::wait_durable`]: crate::ClientState::wait_durable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    /// The graph the commands were committed to.
+    pub storage_id: GraphId,
+    /// The IDs of the committed commands, in order.
+    pub commands: Vec<CommandId>,
+    /// The storage sequence of the commit, which is the max cut of its last command.
+    /// See [`Storage::durable_sequence`](crate::Storage::durable_sequence).
+    pub sequence: usize,
+}
+
+impl Receipt {
+    /// Creates a receipt for the commands in `segment`, which is about to be committed.
+    pub(super) fn new(storage_id: GraphId, segment: &impl Segment) -> Result<Self, StorageError> {
+        let commands = segment
+            .get_from(segment.first_location())
+            .iter()
+            .map(|command| command.id())
+            .collect();
+        let sequence = segment.head()?.max_cut()?;
+        Ok(Self {
+            storage_id,
+            commands,
+            sequence,
+        })
+    }
+}
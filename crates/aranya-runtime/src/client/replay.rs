@@ -0,0 +1,349 @@
+//! Deterministic replay of a client's changes to its graphs.
+//!
+//! See [`ClientState::record_replay`](crate::ClientState::record_replay) and
+//! [`Replayer`].
+//!
+//! A replay log records the commands each action committed and each batch of commands
+//! received, in the order the client applied them. Because commands are evaluated
+//! deterministically, adding them to another client in the same order rebuilds the
+//! same graphs and facts, which helps to debug reports of peers whose state diverged.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{fmt, slice};
+
+use buggy::Bug;
+use serde::{Deserialize, Serialize};
+
+use super::archive::ArchivedCommand;
+use crate::{
+    ClientError, ClientState, Command, CommandId, Engine, GraphId, PeerCache, Segment, Sink,
+    StorageProvider, Transaction,
+};
+
+/// The version of the replay log format written by this crate.
+const REPLAY_VERSION: u32 = 1;
+
+/// How the commands of a [`ReplayStep`] were applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayKind {
+    /// The commands were committed by an action.
+    Action,
+    /// The commands were added to a [`Transaction`], such as when syncing.
+    Receive,
+    /// A [`Transaction`] was committed.
+    Commit,
+}
+
+/// One change to a graph in a [`ReplayLog`].
+#[derive(Serialize, Deserialize)]
+pub struct ReplayStep {
+    storage_id: GraphId,
+    kind: ReplayKind,
+    commands: Vec<ArchivedCommand>,
+}
+
+impl ReplayStep {
+    /// Records the commands in `segment`, which an action is about to commit.
+    pub(super) fn action(storage_id: GraphId, segment: &impl Segment) -> Result<Self, Bug> {
+        let commands = segment
+            .get_from(segment.first_location())
+            .iter()
+            .map(ArchivedCommand::from_cmd)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            storage_id,
+            kind: ReplayKind::Action,
+            commands,
+        })
+    }
+
+    /// Records `commands` added to a transaction.
+    pub(super) fn receive(storage_id: GraphId, commands: &[impl Command]) -> Result<Self, Bug> {
+        let commands = commands
+            .iter()
+            .map(ArchivedCommand::from_cmd)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            storage_id,
+            kind: ReplayKind::Receive,
+            commands,
+        })
+    }
+
+    /// Records the commit of a transaction.
+    pub(super) const fn commit(storage_id: GraphId) -> Self {
+        Self {
+            storage_id,
+            kind: ReplayKind::Commit,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Returns the ID of the graph which was changed.
+    pub fn storage_id(&self) -> GraphId {
+        self.storage_id
+    }
+
+    /// Returns how the step's commands were applied.
+    pub fn kind(&self) -> ReplayKind {
+        self.kind
+    }
+
+    /// Returns the IDs of the step's commands, in order.
+    pub fn commands(&self) -> impl Iterator<Item = CommandId> + '_ {
+        self.commands.iter().map(|command| command.id())
+    }
+}
+
+impl fmt::Debug for ReplayStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayStep")
+            .field("storage_id", &self.storage_id)
+            .field("kind", &self.kind)
+            .field("commands", &self.commands.len())
+            .finish()
+    }
+}
+
+/// The changes a client made to its graphs, in order, from
+/// [`ClientState::replay_log`].
+///
+/// The log holds a copy of every command the client applied, so it grows until it is
+/// taken with [`ClientState::take_replay_log`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayLog {
+    version: u32,
+    steps: Vec<ReplayStep>,
+}
+
+impl ReplayLog {
+    /// Creates an empty log.
+    pub const fn new() -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Returns the steps of the log, in the order they were applied.
+    pub fn steps(&self) -> &[ReplayStep] {
+        &self.steps
+    }
+
+    /// Returns the number of steps in the log.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Reports whether the log has no steps.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Encodes the log, such as to attach it to a bug report.
+    pub fn encode(&self) -> Result<Vec<u8>, ClientError> {
+        postcard::to_allocvec(self).map_err(ClientError::ReplayEncoding)
+    }
+
+    /// Decodes a log written by [`ReplayLog::encode`].
+    pub fn decode(data: &[u8]) -> Result<Self, ClientError> {
+        let log: Self = postcard::from_bytes(data).map_err(ClientError::ReplayEncoding)?;
+        if log.version != REPLAY_VERSION {
+            return Err(ClientError::UnsupportedReplayLog(log.version));
+        }
+        Ok(log)
+    }
+}
+
+impl Default for ReplayLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Records a client's changes when enabled.
+#[derive(Debug)]
+pub(super) struct Recorder {
+    log: Option<ReplayLog>,
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self { log: None }
+    }
+
+    /// Starts recording, if not already.
+    pub fn enable(&mut self) {
+        self.log.get_or_insert_with(ReplayLog::new);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.log.is_some()
+    }
+
+    pub fn log(&self) -> Option<&ReplayLog> {
+        self.log.as_ref()
+    }
+
+    /// Returns the recorded log, continuing to record to a new one.
+    pub fn take(&mut self) -> Option<ReplayLog> {
+        self.log.as_mut().map(core::mem::take)
+    }
+
+    /// Appends `step` to the log, if recording.
+    pub fn record(&mut self, step: Option<ReplayStep>) {
+        if let (Some(log), Some(step)) = (&mut self.log, step) {
+            log.steps.push(step);
+        }
+    }
+}
+
+/// Applies the steps of a [`ReplayLog`] to a client, one at a time.
+///
+/// The client should not have the log's graphs, or have them only as they were when
+/// recording started. Its state can be inspected between steps to find where it
+/// diverges from a peer's.
+pub struct Replayer<'a, SP: StorageProvider, E: Engine> {
+    steps: slice::Iter<'a, ReplayStep>,
+    /// The open transactions of commands received before they were committed.
+    transactions: BTreeMap<GraphId, Transaction<SP, E>>,
+}
+
+impl<'a, SP: StorageProvider, E: Engine> Replayer<'a, SP, E> {
+    /// Creates a replayer for the steps of `log`.
+    pub fn new(log: &'a ReplayLog) -> Self {
+        Self {
+            steps: log.steps.iter(),
+            transactions: BTreeMap::new(),
+        }
+    }
+
+    /// Applies the next step to `client`, writing the effects of its commands to
+    /// `sink`. Returns the step, or `None` once every step has been applied.
+    pub fn step(
+        &mut self,
+        client: &mut ClientState<E, SP>,
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<Option<&'a ReplayStep>, ClientError> {
+        let Some(step) = self.steps.next() else {
+            return Ok(None);
+        };
+        let storage_id = step.storage_id;
+        match step.kind {
+            ReplayKind::Action => {
+                let mut trx = client.transaction(storage_id);
+                client.add_commands(&mut trx, sink, &step.commands, &mut PeerCache::new())?;
+                client.commit(&mut trx, sink)?;
+            }
+            ReplayKind::Receive => {
+                let trx = self
+                    .transactions
+                    .entry(storage_id)
+                    .or_insert_with(|| client.transaction(storage_id));
+                client.add_commands(trx, sink, &step.commands, &mut PeerCache::new())?;
+            }
+            ReplayKind::Commit => {
+                if let Some(mut trx) = self.transactions.remove(&storage_id) {
+                    client.commit(&mut trx, sink)?;
+                }
+            }
+        }
+        Ok(Some(step))
+    }
+
+    /// Applies the remaining steps to `client`.
+    pub fn finish(
+        mut self,
+        client: &mut ClientState<E, SP>,
+        sink: &mut impl Sink<E::Effect>,
+    ) -> Result<(), ClientError> {
+        while self.step(client, sink)?.is_some() {}
+        Ok(())
+    }
+}
+
+impl<SP: StorageProvider, E: Engine> fmt::Debug for Replayer<'_, SP, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replayer")
+            .field("remaining", &self.steps.len())
+            .field("transactions", &self.transactions.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        NullSink, Storage,
+    };
+
+    type Client = ClientState<TestEngine, MemStorageProvider>;
+
+    fn new_client() -> Client {
+        ClientState::new(TestEngine::new(), MemStorageProvider::new())
+    }
+
+    fn head(client: &mut Client, storage_id: GraphId) -> CommandId {
+        let storage = client.provider().get_storage(storage_id).unwrap();
+        storage.get_command_id(storage.get_head().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut alice = new_client();
+        let mut bob = new_client();
+        bob.record_replay();
+
+        let storage_id = alice
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        alice
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 1))
+            .unwrap();
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+
+        // Both branch, then Bob merges Alice's branch.
+        alice
+            .action(storage_id, &mut NullSink, TestActions::SetValue(2, 2))
+            .unwrap();
+        bob.action(storage_id, &mut NullSink, TestActions::SetValue(3, 3))
+            .unwrap();
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+
+        let log = bob.take_replay_log().unwrap();
+        assert_eq!(
+            log.steps().iter().map(ReplayStep::kind).collect::<Vec<_>>(),
+            [
+                ReplayKind::Receive,
+                ReplayKind::Commit,
+                ReplayKind::Action,
+                ReplayKind::Receive,
+                ReplayKind::Commit,
+            ]
+        );
+        assert!(bob.replay_log().is_some_and(ReplayLog::is_empty));
+
+        let log = ReplayLog::decode(&log.encode().unwrap()).unwrap();
+        let mut carol = new_client();
+        let mut replayer = Replayer::new(&log);
+        let steps =
+            core::iter::from_fn(|| replayer.step(&mut carol, &mut NullSink).unwrap()).count();
+        assert_eq!(steps, log.len());
+        assert_eq!(head(&mut carol, storage_id), head(&mut bob, storage_id));
+
+        for key in 1u64..=3 {
+            let keys = [Box::from(key.to_be_bytes())];
+            assert_eq!(
+                carol.query_facts(storage_id, "payload", &keys).unwrap(),
+                bob.query_facts(storage_id, "payload", &keys).unwrap(),
+            );
+        }
+    }
+}
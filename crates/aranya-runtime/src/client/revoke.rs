@@ -0,0 +1,309 @@
+//! Revocation of previously accepted commands.
+//!
+//! See [`Policy::revokes`] and [`ClientState::revoke`](crate::ClientState::revoke).
+//!
+//! A revocation is a command which the policy reports revokes an earlier command in
+//! its ancestry, such as one signed by a compromised author. Before the revocation is
+//! evaluated, the commands in its ancestry since the parent of the revoked command are
+//! evaluated again without the revoked command, ordered by max cut and then by braid
+//! order. Commands which no
+//! longer pass their checks are recalled, and the effects of their recall blocks are
+//! emitted to compensate for them. Every fact whose value differs from the original
+//! evaluation is then set to its new value, so every client which evaluates the
+//! revocation reaches the same facts.
+
+use alloc::{boxed::Box, collections::BTreeSet, string::String, vec::Vec};
+
+use buggy::BugExt;
+
+use super::{dry_run::EffectLog, introspect};
+use crate::{
+    ClientError, Command, CommandId, CommandRecall, EngineError, Fact, FactPerspective, Keys,
+    Location, NullSink, Policy, Prior, Query, QueryMut, Segment, Sink, Storage, StorageError,
+};
+
+/// A fact perspective which records the facts written to it.
+struct Tracked<P> {
+    facts: P,
+    written: BTreeSet<(String, Keys)>,
+}
+
+impl<P> Tracked<P> {
+    fn new(facts: P) -> Self {
+        Self {
+            facts,
+            written: BTreeSet::new(),
+        }
+    }
+}
+
+impl<P: Query> Query for Tracked<P> {
+    type QueryIterator = P::QueryIterator;
+
+    fn query(&self, name: &str, keys: &[Box<[u8]>]) -> Result<Option<Box<[u8]>>, StorageError> {
+        self.facts.query(name, keys)
+    }
+
+    fn query_prefix(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+    ) -> Result<Self::QueryIterator, StorageError> {
+        self.facts.query_prefix(name, prefix)
+    }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        self.facts.query_page(name, prefix, after, limit)
+    }
+}
+
+impl<P: QueryMut> QueryMut for Tracked<P> {
+    fn insert(&mut self, name: String, keys: Keys, value: Box<[u8]>) {
+        self.written.insert((name.clone(), keys.clone()));
+        self.facts.insert(name, keys, value);
+    }
+
+    fn delete(&mut self, name: String, keys: Keys) {
+        self.written.insert((name.clone(), keys.clone()));
+        self.facts.delete(name, keys);
+    }
+}
+
+impl<P: QueryMut> FactPerspective for Tracked<P> {}
+
+/// The changes to facts, and the compensating effects, of revoking a command.
+pub(super) struct Revocation<E> {
+    /// The new value of each changed fact, or `None` if it was deleted.
+    changes: Vec<(String, Keys, Option<Box<[u8]>>)>,
+    /// The effects of the commands which were recalled.
+    effects: Vec<E>,
+}
+
+impl<E> Revocation<E> {
+    /// Writes the changed facts to `facts` and the compensating effects to `sink`.
+    pub fn apply(self, facts: &mut impl FactPerspective, sink: &mut impl Sink<E>) {
+        for (name, keys, value) in self.changes {
+            match value {
+                Some(value) => facts.insert(name, keys, value),
+                None => facts.delete(name, keys),
+            }
+        }
+        for effect in self.effects {
+            sink.consume(effect);
+        }
+    }
+}
+
+/// Computes the revocation of the command `revoked` by a command whose parent is at
+/// `parent`.
+pub(super) fn revoke<S: Storage, P: Policy>(
+    storage: &S,
+    policy: &P,
+    parent: Location,
+    revoked: CommandId,
+) -> Result<Revocation<P::Effect>, ClientError> {
+    let target =
+        introspect::find_from(storage, parent, revoked)?.ok_or(StorageError::NoSuchId(revoked))?;
+    if target != parent && !introspect::is_ancestor(storage, target, parent)? {
+        return Err(StorageError::NoSuchId(revoked).into());
+    }
+    let start = match target.previous() {
+        Some(previous) => previous,
+        None => match storage.get_segment(target)?.prior() {
+            Prior::Single(prior) => prior,
+            // Init commands can't be revoked, and merges write no facts.
+            Prior::None | Prior::Merge(..) => return Err(ClientError::CannotRevoke(revoked)),
+        },
+    };
+
+    let order = since(storage, policy, start, parent)?;
+
+    let facts = storage.get_fact_perspective(start)?;
+    let mut with = Tracked::new(facts.clone());
+    let mut without = Tracked::new(facts);
+    let mut effects = Vec::new();
+    for location in order {
+        let segment = storage.get_segment(location)?;
+        let command = segment
+            .get_command(location)
+            .assume("order only contains existing commands")?;
+
+        match policy.call_rule(&command, &mut with, &mut NullSink, CommandRecall::OnCheck) {
+            Ok(()) | Err(EngineError::Check(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if command.id() == revoked {
+            continue;
+        }
+        // The command's effects were emitted when it was accepted, so only the
+        // effects of recalling it are emitted again.
+        let mut log = EffectLog::new();
+        match policy.call_rule(&command, &mut without, &mut log, CommandRecall::OnCheck) {
+            Ok(()) => {}
+            Err(EngineError::Check(_)) => effects.extend(log.into_effects()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (name, keys) in with.written.union(&without.written) {
+        let old = with.query(name, keys)?;
+        let new = without.query(name, keys)?;
+        if old != new {
+            changes.push((name.clone(), keys.clone(), new));
+        }
+    }
+    Ok(Revocation { changes, effects })
+}
+
+/// Returns the locations of the commands which are `end` or its ancestors, but not
+/// `start` or its ancestors, except merges. They are ordered by max cut and then by
+/// braid order, so every command follows its parents.
+fn since<S: Storage>(
+    storage: &S,
+    policy: &impl Policy,
+    start: Location,
+    end: Location,
+) -> Result<Vec<Location>, ClientError> {
+    let order = policy.braid_order();
+    let mut found = Vec::new();
+    let mut seen = BTreeSet::new();
+    let mut queue = Vec::new();
+    queue.push(end);
+    'segments: while let Some(location) = queue.pop() {
+        let segment = storage.get_segment(location)?;
+        let mut next = Some(location);
+        while let Some(location) = next {
+            if location == start
+                || !seen.insert(location)
+                || introspect::is_ancestor(storage, location, start)?
+            {
+                // Every earlier command is also seen or an ancestor of `start`.
+                continue 'segments;
+            }
+            let command = segment
+                .get_command(location)
+                .assume("location must exist")?;
+            if !matches!(command.parent(), Prior::Merge(..)) {
+                found.push((command.max_cut()?, order.key(&command), location));
+            }
+            next = location.previous();
+        }
+        queue.extend(segment.prior());
+    }
+    found.sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    Ok(found.into_iter().map(|(_, _, location)| location).collect())
+}
+
+/// Returns the index and the revoked command of each revocation in `segment`, which
+/// was written by an action.
+pub(super) fn revocations<S: Storage>(
+    storage: &S,
+    policy: &impl Policy,
+    segment: &S::Segment,
+) -> Result<Vec<(usize, CommandId)>, ClientError> {
+    let mut revocations = Vec::new();
+    let first = segment.first_location();
+    for (i, command) in segment.get_from(first).iter().enumerate() {
+        let parent = match Location::new(first.segment, i).previous() {
+            Some(previous) => previous,
+            None => match segment.prior() {
+                Prior::Single(prior) => prior,
+                Prior::None | Prior::Merge(..) => continue,
+            },
+        };
+        let mut facts = storage.get_fact_perspective(parent)?;
+        if let Some(revoked) = policy.revokes(command, &mut facts)? {
+            revocations.push((i, revoked));
+        }
+    }
+    Ok(revocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, GraphId,
+    };
+
+    type Client = ClientState<TestEngine, MemStorageProvider>;
+
+    fn value(client: &mut Client, storage_id: GraphId, key: u64) -> Option<Box<[u8]>> {
+        let keys = [Box::from(key.to_be_bytes())];
+        let facts = client.query_facts(storage_id, "payload", &keys).unwrap();
+        facts.into_iter().next().map(|fact| fact.value)
+    }
+
+    #[test]
+    fn test_revoke() {
+        let mut alice = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = alice
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        let revoked = alice
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 10))
+            .unwrap()
+            .commands[0];
+        alice
+            .action(storage_id, &mut NullSink, TestActions::SetValue(2, 20))
+            .unwrap();
+
+        // Revocations must be made with `ClientState::revoke`.
+        assert!(matches!(
+            alice.action(storage_id, &mut NullSink, TestActions::Revoke(revoked)),
+            Err(ClientError::UnexpectedRevocation)
+        ));
+        assert!(matches!(
+            alice.revoke(
+                storage_id,
+                revoked,
+                &mut NullSink,
+                TestActions::SetValue(3, 30)
+            ),
+            Err(ClientError::UnexpectedRevocation)
+        ));
+        assert!(matches!(
+            alice.revoke(
+                storage_id,
+                storage_id.into_id().into(),
+                &mut NullSink,
+                TestActions::Revoke(storage_id.into_id().into())
+            ),
+            Err(ClientError::CannotRevoke(_))
+        ));
+
+        alice
+            .revoke(
+                storage_id,
+                revoked,
+                &mut NullSink,
+                TestActions::Revoke(revoked),
+            )
+            .unwrap();
+        assert_eq!(value(&mut alice, storage_id, 1), None);
+        assert_eq!(
+            value(&mut alice, storage_id, 2),
+            Some(Box::from(20u64.to_be_bytes()))
+        );
+
+        // Peers revoke the command when they receive the revocation.
+        let mut bob = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+        assert_eq!(value(&mut bob, storage_id, 1), None);
+        assert_eq!(
+            value(&mut bob, storage_id, 2),
+            Some(Box::from(20u64.to_be_bytes()))
+        );
+    }
+}
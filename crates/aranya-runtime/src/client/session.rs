@@ -11,20 +11,60 @@ use alloc::{
     sync::Arc,
     vec::Vec,
 };
-use core::{cmp::Ordering, iter::Peekable, marker::PhantomData, mem, ops::Bound};
+use core::{cmp::Ordering, fmt, iter::Peekable, marker::PhantomData, mem, ops::Bound};
 
 use buggy::{bug, Bug, BugExt};
 use serde::{Deserialize, Serialize};
 use yoke::{Yoke, Yokeable};
 
 use crate::{
-    Address, Checkpoint, ClientError, ClientState, Command, CommandId, CommandRecall, Engine, Fact,
-    FactPerspective, GraphId, Keys, NullSink, Perspective, Policy, PolicyId, Prior, Priority,
-    Query, QueryMut, Revertable, Segment, Sink, Storage, StorageError, StorageProvider,
+    storage::overlay_page, Address, Checkpoint, ClientError, ClientState, Command, CommandId,
+    CommandRecall, Engine, Fact, FactPerspective, GraphId, Keys, Location, NullSink, Perspective,
+    Policy, PolicyId, Prior, Priority, Query, QueryMut, Revertable, Segment, Sink, Storage,
+    StorageError, StorageProvider,
 };
 
 type Bytes = Box<[u8]>;
 
+/// Limits on the resources used by a [`Session`], from
+/// [`ClientState::session_with_limits`]. Every limit is unset by default.
+///
+/// Sessions often process commands streamed from a remote peer, so these bound what
+/// the peer can make the session hold. An action or command which takes the session
+/// past a limit is rolled back and fails with [`ClientError::SessionLimit`].
+#[derive(Clone, Debug, Default)]
+pub struct SessionLimits {
+    /// The number of bytes of fact changes the session may hold.
+    pub max_fact_bytes: Option<usize>,
+    /// The number of commands the session may produce or receive.
+    pub max_commands: Option<usize>,
+    /// How long the session may be used for after it is created.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub max_lifetime: Option<core::time::Duration>,
+}
+
+/// The [`SessionLimits`] a session exceeded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SessionLimit {
+    /// [`SessionLimits::max_fact_bytes`].
+    FactBytes,
+    /// [`SessionLimits::max_commands`].
+    Commands,
+    /// [`SessionLimits::max_lifetime`].
+    Lifetime,
+}
+
+impl fmt::Display for SessionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FactBytes => write!(f, "too many bytes of facts"),
+            Self::Commands => write!(f, "too many commands"),
+            Self::Lifetime => write!(f, "session expired"),
+        }
+    }
+}
+
 /// Ephemeral session used to handle/generate off-graph commands.
 pub struct Session<SP: StorageProvider, E> {
     /// The ID of the associated storage.
@@ -42,7 +82,27 @@ pub struct Session<SP: StorageProvider, E> {
     /// Tag for associated engine.
     _engine: PhantomData<E>,
 
+    /// The graph head the session was created at, which `base_facts` are from.
+    base: Address,
+    head: Address,
+
+    limits: SessionLimits,
+    /// The number of commands produced or received.
+    commands: usize,
+    /// The size of `fact_log` in bytes.
+    fact_bytes: usize,
+    #[cfg(feature = "std")]
+    created: std::time::Instant,
+}
+
+/// The state of a [`Session`] saved by [`Session::save`].
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    storage_id: GraphId,
+    base: Address,
     head: Address,
+    fact_log: Vec<(String, Keys, Option<Bytes>)>,
+    commands: usize,
 }
 
 struct SessionPerspective<'a, SP: StorageProvider, E, MS> {
@@ -51,13 +111,49 @@ struct SessionPerspective<'a, SP: StorageProvider, E, MS> {
 }
 
 impl<SP: StorageProvider, E> Session<SP, E> {
-    pub(super) fn new(provider: &mut SP, storage_id: GraphId) -> Result<Self, ClientError> {
+    pub(super) fn new(
+        provider: &mut SP,
+        storage_id: GraphId,
+        limits: SessionLimits,
+    ) -> Result<Self, ClientError> {
         let storage = provider.get_storage(storage_id)?;
         let head_loc = storage.get_head()?;
-        let seg = storage.get_segment(head_loc)?;
-        let command = seg.get_command(head_loc).assume("location must exist")?;
+        Self::at(storage, storage_id, head_loc, limits)
+    }
+
+    pub(super) fn resume(
+        provider: &mut SP,
+        saved: &[u8],
+        limits: SessionLimits,
+    ) -> Result<Self, ClientError> {
+        let saved: SavedSession =
+            postcard::from_bytes(saved).map_err(ClientError::SessionDeserialize)?;
+        let storage = provider.get_storage(saved.storage_id)?;
+        let base = storage
+            .get_location(saved.base)?
+            .ok_or(StorageError::NoSuchId(saved.base.id))?;
+
+        let mut session = Self::at(storage, saved.storage_id, base, limits)?;
+        session.head = saved.head;
+        session.commands = saved.commands;
+        session.fact_log = saved.fact_log;
+        session.replay();
+        Ok(session)
+    }
+
+    /// Creates a session based on the facts at `location`, which must be the last
+    /// command of its segment.
+    fn at(
+        storage: &SP::Storage,
+        storage_id: GraphId,
+        location: Location,
+        limits: SessionLimits,
+    ) -> Result<Self, ClientError> {
+        let seg = storage.get_segment(location)?;
+        let command = seg.get_command(location).assume("location must exist")?;
 
         let base_facts = seg.facts()?;
+        let address = command.address()?;
 
         let result = Self {
             storage_id,
@@ -66,11 +162,79 @@ impl<SP: StorageProvider, E> Session<SP, E> {
             fact_log: Vec::new(),
             current_facts: Arc::default(),
             _engine: PhantomData,
-            head: command.address()?,
+            base: address,
+            head: address,
+            limits,
+            commands: 0,
+            fact_bytes: 0,
+            #[cfg(feature = "std")]
+            created: std::time::Instant::now(),
         };
 
         Ok(result)
     }
+
+    /// Saves the state of the session, so it can be resumed later with
+    /// [`ClientState::resume_session`], such as after the process restarts or on
+    /// another thread.
+    pub fn save(&self) -> Result<Vec<u8>, ClientError> {
+        let saved = SavedSession {
+            storage_id: self.storage_id,
+            base: self.base,
+            head: self.head,
+            fact_log: self.fact_log.clone(),
+            commands: self.commands,
+        };
+        Ok(postcard::to_allocvec(&saved).assume("serialize session")?)
+    }
+
+    /// Rebuilds the current facts of the session from `fact_log`.
+    fn replay(&mut self) {
+        // Create empty map, but reuse allocation if not shared
+        let mut facts = Arc::get_mut(&mut self.current_facts).map_or_else(BTreeMap::new, mem::take);
+        facts.clear();
+        self.fact_bytes = 0;
+        for (n, k, v) in self.fact_log.iter().cloned() {
+            self.fact_bytes = self
+                .fact_bytes
+                .saturating_add(fact_size(&n, &k, v.as_ref()));
+            facts.entry(n).or_default().insert(k, v);
+        }
+        self.current_facts = Arc::new(facts);
+    }
+
+    /// Fails if the session has outlived [`SessionLimits::max_lifetime`].
+    fn check_lifetime(&self) -> Result<(), ClientError> {
+        #[cfg(feature = "std")]
+        if let Some(max) = self.limits.max_lifetime {
+            if self.created.elapsed() > max {
+                return Err(ClientError::SessionLimit(SessionLimit::Lifetime));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if the session holds more than its limits allow.
+    fn check_limits(&self) -> Result<(), ClientError> {
+        if let Some(max) = self.limits.max_commands {
+            if self.commands > max {
+                return Err(ClientError::SessionLimit(SessionLimit::Commands));
+            }
+        }
+        if let Some(max) = self.limits.max_fact_bytes {
+            if self.fact_bytes > max {
+                return Err(ClientError::SessionLimit(SessionLimit::FactBytes));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the size in bytes of a fact change.
+fn fact_size(name: &str, keys: &Keys, value: Option<&Bytes>) -> usize {
+    keys.iter()
+        .fold(name.len(), |size, key| size.saturating_add(key.len()))
+        .saturating_add(value.map_or(0, |value| value.len()))
 }
 
 impl<SP: StorageProvider, E: Engine> Session<SP, E> {
@@ -87,7 +251,9 @@ impl<SP: StorageProvider, E: Engine> Session<SP, E> {
         ES: Sink<E::Effect>,
         MS: for<'b> Sink<&'b [u8]>,
     {
+        self.check_lifetime()?;
         let policy = client.engine.get_policy(self.policy_id)?;
+        let commands = self.commands;
 
         // Use a special perspective so we can send to the message sink.
         let mut perspective = SessionPerspective {
@@ -98,8 +264,12 @@ impl<SP: StorageProvider, E: Engine> Session<SP, E> {
         effect_sink.begin();
 
         // Try to perform action.
-        match policy.call_action(action, &mut perspective, effect_sink) {
-            Ok(_) => {
+        let result = policy
+            .call_action(action, &mut perspective, effect_sink)
+            .map_err(ClientError::from)
+            .and_then(|()| perspective.session.check_limits());
+        match result {
+            Ok(()) => {
                 // Success, commit effects
                 effect_sink.commit();
                 Ok(())
@@ -107,9 +277,10 @@ impl<SP: StorageProvider, E: Engine> Session<SP, E> {
             Err(e) => {
                 // Other error, revert all? See #513.
                 perspective.revert(checkpoint)?;
+                perspective.session.commands = commands;
                 perspective.message_sink.rollback();
                 effect_sink.rollback();
-                Err(e.into())
+                Err(e)
             }
         }
     }
@@ -131,7 +302,10 @@ impl<SP: StorageProvider, E: Engine> Session<SP, E> {
             bug!("ephemeral commands must be run on the same graph");
         }
 
+        self.check_lifetime()?;
         let policy = client.engine.get_policy(self.policy_id)?;
+        let commands = self.commands;
+        self.commands = commands.checked_add(1).assume("must not overflow")?;
 
         // Use a special perspective which doesn't check the head
         let mut perspective = SessionPerspective {
@@ -142,10 +316,15 @@ impl<SP: StorageProvider, E: Engine> Session<SP, E> {
         // Try to evaluate command.
         sink.begin();
         let checkpoint = perspective.checkpoint();
-        if let Err(e) = policy.call_rule(&command, &mut perspective, sink, CommandRecall::None) {
+        let result = policy
+            .call_rule(&command, &mut perspective, sink, CommandRecall::None)
+            .map_err(ClientError::from)
+            .and_then(|()| perspective.session.check_limits());
+        if let Err(e) = result {
             perspective.revert(checkpoint)?;
+            perspective.session.commands = commands;
             sink.rollback();
-            return Err(e.into());
+            return Err(e);
         }
         sink.commit();
 
@@ -313,6 +492,23 @@ where
         );
         Ok(QueryIterator::new(prior, YokeIter::new(current)))
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        overlay_page(
+            self.session.current_facts.get(name),
+            &self.session.base_facts,
+            name,
+            prefix,
+            after,
+            limit,
+        )
+    }
 }
 
 /// Iterator over matching prefix of a [`BTreeMap`].
@@ -374,6 +570,10 @@ where
 
 impl<SP: StorageProvider, E, MS> QueryMut for SessionPerspective<'_, SP, E, MS> {
     fn insert(&mut self, name: String, keys: Keys, value: Box<[u8]>) {
+        self.session.fact_bytes =
+            self.session
+                .fact_bytes
+                .saturating_add(fact_size(&name, &keys, Some(&value)));
         self.session
             .fact_log
             .push((name.clone(), keys.clone(), Some(value.clone())));
@@ -384,6 +584,10 @@ impl<SP: StorageProvider, E, MS> QueryMut for SessionPerspective<'_, SP, E, MS>
     }
 
     fn delete(&mut self, name: String, keys: Keys) {
+        self.session.fact_bytes = self
+            .session
+            .fact_bytes
+            .saturating_add(fact_size(&name, &keys, None));
         self.session
             .fact_log
             .push((name.clone(), keys.clone(), None));
@@ -406,6 +610,11 @@ where
     fn add_command(&mut self, command: &impl Command) -> Result<usize, StorageError> {
         let command = SessionCommand::from_cmd(self.session.storage_id, command)?;
         self.session.head = command.address()?;
+        self.session.commands = self
+            .session
+            .commands
+            .checked_add(1)
+            .assume("must not overflow")?;
         let bytes = postcard::to_allocvec(&command).assume("serialize session command")?;
         self.message_sink.consume(&bytes);
 
@@ -443,14 +652,7 @@ where
         }
 
         self.session.fact_log.truncate(checkpoint.index);
-        // Create empty map, but reuse allocation if not shared
-        let mut facts =
-            Arc::get_mut(&mut self.session.current_facts).map_or_else(BTreeMap::new, mem::take);
-        facts.clear();
-        for (n, k, v) in self.session.fact_log.iter().cloned() {
-            facts.entry(n).or_default().insert(k, v);
-        }
-        self.session.current_facts = Arc::new(facts);
+        self.session.replay();
 
         Ok(())
     }
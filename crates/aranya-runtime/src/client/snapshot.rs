@@ -0,0 +1,339 @@
+//! Signed snapshots of a graph's facts, for bootstrapping new clients.
+//!
+//! See [`ClientState::export_snapshot`](crate::ClientState::export_snapshot) and
+//! [`ClientState::import_snapshot`](crate::ClientState::import_snapshot).
+//!
+//! A snapshot holds the facts at a checkpoint command, the checkpoint and its
+//! ancestors, and the commands since the checkpoint. When it is imported, the
+//! checkpoint and its ancestors are stored without being evaluated and the facts are
+//! written at the checkpoint, so only the commands since the checkpoint are checked
+//! against the graph's policy. The importing client trusts the signer of the
+//! snapshot for the facts, so snapshots are signed.
+//!
+//! A client which bootstraps from a snapshot has no facts before the checkpoint, so
+//! it can't braid a branch which forks before the checkpoint. The checkpoint should
+//! be a command which every peer has built on.
+
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+use core::{borrow::Borrow, mem};
+
+use aranya_crypto::{CipherSuite, Signature, SigningKey, VerifyingKey};
+use buggy::BugExt;
+use serde::{Deserialize, Serialize};
+
+use super::{archive::ArchivedCommand, introspect};
+use crate::{
+    Address, ClientError, Command, CommandId, Engine, GraphId, Keys, Location, Perspective, Policy,
+    Prior, Query, QueryMut, Segment, Storage, StorageError, StorageProvider,
+};
+
+/// The version of the snapshot format written by this crate.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The context a snapshot's signature is bound to.
+const SIGNATURE_CONTEXT: &[u8] = b"aranya-runtime graph snapshot";
+
+#[derive(Serialize, Deserialize)]
+struct SignedSnapshot {
+    /// The encoded [`SnapshotBody`].
+    body: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotBody {
+    version: u32,
+    graph: GraphId,
+    checkpoint: CommandId,
+    /// The facts at the checkpoint.
+    facts: Vec<(String, Keys, Box<[u8]>)>,
+    /// The checkpoint and its ancestors, with every command after its parents.
+    history: Vec<ArchivedCommand>,
+    /// The commands since the checkpoint, with every command after its parents.
+    recent: Vec<ArchivedCommand>,
+}
+
+/// Writes a snapshot of `graph` at `checkpoint`, signed with `key`.
+pub(super) fn export<CS: CipherSuite>(
+    graph: GraphId,
+    storage: &impl Storage,
+    engine: &impl Engine,
+    checkpoint: CommandId,
+    key: &SigningKey<CS>,
+) -> Result<Vec<u8>, ClientError> {
+    let location =
+        introspect::find(storage, checkpoint)?.ok_or(StorageError::NoSuchId(checkpoint))?;
+
+    let policy = engine.get_policy(storage.get_segment(location)?.policy())?;
+    let perspective = storage.get_fact_perspective(location)?;
+    let mut facts = Vec::new();
+    for name in policy.fact_names() {
+        for fact in perspective.query_prefix(name, &[])? {
+            let fact = fact?;
+            facts.push((name.to_owned(), fact.key, fact.value));
+        }
+    }
+
+    let history = ancestry(storage, location)?;
+    let seen: BTreeSet<Location> = history.iter().copied().collect();
+    let mut recent = ancestry(storage, storage.get_head()?)?;
+    recent.retain(|l| !seen.contains(l));
+    for &l in &recent {
+        if !introspect::is_ancestor(storage, location, l)? {
+            return Err(ClientError::SnapshotCheckpoint(checkpoint));
+        }
+    }
+
+    let body = SnapshotBody {
+        version: SNAPSHOT_VERSION,
+        graph,
+        checkpoint,
+        facts,
+        history: read(storage, history)?,
+        recent: read(storage, recent)?,
+    };
+    let body = postcard::to_allocvec(&body).map_err(ClientError::SnapshotEncoding)?;
+    let signature = key
+        .sign(&body, SIGNATURE_CONTEXT)
+        .map_err(ClientError::SnapshotSignature)?;
+    let snapshot = SignedSnapshot {
+        body,
+        signature: signature.to_bytes().borrow().to_vec(),
+    };
+    postcard::to_allocvec(&snapshot).map_err(ClientError::SnapshotEncoding)
+}
+
+/// Checks the signature of a snapshot with `key`, then stores its checkpoint and
+/// the checkpoint's ancestors without evaluating them. Returns the graph's ID and
+/// the commands since the checkpoint, which must still be added.
+pub(super) fn import<CS: CipherSuite, SP: StorageProvider>(
+    snapshot: &[u8],
+    key: &VerifyingKey<CS>,
+    provider: &mut SP,
+    engine: &mut impl Engine,
+) -> Result<(GraphId, Vec<ArchivedCommand>), ClientError> {
+    let snapshot: SignedSnapshot =
+        postcard::from_bytes(snapshot).map_err(ClientError::SnapshotEncoding)?;
+    let signature = Signature::<CS>::from_bytes(&snapshot.signature)
+        .map_err(|e| ClientError::SnapshotSignature(e.into()))?;
+    key.verify(&snapshot.body, SIGNATURE_CONTEXT, &signature)
+        .map_err(ClientError::SnapshotSignature)?;
+
+    let body: SnapshotBody =
+        postcard::from_bytes(&snapshot.body).map_err(ClientError::SnapshotEncoding)?;
+    if body.version != SNAPSHOT_VERSION {
+        return Err(ClientError::UnsupportedSnapshot(body.version));
+    }
+
+    let mut history = body.history.into_iter();
+    let init = history.next().ok_or(ClientError::InitError)?;
+    // Storage ID is the id of the init command by definition.
+    if init.id().into_id() != body.graph.into_id() || !matches!(init.parent(), Prior::None) {
+        return Err(ClientError::InitError);
+    }
+    let policy = init.policy().ok_or(ClientError::InitError)?;
+    let policy_id = engine.add_policy(policy)?;
+
+    let mut facts = body.facts;
+    let mut seed = |perspective: &mut SP::Perspective, command: &ArchivedCommand| {
+        if command.id() == body.checkpoint {
+            for (name, keys, value) in mem::take(&mut facts) {
+                perspective.insert(name, keys, value);
+            }
+        }
+    };
+
+    let mut perspective = provider.new_perspective(policy_id);
+    seed(&mut perspective, &init);
+    perspective.add_command(&init)?;
+    let (_, storage) = provider.new_storage(perspective)?;
+
+    let mut locations = BTreeMap::new();
+    locations.insert(init.id(), storage.get_head()?);
+    // The perspective being written, and the ID of its head.
+    let mut current: Option<(SP::Perspective, CommandId)> = None;
+    for command in history {
+        let mut perspective = match command.parent() {
+            Prior::None => return Err(ClientError::InitError),
+            Prior::Single(parent) => match current.take() {
+                Some((perspective, head)) if head == parent.id => perspective,
+                other => {
+                    if let Some((perspective, _)) = other {
+                        write(storage, perspective, &mut locations)?;
+                    }
+                    storage
+                        .get_linear_perspective(locate(&locations, parent)?)?
+                        .assume("location should already be in storage")?
+                }
+            },
+            Prior::Merge(left, right) => {
+                if let Some((perspective, _)) = current.take() {
+                    write(storage, perspective, &mut locations)?;
+                }
+                let left = locate(&locations, left)?;
+                let right = locate(&locations, right)?;
+                let last_common_ancestor = super::last_common_ancestor(storage, left, right)?;
+                let policy_id = storage.get_segment(left)?.policy();
+                // The braided facts are not needed, since the merge is not evaluated.
+                let braid = storage.write_facts(storage.get_fact_perspective(left)?)?;
+                storage
+                    .new_merge_perspective(left, right, last_common_ancestor, policy_id, braid)?
+                    .assume("parents should already be in storage")?
+            }
+        };
+        seed(&mut perspective, &command);
+        perspective.add_command(&command)?;
+        current = Some((perspective, command.id()));
+    }
+    // The checkpoint is the last command of its ancestry.
+    if let Some((perspective, _)) = current {
+        let segment = storage.write(perspective)?;
+        storage.commit(segment)?;
+    }
+    Ok((body.graph, body.recent))
+}
+
+/// Returns the locations of `start` and its ancestors.
+fn ancestry(storage: &impl Storage, start: Location) -> Result<Vec<Location>, StorageError> {
+    // The last command of each segment which is an ancestor.
+    let mut reached = BTreeMap::new();
+    let mut queue = Vec::new();
+    queue.push(start);
+    while let Some(location) = queue.pop() {
+        match reached.entry(location.segment) {
+            Entry::Vacant(e) => {
+                e.insert(location.command);
+                queue.extend(storage.get_segment(location)?.prior());
+            }
+            Entry::Occupied(mut e) => {
+                if *e.get() < location.command {
+                    e.insert(location.command);
+                }
+            }
+        }
+    }
+    Ok(reached
+        .into_iter()
+        .flat_map(|(segment, last)| (0..=last).map(move |command| Location::new(segment, command)))
+        .collect())
+}
+
+/// Reads the commands at `locations`, with every command after its parents.
+fn read(
+    storage: &impl Storage,
+    locations: Vec<Location>,
+) -> Result<Vec<ArchivedCommand>, ClientError> {
+    let mut commands = Vec::with_capacity(locations.len());
+    for location in locations {
+        let segment = storage.get_segment(location)?;
+        let command = segment
+            .get_command(location)
+            .ok_or(StorageError::CommandOutOfBounds(location))?;
+        commands.push((command.max_cut()?, ArchivedCommand::from_cmd(&command)?));
+    }
+    // A command's max cut is greater than its parents', so this puts every
+    // command after its parents.
+    commands.sort_by_key(|&(max_cut, _)| max_cut);
+    Ok(commands.into_iter().map(|(_, command)| command).collect())
+}
+
+/// Writes `perspective` to `storage`, recording the locations of its commands.
+fn write<S: Storage>(
+    storage: &mut S,
+    perspective: S::Perspective,
+    locations: &mut BTreeMap<CommandId, Location>,
+) -> Result<(), StorageError> {
+    let segment = storage.write(perspective)?;
+    let first = segment.first_location();
+    for (i, command) in segment.get_from(first).iter().enumerate() {
+        locations.insert(command.id(), Location::new(first.segment, i));
+    }
+    Ok(())
+}
+
+fn locate(
+    locations: &BTreeMap<CommandId, Location>,
+    parent: Address,
+) -> Result<Location, ClientError> {
+    locations
+        .get(&parent.id)
+        .copied()
+        .ok_or(ClientError::NoSuchParent(parent.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::{default::DefaultCipherSuite, Rng};
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEffect, TestEngine, TestSink},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink,
+    };
+
+    #[test]
+    fn test_snapshot_bootstrap() {
+        let mut alice = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = alice
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 1u64..=3 {
+            alice
+                .action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let checkpoint = alice.head(storage_id).unwrap().id;
+        for i in 4u64..=5 {
+            alice
+                .action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+
+        let key = SigningKey::<DefaultCipherSuite>::new(&mut Rng);
+        let snapshot = alice.export_snapshot(storage_id, checkpoint, &key).unwrap();
+
+        // Only commands since the checkpoint are evaluated.
+        let mut bob = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let mut sink = TestSink::new();
+        sink.add_expectation(TestEffect::Got(4));
+        sink.add_expectation(TestEffect::Got(5));
+        let other = SigningKey::<DefaultCipherSuite>::new(&mut Rng);
+        assert!(matches!(
+            bob.import_snapshot(&snapshot, &other.public().unwrap(), &mut sink),
+            Err(ClientError::SnapshotSignature(_))
+        ));
+        bob.import_snapshot(&snapshot, &key.public().unwrap(), &mut sink)
+            .unwrap();
+        assert_eq!(sink.count(), 0);
+        assert_eq!(
+            bob.head(storage_id).unwrap().id,
+            alice.head(storage_id).unwrap().id
+        );
+        for i in 1u64..=5 {
+            let keys = [Box::from(i.to_be_bytes())];
+            assert_eq!(
+                bob.query_facts(storage_id, "payload", &keys).unwrap(),
+                alice.query_facts(storage_id, "payload", &keys).unwrap()
+            );
+        }
+
+        // The bootstrapped graph syncs like any other.
+        alice
+            .action(storage_id, &mut NullSink, TestActions::SetValue(6, 6))
+            .unwrap();
+        let archive = alice.export_graph(storage_id).unwrap();
+        bob.import_graph(&archive, &mut NullSink).unwrap();
+        let keys = [Box::from(6u64.to_be_bytes())];
+        assert_eq!(
+            bob.query_facts(storage_id, "payload", &keys).unwrap().len(),
+            1
+        );
+    }
+}
@@ -1,12 +1,18 @@
 use alloc::collections::{BTreeMap, VecDeque};
-use core::{marker::PhantomData, mem};
+#[cfg(feature = "std")]
+use alloc::{vec, vec::Vec};
+use core::mem;
 
 use buggy::{bug, BugExt};
+use tracing::debug;
 
+#[cfg(feature = "std")]
+use super::dry_run::EffectLog;
+use super::{expiry, recall::RecallLog, revoke};
 use crate::{
-    Address, ClientError, Command, CommandId, CommandRecall, Engine, EngineError, GraphId,
-    Location, MergeIds, PeerCache, Perspective, Policy, PolicyId, Prior, Revertable, Segment, Sink,
-    Storage, StorageError, StorageProvider, MAX_COMMAND_LENGTH,
+    Address, ClientError, Command, CommandId, CommandRecall, Engine, EngineError, FactPerspective,
+    GraphId, Location, MergeIds, PeerCache, Perspective, Policy, PolicyId, Prior, Revertable,
+    Segment, Sink, Storage, StorageError, StorageProvider, MAX_COMMAND_LENGTH,
 };
 
 /// Transaction used to receive many commands at once.
@@ -15,7 +21,7 @@ use crate::{
 /// need as many merges when adding commands. When the transaction is committed,
 /// we will merge all temporary heads and the storage head, and then commit the
 /// result as the new storage head.
-pub struct Transaction<SP: StorageProvider, E> {
+pub struct Transaction<SP: StorageProvider, E: Engine> {
     /// The ID of the associated storage
     storage_id: GraphId,
     /// Current working perspective
@@ -24,20 +30,30 @@ pub struct Transaction<SP: StorageProvider, E> {
     phead: Option<CommandId>,
     /// Written but not committed heads
     heads: BTreeMap<Address, Location>,
-    /// Tag for associated engine
-    _engine: PhantomData<E>,
+    /// Commands recalled while merging
+    recalls: RecallLog<E::Effect>,
 }
 
-impl<SP: StorageProvider, E> Transaction<SP, E> {
+impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
     pub(super) const fn new(storage_id: GraphId) -> Self {
         Self {
             storage_id,
             perspective: None,
             phead: None,
             heads: BTreeMap::new(),
-            _engine: PhantomData,
+            recalls: RecallLog::new(),
         }
     }
+
+    /// Logs commands recalled while merging to a new log like `log`.
+    pub(super) fn log_recalls(&mut self, log: &RecallLog<E::Effect>) {
+        self.recalls = log.child();
+    }
+
+    /// Moves the commands recalled so far to `log`.
+    pub(super) fn take_recalls(&mut self, log: &mut RecallLog<E::Effect>) {
+        log.append(&mut self.recalls);
+    }
 }
 
 impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
@@ -99,8 +115,14 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
                 }
                 let command = policy.merge(&mut buffer, merge_ids)?;
 
-                let (braid, last_common_ancestor) =
-                    make_braid_segment::<_, E>(storage, left_loc, right_loc, sink, policy)?;
+                let (braid, last_common_ancestor) = make_braid_segment::<_, E>(
+                    storage,
+                    left_loc,
+                    right_loc,
+                    sink,
+                    policy,
+                    &mut self.recalls,
+                )?;
 
                 let mut perspective = storage
                     .new_merge_perspective(
@@ -214,6 +236,10 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
         Ok(count)
     }
 
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(level = "trace", skip_all, fields(id = %command.id()))
+    )]
     fn add_single(
         &mut self,
         storage: &mut <SP as StorageProvider>::Storage,
@@ -227,10 +253,34 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
         let policy_id = perspective.policy();
         let policy = engine.get_policy(policy_id)?;
 
+        expiry::check(policy, command)?;
+
+        let revocation = match policy.revokes(command, perspective)? {
+            Some(revoked) => {
+                // Revocations are computed from storage, so the parent must be in it.
+                if let Some(p) = Option::take(&mut self.perspective) {
+                    self.phead = None;
+                    let seg = storage.write(p)?;
+                    let head = seg.head()?;
+                    self.heads.insert(head.address()?, seg.head_location());
+                }
+                let loc = self
+                    .locate(storage, parent)?
+                    .ok_or(ClientError::NoSuchParent(parent.id))?;
+                Some(revoke::revoke(&*storage, policy, loc, revoked)?)
+            }
+            None => None,
+        };
+        let perspective = self.get_perspective(parent, storage)?;
+
         // Try to run command, or revert if failed.
         sink.begin();
         let checkpoint = perspective.checkpoint();
+        if let Some(revocation) = revocation {
+            revocation.apply(perspective, sink);
+        }
         if let Err(e) = policy.call_rule(command, perspective, sink, CommandRecall::None) {
+            debug!(id = %command.id(), "command rejected: {e}");
             perspective.revert(checkpoint)?;
             sink.rollback();
             return Err(e.into());
@@ -243,6 +293,10 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(level = "trace", skip_all, fields(id = %command.id()))
+    )]
     fn add_merge(
         &mut self,
         storage: &mut <SP as StorageProvider>::Storage,
@@ -269,8 +323,14 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
         let (policy, policy_id) = choose_policy(storage, engine, left_loc, right_loc)?;
 
         // Braid commands from left and right into an ordered sequence.
-        let (braid, last_common_ancestor) =
-            make_braid_segment::<_, E>(storage, left_loc, right_loc, sink, policy)?;
+        let (braid, last_common_ancestor) = make_braid_segment::<_, E>(
+            storage,
+            left_loc,
+            right_loc,
+            sink,
+            policy,
+            &mut self.recalls,
+        )?;
 
         let mut perspective = storage
             .new_merge_perspective(left_loc, right_loc, last_common_ancestor, policy_id, braid)?
@@ -375,6 +435,229 @@ impl<SP: StorageProvider, E: Engine> Transaction<SP, E> {
     }
 }
 
+/// A run of commands being added, each the child of the one before it.
+#[cfg(feature = "std")]
+struct Branch<'c, C> {
+    /// The parent of the first command.
+    parent: Address,
+    /// The location of `parent` in storage.
+    start: Location,
+    /// The commands, with their indices in the commands being added.
+    commands: Vec<(usize, &'c C)>,
+}
+
+/// The perspective holding a validated [`Branch`], and the effects of each of its
+/// commands.
+#[cfg(feature = "std")]
+type Validated<P, X> = (P, Vec<(usize, Vec<X>)>);
+
+#[cfg(feature = "std")]
+impl<SP, E> Transaction<SP, E>
+where
+    SP: StorageProvider,
+    SP::Storage: Sync,
+    SP::Perspective: Send,
+    E: Engine + Sync,
+    E::Effect: Send,
+{
+    /// Like [`Transaction::add_commands`], but validates independent branches on
+    /// separate threads.
+    ///
+    /// The leading commands which extend the graph in separate branches, such as
+    /// the two sides of a long divergence, are each checked on their own
+    /// copy-on-write perspective and then written in order, before any merge
+    /// weaves them together. The remaining commands are added one at a time. If a
+    /// command in any of the branches is rejected, none of the branches are added.
+    pub(super) fn add_commands_parallel<C: Command + Sync>(
+        &mut self,
+        commands: &[C],
+        provider: &mut SP,
+        engine: &mut E,
+        sink: &mut impl Sink<E::Effect>,
+        request_heads: &mut PeerCache,
+    ) -> Result<usize, ClientError> {
+        let storage = match provider.get_storage(self.storage_id) {
+            Ok(s) => s,
+            // The graph must be initialized first.
+            Err(StorageError::NoSuchStorage) => {
+                return self.add_commands(commands, provider, engine, sink, request_heads)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Write out the current perspective so branches can start from it.
+        if let Some(p) = Option::take(&mut self.perspective) {
+            self.phead = None;
+            let seg = storage.write(p)?;
+            let head = seg.head()?;
+            self.heads.insert(head.address()?, seg.head_location());
+        }
+
+        let (branches, end) = self.split_branches(storage, commands)?;
+        if branches.len() < 2 {
+            return self.add_commands(commands, provider, engine, sink, request_heads);
+        }
+
+        let threads = std::thread::available_parallelism()
+            .map_or(1, core::num::NonZeroUsize::get)
+            .min(branches.len());
+        let mut results = {
+            let storage = &*storage;
+            let engine = &*engine;
+            let branches = &branches;
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..threads)
+                    .map(|first| {
+                        scope.spawn(move || {
+                            (first..branches.len())
+                                .step_by(threads)
+                                .map(|b| (b, validate_branch(storage, engine, &branches[b])))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        results.sort_unstable_by_key(|&(b, _)| b);
+
+        // Report the first rejected command, as if the commands were added in order.
+        let mut validated = Vec::with_capacity(results.len());
+        let mut rejected: Option<(usize, ClientError)> = None;
+        let mut revoking = false;
+        for (_, result) in results {
+            match result {
+                Ok(Some(v)) => validated.push(v),
+                Ok(None) => revoking = true,
+                Err((i, e)) => {
+                    if rejected.as_ref().map_or(true, |&(first, _)| i < first) {
+                        rejected = Some((i, e));
+                    }
+                }
+            }
+        }
+        if let Some((_, e)) = rejected {
+            return Err(e);
+        }
+        if revoking {
+            return self.add_commands(commands, provider, engine, sink, request_heads);
+        }
+
+        let mut effects = Vec::new();
+        for (branch, (perspective, branch_effects)) in branches.iter().zip(validated) {
+            let seg = storage.write(perspective)?;
+            let head = seg.head()?;
+            self.heads.remove(&branch.parent);
+            self.heads.insert(head.address()?, seg.head_location());
+            effects.extend(branch_effects);
+        }
+        effects.sort_unstable_by_key(|&(i, _)| i);
+        let count = effects.len();
+        for (_, command_effects) in effects {
+            sink.begin();
+            for effect in command_effects {
+                sink.consume(effect);
+            }
+            sink.commit();
+        }
+        for command in &commands[..end] {
+            if let Some(loc) = self.locate(storage, command.address()?)? {
+                request_heads.add_command(storage, command.address()?, loc)?;
+            }
+        }
+
+        let rest = self.add_commands(&commands[end..], provider, engine, sink, request_heads)?;
+        Ok(count.checked_add(rest).assume("must not overflow")?)
+    }
+
+    /// Splits the leading commands which extend the graph in separate branches from
+    /// the rest. Returns the branches and the number of commands they cover.
+    ///
+    /// Commands already in the graph are skipped. The branches end at the first
+    /// merge, or the first command whose parent is not in the graph or at the end of
+    /// a branch.
+    fn split_branches<'c, C: Command>(
+        &self,
+        storage: &mut SP::Storage,
+        commands: &'c [C],
+    ) -> Result<(Vec<Branch<'c, C>>, usize), ClientError> {
+        let mut branches: Vec<Branch<'c, C>> = Vec::new();
+        // The index of the branch each command ends.
+        let mut tails = BTreeMap::new();
+        for (i, command) in commands.iter().enumerate() {
+            if self.locate(storage, command.address()?)?.is_some() {
+                continue;
+            }
+            let Prior::Single(parent) = command.parent() else {
+                return Ok((branches, i));
+            };
+            if let Some(b) = tails.remove(&parent.id) {
+                let branch: &mut Branch<'c, C> = branches.get_mut(b).assume("branch exists")?;
+                branch.commands.push((i, command));
+                tails.insert(command.id(), b);
+                continue;
+            }
+            let Some(start) = self.locate(storage, parent)? else {
+                return Ok((branches, i));
+            };
+            tails.insert(command.id(), branches.len());
+            branches.push(Branch {
+                parent,
+                start,
+                commands: vec![(i, command)],
+            });
+        }
+        Ok((branches, commands.len()))
+    }
+}
+
+/// Checks each command in `branch` on a perspective at its parent.
+#[cfg(feature = "std")]
+fn validate_branch<S: Storage, E: Engine, C: Command>(
+    storage: &S,
+    engine: &E,
+    branch: &Branch<'_, C>,
+) -> Result<Option<Validated<S::Perspective, E::Effect>>, (usize, ClientError)> {
+    let first = branch.commands.first().map_or(0, |&(i, _)| i);
+    let mut perspective = storage
+        .get_linear_perspective(branch.start)
+        .map_err(|e| (first, e.into()))?
+        .assume("location should already be in storage")
+        .map_err(|e| (first, e.into()))?;
+    let policy = engine
+        .get_policy(perspective.policy())
+        .map_err(|e| (first, e.into()))?;
+
+    let mut effects = Vec::with_capacity(branch.commands.len());
+    for &(i, command) in &branch.commands {
+        expiry::check(policy, command).map_err(|e| (i, e.into()))?;
+        // Revocations are computed from storage, so they are added one at a time.
+        if policy
+            .revokes(command, &mut perspective)
+            .map_err(|e| (i, e.into()))?
+            .is_some()
+        {
+            return Ok(None);
+        }
+        let mut log = EffectLog::new();
+        policy
+            .call_rule(command, &mut perspective, &mut log, CommandRecall::None)
+            .map_err(|e| (i, e.into()))?;
+        perspective
+            .add_command(command)
+            .map_err(|e| (i, e.into()))?;
+        effects.push((i, log.into_effects()));
+    }
+    Ok(Some((perspective, effects)))
+}
+
 /// Run the braid algorithm and evaluate the sequence to create a braided fact index.
 fn make_braid_segment<S: Storage, E: Engine>(
     storage: &mut S,
@@ -382,13 +665,15 @@ fn make_braid_segment<S: Storage, E: Engine>(
     right: Location,
     sink: &mut impl Sink<E::Effect>,
     policy: &E::Policy,
+    recalls: &mut RecallLog<E::Effect>,
 ) -> Result<(S::FactIndex, (Location, usize)), ClientError> {
-    let order = super::braid(storage, left, right)?;
+    let order = super::braid_with(storage, left, right, policy.braid_order())?;
     let last_common_ancestor = super::last_common_ancestor(storage, left, right)?;
 
     let (&first, rest) = order.split_first().assume("braid is non-empty")?;
 
     let mut braid_perspective = storage.get_fact_perspective(first)?;
+    let heads = expiry::Heads::new(&*storage, left, right)?;
 
     sink.begin();
 
@@ -398,16 +683,51 @@ fn make_braid_segment<S: Storage, E: Engine>(
             .get_command(location)
             .assume("braid only contains existing commands")?;
 
+        let expired = match heads.expired(&*storage, policy, &command, location) {
+            Ok(expired) => expired,
+            Err(e) => {
+                sink.rollback();
+                return Err(e);
+            }
+        };
+        if expired {
+            recalls
+                .record(command.id(), sink)
+                .recalled(expiry::rejection());
+            continue;
+        }
+
+        let revocation = match revoke_in_braid(
+            &*storage,
+            policy,
+            &command,
+            location,
+            &mut braid_perspective,
+        ) {
+            Ok(revocation) => revocation,
+            Err(e) => {
+                sink.rollback();
+                return Err(e);
+            }
+        };
+
+        let mut recording = recalls.record(command.id(), sink);
+        if let Some(revocation) = revocation {
+            revocation.apply(&mut braid_perspective, &mut recording);
+        }
         let result = policy.call_rule(
             &command,
             &mut braid_perspective,
-            sink,
+            &mut recording,
             CommandRecall::OnCheck,
         );
 
-        // If the command failed in an uncontrolled way, rollback
-        if let Err(e) = result {
-            if e != EngineError::Check {
+        match result {
+            Ok(()) => {}
+            // The command failed a check, so it was recalled.
+            Err(EngineError::Check(rejection)) => recording.recalled(rejection),
+            // If the command failed in an uncontrolled way, rollback
+            Err(e) => {
                 sink.rollback();
                 return Err(e.into());
             }
@@ -421,6 +741,27 @@ fn make_braid_segment<S: Storage, E: Engine>(
     Ok((braid, last_common_ancestor))
 }
 
+/// Computes the revocation made by `command` at `location` in a braid, if it makes one.
+fn revoke_in_braid<S: Storage, P: Policy>(
+    storage: &S,
+    policy: &P,
+    command: &impl Command,
+    location: Location,
+    facts: &mut impl FactPerspective,
+) -> Result<Option<revoke::Revocation<P::Effect>>, ClientError> {
+    let Some(revoked) = policy.revokes(command, facts)? else {
+        return Ok(None);
+    };
+    let parent = match location.previous() {
+        Some(previous) => previous,
+        None => match storage.get_segment(location)?.prior() {
+            Prior::Single(prior) => prior,
+            Prior::None | Prior::Merge(..) => bug!("revocation must have a single parent"),
+        },
+    };
+    Ok(Some(revoke::revoke(storage, policy, parent, revoked)?))
+}
+
 /// Select the policy from two locations with the greatest serial value.
 fn choose_policy<'a, E: Engine>(
     storage: &impl Storage,
@@ -452,7 +793,11 @@ mod test {
     use test_log::test;
 
     use super::*;
-    use crate::{memory::MemStorageProvider, ClientState, Keys, MergeIds, Priority};
+    use crate::{
+        linear::{testing::Manager, LinearStorageProvider},
+        memory::MemStorageProvider,
+        BraidKey, BraidOrder, ClientState, DefaultBraidOrder, GcStats, Keys, MergeIds, Priority,
+    };
 
     struct SeqEngine;
 
@@ -825,6 +1170,328 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_introspection() {
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            "a" 0 < "b";
+            "a" 0 < "c" "d";
+            "b" 1 "d" 2 < "ma";
+            commit;
+        };
+        let client = &mut gb.client;
+        let graph = "a".parse().unwrap();
+
+        let head = client.head(graph).unwrap();
+        assert_eq!(head.id, mkid("ma"));
+        assert_eq!(head.max_cut, 3);
+
+        let c = client.command_info(graph, mkid("c")).unwrap().unwrap();
+        assert_eq!(
+            c.parent,
+            Prior::Single(Address {
+                id: mkid("a"),
+                max_cut: 0
+            })
+        );
+        assert_eq!(c.max_cut, 1);
+        assert!(client.command_info(graph, mkid("x")).unwrap().is_none());
+
+        let ancestors: Vec<_> = client
+            .ancestors(graph, mkid("ma"))
+            .unwrap()
+            .map(|info| info.unwrap().id)
+            .collect();
+        assert_eq!(ancestors[0], mkid("d"));
+        assert_eq!(ancestors.last(), Some(&mkid("a")));
+        let mut sorted = ancestors.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 4);
+        let ancestors: Vec<_> = client
+            .ancestors(graph, mkid("d"))
+            .unwrap()
+            .map(|info| info.unwrap().id)
+            .collect();
+        assert_eq!(ancestors, [mkid("c"), mkid("a")]);
+
+        assert!(client.is_ancestor(graph, mkid("a"), mkid("ma")).unwrap());
+        assert!(client.is_ancestor(graph, mkid("c"), mkid("d")).unwrap());
+        assert!(!client.is_ancestor(graph, mkid("d"), mkid("c")).unwrap());
+        assert!(!client.is_ancestor(graph, mkid("b"), mkid("d")).unwrap());
+        assert!(!client.is_ancestor(graph, mkid("b"), mkid("b")).unwrap());
+        assert!(client.is_ancestor(graph, mkid("x"), mkid("b")).is_err());
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            "a" 0 < "b";
+            commit;
+            "b" 1 < "c" "d";
+        };
+        let orphan = *gb.trx.heads.values().next().unwrap();
+        let g = gb
+            .client
+            .provider
+            .get_storage("a".parse().unwrap())
+            .unwrap();
+        let head = g.get_head().unwrap();
+
+        let stats = g.collect_garbage().unwrap();
+        assert_eq!(stats.segments, 1);
+        assert_eq!(stats.commands, 2);
+        assert!(stats.bytes > 0);
+
+        assert_eq!(g.get_head().unwrap(), head);
+        assert!(g.get_segment(orphan).is_err());
+        let seq = lookup(g, "seq").unwrap();
+        assert_eq!(&*seq, b"a:b");
+
+        assert_eq!(g.collect_garbage().unwrap(), GcStats::default());
+    }
+
+    #[test]
+    fn test_collect_garbage_unsupported() {
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, LinearStorageProvider::new(Manager));
+            "a";
+            commit;
+        };
+        let g = gb
+            .client
+            .provider
+            .get_storage("a".parse().unwrap())
+            .unwrap();
+        assert!(matches!(
+            g.collect_garbage(),
+            Err(StorageError::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn test_quota() {
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            commit;
+        };
+        let storage_id = "a".parse().unwrap();
+        let g = gb.client.provider.get_storage(storage_id).unwrap();
+        let head = g.get_head().unwrap();
+        let size = g.size().unwrap();
+        assert!(size > 0);
+        gb.client
+            .provider
+            .set_quota(storage_id, Some(size))
+            .unwrap();
+
+        gb.line(
+            Address {
+                id: mkid("a"),
+                max_cut: 0,
+            },
+            &[mkid("b")],
+        );
+        let err = gb
+            .trx
+            .commit(
+                &mut gb.client.provider,
+                &mut gb.client.engine,
+                &mut NullSink,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::StorageError(StorageError::QuotaExceeded(quota)) if quota == size
+        ));
+        let g = gb.client.provider.get_storage(storage_id).unwrap();
+        assert_eq!(g.get_head().unwrap(), head);
+        assert_eq!(g.size().unwrap(), size);
+
+        gb.client.provider.set_quota(storage_id, None).unwrap();
+        gb.line(
+            Address {
+                id: mkid("a"),
+                max_cut: 0,
+            },
+            &[mkid("b")],
+        );
+        gb.commit();
+        let g = gb.client.provider.get_storage(storage_id).unwrap();
+        assert!(g.size().unwrap() > size);
+        assert_eq!(&*lookup(g, "seq").unwrap(), b"a:b");
+    }
+
+    #[test]
+    fn test_size_counts_commits() {
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, LinearStorageProvider::new(Manager));
+            "a";
+            commit;
+        };
+        let storage_id = "a".parse().unwrap();
+        let size = gb
+            .client
+            .provider
+            .get_storage(storage_id)
+            .unwrap()
+            .size()
+            .unwrap();
+        gb.client
+            .provider
+            .set_quota(storage_id, Some(usize::MAX))
+            .unwrap();
+
+        // Written but not committed, so not counted.
+        gb.line(
+            Address {
+                id: mkid("a"),
+                max_cut: 0,
+            },
+            &[mkid("b")],
+        );
+        gb.flush();
+        let g = gb.client.provider.get_storage(storage_id).unwrap();
+        assert_eq!(g.size().unwrap(), size);
+
+        gb.commit();
+        let g = gb.client.provider.get_storage(storage_id).unwrap();
+        assert!(g.size().unwrap() > size);
+        assert_eq!(&*lookup(g, "seq").unwrap(), b"a:b");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_parallel() {
+        let mut expected = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            commit;
+            "a" 0 < "b" "c";
+            "a" 0 < "d" "e";
+            "c" 2 "e" 2 < "m";
+            commit;
+        };
+
+        let mut gb = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            commit;
+        };
+        let addr = |id, max_cut| Address {
+            id: mkid(id),
+            max_cut,
+        };
+        let commands = [
+            SeqCommand::new(mkid("b"), Prior::Single(addr("a", 0)), 1),
+            SeqCommand::new(mkid("c"), Prior::Single(addr("b", 1)), 2),
+            SeqCommand::new(mkid("d"), Prior::Single(addr("a", 0)), 1),
+            SeqCommand::new(mkid("e"), Prior::Single(addr("d", 1)), 2),
+            SeqCommand::new(mkid("m"), Prior::Merge(addr("c", 2), addr("e", 2)), 3),
+        ];
+        let count = gb
+            .trx
+            .add_commands_parallel(
+                &commands,
+                &mut gb.client.provider,
+                &mut gb.client.engine,
+                &mut NullSink,
+                &mut PeerCache::new(),
+            )
+            .unwrap();
+        assert_eq!(count, 5);
+        gb.commit();
+
+        let id = "a".parse().unwrap();
+        let expected = lookup(expected.client.provider.get_storage(id).unwrap(), "seq");
+        let seq = lookup(gb.client.provider.get_storage(id).unwrap(), "seq");
+        assert_eq!(seq, expected);
+    }
+
+    /// Ignores priorities, ordering concurrent commands by ID in reverse.
+    struct ReverseOrder;
+
+    impl BraidOrder for ReverseOrder {
+        fn key(&self, command: &dyn Command) -> BraidKey {
+            let id = command.id();
+            let (_, low) = id.as_bytes().split_last_chunk::<8>().unwrap();
+            BraidKey {
+                priority: match command.priority() {
+                    Priority::Basic(_) => Priority::Basic(0),
+                    priority => priority,
+                },
+                bias: !u64::from_be_bytes(*low),
+                id,
+            }
+        }
+    }
+
+    /// Braids the heads `left` and `right` with `order`, returning the IDs of the
+    /// braided commands.
+    fn braid_ids(
+        gb: &mut GraphBuilder<MemStorageProvider>,
+        left: &str,
+        right: &str,
+        order: &dyn BraidOrder,
+    ) -> Vec<CommandId> {
+        let locate = |id| {
+            gb.trx
+                .heads
+                .iter()
+                .find_map(|(address, &location)| (address.id == mkid(id)).then_some(location))
+                .unwrap()
+        };
+        let (left, right) = (locate(left), locate(right));
+        let storage = gb
+            .client
+            .provider
+            .get_storage("a".parse().unwrap())
+            .unwrap();
+        crate::braid_with(storage, left, right, order)
+            .unwrap()
+            .into_iter()
+            .map(|location| {
+                let segment = storage.get_segment(location).unwrap();
+                segment.get_command(location).unwrap().id()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_braid_order() {
+        let mut left_first = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            "a" 0 < "b" "c" "d";
+            "a" 0 < "e" "f";
+        };
+        let mut right_first = graph! {
+            ClientState::new(SeqEngine, MemStorageProvider::new());
+            "a";
+            "a" 0 < "e" "f";
+            "a" 0 < "b" "c" "d";
+        };
+
+        // Every order must braid the same commands the same way, however they were
+        // added.
+        for order in [&DefaultBraidOrder as &dyn BraidOrder, &ReverseOrder] {
+            let expected = braid_ids(&mut left_first, "d", "f", order);
+            assert_eq!(expected.len(), 6);
+            assert_eq!(braid_ids(&mut left_first, "f", "d", order), expected);
+            assert_eq!(braid_ids(&mut right_first, "d", "f", order), expected);
+            assert_eq!(braid_ids(&mut right_first, "f", "d", order), expected);
+        }
+
+        assert_ne!(
+            braid_ids(&mut left_first, "d", "f", &DefaultBraidOrder),
+            braid_ids(&mut left_first, "d", "f", &ReverseOrder),
+        );
+    }
+
     #[test]
     fn test_duplicates() {
         let mut gb = graph! {
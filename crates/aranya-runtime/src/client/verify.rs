@@ -0,0 +1,223 @@
+//! Offline verification of a stored graph.
+//!
+//! See [`ClientState::verify_graph`](crate::ClientState::verify_graph).
+
+use alloc::{collections::BTreeSet, vec::Vec};
+
+use buggy::BugExt;
+
+use crate::{
+    Address, ClientError, Command, CommandId, Engine, EngineError, GraphId, Location, Policy,
+    Prior, Segment, Storage, StorageProvider,
+};
+
+/// A stored command which failed verification, from
+/// [`ClientState::verify_graph`](crate::ClientState::verify_graph).
+#[derive(Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// The ID the command is stored with.
+    pub id: CommandId,
+    /// Where the command is stored.
+    pub location: Location,
+    /// What is wrong with the command.
+    pub kind: CorruptionKind,
+}
+
+/// What is wrong with the command of a [`Corruption`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CorruptionKind {
+    /// The command's parents are not the commands it is stored after.
+    Parent,
+    /// The command's max cut is not one more than the greatest of its parents'.
+    MaxCut,
+    /// The command is the root of the graph, but its ID is not the graph's ID.
+    GraphId,
+    /// The policy rejected the command's envelope, such as because its ID or
+    /// signature is not valid.
+    Envelope(EngineError),
+}
+
+/// Verifies every command of `graph` reachable from its head.
+pub(super) fn verify<SP: StorageProvider, E: Engine>(
+    provider: &mut SP,
+    engine: &E,
+    graph: GraphId,
+) -> Result<Vec<Corruption>, ClientError> {
+    let (locations, root) = {
+        let storage = provider.get_storage(graph)?;
+        let mut seen = BTreeSet::new();
+        let mut queue = Vec::new();
+        queue.push(storage.get_head()?);
+        let mut locations = Vec::new();
+        let mut root = None;
+        while let Some(location) = queue.pop() {
+            if !seen.insert(location.segment) {
+                continue;
+            }
+            let segment = storage.get_segment(location)?;
+            let first = segment.first_location();
+            let count = segment.get_from(first).len();
+            locations.extend((0..count).map(|i| Location::new(first.segment, i)));
+            if matches!(segment.prior(), Prior::None) {
+                root = Some(segment.policy());
+            }
+            queue.extend(segment.prior());
+        }
+        (locations, root.assume("graph must have a root")?)
+    };
+    // The facts before the init command.
+    let mut empty = provider.new_perspective(root);
+    let storage = provider.get_storage(graph)?;
+
+    let mut corruptions = Vec::new();
+    for location in locations {
+        let segment = storage.get_segment(location)?;
+        let command = segment
+            .get_command(location)
+            .assume("location must exist")?;
+        let mut report = |kind| {
+            corruptions.push(Corruption {
+                id: command.id(),
+                location,
+                kind,
+            });
+        };
+
+        let parents = match location.previous() {
+            Some(previous) => Prior::Single(previous),
+            None => segment.prior(),
+        };
+        let address = |location: Location| -> Result<Address, ClientError> {
+            let segment = storage.get_segment(location)?;
+            let command = segment
+                .get_command(location)
+                .assume("location must exist")?;
+            Ok(command.address()?)
+        };
+        let expected = match parents {
+            Prior::None => Prior::None,
+            Prior::Single(parent) => Prior::Single(address(parent)?),
+            Prior::Merge(left, right) => Prior::Merge(address(left)?, address(right)?),
+        };
+        let linked = match (command.parent(), expected) {
+            (Prior::Merge(a, b), Prior::Merge(c, d)) => (a, b) == (c, d) || (a, b) == (d, c),
+            (actual, expected) => actual == expected,
+        };
+        if !linked {
+            report(CorruptionKind::Parent);
+        }
+        if command.max_cut()? != expected.next_max_cut()? {
+            report(CorruptionKind::MaxCut);
+        }
+        if matches!(parents, Prior::None) && command.id().into_id() != graph.into_id() {
+            report(CorruptionKind::GraphId);
+        }
+
+        let policy = engine.get_policy(segment.policy())?;
+        let result = match parents {
+            Prior::None => policy.verify_envelope(&command, &mut empty),
+            Prior::Single(parent) | Prior::Merge(parent, _) => {
+                verify_at(storage, policy, &command, parent)?
+            }
+        };
+        match result {
+            Ok(()) => {}
+            Err(EngineError::Bug(bug)) => return Err(bug.into()),
+            Err(e) => report(CorruptionKind::Envelope(e)),
+        }
+    }
+    Ok(corruptions)
+}
+
+/// Checks the envelope of `command` with the facts at `parent`.
+fn verify_at(
+    storage: &impl Storage,
+    policy: &impl Policy,
+    command: &impl Command,
+    parent: Location,
+) -> Result<Result<(), EngineError>, ClientError> {
+    let mut facts = storage.get_fact_perspective(parent)?;
+    Ok(policy.verify_envelope(command, &mut facts))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine, WireBasic, WireProtocol},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink, PeerCache, Priority,
+    };
+
+    /// A command whose ID was not derived from its contents.
+    struct Forged {
+        id: CommandId,
+        parent: Address,
+        data: Vec<u8>,
+    }
+
+    impl Command for Forged {
+        fn priority(&self) -> Priority {
+            Priority::Basic(0)
+        }
+
+        fn id(&self) -> CommandId {
+            self.id
+        }
+
+        fn parent(&self) -> Prior<Address> {
+            Prior::Single(self.parent)
+        }
+
+        fn policy(&self) -> Option<&[u8]> {
+            None
+        }
+
+        fn bytes(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    #[test]
+    fn test_verify_graph() {
+        let mut client = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = client
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        client
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 1))
+            .unwrap();
+        assert_eq!(client.verify_graph(storage_id).unwrap(), vec![]);
+
+        // The test policy doesn't check IDs when commands are received.
+        let parent = client.head(storage_id).unwrap().address();
+        let forged = Forged {
+            id: CommandId::hash_for_testing_only(b"forged"),
+            parent,
+            data: postcard::to_allocvec(&WireProtocol::Basic(WireBasic {
+                parent,
+                prority: 0,
+                payload: (2, 2),
+            }))
+            .unwrap(),
+        };
+        let mut trx = client.transaction(storage_id);
+        client
+            .add_commands(&mut trx, &mut NullSink, &[forged], &mut PeerCache::new())
+            .unwrap();
+        client.commit(&mut trx, &mut NullSink).unwrap();
+
+        let corruptions = client.verify_graph(storage_id).unwrap();
+        assert_eq!(corruptions.len(), 1);
+        assert_eq!(
+            corruptions[0].id,
+            CommandId::hash_for_testing_only(b"forged")
+        );
+        assert!(matches!(
+            corruptions[0].kind,
+            CorruptionKind::Envelope(EngineError::Check(_))
+        ));
+    }
+}
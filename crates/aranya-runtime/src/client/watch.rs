@@ -0,0 +1,314 @@
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{cmp::Ordering, fmt};
+
+use spin::Mutex;
+use tracing::error;
+
+use crate::{Fact, GraphId, Keys, Location, Query, Storage, StorageError};
+
+type Queue = Mutex<VecDeque<FactChange>>;
+
+/// A change to a watched fact, from a [`FactSubscription`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FactChange {
+    /// The fact `name` with `keys` was created.
+    Created {
+        /// The name of the fact.
+        name: String,
+        /// The keys of the fact.
+        keys: Keys,
+        /// The value of the fact.
+        value: Box<[u8]>,
+    },
+    /// The value of the fact `name` with `keys` changed.
+    Updated {
+        /// The name of the fact.
+        name: String,
+        /// The keys of the fact.
+        keys: Keys,
+        /// The value of the fact before the commit.
+        old: Box<[u8]>,
+        /// The value of the fact after the commit.
+        new: Box<[u8]>,
+    },
+    /// The fact `name` with `keys` was deleted.
+    Deleted {
+        /// The name of the fact.
+        name: String,
+        /// The keys of the fact.
+        keys: Keys,
+        /// The value of the fact before it was deleted.
+        old: Box<[u8]>,
+    },
+}
+
+/// The changes to a set of facts committed by a [`ClientState`](crate::ClientState),
+/// from [`ClientState::watch_facts`](crate::ClientState::watch_facts).
+///
+/// Changes are found by comparing the watched facts at the head of the graph before
+/// and after each commit, so a fact changed and changed back within one commit is not
+/// reported. Changes are queued until they are taken with [`Iterator::next`], which
+/// returns `None` once the queue is empty. Dropping the subscription unsubscribes it.
+pub struct FactSubscription {
+    queue: Arc<Queue>,
+}
+
+impl FactSubscription {
+    /// Returns the number of queued changes.
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    /// Reports whether no changes are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+}
+
+impl Iterator for FactSubscription {
+    type Item = FactChange;
+
+    fn next(&mut self) -> Option<FactChange> {
+        self.queue.lock().pop_front()
+    }
+}
+
+impl fmt::Debug for FactSubscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FactSubscription")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A subscription to the facts of a graph named `name` whose keys start with `prefix`.
+struct Watch {
+    storage_id: GraphId,
+    name: String,
+    prefix: Keys,
+    queue: Weak<Queue>,
+}
+
+/// The subscriptions to a client's facts.
+pub(super) struct FactWatchers {
+    watches: Vec<Watch>,
+}
+
+impl FactWatchers {
+    pub const fn new() -> Self {
+        Self {
+            watches: Vec::new(),
+        }
+    }
+
+    pub fn watch(&mut self, storage_id: GraphId, name: &str, prefix: Keys) -> FactSubscription {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        self.watches.push(Watch {
+            storage_id,
+            name: name.into(),
+            prefix,
+            queue: Arc::downgrade(&queue),
+        });
+        FactSubscription { queue }
+    }
+
+    /// Reports whether any subscription watches the graph `storage_id`.
+    pub fn is_watching(&self, storage_id: GraphId) -> bool {
+        self.watches
+            .iter()
+            .any(|watch| watch.storage_id == storage_id && watch.queue.strong_count() > 0)
+    }
+
+    /// Sends each subscription watching `storage_id` the changes to its facts between
+    /// the head `old`, if any, and the current head of `storage`.
+    ///
+    /// This is called once the changes are committed, so errors are logged rather
+    /// than returned.
+    pub fn publish(&mut self, storage_id: GraphId, storage: &impl Storage, old: Option<Location>) {
+        if let Err(err) = self.try_publish(storage_id, storage, old) {
+            error!(?err, %storage_id, "unable to publish fact changes");
+        }
+    }
+
+    fn try_publish(
+        &mut self,
+        storage_id: GraphId,
+        storage: &impl Storage,
+        old: Option<Location>,
+    ) -> Result<(), StorageError> {
+        self.watches.retain(|watch| watch.queue.strong_count() > 0);
+
+        let new = storage.get_head()?;
+        if old == Some(new) {
+            return Ok(());
+        }
+        let after = storage.get_fact_perspective(new)?;
+        let before = old
+            .map(|old| storage.get_fact_perspective(old))
+            .transpose()?;
+
+        for watch in &self.watches {
+            if watch.storage_id != storage_id {
+                continue;
+            }
+            let Some(queue) = watch.queue.upgrade() else {
+                continue;
+            };
+            let old = match &before {
+                Some(before) => collect(before, &watch.name, &watch.prefix)?,
+                None => Vec::new(),
+            };
+            let new = collect(&after, &watch.name, &watch.prefix)?;
+            queue.lock().extend(diff(&watch.name, old, new));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FactWatchers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FactWatchers")
+            .field("len", &self.watches.len())
+            .finish()
+    }
+}
+
+fn collect(facts: &impl Query, name: &str, prefix: &Keys) -> Result<Vec<Fact>, StorageError> {
+    facts.query_prefix(name, prefix)?.collect()
+}
+
+/// Returns the changes from the facts `old` to the facts `new`, which are both sorted
+/// by key.
+fn diff(name: &str, old: Vec<Fact>, new: Vec<Fact>) -> Vec<FactChange> {
+    let mut changes = Vec::new();
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    loop {
+        let ordering = match (old.peek(), new.peek()) {
+            (Some(o), Some(n)) => o.key.cmp(&n.key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        let change = match ordering {
+            Ordering::Less => old.next().map(|o| FactChange::Deleted {
+                name: name.into(),
+                keys: o.key,
+                old: o.value,
+            }),
+            Ordering::Greater => new.next().map(|n| FactChange::Created {
+                name: name.into(),
+                keys: n.key,
+                value: n.value,
+            }),
+            Ordering::Equal => match (old.next(), new.next()) {
+                (Some(o), Some(n)) if o.value != n.value => Some(FactChange::Updated {
+                    name: name.into(),
+                    keys: n.key,
+                    old: o.value,
+                    new: n.value,
+                }),
+                _ => None,
+            },
+        };
+        changes.extend(change);
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink,
+    };
+
+    fn fact(key: u64, value: u64) -> Fact {
+        Fact {
+            key: Keys::from_iter([key.to_be_bytes()]),
+            value: value.to_be_bytes().into(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![fact(1, 10), fact(2, 20), fact(3, 30)];
+        let new = vec![fact(2, 20), fact(3, 31), fact(4, 40)];
+        assert_eq!(
+            diff("payload", old, new),
+            [
+                FactChange::Deleted {
+                    name: "payload".into(),
+                    keys: fact(1, 0).key,
+                    old: fact(0, 10).value,
+                },
+                FactChange::Updated {
+                    name: "payload".into(),
+                    keys: fact(3, 0).key,
+                    old: fact(0, 30).value,
+                    new: fact(0, 31).value,
+                },
+                FactChange::Created {
+                    name: "payload".into(),
+                    keys: fact(4, 0).key,
+                    value: fact(0, 40).value,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watch_facts() {
+        let mut client = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = client
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        let mut all = client.watch_facts(storage_id, "payload", Keys::default());
+        let mut one =
+            client.watch_facts(storage_id, "payload", Keys::from_iter([1u64.to_be_bytes()]));
+
+        client
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 10))
+            .unwrap();
+        client
+            .action(storage_id, &mut NullSink, TestActions::SetValue(2, 20))
+            .unwrap();
+        client
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 11))
+            .unwrap();
+        assert!(one.by_ref().eq([
+            FactChange::Created {
+                name: "payload".into(),
+                keys: fact(1, 0).key,
+                value: fact(0, 10).value,
+            },
+            FactChange::Updated {
+                name: "payload".into(),
+                keys: fact(1, 0).key,
+                old: fact(0, 10).value,
+                new: fact(0, 11).value,
+            },
+        ]));
+        assert_eq!(all.len(), 3);
+        assert!(all.by_ref().all(|change| matches!(
+            change,
+            FactChange::Created { .. } | FactChange::Updated { .. }
+        )));
+
+        // Dropped subscriptions are no longer sent changes.
+        drop(one);
+        client
+            .action(storage_id, &mut NullSink, TestActions::SetValue(1, 12))
+            .unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(client.facts.watches.len(), 1);
+    }
+}
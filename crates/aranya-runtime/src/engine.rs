@@ -3,6 +3,7 @@
 //! An [`Engine`] stores policies for an application. A [`Policy`] is required
 //! to process [`Command`]s and defines how the runtime's graph is constructed.
 
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 use core::fmt;
 
 use buggy::Bug;
@@ -11,7 +12,8 @@ use serde::{Deserialize, Serialize};
 use crate::{
     command::{Command, CommandId},
     storage::{FactPerspective, Perspective},
-    Address,
+    vm_policy::SizeLimit,
+    Address, BraidOrder, DefaultBraidOrder,
 };
 
 /// An error returned by the runtime engine.
@@ -19,9 +21,13 @@ use crate::{
 pub enum EngineError {
     Read,
     Write,
-    Check,
+    Check(Rejection),
     Panic,
     InternalError,
+    TooLarge(SizeLimit),
+    NoSuchPolicy(PolicyId),
+    /// A foreign function called by the policy failed.
+    Ffi(String),
     Bug(Bug),
 }
 
@@ -30,9 +36,12 @@ impl fmt::Display for EngineError {
         match self {
             Self::Read => write!(f, "read error"),
             Self::Write => write!(f, "write error "),
-            Self::Check => write!(f, "check error"),
+            Self::Check(rejection) => write!(f, "check error: {rejection}"),
             Self::Panic => write!(f, "panic"),
             Self::InternalError => write!(f, "internal error"),
+            Self::TooLarge(limit) => write!(f, "{limit}"),
+            Self::NoSuchPolicy(id) => write!(f, "no such policy: {}", id.value()),
+            Self::Ffi(reason) => write!(f, "FFI error: {reason}"),
             Self::Bug(b) => write!(f, "{b}"),
         }
     }
@@ -46,6 +55,28 @@ impl From<Bug> for EngineError {
 
 impl core::error::Error for EngineError {}
 
+/// Why the policy rejected a command or action with a failed `check`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rejection {
+    /// The reason given by the check, if any.
+    pub reason: Option<String>,
+    /// The line and column of the check in the policy, if known.
+    pub location: Option<(usize, usize)>,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            Some(reason) => write!(f, "{reason}")?,
+            None => write!(f, "no reason given")?,
+        }
+        if let Some((line, col)) = self.location {
+            write!(f, " at line {line} col {col}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PolicyId(usize);
 
@@ -53,6 +84,23 @@ impl PolicyId {
     pub fn new(id: usize) -> Self {
         PolicyId(id)
     }
+
+    /// Returns the numeric value of the ID.
+    pub fn value(&self) -> usize {
+        self.0
+    }
+
+    /// Encodes the ID as policy data for [`Engine::add_policy`], such as to record in
+    /// an init command.
+    pub fn to_bytes(self) -> [u8; 8] {
+        (self.0 as u64).to_le_bytes()
+    }
+
+    /// Decodes an ID written by [`PolicyId::to_bytes`].
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.try_into().ok()?;
+        usize::try_from(u64::from_le_bytes(data)).ok().map(Self)
+    }
 }
 
 /// The [`Engine`] manages storing and retrieving [`Policy`].
@@ -76,6 +124,67 @@ pub trait Engine {
     fn get_policy(&self, id: PolicyId) -> Result<&Self::Policy, EngineError>;
 }
 
+/// An [`Engine`] which holds several versions of a policy, keyed by [`PolicyId`].
+///
+/// A graph is evaluated with the version it was created with, which its init command
+/// records, so a fleet can upgrade its policy in stages: clients hold both versions
+/// while new graphs are created with the new one and existing graphs keep the old one.
+/// Policy data given to [`ClientState::new_graph`](crate::ClientState::new_graph) is a
+/// [`PolicyId::to_bytes`], and a client can only create or receive graphs whose policy
+/// version it holds.
+pub struct PolicyStore<P> {
+    policies: BTreeMap<PolicyId, P>,
+}
+
+impl<P> PolicyStore<P> {
+    /// Creates a store with no policies.
+    pub const fn new() -> Self {
+        Self {
+            policies: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `policy` as the version `id`.
+    pub fn with_policy(mut self, id: PolicyId, policy: P) -> Self {
+        self.insert(id, policy);
+        self
+    }
+
+    /// Adds `policy` as the version `id`, returning the policy it replaces, if any.
+    pub fn insert(&mut self, id: PolicyId, policy: P) -> Option<P> {
+        self.policies.insert(id, policy)
+    }
+
+    /// Returns the IDs of the versions held.
+    pub fn ids(&self) -> impl Iterator<Item = PolicyId> + '_ {
+        self.policies.keys().copied()
+    }
+}
+
+impl<P> Default for PolicyStore<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Policy> Engine for PolicyStore<P> {
+    type Policy = P;
+    type Effect = P::Effect;
+
+    /// Selects the version named by `policy`, a [`PolicyId::to_bytes`].
+    fn add_policy(&mut self, policy: &[u8]) -> Result<PolicyId, EngineError> {
+        let id = PolicyId::from_bytes(policy).ok_or(EngineError::Read)?;
+        if !self.policies.contains_key(&id) {
+            return Err(EngineError::NoSuchPolicy(id));
+        }
+        Ok(id)
+    }
+
+    fn get_policy(&self, id: PolicyId) -> Result<&Self::Policy, EngineError> {
+        self.policies.get(&id).ok_or(EngineError::NoSuchPolicy(id))
+    }
+}
+
 /// The [`Sink`] transactionally consumes effects from evaluating [`Policy`].
 pub trait Sink<E> {
     fn begin(&mut self);
@@ -169,6 +278,70 @@ pub trait Policy {
         sink: &mut impl Sink<Self::Effect>,
     ) -> Result<(), EngineError>;
 
+    /// Processes an action like [`call_action()`](Self::call_action), for a preview whose
+    /// results are discarded. Effects outside of `facts` and `sink`, such as storing keys,
+    /// should not be made. Policies with such effects must override this; by default it
+    /// calls `call_action()`.
+    fn call_action_dry_run(
+        &self,
+        action: Self::Action<'_>,
+        facts: &mut impl Perspective,
+        sink: &mut impl Sink<Self::Effect>,
+    ) -> Result<(), EngineError> {
+        self.call_action(action, facts, sink)
+    }
+
+    /// Returns how concurrent commands are ordered when branches of the graph are
+    /// braided. Defaults to [`DefaultBraidOrder`].
+    fn braid_order(&self) -> &dyn BraidOrder {
+        &DefaultBraidOrder
+    }
+
+    /// Returns the max cut after which `command` has expired, if it expires.
+    ///
+    /// Expiry is measured in max cut, the graph's logical clock, since peers can't agree
+    /// on wall-clock time. A command is rejected if its own max cut is past its expiry,
+    /// and is recalled when branches are merged if the head of a branch it is concurrent
+    /// with is past its expiry. Defaults to `None`.
+    fn expiry(&self, _command: &impl Command) -> Result<Option<usize>, EngineError> {
+        Ok(None)
+    }
+
+    /// Checks the envelope of `command`, such as its ID and signature, without
+    /// evaluating it. `facts` are the facts at the parent of `command`.
+    ///
+    /// Used by [`ClientState::verify_graph`](crate::ClientState::verify_graph) to find
+    /// commands which were corrupted in storage. Defaults to accepting every envelope.
+    fn verify_envelope(
+        &self,
+        _command: &impl Command,
+        _facts: &mut impl FactPerspective,
+    ) -> Result<(), EngineError> {
+        Ok(())
+    }
+
+    /// Returns the names of the facts this policy may write, so the facts of a graph
+    /// can be enumerated, such as by
+    /// [`ClientState::export_snapshot`](crate::ClientState::export_snapshot).
+    /// Defaults to none.
+    fn fact_names(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    /// Returns the ID of the command which `command` revokes, if it is a revocation.
+    /// `facts` are the facts at the parent of `command`.
+    ///
+    /// Before a revocation is evaluated, the runtime recomputes the facts written
+    /// since the revoked command as if it had never been accepted, recalling the
+    /// commands which no longer pass their checks. Defaults to `None`.
+    fn revokes(
+        &self,
+        _command: &impl Command,
+        _facts: &mut impl FactPerspective,
+    ) -> Result<Option<CommandId>, EngineError> {
+        Ok(None)
+    }
+
     /// Produces a merge message serialized to target. The `struct` representing the
     /// Command is returned.
     fn merge<'a>(
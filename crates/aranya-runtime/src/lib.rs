@@ -48,6 +48,7 @@ mod client;
 pub mod command;
 pub mod engine;
 pub mod metrics;
+pub mod nonblocking;
 mod prior;
 pub mod protocol;
 pub mod storage;
@@ -2,16 +2,102 @@
 //!
 //! [`Metrics`] provide an API to collect information about operations preformed within the Aranya runtime.
 
+use alloc::sync::Arc;
 use core::{
     fmt::{self, Display},
     time::Duration,
 };
 
+/// The number of commands received from peers which were validated by the policy.
+pub const COMMANDS_VALIDATED: &str = "commands_validated";
+/// The number of commands or actions rejected by a failed `check`.
+pub const CHECKS_FAILED: &str = "checks_failed";
+/// The number of bytes of sync messages sent.
+pub const SYNC_BYTES_SENT: &str = "sync_bytes_sent";
+/// The number of bytes of sync messages received.
+pub const SYNC_BYTES_RECEIVED: &str = "sync_bytes_received";
+/// The time taken to merge the branches of a graph when committing a
+/// [`Transaction`](crate::Transaction).
+pub const MERGE_DURATION: &str = "merge_duration";
+
 /// [`Metrics`] provides an interface to push a named [`Metric`] to a collection.
-pub trait Metrics {
-    type Error: core::error::Error + Send + Sync + 'static;
+///
+/// The runtime reports into it from [`ClientState`](crate::ClientState),
+/// [`SyncRequester`](crate::SyncRequester) and [`SyncResponder`](crate::SyncResponder).
+/// Each report goes through the method for that metric, which calls
+/// [`Metrics::update`] with the metric's name by default. Every method does nothing
+/// unless overridden.
+///
+/// Metrics are shared between a client and its syncers, so they are updated through a
+/// shared reference. Use atomics or a lock to record them.
+pub trait Metrics: Send + Sync {
+    /// Records a named metric.
+    fn update(&self, name: &'static str, metric: Metric) {
+        let _ = (name, metric);
+    }
+
+    /// Records that `count` commands received from peers were validated. See
+    /// [`COMMANDS_VALIDATED`].
+    fn commands_validated(&self, count: usize) {
+        self.update(COMMANDS_VALIDATED, Metric::Count(count as u64));
+    }
+
+    /// Records that a command or action failed a check. See [`CHECKS_FAILED`].
+    fn check_failed(&self) {
+        self.update(CHECKS_FAILED, Metric::Count(1));
+    }
+
+    /// Records that a sync message of `bytes` bytes was sent. See [`SYNC_BYTES_SENT`].
+    fn sync_bytes_sent(&self, bytes: usize) {
+        self.update(SYNC_BYTES_SENT, Metric::Count(bytes as u64));
+    }
+
+    /// Records that a sync message of `bytes` bytes was received. See
+    /// [`SYNC_BYTES_RECEIVED`].
+    fn sync_bytes_received(&self, bytes: usize) {
+        self.update(SYNC_BYTES_RECEIVED, Metric::Count(bytes as u64));
+    }
+
+    /// Records how long merging took when committing a transaction. See
+    /// [`MERGE_DURATION`].
+    fn merge_duration(&self, duration: Duration) {
+        self.update(MERGE_DURATION, Metric::Duration(duration));
+    }
+}
 
-    fn update(&mut self, name: &'static str, metric: Metric) -> Result<(), Self::Error>;
+/// [`Metrics`] which discards every metric. This is the default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// The [`Metrics`] a component reports into, if any.
+#[derive(Clone, Default)]
+pub(crate) struct MetricsHandle(Option<Arc<dyn Metrics>>);
+
+impl MetricsHandle {
+    pub const fn new() -> Self {
+        Self(None)
+    }
+
+    pub fn set(&mut self, metrics: Arc<dyn Metrics>) {
+        self.0 = Some(metrics);
+    }
+
+    pub fn get(&self) -> &dyn Metrics {
+        match &self.0 {
+            Some(metrics) => metrics.as_ref(),
+            None => &NoMetrics,
+        }
+    }
+}
+
+impl fmt::Debug for MetricsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MetricsHandle")
+            .field(&self.0.is_some())
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
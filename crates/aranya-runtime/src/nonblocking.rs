@@ -0,0 +1,370 @@
+//! An async API for the runtime.
+//!
+//! [`ClientState`] is synchronous: actions, syncs, and storage I/O run
+//! on the calling thread until they finish. [`AsyncClient`] shares a
+//! `ClientState` between tasks and runs that work on tokio's blocking
+//! thread pool, so it can be awaited from async code without stalling
+//! the runtime's worker threads.
+//!
+//! Requires a tokio runtime with blocking threads, which every tokio
+//! runtime has.
+
+#![cfg(feature = "async")]
+#![cfg_attr(docsrs, doc(cfg(feature = "async")))]
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::future::Future;
+use std::io;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    sync::Mutex,
+    task::{self, JoinError},
+};
+
+use crate::{
+    ClientError, ClientState, Engine, GraphId, PeerCache, Policy, Receipt, Sink, StorageProvider,
+    SyncError, SyncRequestMessage, SyncRequester, SyncResponder, MAX_SYNC_MESSAGE_SIZE,
+};
+
+/// An error returned by an [`AsyncClient`].
+#[derive(Debug, thiserror::Error)]
+pub enum AsyncError {
+    /// An error from the client.
+    #[error("client error: {0}")]
+    Client(#[from] ClientError),
+    /// A sync protocol error.
+    #[error("sync error: {0}")]
+    Sync(#[from] SyncError),
+    /// An error sending a sync request or receiving its response.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    /// The blocking task panicked or was cancelled.
+    #[error("task failed: {0}")]
+    Join(#[from] JoinError),
+}
+
+/// A [`ClientState`] which can be used from async code.
+///
+/// Clones share the same `ClientState`. Work on it is run one call at
+/// a time, in the order it was requested.
+pub struct AsyncClient<E: Engine, SP> {
+    state: Arc<Mutex<ClientState<E, SP>>>,
+}
+
+impl<E: Engine, SP> Clone for AsyncClient<E, SP> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<E, SP> AsyncClient<E, SP>
+where
+    E: Engine + Send + 'static,
+    E::Effect: Send,
+    SP: StorageProvider + Send + 'static,
+{
+    /// Creates an `AsyncClient` which owns `state`.
+    pub fn new(state: ClientState<E, SP>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Runs `f` with the client on a blocking thread, returning its
+    /// result.
+    ///
+    /// This is how to reach anything not otherwise provided here, such
+    /// as the storage provider.
+    pub async fn run<F, R>(&self, f: F) -> Result<R, AsyncError>
+    where
+        F: FnOnce(&mut ClientState<E, SP>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut state = Arc::clone(&self.state).lock_owned().await;
+        Ok(task::spawn_blocking(move || f(&mut state)).await?)
+    }
+
+    /// Performs an `action`, writing the results to `sink`. Returns a
+    /// [`Receipt`] for the committed commands. See [`ClientState::action`].
+    pub async fn action<S>(
+        &self,
+        storage_id: GraphId,
+        sink: &Arc<Mutex<S>>,
+        action: <E::Policy as Policy>::Action<'static>,
+    ) -> Result<Receipt, AsyncError>
+    where
+        S: Sink<E::Effect> + Send + 'static,
+        <E::Policy as Policy>::Action<'static>: Send,
+    {
+        let mut sink = Arc::clone(sink).lock_owned().await;
+        let receipt = self
+            .run(move |client| client.action(storage_id, &mut *sink, action))
+            .await??;
+        Ok(receipt)
+    }
+
+    /// Waits until the commands in `receipt` are durable. See
+    /// [`ClientState::wait_durable`].
+    pub async fn wait_durable(&self, receipt: Receipt) -> Result<(), AsyncError> {
+        self.run(move |client| client.wait_durable(&receipt))
+            .await??;
+        Ok(())
+    }
+
+    /// Syncs the graph in `requester` from a peer, adding the commands
+    /// received to the client. Returns the number of commands received.
+    ///
+    /// `send` sends the request to the responder at
+    /// [`SyncRequester::server_addr`] and returns its response, which
+    /// is empty if the client is up to date. See [`AsyncClient::respond`].
+    pub async fn sync<A, S, F, Fut>(
+        &self,
+        mut requester: SyncRequester<'static, A>,
+        heads: &mut PeerCache,
+        sink: &Arc<Mutex<S>>,
+        send: F,
+    ) -> Result<usize, AsyncError>
+    where
+        A: DeserializeOwned + Serialize + Clone + Send + 'static,
+        S: Sink<E::Effect> + Send + 'static,
+        F: FnOnce(Vec<u8>) -> Fut,
+        Fut: Future<Output = io::Result<Vec<u8>>>,
+    {
+        // `heads` is only updated once each task finishes, so it is
+        // kept if a task fails or this future is dropped.
+        let mut cache = heads.clone();
+        let (result, requester, cache) = self
+            .run(move |client| {
+                let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+                let result = requester.poll(&mut buffer, client.provider(), &mut cache);
+                (
+                    result.map(|(len, _)| buffer[..len].to_vec()),
+                    requester,
+                    cache,
+                )
+            })
+            .await?;
+        *heads = cache;
+
+        let response = send(result?).await?;
+        // An empty response means we're up to date and there's nothing to sync.
+        if response.is_empty() {
+            return Ok(0);
+        }
+
+        let mut sink = Arc::clone(sink).lock_owned().await;
+        let mut cache = heads.clone();
+        let (result, cache) = self
+            .run(move |client| {
+                let result = receive(client, requester, &response, &mut *sink, &mut cache);
+                (result, cache)
+            })
+            .await?;
+        *heads = cache;
+        result
+    }
+
+    /// Responds to a sync `request` from a peer with `responder`,
+    /// returning the response to send back. The response is empty if
+    /// the peer is up to date.
+    ///
+    /// `cache` holds the peer's heads for the graph being synced.
+    pub async fn respond<A>(
+        &self,
+        mut responder: SyncResponder<A>,
+        request: SyncRequestMessage,
+        cache: &mut PeerCache,
+    ) -> Result<Vec<u8>, AsyncError>
+    where
+        A: Serialize + Clone + Send + 'static,
+    {
+        // `cache` is only updated once the task finishes, so it is kept
+        // if the task fails or this future is dropped.
+        let mut peer = cache.clone();
+        let (result, peer) = self
+            .run(move |client| {
+                let result = responder.receive(request).and_then(|()| {
+                    let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+                    let len = responder.poll(&mut buffer, client.provider(), &mut peer)?;
+                    buffer.truncate(len);
+                    Ok(buffer)
+                });
+                (result, peer)
+            })
+            .await?;
+        *cache = peer;
+        Ok(result?)
+    }
+}
+
+/// Adds the commands in a sync `response` to the client.
+fn receive<E, SP, A>(
+    client: &mut ClientState<E, SP>,
+    mut requester: SyncRequester<'_, A>,
+    response: &[u8],
+    sink: &mut impl Sink<E::Effect>,
+    heads: &mut PeerCache,
+) -> Result<usize, AsyncError>
+where
+    E: Engine,
+    SP: StorageProvider,
+    A: DeserializeOwned + Serialize + Clone,
+{
+    let storage_id = requester.storage_id();
+    let response = requester.decompress(response)?;
+    let Some(cmds) = requester.receive(&response)? else {
+        return Ok(0);
+    };
+    let mut trx = client.transaction(storage_id);
+    client.add_commands(&mut trx, sink, &cmds, heads)?;
+    client.commit(&mut trx, sink)?;
+    Ok(cmds.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::Rng;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEffect, TestEngine},
+        storage::memory::MemStorageProvider,
+        NullSink, SyncType,
+    };
+
+    type Client = AsyncClient<TestEngine, MemStorageProvider>;
+
+    fn new_client() -> Client {
+        AsyncClient::new(ClientState::new(
+            TestEngine::new(),
+            MemStorageProvider::new(),
+        ))
+    }
+
+    /// Panics on every effect.
+    struct PanicSink;
+
+    impl Sink<TestEffect> for PanicSink {
+        fn begin(&mut self) {}
+
+        fn consume(&mut self, _effect: TestEffect) {
+            panic!("unexpected effect")
+        }
+
+        fn rollback(&mut self) {}
+
+        fn commit(&mut self) {}
+    }
+
+    /// Syncs `storage_id` in `to` from `from`.
+    async fn sync<S>(
+        storage_id: GraphId,
+        from: &Client,
+        to: &Client,
+        requester_cache: &mut PeerCache,
+        responder_cache: &mut PeerCache,
+        sink: &Arc<Mutex<S>>,
+    ) -> Result<usize, AsyncError>
+    where
+        S: Sink<TestEffect> + Send + 'static,
+    {
+        let requester = SyncRequester::new(storage_id, &mut Rng, ());
+        to.sync(requester, requester_cache, sink, |request| async move {
+            let SyncType::Poll { request, .. } =
+                postcard::from_bytes::<SyncType<()>>(&request).unwrap()
+            else {
+                panic!("expected a poll")
+            };
+            let responder = SyncResponder::new(());
+            Ok(from
+                .respond(responder, request, responder_cache)
+                .await
+                .unwrap())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_async_sync() {
+        let from = new_client();
+        let to = new_client();
+        let sink = Arc::new(Mutex::new(NullSink));
+
+        let storage_id = from
+            .run(|client| {
+                client.new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..5 {
+            from.action(storage_id, &sink, TestActions::SetValue(i, i))
+                .await
+                .unwrap();
+        }
+
+        let mut responder_cache = PeerCache::new();
+        let mut requester_cache = PeerCache::new();
+        for expect in [6, 0] {
+            let received = sync(
+                storage_id,
+                &from,
+                &to,
+                &mut requester_cache,
+                &mut responder_cache,
+                &sink,
+            )
+            .await
+            .unwrap();
+            assert_eq!(received, expect);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_sync_keeps_cache() {
+        let from = new_client();
+        let to = new_client();
+        let sink = Arc::new(Mutex::new(NullSink));
+
+        let storage_id = from
+            .run(|client| {
+                client.new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let mut responder_cache = PeerCache::new();
+        let mut requester_cache = PeerCache::new();
+        sync(
+            storage_id,
+            &from,
+            &to,
+            &mut requester_cache,
+            &mut responder_cache,
+            &sink,
+        )
+        .await
+        .unwrap();
+        let heads = requester_cache.heads().to_vec();
+        assert!(!heads.is_empty());
+
+        from.action(storage_id, &sink, TestActions::SetValue(1, 1))
+            .await
+            .unwrap();
+        let err = sync(
+            storage_id,
+            &from,
+            &to,
+            &mut requester_cache,
+            &mut responder_cache,
+            &Arc::new(Mutex::new(PanicSink)),
+        )
+        .await
+        .expect_err("the sink should panic");
+        assert!(matches!(err, AsyncError::Join(_)), "{err}");
+        assert_eq!(requester_cache.heads(), heads);
+    }
+}
@@ -11,7 +11,7 @@ use super::{
     alloc, Command, CommandId, Engine, EngineError, FactPerspective, Perspective, Policy, PolicyId,
     Prior, Priority, Sink, StorageError, MAX_COMMAND_LENGTH,
 };
-use crate::{Address, CommandRecall, Keys, MergeIds};
+use crate::{Address, CommandRecall, Keys, MergeIds, Rejection};
 
 impl From<StorageError> for EngineError {
     fn from(_: StorageError) -> Self {
@@ -50,11 +50,18 @@ pub struct WireBasic {
     pub payload: (u64, u64),
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WireRevoke {
+    pub parent: Address,
+    pub revoked: CommandId,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WireProtocol {
     Init(WireInit),
     Merge(WireMerge),
     Basic(WireBasic),
+    Revoke(WireRevoke),
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +77,7 @@ impl Command for TestProtocol<'_> {
             WireProtocol::Init(_) => Priority::Init,
             WireProtocol::Merge(_) => Priority::Merge,
             WireProtocol::Basic(m) => Priority::Basic(m.prority),
+            WireProtocol::Revoke(_) => Priority::Basic(0),
         }
     }
 
@@ -82,6 +90,7 @@ impl Command for TestProtocol<'_> {
             WireProtocol::Init(_) => Prior::None,
             WireProtocol::Basic(m) => Prior::Single(m.parent),
             WireProtocol::Merge(m) => Prior::Merge(m.left, m.right),
+            WireProtocol::Revoke(m) => Prior::Single(m.parent),
         }
     }
 
@@ -90,6 +99,7 @@ impl Command for TestProtocol<'_> {
             WireProtocol::Init(m) => Some(&m.policy_num),
             WireProtocol::Merge(_) => None,
             WireProtocol::Basic(_) => None,
+            WireProtocol::Revoke(_) => None,
         }
     }
 
@@ -108,6 +118,16 @@ impl TestEngine {
             policy: TestPolicy::new(0),
         }
     }
+
+    /// Creates an engine whose basic commands expire `ttl` after their max cut.
+    pub fn with_ttl(ttl: usize) -> TestEngine {
+        TestEngine {
+            policy: TestPolicy {
+                ttl: Some(ttl),
+                ..TestPolicy::new(0)
+            },
+        }
+    }
 }
 
 impl Default for TestEngine {
@@ -131,11 +151,12 @@ impl Engine for TestEngine {
 
 pub struct TestPolicy {
     serial: u32,
+    ttl: Option<usize>,
 }
 
 impl TestPolicy {
     pub fn new(serial: u32) -> Self {
-        TestPolicy { serial }
+        TestPolicy { serial, ttl: None }
     }
 
     fn origin_check_message(
@@ -275,6 +296,7 @@ impl Sink<TestEffect> for TestSink {
 pub enum TestActions {
     Init(u64),
     SetValue(u64, u64),
+    Revoke(CommandId),
 }
 
 impl Policy for TestPolicy {
@@ -297,6 +319,45 @@ impl Policy for TestPolicy {
         self.call_rule_internal(&policy_command, facts, sink)
     }
 
+    fn expiry(&self, command: &impl Command) -> Result<Option<usize>, EngineError> {
+        let Some(ttl) = self.ttl else {
+            return Ok(None);
+        };
+        match from_bytes(command.bytes())? {
+            WireProtocol::Basic(_) => Ok(Some(command.max_cut()?.saturating_add(ttl))),
+            _ => Ok(None),
+        }
+    }
+
+    fn verify_envelope(
+        &self,
+        command: &impl Command,
+        _facts: &mut impl FactPerspective,
+    ) -> Result<(), EngineError> {
+        if CommandId::hash_for_testing_only(command.bytes()) != command.id() {
+            return Err(EngineError::Check(Rejection {
+                reason: Some("command ID does not match its contents".into()),
+                location: None,
+            }));
+        }
+        Ok(())
+    }
+
+    fn fact_names(&self) -> Vec<&str> {
+        Vec::from(["payload"])
+    }
+
+    fn revokes(
+        &self,
+        command: &impl Command,
+        _facts: &mut impl FactPerspective,
+    ) -> Result<Option<CommandId>, EngineError> {
+        match from_bytes(command.bytes())? {
+            WireProtocol::Revoke(m) => Ok(Some(m.revoked)),
+            _ => Ok(None),
+        }
+    }
+
     fn merge<'a>(
         &self,
         target: &'a mut [u8],
@@ -342,6 +403,18 @@ impl Policy for TestPolicy {
 
                 self.call_rule_internal(&command.command, facts, sink)?;
 
+                facts.add_command(&command)?;
+            }
+            TestActions::Revoke(revoked) => {
+                let mut buffer = [0u8; MAX_COMMAND_LENGTH];
+                let target = buffer.as_mut_slice();
+                let command = WireProtocol::Revoke(WireRevoke { parent, revoked });
+                let data = write(target, &command)?;
+                let id = CommandId::hash_for_testing_only(data);
+                let command = TestProtocol { id, command, data };
+
+                self.call_rule_internal(&command.command, facts, sink)?;
+
                 facts.add_command(&command)?;
             }
         }
@@ -0,0 +1,257 @@
+//! Encryption at rest for linear storage.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use aranya_crypto::{CipherSuite, Engine, KeyStore, KeyStoreExt, Rng, StorageKey, StorageKeyId};
+use buggy::BugExt;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use super::io::{IoManager, Read, Write};
+use crate::{GraphId, Location, StorageError};
+
+/// An [`IoManager`] which encrypts the items written through
+/// another `IoManager` (i.e., segments and fact indices) with a
+/// [`StorageKey`].
+///
+/// Each item is bound to its graph and offset, so items cannot
+/// be moved between graphs or offsets without detection. Commit
+/// heads are not encrypted.
+pub struct EncryptedManager<M, CS> {
+    inner: M,
+    key: Arc<StorageKey<CS>>,
+}
+
+impl<M, CS: CipherSuite> EncryptedManager<M, CS> {
+    /// Creates an `EncryptedManager` which encrypts items with
+    /// `key`.
+    pub fn new(inner: M, key: StorageKey<CS>) -> Self {
+        Self {
+            inner,
+            key: Arc::new(key),
+        }
+    }
+
+    /// Creates an `EncryptedManager` with the [`StorageKey`]
+    /// `id` from `store`, which is unwrapped by `eng`.
+    ///
+    /// Returns `None` if the key does not exist.
+    pub fn from_keystore<E, S>(
+        inner: M,
+        eng: &mut E,
+        store: &S,
+        id: StorageKeyId,
+    ) -> Result<Option<Self>, S::Error>
+    where
+        E: Engine<CS = CS>,
+        S: KeyStore,
+    {
+        let key = store.get_key::<E, StorageKey<CS>>(eng, id.into())?;
+        Ok(key.map(|key| Self::new(inner, key)))
+    }
+}
+
+impl<M: IoManager, CS: CipherSuite> IoManager for EncryptedManager<M, CS> {
+    type Writer = EncryptedWriter<M::Writer, CS>;
+
+    fn create(&mut self, id: GraphId) -> Result<Self::Writer, StorageError> {
+        Ok(EncryptedWriter {
+            inner: self.inner.create(id)?,
+            id,
+            key: Arc::clone(&self.key),
+        })
+    }
+
+    fn open(&mut self, id: GraphId) -> Result<Option<Self::Writer>, StorageError> {
+        Ok(self.inner.open(id)?.map(|inner| EncryptedWriter {
+            inner,
+            id,
+            key: Arc::clone(&self.key),
+        }))
+    }
+}
+
+/// A writer which encrypts items before writing them.
+pub struct EncryptedWriter<W, CS> {
+    inner: W,
+    id: GraphId,
+    key: Arc<StorageKey<CS>>,
+}
+
+impl<W: Write, CS: CipherSuite> Write for EncryptedWriter<W, CS> {
+    type ReadOnly = EncryptedReader<W::ReadOnly, CS>;
+
+    fn readonly(&self) -> Self::ReadOnly {
+        EncryptedReader {
+            inner: self.inner.readonly(),
+            id: self.id,
+            key: Arc::clone(&self.key),
+        }
+    }
+
+    fn head(&self) -> Result<Location, StorageError> {
+        self.inner.head()
+    }
+
+    fn append<F, T>(&mut self, builder: F) -> Result<T, StorageError>
+    where
+        F: FnOnce(usize) -> T,
+        T: Serialize,
+    {
+        let key = &self.key;
+        let id = self.id;
+        let mut item = None;
+        let mut result = Ok(());
+        // If sealing fails, an empty item is written instead. It
+        // is never committed, so it is unreachable.
+        self.inner.append(|offset| {
+            let value = builder(offset);
+            let ciphertext = seal(key, id, offset, &value).unwrap_or_else(|err| {
+                result = Err(err);
+                Vec::new()
+            });
+            item = Some(value);
+            ciphertext
+        })?;
+        result?;
+        Ok(item.assume("`append` calls the builder")?)
+    }
+
+    fn commit(&mut self, head: Location) -> Result<(), StorageError> {
+        self.inner.commit(head)
+    }
+
+    fn begin_batch(&mut self) -> Result<(), StorageError> {
+        self.inner.begin_batch()
+    }
+
+    fn end_batch(&mut self) -> Result<(), StorageError> {
+        self.inner.end_batch()
+    }
+}
+
+/// A reader which decrypts items after reading them.
+pub struct EncryptedReader<R, CS> {
+    inner: R,
+    id: GraphId,
+    key: Arc<StorageKey<CS>>,
+}
+
+impl<R: Clone, CS> Clone for EncryptedReader<R, CS> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            id: self.id,
+            key: Arc::clone(&self.key),
+        }
+    }
+}
+
+impl<R: Read, CS: CipherSuite> Read for EncryptedReader<R, CS> {
+    fn fetch<T>(&self, offset: usize) -> Result<T, StorageError>
+    where
+        T: DeserializeOwned,
+    {
+        let ciphertext: Vec<u8> = self.inner.fetch(offset)?;
+        let len = ciphertext
+            .len()
+            .checked_sub(self.key.overhead())
+            .ok_or_else(|| {
+                error!(offset, "item is too short to decrypt");
+                StorageError::IoError
+            })?;
+        let mut plaintext = vec![0u8; len];
+        self.key
+            .open(&mut plaintext, &ciphertext, &ad(self.id, offset)?)
+            .map_err(|err| {
+                error!(?err, offset, "unable to decrypt item");
+                StorageError::IoError
+            })?;
+        postcard::from_bytes(&plaintext).map_err(|err| {
+            error!(?err, "fetch");
+            StorageError::IoError
+        })
+    }
+}
+
+/// Serializes and encrypts the item at `offset`.
+fn seal<CS: CipherSuite, T: Serialize>(
+    key: &StorageKey<CS>,
+    id: GraphId,
+    offset: usize,
+    item: &T,
+) -> Result<Vec<u8>, StorageError> {
+    let plaintext = postcard::to_allocvec(item).map_err(|err| {
+        error!(?err, "append");
+        StorageError::IoError
+    })?;
+    let len = plaintext
+        .len()
+        .checked_add(key.overhead())
+        .assume("ciphertext length will not overflow")?;
+    let mut ciphertext = vec![0u8; len];
+    key.seal(&mut Rng, &mut ciphertext, &plaintext, &ad(id, offset)?)
+        .map_err(|err| {
+            error!(?err, offset, "unable to encrypt item");
+            StorageError::IoError
+        })?;
+    Ok(ciphertext)
+}
+
+/// Returns the associated data for the item at `offset`.
+fn ad(id: GraphId, offset: usize) -> Result<Vec<u8>, StorageError> {
+    let offset = u64::try_from(offset).assume("offset fits in `u64`")?;
+    let mut ad = Vec::with_capacity(72);
+    ad.extend_from_slice(id.as_bytes());
+    ad.extend_from_slice(&offset.to_be_bytes());
+    Ok(ad)
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::default::DefaultCipherSuite;
+
+    use super::*;
+    use crate::{
+        storage::linear::{testing::Manager, LinearStorageProvider},
+        testing::dsl::{test_suite, StorageBackend},
+    };
+
+    type TestManager = EncryptedManager<Manager, DefaultCipherSuite>;
+
+    #[test]
+    fn test_round_trip() {
+        let mut manager = TestManager::new(Manager, StorageKey::new(&mut Rng));
+        let mut writer = manager.create(GraphId::default()).unwrap();
+        let item: (usize, u64) = writer.append(|offset| (offset, 42)).unwrap();
+        let reader = writer.readonly();
+        assert_eq!(reader.fetch::<(usize, u64)>(item.0).unwrap(), item);
+
+        // The item is not readable with another key.
+        let other = EncryptedReader {
+            inner: reader.inner.clone(),
+            id: reader.id,
+            key: Arc::new(StorageKey::<DefaultCipherSuite>::new(&mut Rng)),
+        };
+        assert!(other.fetch::<(usize, u64)>(item.0).is_err());
+
+        // Nor from another graph.
+        let other = EncryptedReader {
+            id: GraphId::from([1; 64]),
+            ..reader
+        };
+        assert!(other.fetch::<(usize, u64)>(item.0).is_err());
+    }
+
+    struct EncryptedBackend;
+
+    impl StorageBackend for EncryptedBackend {
+        type StorageProvider = LinearStorageProvider<TestManager>;
+
+        fn provider(&mut self, _client_id: u64) -> Self::StorageProvider {
+            LinearStorageProvider::new(TestManager::new(Manager, StorageKey::new(&mut Rng)))
+        }
+    }
+
+    test_suite!(|| EncryptedBackend);
+}
@@ -5,6 +5,11 @@
 //! implementation (e.g. `flock(LOCK_EX)`) then the user must ensure this. For
 //! example, accidentally running two instances of the program will cause
 //! issues.
+//!
+//! Implementations which enforce this should fail with
+//! [`StorageError::Locked`] when another writer has the file open. Readers
+//! which open a file without writing to it should fail writes with
+//! [`StorageError::ReadOnly`].
 
 use serde::{de::DeserializeOwned, Serialize};
 
@@ -39,6 +44,19 @@ pub trait Write {
 
     /// Set the commit head.
     fn commit(&mut self, head: Location) -> Result<(), StorageError>;
+
+    /// Begin a batch of appends.
+    ///
+    /// Items appended until [`Write::end_batch`] may be buffered and written
+    /// together, so they may not be fetched until the batch ends.
+    fn begin_batch(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// End a batch begun with [`Write::begin_batch`], writing its items.
+    fn end_batch(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
 }
 
 /// A share-able reader for a linear storage graph.
@@ -21,6 +21,7 @@ use crate::{
 pub struct FileManager {
     #[cfg_attr(target_os = "vxworks", allow(dead_code))]
     fd: OwnedFd,
+    read_only: bool,
 
     // VxWorks doesn't support `openat`, so we also need to store
     // the path.
@@ -30,10 +31,29 @@ pub struct FileManager {
 
 impl FileManager {
     /// Creates a `FileManager` at `dir`.
+    ///
+    /// Each graph it opens is locked with `flock(LOCK_EX)` until its writer is
+    /// dropped, so opening a graph which another writer, such as one in another
+    /// process, has open fails with [`StorageError::Locked`].
     pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        Self::with_access(dir, false)
+    }
+
+    /// Creates a `FileManager` at `dir` which only reads graphs.
+    ///
+    /// Graphs are opened without locking them, so they can be read while another
+    /// process writes to them. Each read of the head picks up the writer's latest
+    /// commit. Creating a graph, or writing to one, fails with
+    /// [`StorageError::ReadOnly`].
+    pub fn read_only<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        Self::with_access(dir, true)
+    }
+
+    fn with_access<P: AsRef<Path>>(dir: P, read_only: bool) -> Result<Self, Error> {
         let fd = libc::open(dir.as_ref(), O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0)?;
         Ok(Self {
             fd,
+            read_only,
             // TODO(eric): skip the alloc if `P` is `PathBuf`?
             #[cfg(target_os = "vxworks")]
             dir: dir.as_ref().to_path_buf(),
@@ -57,6 +77,9 @@ impl IoManager for FileManager {
     type Writer = Writer;
 
     fn create(&mut self, id: GraphId) -> Result<Self::Writer, StorageError> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
         let name = id.to_path()?;
         let fd = libc::openat(
             self.root(),
@@ -64,28 +87,43 @@ impl IoManager for FileManager {
             O_RDWR | O_CREAT | O_EXCL | O_CLOEXEC,
             S_IRUSR | S_IWUSR | S_IRGRP | S_IWGRP,
         )?;
-        libc::flock(&fd, LOCK_EX | LOCK_NB)?;
+        lock(&fd)?;
         // TODO(jdygert): fallocate?
         Writer::create(fd)
     }
 
     fn open(&mut self, id: GraphId) -> Result<Option<Self::Writer>, StorageError> {
         let name = id.to_path()?;
-        let fd = match libc::openat(self.root(), name, O_RDWR | O_CLOEXEC, 0) {
+        let oflag = if self.read_only { O_RDONLY } else { O_RDWR };
+        let fd = match libc::openat(self.root(), name, oflag | O_CLOEXEC, 0) {
             Ok(fd) => fd,
             Err(Errno::ENOENT) => return Ok(None),
             Err(e) => return Err(e.into()),
         };
-        libc::flock(&fd, LOCK_EX | LOCK_NB)?;
+        if self.read_only {
+            return Ok(Some(Writer::open_read_only(fd)?));
+        }
+        lock(&fd)?;
         Ok(Some(Writer::open(fd)?))
     }
 }
 
+/// Locks `fd` for writing, failing if another writer holds the lock.
+fn lock(fd: &OwnedFd) -> Result<(), StorageError> {
+    match libc::flock(fd, LOCK_EX | LOCK_NB) {
+        Ok(()) => Ok(()),
+        Err(Errno::EWOULDBLOCK) => Err(StorageError::Locked),
+        Err(e) => Err(e.into()),
+    }
+}
+
 /// A file-based writer for linear storage.
 #[derive(Debug)]
 pub struct Writer {
     file: File,
     root: Root,
+    /// Whether the file was opened by [`FileManager::read_only`].
+    read_only: bool,
 }
 
 /// An estimated page size for spacing the control data.
@@ -109,33 +147,44 @@ impl Writer {
         Ok(Self {
             file,
             root: Root::new(),
+            read_only: false,
         })
     }
 
     fn open(fd: OwnedFd) -> Result<Self, StorageError> {
         let file = File { fd: Arc::new(fd) };
-
-        // Pick the latest valid root.
-        let (root, overwrite) = match (
-            file.load(ROOT_A).and_then(Root::validate),
-            file.load(ROOT_B).and_then(Root::validate),
-        ) {
-            (Ok(root_a), Ok(root_b)) => match root_a.generation.cmp(&root_b.generation) {
-                Ordering::Equal => (root_a, None),
-                Ordering::Greater => (root_a, Some(ROOT_B)),
-                Ordering::Less => (root_b, Some(ROOT_A)),
-            },
-            (Ok(root_a), Err(_)) => (root_a, Some(ROOT_B)),
-            (Err(_), Ok(root_b)) => (root_b, Some(ROOT_A)),
-            (Err(e), Err(_)) => return Err(e),
-        };
+        let (root, overwrite) = file.latest_root()?;
 
         // Write other side if needed (corrupted or outdated)
         if let Some(offset) = overwrite {
             file.dump(offset, &root)?;
         }
 
-        Ok(Self { file, root })
+        Ok(Self {
+            file,
+            root,
+            read_only: false,
+        })
+    }
+
+    fn open_read_only(fd: OwnedFd) -> Result<Self, StorageError> {
+        let file = File { fd: Arc::new(fd) };
+        let (root, _) = file.latest_root()?;
+        Ok(Self {
+            file,
+            root,
+            read_only: true,
+        })
+    }
+
+    /// Returns the end of the data written so far.
+    pub(super) fn data_end(&self) -> i64 {
+        self.root.free_offset
+    }
+
+    /// Returns the underlying file descriptor.
+    pub(super) fn fd(&self) -> &OwnedFd {
+        &self.file.fd
     }
 
     fn write_root(&mut self) -> Result<(), StorageError> {
@@ -166,6 +215,10 @@ impl Write for Writer {
     }
 
     fn head(&self) -> Result<Location, StorageError> {
+        // Another process may have committed since the file was opened.
+        if self.read_only {
+            return Ok(self.file.latest_root()?.0.head);
+        }
         if self.root.generation == 0 {
             bug!("not initialized")
         }
@@ -177,6 +230,9 @@ impl Write for Writer {
         F: FnOnce(usize) -> T,
         T: Serialize,
     {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
         let offset = self.root.free_offset;
 
         let item = builder(
@@ -193,6 +249,9 @@ impl Write for Writer {
     }
 
     fn commit(&mut self, head: Location) -> Result<(), StorageError> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
         self.root.head = head;
         self.write_root()?;
         Ok(())
@@ -263,6 +322,24 @@ struct File {
 }
 
 impl File {
+    /// Loads the latest valid [`Root`], along with the offset of the other root if
+    /// it is corrupted or outdated.
+    fn latest_root(&self) -> Result<(Root, Option<i64>), StorageError> {
+        match (
+            self.load(ROOT_A).and_then(Root::validate),
+            self.load(ROOT_B).and_then(Root::validate),
+        ) {
+            (Ok(root_a), Ok(root_b)) => match root_a.generation.cmp(&root_b.generation) {
+                Ordering::Equal => Ok((root_a, None)),
+                Ordering::Greater => Ok((root_a, Some(ROOT_B))),
+                Ordering::Less => Ok((root_b, Some(ROOT_A))),
+            },
+            (Ok(root_a), Err(_)) => Ok((root_a, Some(ROOT_B))),
+            (Err(_), Ok(root_b)) => Ok((root_b, Some(ROOT_A))),
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+
     fn fallocate(&self, offset: i64, len: i64) -> Result<(), StorageError> {
         libc::fallocate(&self.fd, 0, offset, len)?;
         Ok(())
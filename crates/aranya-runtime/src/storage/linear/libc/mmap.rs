@@ -0,0 +1,209 @@
+use alloc::sync::Arc;
+
+use aranya_libc::{self as libc, Mmap, Path};
+use buggy::BugExt;
+use serde::{de::DeserializeOwned, Serialize};
+use spin::mutex::Mutex;
+use tracing::{error, warn};
+
+use super::{
+    error::Error,
+    imp::{FileManager, Reader, Writer},
+};
+use crate::{
+    linear::io::{IoManager, Read, Write},
+    GraphId, Location, StorageError,
+};
+
+/// A file-backed implementation of [`IoManager`] which reads
+/// committed items through a memory mapping.
+///
+/// Files have the same format as [`FileManager`]'s. Since the
+/// mapped pages belong to the page cache, the OS can reclaim
+/// them under memory pressure, which suits devices with little
+/// RAM.
+///
+/// Items appended since the last commit, and all items on
+/// platforms without `mmap`, are read with `pread` instead.
+#[derive(Debug)]
+pub struct MmapManager {
+    files: FileManager,
+}
+
+impl MmapManager {
+    /// Creates a `MmapManager` at `dir`. See [`FileManager::new`].
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        Ok(Self {
+            files: FileManager::new(dir)?,
+        })
+    }
+
+    /// Creates a `MmapManager` at `dir` which only reads graphs. See
+    /// [`FileManager::read_only`].
+    ///
+    /// Graphs are mapped when they are opened. Items committed by other
+    /// processes since then are read with `pread`.
+    pub fn read_only<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        Ok(Self {
+            files: FileManager::read_only(dir)?,
+        })
+    }
+}
+
+impl IoManager for MmapManager {
+    type Writer = MmapWriter;
+
+    fn create(&mut self, id: GraphId) -> Result<Self::Writer, StorageError> {
+        MmapWriter::new(self.files.create(id)?)
+    }
+
+    fn open(&mut self, id: GraphId) -> Result<Option<Self::Writer>, StorageError> {
+        self.files.open(id)?.map(MmapWriter::new).transpose()
+    }
+}
+
+/// The current mapping, shared between a writer and its readers.
+type SharedMap = Arc<Mutex<Option<Arc<Mmap>>>>;
+
+/// A file-based writer for linear storage which maps the file
+/// after each commit.
+#[derive(Debug)]
+pub struct MmapWriter {
+    inner: Writer,
+    map: SharedMap,
+}
+
+impl MmapWriter {
+    fn new(inner: Writer) -> Result<Self, StorageError> {
+        let mut writer = Self {
+            inner,
+            map: Arc::default(),
+        };
+        writer.remap()?;
+        Ok(writer)
+    }
+
+    /// Maps all of the data written so far.
+    ///
+    /// Readers holding the previous mapping keep it alive until
+    /// they are done with it.
+    fn remap(&mut self) -> Result<(), StorageError> {
+        let len = usize::try_from(self.inner.data_end()).assume("data end fits in `usize`")?;
+        // SAFETY: The file is never truncated, and the data
+        // section is append-only, so the items borrowed by
+        // `MmapReader::fetch` are never modified.
+        let map = match unsafe { libc::mmap(self.inner.fd(), len) } {
+            Ok(map) => Some(Arc::new(map)),
+            Err(err) => {
+                warn!(?err, "unable to map file, falling back to `pread`");
+                None
+            }
+        };
+        *self.map.lock() = map;
+        Ok(())
+    }
+}
+
+impl Write for MmapWriter {
+    type ReadOnly = MmapReader;
+
+    fn readonly(&self) -> Self::ReadOnly {
+        MmapReader {
+            inner: self.inner.readonly(),
+            map: Arc::clone(&self.map),
+        }
+    }
+
+    fn head(&self) -> Result<Location, StorageError> {
+        self.inner.head()
+    }
+
+    fn append<F, T>(&mut self, builder: F) -> Result<T, StorageError>
+    where
+        F: FnOnce(usize) -> T,
+        T: Serialize,
+    {
+        self.inner.append(builder)
+    }
+
+    fn commit(&mut self, head: Location) -> Result<(), StorageError> {
+        self.inner.commit(head)?;
+        self.remap()
+    }
+
+    fn begin_batch(&mut self) -> Result<(), StorageError> {
+        self.inner.begin_batch()
+    }
+
+    fn end_batch(&mut self) -> Result<(), StorageError> {
+        self.inner.end_batch()
+    }
+}
+
+/// A file-based reader for linear storage which reads through
+/// the writer's latest mapping.
+#[derive(Clone, Debug)]
+pub struct MmapReader {
+    inner: Reader,
+    map: SharedMap,
+}
+
+impl Read for MmapReader {
+    fn fetch<T>(&self, offset: usize) -> Result<T, StorageError>
+    where
+        T: DeserializeOwned,
+    {
+        let map = self.map.lock().clone();
+        match map.as_deref().and_then(|map| item(map, offset)) {
+            Some(bytes) => postcard::from_bytes(bytes).map_err(|err| {
+                error!(?err, "load");
+                StorageError::IoError
+            }),
+            None => self.inner.fetch(offset),
+        }
+    }
+}
+
+/// Returns the item at `offset`, which is prefixed by its
+/// big-endian `u32` length, or `None` if it is not fully
+/// mapped.
+fn item(map: &Mmap, offset: usize) -> Option<&[u8]> {
+    let start = offset.checked_add(4)?;
+    let len = u32::from_be_bytes(map.get(offset..start)?.try_into().ok()?);
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    map.get(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tracing::info;
+
+    use super::*;
+    use crate::{
+        storage::linear::LinearStorageProvider,
+        testing::dsl::{test_suite, StorageBackend},
+    };
+
+    struct MmapBackend {
+        tempdir: tempfile::TempDir,
+    }
+
+    impl StorageBackend for MmapBackend {
+        type StorageProvider = LinearStorageProvider<MmapManager>;
+
+        fn provider(&mut self, client_id: u64) -> Self::StorageProvider {
+            let dir = self.tempdir.path().join(client_id.to_string());
+            fs::create_dir(&dir).unwrap();
+            let manager = MmapManager::new(&dir).unwrap();
+            LinearStorageProvider::new(manager)
+        }
+    }
+
+    test_suite!(|| {
+        let tempdir = tempfile::tempdir().unwrap();
+        info!(path = ?tempdir.path(), "using tempdir");
+        MmapBackend { tempdir }
+    });
+}
@@ -20,9 +20,11 @@
 
 mod error;
 mod imp;
+mod mmap;
 mod path;
 mod tests;
 
 pub use error::Error;
 pub use imp::*;
+pub use mmap::*;
 pub use path::*;
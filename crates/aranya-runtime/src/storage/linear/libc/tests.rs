@@ -6,8 +6,10 @@ use tracing::info;
 
 use super::*;
 use crate::{
+    protocol::{TestActions, TestEngine},
     storage::linear::LinearStorageProvider,
     testing::dsl::{test_suite, StorageBackend},
+    ClientError, ClientState, NullSink, Storage, StorageError, StorageProvider,
 };
 
 struct LinearBackend {
@@ -30,3 +32,50 @@ test_suite!(|| {
     info!(path = ?tempdir.path(), "using tempdir");
     LinearBackend { tempdir }
 });
+
+#[test]
+fn test_shared_access() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let manager = FileManager::new(tempdir.path()).unwrap();
+    let mut writer = ClientState::new(TestEngine::new(), LinearStorageProvider::new(manager));
+    let storage_id = writer
+        .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+        .unwrap();
+
+    // Only one writer can open the graph at a time.
+    let mut other = LinearStorageProvider::new(FileManager::new(tempdir.path()).unwrap());
+    assert!(matches!(
+        other.get_storage(storage_id),
+        Err(StorageError::Locked)
+    ));
+
+    // Readers can open it alongside the writer and see its commits.
+    let mut reader = LinearStorageProvider::new(FileManager::read_only(tempdir.path()).unwrap());
+    let head = reader.get_storage(storage_id).unwrap().get_head().unwrap();
+    writer
+        .action(storage_id, &mut NullSink, TestActions::SetValue(1, 1))
+        .unwrap();
+    let expected = writer
+        .provider()
+        .get_storage(storage_id)
+        .unwrap()
+        .get_head()
+        .unwrap();
+    let storage = reader.get_storage(storage_id).unwrap();
+    assert_ne!(storage.get_head().unwrap(), head);
+    assert_eq!(storage.get_head().unwrap(), expected);
+    storage.get_segment(expected).unwrap();
+
+    let mut reader = ClientState::new(TestEngine::new(), reader);
+    let err = reader
+        .action(storage_id, &mut NullSink, TestActions::SetValue(2, 2))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::StorageError(StorageError::ReadOnly)
+    ));
+
+    // Once the writer is gone, another can take its place.
+    drop(writer);
+    other.get_storage(storage_id).unwrap();
+}
@@ -22,18 +22,28 @@
 //! committed, it may be overwritten and will become unreachable by intended
 //! means.
 
+pub mod encrypted;
 pub mod libc;
 
 #[cfg(feature = "testing")]
 pub mod testing;
 
-use alloc::{boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
 
 use aranya_crypto::{csprng::rand::Rng as _, Csprng, Rng};
 use buggy::{bug, Bug, BugExt};
 use serde::{Deserialize, Serialize};
+use tracing::trace;
 use vec1::Vec1;
 
+use super::{layered_page, overlay_page};
 use crate::{
     Address, Checkpoint, Command, CommandId, Fact, FactIndex, FactPerspective, GraphId, Keys,
     Location, Perspective, PolicyId, Prior, Priority, Query, QueryMut, Revertable, Segment,
@@ -63,6 +73,11 @@ pub struct LinearStorageProvider<FM: IoManager> {
 
 pub struct LinearStorage<W> {
     writer: W,
+    /// The bytes of data committed, once measured. See [`Storage::size`].
+    size: Option<usize>,
+    /// The bytes of data in each segment written but not yet committed, by offset.
+    uncommitted: BTreeMap<usize, usize>,
+    quota: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -94,6 +109,26 @@ struct CommandData {
     updates: Vec<Update>,
 }
 
+impl CommandData {
+    /// Returns the number of bytes of command and fact data held.
+    fn size(&self) -> Result<usize, Bug> {
+        let mut size = self.data.len();
+        size = size
+            .checked_add(self.policy.as_deref().map_or(0, <[u8]>::len))
+            .assume("must not overflow")?;
+        for (name, keys, value) in &self.updates {
+            size = size.checked_add(name.len()).assume("must not overflow")?;
+            for key in keys.iter() {
+                size = size.checked_add(key.len()).assume("must not overflow")?;
+            }
+            size = size
+                .checked_add(value.as_deref().map_or(0, <[u8]>::len))
+                .assume("must not overflow")?;
+        }
+        Ok(size)
+    }
+}
+
 pub struct LinearCommand<'a> {
     id: &'a CommandId,
     parent: Prior<Address>,
@@ -163,25 +198,29 @@ impl<R> LinearPerspective<R> {
     }
 }
 
-#[derive(Debug)]
+/// The facts at a point in the graph.
+///
+/// Cloning a fact perspective is cheap: its changes are shared with the clone
+/// until either is changed, and its prior facts are always shared.
+#[derive(Clone, Debug)]
 pub struct LinearFactPerspective<R> {
-    map: BTreeMap<String, BTreeMap<Keys, Option<Bytes>>>,
+    map: Arc<BTreeMap<String, BTreeMap<Keys, Option<Bytes>>>>,
     prior: FactPerspectivePrior<R>,
 }
 
 impl<R> LinearFactPerspective<R> {
     fn new(prior: FactPerspectivePrior<R>) -> Self {
         Self {
-            map: BTreeMap::new(),
+            map: Arc::default(),
             prior,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum FactPerspectivePrior<R> {
     None,
-    FactPerspective(Box<LinearFactPerspective<R>>),
+    FactPerspective(Arc<LinearFactPerspective<R>>),
     FactIndex { offset: usize, reader: R },
 }
 
@@ -299,9 +338,10 @@ impl<W: Write> LinearStorage<W> {
         assert!(matches!(init.parents, Prior::None));
         assert!(matches!(init.facts.prior, FactPerspectivePrior::None));
 
-        let mut map = init.facts.map;
+        let mut map = Arc::unwrap_or_clone(init.facts.map);
         map.retain(|_, kv| !kv.is_empty());
 
+        writer.begin_batch()?;
         let facts = writer
             .append(|offset| FactIndexRepr {
                 offset,
@@ -325,6 +365,7 @@ impl<W: Write> LinearStorage<W> {
             max_cut: 0,
             skip_list: vec![],
         })?;
+        writer.end_batch()?;
 
         let head = Location::new(
             segment.offset,
@@ -337,13 +378,42 @@ impl<W: Write> LinearStorage<W> {
 
         writer.commit(head)?;
 
-        let storage = Self { writer };
+        let storage = Self {
+            writer,
+            size: None,
+            uncommitted: BTreeMap::new(),
+            quota: None,
+        };
 
         Ok(storage)
     }
 
     fn open(writer: W) -> Result<Self, StorageError> {
-        Ok(Self { writer })
+        Ok(Self {
+            writer,
+            size: None,
+            uncommitted: BTreeMap::new(),
+            quota: None,
+        })
+    }
+
+    /// Returns the size of the segments reachable from the head, which are the ones
+    /// committed.
+    fn measure(&self) -> Result<usize, StorageError> {
+        let mut seen = BTreeSet::new();
+        let mut queue = vec![self.get_head()?];
+        let mut size = 0usize;
+        while let Some(location) = queue.pop() {
+            if !seen.insert(location.segment) {
+                continue;
+            }
+            let segment = self.get_segment(location)?;
+            for data in &segment.repr.commands {
+                size = size.checked_add(data.size()?).assume("must not overflow")?;
+            }
+            queue.extend(segment.prior());
+        }
+        Ok(size)
     }
 
     fn compact(&mut self, mut repr: FactIndexRepr) -> Result<FactIndexRepr, StorageError> {
@@ -368,7 +438,7 @@ impl<W: Write> LinearStorage<W> {
 
         Ok(self
             .write_facts(LinearFactPerspective {
-                map,
+                map: Arc::new(map),
                 prior: FactPerspectivePrior::None,
             })?
             .repr)
@@ -416,12 +486,12 @@ impl<F: Write> Storage for LinearStorage<F> {
                 facts.apply_updates(&data.updates);
             }
             if facts.prior.is_none() {
-                facts.map.retain(|_, kv| !kv.is_empty());
+                Arc::make_mut(&mut facts.map).retain(|_, kv| !kv.is_empty());
             }
             if facts.map.is_empty() {
                 facts.prior
             } else {
-                FactPerspectivePrior::FactPerspective(Box::new(facts))
+                FactPerspectivePrior::FactPerspective(Arc::new(facts))
             }
         };
         let prior = Prior::Single(parent);
@@ -537,17 +607,68 @@ impl<F: Write> Storage for LinearStorage<F> {
         self.writer.head()
     }
 
+    #[cfg_attr(feature = "instrument", tracing::instrument(level = "debug", skip_all))]
     fn commit(&mut self, segment: Self::Segment) -> Result<(), StorageError> {
         if !self.is_ancestor(self.get_head()?, &segment)? {
             return Err(StorageError::HeadNotAncestor);
         }
 
-        self.writer.commit(segment.head_location())
+        // Segments count toward the size once they are committed, which they are
+        // when they are reachable from the new head.
+        let mut committed = BTreeSet::new();
+        let mut bytes = 0usize;
+        let mut queue = vec![segment.head_location()];
+        while let Some(location) = queue.pop() {
+            let Some(&size) = self.uncommitted.get(&location.segment) else {
+                continue;
+            };
+            if !committed.insert(location.segment) {
+                continue;
+            }
+            bytes = bytes.checked_add(size).assume("must not overflow")?;
+            queue.extend(self.get_segment(location)?.prior());
+        }
+        let size = match self.size {
+            Some(size) => {
+                let size = size.checked_add(bytes).assume("must not overflow")?;
+                if let Some(quota) = self.quota {
+                    if size > quota {
+                        return Err(StorageError::QuotaExceeded(quota));
+                    }
+                }
+                Some(size)
+            }
+            None => None,
+        };
+
+        self.writer.commit(segment.head_location())?;
+        self.uncommitted
+            .retain(|offset, _| !committed.contains(offset));
+        self.size = size;
+        Ok(())
     }
 
+    #[cfg_attr(feature = "instrument", tracing::instrument(level = "debug", skip_all))]
     fn write(&mut self, perspective: Self::Perspective) -> Result<Self::Segment, StorageError> {
         // TODO(jdygert): Validate prior?
 
+        let mut bytes = 0usize;
+        for data in &perspective.commands {
+            bytes = bytes
+                .checked_add(data.size()?)
+                .assume("must not overflow")?;
+        }
+        // The size is only tracked once it has been measured, which it is when a
+        // quota is set. The segment is counted when it is committed.
+        if let (Some(size), Some(quota)) = (self.size, self.quota) {
+            if size.checked_add(bytes).assume("must not overflow")? > quota {
+                return Err(StorageError::QuotaExceeded(quota));
+            }
+        }
+
+        // The fact indices and segment are written in one batch. If writing fails,
+        // the batch is left to the next write, as its items are unreachable anyway.
+        self.writer.begin_batch()?;
         let facts = self.write_facts(perspective.facts)?.repr.offset;
 
         let commands: Vec1<CommandData> = perspective
@@ -606,11 +727,17 @@ impl<F: Write> Storage for LinearStorage<F> {
             max_cut: perspective.max_cut,
             skip_list,
         })?;
+        self.writer.end_batch()?;
 
-        Ok(LinearSegment {
+        self.uncommitted.insert(repr.offset, bytes);
+
+        let segment = LinearSegment {
             repr,
             reader: self.writer.readonly(),
-        })
+        };
+        trace!(location = %segment.head_location(), "wrote segment");
+
+        Ok(segment)
     }
 
     fn write_facts(
@@ -620,7 +747,7 @@ impl<F: Write> Storage for LinearStorage<F> {
         let mut prior = match facts.prior {
             FactPerspectivePrior::None => None,
             FactPerspectivePrior::FactPerspective(prior) => {
-                let prior = self.write_facts(*prior)?;
+                let prior = self.write_facts(Arc::unwrap_or_clone(prior))?;
                 if facts.map.is_empty() {
                     return Ok(prior);
                 }
@@ -654,7 +781,7 @@ impl<F: Write> Storage for LinearStorage<F> {
             offset,
             prior: prior.map(|p| p.offset),
             depth,
-            facts: facts.map,
+            facts: Arc::unwrap_or_clone(facts.map),
         })?;
 
         Ok(LinearFactIndex {
@@ -662,6 +789,21 @@ impl<F: Write> Storage for LinearStorage<F> {
             reader: self.writer.readonly(),
         })
     }
+
+    fn size(&self) -> Result<usize, StorageError> {
+        match self.size {
+            Some(size) => Ok(size),
+            None => self.measure(),
+        }
+    }
+
+    fn set_quota(&mut self, quota: Option<usize>) -> Result<(), StorageError> {
+        if quota.is_some() && self.size.is_none() {
+            self.size = Some(self.measure()?);
+        }
+        self.quota = quota;
+        Ok(())
+    }
 }
 
 impl<R: Read> Segment for LinearSegment<R> {
@@ -875,6 +1017,27 @@ impl<R: Read> Query for LinearFactIndex<R> {
             self.query_prefix_inner(name, prefix)?.into_iter(),
         ))
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        let mut fetched = Vec::new();
+        let mut prior = self.repr.prior;
+        while let Some(offset) = prior {
+            let repr: FactIndexRepr = self.reader.fetch(offset)?;
+            prior = repr.prior;
+            fetched.push(repr);
+        }
+        let layers: Vec<_> = core::iter::once(&self.repr)
+            .chain(&fetched)
+            .filter_map(|repr| repr.facts.get(name))
+            .collect();
+        Ok(layered_page(&layers, prefix, after, limit))
+    }
 }
 
 impl<R: Read> LinearFactIndex<R> {
@@ -904,23 +1067,22 @@ impl<R: Read> LinearFactIndex<R> {
 
 impl<R> LinearFactPerspective<R> {
     fn clear(&mut self) {
-        self.map.clear();
+        self.map = Arc::default();
     }
 
     fn apply_updates(&mut self, updates: &[Update]) {
+        let map = Arc::make_mut(&mut self.map);
         for (name, key, value) in updates {
             if self.prior.is_none() {
                 if let Some(value) = value {
-                    self.map
-                        .entry(name.clone())
+                    map.entry(name.clone())
                         .or_default()
                         .insert(key.clone(), Some(value.clone()));
-                } else if let Some(e) = self.map.get_mut(name) {
+                } else if let Some(e) = map.get_mut(name) {
                     e.remove(key);
                 }
             } else {
-                self.map
-                    .entry(name.clone())
+                map.entry(name.clone())
                     .or_default()
                     .insert(key.clone(), value.clone());
             }
@@ -959,6 +1121,30 @@ impl<R: Read> Query for LinearFactPerspective<R> {
             self.query_prefix_inner(name, prefix)?.into_iter(),
         ))
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        let map = self.map.get(name);
+        match &self.prior {
+            FactPerspectivePrior::None => Ok(layered_page(map.as_slice(), prefix, after, limit)),
+            FactPerspectivePrior::FactPerspective(prior) => {
+                overlay_page(map, &**prior, name, prefix, after, limit)
+            }
+            FactPerspectivePrior::FactIndex { offset, reader } => {
+                let repr: FactIndexRepr = reader.fetch(*offset)?;
+                let prior = LinearFactIndex {
+                    repr,
+                    reader: reader.clone(),
+                };
+                overlay_page(map, &prior, name, prefix, after, limit)
+            }
+        }
+    }
 }
 
 impl<R: Read> LinearFactPerspective<R> {
@@ -993,17 +1179,21 @@ impl<R: Read> LinearFactPerspective<R> {
 
 impl<R: Read> QueryMut for LinearFactPerspective<R> {
     fn insert(&mut self, name: String, keys: Keys, value: Bytes) {
-        self.map.entry(name).or_default().insert(keys, Some(value));
+        Arc::make_mut(&mut self.map)
+            .entry(name)
+            .or_default()
+            .insert(keys, Some(value));
     }
 
     fn delete(&mut self, name: String, keys: Keys) {
+        let map = Arc::make_mut(&mut self.map);
         if self.prior.is_none() {
             // No need for tombstones with no prior.
-            if let Some(kv) = self.map.get_mut(&name) {
+            if let Some(kv) = map.get_mut(&name) {
                 kv.remove(&keys);
             }
         } else {
-            self.map.entry(name).or_default().insert(keys, None);
+            map.entry(name).or_default().insert(keys, None);
         }
     }
 }
@@ -1023,6 +1213,16 @@ impl<R: Read> Query for LinearPerspective<R> {
     ) -> Result<QueryIterator, StorageError> {
         self.facts.query_prefix(name, prefix)
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        self.facts.query_page(name, prefix, after, limit)
+    }
 }
 
 impl<R: Read> QueryMut for LinearPerspective<R> {
@@ -1199,6 +1399,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_query_range() {
+        use core::ops::Bound;
+
+        let mut provider = LinearStorageProvider::new(Manager);
+        let mut fp = provider.new_perspective(PolicyId::new(0));
+
+        for user in ["a", "b"] {
+            for n in 0..10u8 {
+                let keys: Keys = [user.as_bytes(), &[n][..]].into_iter().collect();
+                fp.insert("x".into(), keys, Box::new([n]));
+            }
+        }
+
+        let prefix: Keys = ["b".as_bytes()].into_iter().collect();
+        let found = |range: (Bound<&[u8]>, Bound<&[u8]>), limit| {
+            fp.query_range("x", &prefix, range, limit)
+                .unwrap()
+                .map(|fact| fact.unwrap().value[0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            found((Bound::Included(&[3][..]), Bound::Excluded(&[7][..])), 100),
+            [3, 4, 5, 6]
+        );
+        assert_eq!(
+            found((Bound::Excluded(&[3][..]), Bound::Included(&[7][..])), 100),
+            [4, 5, 6, 7]
+        );
+        assert_eq!(found((Bound::Unbounded, Bound::Unbounded), 2), [0, 1]);
+        assert!(found((Bound::Included(&[10][..]), Bound::Unbounded), 100).is_empty());
+    }
+
     struct LinearBackend;
     impl StorageBackend for LinearBackend {
         type StorageProvider = LinearStorageProvider<Manager>;
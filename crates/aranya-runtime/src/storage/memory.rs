@@ -1,12 +1,20 @@
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
 use core::ops::{Bound, Deref};
 
 use buggy::{bug, Bug, BugExt};
+use tracing::trace;
 use vec1::Vec1;
 
+use super::{layered_page, overlay_page};
 use crate::{
-    Address, Checkpoint, Command, CommandId, Fact, FactIndex, FactPerspective, GraphId, Keys,
-    Location, Perspective, PolicyId, Prior, Priority, Query, QueryMut, Revertable, Segment,
+    Address, Checkpoint, Command, CommandId, Fact, FactIndex, FactPerspective, GcStats, GraphId,
+    Keys, Location, Perspective, PolicyId, Prior, Priority, Query, QueryMut, Revertable, Segment,
     Storage, StorageError, StorageProvider,
 };
 
@@ -115,9 +123,16 @@ type FactMap = BTreeMap<Keys, Option<Box<[u8]>>>;
 type NamedFactMap = BTreeMap<String, FactMap>;
 
 pub struct MemStorage {
-    segments: Vec<MemSegment>,
+    /// Segments removed by [`Storage::collect_garbage`] are `None`, so the locations
+    /// of the others don't change.
+    segments: Vec<Option<MemSegment>>,
     commands: BTreeMap<CommandId, Location>,
     head: Option<Location>,
+    /// The bytes of data held by committed segments.
+    size: usize,
+    /// The bytes of data held by each segment written but not yet committed, by index.
+    uncommitted: BTreeMap<usize, usize>,
+    quota: Option<usize>,
 }
 
 impl MemStorage {
@@ -126,6 +141,9 @@ impl MemStorage {
             segments: Vec::new(),
             commands: BTreeMap::new(),
             head: None,
+            size: 0,
+            uncommitted: BTreeMap::new(),
+            quota: None,
         }
     }
 
@@ -152,7 +170,7 @@ impl MemStorage {
         };
 
         let cell = MemSegment::from(segment);
-        self.segments.push(cell.clone());
+        self.segments.push(Some(cell.clone()));
 
         Ok(cell)
     }
@@ -284,6 +302,7 @@ impl Storage for MemStorage {
     fn get_segment(&self, location: Location) -> Result<MemSegment, StorageError> {
         self.segments
             .get(location.segment)
+            .and_then(Option::as_ref)
             .ok_or(StorageError::SegmentOutOfBounds(location))
             .cloned()
     }
@@ -292,7 +311,21 @@ impl Storage for MemStorage {
         Ok(self.head.assume("storage has head after init")?)
     }
 
+    #[cfg_attr(feature = "instrument", tracing::instrument(level = "debug", skip_all))]
     fn write(&mut self, update: Self::Perspective) -> Result<Self::Segment, StorageError> {
+        let mut bytes = 0usize;
+        for data in &update.commands {
+            bytes = bytes
+                .checked_add(data.size()?)
+                .assume("must not overflow")?;
+        }
+        // The segment is counted when it is committed.
+        if let Some(quota) = self.quota {
+            if self.size.checked_add(bytes).assume("must not overflow")? > quota {
+                return Err(StorageError::QuotaExceeded(quota));
+            }
+        }
+
         let facts = self.write_facts(update.facts)?;
 
         let commands: Vec1<CommandData> = update
@@ -310,6 +343,8 @@ impl Storage for MemStorage {
 
         let segment =
             self.new_segment(update.prior, update.policy, commands, facts, update.max_cut)?;
+        self.uncommitted.insert(segment_index, bytes);
+        trace!(location = %segment.head_location(), "wrote segment");
 
         Ok(segment)
     }
@@ -320,7 +355,9 @@ impl Storage for MemStorage {
     ) -> Result<Self::FactIndex, StorageError> {
         let prior = match facts.prior {
             FactPerspectivePrior::None => None,
-            FactPerspectivePrior::FactPerspective(prior) => Some(self.write_facts(*prior)?),
+            FactPerspectivePrior::FactPerspective(prior) => {
+                Some(self.write_facts(Arc::unwrap_or_clone(prior))?)
+            }
             FactPerspectivePrior::FactIndex(prior) => Some(prior),
         };
         if facts.map.is_empty() {
@@ -329,11 +366,12 @@ impl Storage for MemStorage {
             }
         }
         Ok(MemFactIndex(Arc::new(MemFactsInner {
-            map: facts.map,
+            map: Arc::unwrap_or_clone(facts.map),
             prior,
         })))
     }
 
+    #[cfg_attr(feature = "instrument", tracing::instrument(level = "debug", skip_all))]
     fn commit(&mut self, segment: Self::Segment) -> Result<(), StorageError> {
         // TODO(jdygert): ensure segment belongs to self?
 
@@ -343,9 +381,87 @@ impl Storage for MemStorage {
             }
         }
 
+        // Segments count toward the size once they are committed, which they are
+        // when they are reachable from the new head.
+        let mut committed = BTreeSet::new();
+        let mut size = self.size;
+        let mut queue = alloc::vec![segment.head_location()];
+        while let Some(location) = queue.pop() {
+            let Some(&bytes) = self.uncommitted.get(&location.segment) else {
+                continue;
+            };
+            if !committed.insert(location.segment) {
+                continue;
+            }
+            size = size.checked_add(bytes).assume("must not overflow")?;
+            queue.extend(self.get_segment(location)?.prior());
+        }
+        if let Some(quota) = self.quota {
+            if size > quota {
+                return Err(StorageError::QuotaExceeded(quota));
+            }
+        }
+
+        self.uncommitted
+            .retain(|index, _| !committed.contains(index));
+        self.size = size;
         self.head = Some(segment.head_location());
         Ok(())
     }
+
+    fn collect_garbage(&mut self) -> Result<GcStats, StorageError> {
+        let head = self.get_head()?;
+
+        let mut reachable = alloc::vec![false; self.segments.len()];
+        let mut queue = Vec::new();
+        queue.push(head);
+        while let Some(location) = queue.pop() {
+            let seen = reachable
+                .get_mut(location.segment)
+                .ok_or(StorageError::SegmentOutOfBounds(location))?;
+            if *seen {
+                continue;
+            }
+            *seen = true;
+            queue.extend(self.get_segment(location)?.prior());
+        }
+
+        let mut stats = GcStats::default();
+        for (slot, reachable) in self.segments.iter_mut().zip(reachable) {
+            if reachable {
+                continue;
+            }
+            let Some(segment) = slot.take() else {
+                continue;
+            };
+            for data in segment.commands.iter() {
+                stats.bytes = stats
+                    .bytes
+                    .checked_add(data.size()?)
+                    .assume("must not overflow")?;
+            }
+            stats.commands = stats
+                .commands
+                .checked_add(segment.commands.len())
+                .assume("must not overflow")?;
+            stats.segments = stats.segments.checked_add(1).assume("must not overflow")?;
+        }
+        self.commands
+            .retain(|_, location| matches!(self.segments.get(location.segment), Some(Some(_))));
+        self.uncommitted
+            .retain(|index, _| matches!(self.segments.get(*index), Some(Some(_))));
+
+        Ok(stats)
+    }
+
+    fn size(&self) -> Result<usize, StorageError> {
+        Ok(self.size)
+    }
+
+    fn set_quota(&mut self, quota: Option<usize>) -> Result<(), StorageError> {
+        self.quota = quota;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -406,6 +522,22 @@ impl Query for MemFactIndex {
                 .filter_map(|(key, value)| Some(Ok(Fact { key, value: value? }))),
         ))
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        let mut layers = Vec::new();
+        let mut prior = Some(self.deref());
+        while let Some(facts) = prior {
+            layers.extend(facts.map.get(name));
+            prior = facts.prior.as_deref();
+        }
+        Ok(layered_page(&layers, prefix, after, limit))
+    }
 }
 
 impl MemFactIndex {
@@ -436,6 +568,27 @@ struct CommandData {
     updates: Vec<Update>,
 }
 
+impl CommandData {
+    /// Returns the number of bytes of command and fact data held.
+    fn size(&self) -> Result<usize, Bug> {
+        let command = &self.command;
+        let mut size = command.data.len();
+        size = size
+            .checked_add(command.policy.as_deref().map_or(0, <[u8]>::len))
+            .assume("must not overflow")?;
+        for (name, keys, value) in &self.updates {
+            size = size.checked_add(name.len()).assume("must not overflow")?;
+            for key in keys.iter() {
+                size = size.checked_add(key.len()).assume("must not overflow")?;
+            }
+            size = size
+                .checked_add(value.as_deref().map_or(0, <[u8]>::len))
+                .assume("must not overflow")?;
+        }
+        Ok(size)
+    }
+}
+
 #[derive(Debug)]
 pub struct MemSegmentInner {
     index: usize,
@@ -565,10 +718,10 @@ pub struct MemPerspective {
     max_cut: usize,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum FactPerspectivePrior {
     None,
-    FactPerspective(Box<MemFactPerspective>),
+    FactPerspective(Arc<MemFactPerspective>),
     FactIndex(MemFactIndex),
 }
 
@@ -586,32 +739,36 @@ impl From<Option<MemFactIndex>> for FactPerspectivePrior {
 
 impl From<MemFactPerspective> for FactPerspectivePrior {
     fn from(value: MemFactPerspective) -> Self {
-        Self::FactPerspective(Box::new(value))
+        Self::FactPerspective(Arc::new(value))
     }
 }
 
-#[derive(Debug)]
+/// The facts at a point in the graph.
+///
+/// Cloning a fact perspective is cheap: its changes are shared with the clone
+/// until either is changed, and its prior facts are always shared.
+#[derive(Clone, Debug)]
 pub struct MemFactPerspective {
-    map: NamedFactMap,
+    map: Arc<NamedFactMap>,
     prior: FactPerspectivePrior,
 }
 
 impl MemFactPerspective {
     fn new(prior_facts: FactPerspectivePrior) -> MemFactPerspective {
         Self {
-            map: NamedFactMap::new(),
+            map: Arc::default(),
             prior: prior_facts,
         }
     }
 
     fn clear(&mut self) {
-        self.map.clear();
+        self.map = Arc::default();
     }
 
     fn apply_updates(&mut self, updates: &[Update]) {
+        let map = Arc::make_mut(&mut self.map);
         for (name, key, value) in updates {
-            self.map
-                .entry(name.clone())
+            map.entry(name.clone())
                 .or_default()
                 .insert(key.clone(), value.clone());
         }
@@ -723,6 +880,16 @@ impl Query for MemPerspective {
     ) -> Result<Self::QueryIterator, StorageError> {
         self.facts.query_prefix(name, prefix)
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        self.facts.query_page(name, prefix, after, limit)
+    }
 }
 
 impl QueryMut for MemPerspective {
@@ -782,15 +949,40 @@ impl Query for MemFactPerspective {
                 .filter_map(|(key, value)| Some(Ok(Fact { key, value: value? }))),
         ))
     }
+
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        let map = self.map.get(name);
+        match &self.prior {
+            FactPerspectivePrior::None => Ok(layered_page(map.as_slice(), prefix, after, limit)),
+            FactPerspectivePrior::FactPerspective(prior) => {
+                overlay_page(map, &**prior, name, prefix, after, limit)
+            }
+            FactPerspectivePrior::FactIndex(prior) => {
+                overlay_page(map, prior, name, prefix, after, limit)
+            }
+        }
+    }
 }
 
 impl QueryMut for MemFactPerspective {
     fn insert(&mut self, name: String, keys: Keys, value: Box<[u8]>) {
-        self.map.entry(name).or_default().insert(keys, Some(value));
+        Arc::make_mut(&mut self.map)
+            .entry(name)
+            .or_default()
+            .insert(keys, Some(value));
     }
 
     fn delete(&mut self, name: String, keys: Keys) {
-        self.map.entry(name).or_default().insert(keys, None);
+        Arc::make_mut(&mut self.map)
+            .entry(name)
+            .or_default()
+            .insert(keys, None);
     }
 }
 
@@ -835,7 +1027,7 @@ pub mod graphviz {
         let mut seen_facts = std::collections::HashMap::new();
         let mut external_facts = Vec::new();
 
-        for segment in &storage.segments {
+        for segment in storage.segments.iter().flatten() {
             let mut cluster = graph.cluster();
             match segment.prior {
                 Prior::None => {
@@ -920,7 +1112,7 @@ pub mod graphviz {
         }
 
         // Draw edges to prior facts for fact indices in segments.
-        for segment in &storage.segments {
+        for segment in storage.segments.iter().flatten() {
             if let Some(prior) = &segment.facts.prior {
                 graph
                     .edge(segment.facts.name(), prior.name())
@@ -1002,6 +1194,81 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_query_page() {
+        fn pages<Q: Query>(facts: &Q, limit: usize) -> Vec<Fact> {
+            let mut found = Vec::new();
+            let mut after: Option<Keys> = None;
+            loop {
+                let page = facts.query_page("x", &[], after.as_deref(), limit).unwrap();
+                after = page.last().map(|fact| fact.key.clone());
+                let done = page.len() < limit;
+                found.extend(page);
+                if done {
+                    return found;
+                }
+            }
+        }
+
+        let key = |n: u8| -> Keys { [&[n][..]].into_iter().collect() };
+        let mut graph = MemStorage::new();
+
+        let mut fp = MemFactPerspective::new(FactPerspectivePrior::None);
+        for n in 0..20 {
+            fp.insert("x".into(), key(n), Box::new([n]));
+        }
+        let index = graph.write_facts(fp).unwrap();
+
+        let mut fp = MemFactPerspective::new(index.into());
+        for n in (0..20).step_by(3) {
+            fp.delete("x".into(), key(n));
+        }
+        for n in 20..25 {
+            fp.insert("x".into(), key(n), Box::new([n]));
+        }
+        let index = graph.write_facts(fp).unwrap();
+
+        let mut fp = MemFactPerspective::new(index.clone().into());
+        for n in 1..10 {
+            fp.delete("x".into(), key(n));
+        }
+        fp.insert("x".into(), key(3), Box::new([3]));
+
+        for limit in 1..6 {
+            let expected: Vec<_> = index
+                .query_prefix("x", &[])
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(pages(&index, limit), expected);
+
+            let expected: Vec<_> = fp
+                .query_prefix("x", &[])
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(pages(&fp, limit), expected);
+        }
+    }
+
+    #[test]
+    fn test_fact_perspective_clone() {
+        let key = |k: &str| -> Keys { [k.as_bytes()].into_iter().collect() };
+        let mut fp = MemFactPerspective::new(FactPerspectivePrior::None);
+        fp.insert("x".into(), key("a"), Box::from(&b"1"[..]));
+
+        let mut clone = fp.clone();
+        assert!(Arc::ptr_eq(&fp.map, &clone.map));
+
+        clone.insert("x".into(), key("b"), Box::from(&b"2"[..]));
+        clone.delete("x".into(), key("a"));
+        assert!(!Arc::ptr_eq(&fp.map, &clone.map));
+        assert!(fp.query("x", &key("a")).unwrap().is_some());
+        assert!(fp.query("x", &key("b")).unwrap().is_none());
+        assert!(clone.query("x", &key("a")).unwrap().is_none());
+        assert!(clone.query("x", &key("b")).unwrap().is_some());
+    }
+
     struct MemBackend;
     impl StorageBackend for MemBackend {
         type StorageProvider = MemStorageProvider;
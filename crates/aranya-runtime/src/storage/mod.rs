@@ -5,8 +5,12 @@
 //! its [`Command`]s into [`Segment`]s. Updating the graph is possible using
 //! [`Perspective`]s, which represent a slice of state.
 
-use alloc::{boxed::Box, string::String, vec::Vec};
-use core::{fmt, ops::Deref};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    cmp::Ordering,
+    fmt,
+    ops::{Bound, Deref},
+};
 
 use buggy::{Bug, BugExt};
 use serde::{Deserialize, Serialize};
@@ -15,6 +19,8 @@ use crate::{Address, Command, CommandId, PolicyId, Prior};
 
 pub mod linear;
 pub mod memory;
+pub mod redb;
+pub mod sqlite;
 
 /// The maximum size of a serialized message
 pub const MAX_COMMAND_LENGTH: usize = 2048;
@@ -85,6 +91,10 @@ pub enum StorageError {
     EmptyPerspective,
     HeadNotAncestor,
     PerspectiveHeadMismatch,
+    QuotaExceeded(usize),
+    Locked,
+    ReadOnly,
+    Unsupported,
     Bug(Bug),
 }
 
@@ -112,6 +122,12 @@ impl fmt::Display for StorageError {
             Self::PerspectiveHeadMismatch => {
                 write!(f, "command's parents do not match the perspective head")
             }
+            Self::QuotaExceeded(quota) => {
+                write!(f, "write would exceed the graph's quota of {quota} bytes")
+            }
+            Self::Locked => write!(f, "storage is locked by another writer"),
+            Self::ReadOnly => write!(f, "storage is read-only"),
+            Self::Unsupported => write!(f, "operation is not supported by this storage"),
             Self::Bug(bug) => write!(f, "{bug}"),
         }
     }
@@ -159,13 +175,24 @@ pub trait StorageProvider {
     ///
     /// * `graph` - ID of the graph, taken from the initialization command.
     fn get_storage(&mut self, graph: GraphId) -> Result<&mut Self::Storage, StorageError>;
+
+    /// Limits the data written to a graph to `quota` bytes, or removes its limit if
+    /// `quota` is `None`. See [`Storage::set_quota`].
+    ///
+    /// Quotas are not persisted, so they must be set again each time the provider is
+    /// created.
+    fn set_quota(&mut self, graph: GraphId, quota: Option<usize>) -> Result<(), StorageError> {
+        self.get_storage(graph)?.set_quota(quota)
+    }
 }
 
 /// Represents the runtime's graph; [`Command`]s in storage have been validated
 /// by an associated policy and committed to state.
 pub trait Storage {
     type Perspective: Perspective + Revertable;
-    type FactPerspective: FactPerspective;
+    /// Cloning a fact perspective must not copy the facts it is based on, so that
+    /// speculative evaluation can branch from the same facts cheaply.
+    type FactPerspective: FactPerspective + Clone;
     type Segment: Segment<FactIndex = Self::FactIndex>;
     type FactIndex: FactIndex;
 
@@ -282,6 +309,69 @@ pub trait Storage {
         }
         Ok(false)
     }
+
+    /// Removes the segments which are not reachable from the head of the graph and
+    /// reclaims their storage. These are left behind by branches which were written
+    /// but never committed, such as by a [`Transaction`](crate::Transaction) which
+    /// failed or was dropped.
+    ///
+    /// The segments written by an open transaction are not reachable until it is
+    /// committed, so this must not be called while one is open.
+    ///
+    /// Storage which cannot reclaim segments fails with
+    /// [`StorageError::Unsupported`], which is the default.
+    fn collect_garbage(&mut self) -> Result<GcStats, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Returns the sequence of the latest commit which is durable, meaning it will
+    /// survive a crash. The sequence of a commit is the max cut of the head it sets,
+    /// which grows with each commit.
+    ///
+    /// By default, commits are durable once [`Storage::commit`] returns, so this is the
+    /// sequence of the head. Storage which defers persisting commits must override this
+    /// and [`Storage::flush`]. Storage which does not persist the graph, such as
+    /// in-memory storage, considers every commit durable.
+    fn durable_sequence(&self) -> Result<usize, StorageError> {
+        let head = self.get_head()?;
+        let segment = self.get_segment(head)?;
+        let command = segment
+            .get_command(head)
+            .ok_or(StorageError::CommandOutOfBounds(head))?;
+        Ok(command.max_cut()?)
+    }
+
+    /// Persists every commit, returning once they are all durable.
+    fn flush(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Returns the number of bytes of command and fact data committed to the graph.
+    /// Segments are counted once they are committed, so those which are written but
+    /// never committed are not. Storage which measures its size when it is opened may
+    /// miss segments written before then and committed after.
+    fn size(&self) -> Result<usize, StorageError>;
+
+    /// Limits the data committed to the graph to `quota` bytes, or removes the limit if
+    /// `quota` is `None`.
+    ///
+    /// Once a [`Storage::write`] or [`Storage::commit`] would take the graph's
+    /// [size](Storage::size) past its quota, it fails with
+    /// [`StorageError::QuotaExceeded`] and leaves the graph unchanged. Committed data
+    /// is never reclaimed, so setting a quota below the current size stops all further
+    /// commits until it is raised.
+    fn set_quota(&mut self, quota: Option<usize>) -> Result<(), StorageError>;
+}
+
+/// The storage reclaimed by [`Storage::collect_garbage`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// The number of segments removed.
+    pub segments: usize,
+    /// The number of commands in the removed segments.
+    pub commands: usize,
+    /// The number of bytes of command and fact data freed.
+    pub bytes: usize,
 }
 
 type MaxCut = usize;
@@ -426,6 +516,209 @@ pub trait Query {
         name: &str,
         prefix: &[Box<[u8]>],
     ) -> Result<Self::QueryIterator, StorageError>;
+
+    /// Look up at most `limit` named facts that begin with the prefix of keys and whose
+    /// next key is within `range`, in sorted key order.
+    ///
+    /// The next key is `k_{n+1}` for a `prefix` of `(k_1, k_2, ..., k_n)`, and is compared
+    /// to the bounds of `range` as bytes. Facts whose keys are exactly `prefix` have no next
+    /// key, so they are not returned.
+    fn query_range(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        limit: usize,
+    ) -> Result<QueryRange<Self::QueryIterator>, StorageError> {
+        let facts = self.query_prefix(name, prefix)?;
+        Ok(QueryRange {
+            facts,
+            depth: prefix.len(),
+            start: range.0.map(Box::from),
+            end: range.1.map(Box::from),
+            remaining: limit,
+        })
+    }
+
+    /// Look up at most `limit` named facts that begin with the prefix of keys and sort
+    /// after the keys `after`, in sorted key order.
+    ///
+    /// Passing the keys of the last fact of a page as `after` returns the next page of
+    /// [`Query::query_prefix`]. The default implementation reads the facts before `after`
+    /// too, so implementations should override it to start reading at `after`.
+    fn query_page(
+        &self,
+        name: &str,
+        prefix: &[Box<[u8]>],
+        after: Option<&[Box<[u8]>]>,
+        limit: usize,
+    ) -> Result<Vec<Fact>, StorageError> {
+        self.query_prefix(name, prefix)?
+            .skip_while(|fact| match (fact, after) {
+                (Ok(fact), Some(after)) => *fact.key <= *after,
+                _ => false,
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+/// Iterator for [`Query::query_range`].
+pub struct QueryRange<I> {
+    facts: I,
+    depth: usize,
+    start: Bound<Box<[u8]>>,
+    end: Bound<Box<[u8]>>,
+    remaining: usize,
+}
+
+impl<I> Iterator for QueryRange<I>
+where
+    I: Iterator<Item = Result<Fact, StorageError>>,
+{
+    type Item = Result<Fact, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            let fact = match self.facts.next()? {
+                Ok(fact) => fact,
+                Err(err) => return Some(Err(err)),
+            };
+            let Some(key) = fact.key.get(self.depth) else {
+                continue;
+            };
+            let after_start = match &self.start {
+                Bound::Included(start) => key >= start,
+                Bound::Excluded(start) => key > start,
+                Bound::Unbounded => true,
+            };
+            if !after_start {
+                continue;
+            }
+            let before_end = match &self.end {
+                Bound::Included(end) => key <= end,
+                Bound::Excluded(end) => key < end,
+                Bound::Unbounded => true,
+            };
+            if !before_end {
+                // Facts are in key order, so no later fact is in range either.
+                self.remaining = 0;
+                return None;
+            }
+            self.remaining = self.remaining.saturating_sub(1);
+            return Some(Ok(fact));
+        }
+    }
+}
+
+/// Returns the range of facts in `map` which begin with `prefix` and sort after `after`.
+fn page_range<'m>(
+    map: &'m BTreeMap<Keys, Option<Box<[u8]>>>,
+    prefix: &'m [Box<[u8]>],
+    after: Option<&'m [Box<[u8]>]>,
+) -> impl Iterator<Item = (&'m Keys, &'m Option<Box<[u8]>>)> {
+    let start = match after {
+        Some(after) if after >= prefix => Bound::Excluded(after),
+        _ => Bound::Included(prefix),
+    };
+    map.range::<[Box<[u8]>], _>((start, Bound::Unbounded))
+        .take_while(move |(k, _)| k.starts_with(prefix))
+}
+
+/// Implements [`Query::query_page`] for fact maps stacked in `layers`, newest first,
+/// where a key in a newer layer replaces the same key in older layers and `None` is a
+/// deleted fact.
+///
+/// Each layer is read from `after` onward, only until the page is full.
+pub(crate) fn layered_page(
+    layers: &[&BTreeMap<Keys, Option<Box<[u8]>>>],
+    prefix: &[Box<[u8]>],
+    after: Option<&[Box<[u8]>]>,
+    limit: usize,
+) -> Vec<Fact> {
+    let mut ranges: Vec<_> = layers
+        .iter()
+        .map(|map| page_range(map, prefix, after).peekable())
+        .collect();
+    let mut facts = Vec::new();
+    while facts.len() < limit {
+        let Some(key) = ranges
+            .iter_mut()
+            .filter_map(|range| range.peek().map(|(k, _)| *k))
+            .min()
+        else {
+            break;
+        };
+        let mut newest = None;
+        for range in &mut ranges {
+            if let Some((_, value)) = range.next_if(|(k, _)| *k == key) {
+                newest.get_or_insert(value);
+            }
+        }
+        if let Some(Some(value)) = newest {
+            facts.push(Fact {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    facts
+}
+
+/// Implements [`Query::query_page`] for the fact map `newer` over the facts of `older`,
+/// where a key in `newer` replaces the same key in `older` and `None` is a deleted fact.
+///
+/// `older` is read a page at a time, only until the page is full.
+pub(crate) fn overlay_page<Q: Query + ?Sized>(
+    newer: Option<&BTreeMap<Keys, Option<Box<[u8]>>>>,
+    older: &Q,
+    name: &str,
+    prefix: &[Box<[u8]>],
+    after: Option<&[Box<[u8]>]>,
+    limit: usize,
+) -> Result<Vec<Fact>, StorageError> {
+    let mut newer = newer
+        .into_iter()
+        .flat_map(|map| page_range(map, prefix, after))
+        .peekable();
+    let mut older_after: Option<Keys> = after.map(|after| after.iter().cloned().collect());
+    let mut older_page = Vec::new().into_iter().peekable();
+    let mut older_done = false;
+    let mut facts = Vec::new();
+    while facts.len() < limit {
+        if older_page.peek().is_none() && !older_done {
+            let page = older.query_page(name, prefix, older_after.as_deref(), limit)?;
+            older_done = page.len() < limit;
+            if let Some(last) = page.last() {
+                older_after = Some(last.key.clone());
+            }
+            older_page = page.into_iter().peekable();
+        }
+        let order = match (newer.peek(), older_page.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((key, _)), Some(fact)) => (*key).cmp(&fact.key),
+        };
+        if order.is_gt() {
+            facts.extend(older_page.next());
+            continue;
+        }
+        if order.is_eq() {
+            // The newer fact replaces the older one.
+            older_page.next();
+        }
+        if let Some((key, Some(value))) = newer.next() {
+            facts.push(Fact {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    Ok(facts)
 }
 
 /// A fact with a key and value.
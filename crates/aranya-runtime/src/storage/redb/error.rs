@@ -0,0 +1,49 @@
+use core::fmt;
+
+use tracing::error;
+
+use crate::StorageError;
+
+/// An error returned by this module.
+#[derive(Debug)]
+pub struct Error(::redb::Error);
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+macro_rules! impl_from {
+    ($($err:ty),* $(,)?) => {
+        $(
+            impl From<$err> for Error {
+                fn from(err: $err) -> Self {
+                    Self(err.into())
+                }
+            }
+
+            impl From<$err> for StorageError {
+                fn from(err: $err) -> Self {
+                    error!(?err);
+                    StorageError::IoError
+                }
+            }
+        )*
+    };
+}
+impl_from! {
+    ::redb::Error,
+    ::redb::CommitError,
+    ::redb::CompactionError,
+    ::redb::DatabaseError,
+    ::redb::StorageError,
+    ::redb::TableError,
+    ::redb::TransactionError,
+}
@@ -0,0 +1,252 @@
+use std::{path::Path, sync::Arc};
+
+use ::redb::{Builder, Database, Durability, ReadableTable, TableDefinition, WriteTransaction};
+use buggy::{bug, BugExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use super::error::Error;
+use crate::{
+    linear::io::{IoManager, Read, Write},
+    GraphId, Location, StorageError,
+};
+
+/// The commit head of each graph, keyed by graph ID. The head is `None` until the
+/// first commit.
+const GRAPHS: TableDefinition<&[u8], Option<(u64, u64)>> = TableDefinition::new("graphs");
+
+/// The segments and fact indices appended to each graph, keyed by graph ID and offset.
+const ITEMS: TableDefinition<(&[u8], u64), &[u8]> = TableDefinition::new("items");
+
+/// Options for opening a [`RedbManager`].
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The number of bytes of memory used to cache database pages, or `None` to use
+    /// redb's default.
+    ///
+    /// Reads of large graphs are faster when more of the graph fits in the cache.
+    pub cache_size: Option<usize>,
+    /// Whether to compact the database file when it is opened, returning space left
+    /// over from earlier transactions to the file system.
+    pub compact_on_open: bool,
+}
+
+/// A redb-backed implementation of [`IoManager`].
+pub struct RedbManager {
+    db: Arc<Database>,
+}
+
+impl RedbManager {
+    /// Opens or creates the database at `path` with the default [`Config`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::with_config(path, &Config::default())
+    }
+
+    /// Opens or creates the database at `path`.
+    pub fn with_config<P: AsRef<Path>>(path: P, config: &Config) -> Result<Self, Error> {
+        let mut builder = Builder::new();
+        if let Some(size) = config.cache_size {
+            builder.set_cache_size(size);
+        }
+        let mut db = builder.create(path)?;
+        if config.compact_on_open {
+            db.compact()?;
+        }
+
+        // Create the tables so that readers can always open them.
+        let txn = db.begin_write()?;
+        txn.open_table(GRAPHS)?;
+        txn.open_table(ITEMS)?;
+        txn.commit()?;
+
+        Ok(Self { db: Arc::new(db) })
+    }
+}
+
+impl IoManager for RedbManager {
+    type Writer = Writer;
+
+    fn create(&mut self, id: GraphId) -> Result<Self::Writer, StorageError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut graphs = txn.open_table(GRAPHS)?;
+            if graphs.get(id.as_bytes())?.is_some() {
+                return Err(StorageError::StorageExists);
+            }
+            graphs.insert(id.as_bytes(), None)?;
+        }
+        txn.commit()?;
+        Ok(Writer {
+            db: Arc::clone(&self.db),
+            id,
+            head: None,
+            next_offset: 0,
+            batch: None,
+        })
+    }
+
+    fn open(&mut self, id: GraphId) -> Result<Option<Self::Writer>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let graphs = txn.open_table(GRAPHS)?;
+        let Some(head) = graphs.get(id.as_bytes())? else {
+            return Ok(None);
+        };
+        let head = match head.value() {
+            Some((segment, command)) => Some(Location::new(
+                segment.try_into().assume("segment fits in `usize`")?,
+                command.try_into().assume("command fits in `usize`")?,
+            )),
+            None => None,
+        };
+        // Append after every existing item, including any that were
+        // never committed and so are unreachable.
+        let items = txn.open_table(ITEMS)?;
+        let last = items
+            .range((id.as_bytes(), 0)..=(id.as_bytes(), u64::MAX))?
+            .next_back()
+            .transpose()?;
+        let next_offset = match last {
+            Some((key, _)) => key
+                .value()
+                .1
+                .checked_add(1)
+                .assume("offset will not overflow")?
+                .try_into()
+                .assume("offset fits in `usize`")?,
+            None => 0,
+        };
+        Ok(Some(Writer {
+            db: Arc::clone(&self.db),
+            id,
+            head,
+            next_offset,
+            batch: None,
+        }))
+    }
+}
+
+/// A redb-backed writer for linear storage.
+pub struct Writer {
+    db: Arc<Database>,
+    id: GraphId,
+    head: Option<Location>,
+    next_offset: usize,
+    /// Items appended during a batch, which are inserted when it ends.
+    batch: Option<Vec<(usize, Vec<u8>)>>,
+}
+
+impl Writer {
+    fn begin(&self, durability: Durability) -> Result<WriteTransaction, StorageError> {
+        let mut txn = self.db.begin_write()?;
+        txn.set_durability(durability);
+        Ok(txn)
+    }
+
+    /// Inserts `items` in one transaction.
+    fn insert(
+        &self,
+        items: impl IntoIterator<Item = (usize, Vec<u8>)>,
+    ) -> Result<(), StorageError> {
+        // Items are unreachable until they are committed, which
+        // makes them durable as well.
+        let txn = self.begin(Durability::Eventual)?;
+        {
+            let mut table = txn.open_table(ITEMS)?;
+            for (offset, bytes) in items {
+                table.insert((self.id.as_bytes(), to_key(offset)?), bytes.as_slice())?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+impl Write for Writer {
+    type ReadOnly = Reader;
+
+    fn readonly(&self) -> Self::ReadOnly {
+        Reader {
+            db: Arc::clone(&self.db),
+            id: self.id,
+        }
+    }
+
+    fn head(&self) -> Result<Location, StorageError> {
+        match self.head {
+            Some(head) => Ok(head),
+            None => bug!("not initialized"),
+        }
+    }
+
+    fn append<F, T>(&mut self, builder: F) -> Result<T, StorageError>
+    where
+        F: FnOnce(usize) -> T,
+        T: Serialize,
+    {
+        let offset = self.next_offset;
+        let item = builder(offset);
+        let bytes = postcard::to_allocvec(&item).map_err(|err| {
+            error!(?err, "append");
+            StorageError::IoError
+        })?;
+
+        match &mut self.batch {
+            Some(batch) => batch.push((offset, bytes)),
+            None => self.insert([(offset, bytes)])?,
+        }
+
+        self.next_offset = offset.checked_add(1).assume("offset will not overflow")?;
+        Ok(item)
+    }
+
+    fn commit(&mut self, head: Location) -> Result<(), StorageError> {
+        let txn = self.begin(Durability::Immediate)?;
+        txn.open_table(GRAPHS)?.insert(
+            self.id.as_bytes(),
+            Some((to_key(head.segment)?, to_key(head.command)?)),
+        )?;
+        txn.commit()?;
+        self.head = Some(head);
+        Ok(())
+    }
+
+    fn begin_batch(&mut self) -> Result<(), StorageError> {
+        self.batch.get_or_insert_with(Vec::new);
+        Ok(())
+    }
+
+    fn end_batch(&mut self) -> Result<(), StorageError> {
+        match self.batch.take() {
+            Some(batch) if !batch.is_empty() => self.insert(batch),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A redb-backed reader for linear storage.
+#[derive(Clone)]
+pub struct Reader {
+    db: Arc<Database>,
+    id: GraphId,
+}
+
+impl Read for Reader {
+    fn fetch<T>(&self, offset: usize) -> Result<T, StorageError>
+    where
+        T: DeserializeOwned,
+    {
+        let txn = self.db.begin_read()?;
+        let items = txn.open_table(ITEMS)?;
+        let bytes = items
+            .get((self.id.as_bytes(), to_key(offset)?))?
+            .ok_or(StorageError::SegmentOutOfBounds(Location::new(offset, 0)))?;
+        postcard::from_bytes(bytes.value()).map_err(|err| {
+            error!(?err, "fetch");
+            StorageError::IoError
+        })
+    }
+}
+
+fn to_key(offset: usize) -> Result<u64, StorageError> {
+    Ok(offset.try_into().assume("offset fits in `u64`")?)
+}
@@ -0,0 +1,37 @@
+//! Storage backed by [redb](https://docs.rs/redb), an embedded key-value store written
+//! in pure Rust.
+//!
+//! [`RedbStorageProvider`] is a [`LinearStorageProvider`] whose items and commit heads
+//! are stored in a single redb database, which scales to graphs with millions of
+//! commands. See [`Config`] for tuning.
+//!
+//! Writes are crash-safe: items are appended with eventual durability and become
+//! durable together with the commit which makes them reachable, so a crash can only
+//! lose items the head does not yet point at.
+//!
+//! As with other [`IoManager`](crate::linear::IoManager)s, a database should only be used
+//! by one [`RedbManager`] at a time. redb enforces this with a file lock.
+
+#![cfg(feature = "redb")]
+#![cfg_attr(docsrs, doc(cfg(feature = "redb")))]
+#![deny(
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::missing_panics_doc,
+    clippy::unimplemented,
+    clippy::unwrap_used,
+    clippy::wildcard_imports,
+    missing_docs
+)]
+
+mod error;
+mod imp;
+mod tests;
+
+pub use error::Error;
+pub use imp::*;
+
+use crate::linear::LinearStorageProvider;
+
+/// A [`StorageProvider`](crate::StorageProvider) backed by a redb database.
+pub type RedbStorageProvider = LinearStorageProvider<RedbManager>;
@@ -0,0 +1,34 @@
+use core::fmt;
+
+use tracing::error;
+
+use crate::StorageError;
+
+/// An error returned by this module.
+#[derive(Debug)]
+pub struct Error(rusqlite::Error);
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Self(err)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        error!(?err);
+        StorageError::IoError
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use buggy::{bug, BugExt};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use super::error::Error;
+use crate::{
+    linear::io::{IoManager, Read, Write},
+    GraphId, Location, StorageError,
+};
+
+/// Creates the tables used by [`SqliteManager`].
+///
+/// `items` holds the segments and fact indices appended to each graph, and
+/// `graphs` holds the commit head of each graph, which is `NULL` until the first
+/// commit.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS graphs (
+        id BLOB PRIMARY KEY NOT NULL,
+        head_segment INTEGER,
+        head_command INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        graph BLOB NOT NULL REFERENCES graphs (id),
+        offset INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (graph, offset)
+    ) WITHOUT ROWID;
+";
+
+/// A SQLite-backed implementation of [`IoManager`].
+#[derive(Debug)]
+pub struct SqliteManager {
+    conn: Db,
+}
+
+impl SqliteManager {
+    /// Opens or creates the database at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Creates a database which only exists in memory.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Creates a `SqliteManager` using an existing connection.
+    pub fn from_connection(conn: Connection) -> Result<Self, Error> {
+        // WAL lets readers proceed while a write is in progress, and with
+        // `synchronous = FULL` a committed transaction survives power loss.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Db(Arc::new(Mutex::new(conn))),
+        })
+    }
+}
+
+impl IoManager for SqliteManager {
+    type Writer = Writer;
+
+    fn create(&mut self, id: GraphId) -> Result<Self::Writer, StorageError> {
+        let result = self.conn.lock()?.execute(
+            "INSERT INTO graphs (id) VALUES (?1)",
+            params![id.as_bytes()],
+        );
+        if let Err(err) = result {
+            return Err(match err {
+                rusqlite::Error::SqliteFailure(e, _)
+                    if e.code == ErrorCode::ConstraintViolation =>
+                {
+                    StorageError::StorageExists
+                }
+                err => err.into(),
+            });
+        }
+        Ok(Writer {
+            conn: self.conn.clone(),
+            id,
+            head: None,
+            next_offset: 0,
+            batch: None,
+        })
+    }
+
+    fn open(&mut self, id: GraphId) -> Result<Option<Self::Writer>, StorageError> {
+        let conn = self.conn.lock()?;
+        let Some((segment, command)) = conn
+            .query_row(
+                "SELECT head_segment, head_command FROM graphs WHERE id = ?1",
+                params![id.as_bytes()],
+                |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let head = match (segment, command) {
+            (Some(segment), Some(command)) => Some(Location::new(
+                segment.try_into().assume("segment fits in `usize`")?,
+                command.try_into().assume("command fits in `usize`")?,
+            )),
+            _ => None,
+        };
+        // Append after every existing item, including any that were
+        // never committed and so are unreachable.
+        let next: i64 = conn.query_row(
+            "SELECT COALESCE(MAX(offset) + 1, 0) FROM items WHERE graph = ?1",
+            params![id.as_bytes()],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        Ok(Some(Writer {
+            conn: self.conn.clone(),
+            id,
+            head,
+            next_offset: next.try_into().assume("offset fits in `usize`")?,
+            batch: None,
+        }))
+    }
+}
+
+/// A SQLite-backed writer for linear storage.
+#[derive(Debug)]
+pub struct Writer {
+    conn: Db,
+    id: GraphId,
+    head: Option<Location>,
+    next_offset: usize,
+    /// Items appended during a batch, which are inserted when it ends.
+    batch: Option<Vec<(usize, Vec<u8>)>>,
+}
+
+impl Write for Writer {
+    type ReadOnly = Reader;
+
+    fn readonly(&self) -> Self::ReadOnly {
+        Reader {
+            conn: self.conn.clone(),
+            id: self.id,
+        }
+    }
+
+    fn head(&self) -> Result<Location, StorageError> {
+        match self.head {
+            Some(head) => Ok(head),
+            None => bug!("not initialized"),
+        }
+    }
+
+    fn append<F, T>(&mut self, builder: F) -> Result<T, StorageError>
+    where
+        F: FnOnce(usize) -> T,
+        T: Serialize,
+    {
+        let offset = self.next_offset;
+        let item = builder(offset);
+        let bytes = postcard::to_allocvec(&item).map_err(|err| {
+            error!(?err, "append");
+            StorageError::IoError
+        })?;
+        match &mut self.batch {
+            Some(batch) => batch.push((offset, bytes)),
+            None => {
+                self.conn.lock()?.execute(
+                    "INSERT INTO items (graph, offset, data) VALUES (?1, ?2, ?3)",
+                    params![self.id.as_bytes(), to_sql_offset(offset)?, bytes],
+                )?;
+            }
+        }
+        self.next_offset = offset.checked_add(1).assume("offset will not overflow")?;
+        Ok(item)
+    }
+
+    fn commit(&mut self, head: Location) -> Result<(), StorageError> {
+        self.conn.lock()?.execute(
+            "UPDATE graphs SET head_segment = ?2, head_command = ?3 WHERE id = ?1",
+            params![
+                self.id.as_bytes(),
+                to_sql_offset(head.segment)?,
+                to_sql_offset(head.command)?,
+            ],
+        )?;
+        self.head = Some(head);
+        Ok(())
+    }
+
+    fn begin_batch(&mut self) -> Result<(), StorageError> {
+        self.batch.get_or_insert_with(Vec::new);
+        Ok(())
+    }
+
+    fn end_batch(&mut self) -> Result<(), StorageError> {
+        let Some(batch) = self.batch.take() else {
+            return Ok(());
+        };
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.lock()?;
+        let txn = conn.transaction()?;
+        {
+            let mut insert =
+                txn.prepare("INSERT INTO items (graph, offset, data) VALUES (?1, ?2, ?3)")?;
+            for (offset, bytes) in batch {
+                insert.execute(params![self.id.as_bytes(), to_sql_offset(offset)?, bytes])?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// A SQLite-backed reader for linear storage.
+#[derive(Clone, Debug)]
+pub struct Reader {
+    conn: Db,
+    id: GraphId,
+}
+
+impl Read for Reader {
+    fn fetch<T>(&self, offset: usize) -> Result<T, StorageError>
+    where
+        T: DeserializeOwned,
+    {
+        let bytes: Vec<u8> = self
+            .conn
+            .lock()?
+            .query_row(
+                "SELECT data FROM items WHERE graph = ?1 AND offset = ?2",
+                params![self.id.as_bytes(), to_sql_offset(offset)?],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(StorageError::SegmentOutOfBounds(Location::new(offset, 0)))?;
+        postcard::from_bytes(&bytes).map_err(|err| {
+            error!(?err, "fetch");
+            StorageError::IoError
+        })
+    }
+}
+
+/// A connection shared by a manager and its writers and readers.
+#[derive(Clone, Debug)]
+struct Db(Arc<Mutex<Connection>>);
+
+impl Db {
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, StorageError> {
+        self.0.lock().map_err(|_| {
+            error!("database mutex poisoned");
+            StorageError::IoError
+        })
+    }
+}
+
+fn to_sql_offset(offset: usize) -> Result<i64, StorageError> {
+    Ok(offset.try_into().assume("offset fits in `i64`")?)
+}
@@ -0,0 +1,32 @@
+//! SQLite-backed storage.
+//!
+//! [`SqliteStorageProvider`] is a [`LinearStorageProvider`] whose items and commit heads
+//! are stored in a SQLite database, so graphs persist across restarts. The items written
+//! for a segment are inserted in one SQLite transaction, and each commit is its own.
+//!
+//! As with other [`IoManager`](crate::linear::IoManager)s, a database should only be used
+//! by one [`SqliteManager`] at a time.
+
+#![cfg(feature = "sqlite")]
+#![cfg_attr(docsrs, doc(cfg(feature = "sqlite")))]
+#![deny(
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::missing_panics_doc,
+    clippy::unimplemented,
+    clippy::unwrap_used,
+    clippy::wildcard_imports,
+    missing_docs
+)]
+
+mod error;
+mod imp;
+mod tests;
+
+pub use error::Error;
+pub use imp::*;
+
+use crate::linear::LinearStorageProvider;
+
+/// A [`StorageProvider`](crate::StorageProvider) backed by a SQLite database.
+pub type SqliteStorageProvider = LinearStorageProvider<SqliteManager>;
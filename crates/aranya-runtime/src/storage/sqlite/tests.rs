@@ -0,0 +1,84 @@
+#![cfg(test)]
+
+use tracing::info;
+
+use super::*;
+use crate::{
+    testing::dsl::{test_suite, StorageBackend},
+    GraphId, Location, StorageError,
+};
+
+struct SqliteBackend {
+    tempdir: tempfile::TempDir,
+}
+
+impl StorageBackend for SqliteBackend {
+    type StorageProvider = SqliteStorageProvider;
+
+    fn provider(&mut self, client_id: u64) -> Self::StorageProvider {
+        let path = self.tempdir.path().join(format!("{client_id}.db"));
+        let manager = SqliteManager::open(path).unwrap();
+        SqliteStorageProvider::new(manager)
+    }
+}
+
+test_suite!(|| {
+    let tempdir = tempfile::tempdir().unwrap();
+    info!(path = ?tempdir.path(), "using tempdir");
+    SqliteBackend { tempdir }
+});
+
+#[test]
+fn test_reopen() {
+    use crate::linear::{IoManager, Read, Write};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("graphs.db");
+    let id = GraphId::default();
+    let head = Location::new(0, 1);
+
+    {
+        let mut manager = SqliteManager::open(&path).unwrap();
+        let mut writer = manager.create(id).unwrap();
+        let item: (usize, u64) = writer.append(|offset| (offset, 42)).unwrap();
+        assert_eq!(item, (0, 42));
+        writer.commit(head).unwrap();
+        assert!(matches!(
+            manager.create(id),
+            Err(StorageError::StorageExists)
+        ));
+    }
+
+    let mut manager = SqliteManager::open(&path).unwrap();
+    let writer = manager.open(id).unwrap().expect("graph should persist");
+    assert_eq!(writer.head().unwrap(), head);
+    let item: (usize, u64) = writer.readonly().fetch(0).unwrap();
+    assert_eq!(item, (0, 42));
+    assert!(manager.open(GraphId::from([1; 64])).unwrap().is_none());
+}
+
+#[test]
+fn test_batch() {
+    use crate::linear::{IoManager, Read, Write};
+
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut manager = SqliteManager::open(tempdir.path().join("graphs.db")).unwrap();
+    let mut writer = manager.create(GraphId::default()).unwrap();
+    let reader = writer.readonly();
+
+    writer.begin_batch().unwrap();
+    for value in 0u64..3 {
+        writer.append(|offset| (offset, value)).unwrap();
+    }
+    // Items are only inserted once the batch ends.
+    assert!(reader.fetch::<(usize, u64)>(0).is_err());
+    writer.end_batch().unwrap();
+    for (offset, value) in (0usize..).zip(0u64..3) {
+        let item: (usize, u64) = reader.fetch(offset).unwrap();
+        assert_eq!(item, (offset, value));
+    }
+
+    let item: (usize, u64) = writer.append(|offset| (offset, 3)).unwrap();
+    assert_eq!(item, (3, 3));
+    assert_eq!(reader.fetch::<(usize, u64)>(3).unwrap(), item);
+}
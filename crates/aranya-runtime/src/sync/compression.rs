@@ -0,0 +1,190 @@
+//! Compression of sync payloads.
+//!
+//! A requester offers the [`Compression`] algorithms it will accept in
+//! its `SyncRequest`, in order of preference. The responder picks the
+//! first one it also accepts for that peer, and compresses the command
+//! data in each `SyncResponse` with it. Responses which would not get
+//! smaller are sent uncompressed.
+
+use alloc::vec::Vec;
+
+use heapless::Vec as HVec;
+use serde::{Deserialize, Serialize};
+
+use super::{SyncError, COMPRESSION_MAX};
+
+/// A compression algorithm for sync payloads.
+///
+/// Each algorithm can only be used if its feature is enabled, but is
+/// always understood on the wire.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    /// DEFLATE, which requires the `deflate` feature.
+    Deflate,
+    /// Zstandard, which requires the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// Reports whether this build supports the algorithm.
+    pub const fn is_supported(self) -> bool {
+        match self {
+            Self::Deflate => cfg!(feature = "deflate"),
+            Self::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+/// Collects the supported algorithms in `algorithms`, in order.
+pub(super) fn supported(algorithms: &[Compression]) -> HVec<Compression, COMPRESSION_MAX> {
+    let mut result = HVec::new();
+    for &alg in algorithms {
+        if alg.is_supported() && !result.contains(&alg) {
+            // Every algorithm fits, so this cannot fail.
+            result.push(alg).ok();
+        }
+    }
+    result
+}
+
+/// Picks the first algorithm `offered` by the requester that the
+/// responder `accepts`.
+pub(super) fn negotiate(offered: &[Compression], accepts: &[Compression]) -> Option<Compression> {
+    offered
+        .iter()
+        .copied()
+        .find(|alg| alg.is_supported() && accepts.contains(alg))
+}
+
+/// Compresses `data` with `alg`.
+#[cfg_attr(
+    not(any(feature = "deflate", feature = "zstd")),
+    allow(unused_variables)
+)]
+pub(super) fn compress(alg: Compression, data: &[u8]) -> Result<Vec<u8>, SyncError> {
+    match alg {
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => Ok(miniz_oxide::deflate::compress_to_vec(data, 6)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::compress(data, 0).map_err(|_| SyncError::Compression),
+        #[allow(unreachable_patterns)]
+        _ => Err(SyncError::UnsupportedCompression(alg)),
+    }
+}
+
+/// Decompresses `data` with `alg`, failing if the result is larger
+/// than `max` bytes.
+#[cfg_attr(
+    not(any(feature = "deflate", feature = "zstd")),
+    allow(unused_variables)
+)]
+pub(super) fn decompress(alg: Compression, data: &[u8], max: usize) -> Result<Vec<u8>, SyncError> {
+    match alg {
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => miniz_oxide::inflate::decompress_to_vec_with_limit(data, max)
+            .map_err(|_| SyncError::Compression),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::decompress(data, max).map_err(|_| SyncError::Compression),
+        #[allow(unreachable_patterns)]
+        _ => Err(SyncError::UnsupportedCompression(alg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::borrow::Cow;
+
+    use aranya_crypto::Rng;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, NullSink, PeerCache, SyncRequester, SyncResponder, SyncResponseMessage,
+        SyncType, MAX_SYNC_MESSAGE_SIZE,
+    };
+
+    const ALL: [Compression; 2] = [Compression::Deflate, Compression::Zstd];
+
+    #[test]
+    fn test_negotiate() {
+        use Compression::*;
+
+        assert_eq!(negotiate(&ALL, &ALL), Some(Deflate));
+        assert_eq!(negotiate(&[Zstd, Deflate], &ALL), Some(Zstd));
+        assert_eq!(negotiate(&ALL, &[Zstd]), Some(Zstd));
+        assert_eq!(negotiate(&[Deflate], &[Zstd]), None);
+        assert_eq!(negotiate(&[], &ALL), None);
+        assert_eq!(negotiate(&ALL, &[]), None);
+        assert_eq!(
+            supported(&[Zstd, Zstd, Deflate]).as_slice(),
+            &[Zstd, Deflate]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"abcdefg".repeat(600);
+        for alg in ALL {
+            let compressed = compress(alg, &data).unwrap();
+            assert!(compressed.len() < data.len(), "{alg:?}");
+            assert_eq!(decompress(alg, &compressed, data.len()).unwrap(), data);
+            assert!(decompress(alg, &compressed, 100).is_err());
+            assert!(decompress(alg, &data, data.len()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_response() {
+        let mut from = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = from
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 0..6 {
+            from.action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let mut to = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+
+        let mut requester = SyncRequester::new(storage_id, &mut Rng, ());
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester
+            .poll(&mut buffer, to.provider(), &mut PeerCache::new())
+            .unwrap();
+        let SyncType::Poll { request, .. } =
+            postcard::from_bytes::<SyncType<()>>(&buffer[..len]).unwrap()
+        else {
+            panic!("expected a poll")
+        };
+        let mut responder = SyncResponder::new(());
+        responder.receive(request).unwrap();
+        let len = responder
+            .poll(&mut buffer, from.provider(), &mut PeerCache::new())
+            .unwrap();
+        let plain = &buffer[..len];
+
+        // Compress the command data like a responder would.
+        let (mut message, data): (SyncResponseMessage, &[u8]) =
+            postcard::take_from_bytes(plain).unwrap();
+        let SyncResponseMessage::SyncResponse { compression, .. } = &mut message else {
+            panic!("expected a response")
+        };
+        *compression = Some(Compression::Deflate);
+        let mut compressed = postcard::to_allocvec(&message).unwrap();
+        compressed.extend(compress(Compression::Deflate, data).unwrap());
+
+        assert!(matches!(
+            requester.decompress(plain).unwrap(),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(*requester.decompress(&compressed).unwrap(), *plain);
+        assert!(matches!(
+            requester.receive(&compressed),
+            Err(SyncError::Compression)
+        ));
+
+        let data = requester.decompress(&compressed).unwrap();
+        let cmds = requester.receive(&data).unwrap().unwrap();
+        assert!(!cmds.is_empty());
+    }
+}
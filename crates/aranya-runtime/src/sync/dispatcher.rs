@@ -1,3 +1,5 @@
+use alloc::vec;
+
 use heapless::Vec;
 use serde::{Deserialize, Serialize};
 
@@ -16,6 +18,15 @@ pub enum SyncType<A> {
         /// The remote address of this peer. Used to pull the correct peer cache.
         address: A,
     },
+    /// Polls several graphs in a single exchange. Each request is
+    /// responded to in order with [`respond_multi`](super::respond_multi).
+    MultiPoll {
+        /// A sync request for each graph, at most
+        /// [`GRAPH_BATCH_MAX`](super::GRAPH_BATCH_MAX).
+        requests: vec::Vec<SyncRequestMessage>,
+        /// The remote address of this peer. Used to pull the correct peer cache.
+        address: A,
+    },
     /// Subscribes the peer to receive push syncs from this peer. Calling this
     /// again will update remain_open and max_bytes for this peer.
     Subscribe {
@@ -0,0 +1,184 @@
+//! Backpressure and rate limiting for sync responders.
+
+use core::{num::NonZeroU32, time::Duration};
+
+use super::PeerCache;
+
+/// Limits on how much a [`SyncResponder`](super::SyncResponder) sends
+/// to each peer.
+///
+/// A responder which is over its limits for a peer sends
+/// [`SyncResponseMessage::RetryLater`](super::SyncResponseMessage::RetryLater)
+/// instead of commands. The time a peer must wait is tracked in its
+/// [`PeerCache`].
+#[derive(Clone, Debug, Default)]
+pub struct SyncLimits {
+    /// The maximum number of segments sent in a single sync, or `None`
+    /// for no limit. The remaining segments are sent in later syncs.
+    pub max_segments: Option<usize>,
+    /// The maximum average number of bytes sent to each peer per
+    /// second, or `None` for no limit.
+    pub bytes_per_sec: Option<NonZeroU32>,
+    /// The minimum time between responses to each peer.
+    pub min_interval: Duration,
+}
+
+impl SyncLimits {
+    /// Records that `len` bytes were sent to `peer` at `now`.
+    pub(super) fn sent(&self, peer: &mut PeerCache, now: Duration, len: usize) {
+        let mut next = now.saturating_add(self.min_interval);
+        if let Some(rate) = self.bytes_per_sec {
+            // The peer may not be sent more until the bytes have been
+            // paid for at `rate`, including any still owed.
+            let cost = Duration::from_secs(len as u64)
+                .checked_div(rate.get())
+                .unwrap_or(Duration::MAX);
+            next = next.max(peer.next_send.max(now).saturating_add(cost));
+        }
+        peer.next_send = next;
+    }
+}
+
+impl PeerCache {
+    /// Returns how long the peer must wait before it is sent more, or
+    /// `None` if it may be sent more at `now`.
+    pub(super) fn retry_after(&self, now: Duration) -> Option<Duration> {
+        self.next_send
+            .checked_sub(now)
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::Rng;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientState, GraphId, NullSink, SyncError, SyncRequester, SyncResponder, SyncType,
+        MAX_SYNC_MESSAGE_SIZE,
+    };
+
+    type Client = ClientState<TestEngine, MemStorageProvider>;
+
+    /// Syncs `to` from `from` at `now`, returning the number of
+    /// commands received.
+    fn sync(
+        storage_id: GraphId,
+        from: &mut Client,
+        to: &mut Client,
+        cache: &mut PeerCache,
+        limits: &SyncLimits,
+        now: Duration,
+    ) -> Result<usize, SyncError> {
+        let mut requester = SyncRequester::new(storage_id, &mut Rng, ());
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester.poll(&mut buffer, to.provider(), &mut PeerCache::new())?;
+        let SyncType::Poll { request, .. } = postcard::from_bytes::<SyncType<()>>(&buffer[..len])?
+        else {
+            panic!("expected a poll")
+        };
+
+        let mut responder = SyncResponder::new(()).with_limits(limits.clone(), now);
+        responder.receive(request)?;
+        let len = responder.poll(&mut buffer, from.provider(), cache)?;
+        if len == 0 {
+            return Ok(0);
+        }
+        let Some(cmds) = requester.receive(&buffer[..len])? else {
+            return Ok(0);
+        };
+        let mut trx = to.transaction(storage_id);
+        to.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())
+            .unwrap();
+        to.commit(&mut trx, &mut NullSink).unwrap();
+        Ok(cmds.len())
+    }
+
+    #[test]
+    fn test_retry_later() {
+        let mut from = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = from
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 0..6 {
+            from.action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let mut to = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+
+        let limits = SyncLimits {
+            max_segments: Some(2),
+            min_interval: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut cache = PeerCache::new();
+        let mut now = Duration::from_secs(100);
+        for syncs in 0.. {
+            match sync(storage_id, &mut from, &mut to, &mut cache, &limits, now) {
+                Ok(0) => {
+                    // 7 commands in 7 segments, 2 segments at a time.
+                    assert_eq!(syncs, 4);
+                    break;
+                }
+                Ok(received) => assert!(received <= 2),
+                Err(err) => panic!("{err}"),
+            }
+
+            let err = sync(storage_id, &mut from, &mut to, &mut cache, &limits, now)
+                .expect_err("should be told to retry");
+            assert!(
+                matches!(err, SyncError::RetryLater(wait) if wait == Duration::from_secs(1)),
+                "{err}"
+            );
+            now = now.saturating_add(Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let limits = SyncLimits {
+            min_interval: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut peer = PeerCache::new();
+        assert_eq!(peer.retry_after(Duration::ZERO), None);
+
+        limits.sent(&mut peer, Duration::from_secs(10), 1_000_000);
+        assert_eq!(
+            peer.retry_after(Duration::from_millis(10_250)),
+            Some(Duration::from_millis(750))
+        );
+        assert_eq!(peer.retry_after(Duration::from_secs(11)), None);
+    }
+
+    #[test]
+    fn test_bytes_per_sec() {
+        let limits = SyncLimits {
+            bytes_per_sec: NonZeroU32::new(1000),
+            ..Default::default()
+        };
+        let mut peer = PeerCache::new();
+
+        limits.sent(&mut peer, Duration::ZERO, 500);
+        assert_eq!(
+            peer.retry_after(Duration::ZERO),
+            Some(Duration::from_millis(500))
+        );
+
+        // Sending more before the first response is paid for adds to
+        // what is owed.
+        limits.sent(&mut peer, Duration::from_millis(100), 1000);
+        assert_eq!(
+            peer.retry_after(Duration::from_millis(100)),
+            Some(Duration::from_millis(1400))
+        );
+        assert_eq!(peer.retry_after(Duration::from_millis(1500)), None);
+
+        // Nothing is owed for the time the peer was idle.
+        limits.sent(&mut peer, Duration::from_secs(10), 0);
+        assert_eq!(peer.retry_after(Duration::from_secs(10)), None);
+    }
+}
@@ -1,5 +1,7 @@
 //! Interface for syncing state between clients.
 
+use core::time::Duration;
+
 use buggy::Bug;
 use postcard::Error as PostcardError;
 use serde::{Deserialize, Serialize};
@@ -10,13 +12,26 @@ use crate::{
     Address, Prior,
 };
 
+mod compression;
 mod dispatcher;
+mod limits;
+mod multi;
+mod reconcile;
 mod requester;
 mod responder;
+mod resume;
+mod select;
+pub mod tcp;
 
+pub use compression::Compression;
 pub use dispatcher::{SubscribeResult, SyncType};
+pub use limits::SyncLimits;
+pub use multi::{respond_multi, GraphCommands, MultiSyncRequester};
+pub use reconcile::CommandFilter;
 pub use requester::{SyncRequestMessage, SyncRequester};
 pub use responder::{PeerCache, SyncResponder, SyncResponseMessage};
+pub use resume::SyncToken;
+pub use select::{Labeler, Selection};
 
 // TODO: These should all be compile time parameters
 
@@ -36,11 +51,35 @@ pub const COMMAND_RESPONSE_MAX: usize = 100;
 /// The maximum number of segments which can be stored to send
 const SEGMENT_BUFFER_MAX: usize = 100;
 
+/// The size in bytes of the command filter in a request
+const COMMAND_FILTER_BYTES: usize = 2048;
+
+/// The number of bits set in the command filter for each command
+const COMMAND_FILTER_HASHES: usize = 7;
+
+/// The maximum number of commands added to the command filter
+const COMMAND_FILTER_MAX: usize = 1000;
+
+/// The maximum number of compression algorithms offered in a request
+const COMPRESSION_MAX: usize = 2;
+
+/// The maximum number of labels in a selection
+const SELECTION_MAX: usize = 8;
+
+/// The maximum length in bytes of a label in a selection
+const SELECTION_LABEL_MAX: usize = 32;
+
+/// The maximum number of graphs synced in a single exchange
+pub const GRAPH_BATCH_MAX: usize = 8;
+
 /// The maximum size of a sync message
 // TODO: Use postcard to calculate max size (which accounts for overhead)
 // https://docs.rs/postcard/latest/postcard/experimental/max_size/index.html
 pub const MAX_SYNC_MESSAGE_SIZE: usize = 1024 + MAX_COMMAND_LENGTH * COMMAND_RESPONSE_MAX;
 
+/// The maximum size of a response to a multi-graph sync
+pub const MAX_MULTI_SYNC_MESSAGE_SIZE: usize = MAX_SYNC_MESSAGE_SIZE * GRAPH_BATCH_MAX;
+
 /// Represents high-level data of a command.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CommandMeta {
@@ -74,6 +113,16 @@ pub enum SyncError {
     NotReady,
     #[error("too many commands sent")]
     CommandOverflow,
+    #[error("unsupported compression: {0:?}")]
+    UnsupportedCompression(Compression),
+    #[error("could not compress or decompress payload")]
+    Compression,
+    #[error("too many labels in selection, or label too long")]
+    SelectionOverflow,
+    #[error("too many graphs in a multi-graph sync")]
+    GraphOverflow,
+    #[error("responder is busy, retry after {0:?}")]
+    RetryLater(Duration),
     #[error("storage error: {0}")]
     Storage(#[from] StorageError),
     #[error("serialize error: {0}")]
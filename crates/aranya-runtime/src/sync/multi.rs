@@ -0,0 +1,145 @@
+//! Syncing several graphs in a single exchange.
+//!
+//! A client which shares many graphs with a peer can sync all of them
+//! in one round trip instead of one per graph. A [`MultiSyncRequester`]
+//! sends a [`SyncType::MultiPoll`] with a `SyncRequest` for each graph,
+//! and the peer answers with [`respond_multi`], which contains the
+//! response to each request in the same order.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use heapless::Vec as HVec;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    requester::SyncRequestMessage, PeerCache, SyncCommand, SyncError, SyncRequester, SyncResponder,
+    SyncType, COMMAND_RESPONSE_MAX, GRAPH_BATCH_MAX, MAX_SYNC_MESSAGE_SIZE,
+};
+use crate::{GraphId, StorageProvider};
+
+/// The commands received for a graph in a multi-graph sync.
+pub type GraphCommands<'a> = (GraphId, HVec<SyncCommand<'a>, COMMAND_RESPONSE_MAX>);
+
+/// Requests syncs of several graphs from the same peer in a single
+/// exchange.
+pub struct MultiSyncRequester<'a, A> {
+    requesters: Vec<SyncRequester<'a, A>>,
+    server_address: A,
+}
+
+impl<'a, A: DeserializeOwned + Serialize + Clone> MultiSyncRequester<'a, A> {
+    /// Creates a [`MultiSyncRequester`] for the peer at
+    /// `server_address` with no graphs.
+    pub fn new(server_address: A) -> Self {
+        MultiSyncRequester {
+            requesters: Vec::new(),
+            server_address,
+        }
+    }
+
+    /// Adds a graph to the sync. It is synced as configured by
+    /// `requester`, which must not have been polled.
+    ///
+    /// At most [`GRAPH_BATCH_MAX`] graphs can be synced at once.
+    pub fn push(&mut self, requester: SyncRequester<'a, A>) -> Result<(), SyncError> {
+        if self.requesters.len() >= GRAPH_BATCH_MAX {
+            return Err(SyncError::GraphOverflow);
+        }
+        self.requesters.push(requester);
+        Ok(())
+    }
+
+    /// Returns the server address.
+    pub fn server_addr(&self) -> A {
+        self.server_address.clone()
+    }
+
+    /// Write a [`SyncType::MultiPoll`] in to the target buffer. Returns
+    /// the number of bytes written.
+    ///
+    /// `heads` holds the peer's heads for each graph.
+    pub fn poll(
+        &mut self,
+        target: &mut [u8],
+        provider: &mut impl StorageProvider,
+        heads: &mut BTreeMap<GraphId, PeerCache>,
+    ) -> Result<usize, SyncError> {
+        let mut requests = Vec::with_capacity(self.requesters.len());
+        for requester in &mut self.requesters {
+            let heads = heads.entry(requester.storage_id()).or_default();
+            let (request, _) = requester.start_request(provider, heads)?;
+            requests.push(request);
+        }
+        let message = SyncType::MultiPoll {
+            requests,
+            address: self.server_address.clone(),
+        };
+        Ok(postcard::to_slice(&message, target)?.len())
+    }
+
+    /// Receive the response to a [`SyncType::MultiPoll`]. Returns the
+    /// commands received for each graph, skipping graphs which are up
+    /// to date.
+    ///
+    /// The commands can be added with
+    /// [`ClientState::add_graph_commands`](crate::ClientState::add_graph_commands).
+    pub fn receive<'b>(&mut self, data: &'b [u8]) -> Result<Vec<GraphCommands<'b>>, SyncError> {
+        let responses: HVec<&'b [u8], GRAPH_BATCH_MAX> = postcard::from_bytes(data)?;
+        if responses.len() != self.requesters.len() {
+            return Err(SyncError::MissingSyncResponse);
+        }
+
+        let mut result = Vec::new();
+        for (requester, response) in self.requesters.iter_mut().zip(responses) {
+            // An empty response means the graph is up to date.
+            if response.is_empty() {
+                continue;
+            }
+            let storage_id = requester.storage_id();
+            if let Some(cmds) = requester.receive(response)? {
+                result.push((storage_id, cmds));
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Responds to the `requests` in a [`SyncType::MultiPoll`], writing the
+/// response to each in to the target buffer. Returns the number of
+/// bytes written.
+///
+/// Each request is responded to by a new responder from
+/// `new_responder`, which can configure it as for a single sync.
+/// `caches` holds the peer's heads for each graph. The target buffer
+/// should be [`MAX_MULTI_SYNC_MESSAGE_SIZE`](super::MAX_MULTI_SYNC_MESSAGE_SIZE)
+/// bytes.
+pub fn respond_multi<A, F>(
+    requests: Vec<SyncRequestMessage>,
+    target: &mut [u8],
+    provider: &mut impl StorageProvider,
+    caches: &mut BTreeMap<GraphId, PeerCache>,
+    mut new_responder: F,
+) -> Result<usize, SyncError>
+where
+    A: Serialize + Clone,
+    F: FnMut() -> SyncResponder<A>,
+{
+    if requests.len() > GRAPH_BATCH_MAX {
+        return Err(SyncError::GraphOverflow);
+    }
+
+    let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+    let mut responses = Vec::with_capacity(requests.len());
+    for request in requests {
+        let &SyncRequestMessage::SyncRequest { storage_id, .. } = &request else {
+            return Err(SyncError::SessionState);
+        };
+        let mut responder = new_responder();
+        responder.receive(request)?;
+        let len = responder.poll(&mut buffer, provider, caches.entry(storage_id).or_default())?;
+        responses.push(buffer[..len].to_vec());
+    }
+
+    let responses: HVec<&[u8], GRAPH_BATCH_MAX> = responses.iter().map(Vec::as_slice).collect();
+    Ok(postcard::to_slice(&responses, target)?.len())
+}
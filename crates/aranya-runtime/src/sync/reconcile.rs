@@ -0,0 +1,123 @@
+//! Set reconciliation for sync.
+//!
+//! A requester that is mostly in sync with a responder will usually
+//! have far more commands than fit in a sample. It can instead send a
+//! [`CommandFilter`], a bloom filter of the commands at the tip of its
+//! graph, which the responder uses to skip any segments and commands
+//! the requester already has.
+
+use core::fmt;
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use super::{COMMAND_FILTER_BYTES, COMMAND_FILTER_HASHES};
+use crate::CommandId;
+
+/// Masks a word to a bit index in a [`CommandFilter`]. The number of
+/// bits in the filter must be a power of two.
+const COMMAND_FILTER_MASK: usize = COMMAND_FILTER_BYTES * 8 - 1;
+
+/// A bloom filter of command IDs.
+///
+/// Lookups never have false negatives, but may have false positives.
+/// A false positive causes the responder to skip a command the
+/// requester is missing, so adding its descendants fails with
+/// [`ClientError::NoSuchParent`](crate::ClientError::NoSuchParent).
+/// The requester should then sync again without a filter.
+///
+/// A false positive on the responder's head would leave nothing to
+/// fail, so the head is sent even if it is in the filter, unless the
+/// requester's sample of commands shows it has it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommandFilter {
+    bits: Vec<u8, COMMAND_FILTER_BYTES>,
+}
+
+impl CommandFilter {
+    /// Creates an empty filter.
+    pub fn new() -> Self {
+        let mut bits = Vec::new();
+        bits.resize(COMMAND_FILTER_BYTES, 0).ok();
+        Self { bits }
+    }
+
+    /// Adds `id` to the filter.
+    pub fn insert(&mut self, id: CommandId) {
+        for bit in Self::bits(id) {
+            if let Some(byte) = self.bits.get_mut(bit / 8) {
+                *byte |= Self::mask(bit);
+            }
+        }
+    }
+
+    /// Reports whether `id` may have been added to the filter.
+    ///
+    /// A filter received from a peer which is too short never
+    /// contains anything.
+    pub fn contains(&self, id: CommandId) -> bool {
+        Self::bits(id).all(|bit| {
+            self.bits
+                .get(bit / 8)
+                .is_some_and(|byte| byte & Self::mask(bit) != 0)
+        })
+    }
+
+    /// Returns the bits set for `id`.
+    ///
+    /// Command IDs are cryptographic hashes, so each bit is taken
+    /// directly from four bytes of the ID.
+    fn bits(id: CommandId) -> impl Iterator<Item = usize> {
+        let id = *id.as_array();
+        (0..COMMAND_FILTER_HASHES).filter_map(move |i| {
+            let chunk = id.chunks_exact(4).nth(i)?;
+            let mut word = [0u8; 4];
+            word.copy_from_slice(chunk);
+            Some(u32::from_le_bytes(word) as usize & COMMAND_FILTER_MASK)
+        })
+    }
+
+    fn mask(bit: usize) -> u8 {
+        1u8.rotate_left((bit % 8) as u32)
+    }
+}
+
+impl Default for CommandFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CommandFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self.bits.iter().map(|b| b.count_ones()).sum::<u32>();
+        f.debug_struct("CommandFilter")
+            .field("set", &set)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_filter() {
+        let ids: alloc::vec::Vec<CommandId> = (0u32..500)
+            .map(|i| CommandId::hash_for_testing_only(&i.to_le_bytes()))
+            .collect();
+
+        let mut filter = CommandFilter::new();
+        for &id in &ids[..250] {
+            filter.insert(id);
+            assert!(filter.contains(id));
+        }
+        assert!(ids[..250].iter().all(|&id| filter.contains(id)));
+        let false_positives = ids[250..].iter().filter(|&&id| filter.contains(id)).count();
+        assert!(false_positives < 5, "{false_positives} false positives");
+
+        let bytes = postcard::to_allocvec(&filter).unwrap();
+        let filter: CommandFilter = postcard::from_bytes(&bytes).unwrap();
+        assert!(ids[..250].iter().all(|&id| filter.contains(id)));
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::vec;
+use alloc::{borrow::Cow, collections::BTreeSet, sync::Arc, vec};
 
 use aranya_crypto::Csprng;
 use buggy::BugExt;
@@ -6,10 +6,17 @@ use heapless::Vec;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use super::{
-    dispatcher::SyncType, responder::SyncResponseMessage, PeerCache, SyncCommand, SyncError,
-    COMMAND_RESPONSE_MAX, COMMAND_SAMPLE_MAX, PEER_HEAD_MAX, REQUEST_MISSING_MAX,
+    compression::{self, Compression},
+    dispatcher::SyncType,
+    reconcile::CommandFilter,
+    responder::SyncResponseMessage,
+    resume::SyncToken,
+    select::Selection,
+    PeerCache, SyncCommand, SyncError, COMMAND_FILTER_MAX, COMMAND_RESPONSE_MAX,
+    COMMAND_SAMPLE_MAX, COMPRESSION_MAX, MAX_SYNC_MESSAGE_SIZE, PEER_HEAD_MAX, REQUEST_MISSING_MAX,
 };
 use crate::{
+    metrics::{Metrics, MetricsHandle},
     storage::{Segment, Storage, StorageError, StorageProvider},
     Address, Command, GraphId, Location,
 };
@@ -18,7 +25,7 @@ use crate::{
 // https://rust-lang.github.io/rust-clippy/master/index.html#large_enum_variant.
 // As the buffer consts will be compile-time variables in the future, we will be
 // able to tune these buffers for smaller footprints. Right now, this enum is not
-// suitable for small devices (`SyncRequest` is 6512 bytes).
+// suitable for small devices (`SyncRequest` is over 8500 bytes).
 /// Messages sent from the requester to the responder.
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(clippy::large_enum_variant)]
@@ -37,6 +44,17 @@ pub enum SyncRequestMessage {
         /// the provided sample. When sending commands ancestors must be sent
         /// before descendents.
         commands: Vec<Address, COMMAND_SAMPLE_MAX>,
+        /// Filter of the most recent commands held by the requester, if
+        /// it is reconciling. The responder should not send commands in
+        /// the filter.
+        filter: Option<CommandFilter>,
+        /// Compression algorithms the requester accepts for the command
+        /// data in responses, in order of preference.
+        compression: Vec<Compression, COMPRESSION_MAX>,
+        /// Labels of the commands the requester wants, or `None` for
+        /// all commands. The responder should send only the selected
+        /// commands and their ancestors.
+        selection: Option<Selection>,
     },
 
     /// Sent by the requester if it deduces a `SyncResponse` message has been
@@ -51,13 +69,15 @@ pub enum SyncRequestMessage {
 
     /// Message to request the responder resumes sending `SyncResponse`s
     /// following the specified message. This may be sent after a requester
-    /// timeout or after a `SyncEnd` has been sent.
+    /// timeout, after a `SyncEnd` has been sent, or on a new connection
+    /// after a transfer was interrupted.
     SyncResume {
         /// A random-value produced by a cryptographically secure RNG
         /// corresponding to the `session_id` in the initial `SyncRequest`.
         session_id: u128,
-        /// Indicates the last response message the requester received.
-        response_index: u64,
+        /// The token issued with the last response message the requester
+        /// received.
+        token: SyncToken,
         /// Updates the maximum number of bytes worth of commands that
         /// the requester wishes to receive.
         max_bytes: u64,
@@ -102,6 +122,11 @@ pub struct SyncRequester<'a, A> {
     #[allow(unused)] // TODO(jdygert): Figure out what this is for...
     ooo_buffer: [Option<&'a [u8]>; OOO_LEN],
     server_address: A,
+    reconcile: bool,
+    compression: Vec<Compression, COMPRESSION_MAX>,
+    selection: Option<Selection>,
+    token: Option<SyncToken>,
+    metrics: MetricsHandle,
 }
 
 impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
@@ -120,6 +145,30 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
             next_index: 0,
             ooo_buffer: core::array::from_fn(|_| None),
             server_address,
+            reconcile: false,
+            compression: Vec::new(),
+            selection: None,
+            token: None,
+            metrics: MetricsHandle::new(),
+        }
+    }
+
+    /// Create a [`SyncRequester`] which resumes an interrupted sync
+    /// from the latest [`SyncToken`] it received.
+    pub fn from_token(token: SyncToken, server_address: A) -> Self {
+        SyncRequester {
+            session_id: token.session_id,
+            storage_id: token.storage_id,
+            state: SyncRequesterState::Resync,
+            max_bytes: 0,
+            next_index: token.index.saturating_add(1),
+            ooo_buffer: core::array::from_fn(|_| None),
+            server_address,
+            reconcile: false,
+            compression: Vec::new(),
+            selection: None,
+            token: Some(token),
+            metrics: MetricsHandle::new(),
         }
     }
 
@@ -133,14 +182,80 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
             next_index: 0,
             ooo_buffer: core::array::from_fn(|_| None),
             server_address,
+            reconcile: false,
+            compression: Vec::new(),
+            selection: None,
+            token: None,
+            metrics: MetricsHandle::new(),
         }
     }
 
+    /// Sends a [`CommandFilter`] of the requester's most recent
+    /// commands with the request, so the responder only sends commands
+    /// the requester is missing.
+    ///
+    /// This greatly reduces traffic between peers that are mostly in
+    /// sync. If adding the received commands fails with
+    /// [`ClientError::NoSuchParent`](crate::ClientError::NoSuchParent),
+    /// the filter had a false positive and the requester should sync
+    /// again without reconciling.
+    pub fn with_reconciliation(mut self) -> Self {
+        self.reconcile = true;
+        self
+    }
+
+    /// Asks the responder to send at most about `max_bytes` of command
+    /// data in each response. At least one command is always sent.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Offers to receive command data compressed with `algorithms`,
+    /// in order of preference. Algorithms this build does not support
+    /// are ignored.
+    ///
+    /// Responses must then be passed through [`Self::decompress`].
+    pub fn with_compression(mut self, algorithms: &[Compression]) -> Self {
+        self.compression = compression::supported(algorithms);
+        self
+    }
+
+    /// Asks the responder to send only the commands in `selection`,
+    /// along with their ancestors.
+    ///
+    /// The received commands can be added and verified as usual, but
+    /// the requester's graph only follows the selected commands. A
+    /// responder that does not label commands sends every command.
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Reports the bytes of sync messages sent and received into
+    /// `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics.set(metrics);
+        self
+    }
+
     /// Returns the server address.
     pub fn server_addr(&self) -> A {
         self.server_address.clone()
     }
 
+    /// Returns the token to resume the sync from, if the responder has
+    /// more to send. It can be saved to resume the sync later with
+    /// [`Self::from_token`].
+    pub fn token(&self) -> Option<&SyncToken> {
+        self.token.as_ref()
+    }
+
+    /// Returns the graph being synced.
+    pub fn storage_id(&self) -> GraphId {
+        self.storage_id
+    }
+
     /// Returns true if [`Self::poll`] would produce a message.
     pub fn ready(&self) -> bool {
         use SyncRequesterState as S;
@@ -152,6 +267,10 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
 
     /// Write a sync message in to the target buffer. Returns the number
     /// of bytes written and the number of commands sent in the sample.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(session_id = %self.session_id))
+    )]
     pub fn poll(
         &mut self,
         target: &mut [u8],
@@ -167,21 +286,51 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                 self.state = S::Start;
                 self.start(self.max_bytes, target, provider, heads)?
             }
-            S::Resync => self.resume(self.max_bytes, target)?,
+            S::Resync => self.resume(self.max_bytes, target, provider, heads)?,
             S::Reset => {
                 self.state = S::Closed;
                 self.end_session(target)?
             }
         };
+        self.metrics.get().sync_bytes_sent(result.0);
 
         Ok(result)
     }
 
+    /// Decompresses a sync message, returning `data` unchanged if it
+    /// is not compressed.
+    ///
+    /// If [`Self::with_compression`] was used, messages must be passed
+    /// through this before [`Self::receive`].
+    pub fn decompress<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>, SyncError> {
+        let (mut message, remaining): (SyncResponseMessage, &'a [u8]) =
+            postcard::take_from_bytes(data)?;
+        let SyncResponseMessage::SyncResponse { compression, .. } = &mut message else {
+            return Ok(Cow::Borrowed(data));
+        };
+        let Some(alg) = compression.take() else {
+            return Ok(Cow::Borrowed(data));
+        };
+
+        let payload = compression::decompress(alg, remaining, MAX_SYNC_MESSAGE_SIZE)?;
+        let mut result = postcard::to_allocvec(&message)?;
+        result.extend_from_slice(&payload);
+        Ok(Cow::Owned(result))
+    }
+
     /// Receive a sync message. Returns parsed sync commands.
+    ///
+    /// Compressed messages must first be passed through
+    /// [`Self::decompress`].
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(session_id = %self.session_id))
+    )]
     pub fn receive<'a>(
         &mut self,
         data: &'a [u8],
     ) -> Result<Option<Vec<SyncCommand<'a>, COMMAND_RESPONSE_MAX>>, SyncError> {
+        self.metrics.get().sync_bytes_received(data.len());
         let (message, remaining): (SyncResponseMessage, &'a [u8]) =
             postcard::take_from_bytes(data)?;
 
@@ -200,7 +349,11 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
 
         let result = match message {
             SyncResponseMessage::SyncResponse {
-                index, commands, ..
+                index,
+                commands,
+                compression,
+                token,
+                ..
             } => {
                 if !matches!(
                     self.state,
@@ -209,6 +362,12 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                     return Err(SyncError::SessionState);
                 }
 
+                // The commands must be decompressed with
+                // `Self::decompress` first.
+                if compression.is_some() {
+                    return Err(SyncError::Compression);
+                }
+
                 if index != self.next_index {
                     self.state = SyncRequesterState::Resync;
                     return Err(SyncError::MissingSyncResponse);
@@ -218,6 +377,7 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                     .checked_add(1)
                     .assume("next_index + 1 mustn't overflow")?;
                 self.state = SyncRequesterState::Waiting;
+                self.token = token;
 
                 let mut result = Vec::new();
                 let mut start: usize = 0;
@@ -240,7 +400,7 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                     let end = start
                         .checked_add(len)
                         .assume("start + len mustn't overflow")?;
-                    let payload = &remaining[start..end];
+                    let data = &remaining[start..end];
                     start = end;
 
                     let command = SyncCommand {
@@ -248,7 +408,7 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                         priority: meta.priority,
                         parent: meta.parent,
                         policy,
-                        data: payload,
+                        data,
                         max_cut: meta.max_cut,
                     };
 
@@ -293,6 +453,11 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
                 None
             }
 
+            SyncResponseMessage::RetryLater { retry_after, .. } => {
+                self.state = SyncRequesterState::Closed;
+                return Err(SyncError::RetryLater(retry_after));
+            }
+
             SyncResponseMessage::EndSession { .. } => {
                 self.state = SyncRequesterState::Closed;
                 None
@@ -321,7 +486,13 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
         ))
     }
 
-    fn resume(&mut self, max_bytes: u64, target: &mut [u8]) -> Result<(usize, usize), SyncError> {
+    fn resume(
+        &mut self,
+        max_bytes: u64,
+        target: &mut [u8],
+        provider: &mut impl StorageProvider,
+        heads: &mut PeerCache,
+    ) -> Result<(usize, usize), SyncError> {
         if !matches!(
             self.state,
             SyncRequesterState::Resync | SyncRequesterState::Idle
@@ -329,14 +500,18 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
             return Err(SyncError::SessionState);
         }
 
+        // Without a token there is nothing to resume from, so start over.
+        let Some(token) = self.token.clone() else {
+            self.state = SyncRequesterState::Start;
+            self.next_index = 0;
+            return self.start(max_bytes, target, provider, heads);
+        };
+
         self.state = SyncRequesterState::Waiting;
         let message = SyncType::Poll {
             request: SyncRequestMessage::SyncResume {
                 session_id: self.session_id,
-                response_index: self
-                    .next_index
-                    .checked_sub(1)
-                    .assume("next_index must be positive")?,
+                token,
                 max_bytes,
             },
             address: self.server_address.clone(),
@@ -411,6 +586,49 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
         Ok(commands)
     }
 
+    fn get_filter(
+        &self,
+        provider: &mut impl StorageProvider,
+    ) -> Result<Option<CommandFilter>, SyncError> {
+        if !self.reconcile {
+            return Ok(None);
+        }
+
+        let mut filter = CommandFilter::new();
+        let storage = match provider.get_storage(self.storage_id) {
+            Err(StorageError::NoSuchStorage) => return Ok(Some(filter)),
+            Err(err) => return Err(SyncError::Storage(err)),
+            Ok(storage) => storage,
+        };
+
+        // Add commands to the filter starting from the head, so the
+        // commands the responder is most likely to have are included.
+        let mut count: usize = 0;
+        let mut visited = BTreeSet::new();
+        let mut current = vec![storage.get_head()?];
+        while count < COMMAND_FILTER_MAX && !current.is_empty() {
+            let mut next = vec::Vec::new(); //BUG not constant memory
+
+            'current: for location in current {
+                if !visited.insert(location.segment) {
+                    continue;
+                }
+                let segment = storage.get_segment(location)?;
+                for command in segment.get_from(segment.first_location()).iter().rev() {
+                    filter.insert(command.id());
+                    count = count.checked_add(1).assume("count + 1 mustn't overflow")?;
+                    if count >= COMMAND_FILTER_MAX {
+                        break 'current;
+                    }
+                }
+                next.extend(segment.prior());
+            }
+
+            current = next;
+        }
+        Ok(Some(filter))
+    }
+
     /// Writes a Subscribe message to target.
     pub fn subscribe(
         &mut self,
@@ -448,6 +666,23 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
         provider: &mut impl StorageProvider,
         heads: &mut PeerCache,
     ) -> Result<(usize, usize), SyncError> {
+        self.max_bytes = max_bytes;
+        let (request, sent) = self.start_request(provider, heads)?;
+        let message = SyncType::Poll {
+            request,
+            address: self.server_address.clone(),
+        };
+
+        Ok((Self::write(target, message)?, sent))
+    }
+
+    /// Starts a new session, returning its `SyncRequest` and the number
+    /// of commands sent in the sample.
+    pub(super) fn start_request(
+        &mut self,
+        provider: &mut impl StorageProvider,
+        heads: &mut PeerCache,
+    ) -> Result<(SyncRequestMessage, usize), SyncError> {
         if !matches!(
             self.state,
             SyncRequesterState::Start | SyncRequesterState::New
@@ -457,21 +692,21 @@ impl<A: DeserializeOwned + Serialize + Clone> SyncRequester<'_, A> {
         }
 
         self.state = SyncRequesterState::Start;
-        self.max_bytes = max_bytes;
 
         let commands = self.get_commands(provider, heads)?;
+        let filter = self.get_filter(provider)?;
 
         let sent = commands.len();
-        let message = SyncType::Poll {
-            request: SyncRequestMessage::SyncRequest {
-                session_id: self.session_id,
-                storage_id: self.storage_id,
-                max_bytes,
-                commands,
-            },
-            address: self.server_address.clone(),
+        let request = SyncRequestMessage::SyncRequest {
+            session_id: self.session_id,
+            storage_id: self.storage_id,
+            max_bytes: self.max_bytes,
+            commands,
+            filter,
+            compression: self.compression.clone(),
+            selection: self.selection.clone(),
         };
 
-        Ok((Self::write(target, message)?, sent))
+        Ok((request, sent))
     }
 }
@@ -1,28 +1,43 @@
-use alloc::vec;
-use core::mem;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec};
+use core::{mem, time::Duration};
 
 use buggy::{bug, BugExt};
 use heapless::{Deque, Vec};
 use serde::{Deserialize, Serialize};
 
 use super::{
-    requester::SyncRequestMessage, CommandMeta, SyncError, COMMAND_RESPONSE_MAX,
-    COMMAND_SAMPLE_MAX, MAX_SYNC_MESSAGE_SIZE, PEER_HEAD_MAX, SEGMENT_BUFFER_MAX,
+    compression::{self, Compression},
+    reconcile::CommandFilter,
+    requester::SyncRequestMessage,
+    resume::{PendingSync, SyncToken},
+    select::{Labeler, Selection},
+    CommandMeta, SyncError, SyncLimits, COMMAND_RESPONSE_MAX, COMMAND_SAMPLE_MAX, COMPRESSION_MAX,
+    MAX_SYNC_MESSAGE_SIZE, PEER_HEAD_MAX, SEGMENT_BUFFER_MAX,
 };
 use crate::{
     command::{Address, Command, CommandId},
+    metrics::{Metrics, MetricsHandle},
     storage::{GraphId, Location, Segment, Storage, StorageProvider},
     StorageError, SyncType,
 };
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct PeerCache {
     heads: Vec<Address, { PEER_HEAD_MAX }>,
+    /// The earliest time the peer may be sent more. See [`SyncLimits`].
+    pub(super) next_send: Duration,
+    /// The rest of the last sync with the peer, if it was not finished.
+    /// See [`SyncToken`].
+    pub(super) pending: Option<Box<PendingSync>>,
 }
 
 impl PeerCache {
     pub fn new() -> Self {
-        PeerCache { heads: Vec::new() }
+        PeerCache {
+            heads: Vec::new(),
+            next_send: Duration::ZERO,
+            pending: None,
+        }
     }
 
     pub fn heads(&self) -> &[Address] {
@@ -31,7 +46,7 @@ impl PeerCache {
 
     pub fn add_command<S>(
         &mut self,
-        storage: &mut S,
+        storage: &S,
         command: Address,
         cmd_loc: Location,
     ) -> Result<(), StorageError>
@@ -87,6 +102,11 @@ pub enum SyncResponseMessage {
         index: u64,
         /// Commands that the responder believes the requester does not have.
         commands: Vec<CommandMeta, COMMAND_RESPONSE_MAX>,
+        /// The algorithm used to compress the command data following
+        /// this message, if any.
+        compression: Option<Compression>,
+        /// Where to resume the sync if the responder has more to send.
+        token: Option<SyncToken>,
     },
 
     /// End a sync session if `SyncRequest.max_bytes` has been reached or
@@ -114,6 +134,17 @@ pub enum SyncResponseMessage {
         head: CommandId,
     },
 
+    /// Sent instead of a `SyncResponse` if the responder is over its
+    /// limits for the requester. The requester should send a new
+    /// `SyncRequest` after `retry_after` has passed.
+    RetryLater {
+        /// A random-value produced by a cryptographically secure RNG
+        /// corresponding to the `session_id` in the initial `SyncRequest`.
+        session_id: u128,
+        /// How long the requester should wait.
+        retry_after: Duration,
+    },
+
     /// Message sent by either requester or responder to indicate the session
     /// has been terminated or the `session_id` is no longer valid.
     EndSession { session_id: u128 },
@@ -125,12 +156,13 @@ impl SyncResponseMessage {
             Self::SyncResponse { session_id, .. } => *session_id,
             Self::SyncEnd { session_id, .. } => *session_id,
             Self::Offer { session_id, .. } => *session_id,
+            Self::RetryLater { session_id, .. } => *session_id,
             Self::EndSession { session_id, .. } => *session_id,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 enum SyncResponderState {
     New,
     Start,
@@ -152,10 +184,27 @@ pub struct SyncResponder<A> {
     storage_id: Option<GraphId>,
     state: SyncResponderState,
     bytes_sent: u64,
+    max_bytes: u64,
     next_send: usize,
+    /// The first command to send from the segment at `next_send`, if
+    /// the segment was partly sent.
+    resume_from: Option<Location>,
+    /// The index of the next `SyncResponse`.
+    next_index: u64,
+    resume: Option<SyncToken>,
     has: Vec<Address, COMMAND_SAMPLE_MAX>,
+    filter: Option<CommandFilter>,
+    selection: Option<Selection>,
+    labeler: Option<Arc<dyn Labeler + Send + Sync>>,
+    /// The index of the last command to send in each selected segment.
+    selected: BTreeMap<usize, usize>,
+    accepts: Vec<Compression, COMPRESSION_MAX>,
+    compression: Option<Compression>,
+    limits: Option<SyncLimits>,
+    now: Duration,
     to_send: Vec<Location, SEGMENT_BUFFER_MAX>,
     server_address: A,
+    metrics: MetricsHandle,
 }
 
 impl<A: Serialize + Clone> SyncResponder<A> {
@@ -166,13 +215,58 @@ impl<A: Serialize + Clone> SyncResponder<A> {
             storage_id: None,
             state: SyncResponderState::New,
             bytes_sent: 0,
+            max_bytes: 0,
             next_send: 0,
+            resume_from: None,
+            next_index: 0,
+            resume: None,
             has: Vec::new(),
+            filter: None,
+            selection: None,
+            labeler: None,
+            selected: BTreeMap::new(),
+            accepts: Vec::new(),
+            compression: None,
+            limits: None,
+            now: Duration::ZERO,
             to_send: Vec::new(),
             server_address,
+            metrics: MetricsHandle::new(),
         }
     }
 
+    /// Allows compressing command data for this peer with any of
+    /// `algorithms` the requester offers. Algorithms this build does not
+    /// support are ignored.
+    pub fn with_compression(mut self, algorithms: &[Compression]) -> Self {
+        self.accepts = compression::supported(algorithms);
+        self
+    }
+
+    /// Uses `labeler` to honor the requester's [`Selection`], if it
+    /// sends one. Without a labeler, every command is sent.
+    pub fn with_labeler(mut self, labeler: Arc<dyn Labeler + Send + Sync>) -> Self {
+        self.labeler = Some(labeler);
+        self
+    }
+
+    /// Reports the bytes of sync messages sent into `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics.set(metrics);
+        self
+    }
+
+    /// Applies `limits` to the peer.
+    ///
+    /// `now` is the current time on a monotonic clock, such as the time
+    /// since startup. The same clock must be used for every sync with
+    /// the peer.
+    pub fn with_limits(mut self, limits: SyncLimits, now: Duration) -> Self {
+        self.limits = Some(limits);
+        self.now = now;
+        self
+    }
+
     /// Returns true if [`Self::poll`] would produce a message.
     pub fn ready(&self) -> bool {
         use SyncResponderState::*;
@@ -184,11 +278,64 @@ impl<A: Serialize + Clone> SyncResponder<A> {
 
     /// Write a sync message in to the target buffer. Returns the number
     /// of bytes written.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(session_id = ?self.session_id))
+    )]
     pub fn poll(
         &mut self,
         target: &mut [u8],
         provider: &mut impl StorageProvider,
         response_cache: &mut PeerCache,
+    ) -> Result<usize, SyncError> {
+        use SyncResponderState as S;
+        let storage = match (self.state, self.storage_id) {
+            (S::Start | S::Send, Some(storage_id)) => match provider.get_storage(storage_id) {
+                Ok(s) => Some(&*s),
+                Err(e) => {
+                    self.state = S::Reset;
+                    return Err(e.into());
+                }
+            },
+            _ => None,
+        };
+        let length = self.poll_message(target, storage, response_cache)?;
+        self.metrics.get().sync_bytes_sent(length);
+        Ok(length)
+    }
+
+    /// Like [`Self::poll`], but reads the graph from `storage`, which must be the graph
+    /// named by [`Self::storage_id`].
+    ///
+    /// The graph is only borrowed immutably, so a host can serve many requesters
+    /// concurrently from one shared graph, such as a hub which relays a graph between
+    /// peers. Each requester needs its own responder and [`PeerCache`]. The graph must
+    /// not be changed while a response is being written.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(session_id = ?self.session_id))
+    )]
+    pub fn poll_storage(
+        &mut self,
+        target: &mut [u8],
+        storage: &impl Storage,
+        response_cache: &mut PeerCache,
+    ) -> Result<usize, SyncError> {
+        let length = self.poll_message(target, Some(storage), response_cache)?;
+        self.metrics.get().sync_bytes_sent(length);
+        Ok(length)
+    }
+
+    /// Returns the graph the requester is syncing, once a request has been received.
+    pub fn storage_id(&self) -> Option<GraphId> {
+        self.storage_id
+    }
+
+    fn poll_message(
+        &mut self,
+        target: &mut [u8],
+        storage: Option<&impl Storage>,
+        response_cache: &mut PeerCache,
     ) -> Result<usize, SyncError> {
         use SyncResponderState as S;
         let length = match self.state {
@@ -196,31 +343,62 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                 return Err(SyncError::NotReady);
             }
             S::Start => {
-                let Some(storage_id) = self.storage_id else {
+                let (Some(_), Some(storage)) = (self.storage_id, storage) else {
                     self.state = S::Reset;
                     bug!("poll called before storage_id was set");
                 };
 
-                let storage = match provider.get_storage(storage_id) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        self.state = S::Reset;
-                        return Err(e.into());
-                    }
-                };
+                if let Some(retry_after) = self.retry_after(response_cache) {
+                    self.state = S::Stopped;
+                    let message = SyncResponseMessage::RetryLater {
+                        session_id: self.session_id()?,
+                        retry_after,
+                    };
+                    return Self::write(target, message);
+                }
 
                 self.state = S::Send;
-                for command in &self.has {
-                    // We only need to check commands that are a part of our graph.
-                    if let Some(cmd_loc) = storage.get_location(*command)? {
-                        response_cache.add_command(storage, *command, cmd_loc)?;
+                if let Some(token) = self.resume.take() {
+                    if !self.restore(&token, response_cache) {
+                        // The rest of the sync is gone, so the requester
+                        // must start over.
+                        self.state = S::Stopped;
+                        let message = SyncResponseMessage::EndSession {
+                            session_id: self.session_id()?,
+                        };
+                        return Self::write(target, message);
                     }
+                } else {
+                    for command in &self.has {
+                        // We only need to check commands that are a part of our graph.
+                        if let Some(cmd_loc) = storage.get_location(*command)? {
+                            response_cache.add_command(storage, *command, cmd_loc)?;
+                        }
+                    }
+                    self.to_send = SyncResponder::<A>::find_needed_segments(
+                        &self.has,
+                        self.filter.as_ref(),
+                        storage,
+                    )?;
+                    self.select(storage)?;
+                    self.limit_segments();
                 }
-                self.to_send = SyncResponder::<A>::find_needed_segments(&self.has, storage)?;
 
-                self.get_next(target, provider)?
+                let length = self.get_next(target, storage)?;
+                self.record_sent(response_cache, length);
+                self.save_pending(response_cache)?;
+                length
+            }
+            S::Send => {
+                let Some(storage) = storage else {
+                    self.state = S::Reset;
+                    bug!("poll called before storage_id was set");
+                };
+                let length = self.get_next(target, storage)?;
+                self.record_sent(response_cache, length);
+                self.save_pending(response_cache)?;
+                length
             }
-            S::Send => self.get_next(target, provider)?,
             S::Reset => {
                 self.state = S::Stopped;
                 let message = SyncResponseMessage::EndSession {
@@ -234,6 +412,10 @@ impl<A: Serialize + Clone> SyncResponder<A> {
     }
 
     /// Receive a sync message. Updates the responders state for later polling.
+    #[cfg_attr(
+        feature = "instrument",
+        tracing::instrument(skip_all, fields(session_id = %message.session_id()))
+    )]
     pub fn receive(&mut self, message: SyncRequestMessage) -> Result<(), SyncError> {
         if self.session_id.is_none() {
             self.session_id = Some(message.session_id());
@@ -247,21 +429,39 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                 storage_id,
                 max_bytes,
                 commands,
+                filter,
+                compression,
+                selection,
                 ..
             } => {
                 self.state = SyncResponderState::Start;
                 self.storage_id = Some(storage_id);
                 self.bytes_sent = max_bytes;
+                self.max_bytes = max_bytes;
                 self.to_send = Vec::new();
                 self.has = commands;
+                self.filter = filter;
+                self.selection = selection;
+                self.compression = compression::negotiate(&compression, &self.accepts);
                 self.next_send = 0;
+                self.resume_from = None;
+                self.next_index = 0;
+                self.resume = None;
                 return Ok(());
             }
             SyncRequestMessage::RequestMissing { .. } => {
                 todo!()
             }
-            SyncRequestMessage::SyncResume { .. } => {
-                todo!()
+            SyncRequestMessage::SyncResume {
+                token, max_bytes, ..
+            } => {
+                self.state = SyncResponderState::Start;
+                self.storage_id = Some(token.storage_id);
+                self.bytes_sent = max_bytes;
+                self.max_bytes = max_bytes;
+                self.compression = None;
+                self.resume = Some(token);
+                return Ok(());
             }
             SyncRequestMessage::EndSession { .. } => {
                 self.state = SyncResponderState::Stopped;
@@ -281,6 +481,7 @@ impl<A: Serialize + Clone> SyncResponder<A> {
 
     fn find_needed_segments(
         commands: &[Address],
+        filter: Option<&CommandFilter>,
         storage: &impl Storage,
     ) -> Result<Vec<Location, SEGMENT_BUFFER_MAX>, SyncError> {
         let mut have_locations = vec::Vec::new(); //BUG: not constant size
@@ -295,8 +496,9 @@ impl<A: Serialize + Clone> SyncResponder<A> {
             have_locations.push(location);
         }
 
+        let head = storage.get_head()?;
         let mut heads = vec::Vec::new();
-        heads.push(storage.get_head()?);
+        heads.push(head);
 
         let mut result: Deque<Location, SEGMENT_BUFFER_MAX> = Deque::new();
 
@@ -308,6 +510,24 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                     continue 'heads;
                 }
 
+                // The requester has every command in and before a
+                // segment whose head is in its filter.
+                if let Some(filter) = filter {
+                    if filter.contains(segment.head()?.id()) {
+                        // A false positive on the graph's head would
+                        // leave the requester behind without an error,
+                        // so the head is sent unless the requester's
+                        // sample shows it has it.
+                        if segment.head_location() == head && !have_locations.contains(&head) {
+                            if result.is_full() {
+                                result.pop_back();
+                            }
+                            result.push_front(head).ok().assume("too many segments")?;
+                        }
+                        continue 'heads;
+                    }
+                }
+
                 for &location in &have_locations {
                     if segment.contains(location) {
                         if location != segment.head_location() {
@@ -345,33 +565,38 @@ impl<A: Serialize + Clone> SyncResponder<A> {
         Ok(r)
     }
 
-    fn get_next(
-        &mut self,
-        target: &mut [u8],
-        provider: &mut impl StorageProvider,
-    ) -> Result<usize, SyncError> {
+    fn get_next(&mut self, target: &mut [u8], storage: &impl Storage) -> Result<usize, SyncError> {
         if self.next_send >= self.to_send.len() {
             self.state = SyncResponderState::Idle;
             return Ok(0);
         }
-        let (commands, command_data, index) = self.get_commands(provider)?;
+        let (commands, command_data, index) = self.get_commands(storage)?;
+        let compressed = self.compress(&command_data)?;
 
+        self.next_send = index;
         let message = SyncResponseMessage::SyncResponse {
             session_id: self.session_id()?,
-            index: self.next_send as u64,
+            index: self.next_index,
             commands,
+            compression: compressed.as_ref().map(|(alg, _)| *alg),
+            token: self.token()?,
         };
-
-        self.next_send = index;
+        let payload = compressed
+            .as_ref()
+            .map_or(&command_data[..], |(_, data)| data.as_slice());
+        self.next_index = self
+            .next_index
+            .checked_add(1)
+            .assume("next_index + 1 mustn't overflow")?;
 
         let length = Self::write(target, message)?;
         let total_length = length
-            .checked_add(command_data.len())
-            .assume("length + command_data_length mustn't overflow")?;
+            .checked_add(payload.len())
+            .assume("length + payload_length mustn't overflow")?;
         target
             .get_mut(length..total_length)
             .assume("sync message fits in target")?
-            .copy_from_slice(&command_data);
+            .copy_from_slice(payload);
         Ok(total_length)
     }
 
@@ -396,15 +621,26 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                 return Err(e.into());
             }
         };
-        self.to_send = SyncResponder::<A>::find_needed_segments(&self.has, storage)?;
-        let (commands, command_data, index) = self.get_commands(provider)?;
-        let storage = match provider.get_storage(storage_id) {
-            Ok(s) => s,
-            Err(e) => {
-                self.state = S::Reset;
-                return Err(e.into());
-            }
-        };
+        self.push_storage(target, &*storage, response_cache)
+    }
+
+    /// Like [`Self::push`], but reads the graph from `storage`. See
+    /// [`Self::poll_storage`].
+    pub fn push_storage(
+        &mut self,
+        target: &mut [u8],
+        storage: &impl Storage,
+        response_cache: &mut PeerCache,
+    ) -> Result<usize, SyncError> {
+        // The peer will be sent any new commands with the next push.
+        if self.retry_after(response_cache).is_some() {
+            return Ok(0);
+        }
+        self.to_send =
+            SyncResponder::<A>::find_needed_segments(&self.has, self.filter.as_ref(), storage)?;
+        self.select(storage)?;
+        self.limit_segments();
+        let (commands, command_data, index) = self.get_commands(storage)?;
         for command in &commands {
             if let Some(cmd_loc) = storage.get_location(command.address())? {
                 response_cache.add_command(storage, command.address(), cmd_loc)?;
@@ -412,33 +648,44 @@ impl<A: Serialize + Clone> SyncResponder<A> {
         }
         let mut length = 0;
         if !commands.is_empty() {
+            let compressed = self.compress(&command_data)?;
+            let payload = compressed
+                .as_ref()
+                .map_or(&command_data[..], |(_, data)| data.as_slice());
             let message = SyncType::Push {
                 message: SyncResponseMessage::SyncResponse {
                     session_id: self.session_id()?,
-                    index: self.next_send as u64,
+                    index: self.next_index,
                     commands,
+                    compression: compressed.as_ref().map(|(alg, _)| *alg),
+                    token: None,
                 },
                 storage_id: self.storage_id.assume("storage id must exist")?,
                 address: self.server_address.clone(),
             };
             self.next_send = index;
+            self.next_index = self
+                .next_index
+                .checked_add(1)
+                .assume("next_index + 1 mustn't overflow")?;
 
             length = Self::write_sync_type(target, message)?;
             let total_length = length
-                .checked_add(command_data.len())
-                .assume("length + command_data_length mustn't overflow")?;
+                .checked_add(payload.len())
+                .assume("length + payload_length mustn't overflow")?;
             target
                 .get_mut(length..total_length)
                 .assume("sync message fits in target")?
-                .copy_from_slice(&command_data);
+                .copy_from_slice(payload);
             length = total_length;
+            self.record_sent(response_cache, length);
         }
         Ok(length)
     }
 
     fn get_commands(
         &mut self,
-        provider: &mut impl StorageProvider,
+        storage: &impl Storage,
     ) -> Result<
         (
             Vec<CommandMeta, COMMAND_RESPONSE_MAX>,
@@ -447,21 +694,15 @@ impl<A: Serialize + Clone> SyncResponder<A> {
         ),
         SyncError,
     > {
-        let Some(storage_id) = self.storage_id.as_ref() else {
+        if self.storage_id.is_none() {
             self.state = SyncResponderState::Reset;
             bug!("get_next called before storage_id was set");
-        };
-        let storage = match provider.get_storage(*storage_id) {
-            Ok(s) => s,
-            Err(e) => {
-                self.state = SyncResponderState::Reset;
-                return Err(e.into());
-            }
-        };
+        }
         let mut commands: Vec<CommandMeta, COMMAND_RESPONSE_MAX> = Vec::new();
         let mut command_data: Vec<u8, MAX_SYNC_MESSAGE_SIZE> = Vec::new();
         let mut index = self.next_send;
-        for i in self.next_send..self.to_send.len() {
+        let mut resume_from = self.resume_from.take();
+        'segments: for i in self.next_send..self.to_send.len() {
             if commands.is_full() {
                 break;
             }
@@ -470,14 +711,66 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                 self.state = SyncResponderState::Reset;
                 bug!("send index OOB");
             };
+            // Continue a segment which was partly sent.
+            let location = match resume_from.take() {
+                Some(next) if next.segment == location.segment => next,
+                _ => location,
+            };
 
             let segment = storage
                 .get_segment(location)
                 .inspect_err(|_| self.state = SyncResponderState::Reset)?;
 
-            let found = segment.get_from(location);
+            let mut found = segment.get_from(location);
+            if let Some(last) = self.selected.get(&location.segment) {
+                // The requester may already have every needed command.
+                let len = last
+                    .checked_add(1)
+                    .assume("last + 1 mustn't overflow")?
+                    .saturating_sub(location.command);
+                found.truncate(len);
+            }
+
+            // Skip the commands the requester has, which are every
+            // command up to the last one in its filter. The graph's
+            // head is always sent, see `find_needed_segments`.
+            let mut start = 0;
+            if let Some(filter) = &self.filter {
+                let head = storage.get_command_id(storage.get_head()?)?;
+                if let Some(last) = found
+                    .iter()
+                    .rposition(|c| c.id() != head && filter.contains(c.id()))
+                {
+                    start = last.checked_add(1).assume("last + 1 mustn't overflow")?;
+                }
+            }
+
+            for (n, command) in found.get(start..).unwrap_or_default().iter().enumerate() {
+                // Stop once the response is full or the requester's
+                // budget is spent, but always send at least one command.
+                let size = command
+                    .policy()
+                    .map_or(0, <[u8]>::len)
+                    .checked_add(command.bytes().len())
+                    .and_then(|size| size.checked_add(command_data.len()))
+                    .assume("command size mustn't overflow")?;
+                if commands.is_full()
+                    || (self.max_bytes > 0 && !commands.is_empty() && size as u64 > self.max_bytes)
+                {
+                    // The rest of the segment is sent next.
+                    let command = location
+                        .command
+                        .checked_add(start)
+                        .and_then(|c| c.checked_add(n))
+                        .assume("command index mustn't overflow")?;
+                    self.resume_from = Some(Location {
+                        segment: location.segment,
+                        command,
+                    });
+                    index = i;
+                    break 'segments;
+                }
 
-            for command in &found {
                 let mut policy_length = 0;
 
                 if let Some(policy) = command.policy() {
@@ -503,20 +796,323 @@ impl<A: Serialize + Clone> SyncResponder<A> {
                     max_cut: command.max_cut()?,
                 };
 
-                // FIXME(jdygert): Handle segments with more than COMMAND_RESPONSE_MAX commands.
                 commands
                     .push(meta)
                     .ok()
                     .assume("too many commands in segment")?;
-                if commands.is_full() {
-                    break;
-                }
             }
         }
         Ok((commands, command_data, index))
     }
 
+    /// Returns the token to resume from after the response being sent,
+    /// or `None` if the sync is finished.
+    fn token(&self) -> Result<Option<SyncToken>, SyncError> {
+        let next = match self.resume_from {
+            Some(next) => next,
+            None => match self.to_send.get(self.next_send) {
+                Some(&next) => next,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(SyncToken {
+            session_id: self.session_id()?,
+            storage_id: self.storage_id.assume("storage id must exist")?,
+            index: self.next_index,
+            next,
+        }))
+    }
+
+    /// Keeps the rest of an unfinished sync in the peer's cache so it
+    /// can be resumed, or drops it once the sync is finished.
+    fn save_pending(&self, peer: &mut PeerCache) -> Result<(), SyncError> {
+        let session_id = self.session_id()?;
+        let finished = self.resume_from.is_none() && self.next_send >= self.to_send.len();
+        if finished {
+            if peer
+                .pending
+                .as_ref()
+                .is_some_and(|p| p.session_id == session_id)
+            {
+                peer.pending = None;
+            }
+        } else if !peer
+            .pending
+            .as_ref()
+            .is_some_and(|p| p.session_id == session_id)
+        {
+            peer.pending = Some(Box::new(PendingSync {
+                session_id,
+                storage_id: self.storage_id.assume("storage id must exist")?,
+                to_send: self.to_send.clone(),
+                filter: self.filter.clone(),
+                selected: self.selected.clone(),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Restores the rest of the sync `token` was issued for. Returns
+    /// false if the peer's cache no longer has it.
+    fn restore(&mut self, token: &SyncToken, peer: &PeerCache) -> bool {
+        let Some(pending) = peer.pending.as_deref().filter(|p| p.matches(token)) else {
+            return false;
+        };
+        let Some(next_send) = pending
+            .to_send
+            .iter()
+            .position(|l| l.segment == token.next.segment)
+        else {
+            return false;
+        };
+        self.to_send = pending.to_send.clone();
+        self.filter = pending.filter.clone();
+        self.selected = pending.selected.clone();
+        self.next_send = next_send;
+        self.resume_from = Some(token.next);
+        self.next_index = token.index.saturating_add(1);
+        true
+    }
+
+    /// Returns how long the peer must wait if the responder is over its
+    /// limits.
+    fn retry_after(&self, peer: &PeerCache) -> Option<Duration> {
+        self.limits.as_ref()?;
+        peer.retry_after(self.now)
+    }
+
+    /// Records that `len` bytes were sent to the peer.
+    fn record_sent(&self, peer: &mut PeerCache, len: usize) {
+        if let Some(limits) = &self.limits {
+            limits.sent(peer, self.now, len);
+        }
+    }
+
+    /// Drops the commands which are neither selected by the requester
+    /// nor ancestors of a selected command.
+    fn select(&mut self, storage: &impl Storage) -> Result<(), SyncError> {
+        self.selected.clear();
+        let (Some(selection), Some(labeler)) = (&self.selection, &self.labeler) else {
+            return Ok(());
+        };
+
+        // Segments are sorted ancestors first, so walking them in
+        // reverse finds each selected command before its ancestors.
+        let mut needed = BTreeMap::new();
+        for &location in self.to_send.iter().rev() {
+            let segment = storage.get_segment(location)?;
+            let found = segment.get_from(location);
+            let mut last = needed.get(&location.segment).copied();
+            if let Some(i) = found
+                .iter()
+                .rposition(|c| selection.matches(labeler.as_ref(), c.bytes()))
+            {
+                let i = location
+                    .command
+                    .checked_add(i)
+                    .assume("command index mustn't overflow")?;
+                last = Some(last.map_or(i, |last: usize| last.max(i)));
+            }
+            let Some(last) = last else {
+                continue;
+            };
+            needed.insert(location.segment, last);
+
+            // The requester has the ancestors of a partial segment.
+            if location.command == 0 {
+                for prior in segment.prior() {
+                    let entry = needed.entry(prior.segment).or_insert(prior.command);
+                    *entry = (*entry).max(prior.command);
+                }
+            }
+        }
+
+        self.to_send.retain(|l| needed.contains_key(&l.segment));
+        self.selected = needed;
+        Ok(())
+    }
+
+    /// Drops the segments over the limit for a single sync. Segments
+    /// are sorted so that ancestors are kept.
+    fn limit_segments(&mut self) {
+        if let Some(max) = self.limits.as_ref().and_then(|l| l.max_segments) {
+            self.to_send.truncate(max);
+        }
+    }
+
+    /// Compresses `command_data` with the negotiated algorithm, unless
+    /// that would not make it smaller.
+    fn compress(
+        &self,
+        command_data: &[u8],
+    ) -> Result<Option<(Compression, vec::Vec<u8>)>, SyncError> {
+        let Some(alg) = self.compression else {
+            return Ok(None);
+        };
+        let compressed = compression::compress(alg, command_data)?;
+        Ok((compressed.len() < command_data.len()).then_some((alg, compressed)))
+    }
+
     fn session_id(&self) -> Result<u128, SyncError> {
         Ok(self.session_id.assume("session id is set")?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec as StdVec;
+    use std::thread;
+
+    use aranya_crypto::Rng;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        ClientError, ClientState, NullSink, SyncRequester,
+    };
+
+    type Client = ClientState<TestEngine, MemStorageProvider>;
+
+    fn head(client: &mut Client, storage_id: GraphId) -> CommandId {
+        let storage = client.provider().get_storage(storage_id).unwrap();
+        storage.get_command_id(storage.get_head().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_responders() {
+        let mut hub = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = hub
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 0..3 {
+            hub.action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+
+        let mut peers: StdVec<Client> = (0..3)
+            .map(|_| ClientState::new(TestEngine::new(), MemStorageProvider::new()))
+            .collect();
+        let mut requesters = StdVec::new();
+        let mut requests = StdVec::new();
+        for peer in &mut peers {
+            let mut requester = SyncRequester::new(storage_id, &mut Rng, ());
+            let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+            let (len, _) = requester
+                .poll(&mut buffer, peer.provider(), &mut PeerCache::new())
+                .unwrap();
+            let SyncType::Poll { request, .. } =
+                postcard::from_bytes::<SyncType<()>>(&buffer[..len]).unwrap()
+            else {
+                panic!("expected a poll")
+            };
+            requesters.push(requester);
+            requests.push(request);
+        }
+
+        // Every requester is served at once from the same graph.
+        let storage = &*hub.provider().get_storage(storage_id).unwrap();
+        let responses: StdVec<_> = thread::scope(|s| {
+            let handles: StdVec<_> = requests
+                .into_iter()
+                .map(|request| {
+                    s.spawn(move || {
+                        let mut responder = SyncResponder::new(());
+                        responder.receive(request).unwrap();
+                        assert_eq!(responder.storage_id(), Some(storage_id));
+                        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+                        let len = responder
+                            .poll_storage(&mut buffer, storage, &mut PeerCache::new())
+                            .unwrap();
+                        buffer.truncate(len);
+                        buffer
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let expected = head(&mut hub, storage_id);
+        for ((peer, requester), response) in peers.iter_mut().zip(&mut requesters).zip(responses) {
+            let cmds = requester.receive(&response).unwrap().unwrap();
+            let mut trx = peer.transaction(storage_id);
+            peer.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())
+                .unwrap();
+            peer.commit(&mut trx, &mut NullSink).unwrap();
+            assert_eq!(head(peer, storage_id), expected);
+        }
+    }
+
+    /// Syncs `peer` from `hub`, returning the number of commands
+    /// received. If `false_positive` is given, it is added to the
+    /// requester's filter.
+    fn sync(
+        storage_id: GraphId,
+        hub: &mut Client,
+        peer: &mut Client,
+        false_positive: Option<CommandId>,
+    ) -> Result<usize, ClientError> {
+        let mut requester = SyncRequester::new(storage_id, &mut Rng, ());
+        if false_positive.is_some() {
+            requester = requester.with_reconciliation();
+        }
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester
+            .poll(&mut buffer, peer.provider(), &mut PeerCache::new())
+            .unwrap();
+        let SyncType::Poll { mut request, .. } =
+            postcard::from_bytes::<SyncType<()>>(&buffer[..len]).unwrap()
+        else {
+            panic!("expected a poll")
+        };
+        if let (
+            Some(id),
+            SyncRequestMessage::SyncRequest {
+                filter: Some(filter),
+                ..
+            },
+        ) = (false_positive, &mut request)
+        {
+            filter.insert(id);
+        }
+
+        let mut responder = SyncResponder::new(());
+        responder.receive(request).unwrap();
+        let len = responder
+            .poll(&mut buffer, hub.provider(), &mut PeerCache::new())
+            .unwrap();
+        if len == 0 {
+            return Ok(0);
+        }
+        let cmds = requester.receive(&buffer[..len]).unwrap().unwrap();
+        let mut trx = peer.transaction(storage_id);
+        peer.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())?;
+        peer.commit(&mut trx, &mut NullSink)?;
+        Ok(cmds.len())
+    }
+
+    #[test]
+    fn test_head_false_positive() {
+        let mut hub = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = hub
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        let mut peer = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        assert_eq!(sync(storage_id, &mut hub, &mut peer, None).unwrap(), 1);
+
+        for i in 0..2 {
+            hub.action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let expected = head(&mut hub, storage_id);
+
+        // The head is sent even though the filter says the peer has
+        // it, so the peer finds out it is missing the head's parent.
+        let err = sync(storage_id, &mut hub, &mut peer, Some(expected))
+            .expect_err("should be missing the head's parent");
+        assert!(matches!(err, ClientError::NoSuchParent(_)), "{err}");
+
+        assert_eq!(sync(storage_id, &mut hub, &mut peer, None).unwrap(), 2);
+        assert_eq!(head(&mut peer, storage_id), expected);
+    }
+}
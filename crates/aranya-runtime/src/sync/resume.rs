@@ -0,0 +1,146 @@
+//! Resuming interrupted syncs.
+//!
+//! A responder which has more to send after a `SyncResponse` includes a
+//! [`SyncToken`] with it, and keeps the rest of the sync in the
+//! requester's [`PeerCache`](super::PeerCache). If the transfer is
+//! interrupted, the requester can continue from its latest token with
+//! [`SyncRequester::from_token`](super::SyncRequester::from_token)
+//! instead of starting over, even on a new connection.
+
+use alloc::collections::BTreeMap;
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use super::{reconcile::CommandFilter, SEGMENT_BUFFER_MAX};
+use crate::{GraphId, Location};
+
+/// Where to resume a sync, issued by the responder.
+///
+/// Tokens are only valid with the responder which issued them, and only
+/// until it starts another sync with the requester.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncToken {
+    /// The session being resumed.
+    pub(super) session_id: u128,
+    /// The graph being synced.
+    pub(super) storage_id: GraphId,
+    /// The index of the response which issued the token.
+    pub(super) index: u64,
+    /// The first command which has not been sent.
+    pub(super) next: Location,
+}
+
+impl SyncToken {
+    /// Returns the graph being synced.
+    pub fn storage_id(&self) -> GraphId {
+        self.storage_id
+    }
+}
+
+/// The rest of a sync which the responder has not sent.
+#[derive(Clone, Debug)]
+pub(super) struct PendingSync {
+    pub(super) session_id: u128,
+    pub(super) storage_id: GraphId,
+    pub(super) to_send: Vec<Location, SEGMENT_BUFFER_MAX>,
+    pub(super) filter: Option<CommandFilter>,
+    pub(super) selected: BTreeMap<usize, usize>,
+}
+
+impl PendingSync {
+    /// Reports whether `token` was issued for this sync.
+    pub(super) fn matches(&self, token: &SyncToken) -> bool {
+        self.session_id == token.session_id && self.storage_id == token.storage_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aranya_crypto::Rng;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::{memory::MemStorageProvider, Segment, Storage, StorageProvider},
+        ClientState, Command, CommandId, NullSink, PeerCache, SyncRequester, SyncResponder,
+        SyncType, MAX_SYNC_MESSAGE_SIZE,
+    };
+
+    type Client = ClientState<TestEngine, MemStorageProvider>;
+
+    /// Sends the requester's next message to a new responder, as if
+    /// on a new connection, and adds the commands it sends to `to`.
+    /// Returns the number of commands received.
+    fn exchange(
+        requester: &mut SyncRequester<'_, ()>,
+        from: &mut Client,
+        to: &mut Client,
+        cache: &mut PeerCache,
+    ) -> usize {
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester
+            .poll(&mut buffer, to.provider(), &mut PeerCache::new())
+            .unwrap();
+        let SyncType::Poll { request, .. } =
+            postcard::from_bytes::<SyncType<()>>(&buffer[..len]).unwrap()
+        else {
+            panic!("expected a poll")
+        };
+
+        let mut responder = SyncResponder::new(());
+        responder.receive(request).unwrap();
+        let len = responder.poll(&mut buffer, from.provider(), cache).unwrap();
+
+        let storage_id = requester.storage_id();
+        let Some(cmds) = requester.receive(&buffer[..len]).unwrap() else {
+            return 0;
+        };
+        let mut trx = to.transaction(storage_id);
+        to.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())
+            .unwrap();
+        to.commit(&mut trx, &mut NullSink).unwrap();
+        cmds.len()
+    }
+
+    fn head_id(client: &mut Client, storage_id: GraphId) -> CommandId {
+        let storage = client.provider().get_storage(storage_id).unwrap();
+        let segment = storage.get_segment(storage.get_head().unwrap()).unwrap();
+        segment.head().unwrap().id()
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut from = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let storage_id = from
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 0..6 {
+            from.action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+        let mut to = ClientState::new(TestEngine::new(), MemStorageProvider::new());
+        let mut cache = PeerCache::new();
+
+        // Only one command fits in each response.
+        let mut requester = SyncRequester::new(storage_id, &mut Rng, ()).with_max_bytes(1);
+        assert_eq!(exchange(&mut requester, &mut from, &mut to, &mut cache), 1);
+        let token = requester
+            .token()
+            .cloned()
+            .expect("should have more to send");
+        assert_eq!(token.storage_id(), storage_id);
+
+        // The transfer is interrupted, so resume it.
+        let mut requester = SyncRequester::from_token(token.clone(), ());
+        assert_eq!(exchange(&mut requester, &mut from, &mut to, &mut cache), 6);
+        assert!(requester.token().is_none());
+        assert!(cache.pending.is_none());
+        assert_eq!(head_id(&mut from, storage_id), head_id(&mut to, storage_id));
+
+        // Once the sync is finished, the token can't be used again.
+        let mut requester = SyncRequester::from_token(token, ());
+        assert_eq!(exchange(&mut requester, &mut from, &mut to, &mut cache), 0);
+        assert!(!requester.ready());
+    }
+}
@@ -0,0 +1,50 @@
+//! Selective sync.
+//!
+//! A requester can send a [`Selection`] of labels to receive only the
+//! commands with those labels. The responder sends the selected
+//! commands along with all of their ancestors, so every command the
+//! requester holds has a complete, verified history, but skips the
+//! commands that no selected command depends on. Labels are assigned by
+//! the responder's [`Labeler`].
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use super::{SyncError, SELECTION_LABEL_MAX, SELECTION_MAX};
+
+/// Assigns labels to commands for selective sync.
+pub trait Labeler {
+    /// Reports whether the command with serialized `bytes` has `label`.
+    fn has_label(&self, bytes: &[u8], label: &str) -> bool;
+}
+
+/// The labels of the commands a requester wants to receive.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Selection {
+    labels: Vec<String<SELECTION_LABEL_MAX>, SELECTION_MAX>,
+}
+
+impl Selection {
+    /// Creates an empty selection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `label` to the selection.
+    pub fn insert(&mut self, label: &str) -> Result<(), SyncError> {
+        let label = String::try_from(label).map_err(|()| SyncError::SelectionOverflow)?;
+        if !self.labels.contains(&label) {
+            self.labels
+                .push(label)
+                .map_err(|_| SyncError::SelectionOverflow)?;
+        }
+        Ok(())
+    }
+
+    /// Reports whether the command with serialized `bytes` is selected.
+    pub(super) fn matches(&self, labeler: &dyn Labeler, bytes: &[u8]) -> bool {
+        self.labels
+            .iter()
+            .any(|label| labeler.has_label(bytes, label))
+    }
+}
@@ -0,0 +1,509 @@
+//! A reference sync transport over TCP.
+//!
+//! Each sync opens a new connection to the responder. Once both sides
+//! have run their [`Authenticator`], the requester sends one frame
+//! holding a [`SyncType::Poll`] and the responder replies with one frame
+//! holding its response, which is empty if the requester is up to date.
+//! Several graphs can be synced over one connection with a
+//! [`SyncType::MultiPoll`], and a sync can be resumed on a new
+//! connection with a requester from [`SyncRequester::from_token`]. A frame is a big-endian `u32` length
+//! followed by that many bytes.
+//!
+//! Subscriptions are not supported. For QUIC, see the
+//! `aranya-quic-syncer` crate.
+
+#![cfg(feature = "tcp")]
+#![cfg_attr(docsrs, doc(cfg(feature = "tcp")))]
+
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    respond_multi, ClientError, ClientState, Compression, Engine, GraphId, MultiSyncRequester,
+    PeerCache, Sink, StorageProvider, SyncError, SyncRequestMessage, SyncRequester, SyncResponder,
+    SyncType, MAX_MULTI_SYNC_MESSAGE_SIZE, MAX_SYNC_MESSAGE_SIZE,
+};
+
+/// An error returned by an [`Authenticator`].
+pub type AuthError = Box<dyn core::error::Error + Send + Sync>;
+
+/// Authenticates peers before any sync messages are exchanged.
+///
+/// Each method is given the new connection, so it can run a handshake
+/// with the peer (for example, signing a challenge with a device key)
+/// using [`read_frame`] and [`write_frame`]. Returning an error closes
+/// the connection.
+pub trait Authenticator {
+    /// Authenticates the responder at `peer` on an outgoing connection.
+    fn connect(&mut self, stream: &mut TcpStream, peer: SocketAddr) -> Result<(), AuthError>;
+
+    /// Authenticates the requester at `peer` on an incoming connection.
+    fn accept(&mut self, stream: &mut TcpStream, peer: SocketAddr) -> Result<(), AuthError>;
+}
+
+/// An [`Authenticator`] which accepts every peer.
+///
+/// Only use this on a network where every peer is trusted.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoAuth;
+
+impl Authenticator for NoAuth {
+    fn connect(&mut self, _stream: &mut TcpStream, _peer: SocketAddr) -> Result<(), AuthError> {
+        Ok(())
+    }
+
+    fn accept(&mut self, _stream: &mut TcpStream, _peer: SocketAddr) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+
+/// An error returned by the TCP transport.
+#[derive(Debug, thiserror::Error)]
+pub enum TcpSyncError {
+    /// A sync protocol error.
+    #[error("sync error: {0}")]
+    Sync(#[from] SyncError),
+    /// An error adding commands to the client.
+    #[error("client error: {0}")]
+    Client(#[from] ClientError),
+    /// An error reading from or writing to the connection.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    /// An error serializing or deserializing a message.
+    #[error("serialize error: {0}")]
+    Serialize(#[from] postcard::Error),
+    /// The peer failed authentication.
+    #[error("authentication failed: {0}")]
+    Auth(#[source] AuthError),
+    /// A frame is larger than a sync message can be.
+    #[error("frame of {0} bytes is too large")]
+    FrameTooLarge(usize),
+    /// The peer sent a message this transport does not handle.
+    #[error("unsupported sync message")]
+    Unsupported,
+}
+
+/// Writes `data` to `w` as a single frame.
+pub fn write_frame<W: Write>(w: &mut W, data: &[u8]) -> Result<(), TcpSyncError> {
+    let len = u32::try_from(data.len()).map_err(|_| TcpSyncError::FrameTooLarge(data.len()))?;
+    w.write_all(&len.to_be_bytes())?;
+    w.write_all(data)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads a single frame from `r` into `buf`, returning its length.
+pub fn read_frame<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<usize, TcpSyncError> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    let frame = buf.get_mut(..len).ok_or(TcpSyncError::FrameTooLarge(len))?;
+    r.read_exact(frame)?;
+    Ok(len)
+}
+
+/// The default read and write timeout for connections to a
+/// [`TcpSyncServer`].
+pub const DEFAULT_SERVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most peers a [`TcpSyncServer`] keeps a [`PeerCache`] for.
+const MAX_PEERS: usize = 1024;
+
+/// The most graphs a [`TcpSyncServer`] keeps a [`PeerCache`] for, for
+/// each peer.
+const MAX_PEER_GRAPHS: usize = 64;
+
+fn set_timeout(stream: &TcpStream, timeout: Option<Duration>) -> io::Result<()> {
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)
+}
+
+/// Returns the value for `key` in `map`, first evicting another entry
+/// if `map` already holds `max` entries.
+fn bounded_entry<K: Ord, V: Default>(map: &mut BTreeMap<K, V>, key: K, max: usize) -> &mut V {
+    if map.len() >= max && !map.contains_key(&key) {
+        map.pop_first();
+    }
+    map.entry(key).or_default()
+}
+
+/// Requests syncs from peers over TCP.
+pub struct TcpSyncClient<Au> {
+    auth: Au,
+    timeout: Option<Duration>,
+    remote_heads: BTreeMap<SocketAddr, BTreeMap<GraphId, PeerCache>>,
+}
+
+impl<Au: Authenticator> TcpSyncClient<Au> {
+    /// Creates a client which authenticates responders with `auth`.
+    pub fn new(auth: Au) -> Self {
+        Self {
+            auth,
+            timeout: None,
+            remote_heads: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the read and write timeout for connections.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Syncs the specified graph from the responder at
+    /// [`SyncRequester::server_addr`]. Returns the number of commands
+    /// received.
+    ///
+    /// The sync will update your storage, not the peer's.
+    pub fn sync<E, SP, S>(
+        &mut self,
+        client: &mut ClientState<E, SP>,
+        mut syncer: SyncRequester<'_, SocketAddr>,
+        sink: &mut S,
+        storage_id: GraphId,
+    ) -> Result<usize, TcpSyncError>
+    where
+        E: Engine,
+        SP: StorageProvider,
+        S: Sink<E::Effect>,
+    {
+        let peer = syncer.server_addr();
+        let mut stream = self.connect(peer)?;
+
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let heads = self
+            .remote_heads
+            .entry(peer)
+            .or_default()
+            .entry(storage_id)
+            .or_default();
+        let (len, _) = syncer.poll(&mut buffer, client.provider(), heads)?;
+        write_frame(&mut stream, &buffer[..len])?;
+
+        let len = read_frame(&mut stream, &mut buffer)?;
+        // An empty response means we're up to date and there's nothing to sync.
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut received = 0;
+        let data = syncer.decompress(&buffer[..len])?;
+        if let Some(cmds) = syncer.receive(&data)? {
+            received = cmds.len();
+            let mut trx = client.transaction(storage_id);
+            client.add_commands(&mut trx, sink, &cmds, heads)?;
+            client.commit(&mut trx, sink)?;
+        }
+        Ok(received)
+    }
+
+    /// Syncs every graph in `syncer` from the responder at
+    /// [`MultiSyncRequester::server_addr`] in a single exchange.
+    /// Returns the number of commands received.
+    ///
+    /// The sync will update your storage, not the peer's.
+    pub fn sync_multi<E, SP, S>(
+        &mut self,
+        client: &mut ClientState<E, SP>,
+        mut syncer: MultiSyncRequester<'_, SocketAddr>,
+        sink: &mut S,
+    ) -> Result<usize, TcpSyncError>
+    where
+        E: Engine,
+        SP: StorageProvider,
+        S: Sink<E::Effect>,
+    {
+        let peer = syncer.server_addr();
+        let mut stream = self.connect(peer)?;
+
+        let mut buffer = vec![0u8; MAX_MULTI_SYNC_MESSAGE_SIZE];
+        let heads = self.remote_heads.entry(peer).or_default();
+        let len = syncer.poll(&mut buffer, client.provider(), heads)?;
+        write_frame(&mut stream, &buffer[..len])?;
+
+        let len = read_frame(&mut stream, &mut buffer)?;
+        let graphs = syncer.receive(&buffer[..len])?;
+        let received = graphs.iter().map(|(_, cmds)| cmds.len()).sum();
+        client.add_graph_commands(
+            sink,
+            graphs.iter().map(|(id, cmds)| (*id, cmds.as_slice())),
+            heads,
+        )?;
+        Ok(received)
+    }
+
+    /// Connects to and authenticates the responder at `peer`.
+    fn connect(&mut self, peer: SocketAddr) -> Result<TcpStream, TcpSyncError> {
+        let mut stream = TcpStream::connect(peer)?;
+        set_timeout(&stream, self.timeout)?;
+        self.auth
+            .connect(&mut stream, peer)
+            .map_err(TcpSyncError::Auth)?;
+        Ok(stream)
+    }
+}
+
+/// Responds to sync requests from peers over TCP.
+pub struct TcpSyncServer<Au> {
+    listener: TcpListener,
+    server_addr: SocketAddr,
+    auth: Au,
+    timeout: Option<Duration>,
+    compression: Vec<Compression>,
+    remote_heads: BTreeMap<IpAddr, BTreeMap<GraphId, PeerCache>>,
+}
+
+impl<Au: Authenticator> TcpSyncServer<Au> {
+    /// Listens on `addr`, authenticating requesters with `auth`.
+    pub fn bind(addr: impl ToSocketAddrs, auth: Au) -> Result<Self, TcpSyncError> {
+        let listener = TcpListener::bind(addr)?;
+        let server_addr = listener.local_addr()?;
+        Ok(Self {
+            listener,
+            server_addr,
+            auth,
+            timeout: Some(DEFAULT_SERVER_TIMEOUT),
+            compression: Vec::new(),
+            remote_heads: BTreeMap::new(),
+        })
+    }
+
+    /// Sets the read and write timeout for connections, which is
+    /// [`DEFAULT_SERVER_TIMEOUT`] by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Allows compressing responses with any of `algorithms` the
+    /// requester offers. See [`SyncResponder::with_compression`].
+    pub fn with_compression(mut self, algorithms: &[Compression]) -> Self {
+        self.compression = algorithms.to_vec();
+        self
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    /// Accepts a single connection and responds to its sync request,
+    /// which may be for several graphs.
+    ///
+    /// What each peer has is cached by the IP address it connected
+    /// from, not the address in its request.
+    ///
+    /// Call this in a loop to serve peers.
+    pub fn accept<E, SP>(&mut self, client: &mut ClientState<E, SP>) -> Result<(), TcpSyncError>
+    where
+        E: Engine,
+        SP: StorageProvider,
+    {
+        let (mut stream, peer) = self.listener.accept()?;
+        set_timeout(&stream, self.timeout)?;
+        self.auth
+            .accept(&mut stream, peer)
+            .map_err(TcpSyncError::Auth)?;
+
+        let mut buffer = vec![0u8; MAX_SYNC_MESSAGE_SIZE];
+        let len = read_frame(&mut stream, &mut buffer)?;
+        let caches = bounded_entry(&mut self.remote_heads, peer.ip(), MAX_PEERS);
+        match postcard::from_bytes::<SyncType<SocketAddr>>(&buffer[..len])? {
+            SyncType::Poll { request, .. } => {
+                let storage_id = match &request {
+                    SyncRequestMessage::SyncRequest { storage_id, .. } => *storage_id,
+                    SyncRequestMessage::SyncResume { token, .. } => token.storage_id(),
+                    _ => return Err(TcpSyncError::Unsupported),
+                };
+                let response_cache = bounded_entry(caches, storage_id, MAX_PEER_GRAPHS);
+                let mut response_syncer =
+                    SyncResponder::new(self.server_addr).with_compression(&self.compression);
+                response_syncer.receive(request)?;
+                let len = response_syncer.poll(&mut buffer, client.provider(), response_cache)?;
+                write_frame(&mut stream, &buffer[..len])?;
+            }
+            SyncType::MultiPoll { requests, .. } => {
+                let mut target = vec![0u8; MAX_MULTI_SYNC_MESSAGE_SIZE];
+                let len = respond_multi(requests, &mut target, client.provider(), caches, || {
+                    SyncResponder::new(self.server_addr).with_compression(&self.compression)
+                })?;
+                write_frame(&mut stream, &target[..len])?;
+            }
+            _ => return Err(TcpSyncError::Unsupported),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        protocol::{TestActions, TestEngine},
+        storage::memory::MemStorageProvider,
+        NullSink,
+    };
+
+    /// Sends a token which the peer must match.
+    struct TokenAuth(&'static [u8]);
+
+    impl Authenticator for TokenAuth {
+        fn connect(&mut self, stream: &mut TcpStream, _peer: SocketAddr) -> Result<(), AuthError> {
+            write_frame(stream, self.0)?;
+            Ok(())
+        }
+
+        fn accept(&mut self, stream: &mut TcpStream, _peer: SocketAddr) -> Result<(), AuthError> {
+            let mut buf = [0u8; 16];
+            let len = read_frame(stream, &mut buf)?;
+            if &buf[..len] != self.0 {
+                return Err("invalid token".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn new_client() -> ClientState<TestEngine, MemStorageProvider> {
+        ClientState::new(TestEngine::new(), MemStorageProvider::new())
+    }
+
+    #[test]
+    fn test_tcp_sync() {
+        let mut client1 = new_client();
+        let storage_id = client1
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+        for i in 0..6 {
+            client1
+                .action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                .unwrap();
+        }
+
+        let mut server = TcpSyncServer::bind("127.0.0.1:0", TokenAuth(b"token")).unwrap();
+        let addr = server.local_addr();
+        let mut client2 = new_client();
+        let mut sync_client = TcpSyncClient::new(TokenAuth(b"token"));
+        thread::scope(|s| {
+            let handle = s.spawn(|| {
+                for _ in 0..2 {
+                    server.accept(&mut client1).unwrap();
+                }
+            });
+
+            let syncer = SyncRequester::new(storage_id, &mut aranya_crypto::Rng, addr);
+            let received = sync_client
+                .sync(&mut client2, syncer, &mut NullSink, storage_id)
+                .unwrap();
+            assert_eq!(received, 7);
+
+            // Now that we're up to date, nothing is sent.
+            let syncer = SyncRequester::new(storage_id, &mut aranya_crypto::Rng, addr);
+            let received = sync_client
+                .sync(&mut client2, syncer, &mut NullSink, storage_id)
+                .unwrap();
+            assert_eq!(received, 0);
+
+            handle.join().unwrap();
+        });
+
+        // The cache is for the address the requester connected from.
+        assert!(server.remote_heads.keys().eq([&addr.ip()]));
+    }
+
+    #[test]
+    fn test_bounded_entry() {
+        let mut map = BTreeMap::new();
+        for i in 0..4 {
+            *bounded_entry(&mut map, i, 3) += 1;
+        }
+        assert_eq!(map.len(), 3);
+
+        *bounded_entry(&mut map, 3, 3) += 1;
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&3), Some(&2));
+    }
+
+    #[test]
+    fn test_tcp_sync_multi() {
+        let mut client1 = new_client();
+        let storage_ids: Vec<GraphId> = (0..3)
+            .map(|n| {
+                let storage_id = client1
+                    .new_graph(&0u64.to_be_bytes(), TestActions::Init(n), &mut NullSink)
+                    .unwrap();
+                for i in 0..n {
+                    client1
+                        .action(storage_id, &mut NullSink, TestActions::SetValue(i, i))
+                        .unwrap();
+                }
+                storage_id
+            })
+            .collect();
+
+        let mut server = TcpSyncServer::bind("127.0.0.1:0", NoAuth).unwrap();
+        let addr = server.local_addr();
+        let mut client2 = new_client();
+        let mut sync_client = TcpSyncClient::new(NoAuth);
+        let new_syncer = || {
+            let mut syncer = MultiSyncRequester::new(addr);
+            for &storage_id in &storage_ids {
+                syncer
+                    .push(SyncRequester::new(
+                        storage_id,
+                        &mut aranya_crypto::Rng,
+                        addr,
+                    ))
+                    .unwrap();
+            }
+            syncer
+        };
+        thread::scope(|s| {
+            let handle = s.spawn(|| {
+                for _ in 0..2 {
+                    server.accept(&mut client1).unwrap();
+                }
+            });
+
+            // Each graph has its init command and `n` others.
+            let received = sync_client
+                .sync_multi(&mut client2, new_syncer(), &mut NullSink)
+                .unwrap();
+            assert_eq!(received, 6);
+
+            let received = sync_client
+                .sync_multi(&mut client2, new_syncer(), &mut NullSink)
+                .unwrap();
+            assert_eq!(received, 0);
+
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_tcp_sync_unauthorized() {
+        let mut client1 = new_client();
+        let storage_id = client1
+            .new_graph(&0u64.to_be_bytes(), TestActions::Init(0), &mut NullSink)
+            .unwrap();
+
+        let mut server = TcpSyncServer::bind("127.0.0.1:0", TokenAuth(b"token")).unwrap();
+        let addr = server.local_addr();
+        let mut client2 = new_client();
+        let mut sync_client = TcpSyncClient::new(TokenAuth(b"wrong"));
+        thread::scope(|s| {
+            let handle = s.spawn(|| server.accept(&mut client1));
+
+            let syncer = SyncRequester::new(storage_id, &mut aranya_crypto::Rng, addr);
+            let result = sync_client.sync(&mut client2, syncer, &mut NullSink, storage_id);
+            assert!(result.is_err());
+
+            let err = handle.join().unwrap().unwrap_err();
+            assert!(matches!(err, TcpSyncError::Auth(_)), "{err}");
+        });
+    }
+}
@@ -0,0 +1,151 @@
+//! Canned policies and workloads for benchmarking the runtime.
+//!
+//! Each workload drives a [`ClientState`] backed by a [`TestEngine`] which must be
+//! instantiated with [`BENCH_POLICY`].
+
+use super::vm::{test_sync, TestEngine};
+use crate::{
+    storage::memory::MemStorageProvider, vm_action, ClientError, ClientState, GraphId, NullSink,
+};
+
+/// The policy used by the benchmark workloads.
+pub const BENCH_POLICY: &str = r#"---
+policy-version: 1
+---
+
+```policy
+use envelope
+
+fact Counter[]=>{value int}
+
+fact Item[id int]=>{value int}
+
+effect Incremented {
+    value int,
+}
+
+command Init {
+    fields {
+        nonce int,
+    }
+    seal { return envelope::seal(serialize(this)) }
+    open { return deserialize(envelope::open(envelope)) }
+    policy {
+        finish {
+            create Counter[]=>{value: 0}
+        }
+    }
+}
+
+action init(nonce int) {
+    publish Init {
+        nonce: nonce,
+    }
+}
+
+command Increment {
+    fields {
+        amount int,
+    }
+    seal { return envelope::seal(serialize(this)) }
+    open { return deserialize(envelope::open(envelope)) }
+    policy {
+        let counter = unwrap query Counter[]=>{value: ?}
+        check counter.value >= 0
+        let new_value = counter.value + this.amount
+        finish {
+            update Counter[]=>{value: counter.value} to {value: new_value}
+            emit Incremented { value: new_value }
+        }
+    }
+}
+
+action increment() {
+    publish Increment {
+        amount: 1,
+    }
+}
+
+command AddItem {
+    fields {
+        id int,
+        value int,
+    }
+    seal { return envelope::seal(serialize(this)) }
+    open { return deserialize(envelope::open(envelope)) }
+    policy {
+        check this.value >= 0
+        finish {
+            create Item[id: this.id]=>{value: this.value}
+        }
+    }
+}
+
+action add_item(id int, value int) {
+    publish AddItem {
+        id: id,
+        value: value,
+    }
+}
+
+action scan() {
+    map Item[id:?] as item {
+        check item.value >= 0
+    }
+}
+```
+"#;
+
+/// A client used by the benchmark workloads.
+pub type BenchClient = ClientState<TestEngine, MemStorageProvider>;
+
+/// Creates a client with a new graph.
+///
+/// The [`TestEngine`] must be instantiated with [`BENCH_POLICY`].
+pub fn new_client(engine: TestEngine) -> Result<(BenchClient, GraphId), ClientError> {
+    let mut cs = new_peer(engine);
+    let storage_id = cs.new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)?;
+    Ok((cs, storage_id))
+}
+
+/// Creates a client with no graphs, which can receive one with [`sync_graph`].
+///
+/// The [`TestEngine`] must be instantiated with [`BENCH_POLICY`].
+pub fn new_peer(engine: TestEngine) -> BenchClient {
+    ClientState::new(engine, MemStorageProvider::new())
+}
+
+/// Action storm: calls the `increment` action `count` times in a row, each of which
+/// queries and updates the same fact.
+pub fn action_storm(
+    cs: &mut BenchClient,
+    storage_id: GraphId,
+    count: usize,
+) -> Result<(), ClientError> {
+    for _ in 0..count {
+        cs.action(storage_id, &mut NullSink, vm_action!(increment()))?;
+    }
+    Ok(())
+}
+
+/// Creates `count` items, one command each.
+pub fn add_items(cs: &mut BenchClient, storage_id: GraphId, count: i64) -> Result<(), ClientError> {
+    for id in 0..count {
+        cs.action(storage_id, &mut NullSink, vm_action!(add_item(id, id)))?;
+    }
+    Ok(())
+}
+
+/// Large fact scan: previews the `scan` action, which checks every item.
+///
+/// Nothing is committed, so this may be repeated against the same graph.
+pub fn fact_scan(cs: &mut BenchClient, storage_id: GraphId) -> Result<(), ClientError> {
+    cs.dry_run(storage_id, vm_action!(scan()))?;
+    Ok(())
+}
+
+/// Deep graph validation: syncs the graph at `storage_id` from `from` to `to`, which
+/// evaluates every command `to` has not seen against the policy.
+pub fn sync_graph(storage_id: GraphId, from: &mut BenchClient, to: &mut BenchClient) {
+    test_sync(storage_id, from, to, &mut NullSink);
+}
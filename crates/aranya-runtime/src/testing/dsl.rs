@@ -107,6 +107,10 @@ pub fn dispatch<A: DeserializeOwned + Serialize>(
             address: _,
             commands: _,
         } => unimplemented!(),
+        SyncType::MultiPoll {
+            requests: _,
+            address: _,
+        } => unimplemented!(),
         SyncType::Unsubscribe { address: _ } => unimplemented!(),
         SyncType::Push {
             message: _,
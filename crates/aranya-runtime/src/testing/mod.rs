@@ -3,5 +3,6 @@
 #![cfg(any(test, feature = "testing"))]
 #![cfg_attr(docsrs, doc(cfg(feature = "testing")))]
 
+pub mod bench;
 pub mod dsl;
 pub mod vm;
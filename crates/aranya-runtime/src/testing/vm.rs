@@ -1,7 +1,7 @@
 //! VM tests.
 
 extern crate alloc;
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
 
 use aranya_crypto::{default::DefaultEngine, Rng, UserId};
 use aranya_policy_module::Module;
@@ -10,13 +10,16 @@ use tracing::trace;
 
 use super::dsl::dispatch;
 use crate::{
-    engine::{Engine, EngineError, PolicyId, Sink},
+    engine::{Engine, EngineError, PolicyId, PolicyStore, Sink},
+    metrics::{self, Metric, Metrics},
     ser_keys,
-    storage::{memory::MemStorageProvider, Query, Storage, StorageProvider},
+    storage::{memory::MemStorageProvider, Query, Segment, Storage, StorageProvider},
     vm_action, vm_effect,
     vm_policy::testing::TestFfiEnvelope,
-    ClientState, CommandId, GraphId, NullSink, PeerCache, SyncRequester, VmEffect, VmEffectData,
-    VmPolicy, VmPolicyError, MAX_SYNC_MESSAGE_SIZE,
+    ClientError, ClientState, Command, CommandId, FactDelta, GraphId, NullSink, PeerCache,
+    Selection, SessionLimit, SessionLimits, SizeLimit, SizeLimits, SyncRequestMessage,
+    SyncRequester, SyncResponder, SyncType, VmEffect, VmEffectData, VmLabeler, VmPolicy,
+    VmPolicyError, MAX_SYNC_MESSAGE_SIZE,
 };
 
 /// The policy used by these tests.
@@ -79,6 +82,9 @@ action create_action(v int) {
 }
 
 command Increment {
+    attributes {
+        category: "counter"
+    }
     fields {
         key int,
         amount int,
@@ -114,7 +120,7 @@ action increment() {
 }
 
 action incrementFour(n int) {
-    check n == 4
+    check n == 4, "can only increment by four"
     publish Increment {
         key: 1,
         amount: n,
@@ -276,6 +282,13 @@ impl TestEngine {
         .expect("Could not load policy");
         TestEngine { policy }
     }
+
+    /// Sets the [`SizeLimits`] of the engine's policy.
+    pub fn with_size_limits(self, limits: SizeLimits) -> Self {
+        TestEngine {
+            policy: self.policy.with_size_limits(limits),
+        }
+    }
 }
 
 impl Engine for TestEngine {
@@ -527,7 +540,7 @@ pub fn test_aranya_session(engine: TestEngine) -> Result<(), VmPolicyError> {
 }
 
 /// Syncs the first client at `storage_id` to the second client.
-fn test_sync<E, P, S>(
+pub(crate) fn test_sync<E, P, S>(
     storage_id: GraphId,
     cs1: &mut ClientState<E, P>,
     cs2: &mut ClientState<E, P>,
@@ -566,7 +579,197 @@ fn test_sync<E, P, S>(
     cs2.commit(&mut req_transaction, sink).expect("commit");
 }
 
-/// Tests the command ID and recall status in emitted `VmEffect`s.
+/// Syncs the first client at `storage_id` to the second client with
+/// set reconciliation, returning the number of commands received.
+fn sync_reconciling<E, P>(
+    storage_id: GraphId,
+    cs1: &mut ClientState<E, P>,
+    cs2: &mut ClientState<E, P>,
+) -> usize
+where
+    P: StorageProvider,
+    E: Engine,
+{
+    let mut rng = Rng::new();
+    let mut sync_requester = SyncRequester::new(storage_id, &mut rng, ()).with_reconciliation();
+
+    let mut req_transaction = cs2.transaction(storage_id);
+
+    let mut received = 0;
+    while sync_requester.ready() {
+        let mut buffer = [0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = sync_requester
+            .poll(&mut buffer, cs2.provider(), &mut PeerCache::new())
+            .expect("sync req->res");
+
+        let mut target = [0u8; MAX_SYNC_MESSAGE_SIZE];
+        let len = dispatch::<()>(
+            &buffer[..len],
+            &mut target,
+            cs1.provider(),
+            &mut PeerCache::new(),
+        )
+        .expect("dispatch sync response");
+        if len == 0 {
+            break;
+        }
+
+        if let Some(cmds) = sync_requester.receive(&target[..len]).expect("recieve req") {
+            received = cmds.len().checked_add(received).expect("must not overflow");
+            cs2.add_commands(
+                &mut req_transaction,
+                &mut NullSink,
+                &cmds,
+                &mut PeerCache::new(),
+            )
+            .expect("add commands");
+        };
+    }
+
+    cs2.commit(&mut req_transaction, &mut NullSink)
+        .expect("commit");
+    received
+}
+
+/// Tests that syncing with set reconciliation only sends the commands
+/// the requester is missing.
+///
+/// The [`TestEngine`]s must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_sync_reconciliation(
+    engine: TestEngine,
+    engine2: TestEngine,
+) -> Result<(), VmPolicyError> {
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new());
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs1.action(storage_id, &mut NullSink, vm_action!(create_action(1)))
+        .expect("could not call action");
+    for _ in 0..10 {
+        cs1.action(storage_id, &mut NullSink, vm_action!(increment()))
+            .expect("could not call action");
+    }
+
+    // A new peer has nothing to filter, so it receives everything.
+    let mut cs2 = ClientState::new(engine2, MemStorageProvider::new());
+    assert_eq!(sync_reconciling(storage_id, &mut cs1, &mut cs2), 12);
+
+    for _ in 0..3 {
+        cs1.action(storage_id, &mut NullSink, vm_action!(increment()))
+            .expect("could not call action");
+    }
+    assert_eq!(sync_reconciling(storage_id, &mut cs1, &mut cs2), 3);
+    assert_eq!(sync_reconciling(storage_id, &mut cs1, &mut cs2), 0);
+
+    let storage = cs1.provider().get_storage(storage_id)?;
+    let head1 = storage.get_segment(storage.get_head()?)?.head()?.id();
+    let storage = cs2.provider().get_storage(storage_id)?;
+    let head2 = storage.get_segment(storage.get_head()?)?.head()?.id();
+    assert_eq!(head1, head2);
+
+    Ok(())
+}
+
+fn sync_selecting<E, P>(
+    storage_id: GraphId,
+    cs1: &mut ClientState<E, P>,
+    cs2: &mut ClientState<E, P>,
+    selection: Selection,
+    labeler: &VmLabeler,
+) -> usize
+where
+    P: StorageProvider,
+    E: Engine,
+{
+    let mut rng = Rng::new();
+    let mut sync_requester = SyncRequester::new(storage_id, &mut rng, ()).with_selection(selection);
+
+    let mut buffer = [0u8; MAX_SYNC_MESSAGE_SIZE];
+    let (len, _) = sync_requester
+        .poll(&mut buffer, cs2.provider(), &mut PeerCache::new())
+        .expect("sync req->res");
+    let SyncType::Poll { request, .. } =
+        postcard::from_bytes::<SyncType<()>>(&buffer[..len]).expect("deserialize request")
+    else {
+        panic!("expected a poll");
+    };
+
+    let mut sync_responder = SyncResponder::new(()).with_labeler(Arc::new(labeler.clone()));
+    sync_responder.receive(request).expect("receive request");
+    let mut target = [0u8; MAX_SYNC_MESSAGE_SIZE];
+    let len = sync_responder
+        .poll(&mut target, cs1.provider(), &mut PeerCache::new())
+        .expect("sync response");
+    if len == 0 {
+        return 0;
+    }
+
+    let Some(cmds) = sync_requester.receive(&target[..len]).expect("recieve req") else {
+        return 0;
+    };
+    let mut req_transaction = cs2.transaction(storage_id);
+    cs2.add_commands(
+        &mut req_transaction,
+        &mut NullSink,
+        &cmds,
+        &mut PeerCache::new(),
+    )
+    .expect("add commands");
+    cs2.commit(&mut req_transaction, &mut NullSink)
+        .expect("commit");
+    cmds.len()
+}
+
+/// Tests that selective sync only sends the selected commands and
+/// their ancestors.
+///
+/// The [`TestEngine`]s must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_sync_selection(engine: TestEngine, engine2: TestEngine) -> Result<(), VmPolicyError> {
+    let labeler = engine.get_policy(PolicyId::new(0))?.labeler();
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new());
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs1.action(storage_id, &mut NullSink, vm_action!(create_action(1)))
+        .expect("could not call action");
+    for _ in 0..3 {
+        cs1.action(storage_id, &mut NullSink, vm_action!(increment()))
+            .expect("could not call action");
+    }
+
+    // Selecting by command name sends `Create` and its ancestor `Init`.
+    let mut cs2 = ClientState::new(engine2, MemStorageProvider::new());
+    let mut selection = Selection::new();
+    selection.insert("Create").expect("label fits");
+    assert_eq!(
+        sync_selecting(storage_id, &mut cs1, &mut cs2, selection.clone(), &labeler),
+        2
+    );
+    assert_eq!(
+        sync_selecting(storage_id, &mut cs1, &mut cs2, selection, &labeler),
+        0
+    );
+
+    // `Increment` has the `counter` category.
+    let mut selection = Selection::new();
+    selection.insert("counter").expect("label fits");
+    assert_eq!(
+        sync_selecting(storage_id, &mut cs1, &mut cs2, selection, &labeler),
+        3
+    );
+
+    let storage = cs1.provider().get_storage(storage_id)?;
+    let head1 = storage.get_segment(storage.get_head()?)?.head()?.id();
+    let storage = cs2.provider().get_storage(storage_id)?;
+    let head2 = storage.get_segment(storage.get_head()?)?.head()?.id();
+    assert_eq!(head1, head2);
+
+    Ok(())
+}
+
+/// Tests the command ID, author, and recall status in emitted `VmEffect`s.
 ///
 /// The [`TestEngine`] must be instantiated with
 /// [`TEST_POLICY_1`].
@@ -585,6 +788,7 @@ pub fn test_effect_metadata(engine: TestEngine, engine2: TestEngine) -> Result<(
     assert_eq!(sink.last(), &vm_effect!(StuffHappened { x: 1, y: 1 }));
     assert_ne!(sink.last().command, CommandId::default());
     assert!(!sink.last().recalled);
+    let author1 = sink.last().author;
     sink.clear();
 
     // create client 2 and sync it with client 1
@@ -592,6 +796,8 @@ pub fn test_effect_metadata(engine: TestEngine, engine2: TestEngine) -> Result<(
     let mut cs2 = ClientState::new(engine2, provider);
     test_sync(storage_id, &mut cs1, &mut cs2, &mut sink);
     assert_eq!(sink.last(), &vm_effect!(StuffHappened { x: 1, y: 1 }));
+    // Client 2 sees that client 1 wrote the command.
+    assert_eq!(sink.last().author, author1);
     sink.clear();
 
     // At this point, clients are fully synced. Client 2 adds an Increment command, which
@@ -600,6 +806,8 @@ pub fn test_effect_metadata(engine: TestEngine, engine2: TestEngine) -> Result<(
         .expect("could not call action");
     assert_eq!(sink.last(), &vm_effect!(StuffHappened { x: 1, y: 2 }));
     let increment_cmd_id = sink.last().command;
+    let author2 = sink.last().author;
+    assert_ne!(author2, author1);
     sink.clear();
 
     // MEANWHILE, IN A PARALLEL UNIVERSE - client 1 adds the Invalidate command, which sets
@@ -624,8 +832,685 @@ pub fn test_effect_metadata(engine: TestEngine, engine2: TestEngine) -> Result<(
     // We further check that the command that caused this effect is the increment command we
     // created earlier,
     assert_eq!(sink.last().command, increment_cmd_id);
+    assert_eq!(sink.last().author, author2);
     // and that the `recalled` flag is set.
     assert!(sink.last().recalled);
 
     Ok(())
 }
+
+/// Tests listing the commands recalled on a client.
+///
+/// The [`TestEngine`] must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_recall_log(engine: TestEngine, engine2: TestEngine) -> Result<(), VmPolicyError> {
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new());
+    let mut cs2 = ClientState::new(engine2, MemStorageProvider::new());
+    cs2.log_recalls();
+    let mut sink = VecSink::new();
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(1)), &mut sink)
+        .expect("could not create graph");
+    cs1.action(storage_id, &mut sink, vm_action!(create_action(1)))
+        .expect("could not call action");
+    test_sync(storage_id, &mut cs1, &mut cs2, &mut sink);
+    assert_eq!(cs2.recalls().count(), 0);
+
+    // As in `test_effect_metadata`, client 2's increment is recalled once it syncs
+    // client 1's concurrent invalidation.
+    cs2.action(storage_id, &mut sink, vm_action!(increment()))
+        .expect("could not call action");
+    let increment_cmd_id = sink.last().command;
+    cs1.action(storage_id, &mut sink, vm_action!(invalidate()))
+        .expect("could not call action");
+    test_sync(storage_id, &mut cs1, &mut cs2, &mut sink);
+
+    let recalls = cs2.take_recalls();
+    let [recall] = recalls.as_slice() else {
+        panic!("expected one recall, got {recalls:?}");
+    };
+    assert_eq!(recall.command, increment_cmd_id);
+    assert!(recall.rejection.location.is_some());
+    assert_eq!(
+        recall.effects,
+        [vm_effect!(OutOfRange {
+            increment: 1,
+            value: -1
+        })]
+    );
+    assert_eq!(cs2.recalls().count(), 0);
+
+    Ok(())
+}
+
+/// Tests exporting a graph to an archive and importing it on another client.
+///
+/// The [`TestEngine`]s must be instantiated with [`TEST_POLICY_1`].
+pub fn test_export_import(engine: TestEngine, engine2: TestEngine) -> Result<(), VmPolicyError> {
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new());
+    let mut cs2 = ClientState::new(engine2, MemStorageProvider::new());
+    let mut sink = VecSink::new();
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(1)), &mut sink)
+        .expect("could not create graph");
+    cs1.action(storage_id, &mut sink, vm_action!(create_action(1)))
+        .expect("could not call action");
+    cs1.action(storage_id, &mut sink, vm_action!(increment()))
+        .expect("could not call action");
+    let effects = sink.0.len();
+    sink.clear();
+
+    let archive = cs1
+        .export_graph(storage_id)
+        .expect("could not export graph");
+
+    let mut corrupt = archive.clone();
+    corrupt[0] = !corrupt[0];
+    assert!(matches!(
+        cs2.import_graph(&corrupt, &mut sink),
+        Err(ClientError::ArchiveCorrupt)
+    ));
+    assert!(matches!(
+        cs2.import_graph(&archive[..10], &mut sink),
+        Err(ClientError::ArchiveCorrupt)
+    ));
+
+    let imported = cs2
+        .import_graph(&archive, &mut sink)
+        .expect("could not import graph");
+    assert_eq!(imported, storage_id);
+    assert_eq!(sink.0.len(), effects);
+
+    let head1 = cs1.provider().get_storage(storage_id)?.get_head()?;
+    let head1 = cs1
+        .provider()
+        .get_storage(storage_id)?
+        .get_command_id(head1)?;
+    let head2 = cs2.provider().get_storage(storage_id)?.get_head()?;
+    let head2 = cs2
+        .provider()
+        .get_storage(storage_id)?
+        .get_command_id(head2)?;
+    assert_eq!(head1, head2);
+
+    // Importing a graph again adds nothing.
+    sink.clear();
+    cs2.import_graph(&archive, &mut sink)
+        .expect("could not import graph");
+    assert!(sink.0.is_empty());
+
+    Ok(())
+}
+
+/// Tests previewing an action without committing it.
+///
+/// The [`TestEngine`] must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_dry_run(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    let head = cs.provider().get_storage(storage_id)?.get_head()?;
+
+    let preview = cs
+        .dry_run(storage_id, vm_action!(increment()))
+        .expect("could not preview action");
+    assert_eq!(preview.effects, [vm_effect!(StuffHappened { x: 1, y: 4 })]);
+    let fact_keys = ser_keys([FactKey::new("x", HashableValue::Int(1))]);
+    let value = preview.facts.iter().find_map(|delta| match delta {
+        FactDelta::Insert { name, keys, value } if name == "Stuff" && *keys == fact_keys => {
+            Some(value)
+        }
+        _ => None,
+    });
+    let value: Vec<KVPair> =
+        postcard::from_bytes(value.expect("fact was not changed")).expect("deserialize");
+    assert_eq!(value, [KVPair::new("y", Value::Int(4))]);
+
+    // Nothing was committed.
+    let storage = cs.provider().get_storage(storage_id)?;
+    assert_eq!(storage.get_head()?, head);
+    let result = storage
+        .get_fact_perspective(head)?
+        .query("Stuff", &fact_keys)
+        .expect("query")
+        .expect("key does not exist");
+    let value: Vec<KVPair> = postcard::from_bytes(&result).expect("deserialize");
+    assert_eq!(value, [KVPair::new("y", Value::Int(3))]);
+
+    // A rejected action is reported like any other.
+    assert!(cs
+        .dry_run(storage_id, vm_action!(incrementFour(1)))
+        .is_err());
+
+    Ok(())
+}
+
+/// Tests committing several actions as a unit.
+///
+/// The [`TestEngine`] must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_action_transaction(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    let head = cs.provider().get_storage(storage_id)?.get_head()?;
+    let fact_keys = ser_keys([FactKey::new("x", HashableValue::Int(1))]);
+    let query_y = |cs: &mut ClientState<_, MemStorageProvider>| -> Result<_, VmPolicyError> {
+        let storage = cs.provider().get_storage(storage_id)?;
+        let result = storage
+            .get_fact_perspective(storage.get_head()?)?
+            .query("Stuff", &fact_keys)
+            .expect("query")
+            .expect("key does not exist");
+        Ok(postcard::from_bytes::<Vec<KVPair>>(&result).expect("deserialize"))
+    };
+
+    // Each action sees the ones staged before it, and a failed action is not staged.
+    let mut trx = cs
+        .action_transaction(storage_id)
+        .expect("could not start transaction");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    assert!(trx.action(&cs, vm_action!(incrementFour(1))).is_err());
+    assert_eq!(
+        trx.effects(),
+        [
+            vm_effect!(StuffHappened { x: 1, y: 4 }),
+            vm_effect!(StuffHappened { x: 1, y: 5 }),
+        ]
+    );
+    assert_eq!(cs.provider().get_storage(storage_id)?.get_head()?, head);
+
+    let mut sink = VecSink::new();
+    trx.commit(&mut cs, &mut sink)
+        .expect("could not commit transaction");
+    assert_eq!(sink.0.len(), 2);
+    assert_eq!(query_y(&mut cs)?, [KVPair::new("y", Value::Int(5))]);
+
+    // Dropping a transaction discards its actions.
+    let mut trx = cs
+        .action_transaction(storage_id)
+        .expect("could not start transaction");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    drop(trx);
+    assert_eq!(query_y(&mut cs)?, [KVPair::new("y", Value::Int(5))]);
+
+    // A transaction can't be committed once the graph has moved on without it.
+    let mut trx = cs
+        .action_transaction(storage_id)
+        .expect("could not start transaction");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    cs.action(storage_id, &mut NullSink, vm_action!(increment()))
+        .expect("could not call action");
+    assert!(matches!(
+        trx.commit(&mut cs, &mut NullSink),
+        Err(ClientError::HeadChanged)
+    ));
+    assert_eq!(query_y(&mut cs)?, [KVPair::new("y", Value::Int(6))]);
+
+    Ok(())
+}
+
+/// Tests the receipts returned by committing actions.
+///
+/// The [`TestEngine`] must be instantiated with [`TEST_POLICY_1`].
+pub fn test_receipt(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    let receipt = cs
+        .action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    let storage = cs.provider().get_storage(storage_id)?;
+    let head = storage.get_head()?;
+    assert_eq!(receipt.storage_id, storage_id);
+    assert_eq!(receipt.commands, [storage.get_command_id(head)?]);
+    assert_eq!(receipt.sequence, storage.durable_sequence()?);
+    assert!(cs.is_durable(&receipt).expect("could not check durability"));
+
+    let mut trx = cs
+        .action_transaction(storage_id)
+        .expect("could not start transaction");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    trx.action(&cs, vm_action!(increment()))
+        .expect("could not stage action");
+    let next = trx
+        .commit(&mut cs, &mut NullSink)
+        .expect("could not commit transaction");
+    assert_eq!(next.commands.len(), 2);
+    assert!(next.sequence > receipt.sequence);
+    cs.wait_durable(&next)
+        .expect("could not wait for durability");
+    assert!(cs.is_durable(&next).expect("could not check durability"));
+
+    Ok(())
+}
+
+/// Tests querying the committed facts of a graph.
+///
+/// The [`TestEngine`] must be instantiated with [`TEST_POLICY_1`].
+pub fn test_query_facts(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    assert!(cs
+        .query_facts(storage_id, "Stuff", &[])
+        .expect("could not query facts")
+        .is_empty());
+
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    let facts = cs
+        .query_facts(storage_id, "Stuff", &[])
+        .expect("could not query facts");
+    assert_eq!(facts.len(), 1);
+    assert_eq!(
+        facts[0].key,
+        ser_keys([FactKey::new("x", HashableValue::Int(1))])
+    );
+    let value: Vec<KVPair> = postcard::from_bytes(&facts[0].value).expect("deserialize");
+    assert_eq!(value, [KVPair::new("y", Value::Int(3))]);
+
+    let other = ser_keys([FactKey::new("x", HashableValue::Int(2))]);
+    assert!(cs
+        .query_facts(storage_id, "Stuff", &other)
+        .expect("could not query facts")
+        .is_empty());
+    assert!(cs
+        .query_facts(storage_id, "Nothing", &[])
+        .expect("could not query facts")
+        .is_empty());
+
+    Ok(())
+}
+
+/// Tests that sessions fail once they exceed their limits.
+///
+/// The [`TestEngine`] must be instantiated with [`TEST_POLICY_1`].
+pub fn test_session_limits(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+
+    let limits = SessionLimits {
+        max_commands: Some(1),
+        ..SessionLimits::default()
+    };
+    let mut session = cs
+        .session_with_limits(storage_id, limits.clone())
+        .expect("failed to create session");
+    let mut msg_sink = MsgSink::new();
+    session
+        .action(&cs, &mut NullSink, &mut msg_sink, vm_action!(increment()))
+        .expect("failed session action");
+    let err = session
+        .action(&cs, &mut NullSink, &mut msg_sink, vm_action!(increment()))
+        .expect_err("action should exceed the limit");
+    assert!(matches!(
+        err,
+        ClientError::SessionLimit(SessionLimit::Commands)
+    ));
+
+    // Commands received count towards the limit too.
+    let mut session = cs
+        .session_with_limits(storage_id, limits)
+        .expect("failed to create session");
+    session
+        .receive(&cs, &mut NullSink, &msg_sink.0[0])
+        .expect("failed session receive");
+    let err = session
+        .receive(&cs, &mut NullSink, &msg_sink.0[0])
+        .expect_err("receive should exceed the limit");
+    assert!(matches!(
+        err,
+        ClientError::SessionLimit(SessionLimit::Commands)
+    ));
+
+    let limits = SessionLimits {
+        max_fact_bytes: Some(1),
+        ..SessionLimits::default()
+    };
+    let mut session = cs
+        .session_with_limits(storage_id, limits)
+        .expect("failed to create session");
+    let err = session
+        .action(
+            &cs,
+            &mut NullSink,
+            &mut MsgSink::new(),
+            vm_action!(increment()),
+        )
+        .expect_err("action should exceed the limit");
+    assert!(matches!(
+        err,
+        ClientError::SessionLimit(SessionLimit::FactBytes)
+    ));
+
+    #[cfg(feature = "std")]
+    {
+        let limits = SessionLimits {
+            max_lifetime: Some(core::time::Duration::from_millis(1)),
+            ..SessionLimits::default()
+        };
+        let mut session = cs
+            .session_with_limits(storage_id, limits)
+            .expect("failed to create session");
+        std::thread::sleep(core::time::Duration::from_millis(2));
+        let err = session
+            .action(
+                &cs,
+                &mut NullSink,
+                &mut MsgSink::new(),
+                vm_action!(increment()),
+            )
+            .expect_err("session should have expired");
+        assert!(matches!(
+            err,
+            ClientError::SessionLimit(SessionLimit::Lifetime)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Tests saving a session and resuming it later.
+///
+/// The [`TestEngine`] must be instantiated with [`TEST_POLICY_1`].
+pub fn test_session_resume(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+    let mut sink = TestSink::new();
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+
+    let mut msg_sink = MsgSink::new();
+    let saved = {
+        let mut session = cs.session(storage_id).expect("failed to create session");
+        sink.add_expectation(vm_effect!(StuffHappened { x: 1, y: 4 }));
+        session
+            .action(&cs, &mut sink, &mut msg_sink, vm_action!(increment()))
+            .expect("failed session action");
+        session.save().expect("failed to save session")
+    };
+
+    // The graph moving on doesn't change the facts the session sees.
+    for _ in 0..2 {
+        cs.action(storage_id, &mut NullSink, vm_action!(increment()))
+            .expect("could not call action");
+    }
+
+    let mut session = cs
+        .resume_session(&saved, SessionLimits::default())
+        .expect("failed to resume session");
+    sink.add_expectation(vm_effect!(StuffHappened { x: 1, y: 5 }));
+    session
+        .action(&cs, &mut sink, &mut msg_sink, vm_action!(increment()))
+        .expect("failed session action");
+    assert_eq!(msg_sink.0.len(), 2);
+
+    // The commands from before and after resuming form one chain.
+    let mut session = cs.session(storage_id).expect("failed to create session");
+    sink.add_expectation(vm_effect!(StuffHappened { x: 1, y: 6 }));
+    sink.add_expectation(vm_effect!(StuffHappened { x: 1, y: 7 }));
+    for msg in &msg_sink.0 {
+        session
+            .receive(&cs, &mut sink, msg)
+            .expect("failed session receive");
+    }
+    assert!(sink.expect.is_empty());
+
+    assert!(cs.resume_session(&[], SessionLimits::default()).is_err());
+
+    Ok(())
+}
+
+/// Tests that a failed check reports why the action was rejected.
+///
+/// The [`TestEngine`] must be instantiated with
+/// [`TEST_POLICY_1`].
+pub fn test_check_rejection(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let provider = MemStorageProvider::new();
+    let mut cs = ClientState::new(engine, provider);
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+
+    let rejection = match cs.action(storage_id, &mut NullSink, vm_action!(incrementFour(1))) {
+        Err(ClientError::NotAuthorized(rejection)) => Some(rejection),
+        _ => None,
+    }
+    .expect("action should be rejected by a check");
+    assert_eq!(
+        rejection.reason.as_deref(),
+        Some("can only increment by four")
+    );
+    assert!(rejection.location.is_some());
+
+    Ok(())
+}
+
+/// Tests that commands larger than the policy's size limits are refused when they
+/// are published and when they are received.
+///
+/// The [`TestEngine`]s must be instantiated with [`TEST_POLICY_1`].
+pub fn test_size_limits(engine: TestEngine, engine2: TestEngine) -> Result<(), VmPolicyError> {
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new());
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    let archive = cs1
+        .export_graph(storage_id)
+        .expect("could not export graph");
+
+    let mut cs2 = ClientState::new(
+        engine2.with_size_limits(SizeLimits {
+            max_payload: Some(0),
+            ..SizeLimits::default()
+        }),
+        MemStorageProvider::new(),
+    );
+    assert!(matches!(
+        cs2.new_graph(&[0u8], vm_action!(init(0)), &mut NullSink),
+        Err(ClientError::EngineError(EngineError::TooLarge(
+            SizeLimit::Payload
+        )))
+    ));
+    assert!(matches!(
+        cs2.import_graph(&archive, &mut NullSink),
+        Err(ClientError::EngineError(EngineError::TooLarge(
+            SizeLimit::Payload
+        )))
+    ));
+
+    Ok(())
+}
+
+/// Tests that a client holding several policy versions evaluates each graph with
+/// the version it was created with, and refuses graphs whose version it lacks.
+///
+/// The [`TestEngine`]s must be instantiated with [`TEST_POLICY_1`].
+pub fn test_policy_versions(
+    engine: TestEngine,
+    engine2: TestEngine,
+    engine3: TestEngine,
+) -> Result<(), VmPolicyError> {
+    let v1 = PolicyId::new(1);
+    let v2 = PolicyId::new(2);
+    let store = PolicyStore::new()
+        .with_policy(v1, engine.policy)
+        .with_policy(v2, engine2.policy);
+    let mut cs1 = ClientState::new(store, MemStorageProvider::new());
+
+    let old = cs1
+        .new_graph(&v1.to_bytes(), vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    let new = cs1
+        .new_graph(&v2.to_bytes(), vm_action!(init(1)), &mut NullSink)
+        .expect("could not create graph");
+    cs1.action(new, &mut NullSink, vm_action!(create_action(1)))
+        .expect("could not call action");
+    for (storage_id, version) in [(old, v1), (new, v2)] {
+        let storage = cs1.provider().get_storage(storage_id)?;
+        let segment = storage.get_segment(storage.get_head()?)?;
+        assert_eq!(segment.policy(), version);
+    }
+    assert!(matches!(
+        cs1.new_graph(
+            &PolicyId::new(3).to_bytes(),
+            vm_action!(init(2)),
+            &mut NullSink
+        ),
+        Err(ClientError::EngineError(EngineError::NoSuchPolicy(_)))
+    ));
+
+    // A client which has not been upgraded can only receive the old graph.
+    let mut cs2 = ClientState::new(
+        PolicyStore::new().with_policy(v1, engine3.policy),
+        MemStorageProvider::new(),
+    );
+    let archive = cs1.export_graph(old).expect("could not export graph");
+    cs2.import_graph(&archive, &mut NullSink)
+        .expect("could not import graph");
+    let archive = cs1.export_graph(new).expect("could not export graph");
+    assert!(matches!(
+        cs2.import_graph(&archive, &mut NullSink),
+        Err(ClientError::EngineError(EngineError::NoSuchPolicy(id))) if id == v2
+    ));
+
+    Ok(())
+}
+
+/// [`Metrics`] which record every update.
+#[derive(Default)]
+struct MetricsLog(spin::Mutex<Vec<(&'static str, Metric)>>);
+
+impl Metrics for MetricsLog {
+    fn update(&self, name: &'static str, metric: Metric) {
+        self.0.lock().push((name, metric));
+    }
+}
+
+impl MetricsLog {
+    /// Returns the total of the counts recorded for `name`, counting each duration
+    /// as one.
+    fn total(&self, name: &str) -> u64 {
+        self.0
+            .lock()
+            .iter()
+            .filter(|(n, _)| *n == name)
+            .map(|(_, metric)| match metric {
+                Metric::Count(count) => *count,
+                Metric::Duration(_) => 1,
+            })
+            .sum()
+    }
+}
+
+/// Tests that clients and syncers report metrics.
+///
+/// The [`TestEngine`]s must be instantiated with [`TEST_POLICY_1`].
+pub fn test_metrics(engine: TestEngine, engine2: TestEngine) -> Result<(), VmPolicyError> {
+    let metrics = Arc::new(MetricsLog::default());
+    let mut cs1 = ClientState::new(engine, MemStorageProvider::new()).with_metrics(metrics.clone());
+    let mut cs2 =
+        ClientState::new(engine2, MemStorageProvider::new()).with_metrics(metrics.clone());
+
+    let storage_id = cs1
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs1.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    assert!(cs1
+        .action(storage_id, &mut NullSink, vm_action!(incrementFour(1)))
+        .is_err());
+    assert_eq!(metrics.total(metrics::CHECKS_FAILED), 1);
+
+    let mut rng = Rng::new();
+    let mut requester = SyncRequester::new(storage_id, &mut rng, ()).with_metrics(metrics.clone());
+    let mut responder = SyncResponder::new(()).with_metrics(metrics.clone());
+    let mut trx = cs2.transaction(storage_id);
+    while requester.ready() {
+        let mut buffer = [0u8; MAX_SYNC_MESSAGE_SIZE];
+        let (len, _) = requester
+            .poll(&mut buffer, cs2.provider(), &mut PeerCache::new())
+            .expect("sync req->res");
+        let (message, _): (SyncRequestMessage, _) =
+            postcard::take_from_bytes(&buffer[..len]).expect("parse sync request");
+        responder.receive(message).expect("receive sync request");
+
+        let mut target = [0u8; MAX_SYNC_MESSAGE_SIZE];
+        let len = responder
+            .poll(&mut target, cs1.provider(), &mut PeerCache::new())
+            .expect("sync res->req");
+        if let Some(cmds) = requester.receive(&target[..len]).expect("receive response") {
+            cs2.add_commands(&mut trx, &mut NullSink, &cmds, &mut PeerCache::new())
+                .expect("add commands");
+        }
+    }
+    cs2.commit(&mut trx, &mut NullSink).expect("commit");
+
+    assert_eq!(metrics.total(metrics::COMMANDS_VALIDATED), 2);
+    let sent = metrics.total(metrics::SYNC_BYTES_SENT);
+    let received = metrics.total(metrics::SYNC_BYTES_RECEIVED);
+    assert!(received > 0);
+    assert!(sent > received);
+    if cfg!(feature = "std") {
+        assert_eq!(metrics.total(metrics::MERGE_DURATION), 1);
+    }
+
+    Ok(())
+}
+
+/// Tests that an intact graph passes verification.
+///
+/// The [`TestEngine`] must be instantiated with [`TEST_POLICY_1`].
+pub fn test_verify_graph(engine: TestEngine) -> Result<(), VmPolicyError> {
+    let mut cs = ClientState::new(engine, MemStorageProvider::new());
+
+    let storage_id = cs
+        .new_graph(&[0u8], vm_action!(init(0)), &mut NullSink)
+        .expect("could not create graph");
+    cs.action(storage_id, &mut NullSink, vm_action!(create_action(3)))
+        .expect("could not call action");
+    cs.action(storage_id, &mut NullSink, vm_action!(increment()))
+        .expect("could not call action");
+
+    let corruptions = cs.verify_graph(storage_id).expect("could not verify graph");
+    assert!(corruptions.is_empty(), "{corruptions:?}");
+
+    Ok(())
+}
@@ -108,6 +108,78 @@
 //! }
 //! ```
 //!
+//! Commands which are concurrent are braided by priority and then by ID. A deployment can
+//! order them differently with [`VmPolicy::with_braid_order`], as long as every client uses
+//! the same order.
+//!
+//! ## Categories
+//!
+//! Peers can sync only some commands by [selecting](crate::Selection) labels. The
+//! [`VmLabeler`] returned by [`VmPolicy::labeler`] labels each command with its name and
+//! with its `category` attribute, if it has one. It should be a `string` literal.
+//!
+//! ```policy
+//! command Foo {
+//!     attributes {
+//!         category: "telemetry"
+//!     }
+//!     // ... fields, policy, etc.
+//! }
+//! ```
+//!
+//! ## Revocations
+//!
+//! A command can revoke an earlier command, such as one signed by a compromised author,
+//! with its `revokes` attribute. It should be a `string` literal naming the command's
+//! `id` field which holds the ID of the revoked command. See
+//! [`Policy::revokes`](crate::Policy::revokes) and
+//! [`ClientState::revoke`](crate::ClientState::revoke).
+//!
+//! ```policy
+//! command RevokeFoo {
+//!     attributes {
+//!         revokes: "foo_id"
+//!     }
+//!     fields {
+//!         foo_id id,
+//!     }
+//!     // ... policy, etc.
+//! }
+//! ```
+//!
+//! ## Expiry
+//!
+//! A command can expire with its `ttl` attribute. It should be an `int` literal, the
+//! number of generations of the graph, measured in max cut, for which the command stays
+//! valid. See [`Policy::expiry`](crate::Policy::expiry).
+//!
+//! ```policy
+//! command Foo {
+//!     attributes {
+//!         ttl: 100
+//!     }
+//!     // ... fields, policy, etc.
+//! }
+//! ```
+//!
+//! ## Size limits
+//!
+//! [`VmPolicy::with_size_limits`] bounds the size of serialized commands and of the
+//! payload and signature of their envelopes. Commands which exceed a limit are refused
+//! when they are published or received, before they reach storage.
+//!
+//! ## Time
+//!
+//! FFI procedures read the current time with
+//! [`CommandContext::now()`](aranya_policy_vm::CommandContext::now), from the [`Clock`]
+//! given to [`VmPolicy::with_clock`]. It is only available to actions and seal blocks, so
+//! a command which needs a timestamp should store it in one of its fields.
+//!
+//! ```ignore
+//! let clock = Arc::new(ManualClock::new(Timestamp(1_700_000_000)));
+//! let policy = VmPolicy::new(machine, eng, ffi_modules)?.with_clock(clock.clone());
+//! ```
+//!
 //! ## Policy Interface Generator
 //!
 //! A more comfortable way to use `VmPolicy` is via the [Policy Interface
@@ -116,12 +188,21 @@
 
 extern crate alloc;
 
-use alloc::{borrow::Cow, boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use core::fmt;
 
+use aranya_crypto::UserId;
 use aranya_policy_vm::{
-    ActionContext, CommandContext, ExitReason, KVPair, Machine, MachineIO, MachineStack,
-    OpenContext, PolicyContext, RunState, SealContext, Struct, Value,
+    ActionContext, CancellationToken, Clock, CommandContext, ExitReason, KVPair, Machine,
+    MachineError, MachineErrorType, MachineIO, MachineStack, OpenContext, PolicyContext, RunState,
+    SealContext, Struct, Timestamp, Value,
 };
 use buggy::bug;
 use spin::Mutex;
@@ -129,17 +210,19 @@ use tracing::{error, info, instrument};
 
 use crate::{
     command::{Command, CommandId},
-    engine::{EngineError, NullSink, Policy, Sink},
-    CommandRecall, FactPerspective, MergeIds, Perspective, Prior,
+    engine::{EngineError, NullSink, Policy, Rejection, Sink},
+    BraidOrder, CommandRecall, DefaultBraidOrder, FactPerspective, MergeIds, Perspective, Prior,
 };
 
 mod error;
 mod io;
+mod limits;
 mod protocol;
 pub mod testing;
 
 pub use error::*;
 pub use io::*;
+pub use limits::*;
 pub use protocol::*;
 
 /// Creates a [`VmAction`].
@@ -195,6 +278,18 @@ pub struct VmPolicy<E> {
     ffis: Mutex<Vec<Box<dyn FfiCallable<E> + Send + 'static>>>,
     // TODO(chip): replace or fill this with priorities from attributes
     priority_map: Arc<BTreeMap<String, u32>>,
+    category_map: Arc<BTreeMap<String, String>>,
+    /// The field holding the revoked command's ID, for each command which revokes one.
+    revoke_map: BTreeMap<String, String>,
+    /// The number of generations each command with a `ttl` stays valid for.
+    ttl_map: BTreeMap<String, usize>,
+    braid_order: Box<dyn BraidOrder + Send + Sync>,
+    /// Bounds on the size of published and received commands
+    size_limits: SizeLimits,
+    /// The time given to actions and seal blocks, if any
+    clock: Option<Arc<dyn Clock>>,
+    /// Cancels evaluation, such as when an FFI call hangs
+    cancellation: Arc<CancellationToken>,
 }
 
 impl<E> VmPolicy<E> {
@@ -205,26 +300,65 @@ impl<E> VmPolicy<E> {
         ffis: Vec<Box<dyn FfiCallable<E> + Send + 'static>>,
     ) -> Result<Self, VmPolicyError> {
         let priority_map = VmPolicy::<E>::get_command_priorities(&machine)?;
+        let category_map = VmPolicy::<E>::get_command_categories(&machine);
+        let revoke_map = VmPolicy::<E>::get_command_revocations(&machine);
+        let ttl_map = VmPolicy::<E>::get_command_ttls(&machine)?;
         Ok(Self {
             machine,
             engine: Mutex::from(engine),
             ffis: Mutex::from(ffis),
             priority_map: Arc::new(priority_map),
+            category_map: Arc::new(category_map),
+            revoke_map,
+            ttl_map,
+            braid_order: Box::new(DefaultBraidOrder),
+            size_limits: SizeLimits::default(),
+            clock: None,
+            cancellation: Arc::new(CancellationToken::new()),
         })
     }
 
-    fn source_location<M>(&self, rs: &RunState<'_, M>) -> String
-    where
-        M: MachineIO<MachineStack>,
-    {
-        rs.source_location()
-            .unwrap_or(String::from("(unknown location)"))
+    /// Sets how concurrent commands are ordered when braiding. See [`BraidOrder`].
+    pub fn with_braid_order(mut self, order: impl BraidOrder + Send + Sync + 'static) -> Self {
+        self.braid_order = Box::new(order);
+        self
+    }
+
+    /// Sets the bounds on the size of published and received commands. See
+    /// [`SizeLimits`].
+    pub fn with_size_limits(mut self, limits: SizeLimits) -> Self {
+        self.size_limits = limits;
+        self
+    }
+
+    /// Sets the clock which actions and seal blocks read the current time from. See
+    /// [`CommandContext::now`].
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Returns the token which cancels policy evaluation. A host can cancel it from a
+    /// watchdog thread to stop an FFI call which has run for too long. Evaluation fails
+    /// until the token is [reset](CancellationToken::reset).
+    pub fn cancellation_token(&self) -> Arc<CancellationToken> {
+        Arc::clone(&self.cancellation)
+    }
+
+    /// Returns a [`VmLabeler`] for selective sync of this policy's commands.
+    pub fn labeler(&self) -> VmLabeler {
+        VmLabeler::new(Arc::clone(&self.category_map))
+    }
+
+    /// Reads the current time from the clock, if there is one.
+    fn now(&self) -> Option<Timestamp> {
+        self.clock.as_ref().map(|clock| clock.now())
     }
 
     /// Scans command attributes for priorities and creates the priority map from them.
     fn get_command_priorities(machine: &Machine) -> Result<BTreeMap<String, u32>, VmPolicyError> {
         let mut priority_map = BTreeMap::new();
-        for (name, attrs) in &machine.command_attributes {
+        for (name, attrs) in machine.command_attributes.iter() {
             if let Some(Value::Int(p)) = attrs.get("priority") {
                 let pv = (*p).try_into().map_err(|e| {
                     error!(
@@ -238,6 +372,43 @@ impl<E> VmPolicy<E> {
         }
         Ok(priority_map)
     }
+
+    /// Scans command attributes for categories and creates the category map from them.
+    fn get_command_categories(machine: &Machine) -> BTreeMap<String, String> {
+        let mut category_map = BTreeMap::new();
+        for (name, attrs) in machine.command_attributes.iter() {
+            if let Some(Value::String(c)) = attrs.get("category") {
+                category_map.insert(name.clone(), c.clone());
+            }
+        }
+        category_map
+    }
+
+    /// Scans command attributes for revocations and creates the revoke map from them.
+    fn get_command_revocations(machine: &Machine) -> BTreeMap<String, String> {
+        let mut revoke_map = BTreeMap::new();
+        for (name, attrs) in machine.command_attributes.iter() {
+            if let Some(Value::String(field)) = attrs.get("revokes") {
+                revoke_map.insert(name.clone(), field.clone());
+            }
+        }
+        revoke_map
+    }
+
+    /// Scans command attributes for TTLs and creates the TTL map from them.
+    fn get_command_ttls(machine: &Machine) -> Result<BTreeMap<String, usize>, VmPolicyError> {
+        let mut ttl_map = BTreeMap::new();
+        for (name, attrs) in machine.command_attributes.iter() {
+            if let Some(Value::Int(t)) = attrs.get("ttl") {
+                let tv = (*t).try_into().map_err(|e| {
+                    error!(?e, "TTL out of range in {name}: {t} does not fit in usize");
+                    VmPolicyError::Unknown
+                })?;
+                ttl_map.insert(name.clone(), tv);
+            }
+        }
+        Ok(ttl_map)
+    }
 }
 
 impl<E: aranya_crypto::Engine> VmPolicy<E> {
@@ -256,34 +427,34 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
     where
         P: FactPerspective,
     {
+        let CommandContext::Policy(policy_ctx) = ctx else {
+            error!("Non-policy context while evaluating rule: {ctx:?}");
+            return Err(EngineError::InternalError);
+        };
         let mut ffis = self.ffis.lock();
         let mut eng = self.engine.lock();
-        let mut io = VmPolicyIO::new(facts, sink, &mut *eng, &mut ffis);
+        let mut io =
+            VmPolicyIO::new(facts, sink, &mut *eng, &mut ffis).with_author(policy_ctx.author);
         let mut rs = self.machine.create_run_state(&mut io, ctx);
         let self_data = Struct::new(name, fields);
         match rs.call_command_policy(&self_data.name, &self_data, envelope.clone().into()) {
             Ok(reason) => match reason {
                 ExitReason::Normal => Ok(()),
                 ExitReason::Check => {
-                    info!("Check {}", self.source_location(&rs));
+                    let err = rs.exit_error(ExitReason::Check);
+                    info!("{err}");
+                    let rejection = rejection(&err);
                     // Construct a new recall context from the policy context
-                    let CommandContext::Policy(policy_ctx) = ctx else {
-                        error!("Non-policy context while evaluating rule: {ctx:?}");
-                        return Err(EngineError::InternalError);
-                    };
                     let recall_ctx = CommandContext::Recall(policy_ctx.clone());
                     rs.set_context(&recall_ctx);
-                    self.recall_internal(recall, &mut rs, name, &self_data, envelope)
+                    self.recall_internal(recall, &mut rs, name, &self_data, envelope, rejection)
                 }
                 ExitReason::Panic => {
-                    info!("Panicked {}", self.source_location(&rs));
+                    info!("{}", rs.exit_error(ExitReason::Panic));
                     Err(EngineError::Panic)
                 }
             },
-            Err(e) => {
-                error!("\n{e}");
-                Err(EngineError::InternalError)
-            }
+            Err(e) => Err(machine_error(&e)),
         }
     }
 
@@ -294,21 +465,22 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
         name: &str,
         self_data: &Struct,
         envelope: Envelope<'_>,
+        rejection: Rejection,
     ) -> Result<(), EngineError>
     where
         M: MachineIO<MachineStack>,
     {
         match recall {
-            CommandRecall::None => Err(EngineError::Check),
+            CommandRecall::None => Err(EngineError::Check(rejection)),
             CommandRecall::OnCheck => {
                 match rs.call_command_recall(name, self_data, envelope.into()) {
-                    Ok(ExitReason::Normal) => Err(EngineError::Check),
+                    Ok(ExitReason::Normal) => Err(EngineError::Check(rejection)),
                     Ok(ExitReason::Check) => {
-                        info!("Recall failed: {}", self.source_location(rs));
-                        Err(EngineError::Check)
+                        info!("Recall {}", rs.exit_error(ExitReason::Check));
+                        Err(EngineError::Check(rejection))
                     }
                     Ok(ExitReason::Panic) | Err(_) => {
-                        info!("Recall panicked: {}", self.source_location(rs));
+                        info!("Recall {}", rs.exit_error(ExitReason::Panic));
                         Err(EngineError::Panic)
                     }
                 }
@@ -330,7 +502,9 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
         let mut ffis = self.ffis.lock();
         let mut eng = self.engine.lock();
         let mut io = VmPolicyIO::new(facts, &mut sink, &mut *eng, &mut ffis);
-        let ctx = CommandContext::Open(OpenContext { name });
+        let ctx = CommandContext::Open(
+            OpenContext::new(name).with_cancellation(Some(self.cancellation.as_ref())),
+        );
         let mut rs = self.machine.create_run_state(&mut io, &ctx);
         let status = rs.call_open(name, envelope.into());
         match status {
@@ -346,18 +520,16 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
                     })?)
                 }
                 ExitReason::Check => {
-                    info!("Check {}", self.source_location(&rs));
-                    Err(EngineError::Check)
+                    let err = rs.exit_error(ExitReason::Check);
+                    info!("{err}");
+                    Err(EngineError::Check(rejection(&err)))
                 }
                 ExitReason::Panic => {
-                    info!("Panicked {}", self.source_location(&rs));
-                    Err(EngineError::Check)
+                    info!("{}", rs.exit_error(ExitReason::Panic));
+                    Err(EngineError::Check(Rejection::default()))
                 }
             },
-            Err(e) => {
-                error!("\n{e}");
-                Err(EngineError::InternalError)
-            }
+            Err(e) => Err(machine_error(&e)),
         }
     }
 
@@ -373,10 +545,11 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
         let mut ffis = self.ffis.lock();
         let mut eng = self.engine.lock();
         let mut io = VmPolicyIO::new(facts, &mut sink, &mut *eng, &mut ffis);
-        let ctx = CommandContext::Seal(SealContext {
-            name,
-            head_id: ctx_parent.into(),
-        });
+        let ctx = CommandContext::Seal(
+            SealContext::new(name, ctx_parent.into())
+                .with_cancellation(Some(self.cancellation.as_ref()))
+                .with_now(self.now()),
+        );
         let mut rs = self.machine.create_run_state(&mut io, &ctx);
         let command_struct = Struct::new(name, fields);
         let status = rs.call_seal(name, &command_struct);
@@ -398,19 +571,123 @@ impl<E: aranya_crypto::Engine> VmPolicy<E> {
                     Ok(envelope)
                 }
                 ExitReason::Check => {
-                    info!("Check {}", self.source_location(&rs));
-                    Err(EngineError::Check)
+                    let err = rs.exit_error(ExitReason::Check);
+                    info!("{err}");
+                    Err(EngineError::Check(rejection(&err)))
                 }
                 ExitReason::Panic => {
-                    info!("Panicked {}", self.source_location(&rs));
+                    info!("{}", rs.exit_error(ExitReason::Panic));
                     Err(EngineError::Panic)
                 }
             },
-            Err(e) => {
-                error!("\n{e}");
-                Err(EngineError::InternalError)
+            Err(e) => Err(machine_error(&e)),
+        }
+    }
+
+    /// Processes an action, telling FFIs that it is a dry run if `dry_run` is set.
+    fn perform_action(
+        &self,
+        action: VmAction<'_>,
+        facts: &mut impl Perspective,
+        sink: &mut impl Sink<VmEffect>,
+        dry_run: bool,
+    ) -> Result<(), EngineError> {
+        let VmAction { name, args } = action;
+
+        let parent = match facts.head_address()? {
+            Prior::None => None,
+            Prior::Single(id) => Some(id),
+            Prior::Merge(_, _) => bug!("cannot have a merge parent in call_action"),
+        };
+        // FIXME(chip): This is kind of wrong, but it avoids having to
+        // plumb Option<Id> into the VM and FFI
+        let ctx_parent = parent.unwrap_or_default();
+
+        let publish_stack = {
+            let mut ffis = self.ffis.lock();
+            let mut eng = self.engine.lock();
+            let mut io = VmPolicyIO::new(facts, sink, &mut *eng, &mut ffis);
+            let ctx = CommandContext::Action(
+                ActionContext::new(name, ctx_parent.id.into())
+                    .with_cancellation(Some(self.cancellation.as_ref()))
+                    .with_now(self.now())
+                    .with_dry_run(dry_run),
+            );
+            {
+                let mut rs = self.machine.create_run_state(&mut io, &ctx);
+                let exit_reason = match args {
+                    Cow::Borrowed(args) => rs.call_action(name, args.iter().cloned()),
+                    Cow::Owned(args) => rs.call_action(name, args),
+                }
+                .map_err(|e| machine_error(&e))?;
+                match exit_reason {
+                    ExitReason::Normal => {}
+                    ExitReason::Check => {
+                        let err = rs.exit_error(ExitReason::Check);
+                        info!("{err}");
+                        return Err(EngineError::Check(rejection(&err)));
+                    }
+                    ExitReason::Panic => {
+                        info!("{}", rs.exit_error(ExitReason::Panic));
+                        return Err(EngineError::Panic);
+                    }
+                };
             }
+            io.into_publish_stack()
+        };
+
+        for (name, fields) in publish_stack {
+            let envelope = self.seal_command(&name, fields, ctx_parent.id, facts)?;
+            let data = match parent {
+                None => VmProtocolData::Init {
+                    // Peers select the graph's policy version from this.
+                    policy: facts.policy().to_bytes(),
+                    author_id: envelope.author_id,
+                    kind: &name,
+                    serialized_fields: &envelope.payload,
+                    signature: &envelope.signature,
+                },
+                Some(parent) => VmProtocolData::Basic {
+                    author_id: envelope.author_id,
+                    parent,
+                    kind: &name,
+                    serialized_fields: &envelope.payload,
+                    signature: &envelope.signature,
+                },
+            };
+            let wrapped = postcard::to_allocvec(&data)?;
+            let new_command = VmProtocol::new(
+                &wrapped,
+                envelope.command_id,
+                data,
+                Arc::clone(&self.priority_map),
+            );
+
+            self.call_rule(&new_command, facts, sink, CommandRecall::None)?;
+            facts.add_command(&new_command).map_err(|e| {
+                error!("{e}");
+                EngineError::Write
+            })?;
         }
+
+        Ok(())
+    }
+}
+
+/// Converts an error which stopped the VM into an [`EngineError`].
+fn machine_error(err: &MachineError) -> EngineError {
+    error!("\n{err}");
+    match &err.err_type {
+        MachineErrorType::FfiFailed { .. } => EngineError::Ffi(err.err_type.to_string()),
+        _ => EngineError::InternalError,
+    }
+}
+
+/// Describes a failed `check` for the caller.
+fn rejection(err: &MachineError) -> Rejection {
+    Rejection {
+        reason: err.check_reason().map(String::from),
+        location: err.location(),
     }
 }
 
@@ -455,6 +732,8 @@ pub struct VmEffect {
     pub fields: Vec<KVPair>,
     /// The command ID that produced this effect
     pub command: CommandId,
+    /// The author of the command that produced this effect
+    pub author: UserId,
     /// Was this produced from a recall block?
     pub recalled: bool,
 }
@@ -481,6 +760,25 @@ impl<E: aranya_crypto::Engine> Policy for VmPolicy<E> {
             error!("Could not deserialize: {e:?}");
             EngineError::Read
         })?;
+        // Commands are only checked when they are published or received, so that
+        // stored commands are still evaluated if the limits change.
+        if matches!(recall, CommandRecall::None) {
+            let (payload, signature) = match &unpacked {
+                VmProtocolData::Init {
+                    serialized_fields,
+                    signature,
+                    ..
+                }
+                | VmProtocolData::Basic {
+                    serialized_fields,
+                    signature,
+                    ..
+                } => (serialized_fields.len(), signature.len()),
+                _ => (0, 0),
+            };
+            self.size_limits
+                .check(command.bytes().len(), payload, signature)?;
+        }
         match unpacked {
             VmProtocolData::Init {
                 author_id,
@@ -502,12 +800,15 @@ impl<E: aranya_crypto::Engine> Policy for VmPolicy<E> {
                     .into_iter()
                     .map(|(k, v)| KVPair::new(&k, v))
                     .collect();
-                let ctx = CommandContext::Policy(PolicyContext {
-                    name: kind,
-                    id: command.id().into(),
-                    author: author_id,
-                    version: CommandId::default().into(),
-                });
+                let ctx = CommandContext::Policy(
+                    PolicyContext::new(
+                        kind,
+                        command.id().into(),
+                        author_id,
+                        CommandId::default().into(),
+                    )
+                    .with_cancellation(Some(self.cancellation.as_ref())),
+                );
                 self.evaluate_rule(kind, fields.as_slice(), envelope, facts, sink, &ctx, recall)?
             }
             VmProtocolData::Basic {
@@ -530,12 +831,15 @@ impl<E: aranya_crypto::Engine> Policy for VmPolicy<E> {
                     .into_iter()
                     .map(|(k, v)| KVPair::new(&k, v))
                     .collect();
-                let ctx = CommandContext::Policy(PolicyContext {
-                    name: kind,
-                    id: command.id().into(),
-                    author: author_id,
-                    version: CommandId::default().into(),
-                });
+                let ctx = CommandContext::Policy(
+                    PolicyContext::new(
+                        kind,
+                        command.id().into(),
+                        author_id,
+                        CommandId::default().into(),
+                    )
+                    .with_cancellation(Some(self.cancellation.as_ref())),
+                );
                 self.evaluate_rule(kind, fields.as_slice(), envelope, facts, sink, &ctx, recall)?
             }
             // Merges always pass because they're an artifact of the graph
@@ -545,6 +849,114 @@ impl<E: aranya_crypto::Engine> Policy for VmPolicy<E> {
         Ok(())
     }
 
+    fn revokes(
+        &self,
+        command: &impl Command,
+        facts: &mut impl FactPerspective,
+    ) -> Result<Option<CommandId>, EngineError> {
+        if self.revoke_map.is_empty() {
+            return Ok(None);
+        }
+        let unpacked: VmProtocolData<'_> = postcard::from_bytes(command.bytes()).map_err(|e| {
+            error!("Could not deserialize: {e:?}");
+            EngineError::Read
+        })?;
+        let VmProtocolData::Basic {
+            parent,
+            kind,
+            author_id,
+            serialized_fields,
+            signature,
+        } = unpacked
+        else {
+            return Ok(None);
+        };
+        let Some(field) = self.revoke_map.get(kind) else {
+            return Ok(None);
+        };
+        let envelope = Envelope {
+            parent_id: parent.id,
+            author_id,
+            command_id: command.id(),
+            payload: Cow::Borrowed(serialized_fields),
+            signature: Cow::Borrowed(signature),
+        };
+        let command_struct = self.open_command(kind, envelope, facts)?;
+        match command_struct.fields.get(field) {
+            Some(Value::Id(id)) => Ok(Some(CommandId::from(*id))),
+            _ => {
+                error!("Revoked command field {field} in {kind} is not an id");
+                Err(EngineError::InternalError)
+            }
+        }
+    }
+
+    fn expiry(&self, command: &impl Command) -> Result<Option<usize>, EngineError> {
+        if self.ttl_map.is_empty() {
+            return Ok(None);
+        }
+        let unpacked: VmProtocolData<'_> = postcard::from_bytes(command.bytes()).map_err(|e| {
+            error!("Could not deserialize: {e:?}");
+            EngineError::Read
+        })?;
+        let kind = match unpacked {
+            VmProtocolData::Init { kind, .. } | VmProtocolData::Basic { kind, .. } => kind,
+            _ => return Ok(None),
+        };
+        let Some(&ttl) = self.ttl_map.get(kind) else {
+            return Ok(None);
+        };
+        Ok(Some(command.max_cut()?.saturating_add(ttl)))
+    }
+
+    fn verify_envelope(
+        &self,
+        command: &impl Command,
+        facts: &mut impl FactPerspective,
+    ) -> Result<(), EngineError> {
+        let unpacked: VmProtocolData<'_> = postcard::from_bytes(command.bytes()).map_err(|e| {
+            error!("Could not deserialize: {e:?}");
+            EngineError::Read
+        })?;
+        let (kind, parent_id, author_id, serialized_fields, signature) = match unpacked {
+            VmProtocolData::Init {
+                author_id,
+                kind,
+                serialized_fields,
+                signature,
+                ..
+            } => (
+                kind,
+                CommandId::default(),
+                author_id,
+                serialized_fields,
+                signature,
+            ),
+            VmProtocolData::Basic {
+                parent,
+                kind,
+                author_id,
+                serialized_fields,
+                signature,
+            } => (kind, parent.id, author_id, serialized_fields, signature),
+            // Merges have no envelope.
+            _ => return Ok(()),
+        };
+        let envelope = Envelope {
+            parent_id,
+            author_id,
+            command_id: command.id(),
+            payload: Cow::Borrowed(serialized_fields),
+            signature: Cow::Borrowed(signature),
+        };
+        self.open_command(kind, envelope, facts)?;
+        Ok(())
+    }
+
+    fn fact_names(&self) -> Vec<&str> {
+        self.machine.fact_defs.keys().map(String::as_str).collect()
+    }
+
     #[instrument(skip_all, fields(name = action.name))]
     fn call_action(
         &self,
@@ -552,85 +964,21 @@ impl<E: aranya_crypto::Engine> Policy for VmPolicy<E> {
         facts: &mut impl Perspective,
         sink: &mut impl Sink<Self::Effect>,
     ) -> Result<(), EngineError> {
-        let VmAction { name, args } = action;
-
-        let parent = match facts.head_address()? {
-            Prior::None => None,
-            Prior::Single(id) => Some(id),
-            Prior::Merge(_, _) => bug!("cannot have a merge parent in call_action"),
-        };
-        // FIXME(chip): This is kind of wrong, but it avoids having to
-        // plumb Option<Id> into the VM and FFI
-        let ctx_parent = parent.unwrap_or_default();
-
-        let publish_stack = {
-            let mut ffis = self.ffis.lock();
-            let mut eng = self.engine.lock();
-            let mut io = VmPolicyIO::new(facts, sink, &mut *eng, &mut ffis);
-            let ctx = CommandContext::Action(ActionContext {
-                name,
-                head_id: ctx_parent.id.into(),
-            });
-            {
-                let mut rs = self.machine.create_run_state(&mut io, &ctx);
-                let exit_reason = match args {
-                    Cow::Borrowed(args) => rs.call_action(name, args.iter().cloned()),
-                    Cow::Owned(args) => rs.call_action(name, args),
-                }
-                .map_err(|e| {
-                    error!("\n{e}");
-                    EngineError::InternalError
-                })?;
-                match exit_reason {
-                    ExitReason::Normal => {}
-                    ExitReason::Check => {
-                        info!("Check {}", self.source_location(&rs));
-                        return Err(EngineError::Check);
-                    }
-                    ExitReason::Panic => {
-                        info!("Panicked {}", self.source_location(&rs));
-                        return Err(EngineError::Panic);
-                    }
-                };
-            }
-            io.into_publish_stack()
-        };
-
-        for (name, fields) in publish_stack {
-            let envelope = self.seal_command(&name, fields, ctx_parent.id, facts)?;
-            let data = match parent {
-                None => VmProtocolData::Init {
-                    // TODO(chip): where does the policy value come from?
-                    policy: 0u64.to_le_bytes(),
-                    author_id: envelope.author_id,
-                    kind: &name,
-                    serialized_fields: &envelope.payload,
-                    signature: &envelope.signature,
-                },
-                Some(parent) => VmProtocolData::Basic {
-                    author_id: envelope.author_id,
-                    parent,
-                    kind: &name,
-                    serialized_fields: &envelope.payload,
-                    signature: &envelope.signature,
-                },
-            };
-            let wrapped = postcard::to_allocvec(&data)?;
-            let new_command = VmProtocol::new(
-                &wrapped,
-                envelope.command_id,
-                data,
-                Arc::clone(&self.priority_map),
-            );
+        self.perform_action(action, facts, sink, false)
+    }
 
-            self.call_rule(&new_command, facts, sink, CommandRecall::None)?;
-            facts.add_command(&new_command).map_err(|e| {
-                error!("{e}");
-                EngineError::Write
-            })?;
-        }
+    #[instrument(skip_all, fields(name = action.name))]
+    fn call_action_dry_run(
+        &self,
+        action: Self::Action<'_>,
+        facts: &mut impl Perspective,
+        sink: &mut impl Sink<Self::Effect>,
+    ) -> Result<(), EngineError> {
+        self.perform_action(action, facts, sink, true)
+    }
 
-        Ok(())
+    fn braid_order(&self) -> &dyn BraidOrder {
+        &*self.braid_order
     }
 
     fn merge<'a>(
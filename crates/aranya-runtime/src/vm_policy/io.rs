@@ -1,16 +1,21 @@
 extern crate alloc;
 
-use alloc::{boxed::Box, string::String, vec, vec::Vec};
-use core::ops::{Deref, DerefMut};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::ops::{Bound, Deref, DerefMut};
 
-use aranya_crypto::Id;
+use aranya_crypto::{Id, UserId};
 use aranya_policy_vm::{
-    ffi::FfiModule, CommandContext, FactKey, FactValue, HashableValue, KVPair, MachineError,
-    MachineErrorType, MachineIO, MachineIOError, MachineStack,
+    ffi::FfiModule, CommandContext, FactKey, FactKeyRange, FactValue, HashableValue, KVPair,
+    MachineError, MachineErrorType, MachineIO, MachineIOError, MachineStack,
 };
 use tracing::error;
 
-use crate::{FactPerspective, Keys, Query, Sink, VmEffect};
+use crate::{Fact, FactPerspective, Keys, Query, Sink, StorageError, VmEffect};
 
 /// Object safe wrapper for [`FfiModule`].
 pub trait FfiCallable<E> {
@@ -47,6 +52,7 @@ pub struct VmPolicyIO<'o, P, S, E, FFI> {
     publish_stack: Vec<(String, Vec<KVPair>)>,
     engine: &'o mut E,
     ffis: &'o mut [FFI],
+    author: UserId,
 }
 
 pub type FfiList<'a, E> = &'a mut [&'a mut dyn FfiCallable<E>];
@@ -66,9 +72,18 @@ impl<'o, P, S, E, FFI> VmPolicyIO<'o, P, S, E, FFI> {
             publish_stack: vec![],
             engine,
             ffis,
+            author: UserId::default(),
         }
     }
 
+    /// Sets the author of the command being evaluated, which is recorded in the
+    /// [`VmEffect`]s it emits.
+    #[must_use]
+    pub fn with_author(mut self, author: UserId) -> Self {
+        self.author = author;
+        self
+    }
+
     /// Consumes the `VmPolicyIO` object and produces the publish stack.
     pub fn into_publish_stack(self) -> Vec<(String, Vec<KVPair>)> {
         self.publish_stack
@@ -120,6 +135,50 @@ where
         Ok(VmFactCursor { iter })
     }
 
+    fn fact_query_page(
+        &self,
+        name: String,
+        key: impl IntoIterator<Item = FactKey>,
+        after: Option<&[FactKey]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<FactKey>, Vec<FactValue>)>, MachineIOError> {
+        // Storage keys are encoded so that they compare in the same order as `FactKey`s,
+        // so storage can start reading the page at `after`.
+        let keys = ser_keys(key);
+        let after = after.map(|after| ser_keys(after.iter().cloned()));
+        self.facts
+            .query_page(&name, &keys, after.as_deref(), limit)
+            .map_err(|e| {
+                error!("query failed: {e}");
+                MachineIOError::Internal
+            })?
+            .into_iter()
+            .map(|fact| deser_fact(Ok(fact)))
+            .collect()
+    }
+
+    fn fact_query_range(
+        &self,
+        name: String,
+        prefix: impl IntoIterator<Item = FactKey>,
+        range: FactKeyRange,
+        limit: usize,
+    ) -> Result<Vec<(Vec<FactKey>, Vec<FactValue>)>, MachineIOError> {
+        // Storage keys are encoded so that they compare in the same order as `FactKey`s.
+        let keys = ser_keys(prefix);
+        let start = range.0.as_ref().map(ser_key);
+        let end = range.1.as_ref().map(ser_key);
+        let range = (start.as_ref().map(|k| &**k), end.as_ref().map(|k| &**k));
+        self.facts
+            .query_range(&name, &keys, range, limit)
+            .map_err(|e| {
+                error!("query failed: {e}");
+                MachineIOError::Internal
+            })?
+            .map(deser_fact)
+            .collect()
+    }
+
     fn publish(&mut self, name: String, fields: impl IntoIterator<Item = KVPair>) {
         let fields: Vec<_> = fields.into_iter().collect();
         self.publish_stack.push((name, fields));
@@ -137,6 +196,7 @@ where
             name,
             fields,
             command: command.into(),
+            author: self.author,
             recalled,
         });
     }
@@ -152,7 +212,15 @@ where
             Err(MachineError::new(MachineErrorType::FfiModuleNotDefined(
                 module,
             ))),
-            |ffi| ffi.call(procedure, stack, ctx, self.engine),
+            |ffi| {
+                ffi.call(procedure, stack, ctx, self.engine).map_err(|e| {
+                    MachineError::new(MachineErrorType::FfiFailed {
+                        module,
+                        procedure,
+                        reason: e.err_type.to_string(),
+                    })
+                })
+            },
         )
     }
 }
@@ -163,6 +231,17 @@ pub(crate) fn ser_keys(keys: impl IntoIterator<Item = FactKey>) -> Keys {
     keys.into_iter().map(|key| ser_key(&key)).collect()
 }
 
+/// Deserializes the keys and value of a [`Fact`] read from storage.
+fn deser_fact(
+    fact: Result<Fact, StorageError>,
+) -> Result<(Vec<FactKey>, Vec<FactValue>), MachineIOError> {
+    let fact = fact.map_err(|e| {
+        error!("error during query: {e}");
+        MachineIOError::Internal
+    })?;
+    Ok((deser_keys(fact.key)?, deser_values(fact.value)?))
+}
+
 /// Deserializes [`Keys`] into a sequence of [`FactKey`]s.
 fn deser_keys(keys: Keys) -> Result<Vec<FactKey>, MachineIOError> {
     keys.as_ref()
@@ -198,34 +277,11 @@ impl KeyType {
 
 /// Serializes a `FactKey` into bytes.
 ///
-/// This preserves the ordering for two facts with the same identifier and value type.
-/// This is important for the ordering of fact iteration in prefix queries.
-fn ser_key(FactKey { identifier, value }: &FactKey) -> Box<[u8]> {
-    let identifier_len = (identifier.len() as u64).to_be_bytes();
-
-    let int_bytes;
-    let (tag, value_bytes) = match value {
-        &HashableValue::Int(int) => {
-            // flip sign bit and use big-endian to preserve ordering.
-            int_bytes = i64::to_be_bytes(int ^ (1 << 63));
-            (KeyType::Int, int_bytes.as_slice())
-        }
-        &HashableValue::Bool(bool) => {
-            let bytes = if bool { &[1] } else { &[0] };
-            (KeyType::Bool, bytes.as_slice())
-        }
-        HashableValue::String(string) => (KeyType::String, string.as_bytes()),
-        HashableValue::Id(id) => (KeyType::Id, id.as_bytes()),
-    };
-
-    [
-        identifier_len.as_slice(),
-        identifier.as_bytes(),
-        &[tag as u8],
-        value_bytes,
-    ]
-    .concat()
-    .into_boxed_slice()
+/// This preserves the ordering for two facts with the same identifier and value type,
+/// matching the order in which the VM returns query results. See
+/// [`FactKey::to_sortable_bytes`].
+fn ser_key(key: &FactKey) -> Box<[u8]> {
+    key.to_sortable_bytes().into_boxed_slice()
 }
 
 /// Deserializes a key serialized by [`ser_key`].
@@ -316,9 +372,88 @@ impl<P: Query> Iterator for VmFactCursor<P> {
 
 #[cfg(test)]
 mod test {
+    use core::cell::Cell;
+
+    use aranya_crypto::{default::DefaultEngine, Rng};
+    use aranya_policy_vm::FactCursor;
     use proptest::prelude::*;
 
     use super::*;
+    use crate::{memory::MemStorageProvider, NullSink, PolicyId, QueryMut, StorageProvider};
+
+    /// Counts the facts read from `P`, which may only be read a page at a time.
+    struct Counted<P> {
+        facts: P,
+        reads: Cell<usize>,
+    }
+
+    impl<P: Query> Query for Counted<P> {
+        type QueryIterator = P::QueryIterator;
+
+        fn query(&self, name: &str, keys: &[Box<[u8]>]) -> Result<Option<Box<[u8]>>, StorageError> {
+            self.facts.query(name, keys)
+        }
+
+        fn query_prefix(
+            &self,
+            _name: &str,
+            _prefix: &[Box<[u8]>],
+        ) -> Result<Self::QueryIterator, StorageError> {
+            panic!("facts should be read a page at a time")
+        }
+
+        fn query_page(
+            &self,
+            name: &str,
+            prefix: &[Box<[u8]>],
+            after: Option<&[Box<[u8]>]>,
+            limit: usize,
+        ) -> Result<Vec<Fact>, StorageError> {
+            let page = self.facts.query_page(name, prefix, after, limit)?;
+            self.reads.set(self.reads.get().saturating_add(page.len()));
+            Ok(page)
+        }
+    }
+
+    impl<P: QueryMut> QueryMut for Counted<P> {
+        fn insert(&mut self, name: String, keys: Keys, value: Box<[u8]>) {
+            self.facts.insert(name, keys, value);
+        }
+
+        fn delete(&mut self, name: String, keys: Keys) {
+            self.facts.delete(name, keys);
+        }
+    }
+
+    impl<P: QueryMut> FactPerspective for Counted<P> {}
+
+    #[test]
+    fn test_fact_query_page_reads() {
+        let mut provider = MemStorageProvider::new();
+        let mut facts = Counted {
+            facts: provider.new_perspective(PolicyId::new(0)),
+            reads: Cell::new(0),
+        };
+        let mut sink = NullSink;
+        let (mut engine, _) = DefaultEngine::from_entropy(Rng);
+        let mut ffis: [Box<dyn FfiCallable<DefaultEngine<Rng>>>; 0] = [];
+        let mut io = VmPolicyIO::new(&mut facts, &mut sink, &mut engine, &mut ffis);
+
+        for i in 0..200 {
+            io.fact_insert("F".into(), [FactKey::new("i", HashableValue::Int(i))], [])
+                .unwrap();
+        }
+
+        // Each page reads only its own facts from storage.
+        let mut cursor = FactCursor::new("F", [], 64);
+        let mut reads = Vec::new();
+        while !cursor.is_done() {
+            let page = cursor.next_page::<_, MachineStack>(&io).unwrap();
+            assert_eq!(io.facts.reads.replace(0), page.len());
+            reads.push(page.len());
+        }
+        assert_eq!(reads, [64, 64, 64, 8]);
+    }
 
     proptest! {
         #![proptest_config(ProptestConfig::with_cases(10_000))]
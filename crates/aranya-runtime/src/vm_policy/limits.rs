@@ -0,0 +1,61 @@
+use core::fmt;
+
+use crate::engine::EngineError;
+
+/// Bounds on the size of commands, checked when a command is published and when it is
+/// received. See [`VmPolicy::with_size_limits`](super::VmPolicy::with_size_limits).
+///
+/// A command which exceeds a limit fails with [`EngineError::TooLarge`] before it is
+/// evaluated or stored. Every client of a graph should use the same limits, or commands
+/// published by one may be refused by another.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// The number of bytes of a serialized command.
+    pub max_command: Option<usize>,
+    /// The number of bytes of an envelope's payload, the command's sealed fields.
+    pub max_payload: Option<usize>,
+    /// The number of bytes of an envelope's signature.
+    pub max_signature: Option<usize>,
+}
+
+impl SizeLimits {
+    /// Checks the sizes of a command and its envelope against the limits.
+    pub(super) fn check(
+        &self,
+        command: usize,
+        payload: usize,
+        signature: usize,
+    ) -> Result<(), EngineError> {
+        for (limit, size, max) in [
+            (SizeLimit::Command, command, self.max_command),
+            (SizeLimit::Payload, payload, self.max_payload),
+            (SizeLimit::Signature, signature, self.max_signature),
+        ] {
+            if max.is_some_and(|max| size > max) {
+                return Err(EngineError::TooLarge(limit));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The [`SizeLimits`] a command exceeded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SizeLimit {
+    /// [`SizeLimits::max_command`].
+    Command,
+    /// [`SizeLimits::max_payload`].
+    Payload,
+    /// [`SizeLimits::max_signature`].
+    Signature,
+}
+
+impl fmt::Display for SizeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command => write!(f, "command too large"),
+            Self::Payload => write!(f, "envelope payload too large"),
+            Self::Signature => write!(f, "envelope signature too large"),
+        }
+    }
+}
@@ -9,7 +9,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     command::{Command, CommandId, Priority},
-    Address, Prior,
+    Address, Labeler, Prior,
 };
 
 /// The data inside a [VmProtocol]. It gets serialized and deserialized over the wire.
@@ -108,6 +108,31 @@ impl Command for VmProtocol<'_> {
     }
 }
 
+/// A [`Labeler`] for commands produced by [`super::VmPolicy`]. A command has the label
+/// of its name and of its `category` attribute, if it has one.
+#[derive(Clone, Debug)]
+pub struct VmLabeler {
+    /// A mapping between command names and categories, shared with the underlying
+    /// [`super::VmPolicy`].
+    category_map: Arc<BTreeMap<String, String>>,
+}
+
+impl VmLabeler {
+    pub fn new(category_map: Arc<BTreeMap<String, String>>) -> VmLabeler {
+        VmLabeler { category_map }
+    }
+}
+
+impl Labeler for VmLabeler {
+    fn has_label(&self, bytes: &[u8], label: &str) -> bool {
+        let kind = match postcard::from_bytes(bytes) {
+            Ok(VmProtocolData::Init { kind, .. } | VmProtocolData::Basic { kind, .. }) => kind,
+            Ok(VmProtocolData::Merge { .. }) | Err(_) => return false,
+        };
+        kind == label || self.category_map.get(kind).is_some_and(|c| c == label)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Envelope<'a> {
     pub parent_id: CommandId,
@@ -5,14 +5,22 @@ use aranya_policy_compiler::Compiler;
 use aranya_policy_lang::lang::parse_policy_document;
 use aranya_policy_vm::ffi::FfiModule;
 use aranya_runtime::{
-    testing::vm::{self, TestEngine},
+    testing::{
+        bench,
+        vm::{self, TestEngine},
+    },
     vm_policy::testing::TestFfiEnvelope,
 };
 use test_log::test;
 
 /// Creates a `TestEngine` from a policy document.
 fn new_engine() -> TestEngine {
-    let ast = parse_policy_document(vm::TEST_POLICY_1).unwrap_or_else(|e| panic!("{e}"));
+    engine_from(vm::TEST_POLICY_1)
+}
+
+/// Creates a `TestEngine` from `policy`.
+fn engine_from(policy: &str) -> TestEngine {
+    let ast = parse_policy_document(policy).unwrap_or_else(|e| panic!("{e}"));
     let module = Compiler::new(&ast)
         .ffi_modules(&[TestFfiEnvelope::SCHEMA])
         .compile()
@@ -39,3 +47,90 @@ fn test_aranya_session() {
 fn test_effect_metadata() {
     vm::test_effect_metadata(new_engine(), new_engine()).unwrap()
 }
+
+#[test]
+fn test_recall_log() {
+    vm::test_recall_log(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_export_import() {
+    vm::test_export_import(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_metrics() {
+    vm::test_metrics(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_receipt() {
+    vm::test_receipt(new_engine()).unwrap()
+}
+
+#[test]
+fn test_query_facts() {
+    vm::test_query_facts(new_engine()).unwrap()
+}
+
+#[test]
+fn test_session_limits() {
+    vm::test_session_limits(new_engine()).unwrap()
+}
+
+#[test]
+fn test_session_resume() {
+    vm::test_session_resume(new_engine()).unwrap()
+}
+
+#[test]
+fn test_sync_reconciliation() {
+    vm::test_sync_reconciliation(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_sync_selection() {
+    vm::test_sync_selection(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_dry_run() {
+    vm::test_dry_run(new_engine()).unwrap()
+}
+
+#[test]
+fn test_action_transaction() {
+    vm::test_action_transaction(new_engine()).unwrap()
+}
+
+#[test]
+fn test_size_limits() {
+    vm::test_size_limits(new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_policy_versions() {
+    vm::test_policy_versions(new_engine(), new_engine(), new_engine()).unwrap()
+}
+
+#[test]
+fn test_verify_graph() {
+    vm::test_verify_graph(new_engine()).unwrap()
+}
+
+#[test]
+fn test_check_rejection() {
+    vm::test_check_rejection(new_engine()).unwrap()
+}
+
+#[test]
+fn test_bench_workloads() {
+    let (mut cs, storage_id) = bench::new_client(engine_from(bench::BENCH_POLICY)).unwrap();
+    bench::action_storm(&mut cs, storage_id, 3).unwrap();
+    bench::add_items(&mut cs, storage_id, 3).unwrap();
+    bench::fact_scan(&mut cs, storage_id).unwrap();
+
+    let mut peer = bench::new_peer(engine_from(bench::BENCH_POLICY));
+    bench::sync_graph(storage_id, &mut cs, &mut peer);
+    bench::fact_scan(&mut peer, storage_id).unwrap();
+}